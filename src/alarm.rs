@@ -0,0 +1,702 @@
+use serde_json::json;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+use crate::device_configuration::alarm::{AlarmCondition, AlarmSettings};
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::modbus::availability::{Availability, DeviceAvailability};
+use crate::modbus::reading::{Quality, Reading};
+use crate::modbus::watchdog::WatchdogEvent;
+use crate::mqtt::client::MqttClient;
+use crate::mqtt::payload::format_timestamp;
+use crate::pipeline::Sink;
+
+/// 报警状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmState {
+    /// 条件不满足，且启动以来没有报警过
+    Normal,
+    /// 条件已满足，等待持续 delay_ms
+    Pending,
+    /// 正在报警
+    Active,
+    /// 报警已解除
+    Cleared,
+}
+
+impl AlarmState {
+    /// 发布到报警主题的状态名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlarmState::Normal => "normal",
+            AlarmState::Pending => "pending",
+            AlarmState::Active => "active",
+            AlarmState::Cleared => "cleared",
+        }
+    }
+
+    // 不处于报警或等待报警的状态
+    fn is_idle(self) -> bool {
+        matches!(self, AlarmState::Normal | AlarmState::Cleared)
+    }
+}
+
+impl fmt::Display for AlarmState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 一次报警状态变化
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmEvent {
+    /// 报警名称，通信中断报警为 `<name>_stale`
+    pub name: String,
+    /// 产生变化的报警配置
+    pub settings: AlarmSettings,
+    /// 是否为通信中断报警
+    pub stale: bool,
+    /// 变化前的状态
+    pub previous: AlarmState,
+    /// 变化后的状态
+    pub state: AlarmState,
+    /// 触发变化的点位值，通信中断报警为 None
+    pub value: Option<f64>,
+    /// 变化发生的时间
+    pub timestamp: SystemTime,
+}
+
+impl AlarmEvent {
+    /// 发布到报警主题的 JSON 消息
+    pub fn payload(&self) -> Vec<u8> {
+        let settings = &self.settings;
+        let mut payload = json!({
+            "alarm": self.name,
+            "state": self.state.as_str(),
+            "previous": self.previous.as_str(),
+            "gateway": settings.gateway,
+            "slave_id": settings.slave,
+            "point": settings.point,
+            "timestamp": format_timestamp(self.timestamp),
+        });
+        if self.stale {
+            payload["condition"] = json!("comm_failure");
+        } else {
+            payload["condition"] = json!(settings.condition.as_str());
+            payload["value"] = json!(self.value);
+            match settings.condition {
+                AlarmCondition::OutsideRange => payload["range"] = json!(settings.range),
+                _ => payload["threshold"] = json!(settings.threshold),
+            }
+        }
+        if let Some(severity) = &settings.severity {
+            payload["severity"] = json!(severity);
+        }
+        payload.to_string().into_bytes()
+    }
+}
+
+// 一个报警的运行状态
+#[derive(Debug)]
+struct Tracker {
+    settings: AlarmSettings,
+    state: AlarmState,
+    // 等待结束后回到的状态：从未报警过为 Normal，否则为 Cleared
+    idle: AlarmState,
+    pending_since: Option<Instant>,
+    stale: AlarmState,
+}
+
+impl Tracker {
+    fn new(settings: AlarmSettings) -> Self {
+        Tracker {
+            settings,
+            state: AlarmState::Normal,
+            idle: AlarmState::Normal,
+            pending_since: None,
+            stale: AlarmState::Normal,
+        }
+    }
+
+    fn matches(&self, gateway: &str, gateway_name: Option<&str>, slave_id: u8) -> bool {
+        let alarm = &self.settings;
+        alarm.slave == slave_id
+            && (alarm.gateway == gateway || gateway_name == Some(alarm.gateway.as_str()))
+    }
+
+    // 值是否满足报警条件
+    fn triggered(&self, value: f64) -> bool {
+        let alarm = &self.settings;
+        match alarm.condition {
+            AlarmCondition::Gt => alarm.threshold.is_some_and(|t| value > t),
+            AlarmCondition::Lt => alarm.threshold.is_some_and(|t| value < t),
+            AlarmCondition::Eq => alarm.threshold.is_some_and(|t| value == t),
+            AlarmCondition::OutsideRange => alarm
+                .range
+                .is_some_and(|[low, high]| value < low || value > high),
+        }
+    }
+
+    // 值是否已越过回差，可以解除报警
+    fn recovered(&self, value: f64) -> bool {
+        let alarm = &self.settings;
+        let h = alarm.hysteresis;
+        match alarm.condition {
+            AlarmCondition::Gt => alarm.threshold.is_some_and(|t| value <= t - h),
+            AlarmCondition::Lt => alarm.threshold.is_some_and(|t| value >= t + h),
+            AlarmCondition::Eq => alarm.threshold.is_some_and(|t| (value - t).abs() > h),
+            AlarmCondition::OutsideRange => alarm
+                .range
+                .is_some_and(|[low, high]| value >= low + h && value <= high - h),
+        }
+    }
+
+    // 处理一个新的点位值，返回变化后的状态
+    fn update(&mut self, value: f64, now: Instant) -> Option<AlarmState> {
+        let next = match self.state {
+            AlarmState::Normal | AlarmState::Cleared if self.triggered(value) => {
+                self.pending_since = Some(now);
+                if self.settings.delay_ms == 0 {
+                    AlarmState::Active
+                } else {
+                    AlarmState::Pending
+                }
+            }
+            AlarmState::Pending if !self.triggered(value) => {
+                self.pending_since = None;
+                self.idle
+            }
+            AlarmState::Pending => {
+                let since = self.pending_since.unwrap_or(now);
+                if now.saturating_duration_since(since) >= self.settings.delay() {
+                    AlarmState::Active
+                } else {
+                    return None;
+                }
+            }
+            AlarmState::Active if self.recovered(value) => {
+                self.pending_since = None;
+                self.idle = AlarmState::Cleared;
+                AlarmState::Cleared
+            }
+            _ => return None,
+        };
+        self.state = next;
+        Some(next)
+    }
+}
+
+/// 报警判断：根据采集数据和从站可用性维护每个报警的状态
+///
+/// # 说明
+/// * 条件满足时先进入 Pending，持续 `delay_ms` 后进入 Active；期间条件不再满足则回到原来的状态
+/// * Active 的报警在值越过回差后进入 Cleared，之后条件再次满足时重新进入 Pending
+/// * 配置了 `comm_failure` 的报警在从站离线时另外产生通信中断报警，恢复在线后解除
+/// * 只在收到该点位的新值时判断，时间由调用方传入，不依赖系统时钟
+#[derive(Debug, Default)]
+pub struct AlarmEngine {
+    trackers: Vec<Tracker>,
+}
+
+impl AlarmEngine {
+    /// 按报警配置创建，所有报警初始为 Normal
+    pub fn new(alarms: &[AlarmSettings]) -> Self {
+        AlarmEngine {
+            trackers: alarms.iter().cloned().map(Tracker::new).collect(),
+        }
+    }
+
+    /// 应用新的报警配置，配置未变的报警保持原来的状态
+    ///
+    /// 返回删除或修改了的、曾经发布过状态的报警名称，调用方据此清除对应的保留消息
+    pub fn apply(&mut self, alarms: &[AlarmSettings]) -> Vec<String> {
+        let mut old = std::mem::take(&mut self.trackers);
+        for alarm in alarms {
+            match old.iter().position(|t| t.settings == *alarm) {
+                Some(index) => self.trackers.push(old.swap_remove(index)),
+                None => self.trackers.push(Tracker::new(alarm.clone())),
+            }
+        }
+        let mut removed = Vec::new();
+        for tracker in old {
+            if tracker.state != AlarmState::Normal {
+                removed.push(tracker.settings.name.clone());
+            }
+            if tracker.stale != AlarmState::Normal {
+                removed.push(tracker.settings.stale_name());
+            }
+        }
+        removed
+    }
+
+    /// 处理一批采集数据
+    ///
+    /// # 参数说明
+    /// * `readings` - 一个采集周期的数据
+    /// * `now` - 当前时间，用于计算条件持续的时长
+    pub fn evaluate(&mut self, readings: &[Reading], now: Instant) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+        // 过期标记的值是之前已经判断过的
+        for reading in readings.iter().filter(|r| r.quality != Quality::Stale) {
+            for tracker in &mut self.trackers {
+                if tracker.settings.point != reading.point
+                    || !tracker.matches(
+                        &reading.gateway,
+                        reading.gateway_name.as_deref(),
+                        reading.slave_id,
+                    )
+                {
+                    continue;
+                }
+                let previous = tracker.state;
+                if let Some(state) = tracker.update(reading.value, now) {
+                    events.push(AlarmEvent {
+                        name: tracker.settings.name.clone(),
+                        settings: tracker.settings.clone(),
+                        stale: false,
+                        previous,
+                        state,
+                        value: Some(reading.value),
+                        timestamp: reading.timestamp,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// 处理从站可用性的变化，产生或解除通信中断报警
+    pub fn availability(&mut self, device: &DeviceAvailability) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+        for tracker in &mut self.trackers {
+            if !tracker.settings.comm_failure
+                || !tracker.matches(
+                    &device.gateway,
+                    device.gateway_name.as_deref(),
+                    device.slave_id,
+                )
+            {
+                continue;
+            }
+            let previous = tracker.stale;
+            let state = match device.availability {
+                Availability::Offline if previous.is_idle() => AlarmState::Active,
+                Availability::Online | Availability::Disabled if previous == AlarmState::Active => {
+                    AlarmState::Cleared
+                }
+                _ => continue,
+            };
+            tracker.stale = state;
+            events.push(AlarmEvent {
+                name: tracker.settings.stale_name(),
+                settings: tracker.settings.clone(),
+                stale: true,
+                previous,
+                state,
+                value: None,
+                timestamp: SystemTime::now(),
+            });
+        }
+        events
+    }
+}
+
+/// 报警输出：作为分发器的一个输出判断报警，状态变化输出日志并以保留消息发布到报警主题
+pub struct Alarms {
+    engine: Mutex<AlarmEngine>,
+    mqtt: Option<(Arc<MqttClient>, MqttSettings)>,
+}
+
+/// 可在分发器和配置热加载之间共享的报警输出
+pub type SharedAlarms = Arc<Alarms>;
+
+impl Alarms {
+    /// 按配置中的报警创建，未配置 MQTT 时只输出日志
+    pub fn new(config: &Config, client: Option<Arc<MqttClient>>) -> SharedAlarms {
+        let mqtt = client.zip(config.mqtt.clone());
+        Arc::new(Alarms {
+            engine: Mutex::new(AlarmEngine::new(&config.alarms)),
+            mqtt,
+        })
+    }
+
+    /// 配置热加载后应用新的报警配置，清除已删除或修改的报警的保留消息
+    pub fn apply(&self, config: &Config) {
+        let removed = self.lock().apply(&config.alarms);
+        if let Some((client, settings)) = &self.mqtt {
+            for name in removed {
+                let topic = settings.alarm_state_topic(&name);
+                if let Err(e) = client.clear_state(&topic) {
+                    warn!(topic = %topic, error = %e, "清除报警状态失败");
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AlarmEngine> {
+        self.engine.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 输出日志并发布状态变化
+    fn report(&self, events: Vec<AlarmEvent>) {
+        for event in events {
+            let alarm = &event.settings;
+            match event.state {
+                AlarmState::Active => warn!(
+                    alarm = %event.name,
+                    gateway = %alarm.gateway,
+                    slave_id = alarm.slave,
+                    point = %alarm.point,
+                    value = ?event.value,
+                    severity = ?alarm.severity,
+                    "报警"
+                ),
+                AlarmState::Cleared => info!(
+                    alarm = %event.name,
+                    gateway = %alarm.gateway,
+                    slave_id = alarm.slave,
+                    point = %alarm.point,
+                    value = ?event.value,
+                    "报警解除"
+                ),
+                _ => debug!(
+                    alarm = %event.name,
+                    previous = %event.previous,
+                    state = %event.state,
+                    value = ?event.value,
+                    "报警状态变化"
+                ),
+            }
+            if let Some((client, settings)) = &self.mqtt {
+                let topic = settings.alarm_state_topic(&event.name);
+                if let Err(e) = client.publish_state(&topic, &event.payload()) {
+                    warn!(topic = %topic, error = %e, "发布报警状态失败");
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for SharedAlarms {
+    async fn deliver(&mut self, batch: &[Reading]) {
+        let events = self.lock().evaluate(batch, Instant::now());
+        self.report(events);
+    }
+
+    async fn availability(&mut self, device: &DeviceAvailability) {
+        let events = self.lock().availability(device);
+        self.report(events);
+    }
+
+    // 看门狗报警不需要配置，名称为 watchdog_<网关>_<从站>
+    async fn watchdog(&mut self, event: &WatchdogEvent) {
+        let name = format!("watchdog_{}_{}", event.gateway_display(), event.slave_display());
+        let state = if event.failed {
+            AlarmState::Active
+        } else {
+            AlarmState::Cleared
+        };
+        if event.failed {
+            warn!(
+                alarm = %name,
+                gateway = %event.gateway_display(),
+                slave_id = event.slave_id,
+                missed = event.missed,
+                error = event.error.as_deref().unwrap_or(""),
+                "报警"
+            );
+        } else {
+            info!(alarm = %name, gateway = %event.gateway_display(), slave_id = event.slave_id, "报警解除");
+        }
+        if let Some((client, settings)) = &self.mqtt {
+            let topic = settings.alarm_state_topic(&name);
+            let payload = json!({
+                "alarm": name,
+                "state": state.as_str(),
+                "condition": "watchdog",
+                "gateway": event.gateway_display(),
+                "slave_id": event.slave_id,
+                "missed": event.missed,
+                "error": event.error,
+                "timestamp": format_timestamp(event.timestamp),
+            });
+            if let Err(e) = client.publish_state(&topic, &payload.to_string().into_bytes()) {
+                warn!(topic = %topic, error = %e, "发布报警状态失败");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{reading, MockBroker};
+    use std::time::Duration;
+
+    const GATEWAY: &str = "10.0.0.1:502";
+
+    // 网关 BMS 从站1的 cell_temp 大于 45
+    fn alarm(condition: AlarmCondition) -> AlarmSettings {
+        AlarmSettings {
+            name: "cell_temp_high".to_string(),
+            gateway: "BMS".to_string(),
+            slave: 1,
+            point: "cell_temp".to_string(),
+            condition,
+            threshold: Some(45.0),
+            range: None,
+            hysteresis: 0.0,
+            delay_ms: 0,
+            severity: None,
+            comm_failure: false,
+        }
+    }
+
+    fn temp(value: f64) -> Reading {
+        let mut reading = reading(GATEWAY, 1, "cell_temp", value);
+        reading.gateway_name = Some("BMS".to_string());
+        reading
+    }
+
+    // 依次处理 (时间偏移秒数, 值)，返回每一步变化后的状态（没有变化为 None）
+    fn walk(engine: &mut AlarmEngine, start: Instant, steps: &[(u64, f64)]) -> Vec<Option<AlarmState>> {
+        steps
+            .iter()
+            .map(|(seconds, value)| {
+                let events = engine.evaluate(&[temp(*value)], start + Duration::from_secs(*seconds));
+                assert!(events.len() <= 1);
+                events.first().map(|event| event.state)
+            })
+            .collect()
+    }
+
+    fn offline(availability: Availability) -> DeviceAvailability {
+        DeviceAvailability {
+            gateway: GATEWAY.to_string(),
+            gateway_name: Some("BMS".to_string()),
+            slave_id: 1,
+            slave_name: None,
+            availability,
+        }
+    }
+
+    #[test]
+    fn trigger_without_delay_is_immediately_active() {
+        let mut engine = AlarmEngine::new(&[AlarmSettings {
+            severity: Some("critical".to_string()),
+            ..alarm(AlarmCondition::Gt)
+        }]);
+        let start = Instant::now();
+        assert_eq!(engine.evaluate(&[temp(45.0)], start), []);
+        let events = engine.evaluate(&[temp(46.5)], start);
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!((event.previous, event.state), (AlarmState::Normal, AlarmState::Active));
+        let payload: serde_json::Value = serde_json::from_slice(&event.payload()).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "alarm": "cell_temp_high",
+                "state": "active",
+                "previous": "normal",
+                "gateway": "BMS",
+                "slave_id": 1,
+                "point": "cell_temp",
+                "timestamp": format_timestamp(event.timestamp),
+                "condition": "gt",
+                "value": 46.5,
+                "threshold": 45.0,
+                "severity": "critical",
+            })
+        );
+        // 已在报警中，不重复产生事件
+        assert_eq!(engine.evaluate(&[temp(50.0)], start), []);
+        // 其他点位、从站和网关的数据不影响
+        let mut other = temp(50.0);
+        other.slave_id = 2;
+        assert_eq!(engine.evaluate(&[other], start), []);
+    }
+
+    #[test]
+    fn debounce_requires_condition_to_hold_for_delay() {
+        let mut engine = AlarmEngine::new(&[AlarmSettings {
+            delay_ms: 5000,
+            ..alarm(AlarmCondition::Gt)
+        }]);
+        let start = Instant::now();
+        let states = walk(
+            &mut engine,
+            start,
+            &[(0, 50.0), (3, 50.0), (4, 40.0), (5, 50.0), (9, 50.0), (10, 50.0), (11, 50.0)],
+        );
+        assert_eq!(
+            states,
+            [
+                Some(AlarmState::Pending),
+                None,
+                // 持续时间不够时恢复，回到 Normal
+                Some(AlarmState::Normal),
+                // 重新开始计时
+                Some(AlarmState::Pending),
+                None,
+                Some(AlarmState::Active),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn hysteresis_delays_clear_and_retrigger_starts_from_cleared() {
+        let mut engine = AlarmEngine::new(&[AlarmSettings {
+            hysteresis: 2.0,
+            delay_ms: 1000,
+            ..alarm(AlarmCondition::Gt)
+        }]);
+        let start = Instant::now();
+        let states = walk(
+            &mut engine,
+            start,
+            &[(0, 46.0), (1, 46.0), (2, 44.0), (3, 43.5), (4, 43.0), (5, 46.0), (5, 44.0)],
+        );
+        assert_eq!(
+            states,
+            [
+                Some(AlarmState::Pending),
+                Some(AlarmState::Active),
+                // 低于阈值但还在回差内
+                None,
+                None,
+                Some(AlarmState::Cleared),
+                Some(AlarmState::Pending),
+                // 曾经报警过，等待期间恢复时回到 Cleared
+                Some(AlarmState::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_conditions_trigger_and_recover() {
+        let start = Instant::now();
+        let mut lt = AlarmEngine::new(&[AlarmSettings { hysteresis: 1.0, ..alarm(AlarmCondition::Lt) }]);
+        assert_eq!(
+            walk(&mut lt, start, &[(0, 45.0), (0, 44.0), (0, 45.5), (0, 46.0)]),
+            [None, Some(AlarmState::Active), None, Some(AlarmState::Cleared)]
+        );
+
+        let mut eq = AlarmEngine::new(&[AlarmSettings { hysteresis: 0.5, ..alarm(AlarmCondition::Eq) }]);
+        assert_eq!(
+            walk(&mut eq, start, &[(0, 44.0), (0, 45.0), (0, 45.5), (0, 45.6)]),
+            [None, Some(AlarmState::Active), None, Some(AlarmState::Cleared)]
+        );
+
+        let mut range = AlarmEngine::new(&[AlarmSettings {
+            threshold: None,
+            range: Some([10.0, 40.0]),
+            hysteresis: 2.0,
+            ..alarm(AlarmCondition::OutsideRange)
+        }]);
+        assert_eq!(
+            walk(&mut range, start, &[(0, 10.0), (0, 9.0), (0, 11.0), (0, 12.0), (0, 41.0), (0, 39.0), (0, 38.0)]),
+            [
+                None,
+                Some(AlarmState::Active),
+                None,
+                Some(AlarmState::Cleared),
+                Some(AlarmState::Active),
+                None,
+                Some(AlarmState::Cleared),
+            ]
+        );
+        let event = &range.evaluate(&[temp(50.0)], start)[0];
+        let payload: serde_json::Value = serde_json::from_slice(&event.payload()).unwrap();
+        assert_eq!(payload["range"], json!([10.0, 40.0]));
+        assert!(payload.get("threshold").is_none());
+    }
+
+    #[test]
+    fn comm_failure_raises_separate_stale_alarm() {
+        let mut engine = AlarmEngine::new(&[
+            AlarmSettings { comm_failure: true, ..alarm(AlarmCondition::Gt) },
+            AlarmSettings { name: "no_comm".to_string(), ..alarm(AlarmCondition::Lt) },
+        ]);
+        let events = engine.availability(&offline(Availability::Offline));
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.name, "cell_temp_high_stale");
+        assert!(event.stale);
+        assert_eq!((event.previous, event.state), (AlarmState::Normal, AlarmState::Active));
+        let payload: serde_json::Value = serde_json::from_slice(&event.payload()).unwrap();
+        assert_eq!(payload["condition"], "comm_failure");
+        assert!(payload.get("value").is_none());
+
+        // 持续离线不重复报警，数值报警不受影响
+        assert_eq!(engine.availability(&offline(Availability::Offline)), []);
+        // 离线期间过期标记的值不参与判断
+        let mut stale = temp(50.0);
+        stale.quality = Quality::Stale;
+        assert_eq!(engine.evaluate(&[stale], Instant::now()), []);
+
+        let events = engine.availability(&offline(Availability::Online));
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].previous, events[0].state), (AlarmState::Active, AlarmState::Cleared));
+        assert_eq!(engine.availability(&offline(Availability::Online)), []);
+        // 再次离线时从 Cleared 重新报警
+        let events = engine.availability(&offline(Availability::Offline));
+        assert_eq!((events[0].previous, events[0].state), (AlarmState::Cleared, AlarmState::Active));
+    }
+
+    #[test]
+    fn apply_keeps_unchanged_alarms_and_reports_removed_ones() {
+        let high = AlarmSettings { comm_failure: true, ..alarm(AlarmCondition::Gt) };
+        let low = AlarmSettings { name: "cell_temp_low".to_string(), threshold: Some(5.0), ..alarm(AlarmCondition::Lt) };
+        let mut engine = AlarmEngine::new(&[high.clone(), low.clone()]);
+        let start = Instant::now();
+        engine.evaluate(&[temp(50.0)], start);
+        engine.availability(&offline(Availability::Offline));
+
+        // 未变的报警保持 Active，不再产生事件
+        assert_eq!(engine.apply(&[high.clone(), low.clone()]), Vec::<String>::new());
+        assert_eq!(engine.evaluate(&[temp(50.0)], start), []);
+
+        // 从未发布过状态的报警删除时不需要清除
+        assert_eq!(engine.apply(std::slice::from_ref(&high)), Vec::<String>::new());
+        let changed = AlarmSettings { threshold: Some(60.0), ..high };
+        assert_eq!(engine.apply(&[changed]), ["cell_temp_high", "cell_temp_high_stale"]);
+        // 修改后的报警从 Normal 开始
+        assert_eq!(engine.evaluate(&[temp(50.0)], start), []);
+    }
+
+    #[tokio::test]
+    async fn state_changes_are_published_retained() {
+        let broker = MockBroker::start().await;
+        let mut config: Config = serde_yaml::from_str(&format!(
+            "version: 2\nmqtt:\n  broker_host: 127.0.0.1\n  broker_port: {}\n  client_id: alarm-test\ngateways: []\n",
+            broker.port
+        ))
+        .unwrap();
+        config.alarms = vec![alarm(AlarmCondition::Gt)];
+        let client = Arc::new(MqttClient::from_settings(config.mqtt.as_ref().unwrap(), None).unwrap());
+        let mut alarms = Alarms::new(&config, Some(Arc::clone(&client)));
+
+        alarms.deliver(&[temp(50.0)]).await;
+        alarms.deliver(&[temp(40.0)]).await;
+        let topic = "ems/alarm-test/alarms/cell_temp_high";
+        let payloads = broker.wait_for_topic(topic, 2).await;
+        let states: Vec<String> = payloads
+            .iter()
+            .map(|p| serde_json::from_slice::<serde_json::Value>(p).unwrap()["state"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(states, ["active", "cleared"]);
+        assert!(broker.publishes().iter().filter(|p| p.topic == topic).all(|p| p.retain));
+
+        // 删除报警时清除保留消息
+        config.alarms.clear();
+        alarms.apply(&config);
+        assert_eq!(broker.wait_for_topic(topic, 3).await[2], b"");
+        client.close().await;
+    }
+}
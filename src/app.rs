@@ -0,0 +1,553 @@
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{error, info, warn};
+
+use crate::alarm::Alarms;
+use crate::computed::{ComputedPoints, SharedComputed};
+use crate::csv::CsvSink;
+use crate::device_configuration::modbus::{Config, LoadOptions};
+use crate::energy::{self, EnergyMeters, SharedEnergy};
+use crate::http::{ApiState, HttpServer};
+use crate::latest::{ReadingCache, SharedCache};
+use crate::modbus::scheduler::PollEvent;
+use crate::modbus::sunspec;
+use crate::mqtt::client::{MqttClient, SessionHook};
+use crate::mqtt::command::CommandHandler;
+use crate::mqtt::control::ControlHandler;
+use crate::mqtt::dead_letter::{DeadLetters, SharedDeadLetters};
+use crate::mqtt::discovery::Discovery;
+use crate::mqtt::publisher::Publisher;
+use crate::mqtt::read_request::ReadHandler;
+use crate::mqtt::sparkplug::{self, SharedNode, SparkplugNode};
+use crate::pipeline::{LogSink, Pipeline, DEFAULT_SINK_CAPACITY};
+use crate::reload::{self, GatewayTasks, SharedWriters};
+use crate::replay::Replayer;
+use crate::schedule::Schedules;
+use crate::staleness::{self, SharedStaleness, StalenessMonitor};
+use crate::storage::{PublishLog, ReadingStore, SharedPublishLog, StorageSink};
+
+/// 启动选项
+#[derive(Debug, Clone, Default)]
+pub struct AppOptions {
+    /// 重新加载配置时使用的读取选项，应与读取初始配置时相同
+    pub load: LoadOptions,
+    /// 为 true 时每个网关只采集一次，不启动 HTTP 接口、命令处理、定时写入和配置热加载
+    pub once: bool,
+}
+
+/// 按配置运行的完整程序：采集、计算点位、电量、报警、各个输出、MQTT 命令处理和配置热加载
+///
+/// `modbus_pub run` 就是读取配置后依次调用 [`App::start`] 和 [`App::run`]，
+/// 嵌入其他程序时可以同样使用，日志输出和退出信号由调用方处理。
+///
+/// # 示例
+///
+/// ```no_run
+/// use modbus_pub::app::{App, AppOptions};
+/// use modbus_pub::read_config;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = read_config("modbus_config.yaml")?;
+/// let app = App::start(config, "modbus_config.yaml", AppOptions::default()).await?;
+/// let cache = app.cache();
+/// app.run(async {
+///     tokio::signal::ctrl_c().await.ok();
+/// })
+/// .await;
+/// println!("退出时共有 {} 个点位", cache.snapshot().len());
+/// # Ok(())
+/// # }
+/// ```
+pub struct App {
+    cache: SharedCache,
+    mqtt: Option<Arc<MqttClient>>,
+    writers: SharedWriters,
+    stop: oneshot::Sender<()>,
+    // 持续采集直到收到停止信号，然后依次停止采集、关闭输出和 MQTT 连接
+    driver: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl App {
+    /// 按配置启动
+    ///
+    /// # 参数说明
+    /// * `config` - 已读取并校验的配置，设置了 `sunspec: auto` 的从站在这里连接设备发现点位
+    /// * `file_path` - 配置文件路径，持续采集时定期检查该文件并热加载
+    /// * `options` - 启动选项
+    ///
+    /// # 说明
+    /// * 返回时已开始采集；`once` 为 true 时已完成一次采集
+    /// * 后台任务在当前 tokio 运行时中执行
+    ///
+    /// # 错误
+    /// 无法创建 MQTT 客户端、打开本地数据库、创建 CSV 输出或启动 HTTP 接口时返回错误
+    pub async fn start(
+        mut config: Config,
+        file_path: &str,
+        options: AppOptions,
+    ) -> Result<App, Box<dyn Error>> {
+        let once = options.once;
+        // 设置了 sunspec: auto 的从站连接设备读取模型链，生成的点位并入该从站的点位
+        sunspec::discover(&mut config).await;
+        // 每个点位的最新值在分发前记录到缓存中，HTTP 接口和计算点位从缓存读取；死区过滤也在其中记录发布的值
+        let cache = ReadingCache::new();
+        cache.apply(&config);
+
+        // 未配置 MQTT 时只运行 Modbus 采集，不尝试连接 Broker
+        let mut mqtt = None;
+        let mut node: Option<SharedNode> = None;
+        let mut dead_letters: Option<SharedDeadLetters> = None;
+        let mut publish_log: Option<SharedPublishLog> = None;
+        let publisher = match &config.mqtt {
+            Some(settings) => {
+                info!(
+                    broker = %format!("{}:{}", settings.broker_host, settings.broker_port),
+                    client_id = %settings.client_id,
+                    "MQTT 配置"
+                );
+                // Sparkplug B 的 NBIRTH 在连接成功时发布，需要先确定 metric 列表
+                if let Some(sparkplug) = &settings.sparkplug {
+                    let mut state = SparkplugNode::new(sparkplug);
+                    state.set_metrics(&config)?;
+                    node = Some(Arc::new(Mutex::new(state)));
+                }
+                let hook = node.clone().map(|node| node as Arc<dyn SessionHook>);
+                let client = Arc::new(MqttClient::from_settings(settings, hook)?);
+                mqtt = Some(Arc::clone(&client));
+                dead_letters = DeadLetters::new(Arc::clone(&client), settings);
+                // 本地存储记录是否已发布时，消息放入发送队列后再过 2 个 keep-alive 没有断开才算送达
+                if config.storage.as_ref().is_some_and(|storage| storage.forward) {
+                    let settle = Duration::from_secs(settings.keep_alive_secs * 2);
+                    publish_log = Some(PublishLog::new(settle));
+                }
+                let publisher = Arc::new(Publisher::new(
+                    client,
+                    settings,
+                    Arc::clone(&cache),
+                    config.tz()?,
+                    node.clone(),
+                    dead_letters.clone(),
+                    publish_log.clone(),
+                )?);
+                tokio::spawn(Arc::clone(&publisher).run_rate_limiter());
+                Some(publisher)
+            }
+            None => {
+                info!("未配置 MQTT，仅运行 Modbus 采集");
+                None
+            }
+        };
+
+        // 采集数据经过分发器交给各个输出，输出处理不及时时丢弃数据而不阻塞采集
+        let mut pipeline = Pipeline::new();
+        pipeline.add_sink("log", DEFAULT_SINK_CAPACITY, LogSink);
+        if let Some(publisher) = &publisher {
+            pipeline.add_sink("mqtt", DEFAULT_SINK_CAPACITY, Arc::clone(publisher));
+        }
+        if let Some(settings) = &config.storage {
+            let store = match ReadingStore::open(&settings.path).await {
+                Ok(store) => store,
+                Err(e) => {
+                    error!(path = %settings.path, error = %e, "无法打开本地数据库");
+                    return Err(e.into());
+                }
+            };
+            info!(path = %settings.path, "采集数据保存到本地数据库");
+            // 补发与本地存储共用数据库连接
+            if let (Some(replay), Some(client)) = (&settings.replay, &mqtt)
+                && !once
+            {
+                let replayer = Replayer::new(store.clone(), Arc::clone(client), &config, replay)?;
+                tokio::spawn(replayer.run());
+            }
+            let sink = StorageSink::new(store, settings, mqtt.clone().zip(publish_log.clone()));
+            pipeline.add_sink("storage", DEFAULT_SINK_CAPACITY, sink);
+        }
+        if let Some(settings) = &config.csv {
+            let sink = match CsvSink::new(settings, &config) {
+                Ok(sink) => sink,
+                Err(e) => {
+                    error!(error = %e, "无法创建 CSV 输出");
+                    return Err(e);
+                }
+            };
+            info!(directory = %settings.directory, "采集数据写入 CSV 文件");
+            pipeline.add_sink("csv", DEFAULT_SINK_CAPACITY, sink);
+        }
+        // 按配置判断报警并发布报警状态，配置热加载后同步更新
+        let alarms = Alarms::new(&config, mqtt.clone());
+        pipeline.add_sink("alarms", DEFAULT_SINK_CAPACITY, Arc::clone(&alarms));
+        let sender = pipeline.sender();
+
+        // 配置了 staleness 时定期检查点位是否过期，过期标记与采集数据一样交给各个输出
+        let staleness: SharedStaleness = Arc::new(Mutex::new(StalenessMonitor::new(&config)));
+        if config.staleness.is_some() && !once {
+            let sender = sender.clone();
+            let cache = Arc::clone(&cache);
+            tokio::spawn(staleness::run(Arc::clone(&staleness), move |event| {
+                cache.record(&event);
+                sender.send(event)
+            }));
+        }
+
+        // 计算点位在分发前由采集数据算出，与采集点位一样交给各个输出
+        let computed: SharedComputed =
+            Arc::new(Mutex::new(ComputedPoints::new(&config, Arc::clone(&cache))?));
+        // 电量点位由功率点位（包括计算点位）累计得到，累计值定期保存到状态文件
+        let energy: SharedEnergy = Arc::new(Mutex::new(EnergyMeters::new(&config)?));
+        if config.energy.is_some() && !once {
+            tokio::spawn(energy::run(Arc::clone(&energy)));
+        }
+        let on_event = {
+            let cache = Arc::clone(&cache);
+            let computed = Arc::clone(&computed);
+            let energy = Arc::clone(&energy);
+            let staleness = Arc::clone(&staleness);
+            move |event| {
+                cache.record(&event);
+                let derived = match &event {
+                    PollEvent::Readings(readings) => {
+                        staleness
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .record(readings, Instant::now());
+                        let mut derived = computed
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .process(readings);
+                        let mut energy = energy.lock().unwrap_or_else(|e| e.into_inner());
+                        let accumulated = [energy.process(readings), energy.process(&derived)];
+                        derived.extend(accumulated.into_iter().flatten());
+                        derived
+                    }
+                    PollEvent::Availability(_) | PollEvent::Watchdog(_) => Vec::new(),
+                };
+                cache.update(&derived);
+                sender.send(event);
+                if !derived.is_empty() {
+                    sender.send(PollEvent::Readings(derived));
+                }
+            }
+        };
+
+        // 配置了点位的网关按采集组周期持续采集，配置文件变化时自动调整；once 时只采集一次
+        let mut tasks = GatewayTasks::new(on_event.clone());
+        if once {
+            reload::poll_once(&config, on_event).await;
+        } else {
+            tasks.apply(&config);
+        }
+
+        // 配置了 http 时提供查询当前数据和状态的 HTTP 接口，配置热加载后 /api/config 同步更新
+        let (config_sender, config_receiver) = watch::channel(config.clone());
+        if let Some(settings) = &config.http
+            && !once
+        {
+            let state = ApiState {
+                cache: Arc::clone(&cache),
+                writers: tasks.writers(),
+                config: config_receiver,
+                mqtt: mqtt.clone(),
+                publisher,
+                sinks: pipeline.stats().to_vec(),
+                token: settings.effective_token().map(str::to_string),
+                point_metrics: settings.point_metrics,
+            };
+            match HttpServer::bind(settings, state).await {
+                Ok(server) => {
+                    tokio::spawn(server.run());
+                }
+                Err(e) => {
+                    error!(
+                        address = %format!("{}:{}", settings.bind_address, settings.port),
+                        error = %e,
+                        "无法启动 HTTP 接口"
+                    );
+                    tasks.shutdown().await;
+                    return Err(e.into());
+                }
+            }
+        }
+
+        // 配置了 home_assistant 时发布自动发现消息，配置热加载后同步更新
+        let mut discovery = match (&config.mqtt, &mqtt) {
+            (Some(settings), Some(client)) => Discovery::new(Arc::clone(client), settings),
+            _ => None,
+        };
+        if let Some(discovery) = discovery.as_mut() {
+            discovery.apply(&config);
+        }
+        if let (Some(node), Some(client)) = (&node, &mqtt) {
+            tokio::spawn(sparkplug::run_commands(Arc::clone(client), Arc::clone(node)));
+        }
+        let sparkplug = node.zip(mqtt.clone());
+
+        let polling = !once && config.gateways.iter().any(|g| g.has_points());
+
+        // 命令主题上的写入请求和临时读取请求交给对应网关的采集任务执行
+        let (reload_sender, reload_requests) = mpsc::channel(1);
+        if let (Some(settings), Some(client)) = (&config.mqtt, &mqtt)
+            && !once
+        {
+            // 控制命令中的重新加载由配置热加载执行，没有采集任务时不处理控制命令
+            if let Some(control) = &settings.control
+                && polling
+            {
+                let handler = ControlHandler::new(
+                    Arc::clone(client),
+                    settings,
+                    control,
+                    tasks.writers(),
+                    reload_sender,
+                    dead_letters.clone(),
+                );
+                tokio::spawn(handler.run());
+            }
+            if let Some(read_requests) = &settings.read_requests {
+                let handler = ReadHandler::new(
+                    Arc::clone(client),
+                    settings,
+                    read_requests,
+                    tasks.writers(),
+                    dead_letters.clone(),
+                );
+                tokio::spawn(handler.run());
+            }
+            let handler =
+                CommandHandler::new(Arc::clone(client), settings, tasks.writers(), dead_letters)?;
+            tokio::spawn(handler.run());
+        }
+
+        // 定时写入由对应网关的采集任务执行，配置热加载后同步更新
+        let schedules = Schedules::new(&config, mqtt.clone(), tasks.writers());
+        if polling {
+            tokio::spawn(Arc::clone(&schedules).run());
+        }
+
+        // 没有配置点位的网关不采集，也不连接设备
+        for gateway in config.gateways.iter().filter(|g| g.enabled && !g.has_points()) {
+            warn!(gateway = %gateway.display_name(), "网关没有配置点位，已跳过");
+        }
+
+        let writers = tasks.writers();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let driver = {
+            let cache = Arc::clone(&cache);
+            let mqtt = mqtt.clone();
+            let file_path = file_path.to_string();
+            async move {
+                if polling {
+                    let meters = Arc::clone(&energy);
+                    let watch = reload::watch_config(&file_path, &options.load, config, &mut tasks, reload_requests, move |config| {
+                        config_sender.send_replace(config.clone());
+                        cache.apply(config);
+                        if let Some(discovery) = discovery.as_mut() {
+                            discovery.apply(config);
+                        }
+                        let applied = computed.lock().unwrap_or_else(|e| e.into_inner()).apply(config);
+                        if let Err(e) = applied {
+                            error!(error = %e, "更新计算点位失败");
+                        }
+                        let applied = meters.lock().unwrap_or_else(|e| e.into_inner()).apply(config);
+                        if let Err(e) = applied {
+                            error!(error = %e, "更新电量点位失败");
+                        }
+                        alarms.apply(config);
+                        schedules.apply(config);
+                        staleness.lock().unwrap_or_else(|e| e.into_inner()).apply(config);
+                        // metric 列表变化后需要重新出生
+                        if let Some((node, client)) = &sparkplug {
+                            let changed = node.lock().unwrap_or_else(|e| e.into_inner()).set_metrics(config);
+                            match changed {
+                                Ok(true) => sparkplug::publish_birth(client, node),
+                                Ok(false) => {}
+                                Err(e) => error!(error = %e, "更新 Sparkplug metric 列表失败"),
+                            }
+                        }
+                    });
+                    tokio::select! {
+                        _ = watch => {}
+                        _ = stopped => {}
+                    }
+                    // 停止采集并发布各从站 offline
+                    tasks.shutdown().await;
+                }
+                if let Err(e) = energy.lock().unwrap_or_else(|e| e.into_inner()).save() {
+                    error!(error = %e, "保存电量累计值失败");
+                }
+                pipeline.close().await;
+                // 发出队列中的消息后正常断开，Broker 不会发布遗嘱消息
+                if let Some(client) = &mqtt {
+                    client.close().await;
+                }
+            }
+        };
+        Ok(App {
+            cache,
+            mqtt,
+            writers,
+            stop,
+            driver: Box::pin(driver),
+        })
+    }
+
+    /// 每个点位最新采集值的缓存
+    pub fn cache(&self) -> SharedCache {
+        Arc::clone(&self.cache)
+    }
+
+    /// MQTT 客户端，未配置 mqtt 时为 None
+    pub fn mqtt(&self) -> Option<&Arc<MqttClient>> {
+        self.mqtt.as_ref()
+    }
+
+    /// 正在采集的网关的写入入口
+    pub fn writers(&self) -> SharedWriters {
+        Arc::clone(&self.writers)
+    }
+
+    /// 尚未就绪的项目，全部就绪时为空
+    ///
+    /// # 说明
+    /// * 配置了 mqtt 且 `wait_for_mqtt` 为 true 时，需要已连接到 Broker
+    /// * 每个正在采集的网关需要完成第一次采集（包括连接失败），或已停止、已暂停；设备离线不影响就绪
+    pub fn pending(&self, wait_for_mqtt: bool) -> Vec<String> {
+        let mut pending = Vec::new();
+        if wait_for_mqtt && self.mqtt.as_ref().is_some_and(|client| !client.is_connected()) {
+            pending.push("MQTT 连接".to_string());
+        }
+        for writer in self.writers.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            let stats = writer.stats.lock().unwrap_or_else(|e| e.into_inner());
+            if !(stats.failed || stats.paused || stats.slaves.values().any(|slave| slave.cycles > 0)) {
+                pending.push(format!("网关 {} 的首次采集", stats.name));
+            }
+        }
+        pending
+    }
+
+    /// 持续采集并热加载配置，`shutdown` 完成后停止
+    ///
+    /// 停止时依次停止采集（各从站发布 offline）、保存电量累计值、等各个输出处理完已收到的数据、
+    /// 关闭 MQTT 连接，然后返回。没有需要持续采集的网关（或 `once` 为 true）时不等待 `shutdown`，直接停止。
+    pub async fn run<S>(self, shutdown: S)
+    where
+        S: Future<Output = ()>,
+    {
+        let App {
+            stop, mut driver, ..
+        } = self;
+        tokio::select! {
+            _ = &mut driver => return,
+            _ = shutdown => {}
+        }
+        let _ = stop.send(());
+        driver.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockBroker, MockModbus, Received};
+
+    #[tokio::test]
+    async fn shutdown_reports_offline_then_disconnects_mqtt_last() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 0, &[42]);
+        let broker = MockBroker::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modbus_config.yaml");
+        let yaml = format!(
+            "version: 2\nmqtt:\n  broker_host: 127.0.0.1\n  broker_port: {}\n  client_id: shutdown-test\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    poll_interval_ms: 60000\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            broker.port, modbus.port
+        );
+        std::fs::write(&path, yaml).unwrap();
+        let path = path.to_str().unwrap();
+        let config = crate::read_config(path).unwrap();
+        let app = App::start(config, path, AppOptions::default()).await.unwrap();
+        let availability = "ems/PCS-A/meter/availability";
+        broker.wait_for_topic(availability, 1).await;
+        broker.wait_for_topic("ems/PCS-A/meter", 1).await;
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(app.run(async {
+            let _ = stopped.await;
+        }));
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(15), running).await.unwrap().unwrap();
+
+        // 依次为：各从站 offline、程序状态 offline、DISCONNECT
+        let received = broker.wait_for("DISCONNECT", |received| matches!(received.last(), Some(Received::Disconnect))).await;
+        let position = |topic: &str, payload: &[u8]| {
+            received
+                .iter()
+                .rposition(|p| matches!(p, Received::Publish(p) if p.topic == topic && p.payload == payload))
+                .unwrap_or_else(|| panic!("没有收到 {}", topic))
+        };
+        let offline = position(availability, b"offline");
+        let status = position("ems/shutdown-test/status", b"offline");
+        let last_data = position("ems/PCS-A/meter", &broker.payloads("ems/PCS-A/meter").pop().unwrap());
+        assert!(last_data < offline, "{:#?}", received);
+        assert!(offline < status, "{:#?}", received);
+        assert_eq!(status, received.len() - 2, "{:#?}", received);
+
+        // Modbus 连接全部断开，之后不再采集
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while modbus.closed() < modbus.connections() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let requests = modbus.requests().len();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(modbus.requests().len(), requests);
+    }
+
+    #[tokio::test]
+    async fn simulated_gateway_publishes_data_and_discovery_without_modbus() {
+        let broker = MockBroker::start().await;
+        // 模拟的网关不连接配置的地址，端口上没有任何服务
+        let unused = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = unused.local_addr().unwrap().port();
+        drop(unused);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("modbus_config.yaml");
+        let yaml = format!(
+            "version: 2\nmqtt:\n  broker_host: 127.0.0.1\n  broker_port: {}\n  client_id: sim-test\n  home_assistant: {{}}\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: SIM\n    simulation: true\n    poll_interval_ms: 100\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: voltage, address: 0, scale: 0.1, unit: V, sim: {{ kind: sine, min: 210, max: 240, period_s: 60 }} }}\n      - {{ name: running, function_code: 1, address: 0, data_type: bool, sim: {{ kind: toggle, period_s: 30 }} }}\n",
+            broker.port, port
+        );
+        std::fs::write(&path, yaml).unwrap();
+        let path = path.to_str().unwrap();
+        let config = crate::read_config(path).unwrap();
+        let app = App::start(config, path, AppOptions::default()).await.unwrap();
+
+        let payloads = broker.wait_for_topic("ems/SIM/meter", 3).await;
+        for payload in &payloads[..3] {
+            let value: serde_json::Value = serde_json::from_slice(payload).unwrap();
+            let voltage = value["values"]["voltage"].as_f64().unwrap();
+            assert!((210.0..=240.0).contains(&voltage), "{}", value);
+            assert_eq!(value["values"]["running"], 0.0, "{}", value);
+            assert!(value.get("quality").is_none(), "质量为 good 时不输出 quality: {}", value);
+        }
+        let availability = broker.wait_for_topic("ems/SIM/meter/availability", 1).await;
+        assert_eq!(availability.last().unwrap(), b"online");
+        let discovery = broker
+            .wait_for_topic("homeassistant/sensor/sim-test/SIM_meter_voltage/config", 1)
+            .await;
+        let discovery: serde_json::Value = serde_json::from_slice(&discovery[0]).unwrap();
+        assert_eq!(discovery["state_topic"], "ems/SIM/meter");
+        assert_eq!(discovery["unit_of_measurement"], "V");
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(app.run(async {
+            let _ = stopped.await;
+        }));
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(15), running).await.unwrap().unwrap();
+    }
+}
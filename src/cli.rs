@@ -0,0 +1,382 @@
+use std::error::Error;
+use std::time::Duration;
+
+use modbus_pub::modbus::decode::{DataType, WordOrder};
+
+/// 默认配置文件路径
+pub const DEFAULT_CONFIG_PATH: &str = "modbus_config.yaml";
+
+/// read/write 命令默认的连接和请求超时时间，单位毫秒
+pub const DEFAULT_TIMEOUT_MS: u64 = 3000;
+
+const USAGE: &str = "用法: modbus_pub [选项] [命令]
+
+命令:
+  run                  按配置文件采集（默认）
+  check                校验配置文件
+  profiles list        列出内置点位表和配置中的模板
+  profiles show <名称> 输出点位表展开后的点位定义
+  test-connection      测试配置中每个启用的从站能否连接和读取，并测量耗时
+  read                 不读取配置，直接读取一次寄存器并解析
+  write                不读取配置，直接写入一次寄存器
+
+选项:
+  --config <路径>      指定配置文件路径（默认 modbus_config.yaml）
+  --migrate-config     旧版本配置迁移后写回原文件，原文件备份为 .bak
+  --probe              check 时连接每个启用的网关，并对每个从站试读一次
+  --required-only      check --probe 时忽略 optional 网关的失败
+  --once               run 时每个网关只采集一次后退出，不持续采集
+  --daemon             run 时作为守护进程运行：写 PID 文件，就绪后通知 systemd，超时未就绪时退出
+  --simulate           run 时所有网关使用模拟数据，不连接设备
+  --json               test-connection、read、write 以 JSON 输出结果
+  -h, --help           显示帮助信息
+
+read/write 选项:
+  --host <地址>        网关 IP 地址（必填）
+  --port <端口>        网关端口（默认 502）
+  --slave <ID>         从站ID（默认 1）
+  --fc <功能码>        read 为 1-4（默认 3），write 为 5、6、15、16（默认按数据类型选择）
+  --address <地址>     起始地址，可以写成 0x 开头的十六进制
+  --count <数量>       read 读取的寄存器数量（默认为数据类型占用的数量，string 为字符串长度）
+  --type <类型>        数据类型 bool、u16、i16、u32、i32、f32、u64、i64、f64、string（默认 u16，write 不支持 string）
+  --order <字节序>     abcd、cdab、badc、dcba（默认 abcd，string 为 badc 时字内字节交换）
+  --scale <系数>       工程值 = 原始值 × scale（默认 1）
+  --value <值>         write 写入的工程值（必填）
+  --verify             write 后回读校验
+  --yes                write 时不再询问确认
+  --timeout <毫秒>     连接和请求超时（默认 3000）";
+
+/// 要执行的命令
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// 按配置文件采集
+    Run,
+    /// 校验配置文件
+    Check {
+        /// 是否连接设备探测
+        probe: bool,
+        /// 可选网关探测失败时不影响结果
+        required_only: bool,
+    },
+    /// 列出可用的点位表
+    ProfilesList,
+    /// 输出指定点位表的点位定义
+    ProfilesShow(String),
+    /// 测试每个从站的连通性
+    TestConnection {
+        /// 是否以 JSON 输出
+        json: bool,
+    },
+    /// 直接读取一次寄存器
+    Read(RegisterArgs),
+    /// 直接写入一次寄存器
+    Write(RegisterArgs),
+}
+
+/// read/write 命令的参数，不使用配置文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterArgs {
+    /// 网关 IP 地址
+    pub host: String,
+    /// 网关端口
+    pub port: u16,
+    /// 从站ID
+    pub slave: u8,
+    /// 指定的功能码，未指定时 read 为 0x03，write 按数据类型选择
+    pub function_code: Option<u8>,
+    /// 起始地址
+    pub address: u16,
+    /// read 读取的寄存器数量，未指定时为数据类型占用的数量
+    pub count: Option<u16>,
+    /// 数据类型
+    pub data_type: DataType,
+    /// 字节序
+    pub word_order: WordOrder,
+    /// 缩放系数
+    pub scale: f64,
+    /// write 写入的工程值
+    pub value: Option<f64>,
+    /// write 后是否回读校验
+    pub verify: bool,
+    /// write 时是否跳过确认
+    pub yes: bool,
+    /// 连接和请求超时
+    pub timeout: Duration,
+    /// 是否以 JSON 输出
+    pub json: bool,
+}
+
+// 解析过程中收集的 read/write 选项
+#[derive(Default)]
+struct RegisterOptions {
+    host: Option<String>,
+    port: Option<u16>,
+    slave: Option<u8>,
+    function_code: Option<u8>,
+    address: Option<u16>,
+    count: Option<u16>,
+    data_type: Option<DataType>,
+    word_order: Option<WordOrder>,
+    scale: Option<f64>,
+    value: Option<f64>,
+    verify: bool,
+    yes: bool,
+    timeout_ms: Option<u64>,
+    json: bool,
+    // 出现过的选项，用于报告不能用于其他命令的选项
+    used: Vec<String>,
+}
+
+impl RegisterOptions {
+    // 解析一个 read/write 选项，不是这类选项时返回 false
+    fn parse(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut value = || args.next().ok_or_else(|| format!("{} 需要指定值", arg));
+        match arg {
+            "--host" => self.host = Some(value()?),
+            "--port" => self.port = Some(parse_number(arg, &value()?)?),
+            "--slave" => self.slave = Some(parse_number(arg, &value()?)?),
+            "--fc" => self.function_code = Some(parse_number(arg, &value()?)?),
+            "--address" => self.address = Some(parse_number(arg, &value()?)?),
+            "--count" => self.count = Some(parse_number(arg, &value()?)?),
+            "--type" => self.data_type = Some(parse_name(arg, &value()?)?),
+            "--order" => self.word_order = Some(parse_name(arg, &value()?)?),
+            "--scale" => self.scale = Some(parse_float(arg, &value()?)?),
+            "--value" => self.value = Some(parse_float(arg, &value()?)?),
+            "--timeout" => self.timeout_ms = Some(parse_number(arg, &value()?)?),
+            "--verify" => self.verify = true,
+            "--yes" => self.yes = true,
+            "--json" => self.json = true,
+            _ => return Ok(false),
+        }
+        self.used.push(arg.to_string());
+        Ok(true)
+    }
+
+    // 检查并生成 read 或 write 的参数
+    fn build(self, write: bool) -> Result<RegisterArgs, Box<dyn Error>> {
+        let command = if write { "write" } else { "read" };
+        let host = self.host.ok_or_else(|| format!("{} 需要指定 --host", command))?;
+        let address = self.address.ok_or_else(|| format!("{} 需要指定 --address", command))?;
+        let slave = self.slave.unwrap_or(1);
+        if slave == 0 || slave > 247 {
+            return Err(format!("--slave 的范围为 1-247，当前为 {}", slave).into());
+        }
+        let data_type = self.data_type.unwrap_or_default();
+        let scale = self.scale.unwrap_or(1.0);
+        if scale == 0.0 || !scale.is_finite() {
+            return Err(format!("--scale 不能为0，当前为 {}", scale).into());
+        }
+        if let Some(function_code) = self.function_code {
+            let allowed: &[u8] = if write {
+                &[0x05, 0x06, 0x0F, 0x10]
+            } else {
+                &[0x01, 0x02, 0x03, 0x04]
+            };
+            if !allowed.contains(&function_code) {
+                return Err(format!("{} 不支持功能码 {}", command, function_code).into());
+            }
+        }
+        if write {
+            if data_type.is_string() {
+                return Err("write 不支持 --type string".into());
+            }
+            if self.value.is_none() {
+                return Err("write 需要指定 --value".into());
+            }
+            if self.count.is_some() {
+                return Err("write 的寄存器数量由 --type 决定，不能指定 --count".into());
+            }
+        } else {
+            if self.value.is_some() || self.verify || self.yes {
+                return Err("--value、--verify 和 --yes 只能用于 write 命令".into());
+            }
+            if data_type.is_string() && self.count.is_none() {
+                return Err("--type string 需要用 --count 指定字符串占用的寄存器数量".into());
+            }
+            if let Some(count) = self.count
+                && (count < data_type.register_count() || count > 125)
+            {
+                return Err(format!(
+                    "--count 必须在 {}-125 之间，当前为 {}",
+                    data_type.register_count(),
+                    count
+                )
+                .into());
+            }
+        }
+        if address as u32 + data_type.register_count() as u32 > u16::MAX as u32 + 1 {
+            return Err(format!("地址 {} 超出范围", address).into());
+        }
+        let timeout_ms = self.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+        if timeout_ms == 0 {
+            return Err("--timeout 必须大于0".into());
+        }
+        Ok(RegisterArgs {
+            host,
+            port: self.port.unwrap_or(502),
+            slave,
+            function_code: self.function_code,
+            address,
+            count: self.count,
+            data_type,
+            word_order: self.word_order.unwrap_or_default(),
+            scale,
+            value: self.value,
+            verify: self.verify,
+            yes: self.yes,
+            timeout: Duration::from_millis(timeout_ms),
+            json: self.json,
+        })
+    }
+}
+
+// 解析十进制或 0x 开头的十六进制整数
+fn parse_number<T>(option: &str, text: &str) -> Result<T, Box<dyn Error>>
+where
+    T: TryFrom<u64>,
+{
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("{} 的值 {} 不是合法的数字", option, text).into())
+}
+
+fn parse_float(option: &str, text: &str) -> Result<f64, Box<dyn Error>> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("{} 的值 {} 不是合法的数字", option, text).into())
+}
+
+// 按配置文件中的写法解析数据类型和字节序
+fn parse_name<T>(option: &str, text: &str) -> Result<T, Box<dyn Error>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    serde_yaml::from_str(&text.to_lowercase())
+        .map_err(|_| format!("{} 的值 {} 不合法", option, text).into())
+}
+
+/// 命令行参数
+#[derive(Debug, Clone)]
+pub struct Cli {
+    /// 要执行的命令
+    pub command: Command,
+    /// 配置文件路径
+    pub config_path: String,
+    /// 是否将迁移后的配置写回文件
+    pub migrate_config: bool,
+    /// 是否只采集一次后退出
+    pub once: bool,
+    /// 是否作为守护进程运行
+    pub daemon: bool,
+    /// 是否所有网关都使用模拟数据
+    pub simulate: bool,
+    /// 是否只显示帮助信息
+    pub help: bool,
+}
+
+impl Cli {
+    /// 解析命令行参数（不包含程序名）
+    pub fn parse<I>(args: I) -> Result<Cli, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cli = Cli {
+            command: Command::Run,
+            config_path: DEFAULT_CONFIG_PATH.to_string(),
+            migrate_config: false,
+            once: false,
+            daemon: false,
+            simulate: false,
+            help: false,
+        };
+
+        let mut probe = false;
+        let mut required_only = false;
+        let mut register = RegisterOptions::default();
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    cli.config_path = args.next().ok_or("--config 需要指定文件路径")?;
+                }
+                "--migrate-config" => cli.migrate_config = true,
+                "--probe" => probe = true,
+                "--required-only" => required_only = true,
+                "--once" => cli.once = true,
+                "--daemon" => cli.daemon = true,
+                "--simulate" => cli.simulate = true,
+                "-h" | "--help" => cli.help = true,
+                other if register.parse(other, &mut args)? => {}
+                other if other.starts_with('-') => {
+                    return Err(format!("未知参数: {}\n\n{}", other, USAGE).into());
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+        cli.command = match positional.as_slice() {
+            [] | ["run"] => Command::Run,
+            ["check"] => Command::Check {
+                probe,
+                required_only,
+            },
+            ["profiles", "list"] => Command::ProfilesList,
+            ["profiles", "show", name] => Command::ProfilesShow(name.to_string()),
+            ["profiles", "show"] => return Err("profiles show 需要指定点位表名称".into()),
+            ["test-connection"] => {
+                // --json 也可以用于 test-connection
+                register.used.retain(|option| option != "--json");
+                Command::TestConnection {
+                    json: register.json,
+                }
+            }
+            ["read"] => Command::Read(std::mem::take(&mut register).build(false)?),
+            ["write"] => Command::Write(std::mem::take(&mut register).build(true)?),
+            other => {
+                return Err(format!("未知命令: {}\n\n{}", other.join(" "), USAGE).into());
+            }
+        };
+        if !matches!(cli.command, Command::Check { .. }) && (probe || required_only) {
+            return Err("--probe 和 --required-only 只能用于 check 命令".into());
+        }
+        if cli.once && cli.command != Command::Run {
+            return Err("--once 只能用于 run 命令".into());
+        }
+        if cli.daemon && cli.command != Command::Run {
+            return Err("--daemon 只能用于 run 命令".into());
+        }
+        if cli.daemon && cli.once {
+            return Err("--daemon 不能与 --once 一起使用".into());
+        }
+        if cli.simulate && cli.command != Command::Run {
+            return Err("--simulate 只能用于 run 命令".into());
+        }
+        if let Some(option) = register.used.first() {
+            if option == "--json" {
+                return Err("--json 只能用于 test-connection、read 和 write 命令".into());
+            }
+            return Err(format!("{} 只能用于 read 和 write 命令", option).into());
+        }
+        if required_only && !probe {
+            return Err("--required-only 需要与 --probe 一起使用".into());
+        }
+        Ok(cli)
+    }
+
+    /// 帮助信息
+    pub fn usage() -> &'static str {
+        USAGE
+    }
+}
@@ -0,0 +1,786 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::cli::RegisterArgs;
+use modbus_pub::device_configuration::modbus::{read_config, Config};
+use modbus_pub::device_configuration::point::Point;
+use modbus_pub::device_configuration::profiles;
+use modbus_pub::modbus::decode::decode;
+use modbus_pub::modbus::probe::{
+    probe_gateway, test_connection as test_gateway, ConnectionTest, ProbeResult, ProbeStatus,
+    DEFAULT_PROBE_TIMEOUT, TEST_CONNECTION_CONCURRENCY,
+};
+use modbus_pub::mqtt::client::mqtt_options;
+use modbus_pub::{ModbusClient, ModbusDevice, ModbusOperation};
+
+/// 加载配置文件中的模板；配置文件不存在时只使用内置点位表，不会创建新文件
+fn load_templates(config_path: &str) -> Result<Config, Box<dyn Error>> {
+    if Path::new(config_path).try_exists()? {
+        read_config(config_path)
+    } else {
+        Ok(Config::default())
+    }
+}
+
+/// `profiles list`：列出内置点位表和配置文件中的模板
+///
+/// 每个内置点位表都会重新解析和校验，损坏的点位表会直接报错。
+pub fn profiles_list(config_path: &str) -> Result<(), Box<dyn Error>> {
+    let config = load_templates(config_path)?;
+
+    println!("内置点位表:");
+    for name in profiles::names() {
+        let profile = profiles::get(name)?.ok_or_else(|| format!("内置点位表 {} 不存在", name))?;
+        let shadowed = if config.templates.contains_key(name) {
+            "（被同名模板覆盖）"
+        } else {
+            ""
+        };
+        println!(
+            "  {:<20} {} 个点位  {}{}",
+            name,
+            profile.points.len(),
+            profile.description,
+            shadowed
+        );
+    }
+
+    if !config.templates.is_empty() {
+        println!("\n配置文件模板:");
+        for (name, points) in &config.templates {
+            println!("  {:<20} {} 个点位", name, points.len());
+        }
+    }
+    Ok(())
+}
+
+/// `profiles show <名称>`：以 YAML 输出点位表展开后的点位，同名时优先使用配置中的模板
+pub fn profiles_show(config_path: &str, name: &str) -> Result<(), Box<dyn Error>> {
+    let config = load_templates(config_path)?;
+    let points = config.profile_points(name)?;
+    print!("{}", serde_yaml::to_string(&points)?);
+    Ok(())
+}
+
+/// `check`：校验配置文件，`probe` 为 true 时再逐个连接设备探测
+///
+/// # 参数说明
+/// * `config_path` - 配置文件路径，文件不存在时报错而不是创建空文件
+/// * `probe` - 是否连接每个启用的网关并试读每个从站
+/// * `required_only` - 为 true 时 optional 网关的探测失败不影响结果
+///
+/// # 返回值
+/// * `Err` - 配置校验失败，或有需要关注的探测项失败
+pub async fn check(config_path: &str, probe: bool, required_only: bool) -> Result<(), Box<dyn Error>> {
+    if !Path::new(config_path).try_exists()? {
+        return Err(format!("配置文件 {} 不存在", config_path).into());
+    }
+    let config = read_config(config_path)?;
+
+    let slaves: usize = config.gateways.iter().map(|g| g.slave_ids.len()).sum();
+    let mut points = 0;
+    for gateway in &config.gateways {
+        for slave in &gateway.slave_ids {
+            points += config.effective_points(gateway, slave)?.len();
+        }
+    }
+    println!(
+        "配置校验通过: {} 个网关，{} 个从站，{} 个点位",
+        config.gateways.len(),
+        slaves,
+        points
+    );
+    // TLS 证书文件在启动时才会读取，这里提前检查
+    if let Some(settings) = &config.mqtt
+        && settings.tls.is_some()
+    {
+        mqtt_options(settings)?;
+        println!("MQTT TLS 证书检查通过");
+    }
+    if !probe {
+        return Ok(());
+    }
+
+    // 各网关同时探测，结果按配置顺序输出
+    let config = Arc::new(config);
+    let mut probes = JoinSet::new();
+    for (index, gateway) in config.gateways.iter().enumerate() {
+        if !gateway.enabled {
+            println!("网关 {} 已停用，跳过探测", gateway.display_name());
+            continue;
+        }
+        let config = Arc::clone(&config);
+        probes.spawn(async move {
+            let gateway = &config.gateways[index];
+            (index, probe_gateway(&config, gateway, DEFAULT_PROBE_TIMEOUT).await)
+        });
+    }
+    let mut reports = Vec::new();
+    while let Some(report) = probes.join_next().await {
+        reports.push(report?);
+    }
+    reports.sort_by_key(|(index, _)| *index);
+    let results: Vec<ProbeResult> = reports.into_iter().flat_map(|(_, r)| r).collect();
+
+    print_probe_table(&results);
+
+    let failed = results
+        .iter()
+        .filter(|r| r.status.is_failure() && !(required_only && r.optional))
+        .count();
+    let ignored = results
+        .iter()
+        .filter(|r| r.status.is_failure() && required_only && r.optional)
+        .count();
+    if ignored > 0 {
+        println!("{} 项可选设备探测失败，已忽略", ignored);
+    }
+    if failed > 0 {
+        return Err(format!("{} 项探测失败", failed).into());
+    }
+    println!("全部探测通过");
+    Ok(())
+}
+
+/// `test-connection`：测试配置中每个启用的从站能否连接和读取，输出连接耗时和响应时间
+///
+/// # 说明
+/// * 最多同时测试 [`TEST_CONNECTION_CONCURRENCY`] 个网关，结果按配置顺序输出
+/// * 有非可选网关的从站失败时返回错误，以非零退出码结束
+pub async fn test_connection(config_path: &str, json: bool) -> Result<(), Box<dyn Error>> {
+    test_connection_to(config_path, json, &mut io::stdout()).await
+}
+
+// 执行 test-connection，结果输出到 out
+async fn test_connection_to(
+    config_path: &str,
+    json: bool,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn Error>> {
+    if !Path::new(config_path).try_exists()? {
+        return Err(format!("配置文件 {} 不存在", config_path).into());
+    }
+    let config = Arc::new(read_config(config_path)?);
+
+    let permits = Arc::new(Semaphore::new(TEST_CONNECTION_CONCURRENCY));
+    let mut tests = JoinSet::new();
+    for (index, gateway) in config.gateways.iter().enumerate() {
+        if !gateway.enabled {
+            continue;
+        }
+        let config = Arc::clone(&config);
+        let permits = Arc::clone(&permits);
+        tests.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let gateway = &config.gateways[index];
+            (index, test_gateway(&config, gateway).await)
+        });
+    }
+    let mut reports = Vec::new();
+    while let Some(report) = tests.join_next().await {
+        reports.push(report?);
+    }
+    reports.sort_by_key(|(index, _)| *index);
+    let results: Vec<ConnectionTest> = reports.into_iter().flat_map(|(_, r)| r).collect();
+
+    if json {
+        let rows: Vec<serde_json::Value> = results
+            .iter()
+            .map(|r| {
+                let mut row = json!({
+                    "host": r.host,
+                    "port": r.port,
+                    "slave": r.slave,
+                    "name": r.name,
+                    "connect_ms": r.connect_time.map(millis),
+                    "latency_ms": r.latency.map(millis),
+                    "result": r.status.code(),
+                    "optional": r.optional,
+                });
+                if let ProbeStatus::Failed(message) = &r.status {
+                    row["error"] = json!(message);
+                }
+                row
+            })
+            .collect();
+        writeln!(out, "{}", serde_json::Value::Array(rows))?;
+    } else {
+        print_connection_table(out, &results)?;
+    }
+
+    let failed = results
+        .iter()
+        .filter(|r| r.status.is_failure() && !r.optional)
+        .count();
+    if failed > 0 {
+        return Err(format!("{} 个从站连接测试失败", failed).into());
+    }
+    Ok(())
+}
+
+// 毫秒，保留一位小数
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 10_000.0).round() / 10.0
+}
+
+// 输出连接测试结果表
+fn print_connection_table(out: &mut impl Write, results: &[ConnectionTest]) -> io::Result<()> {
+    let format_time = |time: Option<Duration>| time.map_or("-".to_string(), |t| format!("{:.1} ms", millis(t)));
+    let rows: Vec<[String; 7]> = results
+        .iter()
+        .map(|r| {
+            let optional = if r.optional && r.status.is_failure() { "（可选）" } else { "" };
+            [
+                r.host.clone(),
+                r.port.to_string(),
+                r.slave.to_string(),
+                r.name.clone(),
+                format_time(r.connect_time),
+                format_time(r.latency),
+                format!("{}{}", r.status, optional),
+            ]
+        })
+        .collect();
+    let header = ["地址", "端口", "从站", "名称", "连接耗时", "响应时间", "结果"].map(str::to_string);
+    let mut widths = [0; 7];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    writeln!(out)?;
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - display_width(cell))))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+    writeln!(out)
+}
+
+// 输出探测结果表
+fn print_probe_table(results: &[ProbeResult]) {
+    let width = results
+        .iter()
+        .map(|r| display_width(&r.item))
+        .max()
+        .unwrap_or(0);
+    println!();
+    for result in results {
+        let padding = " ".repeat(width - display_width(&result.item));
+        let optional = if result.optional { "（可选）" } else { "" };
+        println!("{}{}  {}{}", result.item, padding, result.status, optional);
+    }
+    println!();
+}
+
+// 终端显示宽度，中文等全角字符按两列计算
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/// `read`：不读取配置，直接读取一次寄存器，输出原始寄存器值和解析后的值
+///
+/// 解析使用与采集相同的 [`Point::value_from`]，寄存器数量多于数据类型占用的数量时只解析前面的寄存器。
+pub async fn read(args: &RegisterArgs) -> Result<(), Box<dyn Error>> {
+    read_to(args, &mut io::stdout()).await
+}
+
+// 执行 read，结果输出到 out
+async fn read_to(args: &RegisterArgs, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let point = register_point(args, args.function_code.unwrap_or(0x03));
+    let count = args.count.unwrap_or(point.register_count());
+    let mut client = connect(args).await?;
+    let result = client
+        .read_registers(point.function_code, args.address, count)
+        .await;
+    let _ = client.disconnect().await;
+    let registers = result?;
+    let raw = decode(point.data_type, point.word_order, &registers);
+    let value = point.value_from(&registers);
+    let text = point.text_from(&registers);
+
+    if args.json {
+        let output = json!({
+            "host": args.host,
+            "port": args.port,
+            "slave": args.slave,
+            "function_code": point.function_code,
+            "address": args.address,
+            "count": count,
+            "registers": registers,
+            "data_type": point.data_type,
+            "word_order": point.word_order,
+            "scale": point.scale,
+            "raw_value": raw,
+            "value": text.as_ref().map_or_else(|| json!(value), |text| json!(text)),
+        });
+        writeln!(out, "{}", output)?;
+        return Ok(());
+    }
+    print_registers(out, args.address, &registers)?;
+    let name = format!("{} {:?}", point.data_type.as_str(), point.word_order).to_lowercase();
+    if let Some(text) = text {
+        writeln!(out, "{} = {:?}", name, text)?;
+        return Ok(());
+    }
+    match (raw, value) {
+        (Some(raw), Some(value)) if point.scale != 1.0 => {
+            writeln!(out, "{} = {}（原始值 {} × {}）", name, value, raw, point.scale)?;
+        }
+        (_, Some(value)) => writeln!(out, "{} = {}", name, value)?,
+        _ => writeln!(out, "寄存器数量不足，无法解析为 {:?}", point.data_type)?,
+    }
+    Ok(())
+}
+
+/// `write`：不读取配置，直接写入一次寄存器
+///
+/// # 说明
+/// * 工程值按 scale 换算后编码，与 MQTT 写入命令使用相同的 [`Point::registers_for_value`]
+/// * 未指定 `--yes` 时先输出要写入的内容，在标准输入确认后才写入
+/// * 指定 `--verify` 时写入后回读，寄存器值不一致时返回错误
+pub async fn write(args: &RegisterArgs) -> Result<(), Box<dyn Error>> {
+    write_to(args, &mut io::stdout()).await
+}
+
+// 执行 write，结果输出到 out
+async fn write_to(args: &RegisterArgs, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    // 线圈的写入功能码对应读取功能码 0x01，寄存器对应 0x03
+    let read_code = match args.function_code {
+        Some(0x05 | 0x0F) => 0x01,
+        _ => 0x03,
+    };
+    let point = register_point(args, read_code);
+    let function_code = match args.function_code {
+        Some(code) => code,
+        None => point.write_function_code().ok_or("无法确定写入功能码")?,
+    };
+    let value = args.value.ok_or("write 需要指定 --value")?;
+    let registers = point.registers_for_value(value)?;
+    if matches!(function_code, 0x05 | 0x06) && registers.len() != 1 {
+        return Err(format!(
+            "功能码 {} 只能写入单个寄存器或线圈，{:?} 需要 {} 个",
+            function_code,
+            point.data_type,
+            registers.len()
+        )
+        .into());
+    }
+
+    if !args.yes && !confirm(args, function_code, &registers)? {
+        return Err("已取消写入".into());
+    }
+
+    let mut client = connect(args).await?;
+    let result = write_and_verify(&mut client, args, &point, function_code, &registers).await;
+    let _ = client.disconnect().await;
+    let read_back = result?;
+    let verified = read_back.as_ref().map(|r| *r == registers);
+
+    if args.json {
+        let output = json!({
+            "host": args.host,
+            "port": args.port,
+            "slave": args.slave,
+            "function_code": function_code,
+            "address": args.address,
+            "registers": registers,
+            "data_type": point.data_type,
+            "word_order": point.word_order,
+            "scale": point.scale,
+            "value": value,
+            "verified": verified,
+            "read_back": read_back,
+        });
+        writeln!(out, "{}", output)?;
+    } else {
+        writeln!(out, "写入成功")?;
+        if let Some(read_back) = &read_back {
+            print_registers(out, args.address, read_back)?;
+        }
+    }
+    if verified == Some(false) {
+        return Err(format!(
+            "回读的寄存器值 {:?} 与写入的 {:?} 不一致",
+            read_back.unwrap_or_default(),
+            registers
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// 用命令行参数构造一个临时点位，解码、编码与配置中的点位完全一致
+fn register_point(args: &RegisterArgs, function_code: u8) -> Point {
+    Point {
+        name: format!("{}", args.address),
+        function_code,
+        address: args.address,
+        data_type: args.data_type,
+        // 字符串的长度为读取的寄存器数量
+        length: args.data_type.is_string().then(|| args.count.unwrap_or(1)),
+        word_order: args.word_order,
+        scale: args.scale,
+        offset: 0.0,
+        scale_factor: None,
+        unit: None,
+        source_unit: None,
+        group: None,
+        enabled: true,
+        writable: true,
+        verify_write: args.verify,
+        qos: None,
+        retain: None,
+        deadband: None,
+        device_class: None,
+        sim: None,
+        bits: BTreeMap::new(),
+        bit: None,
+        conversion: None,
+    }
+}
+
+async fn connect(args: &RegisterArgs) -> Result<ModbusClient, Box<dyn Error>> {
+    let mut client = ModbusClient::new(ModbusDevice {
+        name: None,
+        ip: args.host.clone(),
+        port: args.port,
+        slave_id: args.slave,
+        connect_timeout: args.timeout,
+        request_timeout: args.timeout,
+    });
+    client.connect().await?;
+    Ok(client)
+}
+
+// 写入后按需回读，返回回读的寄存器值
+async fn write_and_verify(
+    client: &mut ModbusClient,
+    args: &RegisterArgs,
+    point: &Point,
+    function_code: u8,
+    registers: &[u16],
+) -> Result<Option<Vec<u16>>, Box<dyn Error>> {
+    client
+        .write_registers(
+            function_code,
+            args.address,
+            registers.len() as u16,
+            registers.to_vec(),
+        )
+        .await?;
+    if !args.verify {
+        return Ok(None);
+    }
+    let read_back = client
+        .read_registers(point.function_code, args.address, registers.len() as u16)
+        .await?;
+    Ok(Some(read_back))
+}
+
+// 输出要写入的内容并在标准输入确认，提示输出到标准错误，不影响 --json 的结果
+fn confirm(args: &RegisterArgs, function_code: u8, registers: &[u16]) -> Result<bool, Box<dyn Error>> {
+    let mut stderr = io::stderr();
+    writeln!(
+        stderr,
+        "将向 {}:{} 从站 {} 功能码 {} 地址 {} 写入 {:?}（{}，值 {}）",
+        args.host,
+        args.port,
+        args.slave,
+        function_code,
+        args.address,
+        registers,
+        format!("{:?} {:?}", args.data_type, args.word_order).to_lowercase(),
+        args.value.unwrap_or_default()
+    )?;
+    write!(stderr, "确认写入？[y/N] ")?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+// 每行输出一个寄存器的地址、十进制和十六进制值
+fn print_registers(out: &mut impl Write, address: u16, registers: &[u16]) -> io::Result<()> {
+    for (index, register) in registers.iter().enumerate() {
+        writeln!(
+            out,
+            "{:>5}  {:>5}  0x{:04X}",
+            address as usize + index,
+            register,
+            register
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Cli, Command};
+    use modbus_pub::test_support::MockModbus;
+    use serde_json::Value;
+
+    // 按命令行解析参数，与 `ems read ...` / `ems write ...` 相同
+    fn args(command: &str, port: u16, options: &str) -> RegisterArgs {
+        let line = format!("{} --host 127.0.0.1 --port {} {}", command, port, options);
+        match Cli::parse(line.split_whitespace().map(str::to_string)).unwrap().command {
+            Command::Read(args) | Command::Write(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    async fn run_read(args: &RegisterArgs) -> Result<String, Box<dyn Error>> {
+        let mut out = Vec::new();
+        read_to(args, &mut out).await?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    async fn run_write(args: &RegisterArgs) -> (Result<(), Box<dyn Error>>, String) {
+        let mut out = Vec::new();
+        let result = write_to(args, &mut out).await;
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[tokio::test]
+    async fn read_prints_registers_and_decoded_value_as_json() {
+        let mock = MockModbus::start().await;
+        // 50.0 的 f32 为 0x42480000，cdab 字序下低位字在前
+        mock.set(7, 4, 3059, &[0x0000, 0x4248]);
+        let options = "--slave 7 --fc 4 --address 3059 --count 2 --type f32 --order cdab --scale 0.1 --json";
+        let output = run_read(&args("read", mock.port, options)).await.unwrap();
+        let json: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "host": "127.0.0.1",
+                "port": mock.port,
+                "slave": 7,
+                "function_code": 4,
+                "address": 3059,
+                "count": 2,
+                "registers": [0, 0x4248],
+                "data_type": "f32",
+                "word_order": "cdab",
+                "scale": 0.1,
+                "raw_value": 50.0,
+                "value": 5.0,
+            })
+        );
+        assert_eq!(output.lines().count(), 1, "--json 只输出一行");
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(
+            (request.slave_id, request.function_code, request.address, request.quantity),
+            (7, 4, 3059, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_prints_register_table_and_text() {
+        let mock = MockModbus::start().await;
+        mock.set(1, 3, 10, &[0x0102, 0xFFFF]);
+        let options = "--address 10 --count 2 --type i16 --scale 0.5";
+        let output = run_read(&args("read", mock.port, options)).await.unwrap();
+        assert_eq!(
+            output,
+            "   10    258  0x0102\n   11  65535  0xFFFF\ni16 abcd = 129（原始值 258 × 0.5）\n"
+        );
+
+        // 字符串的长度为读取的寄存器数量，JSON 中的 value 为文本
+        mock.set(1, 3, 20, &[0x4142, 0x4300]);
+        let options = "--address 20 --count 2 --type string --json";
+        let output = run_read(&args("read", mock.port, options)).await.unwrap();
+        let json: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json["value"], "ABC");
+    }
+
+    #[tokio::test]
+    async fn write_encodes_value_and_verifies_read_back() {
+        let mock = MockModbus::start().await;
+        let options = "--slave 7 --address 100 --type f32 --order cdab --value 50.0 --verify --yes --json";
+        let (result, output) = run_write(&args("write", mock.port, options)).await;
+        result.unwrap();
+        let json: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "host": "127.0.0.1",
+                "port": mock.port,
+                "slave": 7,
+                "function_code": 16,
+                "address": 100,
+                "registers": [0, 0x4248],
+                "data_type": "f32",
+                "word_order": "cdab",
+                "scale": 1.0,
+                "value": 50.0,
+                "verified": true,
+                "read_back": [0, 0x4248],
+            })
+        );
+        assert_eq!(mock.get(7, 3, 100, 2), [0, 0x4248]);
+        let codes: Vec<_> = mock.requests().iter().map(|r| r.function_code).collect();
+        assert_eq!(codes, [16, 3], "写入后回读");
+
+        // 缩放后写入单个寄存器，不回读时 verified 和 read_back 为 null
+        let options = "--address 5 --type u16 --scale 0.1 --value 23.4 --yes --json";
+        let (result, output) = run_write(&args("write", mock.port, options)).await;
+        result.unwrap();
+        let json: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(json["function_code"], 6);
+        assert_eq!(json["registers"], json!([234]));
+        assert!(json["verified"].is_null() && json["read_back"].is_null());
+        assert_eq!(mock.get(1, 3, 5, 1), [234]);
+
+        let options = "--address 6 --value 1 --yes";
+        let (result, output) = run_write(&args("write", mock.port, options)).await;
+        result.unwrap();
+        assert_eq!(output, "写入成功\n");
+    }
+
+    #[tokio::test]
+    async fn write_errors_are_reported_without_output() {
+        let mock = MockModbus::start().await;
+
+        // 功能码 6 不能写入两个寄存器，在连接前就返回错误
+        let options = "--fc 6 --address 1 --type f32 --value 1 --yes";
+        let (result, output) = run_write(&args("write", mock.port, options)).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "功能码 6 只能写入单个寄存器或线圈，F32 需要 2 个"
+        );
+        assert!(output.is_empty());
+        assert_eq!(mock.connections(), 0);
+
+        // 异常响应
+        mock.reject(1, 2);
+        let options = "--address 1 --value 1 --yes --json";
+        let (result, output) = run_write(&args("write", mock.port, options)).await;
+        assert!(result.is_err());
+        assert!(output.is_empty());
+        assert!(run_read(&args("read", mock.port, "--address 1")).await.is_err());
+
+        // 不应答时按 --timeout 超时
+        mock.restore(1);
+        mock.silence(1);
+        let started = std::time::Instant::now();
+        let options = "--address 1 --value 1 --yes --timeout 200";
+        let (result, _) = run_write(&args("write", mock.port, options)).await;
+        assert!(result.is_err());
+        let elapsed = started.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(2),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    // 已经关闭的端口，连接时被拒绝
+    async fn closed_port() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    async fn run_test_connection(
+        gateways: &str,
+        json: bool,
+    ) -> (Result<(), Box<dyn Error>>, String) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), format!("version: 2\ngateways:\n{}", gateways)).unwrap();
+        let mut out = Vec::new();
+        let result = test_connection_to(file.path().to_str().unwrap(), json, &mut out).await;
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    fn gateway(name: &str, port: u16, extra: &str) -> String {
+        format!(
+            "  - {{ ip: 127.0.0.1, port: {}, name: {}, request_timeout_ms: 300, slave_ids: [1]{} }}\n",
+            port, name, extra
+        )
+    }
+
+    #[tokio::test]
+    async fn test_connection_outputs_json_row_per_slave() {
+        let live = MockModbus::start().await;
+        let (closed, optional) = (closed_port().await, closed_port().await);
+        let gateways = format!(
+            "{}{}{}",
+            gateway("PCS-A", live.port, ""),
+            gateway("PCS-B", closed, ""),
+            gateway("METER", optional, ", optional: true"),
+        );
+        let (result, output) = run_test_connection(&gateways, true).await;
+        assert_eq!(result.unwrap_err().to_string(), "1 个从站连接测试失败");
+
+        let rows: Vec<Value> = serde_json::from_str(&output).unwrap();
+        let summary: Vec<_> = rows
+            .iter()
+            .map(|r| {
+                let port = r["port"].as_u64().unwrap() as u16;
+                (port, r["name"].clone(), r["result"].clone(), r["optional"].clone())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (live.port, json!("PCS-A/1"), json!("ok"), json!(false)),
+                (closed, json!("PCS-B/1"), json!("refused"), json!(false)),
+                (optional, json!("METER/1"), json!("refused"), json!(true)),
+            ]
+        );
+        assert!(rows[0]["connect_ms"].is_f64() && rows[0]["latency_ms"].is_f64());
+        assert!(rows[1]["connect_ms"].is_null() && rows[1]["latency_ms"].is_null());
+        assert_eq!((rows[0]["host"].clone(), rows[0]["slave"].clone()), (json!("127.0.0.1"), json!(1)));
+
+        // 只有可选网关失败时成功结束，表格中标注可选
+        let gateways = format!(
+            "{}{}",
+            gateway("PCS-A", live.port, ""),
+            gateway("METER", optional, ", optional: true")
+        );
+        let (result, output) = run_test_connection(&gateways, false).await;
+        result.unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", output);
+        assert!(lines[1].starts_with("地址"));
+        assert!(lines[2].contains("PCS-A/1") && lines[2].ends_with("OK"));
+        assert!(lines[3].contains("METER/1") && lines[3].ends_with("拒绝连接（可选）"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_runs_gateways_concurrently() {
+        // 每个网关都要等 300ms 超时，逐个测试至少需要 1.2s
+        let mut servers = Vec::new();
+        let mut gateways = String::new();
+        for name in ["A", "B", "C", "D"] {
+            let server = MockModbus::start().await;
+            server.silence(1);
+            gateways.push_str(&gateway(name, server.port, ""));
+            servers.push(server);
+        }
+        let started = std::time::Instant::now();
+        let (result, output) = run_test_connection(&gateways, true).await;
+        let elapsed = started.elapsed();
+        assert_eq!(result.unwrap_err().to_string(), "4 个从站连接测试失败");
+        assert!(elapsed < Duration::from_millis(900), "{:?}", elapsed);
+
+        // 结果按配置顺序输出
+        let rows: Vec<Value> = serde_json::from_str(&output).unwrap();
+        let names: Vec<_> = rows.iter().map(|r| r["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["A/1", "B/1", "C/1", "D/1"]);
+        assert!(rows.iter().all(|r| r["result"] == "timeout"));
+        assert!(servers.iter().all(|s| s.connections() == 1));
+    }
+}
@@ -0,0 +1,384 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::device_configuration::computed::{PointReference, COMPUTED_GATEWAY};
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::PublishOptions;
+use crate::expression::Expression;
+use crate::latest::{ReadingCache, SharedCache};
+use crate::modbus::reading::{Quality, Reading};
+
+// 一个计算点位
+struct Computed {
+    name: String,
+    unit: Option<String>,
+    expression: Expression,
+    inputs: Vec<(String, PointReference)>,
+    max_age: Duration,
+    // 上一次计算使用的各输入的采集时间，与 inputs 一一对应
+    used: Vec<Option<SystemTime>>,
+    // 上一次计算的结果是否为 bad，只在变化时输出日志
+    bad: bool,
+}
+
+// 一个输入点位最近一次的值
+#[derive(Debug, Clone, Copy)]
+struct Input {
+    value: f64,
+    quality: Quality,
+    timestamp: SystemTime,
+}
+
+/// 由其他点位计算得到的点位
+///
+/// # 说明
+/// * 收到输入点位的新数据后尝试计算，各输入的值从 [`ReadingCache`] 中读取，缓存需要先于计算点位更新
+/// * 所有输入都在上次计算后更新过，且最早和最新的输入相差不超过 `max_age_ms` 时才计算，
+///   输入来自多个网关时每个采集周期只在最后一个输入到达时计算一次
+/// * 计算结果的时间为最新输入的采集时间，属于网关 [`COMPUTED_GATEWAY`]、从站ID 0
+/// * 任一输入质量不为 good（bad 或 stale）或除以0时，输出值为 NaN、质量为 bad 的点位
+pub struct ComputedPoints {
+    points: Vec<Computed>,
+    cache: SharedCache,
+    publish: PublishOptions,
+}
+
+/// 可在采集任务间共享的计算点位
+pub type SharedComputed = Arc<Mutex<ComputedPoints>>;
+
+impl ComputedPoints {
+    /// 按配置中的计算点位创建
+    ///
+    /// # 参数说明
+    /// * `config` - 配置
+    /// * `cache` - 读取输入点位当前值的缓存
+    pub fn new(config: &Config, cache: SharedCache) -> Result<Self, Box<dyn Error>> {
+        let mut computed = ComputedPoints {
+            points: Vec::new(),
+            cache,
+            publish: PublishOptions::default(),
+        };
+        computed.apply(config)?;
+        Ok(computed)
+    }
+
+    /// 应用新的配置
+    pub fn apply(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        let mut points = Vec::new();
+        for point in &config.computed_points {
+            let resolved = point.resolve(config)?;
+            points.push(Computed {
+                name: point.name.clone(),
+                unit: point.unit.clone(),
+                expression: resolved.expression,
+                used: vec![None; resolved.inputs.len()],
+                inputs: resolved.inputs,
+                max_age: resolved.max_age,
+                bad: false,
+            });
+        }
+        self.points = points;
+        self.publish = config.default_publish_options();
+        Ok(())
+    }
+
+    /// 收到一批采集数据（已记录到缓存中）后，返回因此可以计算的计算点位
+    pub fn process(&mut self, readings: &[Reading]) -> Vec<Reading> {
+        let touched = readings.iter().any(|reading| {
+            self.points
+                .iter()
+                .any(|p| {
+                    p.inputs.iter().any(|(_, r)| {
+                        r.gateway == reading.gateway
+                            && r.slave_id == reading.slave_id
+                            && r.point == reading.point
+                    })
+                })
+        });
+        if !touched {
+            return Vec::new();
+        }
+        let mut results = Vec::new();
+        for point in &mut self.points {
+            if let Some(reading) = point.compute(&self.cache, &self.publish) {
+                results.push(reading);
+            }
+        }
+        results
+    }
+}
+
+impl From<&Reading> for Input {
+    fn from(reading: &Reading) -> Self {
+        Input {
+            value: reading.value,
+            quality: reading.quality,
+            timestamp: reading.timestamp,
+        }
+    }
+}
+
+impl Computed {
+    // 输入都已更新且足够接近时计算
+    fn compute(
+        &mut self,
+        cache: &ReadingCache,
+        publish: &PublishOptions,
+    ) -> Option<Reading> {
+        let mut inputs = Vec::with_capacity(self.inputs.len());
+        for ((_, reference), used) in self.inputs.iter().zip(&self.used) {
+            let input = cache
+                .get(&reference.gateway, reference.slave_id, &reference.point)
+                .map(|reading| Input::from(&reading))?;
+            if used.is_some_and(|used| input.timestamp <= used) {
+                return None;
+            }
+            inputs.push(input);
+        }
+        let newest = inputs.iter().map(|i| i.timestamp).max()?;
+        let oldest = inputs.iter().map(|i| i.timestamp).min()?;
+        if newest.duration_since(oldest).unwrap_or_default() > self.max_age {
+            return None;
+        }
+        self.used = inputs.iter().map(|i| Some(i.timestamp)).collect();
+
+        let result = if inputs.iter().any(|i| i.quality != Quality::Good) {
+            Err("输入点位的质量为 bad".to_string())
+        } else {
+            let lookup = |name: &str| {
+                let index = self.inputs.iter().position(|(n, _)| n == name)?;
+                Some(inputs[index].value)
+            };
+            match self.expression.evaluate(lookup) {
+                Ok(value) if value.is_finite() => Ok(value),
+                Ok(value) => Err(format!("计算结果为 {}", value)),
+                Err(e) => Err(e.to_string()),
+            }
+        };
+        let (value, quality) = match result {
+            Ok(value) => {
+                if self.bad {
+                    info!(point = %self.name, "计算点位恢复正常");
+                }
+                self.bad = false;
+                (value, Quality::Good)
+            }
+            Err(reason) => {
+                if !self.bad {
+                    warn!(
+                        point = %self.name,
+                        expression = %self.expression,
+                        reason = %reason,
+                        "计算点位的值不可用"
+                    );
+                }
+                self.bad = true;
+                (f64::NAN, Quality::Bad)
+            }
+        };
+        Some(Reading {
+            gateway: COMPUTED_GATEWAY.to_string(),
+            gateway_name: None,
+            slave_id: 0,
+            slave_name: None,
+            point: self.name.clone(),
+            group: None,
+            value,
+            text: None,
+            raw: Vec::new(),
+            unit: self.unit.clone(),
+            timestamp: newest,
+            quality,
+            publish: *publish,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::computed::ComputedPoint;
+    use crate::test_support::{at, reading};
+
+    const CONFIG: &str = "version: 2\ngateways:\n  - ip: 10.0.0.1\n    port: 502\n    name: PCS-A\n    poll_interval_ms: 1000\n    slave_ids: [{ id: 1, name: meter_a }]\n    points:\n      - { name: power, address: 0 }\n      - { name: dc_power, address: 1 }\n  - ip: 10.0.0.2\n    port: 502\n    name: PCS-B\n    poll_interval_ms: 2000\n    slave_ids: [{ id: 1, name: meter_b }]\n    points:\n      - { name: power, address: 0 }\n";
+
+    fn config(computed: &str) -> Config {
+        crate::test_support::config(&format!("{}{}", CONFIG, computed))
+    }
+
+    fn validation_error(computed: &str) -> String {
+        let config: Config = serde_yaml::from_str(&format!("{}{}", CONFIG, computed)).unwrap();
+        config.validate().unwrap_err().to_string()
+    }
+
+    fn input(gateway: &str, point: &str, value: f64, millis: u64) -> Reading {
+        Reading {
+            timestamp: at(millis),
+            ..reading(gateway, 1, point, value)
+        }
+    }
+
+    // 先更新缓存再计算，与采集任务中的顺序一致
+    fn feed(
+        computed: &mut ComputedPoints,
+        cache: &ReadingCache,
+        readings: &[Reading],
+    ) -> Vec<Reading> {
+        cache.update(readings);
+        computed.process(readings)
+    }
+
+    const SUM: &str = "computed_points:\n  - { name: site_power, unit: kW, expression: \"meter_a.power + {PCS-B.power}\" }\n";
+
+    #[test]
+    fn computes_once_every_input_has_arrived() {
+        let cache = ReadingCache::new();
+        let mut computed = ComputedPoints::new(&config(SUM), cache.clone()).unwrap();
+
+        let a = input("10.0.0.1:502", "power", 10.0, 1_000);
+        assert!(feed(&mut computed, &cache, &[a]).is_empty(), "缺少输入时不计算");
+
+        let b = input("10.0.0.2:502", "power", 5.5, 1_200);
+        let results = feed(&mut computed, &cache, &[b]);
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+        assert_eq!(
+            (result.gateway.as_str(), result.slave_id, result.point.as_str()),
+            (COMPUTED_GATEWAY, 0, "site_power")
+        );
+        assert_eq!((result.value, result.quality), (15.5, Quality::Good));
+        assert_eq!(result.unit.as_deref(), Some("kW"));
+        assert_eq!(result.timestamp, at(1_200), "时间为最新输入的采集时间");
+
+        // 只有一个输入更新时等待另一个输入，重复的数据不会再次计算
+        let b = input("10.0.0.2:502", "power", 6.5, 2_200);
+        assert!(feed(&mut computed, &cache, std::slice::from_ref(&b)).is_empty());
+        assert!(feed(&mut computed, &cache, &[b]).is_empty());
+        let a = input("10.0.0.1:502", "power", 11.0, 2_000);
+        let results = feed(&mut computed, &cache, &[a]);
+        assert_eq!(results[0].value, 17.5);
+        assert_eq!(results[0].timestamp, at(2_200));
+
+        // 与计算点位无关的数据不触发计算
+        let unrelated = input("10.0.0.1:502", "dc_power", 1.0, 3_000);
+        assert!(feed(&mut computed, &cache, &[unrelated]).is_empty());
+    }
+
+    #[test]
+    fn inputs_further_apart_than_max_age_are_not_combined() {
+        // 未配置 max_age_ms 时为最长的采集周期 2000ms
+        let cache = ReadingCache::new();
+        let mut computed = ComputedPoints::new(&config(SUM), cache.clone()).unwrap();
+        feed(&mut computed, &cache, &[input("10.0.0.1:502", "power", 1.0, 1_000)]);
+        let late = input("10.0.0.2:502", "power", 2.0, 3_001);
+        assert!(feed(&mut computed, &cache, &[late]).is_empty());
+        let fresh = input("10.0.0.1:502", "power", 3.0, 3_500);
+        assert_eq!(feed(&mut computed, &cache, &[fresh])[0].value, 5.0);
+
+        let cache = ReadingCache::new();
+        let yaml = "computed_points:\n  - { name: site_power, expression: \"meter_a.power + meter_b.power\", max_age_ms: 100 }\n";
+        let mut computed = ComputedPoints::new(&config(yaml), cache.clone()).unwrap();
+        feed(&mut computed, &cache, &[input("10.0.0.1:502", "power", 1.0, 1_000)]);
+        let late = input("10.0.0.2:502", "power", 2.0, 1_101);
+        assert!(feed(&mut computed, &cache, &[late]).is_empty());
+        let close = input("10.0.0.1:502", "power", 3.0, 1_150);
+        assert_eq!(feed(&mut computed, &cache, &[close])[0].value, 5.0);
+    }
+
+    #[test]
+    fn bad_input_or_division_by_zero_gives_bad_nan() {
+        let yaml = "computed_points:\n  - { name: efficiency, expression: \"meter_a.power / meter_a.dc_power * 100\" }\n";
+        let cache = ReadingCache::new();
+        let mut computed = ComputedPoints::new(&config(yaml), cache.clone()).unwrap();
+
+        let readings = [
+            input("10.0.0.1:502", "power", 90.0, 1_000),
+            input("10.0.0.1:502", "dc_power", 0.0, 1_000),
+        ];
+        let result = feed(&mut computed, &cache, &readings).remove(0);
+        assert!(result.value.is_nan());
+        assert_eq!(result.quality, Quality::Bad);
+
+        let readings = [
+            input("10.0.0.1:502", "power", 90.0, 2_000),
+            Reading {
+                quality: Quality::Stale,
+                ..input("10.0.0.1:502", "dc_power", 100.0, 2_000)
+            },
+        ];
+        let result = feed(&mut computed, &cache, &readings).remove(0);
+        assert!(result.value.is_nan());
+        assert_eq!(result.quality, Quality::Bad);
+
+        // 输入恢复后结果恢复为 good
+        let readings = [
+            input("10.0.0.1:502", "power", 90.0, 3_000),
+            input("10.0.0.1:502", "dc_power", 100.0, 3_000),
+        ];
+        let result = feed(&mut computed, &cache, &readings).remove(0);
+        assert_eq!((result.value, result.quality), (90.0, Quality::Good));
+    }
+
+    #[test]
+    fn references_resolve_by_slave_gateway_or_address() {
+        let config = config(SUM);
+        let resolve = |reference: &str| {
+            let point = ComputedPoint {
+                name: "x".to_string(),
+                unit: None,
+                expression: format!("{{{}}}", reference),
+                max_age_ms: None,
+            };
+            let resolved = point.resolve(&config).unwrap();
+            (resolved.inputs[0].1.clone(), resolved.max_age)
+        };
+        let expected = PointReference {
+            gateway: "10.0.0.2:502".to_string(),
+            slave_id: 1,
+            point: "power".to_string(),
+        };
+        for reference in [
+            "meter_b.power",
+            "PCS-B.power",
+            "10.0.0.2:502.power",
+            "PCS-B/1.power",
+            "10.0.0.2:502/1.power",
+        ] {
+            assert_eq!(resolve(reference), (expected.clone(), Duration::from_secs(2)), "{}", reference);
+        }
+
+        let resolved = config.computed_points[0].resolve(&config).unwrap();
+        let names: Vec<_> = resolved.inputs.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["meter_a.power", "PCS-B.power"]);
+        assert_eq!(resolved.max_age, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn validation_rejects_bad_computed_points() {
+        assert_eq!(
+            validation_error("computed_points:\n  - { name: x, expression: \"meter_a.power +\" }\n"),
+            "计算点位 x 的表达式不合法: 表达式 \"meter_a.power +\" 第 16 个字符处表达式不完整"
+        );
+        assert_eq!(
+            validation_error("computed_points:\n  - { name: x, expression: \"meter_a.voltage * 2\" }\n"),
+            "计算点位 x 的表达式 meter_a.voltage * 2: 找不到引用的点位 meter_a.voltage（或该点位已停用）"
+        );
+        assert_eq!(
+            validation_error("computed_points:\n  - { name: x, expression: \"1\" }\n  - { name: x, expression: \"2\" }\n"),
+            "计算点位名称 x 重复"
+        );
+        assert_eq!(
+            validation_error("computed_points:\n  - { name: x, expression: \"1\", max_age_ms: 0 }\n"),
+            "计算点位 x 的 max_age_ms 必须大于0"
+        );
+        let renamed = CONFIG.replace("name: PCS-B", "name: computed");
+        let yaml = format!("{}computed_points:\n  - {{ name: x, expression: \"1\" }}\n", renamed);
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "网关名称 computed 用于计算点位，网关 10.0.0.2:502 需要改名"
+        );
+    }
+}
@@ -0,0 +1,507 @@
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, SecondsFormat};
+use chrono_tz::Tz;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::device_configuration::computed::COMPUTED_GATEWAY;
+use crate::device_configuration::csv::CsvSettings;
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::point::with_bit_points;
+use crate::modbus::reading::{PointValue, Quality, Reading};
+use crate::mqtt::payload::{gateway_name, slave_name, to_local};
+use crate::pipeline::Sink;
+
+// 文件开头的 UTF-8 BOM，Excel 据此识别编码，点位名称中的中文不会乱码
+const BOM: &str = "\u{feff}";
+
+// 从站的键：网关地址和从站ID
+type DeviceKey = (String, u8);
+
+// 一个从站当天的文件
+struct DeviceFile {
+    path: PathBuf,
+    date: NaiveDate,
+    columns: Vec<String>,
+    writer: BufWriter<File>,
+    // 已经警告过的不在表头中的点位
+    unknown: HashSet<String>,
+}
+
+/// 把采集数据写入 CSV 文件的输出
+///
+/// # 说明
+/// * 每个从站每天一个文件 `<网关>_<从站>_YYYY-MM-DD.csv`，日期和时间列按配置的时区计算，过了午夜自动切换到新文件
+/// * 表头为 `timestamp` 和配置中该从站的所有点位，每个采集周期一行；某个点位在该周期没有读到时对应的格为空，其他列不会错位
+/// * 当天的文件已存在时（例如程序重启）沿用文件中的表头继续追加；不在表头中的点位（配置热加载后新增的）从第二天的文件开始输出
+/// * 数据先写入缓冲区，按 `flush_interval_ms` 写入磁盘
+/// * 配置了 `retention_days` 时，每天删除一次目录中本输出写过的更早的 CSV 文件；
+///   文件名前缀不属于配置中的从站（也不是本次运行写过的）的文件不会删除，目录可以和其他程序共用
+pub struct CsvSink {
+    directory: PathBuf,
+    decimals: usize,
+    flush_interval: Duration,
+    tz: Option<Tz>,
+    retention_days: Option<u32>,
+    configured: HashMap<DeviceKey, Vec<String>>,
+    files: HashMap<DeviceKey, DeviceFile>,
+    // 本输出的文件名前缀，清理时只删除这些前缀的文件
+    prefixes: HashSet<String>,
+    last_prune: Option<NaiveDate>,
+}
+
+impl CsvSink {
+    /// 创建输出，目录不存在时创建
+    ///
+    /// # 参数说明
+    /// * `settings` - CSV 输出配置
+    /// * `config` - 完整配置，用于确定每个从站的表头；csv.timezone 未配置时使用其中的全局时区
+    pub fn new(settings: &CsvSettings, config: &Config) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(&settings.directory)
+            .map_err(|e| format!("无法创建 CSV 目录 {}: {}", settings.directory, e))?;
+        let mut configured = HashMap::new();
+        // 文件名与写入时一样按网关和从站的名称（未配置时为地址和从站ID）生成
+        let mut prefixes = HashSet::from([file_prefix(COMPUTED_GATEWAY, "0")]);
+        for gateway in config.gateways.iter().filter(|g| g.has_points()) {
+            let address = format!("{}:{}", gateway.ip, gateway.port);
+            let gateway_name = gateway.name.as_deref().unwrap_or(&address);
+            for slave in &gateway.slave_ids {
+                let points = with_bit_points(config.effective_points(gateway, slave)?);
+                let names = points.into_iter().map(|p| p.name).collect();
+                configured.insert((address.clone(), slave.id), names);
+                let slave_name = slave.name.clone().unwrap_or_else(|| slave.id.to_string());
+                prefixes.insert(file_prefix(gateway_name, &slave_name));
+            }
+        }
+        Ok(CsvSink {
+            directory: PathBuf::from(&settings.directory),
+            decimals: settings.decimals,
+            flush_interval: Duration::from_millis(settings.flush_interval_ms),
+            tz: settings.tz()?.or(config.tz()?),
+            retention_days: settings.retention_days,
+            configured,
+            files: HashMap::new(),
+            prefixes,
+            last_prune: None,
+        })
+    }
+
+    // 按配置的时区表示的时间，未配置时区时使用系统时区
+    fn local_time(&self, time: SystemTime) -> DateTime<FixedOffset> {
+        to_local(time, self.tz)
+    }
+
+    // 写入一个从站一个采集周期的数据
+    fn write_row(&mut self, key: DeviceKey, readings: &[&Reading]) -> Result<(), Box<dyn Error>> {
+        let first = readings[0];
+        let time = self.local_time(first.timestamp);
+        let date = time.date_naive();
+        if self.files.get(&key).is_some_and(|file| file.date != date) {
+            // 过了午夜，写完前一天的文件后切换
+            if let Some(mut old) = self.files.remove(&key) {
+                old.writer.flush()?;
+                info!(path = %old.path.display(), "CSV 文件已切换");
+            }
+        }
+        if !self.files.contains_key(&key) {
+            let mut columns = self.configured.get(&key).cloned().unwrap_or_default();
+            for reading in readings {
+                if !columns.contains(&reading.point) {
+                    columns.push(reading.point.clone());
+                }
+            }
+            let name = file_prefix(&gateway_name(first), &slave_name(first));
+            let file = open_file(&self.directory, &name, date, columns)?;
+            self.files.insert(key.clone(), file);
+            self.prefixes.insert(name);
+        }
+        let decimals = self.decimals;
+        let Some(file) = self.files.get_mut(&key) else {
+            return Ok(());
+        };
+        let mut values: HashMap<&str, PointValue> = HashMap::new();
+        for reading in readings {
+            if file.columns.contains(&reading.point) {
+                values.insert(&reading.point, reading.point_value());
+            } else if file.unknown.insert(reading.point.clone()) {
+                warn!(
+                    path = %file.path.display(),
+                    point = %reading.point,
+                    "点位不在 CSV 表头中，从下一个文件开始输出"
+                );
+            }
+        }
+        let mut row = time.to_rfc3339_opts(SecondsFormat::Millis, false);
+        for column in &file.columns {
+            row.push(',');
+            match values.get(column.as_str()) {
+                Some(PointValue::Number(value)) => row.push_str(&format!("{:.*}", decimals, value)),
+                Some(PointValue::Text(text)) => row.push_str(&quote(text)),
+                None => {}
+            }
+        }
+        row.push_str("\r\n");
+        file.writer.write_all(row.as_bytes())?;
+        Ok(())
+    }
+
+    // 删除目录中本输出写的、早于保留天数的 CSV 文件
+    fn prune(&self, today: NaiveDate) {
+        let Some(days) = self.retention_days else {
+            return;
+        };
+        let Some(oldest) = today.checked_sub_days(Days::new(u64::from(days))) else {
+            return;
+        };
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(directory = %self.directory.display(), error = %e, "无法读取 CSV 目录");
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some((prefix, date)) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(split_file_name)
+            else {
+                continue;
+            };
+            if date >= oldest || !self.prefixes.contains(prefix) {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => info!(path = %path.display(), "已删除过期的 CSV 文件"),
+                Err(e) => warn!(path = %path.display(), error = %e, "删除过期的 CSV 文件失败"),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for CsvSink {
+    async fn deliver(&mut self, batch: &[Reading]) {
+        // 一批数据可能包含同一网关的多个从站，每个从站各写一行
+        let mut devices: Vec<(DeviceKey, Vec<&Reading>)> = Vec::new();
+        // 过期标记的值是之前已经写过的，不再写一行
+        for reading in batch.iter().filter(|r| r.quality != Quality::Stale) {
+            let key = (reading.gateway.clone(), reading.slave_id);
+            match devices.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, readings)) => readings.push(reading),
+                None => devices.push((key, vec![reading])),
+            }
+        }
+        for (key, readings) in devices {
+            if let Err(e) = self.write_row(key, &readings) {
+                warn!(
+                    gateway = %gateway_name(readings[0]),
+                    slave_id = readings[0].slave_id,
+                    error = %e,
+                    "写入 CSV 文件失败"
+                );
+            }
+        }
+        let today = self.local_time(SystemTime::now()).date_naive();
+        if self.last_prune != Some(today) {
+            self.last_prune = Some(today);
+            self.prune(today);
+        }
+    }
+
+    fn flush_interval(&self) -> Option<Duration> {
+        Some(self.flush_interval)
+    }
+
+    async fn flush(&mut self) {
+        for file in self.files.values_mut() {
+            if let Err(e) = file.writer.flush() {
+                warn!(path = %file.path.display(), error = %e, "写入 CSV 文件失败");
+            }
+        }
+    }
+}
+
+// 打开从站当天的文件，已存在时沿用其中的表头，否则写入表头
+fn open_file(
+    directory: &Path,
+    name: &str,
+    date: NaiveDate,
+    columns: Vec<String>,
+) -> Result<DeviceFile, Box<dyn Error>> {
+    let path = directory.join(format!("{}_{}.csv", name, date.format("%Y-%m-%d")));
+    let existing = match File::open(&path) {
+        Ok(file) => {
+            let mut header = String::new();
+            BufReader::new(file).read_line(&mut header)?;
+            let header = header.trim_start_matches(BOM).trim_end();
+            let mut fields = parse_line(header);
+            (fields.first().map(String::as_str) == Some("timestamp")).then(|| {
+                fields.remove(0);
+                fields
+            })
+        }
+        Err(_) => None,
+    };
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut writer = BufWriter::new(file);
+    let columns = match existing {
+        Some(columns) => columns,
+        None => {
+            let mut header = format!("{}timestamp", BOM);
+            for column in &columns {
+                header.push(',');
+                header.push_str(&quote(column));
+            }
+            header.push_str("\r\n");
+            writer.write_all(header.as_bytes())?;
+            columns
+        }
+    };
+    Ok(DeviceFile {
+        path,
+        date,
+        columns,
+        writer,
+        unknown: HashSet::new(),
+    })
+}
+
+// 文件名前缀，不能用在文件名中的字符替换为 _
+fn file_prefix(gateway: &str, slave: &str) -> String {
+    format!("{}_{}", gateway, slave)
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// 把文件名 `<前缀>_YYYY-MM-DD.csv` 拆成前缀和日期
+fn split_file_name(name: &str) -> Option<(&str, NaiveDate)> {
+    let stem = name.strip_suffix(".csv")?;
+    let (prefix, date) = stem.rsplit_once('_')?;
+    Some((prefix, NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?))
+}
+
+// 含有逗号、引号或换行的字段加引号
+fn quote(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+// 拆分一行 CSV，处理引号中的逗号和转义的引号
+fn parse_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at, reading};
+
+    const GATEWAY: &str = "10.0.0.1:502";
+
+    fn sink(directory: &Path, retention_days: Option<u32>) -> CsvSink {
+        let config: Config = serde_yaml::from_str(
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    name: plant\n    slave_ids: [1]\n    points:\n      - { name: a, address: 0 }\n      - { name: b, address: 1 }\n      - { name: c, address: 2 }\n",
+        )
+        .unwrap();
+        let settings = CsvSettings {
+            directory: directory.to_string_lossy().into_owned(),
+            decimals: 1,
+            flush_interval_ms: 1000,
+            timezone: Some("Asia/Shanghai".to_string()),
+            retention_days,
+        };
+        CsvSink::new(&settings, &config).unwrap()
+    }
+
+    // 配置中网关名称为 plant 的从站1的一个点位，采集时间为 `millis`
+    fn sample(point: &str, value: f64, millis: u64) -> Reading {
+        let mut reading = reading(GATEWAY, 1, point, value);
+        reading.gateway_name = Some("plant".to_string());
+        reading.timestamp = at(millis);
+        reading
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        let contents = fs::read_to_string(path).unwrap();
+        let contents = contents.strip_prefix(BOM).unwrap();
+        contents.split_terminator("\r\n").map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn missing_point_leaves_empty_cell() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(directory.path(), None);
+        // 2023-11-15 06:13:20 +08:00
+        let time = 1_700_000_000_000;
+        sink.deliver(&[sample("a", 1.0, time), sample("b", 2.0, time), sample("c", 3.0, time)])
+            .await;
+        sink.deliver(&[sample("a", 4.0, time + 1000), sample("c", 6.0, time + 1000)])
+            .await;
+        sink.flush().await;
+
+        let lines = lines(&directory.path().join("plant_1_2023-11-15.csv"));
+        assert_eq!(
+            lines,
+            [
+                "timestamp,a,b,c",
+                "2023-11-15T06:13:20.000+08:00,1.0,2.0,3.0",
+                "2023-11-15T06:13:21.000+08:00,4.0,,6.0",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rotates_at_local_midnight() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(directory.path(), None);
+        // 2023-11-15 23:59:59 +08:00 和 2023-11-16 00:00:01 +08:00
+        sink.deliver(&[sample("a", 1.0, 1_700_063_999_000)]).await;
+        sink.deliver(&[sample("a", 2.0, 1_700_064_001_000)]).await;
+        sink.flush().await;
+
+        let before = lines(&directory.path().join("plant_1_2023-11-15.csv"));
+        let after = lines(&directory.path().join("plant_1_2023-11-16.csv"));
+        assert_eq!(before, ["timestamp,a,b,c", "2023-11-15T23:59:59.000+08:00,1.0,,"]);
+        assert_eq!(after, ["timestamp,a,b,c", "2023-11-16T00:00:01.000+08:00,2.0,,"]);
+    }
+
+    #[tokio::test]
+    async fn dst_transitions_rotate_once_per_local_day() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut sink = sink(directory.path(), None);
+        sink.tz = Some(chrono_tz::America::New_York);
+        let times = [
+            // 2024-03-09 23:59:59 EST，之后跳过 02:00-03:00
+            1_710_046_799_000,
+            1_710_053_999_000,
+            1_710_054_000_000,
+            1_710_129_599_000,
+            1_710_129_601_000,
+            // 2024-11-03 00:30 EDT，01:00-02:00 出现两次
+            1_730_608_200_000,
+            1_730_611_800_000,
+            1_730_615_400_000,
+            1_730_619_000_000,
+            1_730_696_399_000,
+            1_730_696_401_000,
+        ];
+        for (index, time) in times.into_iter().enumerate() {
+            sink.deliver(&[sample("a", index as f64, time)]).await;
+        }
+        sink.flush().await;
+
+        let mut files: Vec<String> = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "plant_1_2024-03-09.csv",
+                "plant_1_2024-03-10.csv",
+                "plant_1_2024-03-11.csv",
+                "plant_1_2024-11-03.csv",
+                "plant_1_2024-11-04.csv",
+            ]
+        );
+        let file = |name: &str| lines(&directory.path().join(name));
+        assert_eq!(file("plant_1_2024-03-09.csv")[1..], ["2024-03-09T23:59:59.000-05:00,0.0,,"]);
+        assert_eq!(
+            file("plant_1_2024-03-10.csv")[1..],
+            [
+                "2024-03-10T01:59:59.000-05:00,1.0,,",
+                "2024-03-10T03:00:00.000-04:00,2.0,,",
+                "2024-03-10T23:59:59.000-04:00,3.0,,",
+            ]
+        );
+        assert_eq!(file("plant_1_2024-03-11.csv")[1..], ["2024-03-11T00:00:01.000-04:00,4.0,,"]);
+        // 重复的一小时写入同一个文件，不会重新打开或覆盖
+        assert_eq!(
+            file("plant_1_2024-11-03.csv"),
+            [
+                "timestamp,a,b,c",
+                "2024-11-03T00:30:00.000-04:00,5.0,,",
+                "2024-11-03T01:30:00.000-04:00,6.0,,",
+                "2024-11-03T01:30:00.000-05:00,7.0,,",
+                "2024-11-03T02:30:00.000-05:00,8.0,,",
+                "2024-11-03T23:59:59.000-05:00,9.0,,",
+            ]
+        );
+        assert_eq!(file("plant_1_2024-11-04.csv")[1..], ["2024-11-04T00:00:01.000-05:00,10.0,,"]);
+    }
+
+    #[test]
+    fn prune_removes_only_own_old_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let names = [
+            "plant_1_2023-10-01.csv",
+            "plant_1_2023-11-10.csv",
+            "computed_0_2023-10-01.csv",
+            "other_1_2023-10-01.csv",
+            "plant_1_backup.csv",
+            "notes.txt",
+        ];
+        for name in names {
+            fs::write(directory.path().join(name), "").unwrap();
+        }
+        let sink = sink(directory.path(), Some(30));
+        sink.prune(NaiveDate::from_ymd_opt(2023, 11, 15).unwrap());
+
+        let mut remaining: Vec<String> = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            [
+                "notes.txt",
+                "other_1_2023-10-01.csv",
+                "plant_1_2023-11-10.csv",
+                "plant_1_backup.csv",
+            ]
+        );
+    }
+
+    #[test]
+    fn prune_without_retention_keeps_everything() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(directory.path().join("plant_1_2000-01-01.csv"), "").unwrap();
+        sink(directory.path(), None).prune(NaiveDate::from_ymd_opt(2023, 11, 15).unwrap());
+        assert!(directory.path().join("plant_1_2000-01-01.csv").exists());
+    }
+}
@@ -0,0 +1,292 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// 等待就绪时检查状态的间隔
+const READY_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// systemd 的 sd_notify 通知，发送到环境变量 NOTIFY_SOCKET 指定的 Unix 数据报套接字
+///
+/// 不是由 systemd 以 Type=notify 启动（没有设置 NOTIFY_SOCKET）时不发送任何通知。
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    #[cfg(unix)]
+    address: std::os::unix::net::SocketAddr,
+}
+
+impl Notifier {
+    /// 按环境变量 NOTIFY_SOCKET 创建，未设置或无法使用时返回 None
+    ///
+    /// 以 @ 开头的地址为 Linux 抽象命名空间中的套接字。
+    pub fn from_env() -> Option<Notifier> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        match Self::connect(&path.to_string_lossy()) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                warn!(socket = %path.to_string_lossy(), error = %e, "无法使用 systemd 通知套接字");
+                None
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn connect(path: &str) -> Result<Notifier, Box<dyn Error>> {
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let address = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name.as_bytes())?
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return Err("当前系统不支持抽象命名空间套接字".into()),
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Notifier {
+            socket: UnixDatagram::unbound()?,
+            address,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn connect(_path: &str) -> Result<Notifier, Box<dyn Error>> {
+        Err("当前系统不支持 systemd 通知".into())
+    }
+
+    /// 发送一条通知，例如 `READY=1`，多个字段用换行分隔；发送失败只输出日志
+    pub fn notify(&self, state: &str) {
+        #[cfg(unix)]
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            warn!(state, error = %e, "发送 systemd 通知失败");
+        }
+        #[cfg(not(unix))]
+        let _ = state;
+    }
+
+    /// 更新 systemctl status 中显示的状态说明
+    pub fn status(&self, status: &str) {
+        // 状态说明只占一行，换行会被当作下一个字段
+        self.notify(&format!("STATUS={}", status.replace('\n', " ")));
+    }
+}
+
+/// systemd 要求的看门狗通知间隔，未启用 WatchdogSec 时返回 None
+///
+/// 取 WATCHDOG_USEC 的一半，WATCHDOG_PID 不是本进程时（例如由 shell 脚本转启动）视为未启用。
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+// 由 WATCHDOG_PID 和 WATCHDOG_USEC 的值计算看门狗通知间隔，`own_pid` 为本进程号
+fn parse_watchdog(pid: Option<&str>, usec: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid
+        && pid.trim() != own_pid.to_string()
+    {
+        return None;
+    }
+    let usec: u64 = usec?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// 定期发送看门狗通知（不会返回）
+///
+/// 在 tokio 运行时中执行，运行时卡住时通知随之停止，由 systemd 重启程序。
+pub async fn run_watchdog(notifier: std::sync::Arc<Notifier>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        notifier.notify("WATCHDOG=1");
+    }
+}
+
+/// 等待 `pending` 返回空列表，期间把等待的项目更新到 systemd 状态说明
+///
+/// # 错误
+/// 到 `deadline` 仍未就绪时返回错误，错误信息中列出未就绪的项目
+pub async fn wait_until_ready<F>(
+    notifier: Option<&Notifier>,
+    deadline: Instant,
+    mut pending: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> Vec<String>,
+{
+    let mut last = Vec::new();
+    loop {
+        let current = pending();
+        if current.is_empty() {
+            return Ok(());
+        }
+        let waiting = current.join("、");
+        if Instant::now() >= deadline {
+            return Err(format!("启动超时，仍在等待 {}", waiting).into());
+        }
+        if current != last {
+            info!(waiting = %waiting, "等待启动完成");
+            if let Some(notifier) = notifier {
+                notifier.status(&format!("等待 {}", waiting));
+            }
+            last = current;
+        }
+        tokio::time::sleep(READY_CHECK_INTERVAL).await;
+    }
+}
+
+/// PID 文件，创建时写入本进程号，drop 时删除
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// 写入 PID 文件
+    ///
+    /// 先写入同目录下的临时文件再改名，其他进程不会读到不完整的内容；文件已存在时覆盖。
+    pub fn create(path: &str) -> Result<PidFile, Box<dyn Error>> {
+        let path = PathBuf::from(path);
+        let temporary = path.with_extension("pid.tmp");
+        fs::write(&temporary, format!("{}\n", std::process::id()))
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|e| format!("无法写入 PID 文件 {}: {}", path.display(), e))?;
+        Ok(PidFile { path })
+    }
+}
+
+impl Drop for PidFile {
+    // 文件已被其他进程改写时不删除
+    fn drop(&mut self) {
+        let ours = fs::read_to_string(&self.path)
+            .is_ok_and(|content| content.trim() == std::process::id().to_string());
+        if ours && let Err(e) = fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "删除 PID 文件失败");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_interval_is_half_of_usec_for_our_pid() {
+        assert_eq!(parse_watchdog(None, Some("30000000"), 42), Some(Duration::from_secs(15)));
+        assert_eq!(parse_watchdog(Some("42"), Some(" 10000000\n"), 42), Some(Duration::from_secs(5)));
+        assert_eq!(parse_watchdog(Some(" 42 "), Some("3"), 42), Some(Duration::from_micros(1)));
+        // 由其他进程（例如启动脚本）接收的看门狗
+        assert_eq!(parse_watchdog(Some("41"), Some("30000000"), 42), None);
+        // 未启用或值不合法
+        assert_eq!(parse_watchdog(Some("42"), None, 42), None);
+        assert_eq!(parse_watchdog(None, Some("0"), 42), None);
+        assert_eq!(parse_watchdog(None, Some("-5"), 42), None);
+        assert_eq!(parse_watchdog(None, Some("15s"), 42), None);
+    }
+
+    // 绑定在临时目录中的数据报套接字，接收 Notifier 发送的通知
+    #[cfg(unix)]
+    fn listener() -> (tempfile::TempDir, std::os::unix::net::UnixDatagram, Notifier) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let notifier = Notifier::connect(path.to_str().unwrap()).unwrap();
+        (dir, socket, notifier)
+    }
+
+    // 已收到的所有通知
+    #[cfg(unix)]
+    fn received(socket: &std::os::unix::net::UnixDatagram) -> Vec<String> {
+        let mut messages = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while let Ok(length) = socket.recv(&mut buffer) {
+            messages.push(String::from_utf8_lossy(&buffer[..length]).into_owned());
+        }
+        messages
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notifier_sends_state_and_single_line_status() {
+        let (_dir, socket, notifier) = listener();
+        notifier.notify("READY=1");
+        notifier.status("已连接 3 个网关\n1 个离线");
+        notifier.notify("WATCHDOG=1");
+        assert_eq!(
+            received(&socket),
+            ["READY=1", "STATUS=已连接 3 个网关 1 个离线", "WATCHDOG=1"]
+        );
+        // 套接字不存在时发送失败只输出日志
+        Notifier::connect("/nonexistent/notify.sock").unwrap().notify("READY=1");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(start_paused = true)]
+    async fn wait_until_ready_reports_changes_of_the_pending_list() {
+        let (_dir, socket, notifier) = listener();
+        let started = Instant::now();
+        // 前600毫秒等待 a、b，之后等待 b，1秒后全部就绪
+        let pending = || {
+            let elapsed = started.elapsed();
+            match elapsed.as_millis() {
+                0..600 => vec!["a".to_string(), "b".to_string()],
+                600..1000 => vec!["b".to_string()],
+                _ => Vec::new(),
+            }
+        };
+        let deadline = started + Duration::from_secs(5);
+        wait_until_ready(Some(&notifier), deadline, pending).await.unwrap();
+        // 每200毫秒检查一次，第一次检查在1秒时发现全部就绪
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+        assert_eq!(received(&socket), ["STATUS=等待 a、b", "STATUS=等待 b"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_until_ready_times_out_listing_pending_items() {
+        let started = Instant::now();
+        let mut checks = 0;
+        let result = wait_until_ready(None, started + Duration::from_secs(1), || {
+            checks += 1;
+            vec!["PCS-A".to_string(), "mqtt".to_string()]
+        })
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "启动超时，仍在等待 PCS-A、mqtt");
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+        assert_eq!(checks, 6);
+
+        // 已经就绪时立即返回
+        let result = wait_until_ready(None, Instant::now(), Vec::new).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn pid_file_is_removed_only_while_it_is_ours() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ems.pid");
+        let pid_file = PidFile::create(path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        assert!(!dir.path().join("ems.pid.tmp").exists());
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // 已被其他进程改写的文件保留
+        let pid_file = PidFile::create(path.to_str().unwrap()).unwrap();
+        fs::write(&path, "1\n").unwrap();
+        drop(pid_file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n");
+
+        // 已存在的文件被覆盖
+        let pid_file = PidFile::create(path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+        drop(pid_file);
+
+        let error = PidFile::create(dir.path().join("missing/ems.pid").to_str().unwrap()).err().unwrap();
+        assert!(error.to_string().starts_with("无法写入 PID 文件"), "{}", error);
+    }
+}
@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use super::computed::COMPUTED_GATEWAY;
+use super::modbus::Config;
+use super::slave::check_topic_safe;
+use super::point::with_bit_points;
+
+/// 报警条件
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmCondition {
+    /// 大于 threshold
+    Gt,
+    /// 小于 threshold
+    Lt,
+    /// 等于 threshold
+    Eq,
+    /// 超出 range 范围
+    OutsideRange,
+}
+
+impl AlarmCondition {
+    /// 配置文件中的写法
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlarmCondition::Gt => "gt",
+            AlarmCondition::Lt => "lt",
+            AlarmCondition::Eq => "eq",
+            AlarmCondition::OutsideRange => "outside_range",
+        }
+    }
+}
+
+impl fmt::Display for AlarmCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 报警配置，对应配置文件中 `alarms:` 下的一项
+///
+/// ```yaml
+/// alarms:
+///   - name: pcs_over_temp
+///     gateway: PCS-A
+///     slave: 1
+///     point: temperature
+///     condition: gt
+///     threshold: 65
+///     hysteresis: 2
+///     delay_ms: 5000
+///     comm_failure: true
+///   - name: grid_voltage
+///     gateway: 192.168.1.100:502
+///     slave: 1
+///     point: voltage
+///     condition: outside_range
+///     range: [198, 242]
+/// ```
+///
+/// 条件持续满足 `delay_ms` 后报警，值回到阈值内侧 `hysteresis` 以外才解除，避免在阈值附近反复报警。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AlarmSettings {
+    /// 报警名称，用于日志和 MQTT 主题，不能重复，不能包含 +、#、/ 和空白字符
+    pub name: String,
+    /// 网关名称或 ip:port；计算点位和电量点位为 `computed`
+    pub gateway: String,
+    /// 从站ID；计算点位为0
+    pub slave: u8,
+    /// 点位名称
+    pub point: String,
+    /// 报警条件
+    pub condition: AlarmCondition,
+    /// 阈值，condition 为 gt、lt、eq 时必须配置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    /// 正常范围 [下限, 上限]，condition 为 outside_range 时必须配置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<[f64; 2]>,
+    /// 解除报警的回差（默认0）；eq 条件下值与阈值相差超过回差才解除
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub hysteresis: f64,
+    /// 条件持续满足多久后才报警，单位毫秒（默认0，立即报警）
+    #[serde(default, skip_serializing_if = "is_zero_ms")]
+    pub delay_ms: u64,
+    /// 报警级别，原样放入报警消息，例如 warning、critical
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    /// 为 true 时从站通信中断期间另外产生 `<name>_stale` 报警，表示该点位的数据已不可信（默认 false）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub comm_failure: bool,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+fn is_zero_ms(value: &u64) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl AlarmSettings {
+    /// 条件需要持续满足的时间
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+
+    /// 通信中断报警的名称
+    pub fn stale_name(&self) -> String {
+        format!("{}_stale", self.name)
+    }
+
+    /// 检查报警配置是否合法
+    ///
+    /// # 校验规则
+    /// * 名称不能为空，不能包含 +、#、/ 和空白字符
+    /// * gt、lt、eq 需要配置 threshold，outside_range 需要配置 range 且下限小于上限
+    /// * hysteresis 不能为负数；outside_range 的回差不能超过范围宽度的一半
+    /// * 引用的网关、从站和点位（或计算点位、电量点位）必须存在
+    pub fn validate(&self, config: &Config) -> Result<(), Box<dyn Error>> {
+        check_topic_safe("报警", &self.name)?;
+        let location = format!("报警 {}", self.name);
+        match self.condition {
+            AlarmCondition::Gt | AlarmCondition::Lt | AlarmCondition::Eq => match self.threshold {
+                Some(threshold) if threshold.is_finite() => {}
+                Some(threshold) => {
+                    return Err(format!("{} 的 threshold 不合法: {}", location, threshold).into());
+                }
+                None => {
+                    return Err(format!(
+                        "{} 的条件为 {}，需要配置 threshold",
+                        location, self.condition
+                    )
+                    .into());
+                }
+            },
+            AlarmCondition::OutsideRange => {
+                let Some([low, high]) = self.range else {
+                    return Err(format!("{} 的条件为 outside_range，需要配置 range", location).into());
+                };
+                if !(low.is_finite() && high.is_finite() && low < high) {
+                    return Err(
+                        format!("{} 的 range 下限必须小于上限: [{}, {}]", location, low, high).into(),
+                    );
+                }
+                if self.hysteresis * 2.0 >= high - low {
+                    return Err(format!(
+                        "{} 的 hysteresis {} 过大，必须小于 range 宽度的一半",
+                        location, self.hysteresis
+                    )
+                    .into());
+                }
+            }
+        }
+        if !(self.hysteresis.is_finite() && self.hysteresis >= 0.0) {
+            return Err(format!("{} 的 hysteresis 不能为负数: {}", location, self.hysteresis).into());
+        }
+        if self.gateway == COMPUTED_GATEWAY && self.slave == 0 {
+            let energy_points = config.energy.iter().flat_map(|e| &e.points);
+            if !config.computed_points.iter().any(|p| p.name == self.point)
+                && !energy_points.map(|p| &p.name).any(|name| *name == self.point)
+            {
+                return Err(format!("{} 引用了不存在的计算点位 {}", location, self.point).into());
+            }
+            return Ok(());
+        }
+        let Some(gateway) = config.gateways.iter().find(|g| {
+            g.name.as_deref() == Some(self.gateway.as_str())
+                || format!("{}:{}", g.ip, g.port) == self.gateway
+        }) else {
+            return Err(format!("{} 引用了不存在的网关 {}", location, self.gateway).into());
+        };
+        let Some(slave) = gateway.find_slave(self.slave) else {
+            return Err(format!(
+                "{} 引用了网关 {} 中不存在的从站 {}",
+                location,
+                gateway.display_name(),
+                self.slave
+            )
+            .into());
+        };
+        if !with_bit_points(config.effective_points(gateway, slave)?)
+            .iter()
+            .any(|point| point.name == self.point)
+        {
+            return Err(format!(
+                "{} 引用了网关 {} 从站 {} 中不存在的点位 {}",
+                location,
+                gateway.display_name(),
+                slave.display_name(),
+                self.point
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// 检查所有报警配置，除逐个校验外还要求报警名称（包括通信中断报警的名称）不重复
+pub fn validate_alarms(alarms: &[AlarmSettings], config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut names = HashSet::new();
+    for alarm in alarms {
+        alarm.validate(config)?;
+        if !names.insert(alarm.name.clone()) {
+            return Err(format!("报警名称 {} 重复", alarm.name).into());
+        }
+    }
+    for alarm in alarms.iter().filter(|a| a.comm_failure) {
+        if names.contains(&alarm.stale_name()) {
+            return Err(format!(
+                "报警 {} 的通信中断报警名称 {} 与其他报警重复",
+                alarm.name,
+                alarm.stale_name()
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
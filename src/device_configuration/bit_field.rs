@@ -0,0 +1,195 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// 寄存器中的一位或连续几位，对应点位配置 `bits:` 中的一项
+///
+/// ```yaml
+/// bits:
+///   cell_overvoltage: 0      # 第0位（最低位），值为0或1
+///   contactor_closed: 3
+///   run_mode: "4-6"          # 第4到6位，值为0-7
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitField {
+    /// 最低位，0-15
+    pub low: u8,
+    /// 最高位，0-15，只有一位时与 low 相同
+    pub high: u8,
+}
+
+impl BitField {
+    /// 是否只有一位
+    pub fn is_single(&self) -> bool {
+        self.low == self.high
+    }
+
+    /// 从寄存器值中取出这几位，右移到最低位
+    pub fn extract(&self, register: u16) -> u16 {
+        let width = u32::from(self.high - self.low) + 1;
+        let mask = ((1u32 << width) - 1) as u16;
+        (register >> self.low) & mask
+    }
+
+    /// 与另一个位域是否有相同的位
+    pub fn overlaps(&self, other: &BitField) -> bool {
+        self.low <= other.high && other.low <= self.high
+    }
+
+    /// 检查位的范围
+    ///
+    /// # 校验规则
+    /// * 位号只能是0-15
+    /// * 范围的起始位不能大于结束位
+    pub fn validate(&self, location: &str) -> Result<(), String> {
+        if self.high > 15 {
+            return Err(format!("{} 的位号 {} 超出范围，只能是0-15", location, self.high));
+        }
+        if self.low > self.high {
+            return Err(format!("{} 的位范围 {} 起始位大于结束位", location, self));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BitField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_single() {
+            write!(f, "{}", self.low)
+        } else {
+            write!(f, "{}-{}", self.low, self.high)
+        }
+    }
+}
+
+impl Serialize for BitField {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_single() {
+            serializer.serialize_u8(self.low)
+        } else {
+            serializer.serialize_str(&self.to_string())
+        }
+    }
+}
+
+// 配置中写作位号或 "起始位-结束位"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BitFieldText {
+    Bit(u8),
+    Range(String),
+}
+
+impl<'de> Deserialize<'de> for BitField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = match BitFieldText::deserialize(deserializer)? {
+            BitFieldText::Bit(bit) => return Ok(BitField { low: bit, high: bit }),
+            BitFieldText::Range(text) => text,
+        };
+        let invalid = || serde::de::Error::custom(format!("位范围 \"{}\" 应为位号或 \"起始位-结束位\"", text));
+        let (low, high) = match text.split_once('-') {
+            Some((low, high)) => (low.trim(), high.trim()),
+            None => (text.trim(), text.trim()),
+        };
+        let low = low.parse().map_err(|_| invalid())?;
+        let high = high.parse().map_err(|_| invalid())?;
+        Ok(BitField { low, high })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::modbus::scheduler::{GatewayPoller, PollEvent};
+    use crate::test_support::MockModbus;
+
+    fn field(text: &str) -> BitField {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    fn config(port: u16, bits: &str) -> Result<Config, String> {
+        let yaml = format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    slave_ids: [1]\n    points:\n      - {{ name: status, address: 10, bits: {{ {} }} }}\n",
+            port, bits
+        );
+        let config: Config = serde_yaml::from_str(&yaml).map_err(|e| e.to_string())?;
+        config.validate().map_err(|e| e.to_string())?;
+        Ok(config)
+    }
+
+    #[test]
+    fn parses_bits_and_ranges() {
+        assert_eq!(field("3"), BitField { low: 3, high: 3 });
+        assert_eq!(field("\"4-6\""), BitField { low: 4, high: 6 });
+        assert_eq!(field("\" 8 - 9 \""), BitField { low: 8, high: 9 });
+        assert_eq!(field("\"12\""), BitField { low: 12, high: 12 });
+        assert!(serde_yaml::from_str::<BitField>("\"a-b\"").is_err());
+        assert!(serde_yaml::from_str::<BitField>("-1").is_err());
+        assert_eq!(serde_yaml::to_string(&field("\"4-6\"")).unwrap().trim(), "4-6");
+        assert_eq!(serde_yaml::to_string(&field("3")).unwrap().trim(), "3");
+    }
+
+    #[test]
+    fn extracts_bits_from_register() {
+        let register = 0b1000_0000_0101_1001;
+        assert_eq!(field("0").extract(register), 1);
+        assert_eq!(field("1").extract(register), 0);
+        assert_eq!(field("3").extract(register), 1);
+        assert_eq!(field("\"4-6\"").extract(register), 0b101);
+        assert_eq!(field("15").extract(register), 1);
+        assert_eq!(field("\"0-15\"").extract(register), register);
+    }
+
+    #[test]
+    fn rejects_out_of_range_and_overlapping_bits() {
+        assert!(config(502, "a: 0, b: 15, c: \"4-6\", d: \"7-8\"").is_ok());
+
+        let error = config(502, "a: 16").unwrap_err();
+        assert!(error.contains("位号 16 超出范围"), "{}", error);
+        let error = config(502, "a: \"14-16\"").unwrap_err();
+        assert!(error.contains("位号 16 超出范围"), "{}", error);
+        let error = config(502, "a: \"6-4\"").unwrap_err();
+        assert!(error.contains("起始位大于结束位"), "{}", error);
+        let error = config(502, "a: \"4-6\", b: 6").unwrap_err();
+        assert!(error.contains("bits.a 与 bits.b 的位重叠"), "{}", error);
+        let error = config(502, "a: \"0-3\", b: \"2-5\"").unwrap_err();
+        assert!(error.contains("位重叠"), "{}", error);
+        let error = config(502, "status: 0").unwrap_err();
+        assert!(error.contains("不能为空或与点位相同"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn one_read_produces_a_reading_per_field() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 10, &[0b0000_0000_0101_1001]);
+        let config = config(
+            modbus.port,
+            "cell_overvoltage: 0, cell_undervoltage: 1, contactor_closed: 3, run_mode: \"4-6\"",
+        )
+        .unwrap();
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        let mut values = Vec::new();
+        poller
+            .run_once(|event| {
+                if let PollEvent::Readings(readings) = event {
+                    values.extend(readings.into_iter().map(|r| (r.point, r.value, r.raw)));
+                }
+            })
+            .await;
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        let expected: Vec<(String, f64, Vec<u16>)> = [
+            ("cell_overvoltage", 1.0),
+            ("cell_undervoltage", 0.0),
+            ("contactor_closed", 1.0),
+            ("run_mode", 5.0),
+            ("status", 89.0),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value, vec![0b0101_1001]))
+        .collect();
+        assert_eq!(values, expected);
+        // 只读取了一次寄存器
+        let reads: Vec<(u16, u16)> = modbus.requests().iter().map(|r| (r.address, r.quantity)).collect();
+        assert_eq!(reads, [(10, 1)]);
+    }
+}
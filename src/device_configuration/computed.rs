@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::time::Duration;
+
+use super::modbus::Config;
+use super::slave::check_topic_safe;
+use crate::expression::Expression;
+
+/// 计算点位所属的虚拟网关名称，计算结果以该网关、从站ID 0 的点位输出
+pub const COMPUTED_GATEWAY: &str = "computed";
+
+/// 计算点位配置，对应配置文件中 `computed_points:` 下的一项
+///
+/// ```yaml
+/// computed_points:
+///   - name: site_power
+///     unit: kW
+///     expression: "meter_a.power + meter_b.power + meter_c.power"
+///   - name: efficiency
+///     expression: "{PCS-A.ac_power} / {PCS-A.dc_power} * 100"
+///     max_age_ms: 3000
+/// ```
+///
+/// 表达式语法见 [`Expression`]，引用 `设备.点位` 中的设备可以是从站名称、
+/// 只有一个从站的网关名称（或 ip:port），或者 `网关/从站ID`。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ComputedPoint {
+    /// 点位名称，不能重复，不能包含 +、#、/ 和空白字符
+    pub name: String,
+    /// 工程单位
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// 计算表达式
+    pub expression: String,
+    /// 输入值的有效时间，单位毫秒，未配置时为输入点位中最长的采集周期
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_ms: Option<u64>,
+}
+
+/// 表达式中引用的一个采集点位
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PointReference {
+    /// 网关地址，格式为 ip:port
+    pub gateway: String,
+    /// 从站ID
+    pub slave_id: u8,
+    /// 点位名称
+    pub point: String,
+}
+
+/// 解析后的计算点位
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedExpression {
+    /// 表达式
+    pub expression: Expression,
+    /// 按首次出现顺序排列的 (引用名称, 采集点位)
+    pub inputs: Vec<(String, PointReference)>,
+    /// 输入值的有效时间
+    pub max_age: Duration,
+}
+
+impl ComputedPoint {
+    /// 解析表达式，并把其中的每个引用对应到采集点位
+    pub fn resolve(&self, config: &Config) -> Result<ResolvedExpression, Box<dyn Error>> {
+        let location = format!("计算点位 {}", self.name);
+        let expression = Expression::parse(&self.expression)
+            .map_err(|e| format!("{} 的表达式不合法: {}", location, e))?;
+        let mut inputs = Vec::new();
+        let mut longest = Duration::ZERO;
+        for name in expression.references() {
+            let (reference, interval) = resolve_reference(config, &name)
+                .map_err(|e| format!("{} 的表达式 {}: {}", location, self.expression, e))?;
+            longest = longest.max(interval);
+            inputs.push((name, reference));
+        }
+        Ok(ResolvedExpression {
+            expression,
+            inputs,
+            max_age: self.max_age_ms.map_or(longest, Duration::from_millis),
+        })
+    }
+}
+
+/// 检查所有计算点位：名称合法且不重复，max_age_ms 不为0，表达式语法正确，引用的点位都存在；
+/// 配置了计算点位或电量点位时网关不能命名为 [`COMPUTED_GATEWAY`]
+pub fn validate_computed_points(config: &Config) -> Result<(), Box<dyn Error>> {
+    if let Some(gateway) = config
+        .gateways
+        .iter()
+        .find(|g| g.name.as_deref() == Some(COMPUTED_GATEWAY))
+        && (!config.computed_points.is_empty()
+            || config.energy.as_ref().is_some_and(|e| !e.points.is_empty()))
+    {
+        return Err(format!(
+            "网关名称 {} 用于计算点位，网关 {}:{} 需要改名",
+            COMPUTED_GATEWAY, gateway.ip, gateway.port
+        )
+        .into());
+    }
+    let mut names = HashSet::new();
+    for point in &config.computed_points {
+        check_topic_safe("计算点位", &point.name)?;
+        if !names.insert(point.name.as_str()) {
+            return Err(format!("计算点位名称 {} 重复", point.name).into());
+        }
+        if point.max_age_ms == Some(0) {
+            return Err(format!("计算点位 {} 的 max_age_ms 必须大于0", point.name).into());
+        }
+        point.resolve(config)?;
+    }
+    Ok(())
+}
+
+// 把引用 `设备.点位` 对应到采集点位，同时返回该点位的采集周期
+pub(super) fn resolve_reference(
+    config: &Config,
+    reference: &str,
+) -> Result<(PointReference, Duration), Box<dyn Error>> {
+    let mut found = Vec::new();
+    // 设备名称和点位名称中都可能有 `.`，逐个尝试所有拆分位置
+    for (index, _) in reference.match_indices('.') {
+        let (device, point) = (&reference[..index], &reference[index + 1..]);
+        for gateway in &config.gateways {
+            let address = format!("{}:{}", gateway.ip, gateway.port);
+            let gateway_matches =
+                gateway.name.as_deref() == Some(device) || address == device;
+            for slave in &gateway.slave_ids {
+                let matches = slave.name.as_deref() == Some(device)
+                    || (gateway_matches && gateway.slave_ids.len() == 1)
+                    || gateway
+                        .name
+                        .iter()
+                        .chain([&address])
+                        .any(|name| format!("{}/{}", name, slave.id) == device);
+                if !matches {
+                    continue;
+                }
+                let Some(p) = config
+                    .published_points(gateway, slave)?
+                    .into_iter()
+                    .find(|p| p.name == point)
+                else {
+                    continue;
+                };
+                let resolved = PointReference {
+                    gateway: address.clone(),
+                    slave_id: slave.id,
+                    point: p.name.clone(),
+                };
+                if !found.iter().any(|(r, _)| *r == resolved) {
+                    found.push((resolved, config.poll_interval(gateway, &p)));
+                }
+            }
+        }
+    }
+    match found.len() {
+        0 => Err(format!("找不到引用的点位 {}（或该点位已停用）", reference).into()),
+        1 => Ok(found.remove(0)),
+        _ => Err(format!(
+            "引用的点位 {} 不唯一，请用 `网关/从站ID.点位` 的形式并写在花括号中",
+            reference
+        )
+        .into()),
+    }
+}
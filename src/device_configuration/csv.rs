@@ -0,0 +1,78 @@
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// CSV 文件输出配置，对应配置文件中的 `csv:` 段
+///
+/// ```yaml
+/// csv:
+///   directory: /var/lib/ems/csv
+///   decimals: 2
+///   flush_interval_ms: 5000
+///   timezone: Asia/Shanghai
+///   retention_days: 90
+/// ```
+///
+/// 每个从站每天一个文件，第一行为点位名称，每个采集周期一行。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CsvSettings {
+    /// 文件所在目录，不存在时自动创建
+    pub directory: String,
+    /// 数值保留的小数位数（默认3）
+    #[serde(default = "default_decimals")]
+    pub decimals: usize,
+    /// 写入文件的间隔（毫秒，默认5000）
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// 时区（IANA 名称，例如 Asia/Shanghai），决定文件日期、午夜切换和时间列，未配置时使用全局 timezone，都未配置时使用系统时区
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// 文件保留天数，更早的文件自动删除，未配置时不删除
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u32>,
+}
+
+fn default_decimals() -> usize {
+    3
+}
+
+fn default_flush_interval_ms() -> u64 {
+    5000
+}
+
+impl CsvSettings {
+    /// 解析配置的时区，未配置时为 None（使用全局 timezone）
+    pub fn tz(&self) -> Result<Option<Tz>, Box<dyn Error>> {
+        match &self.timezone {
+            Some(name) => match name.parse::<Tz>() {
+                Ok(tz) => Ok(Some(tz)),
+                Err(_) => Err(format!("csv.timezone 不是合法的时区名称: {}", name).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// 检查 CSV 输出配置是否合法
+    ///
+    /// # 校验规则
+    /// * directory 不能为空
+    /// * decimals 不能超过 15
+    /// * flush_interval_ms 和 retention_days 必须大于0
+    /// * timezone 必须是合法的 IANA 时区名称
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.directory.trim().is_empty() {
+            return Err("csv.directory 不能为空".into());
+        }
+        if self.decimals > 15 {
+            return Err(format!("csv.decimals 不能超过15: {}", self.decimals).into());
+        }
+        if self.flush_interval_ms == 0 {
+            return Err("csv.flush_interval_ms 必须大于0".into());
+        }
+        if self.retention_days == Some(0) {
+            return Err("csv.retention_days 必须大于0".into());
+        }
+        self.tz()?;
+        Ok(())
+    }
+}
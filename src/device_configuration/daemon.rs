@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// 守护进程配置，对应配置文件中的 `daemon:` 段，只在 `run --daemon` 时使用
+///
+/// ```yaml
+/// daemon:
+///   pid_file: /run/modbus_pub/modbus_pub.pid
+///   startup_timeout_ms: 60000
+///   wait_for_mqtt: true
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct DaemonSettings {
+    /// PID 文件路径，启动时写入进程号，退出时删除；未配置时不写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid_file: Option<String>,
+    /// 启动超时时间（毫秒，默认60000），超时仍未就绪时以非0状态退出
+    #[serde(default = "default_startup_timeout_ms")]
+    pub startup_timeout_ms: u64,
+    /// 是否等连接到 MQTT Broker 后才就绪（默认 true）；为 false 时 Broker 不可用也正常启动，之后自动重连
+    #[serde(default = "default_true")]
+    pub wait_for_mqtt: bool,
+}
+
+impl Default for DaemonSettings {
+    fn default() -> Self {
+        DaemonSettings {
+            pid_file: None,
+            startup_timeout_ms: default_startup_timeout_ms(),
+            wait_for_mqtt: true,
+        }
+    }
+}
+
+fn default_startup_timeout_ms() -> u64 {
+    60_000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl DaemonSettings {
+    /// 检查守护进程配置是否合法
+    ///
+    /// # 校验规则
+    /// * pid_file 不能为空字符串
+    /// * startup_timeout_ms 必须大于0
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.pid_file.as_ref().is_some_and(|path| path.trim().is_empty()) {
+            return Err("daemon.pid_file 不能为空".into());
+        }
+        if self.startup_timeout_ms == 0 {
+            return Err("daemon.startup_timeout_ms 必须大于0".into());
+        }
+        Ok(())
+    }
+}
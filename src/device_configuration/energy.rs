@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::time::Duration;
+
+use super::computed::{resolve_reference, PointReference, COMPUTED_GATEWAY};
+use super::modbus::Config;
+use super::slave::check_topic_safe;
+
+/// 功率点位的单位
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerUnit {
+    /// 瓦
+    W,
+    /// 千瓦（默认）
+    #[default]
+    #[serde(rename = "kW")]
+    Kw,
+}
+
+impl PowerUnit {
+    /// 换算为瓦的倍数
+    pub fn watts(self) -> f64 {
+        match self {
+            PowerUnit::W => 1.0,
+            PowerUnit::Kw => 1000.0,
+        }
+    }
+}
+
+/// 电量点位输出的单位
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnergyUnit {
+    /// 瓦时
+    Wh,
+    /// 千瓦时（默认）
+    #[default]
+    #[serde(rename = "kWh")]
+    Kwh,
+}
+
+impl EnergyUnit {
+    /// 换算为瓦时的倍数
+    pub fn watt_hours(self) -> f64 {
+        match self {
+            EnergyUnit::Wh => 1.0,
+            EnergyUnit::Kwh => 1000.0,
+        }
+    }
+
+    /// 单位名称，作为电量点位的工程单位输出
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EnergyUnit::Wh => "Wh",
+            EnergyUnit::Kwh => "kWh",
+        }
+    }
+}
+
+/// 功率的累计方向
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EnergyDirection {
+    /// 按功率的正负累计，反向功率使电量减少（默认）
+    #[default]
+    Signed,
+    /// 只累计正功率
+    Import,
+    /// 只累计负功率，累计值为正数
+    Export,
+}
+
+impl EnergyDirection {
+    /// 按方向取参与累计的功率
+    pub fn apply(self, power: f64) -> f64 {
+        match self {
+            EnergyDirection::Signed => power,
+            EnergyDirection::Import => power.max(0.0),
+            EnergyDirection::Export => (-power).max(0.0),
+        }
+    }
+}
+
+/// 电量累计配置，对应配置文件中的 `energy:` 段
+///
+/// ```yaml
+/// energy:
+///   state_file: /var/lib/ems/energy.json
+///   points:
+///     - name: pcs_energy
+///       source: PCS-A.active_power
+///     - name: grid_import
+///       source: "{meter-1.power}"
+///       power_unit: W
+///       direction: import
+///     - name: grid_export
+///       source: "{meter-1.power}"
+///       power_unit: W
+///       direction: export
+///       max_gap_ms: 30000
+/// ```
+///
+/// 按功率点位相邻两次正常读数的平均值乘以时间间隔累计电量，累计值保存在 `state_file` 中，程序重启后继续累计。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EnergySettings {
+    /// 保存累计值的文件路径，不存在时自动创建
+    pub state_file: String,
+    /// 保存累计值的间隔，单位毫秒（默认10000）；程序正常退出时也会保存
+    #[serde(default = "default_save_interval_ms")]
+    pub save_interval_ms: u64,
+    /// 电量点位
+    #[serde(default)]
+    pub points: Vec<EnergyPoint>,
+}
+
+fn default_save_interval_ms() -> u64 {
+    10000
+}
+
+/// 一个由功率点位累计得到的电量点位
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EnergyPoint {
+    /// 点位名称，不能与其他电量点位和计算点位重复，不能包含 +、#、/ 和空白字符
+    pub name: String,
+    /// 功率点位，写法与计算点位表达式中的引用相同，也可以是 `computed.计算点位`
+    pub source: String,
+    /// 功率点位的单位（默认 kW）
+    #[serde(default)]
+    pub power_unit: PowerUnit,
+    /// 输出的电量单位（默认 kWh）
+    #[serde(default)]
+    pub unit: EnergyUnit,
+    /// 累计方向（默认 signed）
+    #[serde(default)]
+    pub direction: EnergyDirection,
+    /// 两次读数的间隔超过该时间时不累计这一段，单位毫秒，未配置时为功率点位采集周期的3倍
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_gap_ms: Option<u64>,
+}
+
+impl EnergySettings {
+    /// 保存累计值的间隔
+    pub fn save_interval(&self) -> Duration {
+        Duration::from_millis(self.save_interval_ms)
+    }
+
+    /// 检查电量累计配置是否合法
+    ///
+    /// # 校验规则
+    /// * state_file 不能为空，save_interval_ms 必须大于0
+    /// * 点位名称合法，且不与其他电量点位和计算点位重复
+    /// * max_gap_ms 配置时必须大于0，引用的功率点位必须存在
+    pub fn validate(&self, config: &Config) -> Result<(), Box<dyn Error>> {
+        if self.state_file.trim().is_empty() {
+            return Err("energy.state_file 不能为空".into());
+        }
+        if self.save_interval_ms == 0 {
+            return Err("energy.save_interval_ms 必须大于0".into());
+        }
+        let mut names: HashSet<&str> = config
+            .computed_points
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        for point in &self.points {
+            check_topic_safe("电量点位", &point.name)?;
+            if !names.insert(point.name.as_str()) {
+                return Err(format!("电量点位名称 {} 与其他电量点位或计算点位重复", point.name).into());
+            }
+            if point.max_gap_ms == Some(0) {
+                return Err(format!("电量点位 {} 的 max_gap_ms 必须大于0", point.name).into());
+            }
+            point.resolve(config)?;
+        }
+        Ok(())
+    }
+}
+
+impl EnergyPoint {
+    /// 把 source 对应到功率点位，同时返回允许累计的最长间隔
+    pub fn resolve(&self, config: &Config) -> Result<(PointReference, Duration), Box<dyn Error>> {
+        let prefix = format!("{}.", COMPUTED_GATEWAY);
+        let computed = self
+            .source
+            .trim_matches(|c| c == '{' || c == '}')
+            .strip_prefix(&prefix)
+            .and_then(|name| config.computed_points.iter().find(|p| p.name == name));
+        let (reference, interval) = match computed {
+            Some(point) => (
+                PointReference {
+                    gateway: COMPUTED_GATEWAY.to_string(),
+                    slave_id: 0,
+                    point: point.name.clone(),
+                },
+                point.resolve(config)?.max_age,
+            ),
+            None => resolve_reference(config, self.source.trim_matches(|c| c == '{' || c == '}'))
+                .map_err(|e| format!("电量点位 {} 的 source: {}", self.name, e))?,
+        };
+        let max_gap = self
+            .max_gap_ms
+            .map_or(interval * 3, Duration::from_millis);
+        Ok((reference, max_gap))
+    }
+}
@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+
+use super::secret::{REDACTED, redact, resolve_secret};
+
+/// HTTP 接口配置，对应配置文件中的 `http:` 段
+///
+/// ```yaml
+/// http:
+///   bind_address: 0.0.0.0
+///   port: 8080
+///   token_env: EMS_HTTP_TOKEN
+/// ```
+///
+/// 配置了 token 时，`/api/` 下的接口需要在请求头中带上 `Authorization: Bearer <token>`；
+/// token 可以通过 `token_env`（环境变量）或 `token_file`（文件）间接引用。
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct HttpSettings {
+    /// 监听地址（默认 0.0.0.0）
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// 监听端口（默认 8080）
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// 访问令牌，未配置时不需要认证
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// 从文件读取访问令牌
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<String>,
+    /// 从环境变量读取访问令牌
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    /// 为 true 时 `/metrics` 输出每个点位的最新值（`ems_point_value`），点位很多时序列数也很多（默认 false）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub point_metrics: bool,
+    /// 加载配置时解析出的实际令牌，不会写回配置文件
+    #[serde(skip)]
+    resolved_token: Option<String>,
+}
+
+impl fmt::Debug for HttpSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpSettings")
+            .field("bind_address", &self.bind_address)
+            .field("port", &self.port)
+            .field("token", &redact(&self.token))
+            .field("token_file", &self.token_file)
+            .field("token_env", &self.token_env)
+            .field("point_metrics", &self.point_metrics)
+            .field("resolved_token", &redact(&self.resolved_token))
+            .finish()
+    }
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            bind_address: default_bind_address(),
+            port: default_port(),
+            token: None,
+            token_file: None,
+            token_env: None,
+            point_metrics: false,
+            resolved_token: None,
+        }
+    }
+}
+
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl HttpSettings {
+    /// 解析间接引用的访问令牌（优先级：环境变量 > 文件 > 直接写入）
+    pub fn resolve_secrets(&mut self) -> Result<(), Box<dyn Error>> {
+        self.resolved_token = resolve_secret(
+            "http.token",
+            self.token.as_deref(),
+            self.token_file.as_deref(),
+            self.token_env.as_deref(),
+        )?;
+        Ok(())
+    }
+
+    /// 实际使用的访问令牌，未调用 resolve_secrets 时为直接写入的令牌
+    pub fn effective_token(&self) -> Option<&str> {
+        self.resolved_token.as_deref().or(self.token.as_deref())
+    }
+
+    /// 直接写入的访问令牌替换为 `***` 后的配置
+    pub fn redacted(&self) -> HttpSettings {
+        HttpSettings {
+            token: self.token.as_ref().map(|_| REDACTED.to_string()),
+            resolved_token: None,
+            ..self.clone()
+        }
+    }
+
+    /// 检查 HTTP 接口配置是否合法
+    ///
+    /// # 校验规则
+    /// * bind_address 必须是合法的IPv4/IPv6地址
+    /// * port 不能为0
+    /// * 访问令牌不能为空
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.bind_address.parse::<IpAddr>().is_err() {
+            return Err(format!("http.bind_address 不合法: {}", self.bind_address).into());
+        }
+        if self.port == 0 {
+            return Err("http.port 不能为0".into());
+        }
+        if self
+            .effective_token()
+            .is_some_and(|token| token.trim().is_empty())
+        {
+            return Err("http.token 不能为空".into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use super::modbus::{parse_file, Config, LoadOptions};
+use super::units::Quantity;
+
+// 合并过程中的中间状态，记录每一项配置来自哪个文件，便于冲突时报告
+struct MergeState {
+    config: Config,
+    gateway_sources: Vec<PathBuf>,
+    mqtt_source: Option<PathBuf>,
+    logging_source: Option<PathBuf>,
+    storage_source: Option<PathBuf>,
+    csv_source: Option<PathBuf>,
+    http_source: Option<PathBuf>,
+    staleness_source: Option<PathBuf>,
+    energy_source: Option<PathBuf>,
+    max_concurrent_requests_source: Option<PathBuf>,
+    timezone_source: Option<PathBuf>,
+    daemon_source: Option<PathBuf>,
+    target_unit_sources: HashMap<Quantity, PathBuf>,
+    poll_group_sources: HashMap<String, PathBuf>,
+    template_sources: HashMap<String, PathBuf>,
+}
+
+impl MergeState {
+    fn new() -> Self {
+        MergeState {
+            config: Config::default(),
+            gateway_sources: Vec::new(),
+            mqtt_source: None,
+            logging_source: None,
+            storage_source: None,
+            csv_source: None,
+            http_source: None,
+            staleness_source: None,
+            energy_source: None,
+            max_concurrent_requests_source: None,
+            timezone_source: None,
+            daemon_source: None,
+            target_unit_sources: HashMap::new(),
+            poll_group_sources: HashMap::new(),
+            template_sources: HashMap::new(),
+        }
+    }
+
+    // 将一个文件中的配置合并进来
+    fn merge(&mut self, fragment: Config, source: &Path) -> Result<(), Box<dyn Error>> {
+        merge_once(
+            "mqtt",
+            &mut self.config.mqtt,
+            &mut self.mqtt_source,
+            fragment.mqtt,
+            source,
+        )?;
+        merge_once(
+            "logging",
+            &mut self.config.logging,
+            &mut self.logging_source,
+            fragment.logging,
+            source,
+        )?;
+        merge_once(
+            "storage",
+            &mut self.config.storage,
+            &mut self.storage_source,
+            fragment.storage,
+            source,
+        )?;
+        merge_once(
+            "csv",
+            &mut self.config.csv,
+            &mut self.csv_source,
+            fragment.csv,
+            source,
+        )?;
+        merge_once(
+            "http",
+            &mut self.config.http,
+            &mut self.http_source,
+            fragment.http,
+            source,
+        )?;
+        merge_once(
+            "staleness",
+            &mut self.config.staleness,
+            &mut self.staleness_source,
+            fragment.staleness,
+            source,
+        )?;
+        merge_once(
+            "energy",
+            &mut self.config.energy,
+            &mut self.energy_source,
+            fragment.energy,
+            source,
+        )?;
+        merge_once(
+            "max_concurrent_requests",
+            &mut self.config.max_concurrent_requests,
+            &mut self.max_concurrent_requests_source,
+            fragment.max_concurrent_requests,
+            source,
+        )?;
+        merge_once(
+            "timezone",
+            &mut self.config.timezone,
+            &mut self.timezone_source,
+            fragment.timezone,
+            source,
+        )?;
+        merge_once(
+            "daemon",
+            &mut self.config.daemon,
+            &mut self.daemon_source,
+            fragment.daemon,
+            source,
+        )?;
+        for (quantity, unit) in fragment.target_units {
+            if let Some(first) = self.target_unit_sources.get(&quantity) {
+                return Err(format!(
+                    "target_units.{} 在 {} 和 {} 中重复定义",
+                    quantity,
+                    first.display(),
+                    source.display()
+                )
+                .into());
+            }
+            self.target_unit_sources
+                .insert(quantity, source.to_path_buf());
+            self.config.target_units.insert(quantity, unit);
+        }
+        for (name, group) in fragment.poll_groups {
+            if let Some(first) = self.poll_group_sources.get(&name) {
+                return Err(format!(
+                    "采集组 {} 在 {} 和 {} 中重复定义",
+                    name,
+                    first.display(),
+                    source.display()
+                )
+                .into());
+            }
+            self.poll_group_sources
+                .insert(name.clone(), source.to_path_buf());
+            self.config.poll_groups.insert(name, group);
+        }
+        for (name, points) in fragment.templates {
+            if let Some(first) = self.template_sources.get(&name) {
+                return Err(format!(
+                    "模板 {} 在 {} 和 {} 中重复定义",
+                    name,
+                    first.display(),
+                    source.display()
+                )
+                .into());
+            }
+            self.template_sources.insert(name.clone(), source.to_path_buf());
+            self.config.templates.insert(name, points);
+        }
+        // 计算点位、报警和定时写入的名称重复由 Config::validate 报告
+        self.config.computed_points.extend(fragment.computed_points);
+        self.config.alarms.extend(fragment.alarms);
+        self.config.schedules.extend(fragment.schedules);
+        for gateway in fragment.gateways {
+            if let Some(index) = self.config.gateways.iter().position(|g| {
+                g.ip == gateway.ip
+                    && g.port == gateway.port
+                    && !(g.allow_duplicates && gateway.allow_duplicates)
+            }) {
+                let first = &self.gateway_sources[index];
+                // 同一文件内的重复由 Config::validate 报告
+                if first != source {
+                    return Err(format!(
+                        "网关 {}:{} 在 {} 和 {} 中重复定义",
+                        gateway.ip,
+                        gateway.port,
+                        first.display(),
+                        source.display()
+                    )
+                    .into());
+                }
+            }
+            self.config.gateways.push(gateway);
+            self.gateway_sources.push(source.to_path_buf());
+        }
+        Ok(())
+    }
+}
+
+// 合并只能在一个文件中出现的配置段，记录来源文件以便重复时报告
+fn merge_once<T>(
+    name: &str,
+    slot: &mut Option<T>,
+    slot_source: &mut Option<PathBuf>,
+    value: Option<T>,
+    source: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let Some(value) = value else {
+        return Ok(());
+    };
+    if let Some(first) = slot_source {
+        return Err(format!(
+            "{} 配置只能出现一次，但在 {} 和 {} 中都有定义",
+            name,
+            first.display(),
+            source.display()
+        )
+        .into());
+    }
+    *slot = Some(value);
+    *slot_source = Some(source.to_path_buf());
+    Ok(())
+}
+
+/// 读取配置文件，并递归合并 `include` 中列出的文件
+///
+/// # 合并规则
+/// * 各文件的网关列表按出现顺序拼接
+/// * 同一 ip:port 出现在不同文件中时报错（双方都设置 allow_duplicates 时除外），错误信息包含两个文件路径
+/// * 采集组和模板按名称合并，target_units 按物理量合并，同名项出现在多个文件中时报错
+/// * 计算点位、报警和定时写入列表按出现顺序拼接
+/// * mqtt、logging、storage、csv、http、staleness、energy、daemon 段以及 max_concurrent_requests、timezone 都只能在一个文件中出现
+/// * include 中的相对路径相对于声明它的文件所在目录解析
+/// * 循环引用会报错
+///
+/// 返回的配置中 `include` 为空，即已经展开为一份完整配置；合并进来的文件记录在 `included_files` 中
+pub fn load_with_includes(path: &Path, options: &LoadOptions) -> Result<Config, Box<dyn Error>> {
+    let mut state = MergeState::new();
+    let mut stack = Vec::new();
+    load_into(&mut state, path, options, &mut stack)?;
+    Ok(state.config)
+}
+
+fn load_into(
+    state: &mut MergeState,
+    path: &Path,
+    options: &LoadOptions,
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| format!("无法打开配置文件 {}: {}", path.display(), e))?;
+    if stack.contains(&canonical) {
+        return Err(format!("配置文件 {} 存在循环 include", path.display()).into());
+    }
+
+    let mut fragment = parse_file(path, options)?;
+    let includes = std::mem::take(&mut fragment.include);
+    state.merge(fragment, path)?;
+
+    stack.push(canonical);
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    for include in includes {
+        let include_path = base_dir.join(&include);
+        state.config.included_files.push(include_path.clone());
+        load_into(state, &include_path, options, stack)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::{read_config, write_config};
+    use std::fs;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn merges_gateways_and_records_included_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("sites")).unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ninclude: [sites/a.yaml]\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n",
+        );
+        write(
+            &dir.path().join("sites"),
+            "a.yaml",
+            "version: 2\ninclude: [b.yaml]\ngateways:\n  - ip: 10.0.0.2\n    slave_ids: [1]\n",
+        );
+        write(
+            &dir.path().join("sites"),
+            "b.yaml",
+            "version: 2\ngateways:\n  - ip: 10.0.0.3\n    slave_ids: [1]\n",
+        );
+        let config = load_with_includes(&main, &LoadOptions::default()).unwrap();
+        let ips: Vec<&str> = config.gateways.iter().map(|g| g.ip.as_str()).collect();
+        assert_eq!(ips, ["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+        assert!(config.include.is_empty());
+        assert_eq!(
+            config.included_files,
+            [
+                dir.path().join("sites/a.yaml"),
+                dir.path().join("sites/b.yaml")
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_gateway_across_files_names_both() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ninclude: [other.yaml]\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n",
+        );
+        write(
+            dir.path(),
+            "other.yaml",
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [2]\n",
+        );
+        let error = load_with_includes(&main, &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("10.0.0.1:502"), "{}", error);
+        assert!(error.contains("main.yaml") && error.contains("other.yaml"), "{}", error);
+    }
+
+    #[test]
+    fn section_defined_twice_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ninclude: [other.yaml]\ntimezone: Asia/Shanghai\n",
+        );
+        write(dir.path(), "other.yaml", "version: 2\ntimezone: UTC\n");
+        let error = load_with_includes(&main, &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("timezone 配置只能出现一次"), "{}", error);
+    }
+
+    #[test]
+    fn mqtt_section_in_two_files_names_both() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ninclude: [mqtt.yaml]\nmqtt:\n  broker_host: 10.0.0.5\n  client_id: ems-1\n",
+        );
+        let other = write(
+            dir.path(),
+            "mqtt.yaml",
+            "version: 2\nmqtt:\n  broker_host: 10.0.0.6\n  client_id: ems-2\n",
+        );
+        let error = load_with_includes(&main, &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!("mqtt 配置只能出现一次，但在 {} 和 {} 中都有定义", main.display(), other.display())
+        );
+    }
+
+    #[test]
+    fn duplicate_template_across_files_names_both() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("templates")).unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ninclude: [templates/a.yaml, templates/b.yaml]\n",
+        );
+        let first = write(
+            &dir.path().join("templates"),
+            "a.yaml",
+            "version: 2\ntemplates:\n  meter:\n    - { name: power, address: 0 }\n",
+        );
+        let second = write(
+            &dir.path().join("templates"),
+            "b.yaml",
+            "version: 2\ntemplates:\n  pcs:\n    - { name: soc, address: 0 }\n  meter:\n    - { name: energy, address: 10 }\n",
+        );
+        let error = load_with_includes(&main, &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!("模板 meter 在 {} 和 {} 中重复定义", first.display(), second.display())
+        );
+    }
+
+    #[test]
+    fn circular_include_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(dir.path(), "a.yaml", "version: 2\ninclude: [b.yaml]\n");
+        write(dir.path(), "b.yaml", "version: 2\ninclude: [a.yaml]\n");
+        let error = load_with_includes(&main, &LoadOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("循环 include"), "{}", error);
+    }
+
+    #[test]
+    fn write_config_refuses_included_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ninclude: [other.yaml]\ngateways: []\n",
+        );
+        write(
+            dir.path(),
+            "other.yaml",
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n",
+        );
+        let main = main.to_str().unwrap();
+        let config = read_config(main).unwrap();
+        let error = write_config(main, &config).unwrap_err().to_string();
+        assert!(error.contains("other.yaml"), "{}", error);
+        // 原文件不变
+        assert!(fs::read_to_string(main).unwrap().contains("include"));
+    }
+
+    #[test]
+    fn write_config_accepts_single_file_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = write(
+            dir.path(),
+            "main.yaml",
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n",
+        );
+        let main = main.to_str().unwrap();
+        let config = read_config(main).unwrap();
+        assert!(config.included_files.is_empty());
+        write_config(main, &config).unwrap();
+        assert_eq!(read_config(main).unwrap(), config);
+    }
+}
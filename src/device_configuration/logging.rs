@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// 日志输出格式
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// 每条日志一行文本
+    #[default]
+    Plain,
+    /// 每条日志一行 JSON，便于日志收集系统解析
+    Json,
+}
+
+/// 日志配置，对应配置文件中的 `logging:` 段
+///
+/// ```yaml
+/// logging:
+///   format: json
+/// ```
+///
+/// 日志级别由环境变量 `RUST_LOG` 控制（默认 info），例如 `RUST_LOG=modbus_pub=debug`。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct LoggingSettings {
+    /// 输出格式（默认 plain）
+    #[serde(default)]
+    pub format: LogFormat,
+}
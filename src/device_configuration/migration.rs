@@ -0,0 +1,217 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::path::Path;
+
+use super::modbus::{deserialize_checked, Config, ModbusDevice};
+
+/// 当前程序使用的配置文件格式版本
+///
+/// # 版本历史
+/// * 1: 最初的格式，网关只有 ip、port、slave_ids，文件中没有 version 字段
+/// * 2: 增加 version、include、mqtt、poll_groups、templates、点位定义、网关与从站名称以及各项默认值
+pub const CURRENT_CONFIG_VERSION: u32 = 2;
+
+// 版本1的配置结构，保持当时的样子不再修改
+mod v1 {
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct Gateway {
+        pub ip: String,
+        #[serde(default = "default_port")]
+        pub port: u16,
+        pub slave_ids: Vec<u8>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Config {
+        #[serde(default)]
+        pub version: Option<u32>,
+        pub gateways: Vec<Gateway>,
+    }
+
+    fn default_port() -> u16 {
+        super::super::modbus::DEFAULT_PORT
+    }
+}
+
+impl From<v1::Config> for Config {
+    fn from(old: v1::Config) -> Self {
+        Config {
+            gateways: old
+                .gateways
+                .into_iter()
+                .map(|g| ModbusDevice::new(&g.ip, g.port, g.slave_ids))
+                .collect(),
+            ..Config::default()
+        }
+    }
+}
+
+// 只读取版本号，其余字段留给对应版本的结构解析
+#[derive(Deserialize)]
+struct VersionProbe {
+    #[serde(default = "first_version")]
+    version: u32,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// 将任意版本的配置解析为当前版本的 `Config`
+///
+/// # 返回值
+/// * `Ok((Config, None))` - 文件已经是当前版本
+/// * `Ok((Config, Some(version)))` - 文件是旧版本 `version`，已迁移到当前版本
+/// * `Err` - 解析失败，或文件版本比当前程序支持的版本更新
+pub fn upgrade(
+    value: serde_yaml::Value,
+    path: &Path,
+) -> Result<(Config, Option<u32>), Box<dyn Error>> {
+    let probe: VersionProbe = serde_yaml::from_value(value.clone())
+        .map_err(|e| format!("配置文件 {} 的版本号不合法: {}", path.display(), e))?;
+
+    match probe.version {
+        CURRENT_CONFIG_VERSION => Ok((deserialize_checked(value, path)?, None)),
+        1 => match deserialize_checked::<v1::Config>(value.clone(), path) {
+            Ok(old) => Ok((old.into(), Some(1))),
+            // 按当前版本能解析时，说明文件用了新字段但没有声明版本，给出提示；
+            // 否则是拼写错误等问题，原样报告
+            Err(e) if deserialize_checked::<Config>(value, path).is_ok() => Err(format!(
+                "{}（版本1配置不支持新字段，如需使用请声明 version: {}）",
+                e, CURRENT_CONFIG_VERSION
+            )
+            .into()),
+            Err(e) => Err(e),
+        },
+        version if version > CURRENT_CONFIG_VERSION => Err(format!(
+            "配置文件 {} 的版本为 {}，需要更新版本的 ems（当前支持版本 {}）",
+            path.display(),
+            version,
+            CURRENT_CONFIG_VERSION
+        )
+        .into()),
+        version => Err(format!(
+            "配置文件 {} 的版本 {} 不存在",
+            path.display(),
+            version
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::{read_config, read_config_with, LoadOptions};
+    use std::fs;
+
+    // 固定不变的版本1配置文件
+    fn v1_fixture() -> String {
+        format!("{}/tests/fixtures/v1.yaml", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    // 网关地址和从站ID
+    fn summary(config: &Config) -> Vec<(String, u16, Vec<u8>)> {
+        config
+            .gateways
+            .iter()
+            .map(|g| (g.ip.clone(), g.port, g.slave_ids.iter().map(|s| s.id).collect()))
+            .collect()
+    }
+
+    fn upgrade_str(yaml: &str) -> Result<(Config, Option<u32>), Box<dyn Error>> {
+        upgrade(serde_yaml::from_str(yaml).unwrap(), Path::new("test.yaml"))
+    }
+
+    #[test]
+    fn v1_port_defaults_to_502() {
+        let (config, migrated) = upgrade_str("gateways: [{ip: \"10.0.0.1\", slave_ids: [1]}]").unwrap();
+        assert_eq!(migrated, Some(1));
+        assert_eq!(config.gateways[0].port, 502);
+        assert_eq!(config.gateways[0].slave_ids[0].id, 1);
+    }
+
+    #[test]
+    fn v1_keeps_explicit_port() {
+        let (config, _) =
+            upgrade_str("gateways: [{ip: \"10.0.0.1\", port: 1502, slave_ids: [1, 2]}]").unwrap();
+        assert_eq!(config.gateways[0].port, 1502);
+        assert_eq!(config.gateways[0].slave_ids.len(), 2);
+    }
+
+    #[test]
+    fn typo_has_no_version_hint() {
+        let error = upgrade_str("gateways: [{ip: \"10.0.0.1\", slave_ids: [1], prot: 5}]")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("gateways.0.prot"), "{}", error);
+        assert!(!error.contains("version"), "{}", error);
+    }
+
+    #[test]
+    fn v2_field_without_version_has_hint() {
+        let error = upgrade_str(
+            "gateways: [{ip: \"10.0.0.1\", slave_ids: [1], points: [{name: a, address: 0}]}]",
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("gateways.0.points"), "{}", error);
+        assert!(error.contains("version: 2"), "{}", error);
+    }
+
+    #[test]
+    fn current_version_is_not_migrated() {
+        let (_, migrated) = upgrade_str("version: 2\ngateways: []").unwrap();
+        assert_eq!(migrated, None);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let error = upgrade_str("version: 99\ngateways: []").unwrap_err().to_string();
+        assert!(error.contains("99"), "{}", error);
+    }
+
+    #[test]
+    fn frozen_v1_fixture_still_loads() {
+        let original = fs::read_to_string(v1_fixture()).unwrap();
+        let config = read_config(&v1_fixture()).unwrap();
+        assert_eq!(
+            summary(&config),
+            [
+                ("192.168.1.10".to_string(), 502, vec![1, 2]),
+                ("192.168.1.11".to_string(), 1502, vec![3])
+            ]
+        );
+        // 未要求写回时不修改文件
+        assert_eq!(fs::read_to_string(v1_fixture()).unwrap(), original);
+    }
+
+    #[test]
+    fn migrate_config_rewrites_file_and_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let original = fs::read_to_string(v1_fixture()).unwrap();
+        fs::write(&path, &original).unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = LoadOptions { write_back_migration: true, quiet: true, ..LoadOptions::default() };
+        let config = read_config_with(path, &options).unwrap();
+
+        // 原文件原样备份到 .bak
+        assert_eq!(fs::read_to_string(format!("{}.bak", path)).unwrap(), original);
+        // 写回的文件为当前版本，再次读取不需要迁移，内容与迁移结果相同
+        let migrated = fs::read_to_string(path).unwrap();
+        assert!(migrated.contains(&format!("version: {}", CURRENT_CONFIG_VERSION)), "{}", migrated);
+        let (reloaded, migrated_from) = upgrade(serde_yaml::from_str(&migrated).unwrap(), Path::new(path)).unwrap();
+        assert_eq!(migrated_from, None);
+        assert_eq!(reloaded, config);
+        assert_eq!(summary(&reloaded), summary(&read_config(&v1_fixture()).unwrap()));
+
+        // 已是当前版本时不再写回，备份保持为迁移前的内容
+        read_config_with(path, &options).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), migrated);
+        assert_eq!(fs::read_to_string(format!("{}.bak", path)).unwrap(), original);
+    }
+}
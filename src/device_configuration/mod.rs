@@ -1 +1,46 @@
+/// 报警配置
+pub mod alarm;
+/// 寄存器位域
+pub mod bit_field;
+/// 计算点位配置
+pub mod computed;
+/// CSV 文件输出配置
+pub mod csv;
+/// 守护进程配置
+pub mod daemon;
+/// 电量累计配置
+pub mod energy;
+/// HTTP 接口配置
+pub mod http;
+/// 合并 include 引用的配置文件
+pub mod include;
+/// 日志配置
+pub mod logging;
+/// 旧版本配置的迁移
+pub mod migration;
+/// 配置文件的结构、加载、校验和编辑
 pub mod modbus;
+/// MQTT 配置
+pub mod mqtt;
+/// 点位定义
+pub mod point;
+/// 采集组
+pub mod poll_group;
+/// 内置点位表
+pub mod profiles;
+/// 定时写入配置
+pub mod schedule;
+/// 从环境变量或文件读取密码等敏感配置
+pub mod secret;
+/// 模拟数据参数
+pub mod simulation;
+/// 从站配置
+pub mod slave;
+/// 过期数据检测配置
+pub mod staleness;
+/// 本地存储配置
+pub mod storage;
+/// 单位换算
+pub mod units;
+/// 通信看门狗配置
+pub mod watchdog;
@@ -1,33 +1,725 @@
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_yaml;
-use std::fs::{File, OpenOptions};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
 
-// 定义 ModbusDevice 结构体
-#[derive(Deserialize, Serialize, Debug)]
+use super::alarm::{validate_alarms, AlarmSettings};
+use super::computed::{validate_computed_points, ComputedPoint};
+use super::csv::CsvSettings;
+use super::daemon::DaemonSettings;
+use super::energy::EnergySettings;
+use super::http::HttpSettings;
+use super::include::load_with_includes;
+use super::logging::LoggingSettings;
+use super::migration::{self, CURRENT_CONFIG_VERSION};
+use super::mqtt::{MqttSettings, PublishOptions};
+use super::point::{merge_generated, validate_points, with_bit_points, Point};
+use super::profiles;
+use super::schedule::{validate_schedules, ScheduleSettings};
+use super::poll_group::PollGroup;
+use super::slave::{check_topic_safe, SlaveConfig};
+use super::staleness::StalenessSettings;
+use super::storage::StorageSettings;
+use super::units::{quantity_of, validate_target_units, Conversion, Quantity};
+use crate::mqtt::topic::Placeholder;
+
+/// 从站ID允许的最小值
+pub const MIN_SLAVE_ID: u8 = 1;
+/// 从站ID允许的最大值
+pub const MAX_SLAVE_ID: u8 = 247;
+
+/// 网关配置，对应配置文件中 `gateways:` 下的一项
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct ModbusDevice {
+    /// 网关名称，用于日志和 MQTT 主题，不能包含 +、#、/ 和空白字符
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// 网关IP地址
     pub ip: String,
+    /// 端口号（默认502）
+    #[serde(default = "default_port")]
     pub port: u16,
-    pub slave_ids: Vec<u8>,
+    /// 从站列表，可以直接写ID，也可以写成 {id, name} 对象
+    pub slave_ids: Vec<SlaveConfig>,
+    /// 是否启用该网关（默认启用）
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// 连接超时时间，单位毫秒（默认5000）
+    #[serde(default = "default_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// 单次读写请求超时时间，单位毫秒（默认5000）
+    #[serde(default = "default_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// 未指定采集组的点位的采集周期，单位毫秒（默认1000）
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// 每个从站需要采集的点位（默认为空）
+    #[serde(default)]
+    pub points: Vec<Point>,
+    /// 所有从站共用的点位表，可以是 templates 中的模板或内置点位表
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// 允许重复的从站ID、重叠的点位地址，以及与同样设置了该选项的网关共用 ip:port（默认 false）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub allow_duplicates: bool,
+    /// 可选设备，`check --probe --required-only` 时探测失败不影响结果（默认 false）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub optional: bool,
+    /// 不连接设备，按点位的 sim 参数生成模拟数据（默认 false）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub simulation: bool,
+    /// 模拟模式下每个读请求失败的概率，0-1（默认0）
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub sim_error_rate: f64,
+    /// 采集任务崩溃后一小时内最多重启的次数，超过后停止采集该网关；未配置时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_restarts_per_hour: Option<u32>,
+    /// 同时进行中的读请求数量上限（默认1），大于1时与网关建立同样数量的连接，轮流发送读请求
+    #[serde(default = "default_max_in_flight", skip_serializing_if = "is_one")]
+    pub max_in_flight: u32,
 }
 
-// 定义 Config 结构体
-#[derive(Deserialize, Serialize, Debug)]
+/// 配置文件的完整内容
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Config {
+    /// 配置文件格式版本，缺省视为版本1
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// 需要合并进来的其他配置文件，相对路径相对于当前文件所在目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// 为 true 时忽略未知字段，默认遇到未知字段（例如把 slave_ids 写成 slave_id）直接报错
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub allow_unknown_fields: bool,
+    /// 站点所在时区（IANA 名称，例如 Asia/Shanghai），CSV 文件和定时写入未配置时区时使用，
+    /// 也用于数据消息的 local_time；未配置时使用系统时区
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// 各物理量发布使用的单位，设置了 source_unit 的点位换算为这里的单位；采集组的 target_units 优先
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_units: BTreeMap<Quantity, String>,
+    /// 采集组名称到采集周期的映射
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub poll_groups: BTreeMap<String, PollGroup>,
+    /// 用户定义的点位模板，网关或从站通过 profile 引用
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, Vec<Point>>,
+    /// 网关列表
+    #[serde(default)]
     pub gateways: Vec<ModbusDevice>,
+    /// MQTT 配置，未配置时只运行 Modbus 采集
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttSettings>,
+    /// 日志配置，未配置时输出文本格式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LoggingSettings>,
+    /// 本地存储配置，未配置时不在本地保存采集数据
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageSettings>,
+    /// CSV 文件输出配置，未配置时不输出 CSV 文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csv: Option<CsvSettings>,
+    /// HTTP 接口配置，未配置时不启动 HTTP 服务
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpSettings>,
+    /// 过期数据检测配置，未配置时不检测
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staleness: Option<StalenessSettings>,
+    /// 由其他点位计算得到的点位
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub computed_points: Vec<ComputedPoint>,
+    /// 由功率点位累计得到的电量点位，未配置时不累计
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy: Option<EnergySettings>,
+    /// 报警列表
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alarms: Vec<AlarmSettings>,
+    /// 定时写入列表
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<ScheduleSettings>,
+    /// 所有网关合计同时进行中的 Modbus 请求数量上限，未配置时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<usize>,
+    /// 守护进程配置，`run --daemon` 时使用，未配置时使用默认值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daemon: Option<DaemonSettings>,
+    /// 读取时通过 include 合并进来的文件（不含主文件），不写入配置文件；不为空时 [`write_config`] 拒绝写回
+    #[serde(skip)]
+    pub included_files: Vec<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CURRENT_CONFIG_VERSION,
+            include: Vec::new(),
+            allow_unknown_fields: false,
+            timezone: None,
+            target_units: BTreeMap::new(),
+            poll_groups: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            gateways: Vec::new(),
+            mqtt: None,
+            logging: None,
+            storage: None,
+            csv: None,
+            http: None,
+            staleness: None,
+            computed_points: Vec::new(),
+            energy: None,
+            alarms: Vec::new(),
+            schedules: Vec::new(),
+            max_concurrent_requests: None,
+            daemon: None,
+            included_files: Vec::new(),
+        }
+    }
+}
+
+/// Modbus TCP 默认端口
+pub const DEFAULT_PORT: u16 = 502;
+
+fn default_version() -> u32 {
+    1
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_max_in_flight() -> u32 {
+    1
+}
+
+fn is_one(value: &u32) -> bool {
+    *value == 1
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+impl ModbusDevice {
+    /// 使用默认参数创建网关配置
+    pub fn new(ip: &str, port: u16, slave_ids: Vec<u8>) -> Self {
+        ModbusDevice {
+            name: None,
+            ip: ip.to_string(),
+            port,
+            slave_ids: slave_ids.into_iter().map(SlaveConfig::new).collect(),
+            enabled: true,
+            connect_timeout_ms: default_timeout_ms(),
+            request_timeout_ms: default_timeout_ms(),
+            poll_interval_ms: default_poll_interval_ms(),
+            points: Vec::new(),
+            profile: None,
+            allow_duplicates: false,
+            optional: false,
+            simulation: false,
+            sim_error_rate: 0.0,
+            max_restarts_per_hour: None,
+            max_in_flight: default_max_in_flight(),
+        }
+    }
+
+    /// 用于显示的名称，未配置名称时使用 ip:port
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{}:{}", self.ip, self.port),
+        }
+    }
+
+    /// 是否配置了需要周期采集的点位（直接定义的点位、引用的点位表或 SunSpec 发现生成的点位）
+    pub fn has_points(&self) -> bool {
+        !self.points.is_empty()
+            || self.profile.is_some()
+            || self.slave_ids.iter().any(|slave| {
+                slave.profile.is_some()
+                    || slave.discovered_points.as_ref().is_some_and(|points| !points.is_empty())
+            })
+    }
+
+    /// 已启用的从站
+    pub fn enabled_slaves(&self) -> impl Iterator<Item = &SlaveConfig> {
+        self.slave_ids.iter().filter(|slave| slave.enabled)
+    }
+
+    /// 查找指定ID的从站
+    pub fn find_slave(&self, slave_id: u8) -> Option<&SlaveConfig> {
+        self.slave_ids.iter().find(|s| s.id == slave_id)
+    }
+
+    /// 检查单个网关配置是否合法
+    ///
+    /// # 校验规则
+    /// * 网关和从站名称可以安全地用于 MQTT 主题
+    /// * IP地址必须是合法的IPv4/IPv6地址
+    /// * 端口号不能为0
+    /// * 从站ID范围1-247，且同一网关内不能重复
+    /// * 点位定义合法，且名称不能重复
+    /// * 功能码相同的点位地址范围不能重叠
+    /// * sim_error_rate 在 0-1 之间
+    /// * max_in_flight 至少为1
+    /// * 从站的看门狗配置合法
+    ///
+    /// 设置了 `allow_duplicates` 时跳过从站ID重复和点位地址重叠的检查
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(name) = &self.name {
+            check_topic_safe("网关", name)?;
+        }
+        if self.ip.parse::<IpAddr>().is_err() {
+            return Err(format!("网关IP地址不合法: {}", self.ip).into());
+        }
+        if self.port == 0 {
+            return Err(format!("网关 {} 的端口号不能为0", self.ip).into());
+        }
+        if self.poll_interval_ms == 0 {
+            return Err(format!("网关 {}:{} 的 poll_interval_ms 不能为0", self.ip, self.port).into());
+        }
+        if !(0.0..=1.0).contains(&self.sim_error_rate) {
+            return Err(format!(
+                "网关 {}:{} 的 sim_error_rate 必须在 0-1 之间，当前为 {}",
+                self.ip, self.port, self.sim_error_rate
+            )
+            .into());
+        }
+        if self.max_in_flight == 0 {
+            return Err(format!("网关 {}:{} 的 max_in_flight 不能为0", self.ip, self.port).into());
+        }
+        for (index, slave) in self.slave_ids.iter().enumerate() {
+            check_slave_id(slave.id)?;
+            if let Some(name) = &slave.name {
+                check_topic_safe("从站", name)?;
+            }
+            if let Some(watchdog) = &slave.watchdog {
+                watchdog.validate(&format!(
+                    "网关 {} 从站 {}",
+                    self.display_name(),
+                    slave.display_name()
+                ))?;
+            }
+            if self.allow_duplicates {
+                continue;
+            }
+            if let Some(first) = self.slave_ids[..index].iter().position(|s| s.id == slave.id) {
+                return Err(format!(
+                    "网关 {} 的 slave_ids 第{}项与第{}项重复（从站ID {}），如确有需要请设置 allow_duplicates: true",
+                    self.display_name(),
+                    first + 1,
+                    index + 1,
+                    slave.id
+                )
+                .into());
+            }
+        }
+        validate_points(
+            &format!("网关 {}", self.display_name()),
+            &self.points,
+            self.allow_duplicates,
+        )?;
+        Ok(())
+    }
+
+    // 两个网关是否使用同一 ip:port，且没有同时允许重复
+    fn conflicts_with(&self, other: &ModbusDevice) -> bool {
+        self.ip == other.ip
+            && self.port == other.port
+            && !(self.allow_duplicates && other.allow_duplicates)
+    }
+}
+
+// 错误信息中用于定位网关的描述，例如 "#2（PCS-B）"
+fn gateway_location(index: usize, gateway: &ModbusDevice) -> String {
+    match &gateway.name {
+        Some(name) => format!("#{}（{}）", index, name),
+        None => format!("#{}", index),
+    }
+}
+
+impl Config {
+    /// 检查整个配置是否合法
+    ///
+    /// 除了逐个校验网关外，还要求同一 ip:port 只能出现一次（双方都设置 allow_duplicates 时除外）、
+    /// timezone 必须是合法的 IANA 时区名称、target_units 中的单位必须属于对应的物理量、引用的点位表和采集组必须存在、每个从站展开后的点位不能冲突，并校验模板、MQTT、本地存储、CSV 输出、HTTP 接口、过期数据检测、计算点位、电量累计、报警、定时写入和守护进程配置，以及全局并发请求上限
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.tz()?;
+        validate_target_units("target_units", &self.target_units)?;
+        if self.max_concurrent_requests == Some(0) {
+            return Err("max_concurrent_requests 不能为0".into());
+        }
+        // 数据主题中使用了点位名称或采集组名称时，这些名称也要能用在主题中
+        let mut topic_points = false;
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.validate()?;
+            let topic = mqtt.data_topic()?;
+            topic_points = topic.uses(Placeholder::Point);
+            if topic.uses(Placeholder::Group) {
+                for name in self.poll_groups.keys() {
+                    check_topic_safe("采集组", name)?;
+                }
+            }
+        }
+        if let Some(storage) = &self.storage {
+            storage.validate()?;
+            if storage.forward && self.mqtt.is_none() {
+                return Err("storage.forward 需要同时配置 mqtt".into());
+            }
+            if storage.replay.is_some() && self.mqtt.as_ref().is_some_and(|m| m.sparkplug.is_some()) {
+                return Err("storage.replay 不能与 mqtt.sparkplug 同时使用".into());
+            }
+        }
+        if let Some(csv) = &self.csv {
+            csv.validate()?;
+        }
+        if let Some(http) = &self.http {
+            http.validate()?;
+        }
+        if let Some(staleness) = &self.staleness {
+            staleness.validate()?;
+        }
+        if let Some(daemon) = &self.daemon {
+            daemon.validate()?;
+        }
+        for (name, group) in &self.poll_groups {
+            group.validate(name)?;
+            if let (Some(mqtt), Some(format)) = (&self.mqtt, group.payload_format) {
+                mqtt.check_format(&format!("采集组 {} 的 payload_format", name), format)?;
+            }
+        }
+        for (name, points) in &self.templates {
+            check_topic_safe("模板", name)?;
+            validate_points(&format!("模板 {}", name), points, false)?;
+        }
+        for (index, gateway) in self.gateways.iter().enumerate() {
+            gateway.validate()?;
+            for slave in &gateway.slave_ids {
+                let points = self.effective_points(gateway, slave)?;
+                let location = format!("网关 {} 从站 {}", gateway.display_name(), slave.display_name());
+                validate_points(&location, &points, gateway.allow_duplicates)?;
+                for point in &points {
+                    if topic_points {
+                        for name in std::iter::once(&point.name).chain(point.bits.keys()) {
+                            check_topic_safe(&format!("{} 的点位", location), name)?;
+                        }
+                    }
+                    if let Some(group) = &point.group
+                        && !self.poll_groups.contains_key(group)
+                    {
+                        return Err(format!(
+                            "{} 的点位 {} 引用了不存在的采集组 {}",
+                            location, point.name, group
+                        )
+                        .into());
+                    }
+                }
+            }
+            if let Some(first) = self.gateways[..index]
+                .iter()
+                .position(|g| g.conflicts_with(gateway))
+            {
+                return Err(format!(
+                    "网关 {} 与网关 {} 使用了相同的地址 {}:{}，如确有需要请在两者中都设置 allow_duplicates: true",
+                    gateway_location(first, &self.gateways[first]),
+                    gateway_location(index, gateway),
+                    gateway.ip,
+                    gateway.port
+                )
+                .into());
+            }
+        }
+        validate_computed_points(self)?;
+        if let Some(energy) = &self.energy {
+            energy.validate(self)?;
+        }
+        validate_alarms(&self.alarms, self)?;
+        validate_schedules(&self.schedules, self)?;
+        Ok(())
+    }
+
+    /// 解析全局时区，未配置时为 None（使用系统时区）
+    pub fn tz(&self) -> Result<Option<Tz>, Box<dyn Error>> {
+        match &self.timezone {
+            Some(name) => match name.parse::<Tz>() {
+                Ok(tz) => Ok(Some(tz)),
+                Err(_) => Err(format!("timezone 不是合法的时区名称: {}", name).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// 解析所有间接引用的敏感配置（环境变量、文件）
+    pub fn resolve_secrets(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mqtt) = self.mqtt.as_mut() {
+            mqtt.resolve_secrets()?;
+        }
+        if let Some(http) = self.http.as_mut() {
+            http.resolve_secrets()?;
+        }
+        Ok(())
+    }
+
+    /// 密码、令牌等敏感信息替换为 `***` 后的配置，用于对外展示
+    pub fn redacted(&self) -> Config {
+        Config {
+            mqtt: self.mqtt.as_ref().map(MqttSettings::redacted),
+            http: self.http.as_ref().map(HttpSettings::redacted),
+            ..self.clone()
+        }
+    }
+
+    /// 按名称查找点位表，用户模板优先于内置点位表
+    pub fn profile_points(&self, name: &str) -> Result<Vec<Point>, Box<dyn Error>> {
+        if let Some(points) = self.templates.get(name) {
+            return Ok(points.clone());
+        }
+        match profiles::get(name)? {
+            Some(profile) => Ok(profile.points),
+            None => Err(format!("点位表 {} 不存在（既不是 templates 中的模板，也不是内置点位表）", name).into()),
+        }
+    }
+
+    /// 某个从站实际采集的点位：网关 profile、从站 profile 展开后的点位，网关上直接定义的点位，
+    /// 以及 SunSpec 发现生成的点位（与前面的点位冲突的跳过，见 [`merge_generated`]）
+    ///
+    /// 设置了 source_unit 的点位按采集组或全局的 target_units 确定换算，unit 为发布的单位，source_unit 清空
+    pub fn effective_points(
+        &self,
+        gateway: &ModbusDevice,
+        slave: &SlaveConfig,
+    ) -> Result<Vec<Point>, Box<dyn Error>> {
+        let mut points = Vec::new();
+        if let Some(profile) = &gateway.profile {
+            points.extend(self.profile_points(profile)?);
+        }
+        if let Some(profile) = &slave.profile {
+            points.extend(self.profile_points(profile)?);
+        }
+        points.extend(gateway.points.iter().cloned());
+        if let Some(discovered) = &slave.discovered_points {
+            merge_generated(&mut points, discovered);
+        }
+        for point in &mut points {
+            self.resolve_unit(point)?;
+        }
+        Ok(points)
+    }
+
+    // 由 source_unit 和 target_units 确定点位的单位换算；单位未知或与 unit 冲突的点位保持原样，由 Point::validate 报告
+    fn resolve_unit(&self, point: &mut Point) -> Result<(), Box<dyn Error>> {
+        let Some(source) = &point.source_unit else {
+            return Ok(());
+        };
+        let Some(quantity) = quantity_of(source) else {
+            return Ok(());
+        };
+        if point.unit.is_some() {
+            return Ok(());
+        }
+        let target = point
+            .group
+            .as_ref()
+            .and_then(|name| self.poll_groups.get(name))
+            .and_then(|group| group.target_units.get(&quantity))
+            .or_else(|| self.target_units.get(&quantity));
+        let unit = match target {
+            Some(target) if target != source => {
+                point.conversion = Some(
+                    Conversion::between(source, target)
+                        .map_err(|e| format!("点位 {} 的单位换算无效: {}", point.name, e))?,
+                );
+                target.clone()
+            }
+            _ => source.clone(),
+        };
+        point.unit = Some(unit);
+        point.source_unit = None;
+        Ok(())
+    }
+
+    /// 某个从站实际需要读取的点位，即 effective_points 中已启用的部分
+    pub fn enabled_points(
+        &self,
+        gateway: &ModbusDevice,
+        slave: &SlaveConfig,
+    ) -> Result<Vec<Point>, Box<dyn Error>> {
+        let mut points = self.effective_points(gateway, slave)?;
+        points.retain(|point| point.enabled);
+        Ok(points)
+    }
+
+    /// 某个从站发布的点位：enabled_points 以及由其中 `bits` 生成的点位
+    pub fn published_points(
+        &self,
+        gateway: &ModbusDevice,
+        slave: &SlaveConfig,
+    ) -> Result<Vec<Point>, Box<dyn Error>> {
+        Ok(with_bit_points(self.enabled_points(gateway, slave)?))
+    }
+
+    /// 点位的采集周期，未指定采集组时使用网关的 poll_interval_ms
+    pub fn poll_interval(&self, gateway: &ModbusDevice, point: &Point) -> Duration {
+        let interval_ms = point
+            .group
+            .as_ref()
+            .and_then(|name| self.poll_groups.get(name))
+            .map(|group| group.interval_ms)
+            .unwrap_or(gateway.poll_interval_ms);
+        Duration::from_millis(interval_ms)
+    }
+
+    /// 点位数据发布使用的 QoS、retain、死区、消息格式和压缩方式
+    ///
+    /// 优先级：点位 > 采集组 > mqtt 段；未配置 mqtt 段时使用其默认值。
+    /// 死区只在点位和采集组上配置，消息格式和压缩方式只在采集组和 mqtt 段上配置
+    pub fn publish_options(&self, point: &Point) -> PublishOptions {
+        let global = self.default_publish_options();
+        let group = point.group.as_ref().and_then(|name| self.poll_groups.get(name));
+        PublishOptions {
+            qos: point
+                .qos
+                .or(group.and_then(|g| g.qos))
+                .unwrap_or(global.qos),
+            retain: point
+                .retain
+                .or(group.and_then(|g| g.retain))
+                .unwrap_or(global.retain),
+            deadband: point.deadband.or(group.and_then(|g| g.deadband)),
+            format: group
+                .and_then(|g| g.payload_format)
+                .unwrap_or(global.format),
+            compression: group
+                .and_then(|g| g.compression)
+                .unwrap_or(global.compression),
+        }
+    }
+
+    /// mqtt 段的 QoS、retain、消息格式和压缩方式，未配置 mqtt 段时为默认值；用于不属于任何采集组的计算点位
+    pub fn default_publish_options(&self) -> PublishOptions {
+        self.mqtt.as_ref().map_or_else(PublishOptions::default, |mqtt| PublishOptions {
+            qos: mqtt.qos,
+            retain: mqtt.retain,
+            deadband: None,
+            format: mqtt.payload_format,
+            compression: mqtt.compression,
+        })
+    }
+
+    /// 查找指定 ip:port 的网关
+    pub fn find_gateway(&self, ip: &str, port: u16) -> Option<&ModbusDevice> {
+        self.gateways.iter().find(|g| g.ip == ip && g.port == port)
+    }
+
+    /// 添加网关
+    ///
+    /// # 返回值
+    /// * `Ok(())` - 添加成功
+    /// * `Err` - 网关配置不合法或 ip:port 已存在，配置保持不变
+    pub fn add_gateway(&mut self, gateway: ModbusDevice) -> Result<(), Box<dyn Error>> {
+        gateway.validate()?;
+        if self.gateways.iter().any(|g| g.conflicts_with(&gateway)) {
+            return Err(format!("网关 {}:{} 已存在", gateway.ip, gateway.port).into());
+        }
+        self.gateways.push(gateway);
+        Ok(())
+    }
+
+    /// 删除网关
+    ///
+    /// # 返回值
+    /// * `Some(ModbusDevice)` - 被删除的网关配置
+    /// * `None` - 没有找到对应的网关
+    pub fn remove_gateway(&mut self, ip: &str, port: u16) -> Option<ModbusDevice> {
+        let index = self
+            .gateways
+            .iter()
+            .position(|g| g.ip == ip && g.port == port)?;
+        Some(self.gateways.remove(index))
+    }
+
+    /// 向已有网关添加从站ID
+    ///
+    /// # 返回值
+    /// * `Ok(())` - 添加成功
+    /// * `Err` - 网关不存在、从站ID不合法或已存在
+    pub fn add_slave(&mut self, ip: &str, port: u16, slave_id: u8) -> Result<(), Box<dyn Error>> {
+        check_slave_id(slave_id)?;
+        let gateway = self
+            .gateways
+            .iter_mut()
+            .find(|g| g.ip == ip && g.port == port)
+            .ok_or_else(|| format!("网关 {}:{} 不存在", ip, port))?;
+        if gateway.find_slave(slave_id).is_some() {
+            return Err(format!("网关 {}:{} 中从站ID {} 已存在", ip, port, slave_id).into());
+        }
+        gateway.slave_ids.push(SlaveConfig::new(slave_id));
+        Ok(())
+    }
+}
+
+// 检查从站ID是否在合法范围内
+fn check_slave_id(slave_id: u8) -> Result<(), Box<dyn Error>> {
+    if !(MIN_SLAVE_ID..=MAX_SLAVE_ID).contains(&slave_id) {
+        return Err(format!(
+            "从站ID {} 超出范围（{}-{}）",
+            slave_id, MIN_SLAVE_ID, MAX_SLAVE_ID
+        )
+        .into());
+    }
+    Ok(())
 }
 
-// 读取并解析 YAML 文件的函数
+/// 读取配置时的选项
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// 旧版本配置迁移后是否写回原文件（原文件备份为 .bak）
+    pub write_back_migration: bool,
+    /// 不输出迁移提示，用于定期重新加载配置
+    pub quiet: bool,
+    /// 所有网关都按模拟模式采集，相当于每个网关都设置了 `simulation: true`
+    pub simulate: bool,
+}
+
+/// 读取配置文件，文件不存在时创建空配置，旧版本配置迁移到当前版本（不写回文件）
 pub fn read_config(file_path: &str) -> Result<Config, Box<dyn std::error::Error>> {
+    read_config_with(file_path, &LoadOptions::default())
+}
+
+/// 按指定选项读取并解析 YAML 配置文件
+pub fn read_config_with(
+    file_path: &str,
+    options: &LoadOptions,
+) -> Result<Config, Box<dyn std::error::Error>> {
     let path = Path::new(file_path);
 
     // 如果文件不存在，创建空配置文件
     if !path.try_exists()? {
         // 创建空的配置结构
-        let empty_config = Config {
-            gateways: Vec::new(),
-        };
+        let empty_config = Config::default();
 
         // 序列化为 YAML
         let yaml = serde_yaml::to_string(&empty_config)?;
@@ -40,17 +732,366 @@ pub fn read_config(file_path: &str) -> Result<Config, Box<dyn std::error::Error>
             .open(file_path)?;
 
         file.write_all(yaml.as_bytes())?;
-        println!("配置文件不存在，已创建空配置文件: {}", file_path);
+        info!(path = file_path, "配置文件不存在，已创建空配置文件");
         return Ok(empty_config);
     }
 
-    // 打开并读取现有配置文件
-    let mut file = File::open(file_path)?;
+    // 读取配置文件并合并 include 引用的文件
+    let mut config = load_with_includes(path, options)?;
+    config.validate()?;
+    config.resolve_secrets()?;
+    if options.simulate {
+        for gateway in &mut config.gateways {
+            gateway.simulation = true;
+        }
+    }
+
+    Ok(config)
+}
+
+// 读取并解析单个 YAML 文件，不处理 include
+pub(crate) fn parse_file(path: &Path, options: &LoadOptions) -> Result<Config, Box<dyn Error>> {
+    let mut file =
+        File::open(path).map_err(|e| format!("无法打开配置文件 {}: {}", path.display(), e))?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    // 解析 YAML 内容
-    let config: Config = serde_yaml::from_str(&contents)?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&contents)
+        .map_err(|e| format!("解析配置文件 {} 失败: {}", path.display(), e))?;
 
+    let (config, migrated_from) = migration::upgrade(value, path)?;
+    if let Some(from_version) = migrated_from {
+        if !options.quiet {
+            info!(
+                path = %path.display(),
+                "配置文件已从版本 {} 迁移到版本 {}",
+                from_version,
+                CURRENT_CONFIG_VERSION
+            );
+        }
+        if options.write_back_migration {
+            let backup = format!("{}.bak", path.display());
+            fs::copy(path, &backup)?;
+            write_atomic(path, &serde_yaml::to_string(&config)?)?;
+            info!(backup = %backup, "已写回迁移后的配置文件");
+        }
+    }
     Ok(config)
 }
+
+// 反序列化配置，未知字段在未设置 allow_unknown_fields 时报错
+pub(crate) fn deserialize_checked<T: DeserializeOwned>(
+    value: serde_yaml::Value,
+    path: &Path,
+) -> Result<T, Box<dyn Error>> {
+    let allow_unknown_fields = value
+        .get("allow_unknown_fields")
+        .and_then(serde_yaml::Value::as_bool)
+        .unwrap_or(false);
+
+    // 记录反序列化过程中被忽略的字段
+    let mut unknown_fields = Vec::new();
+    let parsed: T = serde_ignored::deserialize(value, |field| {
+        unknown_fields.push(field.to_string())
+    })
+    .map_err(|e| format!("解析配置文件 {} 失败: {}", path.display(), e))?;
+
+    if !unknown_fields.is_empty() && !allow_unknown_fields {
+        return Err(format!(
+            "配置文件 {} 中存在未知字段: {}（如需忽略请设置 allow_unknown_fields: true）",
+            path.display(),
+            unknown_fields.join(", ")
+        )
+        .into());
+    }
+    Ok(parsed)
+}
+
+/// 将配置写回 YAML 文件
+///
+/// # 说明
+/// * 写入前会先校验配置
+/// * 先写入同目录下的临时文件再重命名覆盖，写入中途崩溃不会损坏原配置文件
+/// * 通过 include 合并了其他文件的配置不能写回：写入单个文件会把拆分的配置合并成一份，
+///   此时返回错误，需要直接编辑各个文件
+pub fn write_config(file_path: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    if !config.included_files.is_empty() {
+        let files: Vec<String> = config
+            .included_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(format!(
+            "配置由多个文件合并而成（include 了 {}），不能写回 {}，请直接编辑对应的文件",
+            files.join("、"),
+            file_path
+        )
+        .into());
+    }
+    config.validate()?;
+    let yaml = serde_yaml::to_string(config)?;
+    write_atomic(Path::new(file_path), &yaml)
+}
+
+// 先写临时文件再重命名，保证目标文件要么是旧内容要么是完整的新内容
+fn write_atomic(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    let tmp_path = format!("{}.tmp", path.display());
+    {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_in(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn edit_save_reload_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = path_in(&dir, "config.yaml");
+        // 文件不存在时创建空配置
+        let mut config = read_config(&path).unwrap();
+        assert_eq!(config, Config::default());
+
+        let mut gateway = ModbusDevice::new("192.168.1.10", 502, vec![1]);
+        gateway.name = Some("PCS-A".to_string());
+        gateway.points = serde_yaml::from_str("[{ name: voltage, address: 0 }]").unwrap();
+        config.add_gateway(gateway).unwrap();
+        config.add_gateway(ModbusDevice::new("192.168.1.11", 1502, vec![3])).unwrap();
+        config.add_slave("192.168.1.10", 502, 2).unwrap();
+        write_config(&path, &config).unwrap();
+
+        let reloaded = read_config(&path).unwrap();
+        assert_eq!(reloaded, config);
+        let slaves: Vec<u8> = reloaded.gateways[0].slave_ids.iter().map(|s| s.id).collect();
+        assert_eq!(slaves, [1, 2]);
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let removed = config.remove_gateway("192.168.1.11", 1502).unwrap();
+        assert_eq!(removed.slave_ids[0].id, 3);
+        write_config(&path, &config).unwrap();
+        assert_eq!(read_config(&path).unwrap().gateways.len(), 1);
+    }
+
+    #[test]
+    fn add_gateway_rejects_duplicate_address() {
+        let mut config = Config::default();
+        config.add_gateway(ModbusDevice::new("10.0.0.1", 502, vec![1])).unwrap();
+        let error = config
+            .add_gateway(ModbusDevice::new("10.0.0.1", 502, vec![2]))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "网关 10.0.0.1:502 已存在");
+        // 端口不同的是另一个网关
+        config.add_gateway(ModbusDevice::new("10.0.0.1", 503, vec![2])).unwrap();
+        assert_eq!(config.gateways.len(), 2);
+    }
+
+    #[test]
+    fn add_gateway_rejects_invalid_gateway() {
+        let mut config = Config::default();
+        assert!(config.add_gateway(ModbusDevice::new("not-an-ip", 502, vec![1])).is_err());
+        assert!(config.add_gateway(ModbusDevice::new("10.0.0.1", 502, vec![1, 1])).is_err());
+        assert!(config.gateways.is_empty());
+    }
+
+    #[test]
+    fn add_slave_rejects_duplicates_and_unknown_gateway() {
+        let mut config = Config::default();
+        config.add_gateway(ModbusDevice::new("10.0.0.1", 502, vec![1])).unwrap();
+
+        let duplicate = config.add_slave("10.0.0.1", 502, 1).unwrap_err();
+        assert_eq!(duplicate.to_string(), "网关 10.0.0.1:502 中从站ID 1 已存在");
+        let missing = config.add_slave("10.0.0.2", 502, 1).unwrap_err();
+        assert_eq!(missing.to_string(), "网关 10.0.0.2:502 不存在");
+        let out_of_range = config.add_slave("10.0.0.1", 502, 248).unwrap_err();
+        assert_eq!(out_of_range.to_string(), "从站ID 248 超出范围（1-247）");
+        assert_eq!(config.gateways[0].slave_ids.len(), 1);
+        assert!(config.remove_gateway("10.0.0.2", 502).is_none());
+    }
+
+    #[test]
+    fn minimal_config_gets_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = path_in(&dir, "config.yaml");
+        fs::write(&path, "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n").unwrap();
+
+        let config = read_config(&path).unwrap();
+        let gateway = &config.gateways[0];
+        assert_eq!(gateway.port, DEFAULT_PORT);
+        assert!(gateway.enabled);
+        assert_eq!(gateway.connect_timeout_ms, 5000);
+        assert_eq!(gateway.request_timeout_ms, 5000);
+        assert_eq!(gateway.poll_interval_ms, 1000);
+        assert_eq!(gateway.max_in_flight, 1);
+        assert!(gateway.points.is_empty());
+        assert!(config.mqtt.is_none());
+        assert!(config.csv.is_none());
+        assert_eq!(gateway, &ModbusDevice::new("10.0.0.1", 502, vec![1]));
+    }
+
+    #[test]
+    fn misspelled_key_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = path_in(&dir, "config.yaml");
+        fs::write(&path, "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n    poll_interval: 500\n").unwrap();
+
+        let error = read_config(&path).unwrap_err().to_string();
+        assert!(error.contains("未知字段: gateways.0.poll_interval"), "{}", error);
+
+        fs::write(&path, "version: 2\nallow_unknown_fields: true\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n    poll_interval: 500\n").unwrap();
+        assert_eq!(read_config(&path).unwrap().gateways[0].poll_interval_ms, 1000);
+    }
+
+    #[test]
+    fn defaults_are_omitted_when_saved() {
+        let mut config = Config::default();
+        config.add_gateway(ModbusDevice::new("10.0.0.1", 502, vec![1])).unwrap();
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        // 默认为空或 false 的可选字段不写入文件
+        for field in ["include", "mqtt", "allow_duplicates", "simulation", "max_in_flight", "name"] {
+            assert!(!yaml.contains(field), "{} 不应写入:\n{}", field, yaml);
+        }
+        let parsed: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, config);
+    }
+
+    fn validate(yaml: &str) -> Result<(), String> {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn invalid_timezones_are_rejected() {
+        let config = |timezone: &str| {
+            format!("version: 2\ntimezone: {}\ngateways:\n  - {{ ip: 10.0.0.1, slave_ids: [1] }}\n", timezone)
+        };
+        assert!(validate(&config("Asia/Shanghai")).is_ok());
+        assert!(validate(&config("America/New_York")).is_ok());
+        assert_eq!(
+            validate(&config("Asia/Beijing")).unwrap_err(),
+            "timezone 不是合法的时区名称: Asia/Beijing"
+        );
+        assert!(validate(&config("\"+08:00\"")).is_err());
+
+        let csv = "version: 2\ncsv: { directory: /tmp/ems-csv, timezone: Mars/Olympus }\ngateways:\n  - { ip: 10.0.0.1, slave_ids: [1] }\n";
+        assert!(validate(csv).unwrap_err().contains("csv.timezone 不是合法的时区名称: Mars/Olympus"));
+    }
+
+    #[test]
+    fn duplicate_slave_ids_are_rejected_unless_allowed() {
+        let error = validate("version: 2\ngateways:\n  - { ip: 10.0.0.1, name: PCS-A, slave_ids: [1, 2, 1] }\n")
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "网关 PCS-A 的 slave_ids 第1项与第3项重复（从站ID 1），如确有需要请设置 allow_duplicates: true"
+        );
+        validate("version: 2\ngateways:\n  - { ip: 10.0.0.1, allow_duplicates: true, slave_ids: [1, 1] }\n")
+            .unwrap();
+    }
+
+    #[test]
+    fn overlapping_point_addresses_are_rejected_unless_allowed() {
+        let points = "    points:\n      - { name: power, address: 0, data_type: f32 }\n      - { name: status, address: 1 }\n      - { name: coil, function_code: 1, address: 0, data_type: bool }\n";
+        let error = validate(&format!(
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n{}",
+            points
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error,
+            "网关 10.0.0.1:502 中点位 power（功能码0x03 地址0-1）与点位 status（地址1-1）地址重叠，如确有需要请设置 allow_duplicates: true"
+        );
+        validate(&format!(
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    allow_duplicates: true\n    slave_ids: [1]\n{}",
+            points
+        ))
+        .unwrap();
+        // 停用的点位不参与重叠检查
+        validate("version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n    points:\n      - { name: a, address: 0 }\n      - { name: b, address: 0, enabled: false }\n")
+            .unwrap();
+    }
+
+    #[test]
+    fn duplicate_gateway_addresses_are_rejected_unless_both_allow() {
+        let gateways = |first: bool, second: bool| {
+            format!(
+                "version: 2\ngateways:\n  - {{ ip: 10.0.0.1, allow_duplicates: {}, slave_ids: [1] }}\n  - {{ ip: 10.0.0.1, name: PCS-B, allow_duplicates: {}, slave_ids: [2] }}\n",
+                first, second
+            )
+        };
+        assert_eq!(
+            validate(&gateways(false, false)).unwrap_err(),
+            "网关 #0 与网关 #1（PCS-B） 使用了相同的地址 10.0.0.1:502，如确有需要请在两者中都设置 allow_duplicates: true"
+        );
+        assert!(validate(&gateways(true, false)).is_err());
+        validate(&gateways(true, true)).unwrap();
+        // 端口不同不算重复
+        validate("version: 2\ngateways:\n  - { ip: 10.0.0.1, slave_ids: [1] }\n  - { ip: 10.0.0.1, port: 503, slave_ids: [1] }\n")
+            .unwrap();
+    }
+
+    #[test]
+    fn publish_options_prefer_point_then_group_then_mqtt() {
+        let config: Config = serde_yaml::from_str(
+            "version: 2\nmqtt: { broker_host: localhost, client_id: ems-1, qos: 0, retain: false }\npoll_groups:\n  slow: { interval_ms: 60000, qos: 1, retain: true }\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n    points:\n      - { name: power, address: 0 }\n      - { name: energy, address: 2, group: slow }\n      - { name: alarm, address: 4, group: slow, qos: 2, retain: false }\n      - { name: serial, address: 6, group: slow, qos: 2 }\n      - { name: state, address: 8, retain: true }\n",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let resolved: Vec<(&str, u8, bool)> = config.gateways[0]
+            .points
+            .iter()
+            .map(|point| {
+                let options = config.publish_options(point);
+                (point.name.as_str(), options.qos, options.retain)
+            })
+            .collect();
+        assert_eq!(
+            resolved,
+            [
+                ("power", 0, false),
+                ("energy", 1, true),
+                ("alarm", 2, false),
+                ("serial", 2, true),
+                ("state", 0, true),
+            ]
+        );
+
+        // 没有 mqtt 段时使用其默认值
+        let config: Config =
+            serde_yaml::from_str("version: 2\ngateways:\n  - { ip: 10.0.0.1, slave_ids: [1], points: [{ name: power, address: 0 }] }\n")
+                .unwrap();
+        assert_eq!(
+            config.publish_options(&config.gateways[0].points[0]),
+            PublishOptions::default()
+        );
+    }
+
+    #[test]
+    fn invalid_qos_is_rejected_at_every_level() {
+        let error = validate("version: 2\nmqtt: { broker_host: localhost, client_id: ems-1, qos: 3 }\n").unwrap_err();
+        assert!(error.contains("mqtt.qos 只能是0、1、2，当前为 3"), "{}", error);
+        let error = validate("version: 2\npoll_groups:\n  slow: { interval_ms: 1000, qos: 3 }\n").unwrap_err();
+        assert!(error.contains("采集组 slow 的 qos 只能是0、1、2，当前为 3"), "{}", error);
+        let error = validate(
+            "version: 2\ngateways:\n  - { ip: 10.0.0.1, slave_ids: [1], points: [{ name: power, address: 0, qos: 5 }] }\n",
+        )
+        .unwrap_err();
+        assert!(error.contains("点位 power 的 qos 只能是0、1、2，当前为 5"), "{}", error);
+    }
+}
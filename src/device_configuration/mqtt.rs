@@ -0,0 +1,819 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use super::secret::{redact, resolve_secret, REDACTED};
+use super::slave::check_topic_safe;
+use crate::mqtt::compression::{Compression, CompressionMarker};
+use crate::mqtt::payload::{PayloadFormat, RESERVED_TAGS};
+use crate::mqtt::topic::{
+    Placeholder, TopicTemplate, DEFAULT_AVAILABILITY_TEMPLATE, DEFAULT_COMMAND_TEMPLATE,
+    DEFAULT_DATA_TEMPLATE,
+};
+
+/// MQTT 默认端口
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// MQTT 连接与发布参数，对应配置文件中的 `mqtt:` 段
+///
+/// 密码可以通过 `password_env`（环境变量）或 `password_file`（文件）间接引用，
+/// 避免明文写在会提交到 git 的配置文件中。Debug 输出中密码显示为 `***`。
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct MqttSettings {
+    /// Broker 地址（必填）
+    pub broker_host: String,
+    /// Broker 端口（默认1883）
+    #[serde(default = "default_broker_port")]
+    pub broker_port: u16,
+    /// 客户端ID（必填，同一 Broker 上需唯一），也用于状态主题 `<topic_prefix>/<client_id>/status`
+    pub client_id: String,
+    /// 用户名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// 密码
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// 保存密码的文件路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<String>,
+    /// 保存密码的环境变量名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+    /// 心跳间隔，单位秒（默认30）
+    #[serde(default = "default_keep_alive_secs")]
+    pub keep_alive_secs: u64,
+    /// 是否使用干净会话（默认 true）；为 false 时 Broker 在断线期间保留订阅和 QoS 1/2 消息
+    #[serde(default = "default_clean_session")]
+    pub clean_session: bool,
+    /// 连接出错后第一次重试前的等待时间，单位毫秒（默认1000），连续失败时每次翻倍
+    #[serde(default = "default_reconnect_delay_ms")]
+    pub reconnect_delay_ms: u64,
+    /// 重试等待时间的上限，单位毫秒（默认30000）
+    #[serde(default = "default_max_reconnect_delay_ms")]
+    pub max_reconnect_delay_ms: u64,
+    /// 主题前缀（默认 "ems"）
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    /// 站点名称，对应主题模板中的 `{site}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    /// 采集数据消息中附带的静态标签，JSON 格式放在 `tags` 中，influx_line 格式作为 tag
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// 数据主题模板（默认 "{prefix}/{gateway}/{slave}"），使用 `{point}` 时每个点位单独发布
+    #[serde(default = "default_topic_template")]
+    pub topic_template: String,
+    /// 采集数据的合并方式，未配置时按数据主题模板确定：使用 `{point}` 时为 per_point，否则为 per_device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<Aggregation>,
+    /// 合并后单条消息的最大字节数，超出时拆分为多条消息；未配置时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_payload_bytes: Option<usize>,
+    /// 合并发布时是否包含未超出死区的点位（默认 false）；为 true 时从站只要有点位变化，
+    /// 消息就包含该从站本次采集的所有点位
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub include_unchanged: bool,
+    /// JSON 格式的数据消息是否附带 `local_time`，即按全局 timezone 表示的采集时间（默认 false）；
+    /// `timestamp` 始终为 UTC
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub local_time: bool,
+    /// 采集数据消息的格式（默认 json），可以被采集组的 payload_format 覆盖
+    #[serde(default)]
+    pub payload_format: PayloadFormat,
+    /// 采集数据消息的压缩方式（默认 none），可以被采集组的 compression 覆盖
+    #[serde(default)]
+    pub compression: Compression,
+    /// 消息超过该字节数才压缩（默认1024）
+    #[serde(default = "default_compression_threshold_bytes")]
+    pub compression_threshold_bytes: usize,
+    /// 接收方识别压缩消息的方式（默认 topic_suffix）
+    #[serde(default)]
+    pub compression_marker: CompressionMarker,
+    /// 每秒最多发布的采集数据消息数，超出时同一主题的消息只保留最新的一条；未配置时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_messages_per_second: Option<f64>,
+    /// 每个从站每秒最多发布的采集数据消息数；未配置时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_device_messages_per_second: Option<f64>,
+    /// 设备可用性主题模板（默认 "{prefix}/{gateway}/{slave}/availability"）
+    #[serde(default = "default_availability_topic_template")]
+    pub availability_topic_template: String,
+    /// 命令主题模板（默认 "{prefix}/{gateway}/{slave}/cmd"）
+    #[serde(default = "default_command_topic_template")]
+    pub command_topic_template: String,
+    /// 发布使用的 QoS（0/1/2，默认1），可以被采集组和点位的 qos 覆盖
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    /// 发布时是否设置 retain 标志（默认 false），可以被采集组和点位的 retain 覆盖
+    #[serde(default)]
+    pub retain: bool,
+    /// TLS 配置，未配置时使用明文 TCP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<MqttTls>,
+    /// Home Assistant 自动发现配置，未配置时不发布发现消息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_assistant: Option<HomeAssistant>,
+    /// Sparkplug B 配置，配置后采集数据以 Sparkplug B 格式发布，不再使用数据主题模板和 payload_format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparkplug: Option<Sparkplug>,
+    /// 通过 MQTT 临时读取寄存器的配置，未配置时不处理读取请求
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_requests: Option<ReadRequests>,
+    /// 远程控制配置，未配置时不处理控制命令
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<Control>,
+    /// 死信主题配置，未配置时无法处理的消息只输出日志
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter: Option<DeadLetterTopic>,
+    /// 报警状态主题前缀，每个报警发布到其下以报警名称命名的子主题，未配置时为 `<topic_prefix>/<client_id>/alarms`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alarm_topic: Option<String>,
+    /// 加载配置时解析出的实际密码，不会写回配置文件
+    #[serde(skip)]
+    resolved_password: Option<String>,
+}
+
+impl fmt::Debug for MqttSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttSettings")
+            .field("broker_host", &self.broker_host)
+            .field("broker_port", &self.broker_port)
+            .field("client_id", &self.client_id)
+            .field("username", &self.username)
+            .field("password", &redact(&self.password))
+            .field("password_file", &self.password_file)
+            .field("password_env", &self.password_env)
+            .field("keep_alive_secs", &self.keep_alive_secs)
+            .field("clean_session", &self.clean_session)
+            .field("reconnect_delay_ms", &self.reconnect_delay_ms)
+            .field("max_reconnect_delay_ms", &self.max_reconnect_delay_ms)
+            .field("topic_prefix", &self.topic_prefix)
+            .field("site", &self.site)
+            .field("tags", &self.tags)
+            .field("topic_template", &self.topic_template)
+            .field("aggregation", &self.aggregation)
+            .field("max_payload_bytes", &self.max_payload_bytes)
+            .field("include_unchanged", &self.include_unchanged)
+            .field("local_time", &self.local_time)
+            .field("payload_format", &self.payload_format)
+            .field("compression", &self.compression)
+            .field("compression_threshold_bytes", &self.compression_threshold_bytes)
+            .field("compression_marker", &self.compression_marker)
+            .field("max_messages_per_second", &self.max_messages_per_second)
+            .field("max_device_messages_per_second", &self.max_device_messages_per_second)
+            .field("availability_topic_template", &self.availability_topic_template)
+            .field("command_topic_template", &self.command_topic_template)
+            .field("qos", &self.qos)
+            .field("retain", &self.retain)
+            .field("tls", &self.tls)
+            .field("home_assistant", &self.home_assistant)
+            .field("sparkplug", &self.sparkplug)
+            .field("read_requests", &self.read_requests)
+            .field("control", &self.control)
+            .field("dead_letter", &self.dead_letter)
+            .field("alarm_topic", &self.alarm_topic)
+            .field("resolved_password", &redact(&self.resolved_password))
+            .finish()
+    }
+}
+
+/// 点位数据发布时使用的 QoS、retain、死区、消息格式和压缩方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PublishOptions {
+    /// QoS 等级（0/1/2）
+    pub qos: u8,
+    /// 是否以保留消息发布
+    pub retain: bool,
+    /// 与上次发布的值相差不超过死区时不发布，None 表示每次都发布
+    pub deadband: Option<f64>,
+    /// 消息格式
+    pub format: PayloadFormat,
+    /// 压缩方式
+    pub compression: Compression,
+}
+
+impl Default for PublishOptions {
+    /// 与 mqtt 段的默认值一致：QoS 1，不保留，不过滤，JSON 格式，不压缩
+    fn default() -> Self {
+        PublishOptions {
+            qos: default_qos(),
+            retain: false,
+            deadband: None,
+            format: PayloadFormat::Json,
+            compression: Compression::None,
+        }
+    }
+}
+
+/// 采集数据发布到 MQTT 时的合并方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Aggregation {
+    /// `per_point`：每个点位一条消息，主题模板中没有 `{point}` 时在末尾追加 `/{point}`
+    #[serde(rename = "per_point")]
+    Point,
+    /// `per_device`：同一次采集中一个从站的所有点位合并为一条消息
+    #[serde(rename = "per_device")]
+    Device,
+    /// `per_cycle`：同一次采集中一个网关的所有从站合并为一条消息，主题模板不能使用 `{slave}`
+    #[serde(rename = "per_cycle")]
+    Cycle,
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Aggregation::Point => "per_point",
+            Aggregation::Device => "per_device",
+            Aggregation::Cycle => "per_cycle",
+        })
+    }
+}
+
+/// MQTT 的 TLS 配置，对应 `mqtt.tls:` 段
+///
+/// 证书和私钥均为 PEM 格式，程序启动时读取并检查，文件无法读取或证书与私钥不匹配时直接报错。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct MqttTls {
+    /// CA 证书文件，未配置时使用系统的根证书
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<String>,
+    /// 客户端证书文件，与 client_key_path 同时配置时使用双向认证
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert_path: Option<String>,
+    /// 客户端私钥文件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key_path: Option<String>,
+    /// 不校验 Broker 的证书（默认 false），仅用于测试环境
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub insecure_skip_verify: bool,
+}
+
+/// Home Assistant 自动发现配置，对应 `mqtt.home_assistant:` 段
+///
+/// 配置后在启动和配置热加载时为每个采集的点位发布保留的发现消息，从配置中删除的点位发布空消息清除。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HomeAssistant {
+    /// 发现主题的前缀（默认 "homeassistant"），需要与 Home Assistant 中的配置一致
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+impl Default for HomeAssistant {
+    fn default() -> Self {
+        HomeAssistant {
+            discovery_prefix: default_discovery_prefix(),
+        }
+    }
+}
+
+/// Sparkplug B 配置，对应 `mqtt.sparkplug:` 段
+///
+/// 主题为 `spBv1.0/<group_id>/<消息类型>/<edge_node_id>`，整个程序作为一个边缘节点，
+/// 每个点位是节点的一个 metric。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Sparkplug {
+    /// 组ID（必填）
+    pub group_id: String,
+    /// 边缘节点ID（必填）
+    pub edge_node_id: String,
+}
+
+/// 临时读取配置，对应 `mqtt.read_requests:` 段
+///
+/// 配置后订阅 `<topic_prefix>/<client_id>/read`，按请求立即读取指定的寄存器，
+/// 结果发布到 `<topic_prefix>/<client_id>/read/response`。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReadRequests {
+    /// 每秒最多处理的读取请求数（默认1），超出时直接拒绝
+    #[serde(default = "default_read_requests_per_second")]
+    pub max_per_second: f64,
+}
+
+impl Default for ReadRequests {
+    fn default() -> Self {
+        ReadRequests {
+            max_per_second: default_read_requests_per_second(),
+        }
+    }
+}
+
+fn default_read_requests_per_second() -> f64 {
+    1.0
+}
+
+/// 远程控制配置，对应 `mqtt.control:` 段
+///
+/// 配置后订阅 `<topic_prefix>/<client_id>/control`，执行重新加载配置、暂停和恢复采集等命令，
+/// 应答发布到 `<topic_prefix>/<client_id>/control/response`。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Control {
+    /// 每秒最多处理的控制命令数（默认1），超出时直接拒绝
+    #[serde(default = "default_control_per_second")]
+    pub max_per_second: f64,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control {
+            max_per_second: default_control_per_second(),
+        }
+    }
+}
+
+fn default_control_per_second() -> f64 {
+    1.0
+}
+
+/// 死信主题配置，对应 `mqtt.dead_letter:` 段
+///
+/// 格式错误或无法执行的命令、无法发出的采集数据消息连同原始内容和原因发布到死信主题。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DeadLetterTopic {
+    /// 死信主题，未配置时为 `<topic_prefix>/<client_id>/dlq`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    /// 每秒最多发布的死信数（默认1），超出时只输出日志
+    #[serde(default = "default_dead_letters_per_second")]
+    pub max_per_second: f64,
+}
+
+impl Default for DeadLetterTopic {
+    fn default() -> Self {
+        DeadLetterTopic {
+            topic: None,
+            max_per_second: default_dead_letters_per_second(),
+        }
+    }
+}
+
+fn default_dead_letters_per_second() -> f64 {
+    1.0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn default_compression_threshold_bytes() -> usize {
+    1024
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_broker_port() -> u16 {
+    DEFAULT_MQTT_PORT
+}
+
+fn default_keep_alive_secs() -> u64 {
+    30
+}
+
+fn default_clean_session() -> bool {
+    true
+}
+
+fn default_reconnect_delay_ms() -> u64 {
+    1000
+}
+
+fn default_max_reconnect_delay_ms() -> u64 {
+    30000
+}
+
+fn default_topic_prefix() -> String {
+    "ems".to_string()
+}
+
+fn default_topic_template() -> String {
+    DEFAULT_DATA_TEMPLATE.to_string()
+}
+
+fn default_availability_topic_template() -> String {
+    DEFAULT_AVAILABILITY_TEMPLATE.to_string()
+}
+
+fn default_command_topic_template() -> String {
+    DEFAULT_COMMAND_TEMPLATE.to_string()
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+impl MqttSettings {
+    /// 使用默认参数创建 MQTT 配置
+    pub fn new(broker_host: &str, client_id: &str) -> Self {
+        MqttSettings {
+            broker_host: broker_host.to_string(),
+            broker_port: default_broker_port(),
+            client_id: client_id.to_string(),
+            username: None,
+            password: None,
+            password_file: None,
+            password_env: None,
+            keep_alive_secs: default_keep_alive_secs(),
+            clean_session: default_clean_session(),
+            reconnect_delay_ms: default_reconnect_delay_ms(),
+            max_reconnect_delay_ms: default_max_reconnect_delay_ms(),
+            topic_prefix: default_topic_prefix(),
+            site: None,
+            tags: BTreeMap::new(),
+            topic_template: default_topic_template(),
+            aggregation: None,
+            max_payload_bytes: None,
+            include_unchanged: false,
+            local_time: false,
+            payload_format: PayloadFormat::Json,
+            compression: Compression::None,
+            compression_threshold_bytes: default_compression_threshold_bytes(),
+            compression_marker: CompressionMarker::TopicSuffix,
+            max_messages_per_second: None,
+            max_device_messages_per_second: None,
+            availability_topic_template: default_availability_topic_template(),
+            command_topic_template: default_command_topic_template(),
+            qos: default_qos(),
+            retain: false,
+            tls: None,
+            home_assistant: None,
+            sparkplug: None,
+            read_requests: None,
+            control: None,
+            dead_letter: None,
+            alarm_topic: None,
+            resolved_password: None,
+        }
+    }
+
+    /// 解析间接引用的密码（优先级：环境变量 > 文件 > 直接写入）
+    pub fn resolve_secrets(&mut self) -> Result<(), Box<dyn Error>> {
+        self.resolved_password = resolve_secret(
+            "mqtt.password",
+            self.password.as_deref(),
+            self.password_file.as_deref(),
+            self.password_env.as_deref(),
+        )?;
+        Ok(())
+    }
+
+    /// 实际使用的密码，未调用 resolve_secrets 时为直接写入的密码
+    pub fn effective_password(&self) -> Option<&str> {
+        self.resolved_password
+            .as_deref()
+            .or(self.password.as_deref())
+    }
+
+    /// 直接写入的密码替换为 `***` 后的配置
+    pub fn redacted(&self) -> MqttSettings {
+        MqttSettings {
+            password: self.password.as_ref().map(|_| REDACTED.to_string()),
+            resolved_password: None,
+            ..self.clone()
+        }
+    }
+
+    /// 检查 MQTT 配置是否合法
+    ///
+    /// # 校验规则
+    /// * broker_host 和 client_id 不能为空，client_id 不能包含 `+`、`#`、`/` 和空白字符
+    /// * broker_port 和 reconnect_delay_ms 不能为0，max_reconnect_delay_ms 不能小于 reconnect_delay_ms
+    /// * qos 只能是0、1、2
+    /// * topic_prefix 不能包含 `+`、`#`、空段，也不能以 `/` 开头或结尾
+    /// * site 不能包含 `+`、`#`、`/` 和空白字符
+    /// * tags 的名称不能为空，也不能是 gateway、slave、group、unit、site、node
+    /// * 主题模板只能使用已知的占位符，展开后不能为空（可用性和命令主题不能使用 `{point}`、`{group}`）
+    /// * aggregation 为 per_device 时数据主题不能使用 `{point}`，为 per_cycle 时不能使用
+    ///   `{slave}`、`{point}`、`{group}`
+    /// * max_payload_bytes 不能为0
+    /// * max_messages_per_second 和 max_device_messages_per_second 必须大于0
+    /// * payload_format 为 raw_registers 时只能按 per_point 发布
+    /// * tls.client_cert_path 和 tls.client_key_path 必须同时配置
+    /// * home_assistant.discovery_prefix 不能为空，不能包含 `+`、`#`、空白字符和空段
+    /// * sparkplug.group_id 和 sparkplug.edge_node_id 不能为空，不能包含 `+`、`#`、`/` 和空白字符
+    /// * read_requests.max_per_second、control.max_per_second 和 dead_letter.max_per_second 必须大于0
+    /// * dead_letter.topic 不能为空，不能包含 `+`、`#` 和空段
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.broker_host.trim().is_empty() {
+            return Err("mqtt.broker_host 不能为空".into());
+        }
+        if self.client_id.trim().is_empty() {
+            return Err("mqtt.client_id 不能为空".into());
+        }
+        check_topic_safe("mqtt.client_id ", &self.client_id)?;
+        if self.broker_port == 0 {
+            return Err("mqtt.broker_port 不能为0".into());
+        }
+        if self.reconnect_delay_ms == 0 {
+            return Err("mqtt.reconnect_delay_ms 不能为0".into());
+        }
+        if self.max_reconnect_delay_ms < self.reconnect_delay_ms {
+            return Err(format!(
+                "mqtt.max_reconnect_delay_ms（{}）不能小于 mqtt.reconnect_delay_ms（{}）",
+                self.max_reconnect_delay_ms, self.reconnect_delay_ms
+            )
+            .into());
+        }
+        if self.qos > 2 {
+            return Err(format!("mqtt.qos 只能是0、1、2，当前为 {}", self.qos).into());
+        }
+        if self.topic_prefix.contains(['+', '#']) {
+            return Err(format!("mqtt.topic_prefix \"{}\" 不能包含 + 或 #", self.topic_prefix).into());
+        }
+        if self.topic_prefix.starts_with('/') || self.topic_prefix.ends_with('/') {
+            return Err(format!(
+                "mqtt.topic_prefix \"{}\" 不能以 / 开头或结尾",
+                self.topic_prefix
+            )
+            .into());
+        }
+        if self.topic_prefix.contains("//") {
+            return Err(format!("mqtt.topic_prefix \"{}\" 包含空段", self.topic_prefix).into());
+        }
+        if let Some(site) = &self.site {
+            check_topic_safe("mqtt.site ", site)?;
+        }
+        for key in self.tags.keys() {
+            if key.trim().is_empty() {
+                return Err("mqtt.tags 的名称不能为空".into());
+            }
+            if RESERVED_TAGS.contains(&key.as_str()) {
+                return Err(format!("mqtt.tags 不能使用程序保留的名称 \"{}\"", key).into());
+            }
+        }
+        self.data_topic()?;
+        self.check_aggregation()?;
+        self.check_format("mqtt.payload_format", self.payload_format)?;
+        if self.max_payload_bytes == Some(0) {
+            return Err("mqtt.max_payload_bytes 不能为0".into());
+        }
+        for (field, rate) in [
+            ("max_messages_per_second", self.max_messages_per_second),
+            ("max_device_messages_per_second", self.max_device_messages_per_second),
+        ] {
+            if let Some(rate) = rate
+                && !(rate.is_finite() && rate > 0.0)
+            {
+                return Err(format!("mqtt.{} 必须大于0，当前为 {}", field, rate).into());
+            }
+        }
+        self.availability_topic()?;
+        self.command_topic()?;
+        if let Some(tls) = &self.tls
+            && tls.client_cert_path.is_some() != tls.client_key_path.is_some()
+        {
+            return Err("mqtt.tls.client_cert_path 和 mqtt.tls.client_key_path 必须同时配置".into());
+        }
+        if let Some(home_assistant) = &self.home_assistant {
+            TopicTemplate::parse(
+                "mqtt.home_assistant.discovery_prefix",
+                &home_assistant.discovery_prefix,
+                &[],
+            )?;
+        }
+        if let Some(sparkplug) = &self.sparkplug {
+            check_topic_safe("mqtt.sparkplug.group_id ", &sparkplug.group_id)?;
+            check_topic_safe("mqtt.sparkplug.edge_node_id ", &sparkplug.edge_node_id)?;
+        }
+        let rates = [
+            ("read_requests", self.read_requests.as_ref().map(|r| r.max_per_second)),
+            ("control", self.control.as_ref().map(|c| c.max_per_second)),
+            ("dead_letter", self.dead_letter.as_ref().map(|d| d.max_per_second)),
+        ];
+        for (section, rate) in rates {
+            if let Some(rate) = rate
+                && !(rate.is_finite() && rate > 0.0)
+            {
+                return Err(
+                    format!("mqtt.{}.max_per_second 必须大于0，当前为 {}", section, rate).into(),
+                );
+            }
+        }
+        if let Some(topic) = self.dead_letter.as_ref().and_then(|d| d.topic.as_ref()) {
+            TopicTemplate::parse("mqtt.dead_letter.topic", topic, &[])?;
+        }
+        if let Some(topic) = &self.alarm_topic {
+            TopicTemplate::parse("mqtt.alarm_topic", topic, &[])?;
+        }
+        Ok(())
+    }
+
+    /// 程序状态主题 `<topic_prefix>/<client_id>/status`，topic_prefix 为空时为 `<client_id>/status`
+    ///
+    /// 连接成功后发布保留消息 online，程序异常退出时 Broker 发布遗嘱消息 offline。
+    pub fn status_topic(&self) -> String {
+        self.node_topic("status")
+    }
+
+    /// 死信主题，未配置 dead_letter 时为 None
+    pub fn dead_letter_topic(&self) -> Option<String> {
+        let dead_letter = self.dead_letter.as_ref()?;
+        Some(dead_letter.topic.clone().unwrap_or_else(|| self.node_topic("dlq")))
+    }
+
+    /// 报警 `name` 的状态主题 `<alarm_topic>/<name>`
+    pub fn alarm_state_topic(&self, name: &str) -> String {
+        match &self.alarm_topic {
+            Some(prefix) => format!("{}/{}", prefix, name),
+            None => format!("{}/{}", self.node_topic("alarms"), name),
+        }
+    }
+
+    /// 定时写入 `name` 的执行结果主题 `<topic_prefix>/<client_id>/schedules/<name>`
+    pub fn schedule_topic(&self, name: &str) -> String {
+        format!("{}/{}", self.node_topic("schedules"), name)
+    }
+
+    /// 临时读取的请求主题 `<topic_prefix>/<client_id>/read`，应答发布到其下的 `response` 子主题
+    pub fn read_topic(&self) -> String {
+        self.node_topic("read")
+    }
+
+    /// 远程控制的命令主题 `<topic_prefix>/<client_id>/control`，应答发布到其下的 `response` 子主题
+    pub fn control_topic(&self) -> String {
+        self.node_topic("control")
+    }
+
+    // 程序自身的主题 `<topic_prefix>/<client_id>/<name>`，topic_prefix 为空时省略
+    fn node_topic(&self, name: &str) -> String {
+        if self.topic_prefix.is_empty() {
+            format!("{}/{}", self.client_id, name)
+        } else {
+            format!("{}/{}/{}", self.topic_prefix, self.client_id, name)
+        }
+    }
+
+    /// 实际使用的合并方式
+    pub fn effective_aggregation(&self) -> Result<Aggregation, Box<dyn Error>> {
+        if let Some(aggregation) = self.aggregation {
+            return Ok(aggregation);
+        }
+        let topic = TopicTemplate::parse("mqtt.topic_template", &self.topic_template, Placeholder::DATA)?;
+        Ok(if topic.uses(Placeholder::Point) {
+            Aggregation::Point
+        } else {
+            Aggregation::Device
+        })
+    }
+
+    /// 解析数据主题模板，aggregation 为 per_point 且模板中没有 `{point}` 时在末尾追加 `/{point}`
+    pub fn data_topic(&self) -> Result<TopicTemplate, Box<dyn Error>> {
+        let field = "mqtt.topic_template";
+        let topic = self.topic(field, &self.topic_template, Placeholder::DATA)?;
+        if self.aggregation == Some(Aggregation::Point) && !topic.uses(Placeholder::Point) {
+            return self.topic(field, &format!("{}/{{point}}", self.topic_template), Placeholder::DATA);
+        }
+        Ok(topic)
+    }
+
+    /// 检查消息格式能否用于当前的合并方式
+    ///
+    /// # 参数说明
+    /// * `field` - 配置项名称，用于错误信息
+    pub fn check_format(&self, field: &str, format: PayloadFormat) -> Result<(), Box<dyn Error>> {
+        let aggregation = self.effective_aggregation()?;
+        if !format.can_aggregate() && aggregation != Aggregation::Point {
+            return Err(format!(
+                "{} 为 {} 时不能合并点位，mqtt.aggregation 需要为 per_point（当前为 {}）",
+                field, format, aggregation
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // 合并发布的消息对应多个点位或从站，主题中不能再区分它们
+    fn check_aggregation(&self) -> Result<(), Box<dyn Error>> {
+        let Some(aggregation) = self.aggregation else {
+            return Ok(());
+        };
+        let forbidden: &[Placeholder] = match aggregation {
+            Aggregation::Point => return Ok(()),
+            Aggregation::Device => &[Placeholder::Point],
+            Aggregation::Cycle => &[Placeholder::Slave, Placeholder::Point, Placeholder::Group],
+        };
+        let topic = self.data_topic()?;
+        if let Some(placeholder) = forbidden.iter().find(|p| topic.uses(**p)) {
+            return Err(format!(
+                "mqtt.aggregation 为 {} 时 mqtt.topic_template \"{}\" 不能使用 {}",
+                aggregation,
+                self.topic_template,
+                placeholder
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// 解析设备可用性主题模板
+    pub fn availability_topic(&self) -> Result<TopicTemplate, Box<dyn Error>> {
+        self.topic(
+            "mqtt.availability_topic_template",
+            &self.availability_topic_template,
+            Placeholder::DEVICE,
+        )
+    }
+
+    /// 解析命令主题模板
+    pub fn command_topic(&self) -> Result<TopicTemplate, Box<dyn Error>> {
+        self.topic(
+            "mqtt.command_topic_template",
+            &self.command_topic_template,
+            Placeholder::DEVICE,
+        )
+    }
+
+    fn topic(
+        &self,
+        field: &str,
+        template: &str,
+        allowed: &[Placeholder],
+    ) -> Result<TopicTemplate, Box<dyn Error>> {
+        let topic = TopicTemplate::parse(field, template, allowed)?;
+        topic.check_not_empty(field, &self.topic_prefix, self.site.as_deref())?;
+        Ok(topic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::mqtt::client::mqtt_options;
+    use std::time::Duration;
+
+    fn parse(yaml: &str) -> Config {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    #[test]
+    fn config_without_mqtt_section() {
+        let config = parse("version: 2\ngateways: []\n");
+        assert!(config.mqtt.is_none());
+        assert_eq!(config.default_publish_options(), PublishOptions::default());
+    }
+
+    #[test]
+    fn mqtt_section_with_defaults() {
+        let config = parse("version: 2\nmqtt:\n  broker_host: broker.local\n  client_id: site-1\n");
+        let mqtt = config.mqtt.unwrap();
+        assert_eq!(mqtt, MqttSettings::new("broker.local", "site-1"));
+        assert_eq!(mqtt.broker_port, DEFAULT_MQTT_PORT);
+        assert_eq!(mqtt.keep_alive_secs, 30);
+        assert!(mqtt.clean_session);
+        assert_eq!(mqtt.qos, 1);
+        assert_eq!(mqtt.status_topic(), "ems/site-1/status");
+    }
+
+    #[test]
+    fn mqtt_section_with_explicit_values() {
+        let config = parse(
+            "version: 2\nmqtt:\n  broker_host: 10.0.0.5\n  broker_port: 8883\n  client_id: site-1\n  username: ems\n  password: secret\n  keep_alive_secs: 10\n  clean_session: false\n  topic_prefix: plant/a\n  qos: 2\n  retain: true\n",
+        );
+        let mqtt = config.mqtt.as_ref().unwrap();
+        assert_eq!(mqtt.broker_port, 8883);
+        assert_eq!(mqtt.effective_password(), Some("secret"));
+        assert_eq!(mqtt.status_topic(), "plant/a/site-1/status");
+        assert_eq!(config.default_publish_options().qos, 2);
+        assert!(config.default_publish_options().retain);
+    }
+
+    #[test]
+    fn empty_client_id_is_rejected() {
+        let mut mqtt = MqttSettings::new("broker.local", " ");
+        assert_eq!(mqtt.validate().unwrap_err().to_string(), "mqtt.client_id 不能为空");
+        mqtt.client_id = "a/b".to_string();
+        assert!(mqtt.validate().is_err());
+        mqtt.client_id = "ok".to_string();
+        mqtt.broker_host = String::new();
+        assert_eq!(mqtt.validate().unwrap_err().to_string(), "mqtt.broker_host 不能为空");
+    }
+
+    #[test]
+    fn builds_mqtt_options() {
+        let mut mqtt = MqttSettings::new("broker.local", "site-1");
+        mqtt.broker_port = 1884;
+        mqtt.keep_alive_secs = 15;
+        mqtt.clean_session = false;
+        mqtt.username = Some("ems".to_string());
+        mqtt.password = Some("secret".to_string());
+
+        let options = mqtt_options(&mqtt).unwrap();
+        assert_eq!(options.broker_address(), ("broker.local".to_string(), 1884));
+        assert_eq!(options.client_id(), "site-1");
+        assert_eq!(options.keep_alive(), Duration::from_secs(15));
+        assert!(!options.clean_session());
+        assert_eq!(
+            options.credentials(),
+            Some(("ems".to_string(), "secret".to_string()))
+        );
+        assert!(matches!(options.transport(), rumqttc::Transport::Tcp));
+
+        // 没有用户名时不发送用户名和密码
+        let options = mqtt_options(&MqttSettings::new("broker.local", "site-1")).unwrap();
+        assert_eq!(options.credentials(), None);
+    }
+}
@@ -0,0 +1,498 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use super::bit_field::BitField;
+use super::simulation::SimulationHint;
+use super::units::{quantity_of, Conversion};
+use crate::modbus::decode::{decode, decode_string, encode, encode_string, DataType, WordOrder};
+
+/// 点位定义，描述从站上一个需要采集的数据项
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Point {
+    /// 点位名称，同一从站内唯一，例如 "voltage_l1"
+    pub name: String,
+    /// 读取使用的功能码（0x01-0x04，默认0x03）
+    #[serde(default = "default_function_code")]
+    pub function_code: u8,
+    /// 起始地址（0-65535）
+    pub address: u16,
+    /// 数据类型（默认u16）
+    #[serde(default)]
+    pub data_type: DataType,
+    /// 字符串占用的寄存器数量，只用于 `data_type: string`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u16>,
+    /// 多寄存器数据的字节序（默认abcd）；字符串只能为 abcd 或 badc（字内字节交换）
+    #[serde(default)]
+    pub word_order: WordOrder,
+    /// 缩放系数，实际值 = 原始值 * scale + offset
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// 偏移量
+    #[serde(default)]
+    pub offset: f64,
+    /// 比例因子点位的名称（SunSpec 的 sunssf）：读取时原始值先乘以 10 的该点位值次方，再应用 scale 和 offset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale_factor: Option<String>,
+    /// 工程单位，例如 "V"、"kWh"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// 按 scale 和 offset 计算后的值的单位，例如 "kW"；设置后按 target_units 换算，发布的单位为换算后的单位，不能与 unit 同时设置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_unit: Option<String>,
+    /// 所属采集组，对应配置中 poll_groups 的名称；不指定时使用网关的 poll_interval_ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// 是否采集该点位（默认启用），停用的点位保留在配置中但不读取也不发布
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub enabled: bool,
+    /// 是否允许通过 MQTT 命令写入（默认 false），只能用于线圈（0x01）和保持寄存器（0x03）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub writable: bool,
+    /// 写入后是否回读校验（默认 false）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub verify_write: bool,
+    /// 发布使用的 QoS，未配置时使用采集组或 mqtt.qos
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qos: Option<u8>,
+    /// 发布时是否设置 retain，未配置时使用采集组或 mqtt.retain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain: Option<bool>,
+    /// 死区：与上次发布的值相差不超过该值时不发布，0 表示只发布变化的值；未配置时使用采集组的 deadband
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadband: Option<f64>,
+    /// Home Assistant 自动发现使用的 device_class，未配置时按单位推断
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    /// 模拟数据参数，只在网关设置了 simulation 时使用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sim: Option<SimulationHint>,
+    /// 按位拆分出的点位名称到位号或位范围的映射，只能用于 u16 点位；读取该点位时同时得到这些点位
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bits: BTreeMap<String, BitField>,
+    /// 由 `bits` 生成的点位取的位，配置中的点位为 None
+    #[serde(skip)]
+    pub bit: Option<BitField>,
+    /// 由 source_unit 和 target_units 确定的单位换算，配置中的点位为 None
+    #[serde(skip)]
+    pub conversion: Option<Conversion>,
+}
+
+impl Point {
+    /// 检查点位定义是否合法
+    ///
+    /// # 校验规则
+    /// * 名称不能为空
+    /// * 功能码只能是0x01-0x04
+    /// * 地址加上数据类型占用的寄存器数量不能超出65535
+    /// * 字符串需要设置 length（1-123），只能是保持寄存器或输入寄存器，字节序为 abcd 或 badc；其他类型不能设置 length
+    /// * 只有线圈和保持寄存器可以设置 writable
+    /// * qos 只能是0、1、2，deadband 不能为负数
+    /// * device_class 只能包含小写字母和下划线
+    /// * source_unit 必须是内置单位表中的单位，只能用于数值点位，不能与 unit 同时设置
+    /// * scale_factor 只能用于数值点位，不能引用自身，设置后不能写入
+    /// * sim 参数合法
+    /// * bits 只能用于保持寄存器或输入寄存器的 u16 点位，位号为0-15，各项的位不能重叠，名称不能为空或与点位相同
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.name.is_empty() {
+            return Err(format!("地址 {} 的点位名称不能为空", self.address).into());
+        }
+        if !(0x01..=0x04).contains(&self.function_code) {
+            return Err(format!(
+                "点位 {} 的功能码 0x{:02X} 不是读取功能码",
+                self.name, self.function_code
+            )
+            .into());
+        }
+        match (self.data_type, self.length) {
+            (DataType::String { .. }, Some(1..=MAX_STRING_REGISTERS)) => {}
+            (DataType::String { .. }, Some(length)) => {
+                return Err(format!(
+                    "点位 {} 的 length 必须在 1-{} 之间，当前为 {}",
+                    self.name, MAX_STRING_REGISTERS, length
+                )
+                .into());
+            }
+            (DataType::String { .. }, None) => {
+                return Err(format!("字符串点位 {} 需要设置 length（寄存器数量）", self.name).into());
+            }
+            (_, Some(_)) => {
+                return Err(format!("点位 {} 不是字符串，不能设置 length", self.name).into());
+            }
+            (_, None) => {}
+        }
+        if self.data_type.is_string() {
+            if !matches!(self.function_code, 0x03 | 0x04) {
+                return Err(format!("字符串点位 {} 只能读取保持寄存器或输入寄存器", self.name).into());
+            }
+            if !matches!(self.word_order, WordOrder::Abcd | WordOrder::Badc) {
+                return Err(format!("字符串点位 {} 的 word_order 只能是 abcd 或 badc", self.name).into());
+            }
+        }
+        let end = self.address as u32 + self.register_count() as u32;
+        if end > u16::MAX as u32 + 1 {
+            return Err(format!("点位 {} 的地址超出范围", self.name).into());
+        }
+        if self.writable && self.write_function_code().is_none() {
+            return Err(format!(
+                "点位 {} 的功能码 0x{:02X} 是只读的，不能设置 writable",
+                self.name, self.function_code
+            )
+            .into());
+        }
+        if let Some(qos) = self.qos
+            && qos > 2
+        {
+            return Err(format!("点位 {} 的 qos 只能是0、1、2，当前为 {}", self.name, qos).into());
+        }
+        if let Some(deadband) = self.deadband
+            && !(deadband >= 0.0 && deadband.is_finite())
+        {
+            return Err(format!("点位 {} 的 deadband 必须是非负数，当前为 {}", self.name, deadband).into());
+        }
+        if let Some(device_class) = &self.device_class
+            && (device_class.is_empty()
+                || !device_class.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+        {
+            return Err(format!(
+                "点位 {} 的 device_class \"{}\" 只能包含小写字母和下划线",
+                self.name, device_class
+            )
+            .into());
+        }
+        if let Some(source_unit) = &self.source_unit {
+            if quantity_of(source_unit).is_none() {
+                return Err(format!("点位 {} 的 source_unit \"{}\" 不是已知的单位", self.name, source_unit).into());
+            }
+            if self.data_type == DataType::Bool || self.data_type.is_string() {
+                return Err(format!("点位 {} 不是数值点位，不能设置 source_unit", self.name).into());
+            }
+            if self.unit.is_some() {
+                return Err(format!("点位 {} 不能同时设置 unit 和 source_unit", self.name).into());
+            }
+        }
+        if let Some(scale_factor) = &self.scale_factor {
+            if self.data_type == DataType::Bool || self.data_type.is_string() {
+                return Err(format!("点位 {} 不是数值点位，不能设置 scale_factor", self.name).into());
+            }
+            if *scale_factor == self.name {
+                return Err(format!("点位 {} 的 scale_factor 不能引用自身", self.name).into());
+            }
+            if self.writable {
+                return Err(format!("点位 {} 设置了 scale_factor，不能写入", self.name).into());
+            }
+        }
+        if let Some(sim) = &self.sim {
+            sim.validate(&self.name)?;
+        }
+        if !self.bits.is_empty() {
+            self.validate_bits()?;
+        }
+        Ok(())
+    }
+
+    fn validate_bits(&self) -> Result<(), Box<dyn Error>> {
+        if self.data_type != DataType::U16 || !matches!(self.function_code, 0x03 | 0x04) {
+            return Err(format!(
+                "点位 {} 的 bits 只能用于保持寄存器或输入寄存器的 u16 点位",
+                self.name
+            )
+            .into());
+        }
+        let fields: Vec<(&String, &BitField)> = self.bits.iter().collect();
+        for (index, (name, field)) in fields.iter().enumerate() {
+            if name.is_empty() || **name == self.name {
+                return Err(format!("点位 {} 的 bits 中的名称不能为空或与点位相同", self.name).into());
+            }
+            field.validate(&format!("点位 {} 的 bits.{}", self.name, name))?;
+            if let Some((other, _)) = fields[..index].iter().find(|(_, f)| f.overlaps(field)) {
+                return Err(format!(
+                    "点位 {} 的 bits.{} 与 bits.{} 的位重叠",
+                    self.name, other, name
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// 由 `bits` 生成的点位：一位的为 bool，多位的为 u16；不单独读取，也不能写入
+    pub fn bit_points(&self) -> Vec<Point> {
+        self.bits
+            .iter()
+            .map(|(name, field)| Point {
+                name: name.clone(),
+                data_type: if field.is_single() {
+                    DataType::Bool
+                } else {
+                    DataType::U16
+                },
+                length: None,
+                word_order: WordOrder::default(),
+                scale: 1.0,
+                offset: 0.0,
+                scale_factor: None,
+                unit: None,
+                source_unit: None,
+                conversion: None,
+                writable: false,
+                verify_write: false,
+                device_class: None,
+                sim: None,
+                bits: BTreeMap::new(),
+                bit: Some(*field),
+                ..self.clone()
+            })
+            .collect()
+    }
+
+    /// 该点位占用的寄存器（或线圈）数量
+    pub fn register_count(&self) -> u16 {
+        self.value_type().register_count()
+    }
+
+    /// 数据类型，字符串带上 length 指定的长度
+    pub fn value_type(&self) -> DataType {
+        match self.data_type {
+            DataType::String { .. } => DataType::String {
+                length_registers: self.length.unwrap_or(0),
+            },
+            data_type => data_type,
+        }
+    }
+
+    /// 占用的最后一个地址
+    pub fn end_address(&self) -> u32 {
+        self.address as u32 + self.register_count() as u32 - 1
+    }
+
+    /// 与另一个点位功能码相同且地址范围重叠
+    pub fn overlaps(&self, other: &Point) -> bool {
+        self.function_code == other.function_code
+            && self.address as u32 <= other.end_address()
+            && other.address as u32 <= self.end_address()
+    }
+
+    /// 写入该点位使用的功能码：线圈用0x05，单个保持寄存器用0x06，多个保持寄存器用0x10
+    ///
+    /// # 返回值
+    /// * `None` - 离散输入和输入寄存器不能写入
+    pub fn write_function_code(&self) -> Option<u8> {
+        match (self.function_code, self.register_count()) {
+            (0x01, _) => Some(0x05),
+            (0x03, 1) => Some(0x06),
+            (0x03, _) => Some(0x10),
+            _ => None,
+        }
+    }
+
+    /// 按单位换算把 scale 和 offset 计算后的值换算为发布的值，没有换算时原样返回
+    pub fn convert(&self, value: f64) -> f64 {
+        match &self.conversion {
+            Some(conversion) => conversion.apply(value),
+            None => value,
+        }
+    }
+
+    /// 由工程值计算要写入的寄存器值（反向应用单位换算、scale 和 offset）
+    pub fn registers_for_value(&self, value: f64) -> Result<Vec<u16>, Box<dyn Error>> {
+        if self.data_type.is_string() {
+            return Err(format!("字符串点位 {} 只能写入字符串", self.name).into());
+        }
+        if self.scale == 0.0 {
+            return Err(format!("点位 {} 的 scale 为0，无法换算写入值", self.name).into());
+        }
+        let value = match &self.conversion {
+            Some(conversion) => conversion.invert(value),
+            None => value,
+        };
+        let raw = (value - self.offset) / self.scale;
+        encode(self.data_type, self.word_order, raw)
+            .map_err(|e| format!("点位 {} 写入值 {} 无效: {}", self.name, value, e).into())
+    }
+
+    /// 由字符串计算要写入的寄存器值，不足 length 的部分用 NUL 填充
+    pub fn registers_for_text(&self, text: &str) -> Result<Vec<u16>, Box<dyn Error>> {
+        let DataType::String { length_registers } = self.value_type() else {
+            return Err(format!("点位 {} 不是字符串，只能写入数值", self.name).into());
+        };
+        encode_string(self.word_order, text, length_registers)
+            .map_err(|e| format!("点位 {} 写入值无效: {}", self.name, e).into())
+    }
+
+    /// 由原始寄存器值计算工程值（已应用 scale、offset 和单位换算），字符串点位返回 None
+    pub fn value_from(&self, registers: &[u16]) -> Option<f64> {
+        if let Some(field) = self.bit {
+            return registers.first().map(|register| f64::from(field.extract(*register)));
+        }
+        decode(self.data_type, self.word_order, registers).map(|raw| self.convert(raw * self.scale + self.offset))
+    }
+
+    /// 由原始寄存器值和比例因子计算工程值
+    ///
+    /// 比例因子为 -32768，或 i16 值为 -32768、u16 值为 65535（SunSpec 中都表示未实现）时返回 None
+    pub fn value_with_scale_factor(&self, registers: &[u16], scale_factor: i16) -> Option<f64> {
+        let raw = decode(self.data_type, self.word_order, registers)?;
+        let not_implemented = match self.data_type {
+            DataType::I16 => raw == f64::from(i16::MIN),
+            DataType::U16 => raw == f64::from(u16::MAX),
+            _ => false,
+        };
+        if scale_factor == i16::MIN || not_implemented {
+            return None;
+        }
+        // 负的比例因子用除法，避免 0.1 这样的系数带来的误差
+        let raw = if scale_factor < 0 {
+            raw / 10f64.powi(-i32::from(scale_factor))
+        } else {
+            raw * 10f64.powi(scale_factor.into())
+        };
+        Some(self.convert(raw * self.scale + self.offset))
+    }
+
+    /// 由原始寄存器值解析字符串，不是字符串点位或寄存器数量不足时返回 None
+    pub fn text_from(&self, registers: &[u16]) -> Option<String> {
+        let count = self.register_count() as usize;
+        if !self.data_type.is_string() || registers.len() < count {
+            return None;
+        }
+        Some(decode_string(self.word_order, &registers[..count]))
+    }
+}
+
+/// 点位以及由其 `bits` 生成的点位，生成的点位紧跟在所属点位之后
+pub fn with_bit_points(points: Vec<Point>) -> Vec<Point> {
+    let mut expanded = Vec::with_capacity(points.len());
+    for point in points {
+        let bit_points = point.bit_points();
+        expanded.push(point);
+        expanded.extend(bit_points);
+    }
+    expanded
+}
+
+/// 把自动生成的点位（例如 SunSpec 发现的点位）加到已有点位之后
+///
+/// 名称与已有点位（包括 bits 中的名称）相同或地址与已启用的已有点位重叠的生成点位被跳过，
+/// 比例因子点位被跳过时引用它的点位也一并跳过，即手动配置的点位优先。
+///
+/// # 返回值
+/// 被跳过的生成点位名称
+pub fn merge_generated(points: &mut Vec<Point>, generated: &[Point]) -> Vec<String> {
+    let existing = points.len();
+    let mut skipped: Vec<String> = Vec::new();
+    for point in generated {
+        let conflicts = points[..existing].iter().any(|p| {
+            p.name == point.name
+                || p.bits.contains_key(&point.name)
+                || (p.enabled && p.overlaps(point))
+        });
+        if conflicts || point.scale_factor.as_ref().is_some_and(|name| skipped.contains(name)) {
+            skipped.push(point.name.clone());
+        } else {
+            points.push(point.clone());
+        }
+    }
+    // 比例因子点位排在引用它的点位之后时，引用它的点位已经加入
+    let added = points.split_off(existing);
+    for point in added {
+        if point.scale_factor.as_ref().is_some_and(|name| skipped.contains(name)) {
+            skipped.push(point.name);
+        } else {
+            points.push(point);
+        }
+    }
+    skipped
+}
+
+/// 检查一组点位的定义是否合法
+///
+/// # 参数说明
+/// * `location` - 这组点位所在的位置，用于错误信息，例如 "网关 PCS-B"
+/// * `points` - 点位列表
+/// * `allow_duplicates` - 为 true 时不检查地址重叠
+///
+/// # 校验规则
+/// * 每个点位定义合法，且名称（包括 bits 中的名称）不能重复
+/// * scale_factor 引用的点位必须是同一采集组中的 i16 寄存器点位，点位启用时它也要启用
+/// * 功能码相同的已启用点位地址范围不能重叠
+pub fn validate_points(
+    location: &str,
+    points: &[Point],
+    allow_duplicates: bool,
+) -> Result<(), Box<dyn Error>> {
+    for (index, point) in points.iter().enumerate() {
+        point
+            .validate()
+            .map_err(|e| format!("{} 中: {}", location, e))?;
+        let names = || points[..index].iter().flat_map(|p| std::iter::once(&p.name).chain(p.bits.keys()));
+        for name in std::iter::once(&point.name).chain(point.bits.keys()) {
+            if names().any(|other| other == name) {
+                return Err(format!("{} 中点位名称 {} 重复", location, name).into());
+            }
+        }
+        if let Some(name) = &point.scale_factor {
+            let Some(factor) = points.iter().find(|p| p.name == *name) else {
+                return Err(format!(
+                    "{} 中点位 {} 的 scale_factor 引用了不存在的点位 {}",
+                    location, point.name, name
+                )
+                .into());
+            };
+            if factor.data_type != DataType::I16
+                || !matches!(factor.function_code, 0x03 | 0x04)
+                || factor.group != point.group
+                || (point.enabled && !factor.enabled)
+            {
+                return Err(format!(
+                    "{} 中点位 {} 的 scale_factor 引用的点位 {} 必须是同一采集组中已启用的 i16 寄存器点位",
+                    location, point.name, name
+                )
+                .into());
+            }
+        }
+        if allow_duplicates {
+            continue;
+        }
+        if let Some(other) = points[..index].iter().find(|p| p.enabled && point.enabled && p.overlaps(point)) {
+            return Err(format!(
+                "{} 中点位 {}（功能码0x{:02X} 地址{}-{}）与点位 {}（地址{}-{}）地址重叠，\
+                 如确有需要请设置 allow_duplicates: true",
+                location,
+                other.name,
+                other.function_code,
+                other.address,
+                other.end_address(),
+                point.name,
+                point.address,
+                point.end_address()
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+// 写入多个寄存器一次最多123个
+const MAX_STRING_REGISTERS: u16 = 123;
+
+fn default_function_code() -> u8 {
+    0x03
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
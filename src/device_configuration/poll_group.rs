@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use super::units::{validate_target_units, Quantity};
+
+use crate::mqtt::compression::Compression;
+use crate::mqtt::payload::PayloadFormat;
+
+/// 采集组，组内点位按相同周期采集，对应配置中 `poll_groups:` 下的一项
+///
+/// ```yaml
+/// poll_groups:
+///   fast:
+///     interval_ms: 2000
+///   energy:
+///     interval_ms: 60000
+///     retain: true
+///     target_units:
+///       energy: kWh
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PollGroup {
+    /// 采集周期，单位毫秒
+    pub interval_ms: u64,
+    /// 组内点位发布使用的 QoS，未配置时使用 mqtt.qos
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qos: Option<u8>,
+    /// 组内点位发布时是否设置 retain，未配置时使用 mqtt.retain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retain: Option<bool>,
+    /// 组内点位的死区，未配置时每次采集都发布
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadband: Option<f64>,
+    /// 组内点位的消息格式，未配置时使用 mqtt.payload_format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_format: Option<PayloadFormat>,
+    /// 组内点位消息的压缩方式，未配置时使用 mqtt.compression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// 组内点位各物理量发布使用的单位，优先于全局的 target_units
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target_units: BTreeMap<Quantity, String>,
+}
+
+impl PollGroup {
+    /// 检查采集组配置是否合法
+    pub fn validate(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() {
+            return Err("采集组名称不能为空".into());
+        }
+        if self.interval_ms == 0 {
+            return Err(format!("采集组 {} 的 interval_ms 不能为0", name).into());
+        }
+        if let Some(qos) = self.qos
+            && qos > 2
+        {
+            return Err(format!("采集组 {} 的 qos 只能是0、1、2，当前为 {}", name, qos).into());
+        }
+        if let Some(deadband) = self.deadband
+            && !(deadband >= 0.0 && deadband.is_finite())
+        {
+            return Err(format!("采集组 {} 的 deadband 必须是非负数，当前为 {}", name, deadband).into());
+        }
+        validate_target_units(&format!("采集组 {} 的 target_units", name), &self.target_units)?;
+        Ok(())
+    }
+}
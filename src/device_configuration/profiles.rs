@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use std::error::Error;
+
+use super::point::{validate_points, Point};
+
+/// 内置的常见设备点位表
+///
+/// 配置中通过 `profile: <名称>` 引用，与用户在 `templates:` 中定义的模板用法相同；
+/// 用户模板与内置点位表同名时优先使用用户模板。
+const BUILTIN: &[(&str, &str)] = &[
+    ("sdm630", include_str!("profiles/sdm630.yaml")),
+    ("sdm120", include_str!("profiles/sdm120.yaml")),
+    ("adl400", include_str!("profiles/adl400.yaml")),
+    ("sunspec_common", include_str!("profiles/sunspec_common.yaml")),
+    ("sunspec_inverter", include_str!("profiles/sunspec_inverter.yaml")),
+];
+
+/// 内置点位表
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// 名称
+    pub name: &'static str,
+    /// 设备说明
+    pub description: String,
+    /// 点位定义
+    pub points: Vec<Point>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    description: String,
+    points: Vec<Point>,
+}
+
+/// 所有内置点位表的名称
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTIN.iter().map(|(name, _)| *name)
+}
+
+/// 按名称加载内置点位表
+///
+/// # 返回值
+/// * `Ok(Some(Profile))` - 加载并校验成功
+/// * `Ok(None)` - 没有该名称的内置点位表
+/// * `Err` - 内置点位表解析或校验失败
+pub fn get(name: &str) -> Result<Option<Profile>, Box<dyn Error>> {
+    let Some((name, source)) = BUILTIN.iter().find(|(n, _)| *n == name) else {
+        return Ok(None);
+    };
+    let file: ProfileFile = serde_yaml::from_str(source)
+        .map_err(|e| format!("内置点位表 {} 解析失败: {}", name, e))?;
+    validate_points(&format!("内置点位表 {}", name), &file.points, false)?;
+    Ok(Some(Profile {
+        name,
+        description: file.description,
+        points: file.points,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+
+    #[test]
+    fn every_profile_parses_validates_and_expands() {
+        for name in names() {
+            let profile = get(name).unwrap().unwrap();
+            assert_eq!(profile.name, name);
+            assert!(!profile.points.is_empty(), "{}", name);
+
+            let yaml = format!(
+                "version: 2\ngateways:\n  - ip: 10.0.0.1\n    profile: {}\n    slave_ids: [1]\n",
+                name
+            );
+            let config: Config = serde_yaml::from_str(&yaml).unwrap();
+            config.validate().unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let gateway = &config.gateways[0];
+            let slave = gateway.enabled_slaves().next().unwrap();
+            let points = config.published_points(gateway, slave).unwrap();
+            let expanded: Vec<&str> = points.iter().map(|p| p.name.as_str()).collect();
+            let defined: Vec<&str> = profile.points.iter().map(|p| p.name.as_str()).collect();
+            assert_eq!(expanded, defined, "{}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_profile_is_none() {
+        assert!(get("no_such_meter").unwrap().is_none());
+    }
+
+    #[test]
+    fn user_template_takes_precedence() {
+        let yaml = "version: 2\ntemplates:\n  sdm120:\n    - { name: only, address: 7 }\n";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let points = config.profile_points("sdm120").unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].name, "only");
+        assert!(config.profile_points("sdm630").unwrap().len() > 1);
+    }
+}
@@ -0,0 +1,392 @@
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+
+use super::modbus::{Config, ModbusDevice};
+use super::slave::check_topic_safe;
+
+/// 星期
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Weekday {
+    /// 星期一
+    Mon,
+    /// 星期二
+    Tue,
+    /// 星期三
+    Wed,
+    /// 星期四
+    Thu,
+    /// 星期五
+    Fri,
+    /// 星期六
+    Sat,
+    /// 星期日
+    Sun,
+}
+
+impl Weekday {
+    // 星期日为0
+    fn number(self) -> u32 {
+        match self {
+            Weekday::Sun => 0,
+            Weekday::Mon => 1,
+            Weekday::Tue => 2,
+            Weekday::Wed => 3,
+            Weekday::Thu => 4,
+            Weekday::Fri => 5,
+            Weekday::Sat => 6,
+        }
+    }
+}
+
+/// 定时写入配置，对应配置文件中 `schedules:` 下的一项
+///
+/// ```yaml
+/// schedules:
+///   - name: valley_charge
+///     gateway: PCS-A
+///     slave: 1
+///     point: power_setpoint
+///     value: -100
+///     at: "23:00"
+///     days: [mon, tue, wed, thu, fri]
+///     timezone: Asia/Shanghai
+///   - name: peak_discharge
+///     gateway: PCS-A
+///     slave: 1
+///     point: power_setpoint
+///     value: 80
+///     cron: "0 10,18 * * *"
+///     catch_up: true
+///     verify: true
+///   - name: reset_fault
+///     gateway: 192.168.1.100:502
+///     slave: 1
+///     function_code: 5
+///     address: 16
+///     value: 1
+///     cron: "*/30 * * * *"
+/// ```
+///
+/// `at` 为每天的 `HH:MM`，`cron` 为5个字段的 cron 表达式（分 时 日 月 星期），两者配置一个。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ScheduleSettings {
+    /// 计划名称，用于日志和 MQTT 主题，不能重复，不能包含 +、#、/ 和空白字符
+    pub name: String,
+    /// 网关名称或 ip:port
+    pub gateway: String,
+    /// 从站ID
+    pub slave: u8,
+    /// 要写入的点位名称，点位需要设置 writable: true；与 function_code、address 二选一
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub point: Option<String>,
+    /// 直接写入寄存器时的写入功能码：5、15 写线圈，6、16 写一个保持寄存器
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_code: Option<u8>,
+    /// 直接写入寄存器时的地址
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<u16>,
+    /// 写入值：点位为工程值，直接写入寄存器时为寄存器值（线圈为0或1）
+    pub value: f64,
+    /// 每天执行的时间 `HH:MM`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<String>,
+    /// cron 表达式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// 只在这些星期执行，未配置时每天执行
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<Weekday>,
+    /// 时区（IANA 名称，例如 Asia/Shanghai），未配置时使用全局 timezone，都未配置时使用系统时区
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// 为 false 时不执行（默认 true）
+    #[serde(default = "default_enabled", skip_serializing_if = "is_true")]
+    pub enabled: bool,
+    /// 为 true 时程序启动或长时间停顿后补执行错过的最近一次写入（默认 false，错过的直接跳过）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub catch_up: bool,
+    /// 为 true 时写入后回读校验（默认 false；点位设置了 verify_write 时也会校验）
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub verify: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl ScheduleSettings {
+    /// 解析执行时间
+    pub fn trigger(&self) -> Result<Trigger, Box<dyn Error>> {
+        let location = format!("定时写入 {}", self.name);
+        let mut trigger = match (&self.at, &self.cron) {
+            (Some(at), None) => {
+                Trigger::daily(at).map_err(|e| format!("{} 的 at 不合法: {}", location, e))?
+            }
+            (None, Some(cron)) => {
+                Trigger::cron(cron).map_err(|e| format!("{} 的 cron 不合法: {}", location, e))?
+            }
+            _ => return Err(format!("{} 需要配置 at 或 cron 中的一个", location).into()),
+        };
+        if !self.days.is_empty() {
+            trigger.days = Some(self.days.iter().fold(0, |days, day| days | 1 << day.number()));
+        }
+        Ok(trigger)
+    }
+
+    /// 解析配置的时区，未配置时为 None（使用全局 timezone）
+    pub fn tz(&self) -> Result<Option<Tz>, Box<dyn Error>> {
+        match &self.timezone {
+            Some(name) => match name.parse::<Tz>() {
+                Ok(tz) => Ok(Some(tz)),
+                Err(_) => Err(format!(
+                    "定时写入 {} 的 timezone 不是合法的时区名称: {}",
+                    self.name, name
+                )
+                .into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// 写入目标的描述，点位名称或 `功能码:地址`，用于日志和判断多个计划是否写入同一目标
+    pub fn target(&self) -> String {
+        match (&self.point, self.function_code, self.address) {
+            (Some(point), _, _) => point.clone(),
+            (None, Some(code), Some(address)) => format!("fc{}:{}", code, address),
+            _ => String::new(),
+        }
+    }
+
+    /// 查找写入的网关
+    pub fn find_gateway<'a>(&self, config: &'a Config) -> Option<&'a ModbusDevice> {
+        config.gateways.iter().find(|g| {
+            g.name.as_deref() == Some(self.gateway.as_str())
+                || format!("{}:{}", g.ip, g.port) == self.gateway
+        })
+    }
+
+    /// 检查定时写入配置是否合法
+    ///
+    /// # 校验规则
+    /// * 名称不能为空，不能包含 +、#、/ 和空白字符
+    /// * at 和 cron 配置且只配置一个，格式正确；timezone 必须是合法的 IANA 时区名称
+    /// * point 与 function_code、address 二选一，function_code 为 5、6、15 或 16
+    /// * 引用的网关和从站必须存在，网关需要配置了点位（写入由该网关的采集任务执行）；
+    ///   点位必须存在且可写，写入值能换算为寄存器值
+    pub fn validate(&self, config: &Config) -> Result<(), Box<dyn Error>> {
+        check_topic_safe("定时写入", &self.name)?;
+        let location = format!("定时写入 {}", self.name);
+        self.trigger()?;
+        self.tz()?;
+        if !self.value.is_finite() {
+            return Err(format!("{} 的 value 不合法: {}", location, self.value).into());
+        }
+        let Some(gateway) = self.find_gateway(config) else {
+            return Err(format!("{} 引用了不存在的网关 {}", location, self.gateway).into());
+        };
+        if !gateway.has_points() {
+            return Err(format!(
+                "{} 引用的网关 {} 没有配置点位，不进行采集，无法执行写入",
+                location,
+                gateway.display_name()
+            )
+            .into());
+        }
+        let Some(slave) = gateway.find_slave(self.slave) else {
+            return Err(format!(
+                "{} 引用了网关 {} 中不存在的从站 {}",
+                location,
+                gateway.display_name(),
+                self.slave
+            )
+            .into());
+        };
+        match (&self.point, self.function_code, self.address) {
+            (Some(name), None, None) => {
+                let points = config.effective_points(gateway, slave)?;
+                let Some(point) = points.iter().find(|p| p.name == *name) else {
+                    return Err(format!(
+                        "{} 引用了网关 {} 从站 {} 中不存在的点位 {}",
+                        location,
+                        gateway.display_name(),
+                        slave.display_name(),
+                        name
+                    )
+                    .into());
+                };
+                if !point.writable {
+                    return Err(format!("{} 引用的点位 {} 不允许写入", location, name).into());
+                }
+                point
+                    .registers_for_value(self.value)
+                    .map_err(|e| format!("{}: {}", location, e))?;
+            }
+            (None, Some(code), Some(_)) => {
+                if !matches!(code, 0x05 | 0x06 | 0x0F | 0x10) {
+                    return Err(format!(
+                        "{} 的 function_code 必须是 5、6、15 或 16，当前为 {}",
+                        location, code
+                    )
+                    .into());
+                }
+                let max = if matches!(code, 0x05 | 0x0F) { 1.0 } else { u16::MAX as f64 };
+                if self.value.fract() != 0.0 || !(0.0..=max).contains(&self.value) {
+                    return Err(format!(
+                        "{} 直接写入寄存器时 value 必须是 0-{} 之间的整数，当前为 {}",
+                        location, max, self.value
+                    )
+                    .into());
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "{} 需要配置 point，或者同时配置 function_code 和 address",
+                    location
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 检查所有定时写入配置，除逐个校验外还要求名称不重复
+pub fn validate_schedules(
+    schedules: &[ScheduleSettings],
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let mut names = HashSet::new();
+    for schedule in schedules {
+        schedule.validate(config)?;
+        if !names.insert(schedule.name.as_str()) {
+            return Err(format!("定时写入名称 {} 重复", schedule.name).into());
+        }
+    }
+    Ok(())
+}
+
+/// 解析后的执行时间，精确到分钟
+///
+/// 每个字段用位表示允许的值。与 cron 一样，日和星期都有限制时满足其中一个即可；
+/// `days` 另外限制星期。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    minutes: u64,
+    hours: u32,
+    // 1-31 日
+    days_of_month: u32,
+    // 1-12 月
+    months: u16,
+    // 星期日为0
+    weekdays: u8,
+    day_of_month_any: bool,
+    weekday_any: bool,
+    days: Option<u8>,
+}
+
+impl Trigger {
+    /// 每天 `HH:MM` 执行
+    pub fn daily(at: &str) -> Result<Self, Box<dyn Error>> {
+        let (hour, minute) = at.split_once(':').ok_or("格式应为 HH:MM")?;
+        let hour: u32 = hour.trim().parse().map_err(|_| "小时不是数字")?;
+        let minute: u32 = minute.trim().parse().map_err(|_| "分钟不是数字")?;
+        if hour > 23 || minute > 59 {
+            return Err(format!("{} 不是合法的时间", at).into());
+        }
+        Trigger::cron(&format!("{} {} * * *", minute, hour))
+    }
+
+    /// 解析5个字段的 cron 表达式：分 时 日 月 星期
+    ///
+    /// 每个字段可以是 `*`、数字、范围 `a-b`、步长 `*/n` 或 `a-b/n`，以及用逗号分隔的列表；
+    /// 星期的 0 和 7 都表示星期日。
+    pub fn cron(expression: &str) -> Result<Self, Box<dyn Error>> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("需要5个字段，实际为 {} 个", fields.len()).into());
+        };
+        let weekdays = parse_field(weekday, 0, 7, "星期")?;
+        Ok(Trigger {
+            minutes: parse_field(minute, 0, 59, "分钟")?,
+            hours: parse_field(hour, 0, 23, "小时")? as u32,
+            days_of_month: parse_field(day, 1, 31, "日")? as u32,
+            months: parse_field(month, 1, 12, "月")? as u16,
+            // 7 与 0 相同
+            weekdays: ((weekdays | weekdays >> 7) & 0x7F) as u8,
+            day_of_month_any: day == "*",
+            weekday_any: weekday == "*",
+            days: None,
+        })
+    }
+
+    /// 本地时间所在的这一分钟是否需要执行
+    pub fn matches(&self, time: &NaiveDateTime) -> bool {
+        let weekday = time.weekday().num_days_from_sunday();
+        let day_of_month = self.days_of_month & 1 << time.day() != 0;
+        let day_of_week = self.weekdays & 1 << weekday != 0;
+        let day = match (self.day_of_month_any, self.weekday_any) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        self.minutes & 1 << time.minute() != 0
+            && self.hours & 1 << time.hour() != 0
+            && self.months & 1 << time.month() != 0
+            && day
+            && self.days.is_none_or(|days| days & 1 << weekday != 0)
+    }
+}
+
+// 解析 cron 的一个字段，返回允许的值的位集合
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, Box<dyn Error>> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("{} 字段的步长 {} 不合法", name, step))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let number = |text: &str| -> Result<u32, Box<dyn Error>> {
+            text.parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("{} 字段的值 {} 不在 {}-{} 之间", name, text, min, max).into())
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/15` 表示从5开始每15个
+                None if step > 1 => (number(range)?, max),
+                None => {
+                    let value = number(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("{} 字段的范围 {} 不合法", name, range).into());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fs;
+
+/// 日志和调试输出中代替敏感信息显示的内容
+pub const REDACTED: &str = "***";
+
+/// 解析间接引用的敏感配置
+///
+/// # 参数说明
+/// * `field` - 字段路径，用于错误信息，例如 "mqtt.password"
+/// * `inline` - 直接写在配置文件中的值
+/// * `file` - `<field>_file` 指定的文件路径，读取后去掉末尾换行
+/// * `env` - `<field>_env` 指定的环境变量名
+///
+/// # 说明
+/// * 优先级：环境变量 > 文件 > 直接写入的值
+/// * 配置了环境变量或文件但无法读取时直接报错，不会退回到优先级更低的来源
+pub fn resolve_secret(
+    field: &str,
+    inline: Option<&str>,
+    file: Option<&str>,
+    env: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if let Some(name) = env {
+        return match std::env::var(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(format!("{}_env 引用的环境变量 {} 未设置", field, name).into()),
+        };
+    }
+    if let Some(path) = file {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("{}_file 引用的文件 {} 无法读取: {}", field, path, e))?;
+        return Ok(Some(contents.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    Ok(inline.map(str::to_string))
+}
+
+/// 将敏感信息替换为 `***`，用于 Debug 输出
+pub fn redact(value: &Option<String>) -> Option<&'static str> {
+    value.as_ref().map(|_| REDACTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::http::HttpSettings;
+    use crate::device_configuration::mqtt::MqttSettings;
+
+    #[test]
+    fn inline_value_is_used_without_indirection() {
+        let value = resolve_secret("mqtt.password", Some("inline"), None, None).unwrap();
+        assert_eq!(value.as_deref(), Some("inline"));
+        assert_eq!(resolve_secret("mqtt.password", None, None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn file_takes_precedence_and_trailing_newline_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "from-file\r\n").unwrap();
+        let path = path.to_string_lossy();
+        let value = resolve_secret("mqtt.password", Some("inline"), Some(&path), None).unwrap();
+        assert_eq!(value.as_deref(), Some("from-file"));
+
+        let missing = dir.path().join("missing").to_string_lossy().into_owned();
+        let error = resolve_secret("mqtt.password", Some("inline"), Some(&missing), None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with(&format!("mqtt.password_file 引用的文件 {} 无法读取", missing)), "{}", error);
+    }
+
+    #[test]
+    fn environment_takes_precedence() {
+        const NAME: &str = "MODBUS_PUB_TEST_SECRET_ENV";
+        // SAFETY: 只有这个测试使用该环境变量
+        unsafe { std::env::set_var(NAME, "from-env") };
+        let value = resolve_secret("mqtt.password", Some("inline"), Some("/nonexistent"), Some(NAME)).unwrap();
+        assert_eq!(value.as_deref(), Some("from-env"));
+
+        let error = resolve_secret("mqtt.password", Some("inline"), None, Some("MODBUS_PUB_TEST_UNSET_ENV"))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "mqtt.password_env 引用的环境变量 MODBUS_PUB_TEST_UNSET_ENV 未设置");
+    }
+
+    #[test]
+    fn debug_output_redacts_passwords() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "file-secret\n").unwrap();
+        let mut mqtt = MqttSettings::new("broker.local", "site-1");
+        mqtt.password = Some("inline-secret".to_string());
+        mqtt.password_file = Some(path.to_string_lossy().into_owned());
+        mqtt.resolve_secrets().unwrap();
+        assert_eq!(mqtt.effective_password(), Some("file-secret"));
+
+        let debug = format!("{:?}", mqtt);
+        assert!(!debug.contains("inline-secret") && !debug.contains("file-secret"), "{}", debug);
+        assert!(debug.contains("password: Some(\"***\")"), "{}", debug);
+        assert_eq!(mqtt.redacted().password.as_deref(), Some(REDACTED));
+        assert_eq!(mqtt.redacted().effective_password(), Some(REDACTED));
+
+        let mut http = HttpSettings::default();
+        http.token = Some("token-secret".to_string());
+        http.resolve_secrets().unwrap();
+        assert_eq!(http.effective_token(), Some("token-secret"));
+        assert!(!format!("{:?}", http).contains("token-secret"));
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// 模拟数据的变化方式
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulationKind {
+    /// 在 min 和 max 之间按正弦变化，周期为 period_s
+    #[default]
+    Sine,
+    /// 从 min 和 max 的中点开始，每次读取随机增减不超过 step，限制在 min 和 max 之间
+    RandomWalk,
+    /// 固定为 value
+    Constant,
+    /// 每 period_s 在 min 和 max 之间切换一次
+    Toggle,
+}
+
+/// 点位的模拟数据参数，对应点位定义中的 `sim:`，只在网关设置了 `simulation: true` 时使用
+///
+/// ```yaml
+/// points:
+///   - { name: voltage, address: 0, scale: 0.1, sim: { kind: sine, min: 210, max: 240, period_s: 60 } }
+///   - { name: soc, address: 1, sim: { kind: random_walk, min: 20, max: 90, step: 0.5 } }
+///   - { name: running, function_code: 1, address: 0, data_type: bool, sim: { kind: toggle, period_s: 30 } }
+/// ```
+///
+/// 数值都是工程值（已应用 scale 和 offset），换算后需要在数据类型的范围内。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SimulationHint {
+    /// 变化方式（默认 sine）
+    #[serde(default)]
+    pub kind: SimulationKind,
+    /// 下限（默认0）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// 上限（默认100，toggle 默认1）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// sine 的周期和 toggle 的切换间隔，单位秒（默认60）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period_s: Option<f64>,
+    /// constant 的值（默认为 min）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    /// random_walk 每次变化的最大幅度（默认为范围的5%）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+}
+
+impl SimulationHint {
+    /// 未配置 sim 的点位使用的参数：布尔点位为 toggle，其他为 0 到 100 之间的 sine
+    pub fn default_for(is_bool: bool) -> Self {
+        SimulationHint {
+            kind: if is_bool {
+                SimulationKind::Toggle
+            } else {
+                SimulationKind::Sine
+            },
+            min: None,
+            max: None,
+            period_s: None,
+            value: None,
+            step: None,
+        }
+    }
+
+    /// 下限
+    pub fn min(&self) -> f64 {
+        self.min.unwrap_or(0.0)
+    }
+
+    /// 上限
+    pub fn max(&self) -> f64 {
+        match (self.max, self.kind) {
+            (Some(max), _) => max,
+            (None, SimulationKind::Toggle) => 1.0,
+            (None, _) => 100.0,
+        }
+    }
+
+    /// 周期，单位秒
+    pub fn period_s(&self) -> f64 {
+        self.period_s.unwrap_or(60.0)
+    }
+
+    /// random_walk 每次变化的最大幅度
+    pub fn step(&self) -> f64 {
+        self.step.unwrap_or((self.max() - self.min()) * 0.05)
+    }
+
+    /// 检查参数是否合法：数值都是有限数，min 不大于 max，period_s 大于0，step 不为负数
+    pub fn validate(&self, point: &str) -> Result<(), Box<dyn Error>> {
+        let values = [self.min, self.max, self.period_s, self.value, self.step];
+        if values.iter().flatten().any(|v| !v.is_finite()) {
+            return Err(format!("点位 {} 的 sim 参数必须是有限数", point).into());
+        }
+        if self.min() > self.max() {
+            return Err(format!(
+                "点位 {} 的 sim.min {} 大于 sim.max {}",
+                point,
+                self.min(),
+                self.max()
+            )
+            .into());
+        }
+        if self.period_s() <= 0.0 {
+            return Err(format!("点位 {} 的 sim.period_s 必须大于0", point).into());
+        }
+        if self.step() < 0.0 {
+            return Err(format!("点位 {} 的 sim.step 不能为负数", point).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+
+use super::point::Point;
+use super::watchdog::WatchdogSettings;
+
+/// 从站配置
+///
+/// 在 `slave_ids` 中既可以直接写从站ID，也可以写成对象：
+///
+/// ```yaml
+/// slave_ids:
+///   - 1
+///   - { id: 7, name: "PCS-B", profile: sdm630 }
+///   - { id: 8, enabled: false }
+///   - { id: 9, watchdog: { address: 100, interval_ms: 2000 } }
+///   - { id: 10, sunspec: auto }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlaveConfig {
+    /// 从站ID（范围1-247）
+    pub id: u8,
+    /// 从站名称，用于日志和 MQTT 主题
+    pub name: Option<String>,
+    /// 该从站专用的点位表，可以是 templates 中的模板或内置点位表
+    pub profile: Option<String>,
+    /// 是否采集该从站（默认启用）
+    pub enabled: bool,
+    /// 通信看门狗，未配置时不写心跳
+    pub watchdog: Option<WatchdogSettings>,
+    /// SunSpec 自动发现，未配置时不发现
+    pub sunspec: Option<SunSpecMode>,
+    /// SunSpec 发现生成的点位，不写入配置文件；尚未发现或发现失败时为 None
+    pub discovered_points: Option<Vec<Point>>,
+}
+
+/// SunSpec 发现方式
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SunSpecMode {
+    /// 启动时读取设备的模型链，为支持的模型生成点位
+    Auto,
+}
+
+impl SlaveConfig {
+    /// 只有ID、没有名称的从站
+    pub fn new(id: u8) -> Self {
+        SlaveConfig {
+            id,
+            name: None,
+            profile: None,
+            enabled: true,
+            watchdog: None,
+            sunspec: None,
+            discovered_points: None,
+        }
+    }
+
+    /// 用于显示的名称，未配置名称时使用从站ID
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.id.to_string(),
+        }
+    }
+}
+
+impl From<u8> for SlaveConfig {
+    fn from(id: u8) -> Self {
+        SlaveConfig::new(id)
+    }
+}
+
+// 对象写法对应的结构
+#[derive(Deserialize, Serialize)]
+struct SlaveObject {
+    id: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    watchdog: Option<WatchdogSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sunspec: Option<SunSpecMode>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+impl Serialize for SlaveConfig {
+    // 只有ID时写成纯数字，保持配置文件简洁
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.name.is_none()
+            && self.profile.is_none()
+            && self.enabled
+            && self.watchdog.is_none()
+            && self.sunspec.is_none()
+        {
+            return serializer.serialize_u8(self.id);
+        }
+        SlaveObject {
+            id: self.id,
+            name: self.name.clone(),
+            profile: self.profile.clone(),
+            enabled: self.enabled,
+            watchdog: self.watchdog.clone(),
+            sunspec: self.sunspec,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SlaveConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SlaveVisitor;
+
+        impl<'de> Visitor<'de> for SlaveVisitor {
+            type Value = SlaveConfig;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("从站ID（整数）或 {id, name, profile, enabled, watchdog, sunspec} 对象")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<SlaveConfig, E> {
+                let id = u8::try_from(value)
+                    .map_err(|_| E::custom(format!("从站ID {} 超出范围", value)))?;
+                Ok(SlaveConfig::new(id))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<SlaveConfig, E> {
+                let id = u8::try_from(value)
+                    .map_err(|_| E::custom(format!("从站ID {} 超出范围", value)))?;
+                Ok(SlaveConfig::new(id))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SlaveConfig, A::Error> {
+                let object = SlaveObject::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(SlaveConfig {
+                    id: object.id,
+                    name: object.name,
+                    profile: object.profile,
+                    enabled: object.enabled,
+                    watchdog: object.watchdog,
+                    sunspec: object.sunspec,
+                    discovered_points: None,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(SlaveVisitor)
+    }
+}
+
+/// 检查名称能否安全地用在 MQTT 主题中
+///
+/// # 校验规则
+/// * 不能为空
+/// * 不能包含 `+`、`#`、`/` 以及空白字符
+pub fn check_topic_safe(kind: &str, name: &str) -> Result<(), Box<dyn Error>> {
+    if name.is_empty() {
+        return Err(format!("{}名称不能为空", kind).into());
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| matches!(c, '+' | '#' | '/') || c.is_whitespace())
+    {
+        return Err(format!(
+            "{}名称 \"{}\" 包含不能用于 MQTT 主题的字符 {:?}",
+            kind, name, c
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_ids_and_objects() {
+        let slaves: Vec<SlaveConfig> = serde_yaml::from_str(
+            "- 1\n- { id: 7, name: PCS-B, profile: sdm630 }\n- { id: 8, enabled: false }\n",
+        )
+        .unwrap();
+        assert_eq!(slaves[0], SlaveConfig::new(1));
+        assert_eq!(slaves[1].id, 7);
+        assert_eq!(slaves[1].name.as_deref(), Some("PCS-B"));
+        assert_eq!(slaves[1].profile.as_deref(), Some("sdm630"));
+        assert!(slaves[1].enabled);
+        assert!(!slaves[2].enabled);
+        assert_eq!(slaves[0].display_name(), "1");
+        assert_eq!(slaves[1].display_name(), "PCS-B");
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let error = serde_yaml::from_str::<Vec<SlaveConfig>>("- 300\n").unwrap_err();
+        assert!(error.to_string().contains("从站ID 300 超出范围"), "{}", error);
+        assert!(serde_yaml::from_str::<Vec<SlaveConfig>>("- -1\n").is_err());
+        assert!(serde_yaml::from_str::<Vec<SlaveConfig>>("- { name: x }\n").is_err());
+        assert!(serde_yaml::from_str::<Vec<SlaveConfig>>("- abc\n").is_err());
+    }
+
+    #[test]
+    fn serializes_plain_id_when_only_id_is_set() {
+        let named = SlaveConfig {
+            name: Some("PCS-B".to_string()),
+            ..SlaveConfig::new(7)
+        };
+        let yaml = serde_yaml::to_string(&vec![SlaveConfig::new(1), named.clone()]).unwrap();
+        assert_eq!(yaml, "- 1\n- id: 7\n  name: PCS-B\n");
+        let parsed: Vec<SlaveConfig> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, [SlaveConfig::new(1), named]);
+    }
+
+    #[test]
+    fn topic_safe_names() {
+        for name in ["PCS-B", "电表_1", "a.b"] {
+            check_topic_safe("从站", name).unwrap();
+        }
+        assert_eq!(check_topic_safe("从站", "").unwrap_err().to_string(), "从站名称不能为空");
+        for name in ["a/b", "a+b", "a#", "a b", "a\tb"] {
+            assert!(check_topic_safe("从站", name).is_err(), "{}", name);
+        }
+        assert_eq!(
+            check_topic_safe("网关", "a/b").unwrap_err().to_string(),
+            "网关名称 \"a/b\" 包含不能用于 MQTT 主题的字符 '/'"
+        );
+    }
+}
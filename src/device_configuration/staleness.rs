@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// 过期数据检测配置，对应配置文件中的 `staleness:` 段
+///
+/// ```yaml
+/// staleness:
+///   multiplier: 3
+/// ```
+///
+/// 点位超过 采集周期 × multiplier 没有读到新值时，质量变为 stale。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct StalenessSettings {
+    /// 采集周期的倍数（默认3），超过该时间没有新值的点位视为过期
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+}
+
+impl Default for StalenessSettings {
+    fn default() -> Self {
+        StalenessSettings {
+            multiplier: default_multiplier(),
+        }
+    }
+}
+
+fn default_multiplier() -> f64 {
+    3.0
+}
+
+impl StalenessSettings {
+    /// 检查过期数据检测配置是否合法
+    ///
+    /// # 校验规则
+    /// * multiplier 不能小于1
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !(self.multiplier.is_finite() && self.multiplier >= 1.0) {
+            return Err(format!("staleness.multiplier 不能小于1，当前为 {}", self.multiplier).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use crate::mqtt::topic::{Placeholder, TopicTemplate};
+
+/// 本地存储配置，对应配置文件中的 `storage:` 段
+///
+/// ```yaml
+/// storage:
+///   path: /var/lib/ems/readings.db
+///   retention_days: 30
+///   max_size_mb: 512
+///   flush_interval_ms: 1000
+///   forward: true
+///   replay:
+///     mode: backfill
+///     max_messages_per_second: 20
+/// ```
+///
+/// 配置后每个采集周期的数据都写入 SQLite 数据库，断电重启后不会丢失。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct StorageSettings {
+    /// 数据库文件路径，不存在时自动创建
+    pub path: String,
+    /// 保留天数，超过的数据定期删除，未配置时不按时间删除
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u32>,
+    /// 数据库大小上限（MB），超出时从最早的数据开始删除，未配置时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    /// 写入间隔（毫秒，默认1000），期间的采集数据在一个事务中写入；为0时每个采集周期立即写入
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// 为 true 时记录每条数据是否已通过 MQTT 发出，没有确认送达的数据（断开、限速合并、发送队列已满、死信等）标记为未发布，供恢复后补发
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub forward: bool,
+    /// MQTT 恢复连接后补发未发布的数据，需要同时设置 forward；未配置时不补发
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay: Option<ReplaySettings>,
+}
+
+/// 补发的目标主题
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayMode {
+    /// 发布到单独的补发主题（replay.topic_template），与实时数据分开
+    #[default]
+    Backfill,
+    /// 发布到实时数据的主题，消息中的 timestamp 为原来的采集时间
+    Original,
+}
+
+/// 历史数据补发配置，对应 `storage.replay:` 段
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ReplaySettings {
+    /// 补发到补发主题还是实时数据的主题（默认 backfill）
+    #[serde(default)]
+    pub mode: ReplayMode,
+    /// 补发主题模板（默认 "{prefix}/backfill/{gateway}/{slave}"），可用的占位符与数据主题相同
+    #[serde(default = "default_replay_topic_template")]
+    pub topic_template: String,
+    /// 每批从数据库取出的行数（默认500），一批发出后在一个事务中标记为已发布
+    #[serde(default = "default_batch_rows")]
+    pub batch_rows: u32,
+    /// 补发消息的速率上限（条/秒，默认10），与实时数据的发布限速分开计算
+    #[serde(default = "default_replay_messages_per_second")]
+    pub max_messages_per_second: f64,
+}
+
+fn default_replay_topic_template() -> String {
+    "{prefix}/backfill/{gateway}/{slave}".to_string()
+}
+
+fn default_batch_rows() -> u32 {
+    500
+}
+
+fn default_replay_messages_per_second() -> f64 {
+    10.0
+}
+
+impl ReplaySettings {
+    /// 检查补发配置是否合法
+    ///
+    /// # 校验规则
+    /// * topic_template 是合法的数据主题模板
+    /// * batch_rows 和 max_messages_per_second 必须大于0
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        TopicTemplate::parse("storage.replay.topic_template", &self.topic_template, Placeholder::DATA)?;
+        if self.batch_rows == 0 {
+            return Err("storage.replay.batch_rows 必须大于0".into());
+        }
+        if !(self.max_messages_per_second > 0.0 && self.max_messages_per_second.is_finite()) {
+            return Err(format!(
+                "storage.replay.max_messages_per_second 必须大于0，当前为 {}",
+                self.max_messages_per_second
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn default_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl StorageSettings {
+    /// 检查存储配置是否合法
+    ///
+    /// # 校验规则
+    /// * path 不能为空
+    /// * retention_days 和 max_size_mb 配置时必须大于0
+    /// * 配置 replay 时需要设置 forward，且 replay 配置合法
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.path.trim().is_empty() {
+            return Err("storage.path 不能为空".into());
+        }
+        if self.retention_days == Some(0) {
+            return Err("storage.retention_days 必须大于0".into());
+        }
+        if self.max_size_mb == Some(0) {
+            return Err("storage.max_size_mb 必须大于0".into());
+        }
+        if let Some(replay) = &self.replay {
+            if !self.forward {
+                return Err("storage.replay 需要同时设置 storage.forward: true".into());
+            }
+            replay.validate()?;
+        }
+        Ok(())
+    }
+}
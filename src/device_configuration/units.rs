@@ -0,0 +1,343 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+/// 物理量种类，`target_units` 按种类指定换算后的单位
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantity {
+    /// 有功功率：mW、W、kW、MW
+    Power,
+    /// 电压：mV、V、kV
+    Voltage,
+    /// 电流：mA、A、kA
+    Current,
+    /// 温度：°C、°F、K（也可写作 ℃、℉）
+    Temperature,
+    /// 电能：Wh、kWh、MWh、J、kJ、MJ
+    Energy,
+    /// 频率：mHz、Hz、kHz
+    Frequency,
+    /// 百分比：%、‰
+    Percent,
+}
+
+impl Quantity {
+    /// 配置中使用的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quantity::Power => "power",
+            Quantity::Voltage => "voltage",
+            Quantity::Current => "current",
+            Quantity::Temperature => "temperature",
+            Quantity::Energy => "energy",
+            Quantity::Frequency => "frequency",
+            Quantity::Percent => "percent",
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 单位换算到同种类基准单位的方式：基准值 = (值 + before) * multiply / divide
+// 分别保存乘数和除数，W→kW 这类换算用除法，避免 0.001 这样的系数带来的误差
+struct UnitDef {
+    symbol: &'static str,
+    quantity: Quantity,
+    before: f64,
+    multiply: f64,
+    divide: f64,
+}
+
+const fn unit(symbol: &'static str, quantity: Quantity, multiply: f64, divide: f64) -> UnitDef {
+    UnitDef {
+        symbol,
+        quantity,
+        before: 0.0,
+        multiply,
+        divide,
+    }
+}
+
+// 内置单位表；温度以 °C 为基准
+const UNITS: &[UnitDef] = &[
+    unit("mW", Quantity::Power, 1.0, 1000.0),
+    unit("W", Quantity::Power, 1.0, 1.0),
+    unit("kW", Quantity::Power, 1000.0, 1.0),
+    unit("MW", Quantity::Power, 1_000_000.0, 1.0),
+    unit("mV", Quantity::Voltage, 1.0, 1000.0),
+    unit("V", Quantity::Voltage, 1.0, 1.0),
+    unit("kV", Quantity::Voltage, 1000.0, 1.0),
+    unit("mA", Quantity::Current, 1.0, 1000.0),
+    unit("A", Quantity::Current, 1.0, 1.0),
+    unit("kA", Quantity::Current, 1000.0, 1.0),
+    unit("°C", Quantity::Temperature, 1.0, 1.0),
+    unit("℃", Quantity::Temperature, 1.0, 1.0),
+    UnitDef {
+        symbol: "°F",
+        quantity: Quantity::Temperature,
+        before: -32.0,
+        multiply: 5.0,
+        divide: 9.0,
+    },
+    UnitDef {
+        symbol: "℉",
+        quantity: Quantity::Temperature,
+        before: -32.0,
+        multiply: 5.0,
+        divide: 9.0,
+    },
+    UnitDef {
+        symbol: "K",
+        quantity: Quantity::Temperature,
+        before: -273.15,
+        multiply: 1.0,
+        divide: 1.0,
+    },
+    unit("Wh", Quantity::Energy, 1.0, 1.0),
+    unit("kWh", Quantity::Energy, 1000.0, 1.0),
+    unit("MWh", Quantity::Energy, 1_000_000.0, 1.0),
+    unit("J", Quantity::Energy, 1.0, 3600.0),
+    unit("kJ", Quantity::Energy, 1000.0, 3600.0),
+    unit("MJ", Quantity::Energy, 1_000_000.0, 3600.0),
+    unit("mHz", Quantity::Frequency, 1.0, 1000.0),
+    unit("Hz", Quantity::Frequency, 1.0, 1.0),
+    unit("kHz", Quantity::Frequency, 1000.0, 1.0),
+    unit("%", Quantity::Percent, 1.0, 1.0),
+    unit("‰", Quantity::Percent, 1.0, 10.0),
+];
+
+fn lookup(symbol: &str) -> Option<&'static UnitDef> {
+    UNITS.iter().find(|unit| unit.symbol == symbol)
+}
+
+/// 单位所属的物理量种类，不在内置单位表中时返回 None
+pub fn quantity_of(symbol: &str) -> Option<Quantity> {
+    lookup(symbol).map(|unit| unit.quantity)
+}
+
+/// 单位换算：换算后的值 = (值 + before) * multiply / divide + after
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    before: f64,
+    multiply: f64,
+    divide: f64,
+    after: f64,
+}
+
+impl Conversion {
+    /// 由 `source` 单位换算到 `target` 单位
+    ///
+    /// # 错误
+    /// 任一单位不在内置单位表中，或两者不是同一种物理量（例如 kW 换算到 kWh）
+    pub fn between(source: &str, target: &str) -> Result<Self, Box<dyn Error>> {
+        let from = lookup(source).ok_or_else(|| format!("未知的单位 \"{}\"", source))?;
+        let to = lookup(target).ok_or_else(|| format!("未知的单位 \"{}\"", target))?;
+        if from.quantity != to.quantity {
+            return Err(format!(
+                "单位 {}（{}）不能换算为 {}（{}）",
+                source, from.quantity, target, to.quantity
+            )
+            .into());
+        }
+        // 基准值 = (值 + before_s) * m_s / d_s，目标值 = 基准值 * d_t / m_t - before_t
+        Ok(Conversion {
+            before: from.before,
+            multiply: from.multiply * to.divide,
+            divide: from.divide * to.multiply,
+            after: -to.before,
+        })
+    }
+
+    /// 换算一个值
+    pub fn apply(&self, value: f64) -> f64 {
+        (value + self.before) * self.multiply / self.divide + self.after
+    }
+
+    /// 反向换算，由目标单位的值计算源单位的值
+    pub fn invert(&self, value: f64) -> f64 {
+        (value - self.after) * self.divide / self.multiply - self.before
+    }
+
+    /// 换算对差值（例如步长）的影响系数
+    pub fn ratio(&self) -> f64 {
+        self.multiply / self.divide
+    }
+}
+
+/// 检查 `target_units` 中的单位都在内置单位表中，且属于对应的物理量
+///
+/// # 参数说明
+/// * `field` - 配置项名称，用于错误信息，例如 "采集组 fast 的 target_units"
+pub fn validate_target_units(
+    field: &str,
+    target_units: &BTreeMap<Quantity, String>,
+) -> Result<(), Box<dyn Error>> {
+    for (quantity, symbol) in target_units {
+        match quantity_of(symbol) {
+            Some(actual) if actual == *quantity => {}
+            Some(actual) => {
+                return Err(format!(
+                    "{}.{} 为 {}，但 {} 是 {} 的单位",
+                    field, quantity, symbol, symbol, actual
+                )
+                .into());
+            }
+            None => {
+                return Err(format!("{}.{} 为未知的单位 \"{}\"", field, quantity, symbol).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+
+    fn convert(source: &str, target: &str, value: f64) -> f64 {
+        Conversion::between(source, target).unwrap().apply(value)
+    }
+
+    fn assert_close(actual: f64, expected: f64, what: &str) {
+        let tolerance = 1e-9 * expected.abs().max(1.0);
+        assert!((actual - expected).abs() < tolerance, "{}: {} != {}", what, actual, expected);
+    }
+
+    #[test]
+    fn converts_each_pair() {
+        let cases: &[(&str, &str, f64, f64)] = &[
+            ("kW", "W", 1.5, 1500.0),
+            ("W", "kW", 1500.0, 1.5),
+            ("mW", "W", 250.0, 0.25),
+            ("MW", "kW", 0.2, 200.0),
+            ("W", "MW", 3_000_000.0, 3.0),
+            ("mV", "V", 2300.0, 2.3),
+            ("V", "kV", 10_500.0, 10.5),
+            ("kV", "mV", 0.4, 400_000.0),
+            ("mA", "A", 50.0, 0.05),
+            ("A", "kA", 1200.0, 1.2),
+            ("Wh", "kWh", 1234.0, 1.234),
+            ("kWh", "MWh", 2500.0, 2.5),
+            ("kWh", "MJ", 1.0, 3.6),
+            ("J", "Wh", 3600.0, 1.0),
+            ("kJ", "kWh", 7200.0, 2.0),
+            ("MWh", "kWh", 0.1, 100.0),
+            ("mHz", "Hz", 50_020.0, 50.02),
+            ("kHz", "Hz", 0.05, 50.0),
+            ("%", "‰", 1.5, 15.0),
+            ("‰", "%", 998.0, 99.8),
+            // 温度是仿射换算，需要加上偏移
+            ("°C", "°F", 100.0, 212.0),
+            ("°C", "°F", -40.0, -40.0),
+            ("°F", "°C", 32.0, 0.0),
+            ("°F", "°C", 98.6, 37.0),
+            ("°C", "K", 0.0, 273.15),
+            ("K", "°C", 300.0, 26.85),
+            ("K", "°F", 273.15, 32.0),
+            ("°F", "K", 212.0, 373.15),
+            ("℃", "°F", 20.0, 68.0),
+            ("℉", "℃", 50.0, 10.0),
+            ("°C", "℃", 25.0, 25.0),
+        ];
+        for (source, target, value, expected) in cases {
+            let what = format!("{} {} → {}", value, source, target);
+            assert_close(convert(source, target, *value), *expected, &what);
+            // 反向换算得到原值
+            let conversion = Conversion::between(source, target).unwrap();
+            assert_close(conversion.invert(*expected), *value, &what);
+        }
+    }
+
+    #[test]
+    fn every_pair_of_the_same_quantity_round_trips() {
+        for from in UNITS {
+            for to in UNITS.iter().filter(|to| to.quantity == from.quantity) {
+                let forward = Conversion::between(from.symbol, to.symbol).unwrap();
+                let back = Conversion::between(to.symbol, from.symbol).unwrap();
+                for value in [-12.5, 0.0, 1.0, 480.25] {
+                    let what = format!("{} {} → {} → {}", value, from.symbol, to.symbol, from.symbol);
+                    assert_close(back.apply(forward.apply(value)), value, &what);
+                    assert_close(forward.invert(forward.apply(value)), value, &what);
+                }
+                assert_close(forward.ratio() * back.ratio(), 1.0, from.symbol);
+            }
+        }
+    }
+
+    #[test]
+    fn ratio_ignores_offsets() {
+        assert_close(Conversion::between("°C", "°F").unwrap().ratio(), 1.8, "°C → °F");
+        assert_close(Conversion::between("K", "°C").unwrap().ratio(), 1.0, "K → °C");
+        assert_close(Conversion::between("W", "kW").unwrap().ratio(), 0.001, "W → kW");
+    }
+
+    #[test]
+    fn incompatible_and_unknown_units_are_errors() {
+        let error = |source: &str, target: &str| Conversion::between(source, target).unwrap_err().to_string();
+        assert_eq!(error("kW", "kWh"), "单位 kW（power）不能换算为 kWh（energy）");
+        assert_eq!(error("°C", "%"), "单位 °C（temperature）不能换算为 %（percent）");
+        assert_eq!(error("kVA", "kW"), "未知的单位 \"kVA\"");
+        assert_eq!(error("W", "hp"), "未知的单位 \"hp\"");
+        // 单位区分大小写
+        assert_eq!(error("kw", "W"), "未知的单位 \"kw\"");
+        for from in UNITS {
+            for to in UNITS.iter().filter(|to| to.quantity != from.quantity) {
+                assert!(Conversion::between(from.symbol, to.symbol).is_err(), "{} → {}", from.symbol, to.symbol);
+            }
+        }
+
+        let units = |quantity: Quantity, symbol: &str| BTreeMap::from([(quantity, symbol.to_string())]);
+        assert!(validate_target_units("target_units", &units(Quantity::Temperature, "K")).is_ok());
+        assert_eq!(
+            validate_target_units("target_units", &units(Quantity::Power, "kWh")).unwrap_err().to_string(),
+            "target_units.power 为 kWh，但 kWh 是 energy 的单位"
+        );
+        assert_eq!(
+            validate_target_units("target_units", &units(Quantity::Voltage, "volt")).unwrap_err().to_string(),
+            "target_units.voltage 为未知的单位 \"volt\""
+        );
+    }
+
+    #[test]
+    fn points_are_converted_to_group_or_global_target_units() {
+        let yaml = "version: 2\ntarget_units: { power: kW, temperature: °F }\npoll_groups:\n  fast: { interval_ms: 1000, target_units: { power: MW } }\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n    points:\n      - { name: power, address: 0, source_unit: W }\n      - { name: fast_power, address: 1, source_unit: W, group: fast }\n      - { name: temperature, address: 2, source_unit: °C, scale: 0.1 }\n      - { name: frequency, address: 3, source_unit: Hz }\n";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+        let gateway = &config.gateways[0];
+        let points = config.effective_points(gateway, &gateway.slave_ids[0]).unwrap();
+        let converted: Vec<(&str, Option<&str>, f64)> = points
+            .iter()
+            .map(|p| (p.name.as_str(), p.unit.as_deref(), p.convert(1000.0)))
+            .collect();
+        assert_eq!(
+            converted,
+            [
+                ("power", Some("kW"), 1.0),
+                ("fast_power", Some("MW"), 0.001),
+                ("temperature", Some("°F"), 1832.0),
+                // 没有配置目标单位时按 source_unit 发布
+                ("frequency", Some("Hz"), 1000.0),
+            ]
+        );
+        assert!(points.iter().all(|p| p.source_unit.is_none()));
+
+        for (field, error) in [
+            ("target_units: { power: kWh }\n", "target_units.power 为 kWh，但 kWh 是 energy 的单位"),
+            ("target_units: { energy: kWhr }\n", "target_units.energy 为未知的单位 \"kWhr\""),
+        ] {
+            let yaml = yaml.replace("target_units: { power: kW, temperature: °F }\n", field);
+            let config: Config = serde_yaml::from_str(&yaml).unwrap();
+            assert_eq!(config.validate().unwrap_err().to_string(), error);
+        }
+        let yaml = yaml.replace("source_unit: Hz", "source_unit: rpm");
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("source_unit \"rpm\" 不是已知的单位"));
+    }
+}
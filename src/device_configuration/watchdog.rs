@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::Duration;
+
+/// 心跳值的变化方式
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogPattern {
+    /// 在0和 value 之间交替（默认）
+    #[default]
+    Toggle,
+    /// 从 value 开始每次加1，超过65535后回到0
+    Increment,
+    /// 每次都写 value
+    Constant,
+}
+
+/// 通信看门狗配置，对应从站配置中的 `watchdog:`
+///
+/// ```yaml
+/// slave_ids:
+///   - id: 1
+///     name: PCS-A
+///     watchdog: { address: 100, interval_ms: 2000, pattern: toggle }
+///   - id: 2
+///     watchdog: { address: 5, function_code: 5, interval_ms: 1000 }
+/// ```
+///
+/// 设备在一段时间内收不到心跳时会进入安全状态，采集任务按 `interval_ms` 定期写入心跳寄存器，优先于采集执行。
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct WatchdogSettings {
+    /// 心跳寄存器（或线圈）地址
+    pub address: u16,
+    /// 写入功能码：5 写线圈，6（默认）或 16 写一个保持寄存器
+    #[serde(default = "default_function_code")]
+    pub function_code: u8,
+    /// 写入间隔，单位毫秒（默认5000）
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// 心跳值的变化方式（默认 toggle）
+    #[serde(default)]
+    pub pattern: WatchdogPattern,
+    /// toggle 的非零值、increment 的初始值、constant 的值（默认1）；线圈只能为0或1
+    #[serde(default = "default_value")]
+    pub value: u16,
+    /// 连续失败多少次后产生看门狗报警（默认3）
+    #[serde(default = "default_max_missed")]
+    pub max_missed: u32,
+}
+
+fn default_function_code() -> u8 {
+    0x06
+}
+
+fn default_interval_ms() -> u64 {
+    5000
+}
+
+fn default_value() -> u16 {
+    1
+}
+
+fn default_max_missed() -> u32 {
+    3
+}
+
+impl WatchdogSettings {
+    /// 写入间隔
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    /// 第 `count` 次（从0开始）写入的值
+    pub fn value_at(&self, count: u64) -> u16 {
+        match self.pattern {
+            WatchdogPattern::Toggle if count.is_multiple_of(2) => self.value,
+            WatchdogPattern::Toggle => 0,
+            WatchdogPattern::Increment => self.value.wrapping_add(count as u16),
+            WatchdogPattern::Constant => self.value,
+        }
+    }
+
+    /// 检查看门狗配置是否合法
+    ///
+    /// # 校验规则
+    /// * function_code 为 5、6 或 16
+    /// * interval_ms 和 max_missed 必须大于0
+    /// * 线圈的 value 只能为0或1，toggle 的 value 不能为0
+    pub fn validate(&self, location: &str) -> Result<(), Box<dyn Error>> {
+        if !matches!(self.function_code, 0x05 | 0x06 | 0x10) {
+            return Err(format!(
+                "{} 的 watchdog.function_code 必须是 5、6 或 16，当前为 {}",
+                location, self.function_code
+            )
+            .into());
+        }
+        if self.interval_ms == 0 {
+            return Err(format!("{} 的 watchdog.interval_ms 必须大于0", location).into());
+        }
+        if self.max_missed == 0 {
+            return Err(format!("{} 的 watchdog.max_missed 必须大于0", location).into());
+        }
+        if self.function_code == 0x05
+            && (self.value > 1 || self.pattern == WatchdogPattern::Increment)
+        {
+            return Err(format!(
+                "{} 的 watchdog 写线圈时 value 只能为0或1，且不能使用 increment",
+                location
+            )
+            .into());
+        }
+        if self.pattern == WatchdogPattern::Toggle && self.value == 0 {
+            return Err(format!("{} 的 watchdog 为 toggle 时 value 不能为0", location).into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info};
+
+use crate::device_configuration::computed::{PointReference, COMPUTED_GATEWAY};
+use crate::device_configuration::energy::{EnergyDirection, EnergyUnit};
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::PublishOptions;
+use crate::modbus::reading::{Quality, Reading};
+
+const SECONDS_PER_HOUR: f64 = 3600.0;
+
+// 一个电量点位
+struct Meter {
+    name: String,
+    source: PointReference,
+    watts: f64,
+    unit: EnergyUnit,
+    direction: EnergyDirection,
+    max_gap: Duration,
+    // 上一次正常读数的采集时间和功率（W）
+    last: Option<(SystemTime, f64)>,
+}
+
+/// 由功率点位累计得到的电量点位
+///
+/// # 说明
+/// * 功率点位相邻两次质量为 good 的读数之间按梯形累计电量（两次功率的平均值乘以时间间隔）
+/// * 读数质量不是 good、间隔超过 `max_gap_ms` 或采集时间倒退时，这一段不累计，从下一次读数重新开始
+/// * 每次收到功率点位的正常读数后输出累计值，时间与功率读数相同，属于网关 [`COMPUTED_GATEWAY`]、从站ID 0
+/// * 累计值以 Wh 为单位保存在状态文件中，创建时读取，之后由 [`run`] 定期保存；
+///   从配置中删除的点位的累计值仍保留在文件中，重新加入后继续累计
+pub struct EnergyMeters {
+    meters: Vec<Meter>,
+    // 各点位的累计值，单位 Wh
+    totals: BTreeMap<String, f64>,
+    state_file: Option<PathBuf>,
+    save_interval: Duration,
+    publish: PublishOptions,
+    // 累计值是否有未保存的变化
+    dirty: bool,
+}
+
+/// 可在采集任务和保存任务间共享的电量点位
+pub type SharedEnergy = Arc<Mutex<EnergyMeters>>;
+
+impl EnergyMeters {
+    /// 按配置创建，配置了 energy 段时从状态文件读取已有的累计值
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let mut meters = EnergyMeters {
+            meters: Vec::new(),
+            totals: BTreeMap::new(),
+            state_file: None,
+            save_interval: Duration::ZERO,
+            publish: PublishOptions::default(),
+            dirty: false,
+        };
+        if let Some(settings) = &config.energy {
+            let path = Path::new(&settings.state_file);
+            meters.totals = load_state(path)
+                .map_err(|e| format!("无法读取电量状态文件 {}: {}", path.display(), e))?;
+        }
+        meters.apply(config)?;
+        Ok(meters)
+    }
+
+    /// 应用新的配置，已累计的电量保留；功率点位不变的电量点位继续从上一次读数累计
+    pub fn apply(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        let mut meters = Vec::new();
+        if let Some(settings) = &config.energy {
+            for point in &settings.points {
+                let (source, max_gap) = point.resolve(config)?;
+                let last = self
+                    .meters
+                    .iter()
+                    .find(|m| m.name == point.name && m.source == source)
+                    .and_then(|m| m.last);
+                meters.push(Meter {
+                    name: point.name.clone(),
+                    source,
+                    watts: point.power_unit.watts(),
+                    unit: point.unit,
+                    direction: point.direction,
+                    max_gap,
+                    last,
+                });
+            }
+        }
+        self.meters = meters;
+        self.state_file = config.energy.as_ref().map(|e| PathBuf::from(&e.state_file));
+        self.save_interval = config.energy.as_ref().map_or(Duration::ZERO, |e| e.save_interval());
+        self.publish = config.default_publish_options();
+        Ok(())
+    }
+
+    /// 保存累计值的间隔
+    pub fn save_interval(&self) -> Duration {
+        self.save_interval
+    }
+
+    /// 累计一批采集数据中的功率，返回更新后的电量点位
+    pub fn process(&mut self, readings: &[Reading]) -> Vec<Reading> {
+        let mut results = Vec::new();
+        for reading in readings {
+            for meter in &mut self.meters {
+                if meter.source.gateway != reading.gateway
+                    || meter.source.slave_id != reading.slave_id
+                    || meter.source.point != reading.point
+                {
+                    continue;
+                }
+                if reading.quality != Quality::Good || !reading.value.is_finite() {
+                    meter.last = None;
+                    continue;
+                }
+                let power = meter.direction.apply(reading.value * meter.watts);
+                let total = self.totals.entry(meter.name.clone()).or_default();
+                if let Some((time, previous)) = meter.last {
+                    match reading.timestamp.duration_since(time) {
+                        Ok(elapsed) if elapsed <= meter.max_gap => {
+                            *total += (previous + power) / 2.0 * elapsed.as_secs_f64() / SECONDS_PER_HOUR;
+                            self.dirty = true;
+                        }
+                        Ok(elapsed) => {
+                            debug!(
+                                point = %meter.name,
+                                gap_ms = elapsed.as_millis() as u64,
+                                "功率读数间隔过长，不累计这一段电量"
+                            );
+                        }
+                        // 采集时间倒退的读数丢弃，不更新上一次读数
+                        Err(_) => continue,
+                    }
+                }
+                meter.last = Some((reading.timestamp, power));
+                results.push(Reading {
+                    gateway: COMPUTED_GATEWAY.to_string(),
+                    gateway_name: None,
+                    slave_id: 0,
+                    slave_name: None,
+                    point: meter.name.clone(),
+                    group: None,
+                    value: *total / meter.unit.watt_hours(),
+                    text: None,
+                    raw: Vec::new(),
+                    unit: Some(meter.unit.as_str().to_string()),
+                    timestamp: reading.timestamp,
+                    quality: Quality::Good,
+                    publish: self.publish,
+                });
+            }
+        }
+        results
+    }
+
+    /// 累计值有变化时写入状态文件，先写临时文件再改名，写入中途断电不会损坏原文件
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(&self.totals)?)?;
+        fs::rename(&temporary, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+// 读取状态文件，文件不存在时返回空的累计值
+fn load_state(path: &Path) -> Result<BTreeMap<String, f64>, Box<dyn Error>> {
+    match fs::read(path) {
+        Ok(content) => {
+            let totals: BTreeMap<String, f64> = serde_json::from_slice(&content)?;
+            info!(path = %path.display(), points = totals.len(), "读取已累计的电量");
+            Ok(totals)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 按配置的间隔定期保存累计值
+pub async fn run(meters: SharedEnergy) {
+    loop {
+        let interval = meters.lock().unwrap_or_else(|e| e.into_inner()).save_interval();
+        // 热加载后去掉了 energy 段时不再需要保存，仍按默认间隔检查
+        tokio::time::sleep(interval.max(Duration::from_secs(1))).await;
+        let saved = meters.lock().unwrap_or_else(|e| e.into_inner()).save();
+        if let Err(e) = saved {
+            error!(error = %e, "保存电量累计值失败");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at, reading};
+    use std::f64::consts::TAU;
+
+    const START: u64 = 1_700_000_000_000;
+
+    fn config(state_file: &Path, points: &str) -> Config {
+        let yaml = format!(
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    name: PCS-A\n    poll_interval_ms: 1000\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: power, address: 0 }}\nenergy:\n  state_file: {}\n  points:\n{}",
+            state_file.display(),
+            points
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    fn power(value: f64, seconds: f64) -> Reading {
+        Reading {
+            timestamp: at(START) + Duration::from_secs_f64(seconds),
+            ..reading("10.0.0.1:502", 1, "power", value)
+        }
+    }
+
+    // 按 step 秒的间隔采样功率曲线 profile(秒)，返回每个电量点位最后输出的值
+    fn integrate(
+        meters: &mut EnergyMeters,
+        seconds: u32,
+        step: u32,
+        profile: impl Fn(f64) -> f64,
+    ) -> BTreeMap<String, f64> {
+        let mut latest = BTreeMap::new();
+        for t in (0..=seconds).step_by(step as usize) {
+            let t = t as f64;
+            for output in meters.process(&[power(profile(t), t)]) {
+                latest.insert(output.point, output.value);
+            }
+        }
+        latest
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn synthetic_profiles_integrate_to_expected_energy() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("energy.json");
+        let config = config(&state, "    - { name: energy, source: meter.power }\n");
+        // 恒定 10kW 一小时为 10kWh
+        let mut meters = EnergyMeters::new(&config).unwrap();
+        assert_close(integrate(&mut meters, 3600, 1, |_| 10.0)["energy"], 10.0, 1e-9);
+
+        // 0 到 100kW 的线性斜坡，梯形累计没有误差
+        let mut meters = EnergyMeters::new(&config).unwrap();
+        assert_close(integrate(&mut meters, 3600, 1, |t| t / 36.0)["energy"], 50.0, 1e-9);
+
+        // 50kW 上下摆动 50kW 的正弦，一个周期的平均为 50kW
+        let mut meters = EnergyMeters::new(&config).unwrap();
+        let sine = |t: f64| 50.0 + 50.0 * (TAU * t / 3600.0).sin();
+        assert_close(integrate(&mut meters, 3600, 2, sine)["energy"], 50.0, 0.01);
+
+        let output = meters.process(&[power(50.0, 3601.0)]).remove(0);
+        assert_eq!(
+            (output.gateway.as_str(), output.slave_id, output.unit.as_deref()),
+            (COMPUTED_GATEWAY, 0, Some("kWh"))
+        );
+        assert_eq!((output.timestamp, output.quality), (at(START + 3_601_000), Quality::Good));
+    }
+
+    #[test]
+    fn bidirectional_power_is_split_by_direction() {
+        let dir = tempfile::tempdir().unwrap();
+        let points = "    - { name: net, source: meter.power, power_unit: W, unit: Wh }\n    - { name: import, source: meter.power, power_unit: W, unit: Wh, direction: import }\n    - { name: export, source: meter.power, power_unit: W, unit: Wh, direction: export }\n";
+        let mut meters = EnergyMeters::new(&config(&dir.path().join("energy.json"), points)).unwrap();
+        // 前半小时取电 1000W，后半小时送电 500W
+        let totals = integrate(&mut meters, 3600, 1, |t| if t < 1800.0 { 1000.0 } else { -500.0 });
+        // 功率反向的那一秒按两端平均累计，误差不超过 1000W × 1s
+        let tolerance = 1000.0 / 3600.0;
+        assert_close(totals["net"], 250.0, tolerance);
+        assert_close(totals["import"], 500.0, tolerance);
+        assert_close(totals["export"], 250.0, tolerance);
+        assert_close(totals["import"] - totals["export"], totals["net"], tolerance);
+    }
+
+    #[test]
+    fn gaps_bad_readings_and_time_going_back_are_not_integrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let points = "    - { name: energy, source: \"{PCS-A.power}\", unit: Wh, max_gap_ms: 10000 }\n";
+        let mut meters = EnergyMeters::new(&config(&dir.path().join("energy.json"), points)).unwrap();
+        let total = |meters: &mut EnergyMeters, value: f64, seconds: f64| {
+            meters.process(&[power(value, seconds)]).pop().map(|r| r.value)
+        };
+
+        // 第一次读数没有上一次读数，累计值为0
+        assert_eq!(total(&mut meters, 3.6, 0.0), Some(0.0));
+        assert_eq!(total(&mut meters, 3.6, 10.0), Some(10.0));
+        // 间隔超过 max_gap_ms 的一段不累计
+        assert_eq!(total(&mut meters, 3.6, 20.001), Some(10.0));
+        assert_eq!(total(&mut meters, 3.6, 30.001), Some(20.0));
+        // 质量不是 good 时不输出，下一段从下一次正常读数重新开始
+        let mut bad = power(3.6, 35.0);
+        bad.quality = Quality::Stale;
+        assert!(meters.process(&[bad]).is_empty());
+        assert_eq!(total(&mut meters, 3.6, 40.0), Some(20.0));
+        assert!(meters.process(&[power(f64::NAN, 45.0)]).is_empty());
+        assert_eq!(total(&mut meters, 3.6, 50.0), Some(20.0));
+        // 采集时间倒退的读数丢弃
+        assert!(meters.process(&[power(3.6, 45.0)]).is_empty());
+        assert_eq!(total(&mut meters, 3.6, 60.0), Some(30.0));
+        // 其他点位的读数不参与累计
+        assert!(meters.process(&[reading("10.0.0.1:502", 2, "power", 1.0)]).is_empty());
+    }
+
+    #[test]
+    fn totals_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("energy.json");
+        let config = config(&state, "    - { name: energy, source: meter.power }\n");
+
+        let mut meters = EnergyMeters::new(&config).unwrap();
+        assert_close(integrate(&mut meters, 1800, 1, |_| 10.0)["energy"], 5.0, 1e-9);
+        meters.save().unwrap();
+        // 文件中以 Wh 为单位
+        let saved: BTreeMap<String, f64> = serde_json::from_slice(&fs::read(&state).unwrap()).unwrap();
+        assert_close(saved["energy"], 5000.0, 1e-6);
+        // 没有变化时不重写文件
+        fs::remove_file(&state).unwrap();
+        meters.save().unwrap();
+        assert!(!state.exists());
+        meters.dirty = true;
+        meters.save().unwrap();
+
+        // 重启后从文件中的累计值继续，重启前的最后一次读数不参与累计
+        let mut restarted = EnergyMeters::new(&config).unwrap();
+        let first = restarted.process(&[power(10.0, 1801.0)]).remove(0);
+        assert_close(first.value, 5.0, 1e-9);
+        let mut last = first.value;
+        for t in 1802..=3601 {
+            last = restarted.process(&[power(10.0, t as f64)]).remove(0).value;
+        }
+        assert_close(last, 10.0, 1e-9);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_saves_periodically_and_bad_state_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("energy.json");
+        let config = config(&state, "    - { name: energy, source: meter.power }\n");
+        let meters: SharedEnergy = Arc::new(Mutex::new(EnergyMeters::new(&config).unwrap()));
+        integrate(&mut meters.lock().unwrap(), 360, 1, |_| 10.0);
+        let saver = tokio::spawn(run(Arc::clone(&meters)));
+
+        tokio::time::sleep(Duration::from_secs(9)).await;
+        assert!(!state.exists());
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let saved: BTreeMap<String, f64> = serde_json::from_slice(&fs::read(&state).unwrap()).unwrap();
+        assert_close(saved["energy"], 1000.0, 1e-6);
+        saver.abort();
+
+        fs::write(&state, "not json").unwrap();
+        let error = EnergyMeters::new(&config).err().unwrap().to_string();
+        assert!(error.starts_with(&format!("无法读取电量状态文件 {}", state.display())), "{}", error);
+    }
+}
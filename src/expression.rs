@@ -0,0 +1,436 @@
+use std::error::Error;
+use std::fmt;
+
+/// 计算点位使用的四则运算表达式
+///
+/// # 语法
+/// * 数字、`+ - * /`、一元负号和括号，乘除优先于加减
+/// * 函数 `min(a, b, ...)`、`max(a, b, ...)`、`abs(x)`
+/// * 点位引用 `设备.点位`，由字母、数字、下划线、`.` 和 `:` 组成；
+///   包含 `-`、`/` 等其他字符的引用写在花括号中，例如 `{PCS-A.power}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    text: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Reference(String),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Min,
+    Max,
+    Abs,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "abs" => Some(Function::Abs),
+            _ => None,
+        }
+    }
+}
+
+/// 表达式求值失败的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// 引用的点位没有可用的值
+    Missing(String),
+    /// 除数为0
+    DivisionByZero,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Missing(name) => write!(f, "点位 {} 没有可用的值", name),
+            EvalError::DivisionByZero => f.write_str("除数为0"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+impl Expression {
+    /// 解析表达式，语法错误时返回包含出错位置的错误
+    pub fn parse(text: &str) -> Result<Expression, Box<dyn Error>> {
+        let mut parser = Parser {
+            text,
+            chars: text.char_indices().collect(),
+            position: 0,
+        };
+        let root = parser.expression()?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("多余的内容"));
+        }
+        Ok(Expression {
+            text: text.to_string(),
+            root,
+        })
+    }
+
+    /// 表达式中引用的点位，按首次出现的顺序，不重复
+    pub fn references(&self) -> Vec<String> {
+        let mut references = Vec::new();
+        collect_references(&self.root, &mut references);
+        references
+    }
+
+    /// 求值
+    ///
+    /// # 参数说明
+    /// * `lookup` - 按引用名称查找点位的当前值，没有可用的值时返回 None
+    pub fn evaluate<F>(&self, lookup: F) -> Result<f64, EvalError>
+    where
+        F: Fn(&str) -> Option<f64>,
+    {
+        evaluate(&self.root, &lookup)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn collect_references(node: &Node, references: &mut Vec<String>) {
+    match node {
+        Node::Number(_) => {}
+        Node::Reference(name) => {
+            if !references.contains(name) {
+                references.push(name.clone());
+            }
+        }
+        Node::Negate(inner) => collect_references(inner, references),
+        Node::Binary(_, left, right) => {
+            collect_references(left, references);
+            collect_references(right, references);
+        }
+        Node::Call(_, args) => {
+            for arg in args {
+                collect_references(arg, references);
+            }
+        }
+    }
+}
+
+fn evaluate<F>(node: &Node, lookup: &F) -> Result<f64, EvalError>
+where
+    F: Fn(&str) -> Option<f64>,
+{
+    Ok(match node {
+        Node::Number(value) => *value,
+        Node::Reference(name) => lookup(name).ok_or_else(|| EvalError::Missing(name.clone()))?,
+        Node::Negate(inner) => -evaluate(inner, lookup)?,
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, lookup)?;
+            let right = evaluate(right, lookup)?;
+            match operator {
+                Operator::Add => left + right,
+                Operator::Subtract => left - right,
+                Operator::Multiply => left * right,
+                Operator::Divide if right == 0.0 => return Err(EvalError::DivisionByZero),
+                Operator::Divide => left / right,
+            }
+        }
+        Node::Call(function, args) => {
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                values.push(evaluate(arg, lookup)?);
+            }
+            match function {
+                Function::Min => values.into_iter().fold(f64::INFINITY, f64::min),
+                Function::Max => values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                Function::Abs => values[0].abs(),
+            }
+        }
+    })
+}
+
+// 递归下降解析：表达式 = 项 {(+|-) 项}，项 = 因子 {(*|/) 因子}，因子 = -因子 | 数字 | 引用 | 函数调用 | (表达式)
+struct Parser<'a> {
+    text: &'a str,
+    chars: Vec<(usize, char)>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> Box<dyn Error> {
+        format!("表达式 \"{}\" 第 {} 个字符处{}", self.text, self.position + 1, reason).into()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).map(|(_, c)| *c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    // 跳过空白后如果下一个字符是 expected 则消耗它
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.term()?;
+        loop {
+            let operator = if self.eat('+') {
+                Operator::Add
+            } else if self.eat('-') {
+                Operator::Subtract
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(operator, Box::new(node), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut node = self.factor()?;
+        loop {
+            let operator = if self.eat('*') {
+                Operator::Multiply
+            } else if self.eat('/') {
+                Operator::Divide
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(operator, Box::new(node), Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Result<Node, Box<dyn Error>> {
+        if self.eat('-') {
+            return Ok(Node::Negate(Box::new(self.factor()?)));
+        }
+        if self.eat('(') {
+            let node = self.expression()?;
+            if !self.eat(')') {
+                return Err(self.error("缺少 )"));
+            }
+            return Ok(node);
+        }
+        if self.eat('{') {
+            let start = self.position;
+            while self.peek().is_some_and(|c| c != '}') {
+                self.position += 1;
+            }
+            if self.peek().is_none() {
+                return Err(self.error("缺少 }"));
+            }
+            let name = self.slice(start, self.position).trim().to_string();
+            self.position += 1;
+            if name.is_empty() {
+                return Err(self.error("花括号中缺少点位引用"));
+            }
+            return Ok(Node::Reference(name));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if is_name_char(c) => {
+                let start = self.position;
+                while self.peek().is_some_and(is_name_char) {
+                    self.position += 1;
+                }
+                let name = self.slice(start, self.position).to_string();
+                if self.eat('(') {
+                    return self.call(&name);
+                }
+                Ok(Node::Reference(name))
+            }
+            Some(_) => Err(self.error("需要数字、点位引用或 (")),
+            None => Err(self.error("表达式不完整")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Node, Box<dyn Error>> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.position += 1;
+        }
+        // 科学计数法，例如 1e-3
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.position += 1;
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.position += 1;
+            }
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.position += 1;
+            }
+        }
+        let text = self.slice(start, self.position).to_string();
+        match text.parse::<f64>() {
+            Ok(value) => Ok(Node::Number(value)),
+            Err(_) => {
+                self.position = start;
+                Err(self.error(&format!("的数字 {} 不合法", text)))
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<Node, Box<dyn Error>> {
+        let Some(function) = Function::from_name(name) else {
+            return Err(self.error(&format!("的函数 {} 不存在，只支持 min、max 和 abs", name)));
+        };
+        let mut args = Vec::new();
+        if !self.eat(')') {
+            loop {
+                args.push(self.expression()?);
+                if self.eat(')') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return Err(self.error("缺少 , 或 )"));
+                }
+            }
+        }
+        let valid = match function {
+            Function::Abs => args.len() == 1,
+            Function::Min | Function::Max => !args.is_empty(),
+        };
+        if !valid {
+            return Err(self.error(&format!("：{} 的参数个数不正确", name)));
+        }
+        Ok(Node::Call(function, args))
+    }
+
+    fn slice(&self, start: usize, end: usize) -> &str {
+        let byte = |index: usize| {
+            self.chars
+                .get(index)
+                .map_or(self.text.len(), |(offset, _)| *offset)
+        };
+        &self.text[byte(start)..byte(end)]
+    }
+}
+
+// 不加花括号的点位引用中可以使用的字符，包括中文等非 ASCII 字母
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | ':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn eval(text: &str, values: &[(&str, f64)]) -> Result<f64, EvalError> {
+        let values: HashMap<&str, f64> = values.iter().copied().collect();
+        Expression::parse(text)
+            .unwrap()
+            .evaluate(|name| values.get(name).copied())
+    }
+
+    fn parse_error(text: &str) -> String {
+        Expression::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn arithmetic_follows_precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3", &[]), Ok(7.0));
+        assert_eq!(eval("(1 + 2) * 3", &[]), Ok(9.0));
+        assert_eq!(eval("10 - 4 - 3", &[]), Ok(3.0));
+        assert_eq!(eval("24 / 4 / 2", &[]), Ok(3.0));
+        assert_eq!(eval("-2 * -3", &[]), Ok(6.0));
+        assert_eq!(eval("-(1 + 2)", &[]), Ok(-3.0));
+        assert_eq!(eval("1.5e3 + 2E-1 + .5", &[]), Ok(1500.7));
+    }
+
+    #[test]
+    fn functions_and_references_are_evaluated() {
+        let values = [("a.p", 3.0), ("b.p", -5.0)];
+        assert_eq!(eval("min(a.p, b.p, 1)", &values), Ok(-5.0));
+        assert_eq!(eval("max(a.p, b.p)", &values), Ok(3.0));
+        assert_eq!(eval("max(a.p)", &values), Ok(3.0));
+        assert_eq!(eval("abs(b.p) * 2", &values), Ok(10.0));
+
+        let values = [("PCS-A.ac_power", 90.0), ("PCS-A.dc_power", 100.0)];
+        assert_eq!(
+            eval("{PCS-A.ac_power} / { PCS-A.dc_power } * 100", &values),
+            Ok(90.0)
+        );
+        assert_eq!(eval("电表.有功功率 + 1", &[("电表.有功功率", 41.0)]), Ok(42.0));
+        assert_eq!(
+            eval("{192.168.1.10:502/1.power}", &[("192.168.1.10:502/1.power", 7.0)]),
+            Ok(7.0)
+        );
+    }
+
+    #[test]
+    fn references_are_unique_in_first_seen_order() {
+        let expression = Expression::parse("b.p + a.p * max(b.p, {c-1.p}, 2) - a.p").unwrap();
+        assert_eq!(expression.references(), ["b.p", "a.p", "c-1.p"]);
+        assert!(Expression::parse("1 + 2").unwrap().references().is_empty());
+        assert_eq!(expression.to_string(), "b.p + a.p * max(b.p, {c-1.p}, 2) - a.p");
+    }
+
+    #[test]
+    fn missing_input_and_division_by_zero_are_errors() {
+        assert_eq!(
+            eval("a.p + b.p", &[("a.p", 1.0)]),
+            Err(EvalError::Missing("b.p".to_string()))
+        );
+        assert_eq!(
+            EvalError::Missing("b.p".to_string()).to_string(),
+            "点位 b.p 没有可用的值"
+        );
+        assert_eq!(
+            eval("a.p / (b.p - 1)", &[("a.p", 1.0), ("b.p", 1.0)]),
+            Err(EvalError::DivisionByZero)
+        );
+        assert_eq!(EvalError::DivisionByZero.to_string(), "除数为0");
+        // 除数不为0时结果可以不是有限值，由计算点位判断
+        assert_eq!(eval("a.p * 1e308 * 10", &[("a.p", 1.0)]), Ok(f64::INFINITY));
+    }
+
+    #[test]
+    fn syntax_errors_report_position_and_reason() {
+        assert_eq!(parse_error("1 + 2)"), "表达式 \"1 + 2)\" 第 6 个字符处多余的内容");
+        assert_eq!(parse_error("(1 + 2"), "表达式 \"(1 + 2\" 第 7 个字符处缺少 )");
+        assert_eq!(parse_error("{a.p + 1"), "表达式 \"{a.p + 1\" 第 9 个字符处缺少 }");
+        assert_eq!(parse_error("{ } + 1"), "表达式 \"{ } + 1\" 第 4 个字符处花括号中缺少点位引用");
+        assert_eq!(parse_error("1 + * 2"), "表达式 \"1 + * 2\" 第 5 个字符处需要数字、点位引用或 (");
+        assert_eq!(parse_error("1 +"), "表达式 \"1 +\" 第 4 个字符处表达式不完整");
+        assert_eq!(parse_error(""), "表达式 \"\" 第 1 个字符处表达式不完整");
+        assert_eq!(parse_error("1.2.3"), "表达式 \"1.2.3\" 第 1 个字符处的数字 1.2.3 不合法");
+        assert_eq!(
+            parse_error("avg(a.p)"),
+            "表达式 \"avg(a.p)\" 第 5 个字符处的函数 avg 不存在，只支持 min、max 和 abs"
+        );
+        assert_eq!(parse_error("min(1 2)"), "表达式 \"min(1 2)\" 第 7 个字符处缺少 , 或 )");
+        assert_eq!(parse_error("abs(1, 2)"), "表达式 \"abs(1, 2)\" 第 10 个字符处：abs 的参数个数不正确");
+        assert_eq!(parse_error("max()"), "表达式 \"max()\" 第 6 个字符处：max 的参数个数不正确");
+        // 位置按字符而不是字节计算
+        assert_eq!(parse_error("电表.功率 ?"), "表达式 \"电表.功率 ?\" 第 7 个字符处多余的内容");
+    }
+}
@@ -0,0 +1,762 @@
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::device_configuration::http::HttpSettings;
+use crate::device_configuration::modbus::Config;
+use crate::latest::SharedCache;
+use crate::modbus::availability::Availability;
+use crate::modbus::reading::Reading;
+use crate::mqtt::client::MqttClient;
+use crate::metrics::MetricsText;
+use crate::mqtt::payload::{format_timestamp, gateway_name, slave_name};
+use crate::mqtt::publisher::Publisher;
+use crate::pipeline::SinkStats;
+use crate::reload::SharedWriters;
+
+/// HTTP 接口处理请求时读取的共享状态
+#[derive(Clone)]
+pub struct ApiState {
+    /// 每个点位的最新采集值和从站可用性
+    pub cache: SharedCache,
+    /// 正在采集的网关，随配置热加载更新
+    pub writers: SharedWriters,
+    /// 当前生效的配置，随配置热加载更新
+    pub config: watch::Receiver<Config>,
+    /// MQTT 客户端，未配置 MQTT 时为 None
+    pub mqtt: Option<Arc<MqttClient>>,
+    /// MQTT 发布器，未配置 MQTT 时为 None
+    pub publisher: Option<Arc<Publisher>>,
+    /// 各输出的投递统计
+    pub sinks: Vec<Arc<SinkStats>>,
+    /// 访问令牌，未配置时不需要认证
+    pub token: Option<String>,
+    /// `/metrics` 是否输出每个点位的最新值
+    pub point_metrics: bool,
+}
+
+/// 创建 HTTP 接口的路由
+///
+/// # 接口
+/// * `GET /api/devices` - 所有正在采集的网关和从站，包括可用性和采集统计
+/// * `GET /api/devices/{name}/points` - 网关（名称或 ip:port）所有从站每个点位的最新采集值
+/// * `GET /api/config` - 当前生效的配置，密码和令牌替换为 `***`
+/// * `GET /metrics` - Prometheus 格式的指标
+/// * `GET /healthz` - 健康检查，正常时返回 200，否则返回 503；不需要认证
+pub fn router(state: ApiState) -> Router {
+    let api = Router::new()
+        .route("/api/devices", get(devices))
+        .route("/api/devices/{name}/points", get(points))
+        .route("/api/config", get(config))
+        .route("/metrics", get(metrics))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize));
+    Router::new()
+        .merge(api)
+        .route("/healthz", get(healthz))
+        .with_state(state)
+}
+
+/// 已绑定监听地址的 HTTP 服务
+pub struct HttpServer {
+    listener: TcpListener,
+    router: Router,
+}
+
+impl HttpServer {
+    /// 按配置绑定监听地址
+    pub async fn bind(settings: &HttpSettings, state: ApiState) -> std::io::Result<Self> {
+        let address = format!("{}:{}", settings.bind_address, settings.port);
+        let listener = TcpListener::bind(&address).await?;
+        Ok(HttpServer {
+            listener,
+            router: router(state),
+        })
+    }
+
+    /// 实际监听的地址
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// 处理请求，直到程序退出
+    pub async fn run(self) {
+        if let Ok(address) = self.listener.local_addr() {
+            info!(address = %address, "HTTP 接口已启动");
+        }
+        if let Err(e) = axum::serve(self.listener, self.router).await {
+            error!(error = %e, "HTTP 接口已停止");
+        }
+    }
+}
+
+// 配置了令牌时检查 Authorization 请求头
+async fn authorize(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    if let Some(token) = &state.token {
+        let provided = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !provided.is_some_and(|provided| same_token(provided, token)) {
+            return failure(StatusCode::UNAUTHORIZED, "缺少访问令牌或访问令牌不正确");
+        }
+    }
+    next.run(request).await
+}
+
+// 比较耗时与令牌内容无关，避免通过响应时间猜测令牌
+fn same_token(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn failure(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+async fn devices(State(state): State<ApiState>) -> Json<Value> {
+    let writers = state
+        .writers
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let mut gateways = Vec::new();
+    for writer in &writers {
+        let gateway = &writer.gateway;
+        let address = format!("{}:{}", gateway.ip, gateway.port);
+        let stats = writer
+            .stats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let slaves: Vec<Value> = gateway
+            .enabled_slaves()
+            .map(|slave| {
+                let slave_stats = stats.slaves.get(&slave.id).cloned().unwrap_or_default();
+                json!({
+                    "id": slave.id,
+                    "name": slave.display_name(),
+                    "availability": state
+                        .cache
+                        .availability(&address, slave.id)
+                        .map(Availability::as_str),
+                    "cycles": slave_stats.cycles,
+                    "reads_ok": slave_stats.reads_ok,
+                    "reads_failed": slave_stats.reads_failed,
+                    "overruns": slave_stats.overruns,
+                    "last_error": slave_stats.last_error,
+                    "last_success": slave_stats.last_success.map(format_timestamp),
+                    "stale_points": state.cache.stale_count(&address, slave.id),
+                })
+            })
+            .collect();
+        gateways.push(json!({
+            "name": gateway.display_name(),
+            "address": address,
+            "restarts": stats.restarts,
+            "last_failure": stats.last_failure,
+            "failed": stats.failed,
+            "paused": stats.paused,
+            "slaves": slaves,
+        }));
+    }
+    Json(Value::Array(gateways))
+}
+
+async fn points(State(state): State<ApiState>, Path(name): Path<String>) -> Response {
+    let address = state
+        .writers
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|writer| &writer.gateway)
+        .map(|gateway| {
+            (
+                gateway.display_name(),
+                format!("{}:{}", gateway.ip, gateway.port),
+            )
+        })
+        .find(|(display, address)| *display == name || *address == name)
+        .map(|(_, address)| address);
+    let Some(address) = address else {
+        return failure(
+            StatusCode::NOT_FOUND,
+            &format!("网关 {} 不存在或未在采集", name),
+        );
+    };
+    let readings: Vec<Value> = state
+        .cache
+        .gateway_readings(&address)
+        .iter()
+        .map(reading_json)
+        .collect();
+    Json(Value::Array(readings)).into_response()
+}
+
+fn reading_json(reading: &Reading) -> Value {
+    json!({
+        "slave_id": reading.slave_id,
+        "slave": reading.slave_name,
+        "point": reading.point,
+        "group": reading.group,
+        "value": reading.point_value(),
+        "raw": reading.raw,
+        "unit": reading.unit,
+        "quality": reading.quality,
+        "timestamp": format_timestamp(reading.timestamp),
+    })
+}
+
+async fn config(State(state): State<ApiState>) -> Response {
+    let config = state.config.borrow().redacted();
+    match serde_json::to_value(&config) {
+        Ok(value) => Json(value).into_response(),
+        Err(e) => failure(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+// MQTT 已连接（未配置时不检查），且不是所有从站都离线时为正常
+async fn healthz(State(state): State<ApiState>) -> Response {
+    let mqtt = state.mqtt.as_ref().map(|client| client.is_connected());
+    let writers = state
+        .writers
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let (mut online, mut offline) = (0, 0);
+    for writer in &writers {
+        let address = format!("{}:{}", writer.gateway.ip, writer.gateway.port);
+        for slave in writer.gateway.enabled_slaves() {
+            match state.cache.availability(&address, slave.id) {
+                Some(Availability::Online) => online += 1,
+                Some(Availability::Offline) => offline += 1,
+                _ => {}
+            }
+        }
+    }
+    let healthy = mqtt != Some(false) && !(online == 0 && offline > 0);
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": if healthy { "ok" } else { "unhealthy" },
+        "mqtt_connected": mqtt,
+        "slaves_online": online,
+        "slaves_offline": offline,
+    });
+    (status, Json(body)).into_response()
+}
+
+async fn metrics(State(state): State<ApiState>) -> Response {
+    let writers = state
+        .writers
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let gateways: Vec<_> = writers
+        .iter()
+        .map(|writer| {
+            let stats = writer
+                .stats
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            (writer, stats)
+        })
+        .collect();
+    let mut text = MetricsText::new();
+
+    text.header(
+        "ems_modbus_requests_total",
+        "counter",
+        "Modbus 请求数，按网关、从站、功能码和结果区分",
+    );
+    for (writer, stats) in &gateways {
+        for ((slave_id, function_code, result), count) in &stats.requests {
+            let slave = writer
+                .gateway
+                .find_slave(*slave_id)
+                .map(|slave| slave.display_name())
+                .unwrap_or_else(|| slave_id.to_string());
+            text.sample(
+                "ems_modbus_requests_total",
+                &[
+                    ("gateway", &stats.name),
+                    ("slave", &slave),
+                    ("function", &function_code.to_string()),
+                    ("result", result.as_str()),
+                ],
+                *count as f64,
+            );
+        }
+    }
+    text.header(
+        "ems_modbus_request_duration_seconds",
+        "histogram",
+        "Modbus 请求耗时",
+    );
+    for (_, stats) in &gateways {
+        text.histogram(
+            "ems_modbus_request_duration_seconds",
+            &[("gateway", &stats.name)],
+            &stats.request_durations,
+        );
+    }
+    text.header("ems_modbus_connected", "gauge", "是否已连接到网关");
+    for (_, stats) in &gateways {
+        text.sample(
+            "ems_modbus_connected",
+            &[("gateway", &stats.name)],
+            if stats.connected { 1.0 } else { 0.0 },
+        );
+    }
+    text.header(
+        "ems_poll_cycle_duration_seconds",
+        "histogram",
+        "执行一个采集任务（一个从站的一个采集组）的耗时",
+    );
+    for (_, stats) in &gateways {
+        text.histogram(
+            "ems_poll_cycle_duration_seconds",
+            &[("gateway", &stats.name)],
+            &stats.cycle_durations,
+        );
+    }
+    text.header(
+        "ems_gateway_restarts_total",
+        "counter",
+        "采集任务崩溃或意外结束后重启的次数",
+    );
+    for (_, stats) in &gateways {
+        text.sample(
+            "ems_gateway_restarts_total",
+            &[("gateway", &stats.name)],
+            stats.restarts as f64,
+        );
+    }
+    text.header(
+        "ems_gateway_failed",
+        "gauge",
+        "是否因重启次数过多已停止采集",
+    );
+    for (_, stats) in &gateways {
+        text.sample(
+            "ems_gateway_failed",
+            &[("gateway", &stats.name)],
+            if stats.failed { 1.0 } else { 0.0 },
+        );
+    }
+
+    text.header(
+        "ems_slave_last_success_timestamp_seconds",
+        "gauge",
+        "从站最近一次有成功读请求的采集周期的完成时间（Unix 时间戳）",
+    );
+    for (writer, stats) in &gateways {
+        for slave in writer.gateway.enabled_slaves() {
+            let Some(last_success) = stats.slaves.get(&slave.id).and_then(|s| s.last_success) else {
+                continue;
+            };
+            let seconds = last_success
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            text.sample(
+                "ems_slave_last_success_timestamp_seconds",
+                &[("gateway", &stats.name), ("slave", &slave.display_name())],
+                seconds,
+            );
+        }
+    }
+
+    text.header("ems_stale_points", "gauge", "最新值已过期的点位数");
+    for (writer, stats) in &gateways {
+        let address = format!("{}:{}", writer.gateway.ip, writer.gateway.port);
+        for slave in writer.gateway.enabled_slaves() {
+            text.sample(
+                "ems_stale_points",
+                &[("gateway", &stats.name), ("slave", &slave.display_name())],
+                state.cache.stale_count(&address, slave.id) as f64,
+            );
+        }
+    }
+
+    if let Some(client) = &state.mqtt {
+        text.header("ems_mqtt_connected", "gauge", "是否已连接到 MQTT Broker");
+        let connected = if client.is_connected() { 1.0 } else { 0.0 };
+        text.sample("ems_mqtt_connected", &[], connected);
+    }
+    if let Some(publisher) = &state.publisher {
+        text.header(
+            "ems_mqtt_published_total",
+            "counter",
+            "已放入 MQTT 发送队列的采集数据消息数",
+        );
+        text.sample("ems_mqtt_published_total", &[], publisher.published() as f64);
+        let queued = state
+            .sinks
+            .iter()
+            .find(|sink| sink.name() == "mqtt")
+            .map(|sink| sink.queued())
+            .unwrap_or(0);
+        text.header(
+            "ems_mqtt_buffered_messages",
+            "gauge",
+            "等待发布的采集数据：分发队列中的采集事件和超出限速等待发布的消息",
+        );
+        text.sample(
+            "ems_mqtt_buffered_messages",
+            &[],
+            (queued + publisher.pending() as u64) as f64,
+        );
+    }
+
+    text.header(
+        "ems_pipeline_queued_events",
+        "gauge",
+        "各输出队列中等待处理的采集事件数",
+    );
+    for sink in &state.sinks {
+        text.sample(
+            "ems_pipeline_queued_events",
+            &[("sink", sink.name())],
+            sink.queued() as f64,
+        );
+    }
+    text.header(
+        "ems_pipeline_dropped_events_total",
+        "counter",
+        "输出队列已满被丢弃的采集事件数",
+    );
+    for sink in &state.sinks {
+        text.sample(
+            "ems_pipeline_dropped_events_total",
+            &[("sink", sink.name())],
+            sink.dropped() as f64,
+        );
+    }
+
+    if state.point_metrics {
+        text.header("ems_point_value", "gauge", "点位的最新采集值");
+        for (writer, _) in &gateways {
+            let address = format!("{}:{}", writer.gateway.ip, writer.gateway.port);
+            // 字符串点位没有数值，不输出
+            for reading in state.cache.gateway_readings(&address).into_iter().filter(|r| r.text.is_none()) {
+                let device = format!("{}/{}", gateway_name(&reading), slave_name(&reading));
+                text.sample(
+                    "ems_point_value",
+                    &[("device", &device), ("point", &reading.point)],
+                    reading.value,
+                );
+            }
+        }
+    }
+
+    let content_type = HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8");
+    ([(header::CONTENT_TYPE, content_type)], text.finish()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::mqtt::MqttSettings;
+    use crate::latest::ReadingCache;
+    use crate::modbus::availability::DeviceAvailability;
+    use crate::modbus::scheduler::PollEvent;
+    use crate::modbus::stats::{GatewayStats, SharedStats, SlaveStats};
+    use crate::pipeline::{LogSink, Pipeline};
+    use crate::reload::{GatewayTasks, GatewayWriter};
+    use crate::test_support::{at, reading, MockBroker, MockModbus};
+    use axum::body::Body;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    const GATEWAY: &str = "10.0.0.1:502";
+
+    fn config() -> Config {
+        let config: Config = serde_yaml::from_str(
+            "version: 2\nmqtt:\n  broker_host: 127.0.0.1\n  client_id: http-test\n  username: ems\n  password: secret\nhttp:\n  port: 8080\n  token: abc\ngateways:\n  - ip: 10.0.0.1\n    name: PCS-A\n    slave_ids: [{ id: 1, name: meter }, 2, { id: 3, enabled: false }]\n    points:\n      - { name: power, address: 0 }\n",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    fn availability(cache: &ReadingCache, slave_id: u8, availability: Availability) {
+        cache.set_availability(&DeviceAvailability {
+            gateway: GATEWAY.to_string(),
+            gateway_name: Some("PCS-A".to_string()),
+            slave_id,
+            slave_name: None,
+            availability,
+        });
+    }
+
+    // 缓存中有从站1的 power 和从站2的 serial，从站1在线、从站2离线
+    fn state(token: Option<&str>) -> ApiState {
+        let config = config();
+        let cache = ReadingCache::new();
+        let mut power = reading(GATEWAY, 1, "power", 12.5);
+        power.slave_name = Some("meter".to_string());
+        power.unit = Some("kW".to_string());
+        power.raw = vec![125];
+        let mut serial = reading(GATEWAY, 2, "serial", 0.0);
+        serial.text = Some("SN-1".to_string());
+        cache.update(&[power, serial]);
+        availability(&cache, 1, Availability::Online);
+        availability(&cache, 2, Availability::Offline);
+
+        let stats: SharedStats = Arc::new(Mutex::new(GatewayStats {
+            name: "PCS-A".to_string(),
+            connected: true,
+            restarts: 1,
+            slaves: BTreeMap::from([(
+                1,
+                SlaveStats {
+                    cycles: 3,
+                    reads_ok: 3,
+                    last_success: Some(at(1_700_000_000_000)),
+                    ..SlaveStats::default()
+                },
+            )]),
+            ..GatewayStats::default()
+        }));
+        let writer = GatewayWriter {
+            gateway: config.gateways[0].clone(),
+            points: BTreeMap::new(),
+            sender: mpsc::channel(1).0,
+            stats,
+        };
+        ApiState {
+            cache,
+            writers: Arc::new(Mutex::new(vec![writer])),
+            config: watch::channel(config).1,
+            mqtt: None,
+            publisher: None,
+            sinks: Vec::new(),
+            token: token.map(str::to_string),
+            point_metrics: false,
+        }
+    }
+
+    async fn get(state: &ApiState, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::builder().uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn devices_list_enabled_slaves_with_availability_and_stats() {
+        let (status, body) = get(&state(None), "/api/devices", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let gateway = &body[0];
+        assert_eq!(gateway["name"], "PCS-A");
+        assert_eq!(gateway["address"], GATEWAY);
+        assert_eq!(gateway["restarts"], 1);
+        assert_eq!(gateway["failed"], false);
+        // 停用的从站3不列出
+        let slaves = gateway["slaves"].as_array().unwrap();
+        assert_eq!(slaves.len(), 2);
+        assert_eq!(slaves[0]["name"], "meter");
+        assert_eq!(slaves[0]["availability"], "online");
+        assert_eq!(slaves[0]["cycles"], 3);
+        assert_eq!(slaves[0]["reads_ok"], 3);
+        assert_eq!(slaves[0]["last_success"], format_timestamp(at(1_700_000_000_000)));
+        assert_eq!(slaves[1]["name"], "2");
+        assert_eq!(slaves[1]["availability"], "offline");
+        assert_eq!(slaves[1]["last_success"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn points_are_found_by_name_or_address() {
+        let state = state(None);
+        let (status, body) = get(&state, "/api/devices/PCS-A/points", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([
+                {
+                    "slave_id": 1,
+                    "slave": "meter",
+                    "point": "power",
+                    "group": null,
+                    "value": 12.5,
+                    "raw": [125],
+                    "unit": "kW",
+                    "quality": "good",
+                    "timestamp": format_timestamp(at(1_700_000_000_000)),
+                },
+                {
+                    "slave_id": 2,
+                    "slave": null,
+                    "point": "serial",
+                    "group": null,
+                    "value": "SN-1",
+                    "raw": [],
+                    "unit": null,
+                    "quality": "good",
+                    "timestamp": format_timestamp(at(1_700_000_000_000)),
+                },
+            ])
+        );
+        assert_eq!(get(&state, "/api/devices/10.0.0.1:502/points", None).await.1, body);
+
+        let (status, body) = get(&state, "/api/devices/PCS-B/points", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["error"].as_str().unwrap().contains("PCS-B"));
+    }
+
+    #[tokio::test]
+    async fn config_is_returned_with_secrets_redacted() {
+        let (status, body) = get(&state(None), "/api/config", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["mqtt"]["username"], "ems");
+        assert_eq!(body["mqtt"]["password"], "***");
+        assert_eq!(body["http"]["token"], "***");
+        assert_eq!(body["gateways"][0]["name"], "PCS-A");
+        assert!(!body.to_string().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn healthz_fails_when_every_slave_is_offline() {
+        let state = state(None);
+        let (status, body) = get(&state, "/healthz", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({"status": "ok", "mqtt_connected": null, "slaves_online": 1, "slaves_offline": 1})
+        );
+
+        availability(&state.cache, 1, Availability::Offline);
+        let (status, body) = get(&state, "/healthz", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["slaves_offline"], 2);
+    }
+
+    #[tokio::test]
+    async fn token_is_required_except_for_healthz() {
+        let state = state(Some("abc"));
+        for uri in ["/api/devices", "/api/devices/PCS-A/points", "/api/config"] {
+            assert_eq!(get(&state, uri, None).await.0, StatusCode::UNAUTHORIZED, "{}", uri);
+            assert_eq!(get(&state, uri, Some("abd")).await.0, StatusCode::UNAUTHORIZED, "{}", uri);
+            assert_eq!(get(&state, uri, Some("abc")).await.0, StatusCode::OK, "{}", uri);
+        }
+        assert_eq!(get(&state, "/healthz", None).await.0, StatusCode::OK);
+        assert!(same_token("abc", "abc"));
+        assert!(!same_token("abc", "abcd"));
+    }
+
+    #[tokio::test]
+    async fn metrics_scrape_reports_series_after_activity() {
+        // 从站1读取成功，从站2返回异常响应
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 0, &[42]);
+        modbus.reject(2, 0x02);
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    poll_interval_ms: 60000\n    slave_ids: [{{ id: 1, name: meter }}, 2]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            modbus.port
+        ))
+        .unwrap();
+        config.validate().unwrap();
+
+        let cache = ReadingCache::new();
+        let mut pipeline = Pipeline::new();
+        pipeline.add_sink("mqtt", 8, LogSink);
+        let sender = pipeline.sender();
+        let recorded = Arc::clone(&cache);
+        let mut tasks = GatewayTasks::new(move |event: PollEvent| {
+            recorded.record(&event);
+            sender.send(event);
+        });
+        tasks.apply(&config);
+        let writers = tasks.writers();
+        let stats = Arc::clone(&writers.lock().unwrap()[0].stats);
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while stats.lock().unwrap().slaves.values().filter(|s| s.cycles > 0).count() < 2 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "metrics-test");
+        settings.broker_port = broker.port;
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher = Publisher::new(Arc::clone(&client), &settings, Arc::clone(&cache), None, None, None, None).unwrap();
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        publisher.publish(&[reading("127.0.0.1:1", 1, "power", 1.0)]);
+
+        let state = ApiState {
+            cache,
+            writers,
+            config: watch::channel(config).1,
+            mqtt: Some(Arc::clone(&client)),
+            publisher: Some(Arc::new(publisher)),
+            sinks: pipeline.stats().to_vec(),
+            token: None,
+            point_metrics: true,
+        };
+        let response = router(state)
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/plain; version=0.0.4"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "# TYPE ems_modbus_requests_total counter",
+            "ems_modbus_requests_total{gateway=\"PCS-A\",slave=\"meter\",function=\"3\",result=\"ok\"} 1",
+            "ems_modbus_requests_total{gateway=\"PCS-A\",slave=\"2\",function=\"3\",result=\"exception\"} 1",
+            "# TYPE ems_modbus_request_duration_seconds histogram",
+            "ems_modbus_request_duration_seconds_bucket{gateway=\"PCS-A\",le=\"+Inf\"} 2",
+            "ems_modbus_request_duration_seconds_count{gateway=\"PCS-A\"} 2",
+            "ems_modbus_connected{gateway=\"PCS-A\"} 1",
+            "ems_poll_cycle_duration_seconds_count{gateway=\"PCS-A\"} 2",
+            "ems_gateway_restarts_total{gateway=\"PCS-A\"} 0",
+            "ems_stale_points{gateway=\"PCS-A\",slave=\"meter\"} 0",
+            "ems_mqtt_connected 1",
+            "ems_mqtt_published_total 1",
+            "ems_mqtt_buffered_messages 0",
+            "ems_pipeline_dropped_events_total{sink=\"mqtt\"} 0",
+            "# TYPE ems_point_value gauge",
+            "ems_point_value{device=\"PCS-A/meter\",point=\"power\"} 42",
+        ] {
+            assert!(lines.contains(&expected), "缺少 {}\n{}", expected, text);
+        }
+        // 从站2没有成功的采集，不输出最近成功时间
+        assert!(lines.iter().any(|l| l.starts_with("ems_slave_last_success_timestamp_seconds{gateway=\"PCS-A\",slave=\"meter\"}")));
+        assert!(!text.contains("ems_slave_last_success_timestamp_seconds{gateway=\"PCS-A\",slave=\"2\"}"));
+        client.close().await;
+        tasks.shutdown().await;
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+
+use crate::device_configuration::computed::COMPUTED_GATEWAY;
+use crate::device_configuration::modbus::Config;
+use crate::modbus::availability::{Availability, DeviceAvailability};
+use crate::modbus::reading::{PointValue, Quality, Reading};
+use crate::modbus::scheduler::PollEvent;
+
+/// 变化通知通道的容量，订阅方处理不及时时丢弃最早的通知，下次接收时得到 `RecvError::Lagged`
+pub const CHANGE_CAPACITY: usize = 1024;
+
+// 从站的键：网关地址和从站ID
+type DeviceKey = (String, u8);
+
+/// 每个点位最近一次的采集值、最近一次发布的值和每个从站的可用性
+///
+/// # 说明
+/// * 由采集任务的事件回调直接更新（见 [`ReadingCache::record`]），早于分发给各个输出，
+///   HTTP 接口和计算点位都从这里读取当前值
+/// * 缓存的 [`Reading`] 带有质量和采集时间；过期检测标记的点位质量为 stale，值和时间为最后一次读到的
+/// * 计算点位和电量点位在网关 [`COMPUTED_GATEWAY`]、从站ID 0 下
+/// * 配置热加载后调用 [`ReadingCache::apply`]：已删除或停用的点位、从站和网关的条目被删除，其余保留
+/// * MQTT 发布的死区过滤比较的是上次发布的值而不是上次采集的值，发布的值另外记录
+///   （见 [`ReadingCache::mark_published`]），可以用 [`ReadingCache::last_published`] 查询
+///
+/// # 示例
+///
+/// 嵌入程序时在采集事件回调中更新缓存，之后随时查询或订阅变化（示例使用模拟数据，不连接设备）：
+///
+/// ```
+/// use std::sync::Arc;
+/// use modbus_pub::latest::ReadingCache;
+/// use modbus_pub::modbus::scheduler::PollEvent;
+/// use modbus_pub::reload::GatewayTasks;
+/// use modbus_pub::Config;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let config: Config = serde_yaml::from_str(
+///     r#"
+/// version: 2
+/// gateways:
+///   - ip: 192.168.1.100
+///     slave_ids: [1]
+///     simulation: true
+///     poll_interval_ms: 100
+///     points:
+///       - { name: active_power, address: 0, sim: { kind: constant, value: 42 } }
+/// "#,
+/// )?;
+/// config.validate()?;
+///
+/// let cache = ReadingCache::new();
+/// let mut changes = cache.subscribe_changes();
+/// let mut tasks = GatewayTasks::new({
+///     let cache = Arc::clone(&cache);
+///     move |event: PollEvent| cache.record(&event)
+/// });
+/// tasks.apply(&config);
+///
+/// // 第一次采集到该点位时收到通知
+/// let reading = changes.recv().await?;
+/// assert_eq!(reading.point, "active_power");
+///
+/// let reading = cache.get("192.168.1.100:502", 1, "active_power").unwrap();
+/// assert_eq!(reading.value, 42.0);
+/// assert_eq!(cache.get_device("192.168.1.100:502", 1).len(), 1);
+/// tasks.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ReadingCache {
+    readings: RwLock<BTreeMap<DeviceKey, BTreeMap<String, Reading>>>,
+    published: RwLock<BTreeMap<DeviceKey, BTreeMap<String, Reading>>>,
+    availability: RwLock<BTreeMap<DeviceKey, Availability>>,
+    changes: broadcast::Sender<Reading>,
+}
+
+/// 可在多个任务间共享的最新采集值缓存
+pub type SharedCache = Arc<ReadingCache>;
+
+impl Default for ReadingCache {
+    fn default() -> Self {
+        ReadingCache {
+            readings: RwLock::default(),
+            published: RwLock::default(),
+            availability: RwLock::default(),
+            changes: broadcast::channel(CHANGE_CAPACITY).0,
+        }
+    }
+}
+
+impl ReadingCache {
+    /// 创建空的缓存
+    pub fn new() -> SharedCache {
+        Arc::new(ReadingCache::default())
+    }
+
+    /// 按采集事件更新：采集数据更新点位的值，可用性变化更新从站的可用性
+    pub fn record(&self, event: &PollEvent) {
+        match event {
+            PollEvent::Readings(readings) => self.update(readings),
+            PollEvent::Availability(device) => self.set_availability(device),
+            PollEvent::Watchdog(_) => {}
+        }
+    }
+
+    /// 记录一批采集数据，值或质量有变化的点位发送给变化的订阅方
+    pub fn update(&self, readings: &[Reading]) {
+        let notify = self.changes.receiver_count() > 0;
+        let mut latest = self.readings.write().unwrap_or_else(|e| e.into_inner());
+        for reading in readings {
+            let previous = latest
+                .entry((reading.gateway.clone(), reading.slave_id))
+                .or_default()
+                .insert(reading.point.clone(), reading.clone());
+            if notify && previous.is_none_or(|previous| changed(&previous, reading)) {
+                // 没有订阅方时发送失败，忽略
+                let _ = self.changes.send(reading.clone());
+            }
+        }
+    }
+
+    /// 记录从站的可用性
+    pub fn set_availability(&self, device: &DeviceAvailability) {
+        self.availability
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                (device.gateway.clone(), device.slave_id),
+                device.availability,
+            );
+    }
+
+    /// 应用新的配置，删除配置中已不存在或已停用的点位、从站和网关的条目
+    ///
+    /// 保留的是各启用从站发布的点位（包括位域点位和 SunSpec 生成的点位）、计算点位和电量点位。
+    pub fn apply(&self, config: &Config) {
+        let mut known: BTreeMap<DeviceKey, BTreeSet<String>> = BTreeMap::new();
+        for gateway in config.gateways.iter().filter(|g| g.enabled) {
+            let address = format!("{}:{}", gateway.ip, gateway.port);
+            for slave in gateway.enabled_slaves() {
+                // 配置已校验过，点位表一定存在
+                let Ok(points) = config.published_points(gateway, slave) else {
+                    continue;
+                };
+                known.insert(
+                    (address.clone(), slave.id),
+                    points.into_iter().map(|point| point.name).collect(),
+                );
+            }
+        }
+        let derived = known.entry((COMPUTED_GATEWAY.to_string(), 0)).or_default();
+        derived.extend(config.computed_points.iter().map(|point| point.name.clone()));
+        if let Some(energy) = &config.energy {
+            derived.extend(energy.points.iter().map(|point| point.name.clone()));
+        }
+
+        for readings in [&self.readings, &self.published] {
+            readings
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .retain(|key, points| {
+                    let Some(names) = known.get(key) else {
+                        return false;
+                    };
+                    points.retain(|name, _| names.contains(name));
+                    !points.is_empty()
+                });
+        }
+        self.availability
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| known.contains_key(key));
+    }
+
+    /// 点位最近一次的采集值，尚未采集到时为 None
+    ///
+    /// # 参数说明
+    /// * `gateway` - 网关地址，格式为 ip:port；计算点位和电量点位为 [`COMPUTED_GATEWAY`]
+    /// * `slave_id` - 从站ID，计算点位和电量点位为 0
+    /// * `point` - 点位名称
+    pub fn get(&self, gateway: &str, slave_id: u8, point: &str) -> Option<Reading> {
+        self.readings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(gateway.to_string(), slave_id))
+            .and_then(|points| points.get(point))
+            .cloned()
+    }
+
+    /// 从站所有点位最近一次的采集值，键为点位名称
+    pub fn get_device(&self, gateway: &str, slave_id: u8) -> HashMap<String, Reading> {
+        self.readings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(gateway.to_string(), slave_id))
+            .map(|points| {
+                points
+                    .iter()
+                    .map(|(name, reading)| (name.clone(), reading.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// 所有点位最近一次的采集值，按网关地址、从站ID和点位名称排序
+    pub fn snapshot(&self) -> Vec<Reading> {
+        self.readings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .flat_map(|points| points.values().cloned())
+            .collect()
+    }
+
+    /// 订阅点位的变化：第一次采集到以及值或质量变化时收到该点位的新数据，采集时间的变化不算
+    ///
+    /// 只收到订阅之后的变化，当前值可以先用 [`ReadingCache::snapshot`] 取得。
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<Reading> {
+        self.changes.subscribe()
+    }
+
+    /// 网关（地址为 ip:port）所有从站的最新采集值，按从站ID和点位名称排序
+    pub fn gateway_readings(&self, gateway: &str) -> Vec<Reading> {
+        self.readings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|((address, _), _)| address == gateway)
+            .flat_map(|(_, points)| points.values().cloned())
+            .collect()
+    }
+
+    /// 从站中最新值已过期的点位数
+    pub fn stale_count(&self, gateway: &str, slave_id: u8) -> usize {
+        self.readings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(gateway.to_string(), slave_id))
+            .map_or(0, |points| {
+                points
+                    .values()
+                    .filter(|r| r.quality == Quality::Stale)
+                    .count()
+            })
+    }
+
+    /// 记录已发布的采集数据，死区过滤以此为基准
+    pub fn mark_published(&self, readings: &[Reading]) {
+        let mut published = self.published.write().unwrap_or_else(|e| e.into_inner());
+        for reading in readings {
+            published
+                .entry((reading.gateway.clone(), reading.slave_id))
+                .or_default()
+                .insert(reading.point.clone(), reading.clone());
+        }
+    }
+
+    /// 点位最近一次发布的值，尚未发布或已被 [`ReadingCache::forget_published`] 清除时为 None
+    pub fn last_published(&self, gateway: &str, slave_id: u8, point: &str) -> Option<Reading> {
+        self.published
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(gateway.to_string(), slave_id))
+            .and_then(|points| points.get(point))
+            .cloned()
+    }
+
+    /// 清除从站所有点位的发布记录，下一次采集的所有点位都会发布
+    pub fn forget_published(&self, gateway: &str, slave_id: u8) {
+        self.published
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&(gateway.to_string(), slave_id));
+    }
+
+    /// 从站最近一次报告的可用性，尚未报告时为 None
+    pub fn availability(&self, gateway: &str, slave_id: u8) -> Option<Availability> {
+        self.availability
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(gateway.to_string(), slave_id))
+            .copied()
+    }
+}
+
+// 值或质量是否变化；NaN 与任何值比较都不成立，按是否为 NaN 单独判断
+fn changed(previous: &Reading, reading: &Reading) -> bool {
+    if previous.quality != reading.quality {
+        return true;
+    }
+    match (previous.point_value(), reading.point_value()) {
+        (PointValue::Number(previous), PointValue::Number(value))
+            if previous.is_nan() || value.is_nan() =>
+        {
+            previous.is_nan() != value.is_nan()
+        }
+        (previous, value) => previous != value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::reading;
+    use std::thread;
+
+    const GATEWAY: &str = "10.0.0.1:502";
+
+    #[test]
+    fn notifies_only_on_value_or_quality_change() {
+        let cache = ReadingCache::new();
+        let mut changes = cache.subscribe_changes();
+        cache.update(&[reading(GATEWAY, 1, "p", 1.0)]);
+        let mut later = reading(GATEWAY, 1, "p", 1.0);
+        later.timestamp += std::time::Duration::from_secs(1);
+        cache.update(&[later.clone()]);
+        later.quality = Quality::Stale;
+        cache.update(&[later]);
+        cache.update(&[reading(GATEWAY, 1, "p", f64::NAN)]);
+        cache.update(&[reading(GATEWAY, 1, "p", f64::NAN)]);
+
+        let mut received = Vec::new();
+        while let Ok(reading) = changes.try_recv() {
+            received.push(reading.quality);
+        }
+        assert_eq!(received, [Quality::Good, Quality::Stale, Quality::Good]);
+        assert_eq!(cache.stale_count(GATEWAY, 1), 0);
+    }
+
+    #[test]
+    fn apply_prunes_removed_points_and_devices() {
+        let config: Config = serde_yaml::from_str(
+            "version: 2\ngateways:\n  - ip: 10.0.0.1\n    slave_ids: [1]\n    points:\n      - { name: kept, address: 0 }\n",
+        )
+        .unwrap();
+        let cache = ReadingCache::new();
+        let readings = [
+            reading(GATEWAY, 1, "kept", 1.0),
+            reading(GATEWAY, 1, "removed", 1.0),
+            reading(GATEWAY, 2, "kept", 1.0),
+        ];
+        cache.update(&readings);
+        cache.mark_published(&readings);
+        cache.apply(&config);
+        assert_eq!(cache.snapshot().len(), 1);
+        assert!(cache.last_published(GATEWAY, 1, "kept").is_some());
+        assert!(cache.last_published(GATEWAY, 1, "removed").is_none());
+        assert!(cache.last_published(GATEWAY, 2, "kept").is_none());
+    }
+
+    #[test]
+    fn concurrent_writers_and_readers() {
+        const WRITERS: u8 = 4;
+        const ROUNDS: usize = 500;
+        let cache = ReadingCache::new();
+        let mut changes = cache.subscribe_changes();
+        let writers: Vec<_> = (1..=WRITERS)
+            .map(|slave_id| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for round in 0..ROUNDS {
+                        let value = round as f64;
+                        cache.update(&[
+                            reading(GATEWAY, slave_id, "a", value),
+                            reading(GATEWAY, slave_id, "b", value),
+                        ]);
+                        cache.mark_published(&[reading(GATEWAY, slave_id, "a", value)]);
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        // 同一批数据一起写入，读到的同一从站的两个点位来自同一轮
+                        for slave_id in 1..=WRITERS {
+                            let device = cache.get_device(GATEWAY, slave_id);
+                            if let (Some(a), Some(b)) = (device.get("a"), device.get("b")) {
+                                assert_eq!(a.value, b.value);
+                            }
+                        }
+                        assert!(cache.snapshot().len() <= 2 * WRITERS as usize);
+                    }
+                })
+            })
+            .collect();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        let last = (ROUNDS - 1) as f64;
+        for slave_id in 1..=WRITERS {
+            assert_eq!(cache.get(GATEWAY, slave_id, "a").unwrap().value, last);
+            assert_eq!(cache.get(GATEWAY, slave_id, "b").unwrap().value, last);
+            assert_eq!(cache.last_published(GATEWAY, slave_id, "a").unwrap().value, last);
+        }
+        // 每轮两个点位都变化；超出通道容量被丢弃的通知以 Lagged 报告数量
+        let mut notified = 0;
+        loop {
+            match changes.try_recv() {
+                Ok(_) => notified += 1,
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => notified += skipped as usize,
+                Err(_) => break,
+            }
+        }
+        assert_eq!(notified, WRITERS as usize * ROUNDS * 2);
+    }
+}
@@ -0,0 +1,117 @@
+//! Modbus TCP 采集与 MQTT 发布
+//!
+//! 本 crate 同时提供 `modbus_pub` 程序和可嵌入其他程序的库：
+//!
+//! * [`app`] - 按配置启动采集、各个输出和 MQTT 命令处理，`modbus_pub run` 即由它实现
+//! * [`modbus`] - Modbus TCP 客户端、寄存器解码和按采集组调度的采集任务
+//! * [`device_configuration`] - YAML 配置文件的加载、迁移、校验和编辑
+//! * [`mqtt`] - MQTT 客户端、采集数据发布、远程写入和临时读取
+//! * [`reload`] - 按配置启动和调整各网关的采集任务，支持配置热加载
+//! * [`pipeline`] - 把采集数据分发给 MQTT、日志等多个输出
+//! * [`storage`] - 在本地 SQLite 数据库中保存采集数据
+//! * [`replay`] - MQTT 恢复连接后补发本地数据库中未发布的数据
+//! * [`csv`] - 把采集数据按天写入 CSV 文件
+//! * [`http`] - 查询最新采集值、设备状态和配置的 HTTP 接口
+//! * [`latest`] - 每个点位最新采集值的缓存，可以按点位查询或订阅变化
+//! * [`computed`] - 由其他点位按表达式计算得到的点位
+//! * [`energy`] - 由功率点位累计得到的电量点位
+//! * [`alarm`] - 按阈值判断报警并发布报警状态
+//! * [`staleness`] - 检测长时间没有更新的点位并标记为过期
+//! * [`schedule`] - 按计划在指定时间写入点位
+//!
+//! 库代码不会安装全局日志输出（日志通过 `tracing` 输出，由调用方决定是否安装 subscriber），
+//! 也不会调用 `std::process::exit`。
+//!
+//! # 示例
+//!
+//! 连接设备并读取两个保持寄存器：
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use modbus_pub::{ModbusClient, ModbusDevice, ModbusOperation};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut client = ModbusClient::new(ModbusDevice {
+//!     name: Some("PCS-A".to_string()),
+//!     ip: "192.168.1.100".to_string(),
+//!     port: 502,
+//!     slave_id: 1,
+//!     connect_timeout: Duration::from_secs(5),
+//!     request_timeout: Duration::from_secs(5),
+//! });
+//! client.connect().await?;
+//! let registers = client.read_registers(0x03, 100, 2).await?;
+//! println!("{:?}", registers);
+//! client.disconnect().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! 读取配置文件并按配置持续采集：
+//!
+//! ```no_run
+//! use modbus_pub::modbus::scheduler::PollEvent;
+//! use modbus_pub::read_config;
+//! use modbus_pub::reload::GatewayTasks;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = read_config("modbus_config.yaml")?;
+//! let mut tasks = GatewayTasks::new(|event: PollEvent| {
+//!     if let PollEvent::Readings(readings) = event {
+//!         for reading in readings {
+//!             println!("{} = {}", reading.point, reading.value);
+//!         }
+//!     }
+//! });
+//! tasks.apply(&config);
+//! # Ok(())
+//! # }
+//! ```
+
+#![warn(missing_docs)]
+
+/// 报警
+pub mod alarm;
+/// 按配置运行的完整程序
+pub mod app;
+/// 计算点位
+pub mod computed;
+/// CSV 文件输出
+pub mod csv;
+/// 配置文件
+pub mod device_configuration;
+/// 电量累计
+pub mod energy;
+/// 计算点位的表达式
+pub mod expression;
+/// HTTP 接口
+pub mod http;
+/// 最新采集值缓存
+pub mod latest;
+/// Prometheus 指标
+pub mod metrics;
+/// Modbus 采集
+pub mod modbus;
+/// MQTT 发布与远程控制
+pub mod mqtt;
+/// 采集数据分发
+pub mod pipeline;
+/// 采集任务管理与配置热加载
+pub mod reload;
+/// 历史数据补发
+pub mod replay;
+/// 定时写入
+pub mod schedule;
+/// 过期数据检测
+pub mod staleness;
+/// 本地存储
+pub mod storage;
+
+/// 测试用的模拟 Modbus 服务器和 MQTT Broker
+#[cfg(any(test, feature = "test-support"))]
+#[doc(hidden)]
+pub mod test_support;
+
+pub use device_configuration::modbus::{read_config, read_config_with, Config, LoadOptions};
+pub use modbus::client::{ModbusClient, ModbusDevice, ModbusOperation};
+pub use mqtt::client::MqttClient;
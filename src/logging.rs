@@ -0,0 +1,55 @@
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+use modbus_pub::device_configuration::logging::LogFormat;
+
+/// 未设置 RUST_LOG 时的日志级别
+const DEFAULT_FILTER: &str = "info";
+
+type FormatLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// 全局日志输出的句柄，读取配置后用于切换输出格式
+pub struct Logging {
+    format: reload::Handle<FormatLayer, Registry>,
+    stderr: bool,
+}
+
+impl Logging {
+    /// 安装全局日志输出，只应在程序启动时调用一次
+    ///
+    /// # 说明
+    /// * 日志级别由环境变量 RUST_LOG 决定，未设置或无法解析时为 info
+    /// * 读取配置之前使用文本格式，配置了 logging.format 后通过 [`Logging::set_format`] 切换
+    pub fn init() -> Logging {
+        Self::install(false)
+    }
+
+    /// 与 [`Logging::init`] 相同，但日志输出到标准错误，标准输出只留给命令的结果
+    pub fn init_stderr() -> Logging {
+        Self::install(true)
+    }
+
+    fn install(stderr: bool) -> Logging {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+        let (layer, format) = reload::Layer::new(format_layer(LogFormat::Plain, stderr));
+        tracing_subscriber::registry().with(layer).with(filter).init();
+        Logging { format, stderr }
+    }
+
+    /// 切换日志的输出格式
+    pub fn set_format(&self, format: LogFormat) {
+        if let Err(e) = self.format.reload(format_layer(format, self.stderr)) {
+            tracing::error!(error = %e, "切换日志格式失败");
+        }
+    }
+}
+
+fn format_layer(format: LogFormat, stderr: bool) -> FormatLayer {
+    match (format, stderr) {
+        (LogFormat::Plain, false) => fmt::layer().boxed(),
+        (LogFormat::Json, false) => fmt::layer().json().boxed(),
+        (LogFormat::Plain, true) => fmt::layer().with_writer(std::io::stderr).boxed(),
+        (LogFormat::Json, true) => fmt::layer().json().with_writer(std::io::stderr).boxed(),
+    }
+}
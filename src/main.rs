@@ -1,97 +1,138 @@
-mod device_configuration;
-mod modbus;
+mod cli;
+mod commands;
+mod daemon;
+mod logging;
+mod shutdown;
 
-use crate::modbus::client::{ModbusClient, ModbusDevice as ClientModbusDevice, ModbusOperation};
-use device_configuration::modbus::read_config;
+use crate::cli::{Cli, Command};
+use crate::daemon::{Notifier, PidFile};
+use crate::logging::Logging;
+use modbus_pub::app::{App, AppOptions};
+use modbus_pub::{read_config_with, LoadOptions};
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse(std::env::args().skip(1))?;
+    if cli.help {
+        println!("{}", Cli::usage());
+        return Ok(());
+    }
+    let logging = match &cli.command {
+        Command::TestConnection { .. } | Command::Read(_) | Command::Write(_) => {
+            Logging::init_stderr()
+        }
+        _ => Logging::init(),
+    };
+    match &cli.command {
+        Command::Run => {}
+        Command::ProfilesList => return commands::profiles_list(&cli.config_path),
+        Command::ProfilesShow(name) => return commands::profiles_show(&cli.config_path, name),
+        Command::Check {
+            probe,
+            required_only,
+        } => return commands::check(&cli.config_path, *probe, *required_only).await,
+        Command::TestConnection { json } => {
+            return commands::test_connection(&cli.config_path, *json).await;
+        }
+        Command::Read(args) => return commands::read(args).await,
+        Command::Write(args) => return commands::write(args).await,
+    }
+
+    // --daemon 时启动超时从这里开始计算
+    let started = tokio::time::Instant::now();
+
     // 指定 YAML 配置文件路径
-    let file_path = "modbus_config.yaml";
-    println!("正在读取配置文件: {}", file_path);
+    let file_path = cli.config_path.as_str();
+    info!(path = file_path, "正在读取配置文件");
 
     // 读取和解析 YAML 配置文件
-    let config = match read_config(file_path) {
+    let options = LoadOptions {
+        write_back_migration: cli.migrate_config,
+        simulate: cli.simulate,
+        ..LoadOptions::default()
+    };
+    let config = match read_config_with(file_path, &options) {
         Ok(cfg) => {
-            println!("配置文件加载成功");
+            info!("配置文件加载成功");
             cfg
         }
         Err(e) => {
-            eprintln!("无法读取配置文件: {}", e);
+            error!(error = %e, "无法读取配置文件");
             return Err(e);
         }
     };
-
+    if let Some(settings) = &config.logging {
+        logging.set_format(settings.format);
+    }
+    // --daemon 时写 PID 文件，由 systemd 启动时发送看门狗通知，就绪后发送 READY
+    let daemon_settings = config.daemon.clone().unwrap_or_default();
+    // PID 文件在 main 返回时删除
+    let _pid_file = match (&daemon_settings.pid_file, cli.daemon) {
+        (Some(path), true) => match PidFile::create(path) {
+            Ok(pid_file) => Some(pid_file),
+            Err(e) => {
+                error!(error = %e, "无法写入 PID 文件");
+                return Err(e);
+            }
+        },
+        _ => None,
+    };
+    let mut notifier = None;
+    if cli.daemon {
+        notifier = Notifier::from_env().map(Arc::new);
+        if let Some(notifier) = &notifier
+            && let Some(interval) = daemon::watchdog_interval()
+        {
+            info!(interval_ms = interval.as_millis() as u64, "启用 systemd 看门狗通知");
+            tokio::spawn(daemon::run_watchdog(Arc::clone(notifier), interval));
+        }
+    }
     // 检查是否有配置的网关设备
     if config.gateways.is_empty() {
-        println!("警告: 配置文件中没有定义Modbus设备");
+        warn!("配置文件中没有定义Modbus设备");
         return Ok(());
     }
 
-    // 遍历所有配置的网关设备
-    for gateway in &config.gateways {
-        println!("\n处理网关: {}:{}", gateway.ip, gateway.port);
-
-        // 遍历网关中的所有从站ID
-        for &slave_id in &gateway.slave_ids {
-            println!("连接从站ID: {}", slave_id);
-
-            // 从配置创建客户端设备实例
-            let device = ClientModbusDevice {
-                ip: gateway.ip.clone(),
-                port: gateway.port,
-                slave_id,
-            };
-
-            // 创建并连接客户端
-            let mut client = ModbusClient::new(device.clone());
-            match client.connect().await {
-                Ok(_) => println!(
-                    "成功连接到设备 {}:{} 从站ID {}",
-                    gateway.ip, gateway.port, slave_id
-                ),
-                Err(e) => {
-                    println!(
-                        "连接失败: {}:{} 从站ID {}, 错误: {:?}",
-                        gateway.ip, gateway.port, slave_id, e
-                    );
-                    continue; // 连接失败，继续处理下一个从站ID
-                }
-            }
-
-            // 暂停一小段时间确保连接稳定
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-            // 执行Modbus操作示例
-            println!("读取输入寄存器...");
-            match client.read_registers(0x04, 0, 4).await {
-                Ok(values) => println!("输入寄存器值: {:?}", values),
-                Err(e) => println!("读取输入寄存器失败: {:?}", e),
-            }
-
-            println!("读取保持寄存器...");
-            match client.read_registers(0x03, 0, 4).await {
-                Ok(values) => println!("保持寄存器值: {:?}", values),
-                Err(e) => println!("读取保持寄存器失败: {:?}", e),
-            }
-
-            // 示例写操作 (可选)
-            println!("写入线圈...");
-            match client.write_registers(0x0F, 0, 4, vec![1, 1, 1, 1]).await {
-                Ok(_) => println!("写入线圈成功"),
-                Err(e) => println!("写入线圈失败: {:?}", e),
-            }
+    let app_options = AppOptions {
+        load: options,
+        once: cli.once,
+    };
+    let app = App::start(config, file_path, app_options).await?;
 
-            // 断开连接
-            if let Err(e) = client.disconnect().await {
-                println!("断开连接失败: {:?}", e);
-            } else {
-                println!("已断开与设备的连接");
+    // --daemon 时等连接到 MQTT、各网关完成第一次采集后才算启动完成，超时以非0状态退出
+    if cli.daemon {
+        let deadline = started + Duration::from_millis(daemon_settings.startup_timeout_ms);
+        let ready = daemon::wait_until_ready(notifier.as_deref(), deadline, || {
+            app.pending(daemon_settings.wait_for_mqtt)
+        })
+        .await;
+        if let Err(e) = ready {
+            error!(error = %e, "启动失败");
+            if let Some(notifier) = &notifier {
+                notifier.status(&e.to_string());
             }
+            return Err(e);
+        }
+        info!("启动完成");
+        if let Some(notifier) = &notifier {
+            notifier.notify("READY=1\nSTATUS=正在采集");
         }
     }
 
-    println!("\n所有设备操作完成");
+    app.run(async {
+        shutdown::wait_for_signal().await;
+        // 停止采集并发布各从站 offline，再次收到信号时立即退出
+        info!("收到退出信号，正在停止采集");
+        if let Some(notifier) = &notifier {
+            notifier.notify("STOPPING=1\nSTATUS=正在停止采集");
+        }
+        shutdown::force_exit_on_signal();
+    })
+    .await;
     Ok(())
 }
+
@@ -0,0 +1,190 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+/// 请求和采集耗时直方图的分桶上限（秒），与 Prometheus 客户端库的默认值相同
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// 耗时直方图，按 [`DURATION_BUCKETS`] 分桶累计
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; DURATION_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    /// 记录一次耗时
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, count) in DURATION_BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bucket {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// 记录的次数
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// 记录的总耗时（秒）
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+}
+
+/// 按 Prometheus 文本格式（0.0.4）输出指标
+///
+/// 同一指标的所有样本应连续输出，每个指标先调用 [`MetricsText::header`]。
+#[derive(Debug, Default)]
+pub struct MetricsText {
+    text: String,
+}
+
+impl MetricsText {
+    /// 创建空的输出
+    pub fn new() -> Self {
+        MetricsText::default()
+    }
+
+    /// 输出指标的说明和类型
+    ///
+    /// # 参数说明
+    /// * `name` - 指标名称
+    /// * `kind` - 指标类型：counter、gauge 或 histogram
+    /// * `help` - 说明
+    pub fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    /// 输出一个样本
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        self.labels(labels, None);
+        let _ = writeln!(self.text, " {}", format_value(value));
+    }
+
+    /// 输出一个直方图的所有分桶、总和与次数
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let bucket = format!("{}_bucket", name);
+        for (le, count) in DURATION_BUCKETS.iter().zip(histogram.counts) {
+            self.text.push_str(&bucket);
+            self.labels(labels, Some(&format_value(*le)));
+            let _ = writeln!(self.text, " {}", count);
+        }
+        self.text.push_str(&bucket);
+        self.labels(labels, Some("+Inf"));
+        let _ = writeln!(self.text, " {}", histogram.count);
+        self.sample(&format!("{}_sum", name), labels, histogram.sum);
+        self.sample(&format!("{}_count", name), labels, histogram.count as f64);
+    }
+
+    /// 输出的文本
+    pub fn finish(self) -> String {
+        self.text
+    }
+
+    fn labels(&mut self, labels: &[(&str, &str)], le: Option<&str>) {
+        if labels.is_empty() && le.is_none() {
+            return;
+        }
+        self.text.push('{');
+        let le = le.map(|le| ("le", le));
+        for (index, (name, value)) in labels.iter().copied().chain(le).enumerate() {
+            if index > 0 {
+                self.text.push(',');
+            }
+            let _ = write!(self.text, "{}=\"{}\"", name, escape(value));
+        }
+        self.text.push('}');
+    }
+}
+
+// 标签值中的反斜杠、双引号和换行需要转义
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        for millis in [3, 20, 20, 700, 30_000] {
+            histogram.observe(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert!((histogram.sum() - 30.743).abs() < 1e-9);
+        assert_eq!(histogram.counts, [1, 1, 3, 3, 3, 3, 3, 4, 4, 4, 4]);
+    }
+
+    #[test]
+    fn text_format_has_headers_labels_and_histogram_series() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(50));
+        let mut text = MetricsText::new();
+        text.header("ems_up", "gauge", "是否运行");
+        text.sample("ems_up", &[], 1.0);
+        text.header("ems_duration_seconds", "histogram", "耗时");
+        text.histogram("ems_duration_seconds", &[("gateway", "PCS-A")], &histogram);
+        let text = text.finish();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..5], [
+            "# HELP ems_up 是否运行",
+            "# TYPE ems_up gauge",
+            "ems_up 1",
+            "# HELP ems_duration_seconds 耗时",
+            "# TYPE ems_duration_seconds histogram",
+        ]);
+        assert_eq!(lines[5], "ems_duration_seconds_bucket{gateway=\"PCS-A\",le=\"0.005\"} 0");
+        assert_eq!(lines[8], "ems_duration_seconds_bucket{gateway=\"PCS-A\",le=\"0.05\"} 1");
+        assert_eq!(lines[16], "ems_duration_seconds_bucket{gateway=\"PCS-A\",le=\"+Inf\"} 1");
+        assert_eq!(lines[17], "ems_duration_seconds_sum{gateway=\"PCS-A\"} 0.05");
+        assert_eq!(lines[18], "ems_duration_seconds_count{gateway=\"PCS-A\"} 1");
+        assert_eq!(lines.len(), 19);
+    }
+
+    #[test]
+    fn label_values_and_special_numbers_are_escaped() {
+        let mut text = MetricsText::new();
+        text.sample("m", &[("device", "a\"b\\c\nd")], f64::NAN);
+        text.sample("m", &[], f64::INFINITY);
+        text.sample("m", &[], f64::NEG_INFINITY);
+        text.sample("m", &[], -2.5);
+        assert_eq!(
+            text.finish(),
+            "m{device=\"a\\\"b\\\\c\\nd\"} NaN\nm +Inf\nm -Inf\nm -2.5\n"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// 设备可用性
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    /// 最近一次采集有成功的读请求
+    Online,
+    /// 最近一次采集全部失败，或网关已从配置中删除
+    Offline,
+    /// 网关或从站在配置中被停用
+    Disabled,
+}
+
+impl Availability {
+    /// 发布到可用性主题的内容
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Availability::Online => "online",
+            Availability::Offline => "offline",
+            Availability::Disabled => "disabled",
+        }
+    }
+}
+
+impl fmt::Display for Availability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 一个从站的可用性变化
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceAvailability {
+    /// 网关地址，格式为 ip:port
+    pub gateway: String,
+    /// 网关名称
+    pub gateway_name: Option<String>,
+    /// 从站ID
+    pub slave_id: u8,
+    /// 从站名称
+    pub slave_name: Option<String>,
+    /// 可用性
+    pub availability: Availability,
+}
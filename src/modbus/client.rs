@@ -1,21 +1,34 @@
 use std::error::Error;
-use std::time::Duration;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio_modbus::client::tcp;
 use tokio_modbus::client::Context;
+use tokio::sync::Semaphore;
 use tokio_modbus::prelude::*;
+use tracing::{debug, info, warn};
 
-// Modbus设备参数结构体
+use crate::modbus::simulator::Simulator;
+use crate::modbus::stats::{RequestResult, SharedStats};
+
+/// Modbus设备参数
 #[derive(Debug, Clone)]
 pub struct ModbusDevice {
+    /// 设备名称，用于日志显示，未配置时显示 ip:port
+    pub name: Option<String>,
     /// Modbus设备的IP地址
     pub ip: String,
     /// Modbus设备的端口号（默认502）
     pub port: u16,
     /// 从站ID（范围1-247）
     pub slave_id: u8,
+    /// 连接超时时间
+    pub connect_timeout: Duration,
+    /// 单次读写请求超时时间
+    pub request_timeout: Duration,
 }
 
-// Modbus操作trait
+/// Modbus读写操作
 #[async_trait::async_trait]
 pub trait ModbusOperation {
     /// 从Modbus设备读取寄存器
@@ -68,10 +81,18 @@ pub trait ModbusOperation {
     async fn disconnect(&mut self) -> Result<(), Box<dyn Error>>;
 }
 
-// Modbus客户端结构体
+/// Modbus TCP 客户端，同一网关下的多个从站可以通过 set_slave_id 共用一个连接
+///
+/// 设置了模拟设备时不建立 TCP 连接，读写由 [`Simulator`] 完成，统计和日志与真实设备相同。
 pub struct ModbusClient {
     device: ModbusDevice,
     ctx: Option<Context>,
+    stats: Option<SharedStats>,
+    simulator: Option<Simulator>,
+    // 所有网关共用的并发请求上限，见 Config::max_concurrent_requests
+    request_limit: Option<Arc<Semaphore>>,
+    // 模拟设备的“连接”状态，请求失败后与真实连接一样断开
+    simulated: bool,
 }
 
 impl ModbusClient {
@@ -79,129 +100,230 @@ impl ModbusClient {
     ///
     /// # 参数说明
     /// * `device` - Modbus设备配置:
+    ///   * name: 设备名称（可选，例如："PCS-B"）
     ///   * ip: 设备IP地址（例如："192.168.1.100"）
     ///   * port: 端口号（默认502）
     ///   * slave_id: 从站ID（范围1-247）
+    ///   * connect_timeout / request_timeout: 连接与读写超时时间
     pub fn new(device: ModbusDevice) -> Self {
-        ModbusClient { device, ctx: None }
+        ModbusClient {
+            device,
+            ctx: None,
+            stats: None,
+            simulator: None,
+            request_limit: None,
+            simulated: false,
+        }
+    }
+
+    /// 用模拟设备代替真实的网关
+    pub fn set_simulator(&mut self, simulator: Simulator) {
+        self.simulator = Some(simulator);
+    }
+
+    /// 把连接状态、请求数和请求耗时记录到网关的采集统计中
+    pub fn set_stats(&mut self, stats: SharedStats) {
+        self.stats = Some(stats);
+    }
+
+    /// 每个读写请求执行前先从 `limit` 取得许可，与其他网关共同限制同时进行的请求数量
+    pub fn set_request_limit(&mut self, limit: Arc<Semaphore>) {
+        self.request_limit = Some(limit);
+    }
+
+    /// 切换后续请求使用的从站ID
+    ///
+    /// # 说明
+    /// * 同一网关下的多个从站可以共用一个TCP连接
+    pub fn set_slave_id(&mut self, slave_id: u8) {
+        self.device.slave_id = slave_id;
+        if let Some(ctx) = self.ctx.as_mut() {
+            ctx.set_slave(Slave(slave_id));
+        }
+    }
+
+    /// 是否已连接
+    pub fn is_connected(&self) -> bool {
+        self.ctx.is_some() || self.simulated
     }
 
     /// 连接到Modbus服务器
     ///
     /// # 说明
-    /// * 连接超时时间为5秒
+    /// * 连接超时时间由设备配置的 connect_timeout 决定
     /// * 连接成功后才能执行读写操作
     ///
     /// # 返回值
     /// * `Ok(())` - 连接成功
     /// * `Err` - 连接失败，返回错误信息
     pub async fn connect(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.simulator.is_some() {
+            debug!(gateway = self.gateway(), "使用模拟设备");
+            self.simulated = true;
+            self.record_connected();
+            return Ok(());
+        }
         let socket_addr = format!("{}:{}", self.device.ip, self.device.port).parse()?;
         let slave = Slave(self.device.slave_id);
 
-        println!("尝试连接到Modbus服务器 {}...", socket_addr);
+        debug!(
+            gateway = self.gateway(),
+            address = %socket_addr,
+            "尝试连接到Modbus服务器"
+        );
 
         match tokio::time::timeout(
-            Duration::from_secs(5),
+            self.device.connect_timeout,
             tcp::connect_slave(socket_addr, slave),
         )
         .await
         {
             Ok(result) => match result {
                 Ok(ctx) => {
-                    println!("成功连接到服务器");
+                    info!(gateway = self.gateway(), "成功连接到Modbus服务器");
                     self.ctx = Some(ctx);
+                    self.record_connected();
                     Ok(())
                 }
                 Err(e) => {
-                    println!("连接失败: {:?}", e);
+                    warn!(gateway = self.gateway(), error = %e, "连接Modbus服务器失败");
                     Err(e.into())
                 }
             },
             Err(_) => {
-                println!("连接尝试超时");
-                Err("Connection timeout".into())
+                warn!(
+                    gateway = self.gateway(),
+                    timeout_ms = self.device.connect_timeout.as_millis() as u64,
+                    "连接Modbus服务器超时"
+                );
+                Err(io::Error::new(io::ErrorKind::TimedOut, "连接超时").into())
             }
         }
     }
-}
 
-#[async_trait::async_trait]
-impl ModbusOperation for ModbusClient {
-    async fn read_registers(
+    // 请求出错（异常响应除外）或超时后连接可能已失效，或者迟到的响应会错位，
+    // 关闭连接，下次采集前重新连接
+    fn drop_broken_connection(&mut self, error: &(dyn Error + 'static)) {
+        if error.downcast_ref::<ExceptionCode>().is_none()
+            && (self.ctx.take().is_some() || std::mem::take(&mut self.simulated))
+        {
+            warn!(gateway = self.gateway(), "请求失败，关闭连接");
+            self.record_connected();
+        }
+    }
+
+    fn record_connected(&self) {
+        if let Some(stats) = &self.stats {
+            stats.lock().unwrap_or_else(|e| e.into_inner()).connected = self.is_connected();
+        }
+    }
+
+    fn record_request<T>(
+        &self,
+        function_code: u8,
+        started: Instant,
+        result: &Result<T, Box<dyn Error>>,
+    ) {
+        if let Some(stats) = &self.stats {
+            let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+            let key = (self.device.slave_id, function_code, RequestResult::of(result));
+            *stats.requests.entry(key).or_default() += 1;
+            stats.request_durations.observe(started.elapsed());
+        }
+    }
+
+    // 日志中的网关标识，未配置名称时为 ip:port
+    fn gateway(&self) -> String {
+        self.device
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{}:{}", self.device.ip, self.device.port))
+    }
+
+    async fn read(
         &mut self,
         function_code: u8,
         address: u16,
         quantity: u16,
     ) -> Result<Vec<u16>, Box<dyn Error>> {
+        if let Some(simulator) = self.simulator.as_mut() {
+            if !self.simulated {
+                return Err("客户端未连接".into());
+            }
+            return simulator.read(self.device.slave_id, function_code, address, quantity);
+        }
+        let timeout = self.device.request_timeout;
         let ctx = self.ctx.as_mut().ok_or("客户端未连接")?;
 
         let result = match function_code {
             //OXO1 读取线圈
             0x01 => {
                 let coils =
-                    tokio::time::timeout(Duration::from_secs(5), ctx.read_coils(address, quantity))
-                        .await?
-                        .expect("读取线圈失败");
-
-                // 展平嵌套的 Vec<Vec<bool>> 并映射为 Vec<u16>
-                let coils_vec: Vec<u16> = coils
-                    .into_iter() // 获取 Vec<Vec<bool>> 的所有权
-                    .flatten() // 展平为 Vec<bool>
-                    .map(|b| if b { 1 } else { 0 }) // 将每个 bool 映射为 u16
-                    .collect();
-                Ok(coils_vec)
+                    tokio::time::timeout(timeout, ctx.read_coils(address, quantity))
+                        .await??;
+
+                // 异常响应保留为错误，读到的线圈映射为 0/1
+                coils.map(|coils| coils.into_iter().map(u16::from).collect())
             }
             //OXO2 读取输入寄存器
             0x02 => {
                 let discrete_inputs = tokio::time::timeout(
-                    Duration::from_secs(1),
+                    timeout,
                     ctx.read_discrete_inputs(address, quantity),
                 )
-                .await?
-                .expect("读取离散输入失败");
-
-                // 展平嵌套的 Vec<Vec<bool>> 并映射为 Vec<u16>
-                let discrete_inputs_vec: Vec<u16> = discrete_inputs
-                    .into_iter() // 获取 Vec<Vec<bool>> 的所有权
-                    .flatten() // 展平为 Vec<bool>
-                    .map(|b| if b { 1 } else { 0 }) // 将每个 bool 映射为 u16
-                    .collect();
-                Ok(discrete_inputs_vec)
+                .await??;
+
+                discrete_inputs.map(|inputs| inputs.into_iter().map(u16::from).collect())
             }
             //OXO3 读取保持寄存器
             0x03 => tokio::time::timeout(
-                Duration::from_secs(5),
+                timeout,
                 ctx.read_holding_registers(address, quantity),
             )
-            .await?
-            .expect("读取保持寄存器失败"),
+            .await??,
             //OXO4 读取输入寄存器
             0x04 => tokio::time::timeout(
-                Duration::from_secs(5),
+                timeout,
                 ctx.read_input_registers(address, quantity),
             )
-            .await?
-            .expect("读取输入寄存器失败"),
+            .await??,
             _ => return Err("不支持的功能码".into()),
         };
 
         match result {
             Ok(response) => Ok(response),
             Err(e) => {
-                println!("读取失败: {:?}", e);
+                warn!(
+                    gateway = self.gateway(),
+                    slave_id = self.device.slave_id,
+                    function_code,
+                    address,
+                    quantity,
+                    exception = ?e,
+                    "读取失败"
+                );
                 Err(e.into())
             }
         }
     }
 
-    async fn write_registers(
+    async fn write(
         &mut self,
         function_code: u8,
         address: u16,
         quantity: u16,
         values: Vec<u16>,
     ) -> Result<(), Box<dyn Error>> {
+        if let Some(simulator) = self.simulator.as_mut() {
+            if !self.simulated {
+                return Err("客户端未连接".into());
+            }
+            if values.len() != quantity as usize {
+                return Err("值的长度与数量不匹配".into());
+            }
+            return simulator.write(self.device.slave_id, function_code, address, &values);
+        }
+        let timeout = self.device.request_timeout;
         let ctx = self.ctx.as_mut().ok_or("客户端未连接")?;
 
         let result = match function_code {
@@ -212,7 +334,7 @@ impl ModbusOperation for ModbusClient {
                 }
 
                 let coil = values[0] >= 1;
-                tokio::time::timeout(Duration::from_secs(5), ctx.write_single_coil(address, coil))
+                tokio::time::timeout(timeout, ctx.write_single_coil(address, coil))
                     .await
             }
             //0x0F 写入多个线圈
@@ -222,7 +344,7 @@ impl ModbusOperation for ModbusClient {
                 }
                 let coils: Vec<bool> = values.into_iter().map(|v| v >= 1).collect();
                 tokio::time::timeout(
-                    Duration::from_secs(5),
+                    timeout,
                     ctx.write_multiple_coils(address, &coils),
                 )
                 .await
@@ -233,7 +355,7 @@ impl ModbusOperation for ModbusClient {
                     return Err("功能码0x06仅支持写入单个寄存器".into());
                 }
                 tokio::time::timeout(
-                    Duration::from_secs(5),
+                    timeout,
                     ctx.write_single_register(address, values[0]),
                 )
                 .await
@@ -244,7 +366,7 @@ impl ModbusOperation for ModbusClient {
                     return Err("值的长度与数量不匹配".into());
                 }
                 tokio::time::timeout(
-                    Duration::from_secs(5),
+                    timeout,
                     ctx.write_multiple_registers(address, &values),
                 )
                 .await
@@ -254,27 +376,215 @@ impl ModbusOperation for ModbusClient {
 
         match result {
             Ok(response) => match response {
-                Ok(_) => Ok(()),
+                Ok(Ok(())) => Ok(()),
+                // 异常响应也是写入失败
+                Ok(Err(exception)) => {
+                    warn!(
+                        gateway = self.gateway(),
+                        slave_id = self.device.slave_id,
+                        function_code,
+                        address,
+                        exception = ?exception,
+                        "写入失败"
+                    );
+                    Err(exception.into())
+                }
                 Err(e) => {
-                    println!("写入失败: {:?}", e);
+                    warn!(
+                        gateway = self.gateway(),
+                        slave_id = self.device.slave_id,
+                        function_code,
+                        address,
+                        exception = ?e,
+                        "写入失败"
+                    );
                     Err(e.into())
                 }
             },
             Err(_) => {
-                println!("写入超时");
-                Err("写入超时".into())
+                warn!(
+                    gateway = self.gateway(),
+                    slave_id = self.device.slave_id,
+                    function_code,
+                    address,
+                    "写入超时"
+                );
+                Err(io::Error::new(io::ErrorKind::TimedOut, "写入超时").into())
             }
         }
     }
+}
+
+#[async_trait::async_trait]
+impl ModbusOperation for ModbusClient {
+    async fn read_registers(
+        &mut self,
+        function_code: u8,
+        address: u16,
+        quantity: u16,
+    ) -> Result<Vec<u16>, Box<dyn Error>> {
+        // 等待许可的时间不计入请求耗时
+        let _permit = acquire(&self.request_limit).await;
+        let started = Instant::now();
+        let result = self.read(function_code, address, quantity).await;
+        self.record_request(function_code, started, &result);
+        if let Err(e) = &result {
+            self.drop_broken_connection(e.as_ref());
+        }
+        result
+    }
+
+    async fn write_registers(
+        &mut self,
+        function_code: u8,
+        address: u16,
+        quantity: u16,
+        values: Vec<u16>,
+    ) -> Result<(), Box<dyn Error>> {
+        let _permit = acquire(&self.request_limit).await;
+        let started = Instant::now();
+        let result = self.write(function_code, address, quantity, values).await;
+        self.record_request(function_code, started, &result);
+        if let Err(e) = &result {
+            self.drop_broken_connection(e.as_ref());
+        }
+        result
+    }
 
     async fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Some(ctx) = self.ctx.as_mut() {
+        if std::mem::take(&mut self.simulated) {
+            self.record_connected();
+        }
+        if let Some(mut ctx) = self.ctx.take() {
+            self.record_connected();
             if let Err(e) = ctx.disconnect().await {
-                println!("断开连接失败: {:?}", e);
+                warn!(gateway = self.gateway(), error = %e, "断开连接失败");
                 return Err(e.into());
             }
-            println!("连接已关闭");
+            debug!(gateway = self.gateway(), "连接已关闭");
         }
         Ok(())
     }
 }
+
+// 取得一个并发请求许可，未设置上限时直接返回
+async fn acquire(limit: &Option<Arc<Semaphore>>) -> Option<tokio::sync::OwnedSemaphorePermit> {
+    // 信号量不会被关闭
+    Arc::clone(limit.as_ref()?).acquire_owned().await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::modbus::scheduler::GatewayPoller;
+    use crate::test_support::MockModbus;
+    use serde_json::Value;
+    use std::sync::Mutex;
+
+    // 收集 JSON 格式日志输出的缓冲区
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn events(&self) -> Vec<Value> {
+            let output = self.0.lock().unwrap();
+            output
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| serde_json::from_slice(line).unwrap())
+                .collect()
+        }
+    }
+
+    // 在当前线程上以 JSON 格式收集日志，返回的 guard 被丢弃前有效
+    fn capture() -> (Captured, tracing::subscriber::DefaultGuard) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(move || writer.clone()),
+        );
+        (captured, tracing::subscriber::set_default(subscriber))
+    }
+
+    #[tokio::test]
+    async fn failed_read_emits_warn_event_with_device_fields() {
+        let (captured, _guard) = capture();
+
+        let modbus = MockModbus::start().await;
+        modbus.reject(7, 0x02);
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    slave_ids: [7]\n    points:\n      - {{ name: power, address: 40, data_type: u32 }}\n",
+            modbus.port
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        poller.run_once(|_| {}).await;
+
+        let events = captured.events();
+        let event = events
+            .iter()
+            .find(|e| e["fields"]["message"] == "读取失败")
+            .unwrap_or_else(|| panic!("没有读取失败的日志: {:#?}", events));
+        assert_eq!(event["level"], "WARN");
+        let fields = &event["fields"];
+        assert_eq!(fields["gateway"], "PCS-A");
+        assert_eq!(fields["slave_id"], 7);
+        assert_eq!(fields["function_code"], 3);
+        assert_eq!(fields["address"], 40);
+        assert_eq!(fields["quantity"], 2);
+        assert!(fields["exception"].as_str().unwrap().contains("IllegalDataAddress"), "{}", fields);
+        // 事件位于采集的 span 中，带有网关、从站和采集组
+        assert_eq!(event["span"]["name"], "poll");
+        assert_eq!(event["span"]["gateway"], "PCS-A");
+        assert_eq!(event["span"]["slave_id"], 7);
+        // 连接成功是 info，不是 warn
+        assert!(
+            events
+                .iter()
+                .any(|e| e["fields"]["message"] == "成功连接到Modbus服务器" && e["level"] == "INFO")
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_failure_is_a_warn_event() {
+        let (captured, _guard) = capture();
+
+        // 绑定后立即释放的端口上没有服务器
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut client = ModbusClient::new(ModbusDevice {
+            name: None,
+            ip: "127.0.0.1".to_string(),
+            port,
+            slave_id: 1,
+            connect_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(1),
+        });
+        assert!(client.connect().await.is_err());
+
+        let events = captured.events();
+        let event = events
+            .iter()
+            .find(|e| e["fields"]["message"] == "连接Modbus服务器失败")
+            .unwrap_or_else(|| panic!("没有连接失败的日志: {:#?}", events));
+        assert_eq!(event["level"], "WARN");
+        assert_eq!(event["fields"]["gateway"], format!("127.0.0.1:{}", port));
+        assert!(event["fields"]["error"].is_string());
+    }
+}
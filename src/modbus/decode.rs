@@ -0,0 +1,398 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+
+/// 点位的数据类型，决定占用的寄存器数量以及如何解析寄存器值
+///
+/// 配置中写作小写名称，例如 `f32`；`string` 的长度由点位的 `length` 决定，解析后为0。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataType {
+    /// 线圈/离散输入，或寄存器非0即为真
+    Bool,
+    /// 16位无符号整数（1个寄存器）
+    #[default]
+    U16,
+    /// 16位有符号整数（1个寄存器）
+    I16,
+    /// 32位无符号整数（2个寄存器）
+    U32,
+    /// 32位有符号整数（2个寄存器）
+    I32,
+    /// 32位浮点数（2个寄存器）
+    F32,
+    /// 64位无符号整数（4个寄存器）
+    U64,
+    /// 64位有符号整数（4个寄存器）
+    I64,
+    /// 64位浮点数（4个寄存器）
+    F64,
+    /// ASCII 字符串，每个寄存器两个字符
+    String {
+        /// 占用的寄存器数量
+        length_registers: u16,
+    },
+}
+
+// 配置中可以使用的数据类型名称
+const DATA_TYPE_NAMES: &[&str] = &["bool", "u16", "i16", "u32", "i32", "f32", "u64", "i64", "f64", "string"];
+
+impl DataType {
+    /// 该数据类型占用的寄存器（或线圈）数量
+    pub fn register_count(&self) -> u16 {
+        match self {
+            DataType::Bool | DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+            DataType::String { length_registers } => *length_registers,
+        }
+    }
+
+    /// 该数据类型能表示的原始值范围（最小值，最大值）
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            DataType::Bool => (0.0, 1.0),
+            DataType::U16 => (0.0, u16::MAX as f64),
+            DataType::I16 => (i16::MIN as f64, i16::MAX as f64),
+            DataType::U32 => (0.0, u32::MAX as f64),
+            DataType::I32 => (i32::MIN as f64, i32::MAX as f64),
+            DataType::F32 => (f32::MIN as f64, f32::MAX as f64),
+            DataType::U64 => (0.0, u64::MAX as f64),
+            DataType::I64 => (i64::MIN as f64, i64::MAX as f64),
+            DataType::F64 => (f64::MIN, f64::MAX),
+            DataType::String { .. } => (0.0, 0.0),
+        }
+    }
+
+    /// 是否为浮点类型
+    pub fn is_float(&self) -> bool {
+        matches!(self, DataType::F32 | DataType::F64)
+    }
+
+    /// 是否为字符串类型
+    pub fn is_string(&self) -> bool {
+        matches!(self, DataType::String { .. })
+    }
+
+    /// 配置中使用的名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataType::Bool => "bool",
+            DataType::U16 => "u16",
+            DataType::I16 => "i16",
+            DataType::U32 => "u32",
+            DataType::I32 => "i32",
+            DataType::F32 => "f32",
+            DataType::U64 => "u64",
+            DataType::I64 => "i64",
+            DataType::F64 => "f64",
+            DataType::String { .. } => "string",
+        }
+    }
+}
+
+impl Serialize for DataType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for DataType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        let data_type = match name.as_str() {
+            "bool" => DataType::Bool,
+            "u16" => DataType::U16,
+            "i16" => DataType::I16,
+            "u32" => DataType::U32,
+            "i32" => DataType::I32,
+            "f32" => DataType::F32,
+            "u64" => DataType::U64,
+            "i64" => DataType::I64,
+            "f64" => DataType::F64,
+            "string" => DataType::String { length_registers: 0 },
+            other => return Err(serde::de::Error::unknown_variant(other, DATA_TYPE_NAMES)),
+        };
+        Ok(data_type)
+    }
+}
+
+/// 多寄存器数据的字节序，以 ABCD 表示大端顺序的 4 个字节
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WordOrder {
+    /// 大端，高字在前（Modbus 标准顺序）
+    #[default]
+    Abcd,
+    /// 字交换，低字在前
+    Cdab,
+    /// 字内字节交换
+    Badc,
+    /// 小端
+    Dcba,
+}
+
+impl WordOrder {
+    // 按字节序把寄存器整理成大端字节序列
+    fn to_big_endian(self, registers: &[u16]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = registers.iter().flat_map(|r| r.to_be_bytes()).collect();
+        match self {
+            WordOrder::Abcd => {}
+            WordOrder::Dcba => bytes.reverse(),
+            WordOrder::Badc => bytes.chunks_mut(2).for_each(|word| word.swap(0, 1)),
+            WordOrder::Cdab => {
+                bytes.reverse();
+                bytes.chunks_mut(2).for_each(|word| word.swap(0, 1));
+            }
+        }
+        bytes
+    }
+}
+
+/// 将寄存器值解析为数值
+///
+/// # 参数说明
+/// * `data_type` - 数据类型
+/// * `word_order` - 字节序，只影响多寄存器类型
+/// * `registers` - 原始寄存器值，长度至少为 `data_type.register_count()`
+///
+/// # 返回值
+/// * `Some(f64)` - 解析后的数值
+/// * `None` - 寄存器数量不足，或数据类型为字符串（使用 [`decode_string`]）
+pub fn decode(data_type: DataType, word_order: WordOrder, registers: &[u16]) -> Option<f64> {
+    let count = data_type.register_count() as usize;
+    if registers.len() < count || data_type.is_string() {
+        return None;
+    }
+    let registers = &registers[..count];
+    let value = match data_type {
+        DataType::Bool => (registers[0] != 0) as u8 as f64,
+        DataType::U16 => registers[0] as f64,
+        DataType::I16 => registers[0] as i16 as f64,
+        _ => {
+            let bytes = word_order.to_big_endian(registers);
+            match data_type {
+                DataType::U32 => u32::from_be_bytes(bytes[..4].try_into().ok()?) as f64,
+                DataType::I32 => i32::from_be_bytes(bytes[..4].try_into().ok()?) as f64,
+                DataType::F32 => f32::from_be_bytes(bytes[..4].try_into().ok()?) as f64,
+                DataType::U64 => u64::from_be_bytes(bytes[..8].try_into().ok()?) as f64,
+                DataType::I64 => i64::from_be_bytes(bytes[..8].try_into().ok()?) as f64,
+                DataType::F64 => f64::from_be_bytes(bytes[..8].try_into().ok()?),
+                DataType::Bool | DataType::U16 | DataType::I16 | DataType::String { .. } => {
+                    unreachable!()
+                }
+            }
+        }
+    };
+    Some(value)
+}
+
+/// 将数值编码为寄存器值，是 `decode` 的逆过程
+///
+/// # 参数说明
+/// * `data_type` - 数据类型
+/// * `word_order` - 字节序，只影响多寄存器类型
+/// * `value` - 要写入的原始值（未应用 scale 和 offset），整数类型四舍五入
+///
+/// # 返回值
+/// * `Ok(Vec<u16>)` - 长度为 `data_type.register_count()` 的寄存器值
+/// * `Err` - 数值不是有限数，超出数据类型的范围，或数据类型为字符串（使用 [`encode_string`]）
+pub fn encode(
+    data_type: DataType,
+    word_order: WordOrder,
+    value: f64,
+) -> Result<Vec<u16>, Box<dyn Error>> {
+    if data_type.is_string() {
+        return Err("字符串类型只能写入字符串".into());
+    }
+    if !value.is_finite() {
+        return Err(format!("数值 {} 不是有限数", value).into());
+    }
+    let out_of_range = || format!("数值 {} 超出 {:?} 的范围", value, data_type);
+    let integer = value.round();
+    let in_range = |min: f64, max: f64| (min..=max).contains(&integer);
+
+    let bytes: Vec<u8> = match data_type {
+        DataType::Bool => {
+            if value != 0.0 && value != 1.0 {
+                return Err(format!("布尔点位只能写入0或1，当前为 {}", value).into());
+            }
+            return Ok(vec![value as u16]);
+        }
+        DataType::U16 if in_range(0.0, u16::MAX as f64) => return Ok(vec![integer as u16]),
+        DataType::I16 if in_range(i16::MIN as f64, i16::MAX as f64) => {
+            return Ok(vec![integer as i16 as u16]);
+        }
+        DataType::U32 if in_range(0.0, u32::MAX as f64) => (integer as u32).to_be_bytes().to_vec(),
+        DataType::I32 if in_range(i32::MIN as f64, i32::MAX as f64) => {
+            (integer as i32).to_be_bytes().to_vec()
+        }
+        DataType::F32 if value.abs() <= f32::MAX as f64 => (value as f32).to_be_bytes().to_vec(),
+        // u64/i64 的上限无法用 f64 精确表示，等于上限时同样视为超出范围
+        DataType::U64 if integer >= 0.0 && integer < u64::MAX as f64 => {
+            (integer as u64).to_be_bytes().to_vec()
+        }
+        DataType::I64 if integer >= i64::MIN as f64 && integer < i64::MAX as f64 => {
+            (integer as i64).to_be_bytes().to_vec()
+        }
+        DataType::F64 => value.to_be_bytes().to_vec(),
+        _ => return Err(out_of_range().into()),
+    };
+
+    // 字节序变换都是自身的逆变换
+    let words: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    Ok(word_order
+        .to_big_endian(&words)
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+/// 将寄存器值解析为字符串
+///
+/// 每个寄存器两个字节，默认高字节在前；`word_order` 为 `badc` 时低字节在前，其他字节序按 `abcd` 处理。
+/// 去掉末尾的 NUL 和空格，不是合法 UTF-8 的字节替换为 U+FFFD。
+pub fn decode_string(word_order: WordOrder, registers: &[u16]) -> String {
+    let bytes = string_bytes(word_order, registers);
+    String::from_utf8_lossy(&bytes)
+        .trim_end_matches(['\0', ' '])
+        .to_string()
+}
+
+/// 将字符串编码为寄存器值，是 [`decode_string`] 的逆过程，不足的部分用 NUL 填充
+///
+/// # 返回值
+/// * `Ok(Vec<u16>)` - 长度为 `length_registers` 的寄存器值
+/// * `Err` - 字符串的 UTF-8 字节数超过 `length_registers * 2`
+pub fn encode_string(
+    word_order: WordOrder,
+    text: &str,
+    length_registers: u16,
+) -> Result<Vec<u16>, Box<dyn Error>> {
+    let capacity = length_registers as usize * 2;
+    if text.len() > capacity {
+        return Err(format!(
+            "字符串 \"{}\" 有 {} 个字节，超过 {} 个寄存器能容纳的 {} 个字节",
+            text,
+            text.len(),
+            length_registers,
+            capacity
+        )
+        .into());
+    }
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(capacity, 0);
+    let words: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    // 字内字节交换是自身的逆变换
+    Ok(string_bytes(word_order, &words)
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+// 字符串只区分字内字节是否交换，寄存器之间总是按地址顺序
+fn string_bytes(word_order: WordOrder, registers: &[u16]) -> Vec<u8> {
+    match word_order {
+        WordOrder::Badc => WordOrder::Badc.to_big_endian(registers),
+        _ => WordOrder::Abcd.to_big_endian(registers),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::modbus::scheduler::{GatewayPoller, PollEvent};
+    use crate::test_support::MockModbus;
+
+    // "SN-01" 按标准顺序（高字节在前）存放在4个寄存器中，末尾用 NUL 填充
+    const SERIAL: [u16; 4] = [0x534E, 0x2D30, 0x3100, 0x0000];
+    // 同一字符串，每个寄存器内字节交换
+    const SERIAL_SWAPPED: [u16; 4] = [0x4E53, 0x302D, 0x0031, 0x0000];
+
+    #[test]
+    fn strings_trim_trailing_nul_and_spaces() {
+        assert_eq!(decode_string(WordOrder::Abcd, &SERIAL), "SN-01");
+        assert_eq!(decode_string(WordOrder::Abcd, &[0x4142, 0x2020, 0x2000]), "AB");
+        assert_eq!(decode_string(WordOrder::Abcd, &[0x0000, 0x0000]), "");
+        // 中间的 NUL 和开头的空格保留
+        assert_eq!(decode_string(WordOrder::Abcd, &[0x2041, 0x0042]), " A\0B");
+    }
+
+    #[test]
+    fn strings_with_swapped_bytes() {
+        assert_eq!(decode_string(WordOrder::Badc, &SERIAL_SWAPPED), "SN-01");
+        assert_eq!(decode_string(WordOrder::Abcd, &SERIAL_SWAPPED), "NS0-\x001");
+        // 寄存器之间总是按地址顺序，cdab 和 dcba 与 abcd 相同
+        assert_eq!(decode_string(WordOrder::Cdab, &SERIAL), "SN-01");
+        assert_eq!(decode_string(WordOrder::Dcba, &SERIAL), "SN-01");
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        // "温" 为 E6 B8 A9
+        assert_eq!(decode_string(WordOrder::Abcd, &[0xE6B8, 0xA900]), "温");
+        assert_eq!(decode_string(WordOrder::Abcd, &[0x41FF, 0x4200]), "A\u{FFFD}B");
+        // 末尾被截断的多字节字符
+        assert_eq!(decode_string(WordOrder::Abcd, &[0x4142, 0xE6B8]), "AB\u{FFFD}");
+    }
+
+    #[test]
+    fn encode_pads_with_nul_and_round_trips() {
+        assert_eq!(encode_string(WordOrder::Abcd, "SN-01", 4).unwrap(), SERIAL);
+        assert_eq!(encode_string(WordOrder::Badc, "SN-01", 4).unwrap(), SERIAL_SWAPPED);
+        assert_eq!(encode_string(WordOrder::Abcd, "", 2).unwrap(), [0, 0]);
+        assert_eq!(encode_string(WordOrder::Abcd, "温", 2).unwrap(), [0xE6B8, 0xA900]);
+        for order in [WordOrder::Abcd, WordOrder::Badc] {
+            let registers = encode_string(order, "FW 1.2.3", 8).unwrap();
+            assert_eq!(decode_string(order, &registers), "FW 1.2.3");
+        }
+
+        let error = encode_string(WordOrder::Abcd, "SN-0001", 3).unwrap_err();
+        assert!(error.to_string().contains("有 7 个字节，超过 3 个寄存器能容纳的 6 个字节"), "{}", error);
+        // 按 UTF-8 字节数计算
+        assert!(encode_string(WordOrder::Abcd, "温度", 2).is_err());
+    }
+
+    #[test]
+    fn numeric_paths_reject_strings() {
+        let string = DataType::String { length_registers: 4 };
+        assert_eq!(string.register_count(), 4);
+        assert_eq!(decode(string, WordOrder::Abcd, &SERIAL), None);
+        assert!(encode(string, WordOrder::Abcd, 1.0).is_err());
+    }
+
+    #[tokio::test]
+    async fn string_points_are_read_from_the_mock_server() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 100, &SERIAL);
+        modbus.set(1, 3, 200, &SERIAL_SWAPPED);
+        let yaml = format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    slave_ids: [1]\n    points:\n      - {{ name: serial, address: 100, data_type: string, length: 4 }}\n      - {{ name: swapped, address: 200, data_type: string, length: 4, word_order: badc }}\n",
+            modbus.port
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        let mut texts = Vec::new();
+        poller
+            .run_once(|event| {
+                if let PollEvent::Readings(readings) = event {
+                    texts.extend(readings.into_iter().map(|r| (r.point, r.text, r.raw)));
+                }
+            })
+            .await;
+        texts.sort();
+        assert_eq!(
+            texts,
+            [
+                ("serial".to_string(), Some("SN-01".to_string()), SERIAL.to_vec()),
+                ("swapped".to_string(), Some("SN-01".to_string()), SERIAL_SWAPPED.to_vec()),
+            ]
+        );
+    }
+}
@@ -1 +1,22 @@
+/// 从站可用性（在线、离线、停用）
+pub mod availability;
+/// Modbus TCP 客户端
 pub mod client;
+/// 寄存器值与数据类型之间的转换
+pub mod decode;
+/// `check --probe` 使用的设备探测
+pub mod probe;
+/// 把地址相邻的点位合并为读请求
+pub mod read_plan;
+/// 采集得到的点位数据
+pub mod reading;
+/// 单个网关按采集组周期采集的调度器
+pub mod scheduler;
+/// 模拟设备
+pub mod simulator;
+/// 采集统计
+pub mod stats;
+/// SunSpec 模型发现和点位生成
+pub mod sunspec;
+/// 通信看门狗
+pub mod watchdog;
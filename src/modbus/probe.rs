@@ -0,0 +1,406 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::time::error::Elapsed;
+use tokio_modbus::ExceptionCode;
+
+use crate::device_configuration::modbus::{Config, ModbusDevice as GatewayConfig};
+use crate::device_configuration::slave::SlaveConfig;
+use crate::modbus::client::{ModbusClient, ModbusDevice, ModbusOperation};
+use crate::modbus::read_plan::ReadPlan;
+
+/// 探测时连接和读取的默认超时时间，网关配置的超时更短时使用配置值
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 连接测试时同时测试的网关数
+pub const TEST_CONNECTION_CONCURRENCY: usize = 8;
+
+/// 单项探测结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeStatus {
+    /// 成功
+    Ok,
+    /// 连接或读取超时
+    Timeout,
+    /// 从站返回异常响应
+    Exception(ExceptionCode),
+    /// 网关拒绝连接
+    Refused,
+    /// 其他错误
+    Failed(String),
+    /// 前置步骤失败，未进行探测
+    Skipped,
+}
+
+impl ProbeStatus {
+    /// 是否算作失败（未探测的项由前置步骤的失败体现）
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, ProbeStatus::Ok | ProbeStatus::Skipped)
+    }
+
+    /// 用于机器读取的结果名称：ok、timeout、refused、exception_0xNN、error 或 skipped
+    pub fn code(&self) -> String {
+        match self {
+            ProbeStatus::Ok => "ok".to_string(),
+            ProbeStatus::Timeout => "timeout".to_string(),
+            ProbeStatus::Exception(code) => format!("exception_0x{:02x}", u8::from(*code)),
+            ProbeStatus::Refused => "refused".to_string(),
+            ProbeStatus::Failed(_) => "error".to_string(),
+            ProbeStatus::Skipped => "skipped".to_string(),
+        }
+    }
+
+    // 根据错误类型区分超时、异常响应和其他错误
+    fn from_error(error: &(dyn Error + 'static)) -> ProbeStatus {
+        if error.downcast_ref::<Elapsed>().is_some() {
+            return ProbeStatus::Timeout;
+        }
+        if let Some(e) = error.downcast_ref::<io::Error>() {
+            match e.kind() {
+                io::ErrorKind::TimedOut => return ProbeStatus::Timeout,
+                io::ErrorKind::ConnectionRefused => return ProbeStatus::Refused,
+                _ => {}
+            }
+        }
+        if let Some(code) = error.downcast_ref::<ExceptionCode>() {
+            return ProbeStatus::Exception(*code);
+        }
+        ProbeStatus::Failed(error.to_string())
+    }
+}
+
+impl fmt::Display for ProbeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeStatus::Ok => write!(f, "OK"),
+            ProbeStatus::Timeout => write!(f, "超时"),
+            ProbeStatus::Exception(code) => write!(f, "异常 0x{:02X}", u8::from(*code)),
+            ProbeStatus::Refused => write!(f, "拒绝连接"),
+            ProbeStatus::Failed(message) => write!(f, "失败: {}", message),
+            ProbeStatus::Skipped => write!(f, "未探测"),
+        }
+    }
+}
+
+/// 探测报告中的一行
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    /// 探测对象，例如 "网关 PCS-B" 或 "从站 1 功能码0x03 地址0 数量10"
+    pub item: String,
+    /// 探测结果
+    pub status: ProbeStatus,
+    /// 所属网关是否为可选设备
+    pub optional: bool,
+}
+
+/// 探测一个网关：连接网关，再逐个从站读取
+///
+/// # 说明
+/// * 从站配置了点位时，按读取计划对每个读请求各读一次
+/// * 从站没有点位时，读取一次保持寄存器0作为探测
+/// * 网关连接失败时，其下所有从站记为未探测
+pub async fn probe_gateway(
+    config: &Config,
+    gateway: &GatewayConfig,
+    timeout: Duration,
+) -> Vec<ProbeResult> {
+    let optional = gateway.optional;
+    let result = |item: String, status: ProbeStatus| ProbeResult {
+        item,
+        status,
+        optional,
+    };
+    let gateway_item = format!("网关 {}（{}:{}）", gateway.display_name(), gateway.ip, gateway.port);
+
+    let mut client = ModbusClient::new(ModbusDevice {
+        name: gateway.name.clone(),
+        ip: gateway.ip.clone(),
+        port: gateway.port,
+        slave_id: gateway.slave_ids.first().map_or(1, |slave| slave.id),
+        connect_timeout: timeout.min(Duration::from_millis(gateway.connect_timeout_ms)),
+        request_timeout: timeout.min(Duration::from_millis(gateway.request_timeout_ms)),
+    });
+
+    let mut results = Vec::new();
+    if let Err(e) = client.connect().await {
+        results.push(result(gateway_item, ProbeStatus::from_error(e.as_ref())));
+        for slave in gateway.enabled_slaves() {
+            results.push(result(
+                format!("  从站 {}", slave.display_name()),
+                ProbeStatus::Skipped,
+            ));
+        }
+        return results;
+    }
+    results.push(result(gateway_item, ProbeStatus::Ok));
+
+    for slave in gateway.enabled_slaves() {
+        client.set_slave_id(slave.id);
+        let plan = match config.enabled_points(gateway, slave) {
+            Ok(points) => ReadPlan::build(points.iter()),
+            Err(e) => {
+                results.push(result(
+                    format!("  从站 {}", slave.display_name()),
+                    ProbeStatus::Failed(e.to_string()),
+                ));
+                continue;
+            }
+        };
+
+        let requests: Vec<(u8, u16, u16)> = if plan.blocks.is_empty() {
+            vec![(0x03, 0, 1)]
+        } else {
+            plan.blocks
+                .iter()
+                .map(|block| (block.function_code, block.address, block.quantity))
+                .collect()
+        };
+        for (function_code, address, quantity) in requests {
+            let status = match client.read_registers(function_code, address, quantity).await {
+                Ok(_) => ProbeStatus::Ok,
+                Err(e) => ProbeStatus::from_error(e.as_ref()),
+            };
+            results.push(result(
+                format!(
+                    "  从站 {} 功能码0x{:02X} 地址{} 数量{}",
+                    slave.display_name(),
+                    function_code,
+                    address,
+                    quantity
+                ),
+                status,
+            ));
+        }
+    }
+
+    let _ = client.disconnect().await;
+    results
+}
+
+/// 连接测试的一行结果，对应一个从站
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionTest {
+    /// 网关 IP 地址
+    pub host: String,
+    /// 网关端口
+    pub port: u16,
+    /// 从站ID
+    pub slave: u8,
+    /// 网关和从站的显示名称，格式为 `网关/从站`
+    pub name: String,
+    /// 建立连接的耗时，连接失败时为 None；同一网关的从站共用一个连接
+    pub connect_time: Option<Duration>,
+    /// 试读请求的耗时，未得到响应时为 None
+    pub latency: Option<Duration>,
+    /// 连接失败时为连接的结果，否则为试读的结果
+    pub status: ProbeStatus,
+    /// 所属网关是否为可选设备
+    pub optional: bool,
+}
+
+/// 测试一个网关的连通性：连接网关并记录耗时，再对每个启用的从站试读一次并记录响应时间
+///
+/// # 说明
+/// * 连接和读取使用网关配置的 connect_timeout_ms、request_timeout_ms
+/// * 从站配置了点位时读取读取计划中的第一个请求，否则读取一次保持寄存器0
+/// * 从站返回异常响应也说明设备可达，响应时间照常记录
+/// * 连接失败时所有从站记为连接的结果
+pub async fn test_connection(config: &Config, gateway: &GatewayConfig) -> Vec<ConnectionTest> {
+    let slaves: Vec<&SlaveConfig> = gateway.enabled_slaves().collect();
+    let row = |slave: &SlaveConfig,
+               connect_time: Option<Duration>,
+               latency: Option<Duration>,
+               status: ProbeStatus| ConnectionTest {
+        host: gateway.ip.clone(),
+        port: gateway.port,
+        slave: slave.id,
+        name: format!("{}/{}", gateway.display_name(), slave.display_name()),
+        connect_time,
+        latency,
+        status,
+        optional: gateway.optional,
+    };
+
+    let mut client = ModbusClient::new(ModbusDevice {
+        name: gateway.name.clone(),
+        ip: gateway.ip.clone(),
+        port: gateway.port,
+        slave_id: slaves.first().map_or(1, |slave| slave.id),
+        connect_timeout: Duration::from_millis(gateway.connect_timeout_ms),
+        request_timeout: Duration::from_millis(gateway.request_timeout_ms),
+    });
+    let started = Instant::now();
+    if let Err(e) = client.connect().await {
+        let status = ProbeStatus::from_error(e.as_ref());
+        return slaves
+            .iter()
+            .map(|slave| row(slave, None, None, status.clone()))
+            .collect();
+    }
+    let connect_time = started.elapsed();
+
+    let mut results = Vec::new();
+    for &slave in &slaves {
+        if !client.is_connected() {
+            // 前一个从站超时后连接已关闭，重新连接
+            if let Err(e) = client.connect().await {
+                let status = ProbeStatus::from_error(e.as_ref());
+                results.push(row(slave, Some(connect_time), None, status));
+                continue;
+            }
+        }
+        client.set_slave_id(slave.id);
+        let (function_code, address, quantity) = match config.enabled_points(gateway, slave) {
+            Ok(points) => ReadPlan::build(points.iter())
+                .blocks
+                .first()
+                .map_or((0x03, 0, 1), |b| (b.function_code, b.address, b.quantity)),
+            Err(e) => {
+                let status = ProbeStatus::Failed(e.to_string());
+                results.push(row(slave, Some(connect_time), None, status));
+                continue;
+            }
+        };
+        let started = Instant::now();
+        let (latency, status) = match client.read_registers(function_code, address, quantity).await {
+            Ok(_) => (Some(started.elapsed()), ProbeStatus::Ok),
+            Err(e) => {
+                let status = ProbeStatus::from_error(e.as_ref());
+                let latency = matches!(status, ProbeStatus::Exception(_)).then(|| started.elapsed());
+                (latency, status)
+            }
+        };
+        results.push(row(slave, Some(connect_time), latency, status));
+    }
+    let _ = client.disconnect().await;
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockModbus;
+
+    fn config(port: u16) -> Config {
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    request_timeout_ms: 200\n    slave_ids: [1, 2, 3, {{ id: 4, enabled: false }}]\n    points:\n      - {{ name: power, address: 0 }}\n      - {{ name: state, function_code: 1, address: 10, data_type: bool }}\n",
+            port
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    // 已经关闭的端口，连接时被拒绝
+    async fn closed_port() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    fn statuses(results: &[ProbeResult]) -> Vec<(&str, &ProbeStatus)> {
+        results.iter().map(|r| (r.item.as_str(), &r.status)).collect()
+    }
+
+    #[tokio::test]
+    async fn probes_every_read_of_every_slave() {
+        let server = MockModbus::start().await;
+        server.reject(2, 0x02);
+        server.silence(3);
+        let config = config(server.port);
+
+        let results = probe_gateway(&config, &config.gateways[0], DEFAULT_PROBE_TIMEOUT).await;
+        let illegal_address = ProbeStatus::Exception(ExceptionCode::IllegalDataAddress);
+        assert_eq!(
+            statuses(&results)[..5],
+            [
+                (format!("网关 PCS-A（127.0.0.1:{}）", server.port).as_str(), &ProbeStatus::Ok),
+                ("  从站 1 功能码0x01 地址10 数量1", &ProbeStatus::Ok),
+                ("  从站 1 功能码0x03 地址0 数量1", &ProbeStatus::Ok),
+                ("  从站 2 功能码0x01 地址10 数量1", &illegal_address),
+                ("  从站 2 功能码0x03 地址0 数量1", &illegal_address),
+            ]
+        );
+        assert_eq!(results[5].item, "  从站 3 功能码0x01 地址10 数量1");
+        assert_eq!(results[5].status, ProbeStatus::Timeout);
+        assert_eq!(results.len(), 7);
+        assert!(results.iter().all(|r| !r.optional));
+        assert_eq!(results[5].status.code(), "timeout");
+        assert_eq!(results[3].status.code(), "exception_0x02");
+    }
+
+    #[tokio::test]
+    async fn slave_without_points_reads_register_zero() {
+        let server = MockModbus::start().await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - {{ ip: 127.0.0.1, port: {}, slave_ids: [5] }}\n",
+            server.port
+        ))
+        .unwrap();
+        let results = probe_gateway(&config, &config.gateways[0], DEFAULT_PROBE_TIMEOUT).await;
+        assert_eq!(results[1].item, "  从站 5 功能码0x03 地址0 数量1");
+        assert_eq!(results[1].status, ProbeStatus::Ok);
+        let request = &server.requests()[0];
+        assert_eq!((request.slave_id, request.function_code, request.address, request.quantity), (5, 3, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn refused_connection_skips_slaves() {
+        let config = config(closed_port().await);
+        let results = probe_gateway(&config, &config.gateways[0], DEFAULT_PROBE_TIMEOUT).await;
+        let statuses: Vec<ProbeStatus> = results.iter().map(|r| r.status.clone()).collect();
+        assert_eq!(
+            statuses,
+            [ProbeStatus::Refused, ProbeStatus::Skipped, ProbeStatus::Skipped, ProbeStatus::Skipped]
+        );
+        assert!(results[0].status.is_failure());
+        assert!(!results[1].status.is_failure());
+        assert_eq!(results[1].item, "  从站 1");
+    }
+
+    #[tokio::test]
+    async fn test_connection_measures_each_slave_and_reconnects_after_timeout() {
+        let server = MockModbus::start().await;
+        server.silence(1);
+        server.reject(2, 0x02);
+        let config = config(server.port);
+
+        let results = test_connection(&config, &config.gateways[0]).await;
+        let rows: Vec<_> = results
+            .iter()
+            .map(|r| (r.slave, r.name.as_str(), r.status.code(), r.latency.is_some()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (1, "PCS-A/1", "timeout".to_string(), false),
+                (2, "PCS-A/2", "exception_0x02".to_string(), true),
+                (3, "PCS-A/3", "ok".to_string(), true),
+            ]
+        );
+        for result in &results {
+            assert_eq!((result.host.as_str(), result.port), ("127.0.0.1", server.port));
+            assert!(result.connect_time.is_some());
+            assert!(!result.optional);
+        }
+        // 超时按网关的 request_timeout_ms（200ms）计算，超时后重新连接
+        assert_eq!(server.connections(), 2);
+        // 试读读取计划中的第一个请求
+        let request = server.requests().pop().unwrap();
+        assert_eq!(
+            (request.slave_id, request.function_code, request.address, request.quantity),
+            (3, 1, 10, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_reports_refused_for_every_slave() {
+        let config = config(closed_port().await);
+        let results = test_connection(&config, &config.gateways[0]).await;
+        assert_eq!(results.len(), 3);
+        for result in &results {
+            assert_eq!(result.status, ProbeStatus::Refused);
+            assert_eq!((result.connect_time, result.latency), (None, None));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::device_configuration::point::Point;
+
+/// 单次请求最多读取的寄存器数量
+pub const MAX_REGISTERS_PER_READ: u16 = 125;
+/// 单次请求最多读取的线圈/离散输入数量
+pub const MAX_COILS_PER_READ: u16 = 2000;
+
+/// 一次 Modbus 读请求，覆盖若干个地址相邻的点位
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadBlock {
+    /// 功能码（0x01-0x04）
+    pub function_code: u8,
+    /// 起始地址
+    pub address: u16,
+    /// 读取数量
+    pub quantity: u16,
+    /// 该请求覆盖的点位
+    pub points: Vec<Point>,
+}
+
+impl ReadBlock {
+    /// 从整块读取结果中取出某个点位对应的寄存器
+    pub fn registers_for<'a>(&self, point: &Point, values: &'a [u16]) -> Option<&'a [u16]> {
+        let start = point.address.checked_sub(self.address)? as usize;
+        let end = start + point.register_count() as usize;
+        values.get(start..end)
+    }
+}
+
+/// 读取计划，由一组点位合并得到的最少读请求
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReadPlan {
+    /// 合并后的读请求，按功能码和地址排序
+    pub blocks: Vec<ReadBlock>,
+}
+
+impl ReadPlan {
+    /// 根据点位生成读取计划
+    ///
+    /// # 说明
+    /// * 按功能码分组，组内按地址排序
+    /// * 地址相邻或重叠的点位合并为一次请求
+    /// * 单次请求不超过协议允许的最大数量（寄存器125，线圈2000）
+    pub fn build<'a, I>(points: I) -> ReadPlan
+    where
+        I: IntoIterator<Item = &'a Point>,
+    {
+        let mut points: Vec<&Point> = points.into_iter().collect();
+        points.sort_by_key(|p| (p.function_code, p.address));
+
+        let mut blocks: Vec<ReadBlock> = Vec::new();
+        for point in points {
+            let start = point.address as u32;
+            let end = start + point.register_count() as u32;
+
+            if let Some(block) = blocks.last_mut() {
+                let block_start = block.address as u32;
+                let block_end = block_start + block.quantity as u32;
+                let new_end = block_end.max(end);
+                if block.function_code == point.function_code
+                    && start <= block_end
+                    && new_end - block_start <= max_quantity(point.function_code) as u32
+                {
+                    block.quantity = (new_end - block_start) as u16;
+                    block.points.push(point.clone());
+                    continue;
+                }
+            }
+
+            blocks.push(ReadBlock {
+                function_code: point.function_code,
+                address: point.address,
+                quantity: point.register_count(),
+                points: vec![point.clone()],
+            });
+        }
+        ReadPlan { blocks }
+    }
+}
+
+// 功能码对应的单次最大读取数量
+fn max_quantity(function_code: u8) -> u16 {
+    match function_code {
+        0x01 | 0x02 => MAX_COILS_PER_READ,
+        _ => MAX_REGISTERS_PER_READ,
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::device_configuration::mqtt::PublishOptions;
+
+/// 数据质量
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    /// 成功读取并解码，或由质量正常的数据计算得到
+    #[default]
+    Good,
+    /// 值不可用，例如计算点位除以0，此时值为 NaN
+    Bad,
+    /// 超过预期时间没有读到新值，值为最后一次读到的值
+    Stale,
+}
+
+impl Quality {
+    /// 保存和发布时使用的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quality::Good => "good",
+            Quality::Bad => "bad",
+            Quality::Stale => "stale",
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 一个点位的一次采集结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// 网关地址，格式为 ip:port
+    pub gateway: String,
+    /// 网关名称
+    pub gateway_name: Option<String>,
+    /// 从站ID
+    pub slave_id: u8,
+    /// 从站名称
+    pub slave_name: Option<String>,
+    /// 点位名称
+    pub point: String,
+    /// 点位所属的采集组
+    pub group: Option<String>,
+    /// 工程值（已应用 scale 和 offset），字符串点位为 NaN
+    pub value: f64,
+    /// 字符串点位的值
+    pub text: Option<String>,
+    /// 原始寄存器值
+    pub raw: Vec<u16>,
+    /// 工程单位
+    pub unit: Option<String>,
+    /// 采集时间
+    pub timestamp: SystemTime,
+    /// 数据质量
+    pub quality: Quality,
+    /// 发布到 MQTT 时使用的 QoS 和 retain，创建采集任务时按配置确定
+    pub publish: PublishOptions,
+}
+
+impl Reading {
+    /// 发布和输出使用的值，字符串点位为字符串
+    pub fn point_value(&self) -> PointValue {
+        match &self.text {
+            Some(text) => PointValue::Text(text.clone()),
+            None => PointValue::Number(self.value),
+        }
+    }
+}
+
+/// 点位的值：数值或字符串，JSON 中分别为数字（NaN 为 null）和字符串
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum PointValue {
+    /// 数值
+    Number(f64),
+    /// 字符串点位的值
+    Text(String),
+}
+
+impl fmt::Display for PointValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PointValue::Number(value) => write!(f, "{}", value),
+            PointValue::Text(text) => f.write_str(text),
+        }
+    }
+}
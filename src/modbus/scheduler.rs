@@ -0,0 +1,1068 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use futures_util::future::join_all;
+use tokio::sync::{mpsc, oneshot, watch, Semaphore};
+use tokio::time::Instant;
+use tracing::{info, info_span, trace, warn, Instrument};
+
+use crate::device_configuration::modbus::{Config, ModbusDevice as GatewayConfig};
+use crate::device_configuration::mqtt::PublishOptions;
+use crate::device_configuration::point::Point;
+use crate::modbus::availability::{Availability, DeviceAvailability};
+use crate::modbus::client::{ModbusClient, ModbusDevice, ModbusOperation};
+use crate::modbus::read_plan::ReadPlan;
+use crate::modbus::reading::{Quality, Reading};
+use crate::modbus::simulator::Simulator;
+use crate::modbus::stats::{GatewayStats, SharedStats};
+use crate::modbus::watchdog::{WatchdogEvent, Watchdogs};
+
+/// 写入和读取请求队列长度
+pub(crate) const REQUEST_QUEUE_CAPACITY: usize = 16;
+
+/// 写入请求，由采集调度器在两次采集之间执行，与采集共用同一个连接
+#[derive(Debug)]
+pub struct WriteRequest {
+    /// 从站ID
+    pub slave_id: u8,
+    /// 要写入的点位
+    pub point: Point,
+    /// 已编码的寄存器值（线圈为0或1）
+    pub registers: Vec<u16>,
+    /// 写入功能码，为 None 时由点位决定
+    pub function_code: Option<u8>,
+    /// 写入结果，失败时为错误信息
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+/// 临时读取请求，由采集调度器在两次采集之间执行，与采集共用同一个连接
+#[derive(Debug)]
+pub struct ReadRequest {
+    /// 从站ID
+    pub slave_id: u8,
+    /// 读取功能码（0x01-0x04）
+    pub function_code: u8,
+    /// 起始地址
+    pub address: u16,
+    /// 读取数量
+    pub quantity: u16,
+    /// 读到的寄存器（线圈为0或1），失败时为错误信息
+    pub reply: oneshot::Sender<Result<Vec<u16>, String>>,
+}
+
+/// 对采集任务的控制操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayControl {
+    /// 暂停定时采集和看门狗心跳，断开连接；写入和临时读取请求照常执行
+    Pause,
+    /// 恢复定时采集
+    Resume,
+    /// 断开连接并立即重新连接
+    Reconnect,
+}
+
+/// 控制请求，由采集调度器在两次采集之间执行
+#[derive(Debug)]
+pub struct ControlRequest {
+    /// 要执行的操作
+    pub action: GatewayControl,
+    /// 执行结果，失败时为错误信息
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+/// 交给采集调度器按到达顺序执行的请求
+#[derive(Debug)]
+pub enum GatewayRequest {
+    /// 写入点位
+    Write(Box<WriteRequest>),
+    /// 临时读取寄存器
+    Read(ReadRequest),
+    /// 暂停、恢复采集或重新连接
+    Control(ControlRequest),
+}
+
+/// 采集调度器产生的事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum PollEvent {
+    /// 一次采集得到的点位数据
+    Readings(Vec<Reading>),
+    /// 从站的可用性发生变化
+    Availability(DeviceAvailability),
+    /// 从站的看门狗心跳写入失败或恢复
+    Watchdog(WatchdogEvent),
+}
+
+// 一个（从站，采集组）对应的定时采集任务
+struct PollTask {
+    slave_id: u8,
+    slave_name: Option<String>,
+    group: Option<String>,
+    interval: Duration,
+    plan: ReadPlan,
+    // 点位名称到发布参数的映射，包括由 bits 生成的点位
+    publish: BTreeMap<String, PublishOptions>,
+    // 点位名称到由其 bits 生成的点位
+    bit_points: BTreeMap<String, Vec<Point>>,
+    // 被其他点位的 scale_factor 引用的点位名称
+    scale_factor_points: BTreeSet<String>,
+    // 最近一次读到的比例因子；同时到期的任务并发执行时只通过共享引用访问任务，因此放在 Mutex 中
+    scale_factors: Mutex<BTreeMap<String, i16>>,
+    next_due: Instant,
+    // 最近一次执行是否有成功的读请求，未执行过时为 None
+    last_ok: Option<bool>,
+}
+
+/// 单个网关的采集调度器
+///
+/// # 说明
+/// * 同一网关下的所有从站共用一个TCP连接；max_in_flight 大于1时共用一组连接，同时到期的任务并发读取
+/// * 每个（从站，采集组）维护独立的定时器，快速组不会被慢速组拖慢
+/// * 同时到期的采集任务按读请求轮流执行，执行超时后轮换起始从站，见 [`GatewayPoller::poll_due`]
+/// * 下一次执行时间按周期累加计算，不受执行耗时影响；错过的周期直接跳过
+/// * 一次执行耗时超过采集周期时输出警告并计入统计
+/// * 连接断开或读取出错后，下一次采集或请求时自动重新连接
+/// * 写入和临时读取请求在两次采集之间按到达顺序执行
+/// * 从站的任一采集组最近一次有成功的读请求时为在线，全部失败时为离线
+/// * 配置了 watchdog 的从站定期写入心跳，优先于采集执行
+/// * 暂停期间不采集、不写心跳，从站报告为 disabled，恢复后立即采集
+pub struct GatewayPoller {
+    name: String,
+    address: String,
+    gateway_name: Option<String>,
+    // 与网关的连接，数量为 max_in_flight；看门狗心跳、写入、临时读取和控制请求使用第一个
+    clients: Vec<ModbusClient>,
+    tasks: Vec<PollTask>,
+    stats: SharedStats,
+    requests: Option<mpsc::Receiver<GatewayRequest>>,
+    availability: BTreeMap<u8, Availability>,
+    watchdogs: Watchdogs,
+    // 从站ID按配置顺序排列，用于轮流执行
+    slave_order: Vec<u8>,
+    // 本次从 slave_order 中的第几个从站开始执行
+    rotation: usize,
+    // 已通过控制命令暂停定时采集
+    paused: bool,
+}
+
+impl GatewayPoller {
+    /// 根据网关配置创建调度器，每个从站的点位按采集组分别生成读取计划
+    ///
+    /// # 返回值
+    /// * `Err` - 引用的点位表不存在或无法加载
+    pub fn new(config: &Config, gateway: &GatewayConfig) -> Result<GatewayPoller, Box<dyn Error>> {
+        let device = ModbusDevice {
+            name: gateway.name.clone(),
+            ip: gateway.ip.clone(),
+            port: gateway.port,
+            slave_id: gateway.slave_ids.first().map(|s| s.id).unwrap_or(1),
+            connect_timeout: Duration::from_millis(gateway.connect_timeout_ms),
+            request_timeout: Duration::from_millis(gateway.request_timeout_ms),
+        };
+
+        let now = Instant::now();
+        let mut tasks = Vec::new();
+        let mut stats = GatewayStats {
+            name: gateway.display_name(),
+            ..GatewayStats::default()
+        };
+        for slave in gateway.enabled_slaves() {
+            stats.slaves.entry(slave.id).or_default().name = slave.name.clone();
+
+            // 按采集组归类点位
+            let points = config.enabled_points(gateway, slave)?;
+            let mut groups: BTreeMap<Option<String>, Vec<&Point>> = BTreeMap::new();
+            for point in &points {
+                groups.entry(point.group.clone()).or_default().push(point);
+            }
+
+            for (group, points) in &groups {
+                let bit_points: BTreeMap<String, Vec<Point>> = points
+                    .iter()
+                    .filter(|point| !point.bits.is_empty())
+                    .map(|point| (point.name.clone(), point.bit_points()))
+                    .collect();
+                let mut publish: BTreeMap<String, PublishOptions> = points
+                    .iter()
+                    .map(|point| (point.name.clone(), config.publish_options(point)))
+                    .collect();
+                // 位域点位只有0、1等少数取值，配置了死区时只在值变化时发布
+                for point in bit_points.values().flatten() {
+                    let mut options = config.publish_options(point);
+                    options.deadband = options.deadband.map(|_| 0.0);
+                    publish.insert(point.name.clone(), options);
+                }
+                tasks.push(PollTask {
+                    slave_id: slave.id,
+                    slave_name: slave.name.clone(),
+                    group: group.clone(),
+                    interval: config.poll_interval(gateway, points[0]),
+                    plan: ReadPlan::build(points.iter().copied()),
+                    publish,
+                    bit_points,
+                    scale_factor_points: points.iter().filter_map(|point| point.scale_factor.clone()).collect(),
+                    scale_factors: Mutex::new(BTreeMap::new()),
+                    next_due: now,
+                    last_ok: None,
+                });
+            }
+        }
+
+        let stats = Arc::new(Mutex::new(stats));
+        let mut clients = Vec::new();
+        if gateway.simulation {
+            info!(gateway = %gateway.display_name(), "网关使用模拟数据，不连接设备");
+            // 模拟设备只有一份寄存器状态，不使用多个连接
+            let mut client = ModbusClient::new(device);
+            client.set_simulator(Simulator::new(config, gateway)?);
+            clients.push(client);
+        } else {
+            clients.extend((0..gateway.max_in_flight.max(1)).map(|_| ModbusClient::new(device.clone())));
+        }
+        for client in &mut clients {
+            client.set_stats(Arc::clone(&stats));
+        }
+        Ok(GatewayPoller {
+            name: gateway.display_name(),
+            address: format!("{}:{}", gateway.ip, gateway.port),
+            gateway_name: gateway.name.clone(),
+            clients,
+            tasks,
+            stats,
+            requests: None,
+            availability: BTreeMap::new(),
+            watchdogs: Watchdogs::new(gateway),
+            slave_order: gateway.enabled_slaves().map(|slave| slave.id).collect(),
+            rotation: 0,
+            paused: false,
+        })
+    }
+
+    /// 网关名称，未配置时为 ip:port
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 网关的采集统计，采集过程中持续更新
+    pub fn stats(&self) -> SharedStats {
+        Arc::clone(&self.stats)
+    }
+
+    /// 改为记录到已有的统计中，重启采集任务时用于保留之前的统计和暂停状态
+    pub fn set_stats(&mut self, stats: SharedStats) {
+        for client in &mut self.clients {
+            client.set_stats(Arc::clone(&stats));
+        }
+        self.paused = stats.lock().unwrap_or_else(|e| e.into_inner()).paused;
+        self.stats = stats;
+    }
+
+    /// 与其他网关共用的并发请求上限，每个读写请求执行前先取得许可
+    pub fn set_request_limit(&mut self, limit: Arc<Semaphore>) {
+        for client in &mut self.clients {
+            client.set_request_limit(Arc::clone(&limit));
+        }
+    }
+
+    /// 最近一个需要执行的采集任务或看门狗心跳的时间，都没有时返回 None
+    pub fn next_due(&self) -> Option<Instant> {
+        self.tasks
+            .iter()
+            .map(|t| t.next_due)
+            .chain(self.watchdogs.next_due())
+            .min()
+    }
+
+    /// 执行所有已到期的采集任务；已到期的看门狗心跳在采集之前写入
+    ///
+    /// # 说明
+    /// * 多个任务同时到期时按读请求轮流执行，每轮每个任务执行一个读请求，
+    ///   读取计划较长的从站不会让其他从站的数据一直排在最后
+    /// * 有多个连接（max_in_flight 大于1）时，每轮依次取出与连接数相同的任务，在各自的连接上并发执行一个读请求
+    /// * 从当前轮转到的从站开始执行，有任务耗时超过采集周期时下一次从下一个从站开始，
+    ///   延迟分摊到各个从站，而不是总落在排在后面的从站上
+    ///
+    /// # 返回值
+    /// * 本次采集得到的所有点位数据，失败的读请求不产生数据
+    pub async fn poll_due(&mut self) -> Vec<Reading> {
+        self.watchdogs.service(&mut self.clients[0], &self.availability).await;
+        let now = Instant::now();
+        let slave_count = self.slave_order.len().max(1);
+        let position = |slave_id: u8| {
+            let index = self.slave_order.iter().position(|&id| id == slave_id).unwrap_or(0);
+            (index + slave_count - self.rotation) % slave_count
+        };
+        let mut due: Vec<usize> = (0..self.tasks.len())
+            .filter(|&i| self.tasks[i].next_due <= now)
+            .collect();
+        due.sort_by_key(|&i| (position(self.tasks[i].slave_id), self.tasks[i].next_due));
+
+        let started = Instant::now();
+        let mut runs: VecDeque<TaskRun> = due.into_iter().map(TaskRun::new).collect();
+        let mut readings = Vec::new();
+        let mut overrun = false;
+        let mut first = true;
+        while !runs.is_empty() {
+            if !first && self.watchdogs.next_due().is_some_and(|due| due <= Instant::now()) {
+                self.watchdogs.service(&mut self.clients[0], &self.availability).await;
+            }
+            first = false;
+
+            let batch: Vec<TaskRun> = runs.drain(..runs.len().min(self.clients.len())).collect();
+            let gateway = &GatewayIdentity {
+                address: &self.address,
+                configured_name: self.gateway_name.as_deref(),
+            };
+            let (name, tasks) = (&self.name, &self.tasks);
+            let results = join_all(batch.into_iter().zip(self.clients.iter_mut()).map(
+                |(mut run, client)| {
+                    let task = &tasks[run.index];
+                    // 每个读请求一个 span，其中的日志都带有网关、从站和采集组
+                    let span = info_span!(
+                        "poll",
+                        gateway = %name,
+                        slave_id = task.slave_id,
+                        group = task.group.as_deref().unwrap_or("默认")
+                    );
+                    async move {
+                        let mut block_readings = Vec::new();
+                        execute_block(gateway, client, task, &mut run, &mut block_readings)
+                            .instrument(span.clone())
+                            .await;
+                        (run, block_readings, span)
+                    }
+                },
+            ))
+            .await;
+
+            for (run, block_readings, span) in results {
+                readings.extend(block_readings);
+                let task = &mut self.tasks[run.index];
+                if run.block < task.plan.blocks.len() {
+                    runs.push_back(run);
+                    continue;
+                }
+
+                task.last_ok = Some(run.ok > 0);
+                record(&self.stats, task.slave_id, run.ok, run.failed, run.last_error);
+                // 从本次采集开始计时，包括轮流执行其他任务读请求的时间
+                let elapsed = started.elapsed();
+                self.stats.lock().unwrap_or_else(|e| e.into_inner()).cycle_durations.observe(elapsed);
+                if elapsed > task.interval {
+                    overrun = true;
+                    span.in_scope(|| {
+                        warn!(
+                            elapsed_ms = elapsed.as_millis() as u64,
+                            interval_ms = task.interval.as_millis() as u64,
+                            "采集耗时超过采集周期"
+                        )
+                    });
+                    let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+                    stats.slaves.entry(task.slave_id).or_default().overruns += 1;
+                }
+
+                // 按周期累加计算下一次执行时间，错过的周期直接跳过
+                task.next_due += task.interval;
+                let now = Instant::now();
+                while task.next_due <= now {
+                    task.next_due += task.interval;
+                }
+            }
+        }
+        if overrun {
+            self.rotation = (self.rotation + 1) % slave_count;
+        }
+        readings
+    }
+
+    /// 上次调用以来看门狗心跳写入失败或恢复的从站
+    pub fn watchdog_events(&mut self) -> Vec<WatchdogEvent> {
+        self.watchdogs.take_events()
+    }
+
+    /// 与上次调用相比可用性发生变化的从站，第一次采集后每个从站都会返回一次
+    pub fn availability_changes(&mut self) -> Vec<DeviceAvailability> {
+        let mut current: BTreeMap<u8, (bool, Option<String>)> = BTreeMap::new();
+        for task in &self.tasks {
+            if let Some(ok) = task.last_ok {
+                let entry = current
+                    .entry(task.slave_id)
+                    .or_insert((false, task.slave_name.clone()));
+                entry.0 |= ok;
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (slave_id, (online, slave_name)) in current {
+            let availability = if online {
+                Availability::Online
+            } else {
+                Availability::Offline
+            };
+            let previous = self.availability.insert(slave_id, availability);
+            if previous == Some(availability) {
+                continue;
+            }
+            if previous.is_some() || availability == Availability::Offline {
+                let slave = slave_name.as_deref().map_or_else(|| slave_id.to_string(), str::to_string);
+                if online {
+                    info!(gateway = %self.name, slave_id, slave = %slave, "从站恢复在线");
+                } else {
+                    warn!(gateway = %self.name, slave_id, slave = %slave, "从站离线");
+                }
+            }
+            changes.push(DeviceAvailability {
+                gateway: self.address.clone(),
+                gateway_name: self.gateway_name.clone(),
+                slave_id,
+                slave_name,
+                availability,
+            });
+        }
+        changes
+    }
+
+    /// 创建写入和读取请求队列，返回发送端；重复调用时替换之前的队列
+    pub fn request_sender(&mut self) -> mpsc::Sender<GatewayRequest> {
+        let (sender, receiver) = mpsc::channel(REQUEST_QUEUE_CAPACITY);
+        self.requests = Some(receiver);
+        sender
+    }
+
+    /// 持续按周期采集，采集到数据或从站可用性变化时调用 `on_event`；等待期间执行收到的写入和读取请求
+    ///
+    /// `stop` 变为 true（或发送端被 drop）后不再开始新的采集和请求，正在进行的读写完成后断开连接并返回。
+    pub async fn run<F>(&mut self, mut on_event: F, mut stop: watch::Receiver<bool>)
+    where
+        F: FnMut(PollEvent),
+    {
+        while let Some(due) = self.next_due() {
+            // 采集耗时较长时下一次采集已经到期，select 可能总是选中到期的分支
+            if *stop.borrow() {
+                break;
+            }
+            // 暂停时只等待请求和停止信号
+            let due = if self.paused {
+                Instant::now() + Duration::from_secs(3600)
+            } else {
+                due
+            };
+            if let Some(requests) = self.requests.as_mut() {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(due) => {}
+                    _ = stopped(&mut stop) => break,
+                    request = requests.recv() => {
+                        match request {
+                            Some(GatewayRequest::Write(request)) => self.write(*request).await,
+                            Some(GatewayRequest::Read(request)) => self.read(request).await,
+                            Some(GatewayRequest::Control(request)) => {
+                                for change in self.control(request).await {
+                                    on_event(PollEvent::Availability(change));
+                                }
+                            }
+                            // 所有发送端都已关闭
+                            None => self.requests = None,
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(due) => {}
+                    _ = stopped(&mut stop) => break,
+                }
+            }
+            if self.paused {
+                continue;
+            }
+
+            let readings = self.poll_due().await;
+            if !readings.is_empty() {
+                on_event(PollEvent::Readings(readings));
+            }
+            for change in self.availability_changes() {
+                on_event(PollEvent::Availability(change));
+            }
+            for event in self.watchdog_events() {
+                on_event(PollEvent::Watchdog(event));
+            }
+        }
+        self.disconnect().await;
+    }
+
+    /// 所有采集任务各执行一次，然后断开连接，用于 `--once` 单次采集；不写看门狗心跳
+    pub async fn run_once<F>(&mut self, mut on_event: F)
+    where
+        F: FnMut(PollEvent),
+    {
+        self.watchdogs.clear();
+        for task in &mut self.tasks {
+            task.next_due = Instant::now();
+        }
+        let readings = self.poll_due().await;
+        if !readings.is_empty() {
+            on_event(PollEvent::Readings(readings));
+        }
+        for change in self.availability_changes() {
+            on_event(PollEvent::Availability(change));
+        }
+        self.disconnect().await;
+    }
+
+    /// 执行一个控制请求，结果通过请求中的 reply 返回
+    ///
+    /// # 返回值
+    /// * 暂停时各从站变为 disabled；恢复后由下一次采集重新报告可用性
+    pub async fn control(&mut self, request: ControlRequest) -> Vec<DeviceAvailability> {
+        let mut changes = Vec::new();
+        let result = match request.action {
+            GatewayControl::Pause => {
+                if !self.paused {
+                    self.set_paused(true);
+                    info!(gateway = %self.name, "已暂停采集");
+                    self.disconnect().await;
+                    changes = self.disable_slaves();
+                }
+                Ok(())
+            }
+            GatewayControl::Resume => {
+                if self.paused {
+                    self.set_paused(false);
+                    info!(gateway = %self.name, "已恢复采集");
+                    let now = Instant::now();
+                    for task in &mut self.tasks {
+                        task.next_due = now;
+                    }
+                }
+                Ok(())
+            }
+            GatewayControl::Reconnect => {
+                info!(gateway = %self.name, "断开并重新连接网关");
+                self.disconnect().await;
+                self.clients[0].connect().await.map_err(|e| format!("连接失败: {}", e))
+            }
+        };
+        let _ = request.reply.send(result);
+        changes
+    }
+
+    // 断开所有连接，断开失败时 disconnect 已输出日志
+    async fn disconnect(&mut self) {
+        for client in &mut self.clients {
+            let _ = client.disconnect().await;
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).paused = paused;
+    }
+
+    // 暂停后所有从站报告为 disabled，清除之前的可用性，恢复后重新报告
+    fn disable_slaves(&mut self) -> Vec<DeviceAvailability> {
+        self.availability.clear();
+        let mut slaves: BTreeMap<u8, Option<String>> = BTreeMap::new();
+        for task in &mut self.tasks {
+            task.last_ok = None;
+            slaves.insert(task.slave_id, task.slave_name.clone());
+        }
+        slaves
+            .into_iter()
+            .map(|(slave_id, slave_name)| DeviceAvailability {
+                gateway: self.address.clone(),
+                gateway_name: self.gateway_name.clone(),
+                slave_id,
+                slave_name,
+                availability: Availability::Disabled,
+            })
+            .collect()
+    }
+
+    /// 执行一个写入请求，结果通过请求中的 reply 返回
+    pub async fn write(&mut self, request: WriteRequest) {
+        let result = write_point(&mut self.clients[0], &request).await;
+        match &result {
+            Ok(()) => info!(
+                gateway = %self.name,
+                slave_id = request.slave_id,
+                point = %request.point.name,
+                registers = ?request.registers,
+                "写入点位成功"
+            ),
+            Err(e) => warn!(
+                gateway = %self.name,
+                slave_id = request.slave_id,
+                point = %request.point.name,
+                error = %e,
+                "写入点位失败"
+            ),
+        }
+        let _ = request.reply.send(result);
+    }
+
+    /// 执行一个临时读取请求，结果通过请求中的 reply 返回
+    pub async fn read(&mut self, request: ReadRequest) {
+        let client = &mut self.clients[0];
+        if !client.is_connected()
+            && let Err(e) = client.connect().await
+        {
+            let _ = request.reply.send(Err(format!("连接失败: {}", e)));
+            return;
+        }
+        client.set_slave_id(request.slave_id);
+        let result = client
+            .read_registers(request.function_code, request.address, request.quantity)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(e) = &result {
+            warn!(
+                gateway = %self.name,
+                slave_id = request.slave_id,
+                function_code = request.function_code,
+                address = request.address,
+                quantity = request.quantity,
+                error = %e,
+                "临时读取失败"
+            );
+        }
+        let _ = request.reply.send(result);
+    }
+}
+
+// stop 变为 true 或发送端被 drop 时返回
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    let _ = stop.wait_for(|stopped| *stopped).await;
+}
+
+// 采集数据中使用的网关标识
+struct GatewayIdentity<'a> {
+    address: &'a str,
+    configured_name: Option<&'a str>,
+}
+
+// 一个采集任务本次执行的进度
+struct TaskRun {
+    // 在 GatewayPoller::tasks 中的下标
+    index: usize,
+    // 下一个要执行的读请求
+    block: usize,
+    ok: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+impl TaskRun {
+    fn new(index: usize) -> Self {
+        TaskRun {
+            index,
+            block: 0,
+            ok: 0,
+            failed: 0,
+            last_error: None,
+        }
+    }
+}
+
+// 在给定的连接上执行采集任务读取计划中的下一个读请求；
+// 未连接时先连接，连接失败时本次剩余的读请求都计为失败
+async fn execute_block(
+    gateway: &GatewayIdentity<'_>,
+    client: &mut ModbusClient,
+    task: &PollTask,
+    run: &mut TaskRun,
+    readings: &mut Vec<Reading>,
+) {
+    if !client.is_connected()
+        && let Err(e) = client.connect().await
+    {
+        let message = e.to_string();
+        warn!(error = %message, "网关连接失败，跳过本次采集");
+        run.failed += (task.plan.blocks.len() - run.block) as u64;
+        run.block = task.plan.blocks.len();
+        run.last_error = Some(message);
+        return;
+    }
+    // 轮流读取多个从站，每个读请求之前都要设置从站ID
+    client.set_slave_id(task.slave_id);
+    let Some(block) = task.plan.blocks.get(run.block) else {
+        return;
+    };
+    run.block += 1;
+
+    let result = client
+        .read_registers(block.function_code, block.address, block.quantity)
+        .await
+        .map_err(|e| e.to_string());
+    match result {
+        Ok(values) => {
+            run.ok += 1;
+            let timestamp = SystemTime::now();
+            // 先记录本次读到的比例因子，同一读请求中的数值使用本次的比例因子；
+            // 比例因子在另一个读请求中时使用最近一次读到的，尚未读到时跳过该点位
+            let mut scale_factors = task.scale_factors.lock().unwrap_or_else(|e| e.into_inner());
+            for point in block.points.iter().filter(|p| task.scale_factor_points.contains(&p.name)) {
+                if let Some(&[register]) = block.registers_for(point, &values) {
+                    scale_factors.insert(point.name.clone(), register as i16);
+                }
+            }
+            for point in &block.points {
+                let Some(raw) = block.registers_for(point, &values) else {
+                    continue;
+                };
+                let text = point.text_from(raw);
+                let value = match &text {
+                    Some(_) => f64::NAN,
+                    None => {
+                        let value = match &point.scale_factor {
+                            Some(name) => scale_factors
+                                .get(name)
+                                .and_then(|factor| point.value_with_scale_factor(raw, *factor)),
+                            None => point.value_from(raw),
+                        };
+                        let Some(value) = value else {
+                            continue;
+                        };
+                        value
+                    }
+                };
+                trace!(point = %point.name, value, text = ?text, raw = ?raw, "读取点位");
+                readings.push(Reading {
+                    gateway: gateway.address.to_string(),
+                    gateway_name: gateway.configured_name.map(str::to_string),
+                    slave_id: task.slave_id,
+                    slave_name: task.slave_name.clone(),
+                    point: point.name.clone(),
+                    group: task.group.clone(),
+                    value,
+                    text,
+                    raw: raw.to_vec(),
+                    unit: point.unit.clone(),
+                    timestamp,
+                    quality: Quality::Good,
+                    publish: task.publish.get(&point.name).copied().unwrap_or_default(),
+                });
+                // 位域点位与所在寄存器一起读取，不单独发送请求
+                for bit_point in task.bit_points.get(&point.name).into_iter().flatten() {
+                    let Some(value) = bit_point.value_from(raw) else {
+                        continue;
+                    };
+                    trace!(point = %bit_point.name, value, "读取位域点位");
+                    readings.push(Reading {
+                        gateway: gateway.address.to_string(),
+                        gateway_name: gateway.configured_name.map(str::to_string),
+                        slave_id: task.slave_id,
+                        slave_name: task.slave_name.clone(),
+                        point: bit_point.name.clone(),
+                        group: task.group.clone(),
+                        value,
+                        text: None,
+                        raw: raw.to_vec(),
+                        unit: None,
+                        timestamp,
+                        quality: Quality::Good,
+                        publish: task.publish.get(&bit_point.name).copied().unwrap_or_default(),
+                    });
+                }
+            }
+        }
+        Err(message) => {
+            run.failed += 1;
+            warn!(
+                function_code = block.function_code,
+                address = block.address,
+                quantity = block.quantity,
+                error = %message,
+                "读取失败"
+            );
+            run.last_error = Some(message);
+        }
+    }
+}
+
+// 写入点位，设置了 verify_write 时回读校验
+async fn write_point(client: &mut ModbusClient, request: &WriteRequest) -> Result<(), String> {
+    let point = &request.point;
+    let function_code = request
+        .function_code
+        .or_else(|| point.write_function_code())
+        .ok_or_else(|| format!("点位 {} 不能写入", point.name))?;
+
+    if !client.is_connected() {
+        client.connect().await.map_err(|e| e.to_string())?;
+    }
+    client.set_slave_id(request.slave_id);
+    client
+        .write_registers(
+            function_code,
+            point.address,
+            request.registers.len() as u16,
+            request.registers.clone(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if point.verify_write {
+        let values = client
+            .read_registers(point.function_code, point.address, point.register_count())
+            .await
+            .map_err(|e| format!("回读失败: {}", e))?;
+        if values != request.registers {
+            return Err(format!(
+                "回读校验失败: 写入 {:?}，读回 {:?}",
+                request.registers, values
+            ));
+        }
+    }
+    Ok(())
+}
+
+// 更新从站统计，每调用一次计一个采集周期
+fn record(stats: &SharedStats, slave_id: u8, ok: u64, failed: u64, last_error: Option<String>) {
+    let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+    let slave = stats.slaves.entry(slave_id).or_default();
+    slave.cycles += 1;
+    slave.reads_ok += ok;
+    slave.reads_failed += failed;
+    if last_error.is_some() {
+        slave.last_error = last_error;
+    }
+    if ok > 0 {
+        slave.last_success = Some(SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reload::GatewayTasks;
+    use crate::test_support::{config, MockModbus, ModbusRequest};
+
+    // 运行调度器 `window` 时长，返回每个采集组执行的次数
+    async fn run_for(config: &Config, window: Duration) -> BTreeMap<Option<String>, usize> {
+        let mut poller = GatewayPoller::new(config, &config.gateways[0]).unwrap();
+        let (stop_sender, stop) = watch::channel(false);
+        let stopper = tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let _ = stop_sender.send(true);
+        });
+        let mut counts: BTreeMap<Option<String>, usize> = BTreeMap::new();
+        poller
+            .run(
+                |event| {
+                    if let PollEvent::Readings(readings) = event {
+                        let groups: BTreeSet<Option<String>> =
+                            readings.into_iter().map(|r| r.group).collect();
+                        for group in groups {
+                            *counts.entry(group).or_default() += 1;
+                        }
+                    }
+                },
+                stop,
+            )
+            .await;
+        stopper.await.unwrap();
+        counts
+    }
+
+    // 暂停时钟后，没有任务可运行时时钟直接跳到下一个定时器，请求超时会在模拟服务器的应答到达前触发；
+    // 保持一个1毫秒的定时器，时钟每次最多前进1毫秒
+    fn keep_clock_ticking() -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+    }
+
+    // 按（从站, 地址）统计服务器收到的读请求次数
+    fn reads_by_address(modbus: &MockModbus) -> BTreeMap<(u8, u16), usize> {
+        let mut counts = BTreeMap::new();
+        for request in modbus.requests() {
+            *counts.entry((request.slave_id, request.address)).or_default() += 1;
+        }
+        counts
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn groups_run_at_their_own_intervals() {
+        let modbus = MockModbus::start().await;
+        let ticker = keep_clock_ticking();
+        // 两个组共用一个连接，每个读请求占用100毫秒
+        modbus.set_delay(Duration::from_millis(100));
+        let config = config(&format!(
+            "version: 2\npoll_groups:\n  fast: {{ interval_ms: 1000 }}\n  slow: {{ interval_ms: 5000 }}\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    slave_ids: [1]\n    points:\n      - {{ name: power, address: 0, group: fast }}\n      - {{ name: energy, address: 10, group: slow }}\n",
+            modbus.port
+        ));
+        let started = Instant::now();
+        // 第0秒两个组都执行，之后快速组每秒一次、慢速组每5秒一次
+        let counts = run_for(&config, Duration::from_millis(9_500)).await;
+        assert_eq!(counts.get(&Some("fast".to_string())), Some(&10));
+        assert_eq!(counts.get(&Some("slow".to_string())), Some(&2));
+        assert_eq!(reads_by_address(&modbus), BTreeMap::from([((1, 0), 10), ((1, 10), 2)]));
+
+        // 慢速组的读取最多让快速组推迟一个请求的时间，不会打乱快速组的节奏
+        let fast: Vec<Duration> = modbus
+            .requests()
+            .iter()
+            .filter(|r| r.address == 0)
+            .map(|r| r.received - started)
+            .collect();
+        for (second, offset) in fast.iter().enumerate() {
+            let expected = Duration::from_secs(second as u64);
+            assert!(
+                *offset >= expected && *offset <= expected + Duration::from_millis(100),
+                "第 {} 次快速组读取在 {:?}",
+                second,
+                offset
+            );
+        }
+        ticker.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn points_without_group_use_gateway_interval() {
+        let modbus = MockModbus::start().await;
+        let ticker = keep_clock_ticking();
+        let config = config(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    poll_interval_ms: 2000\n    slave_ids: [1, 2]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            modbus.port
+        ));
+        let counts = run_for(&config, Duration::from_millis(9_500)).await;
+        assert_eq!(counts.get(&None), Some(&5));
+        assert_eq!(reads_by_address(&modbus), BTreeMap::from([((1, 0), 5), ((2, 0), 5)]));
+        ticker.abort();
+    }
+
+    // 6个从站，每个从站4个相距较远的点位，读取计划各有4个读请求
+    #[tokio::test]
+    async fn every_slave_data_age_stays_bounded_under_request_delays() {
+        let modbus = MockModbus::start().await;
+        // 每轮24个读请求共约480毫秒，超过200毫秒的采集周期，每轮都会超时
+        modbus.set_delay(Duration::from_millis(20));
+        let config = config(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    poll_interval_ms: 200\n    slave_ids: [1, 2, 3, 4, 5, 6]\n    points:\n      - {{ name: a, address: 0 }}\n      - {{ name: b, address: 200 }}\n      - {{ name: c, address: 400 }}\n      - {{ name: d, address: 600 }}\n",
+            modbus.port
+        ));
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        let stats = poller.stats();
+        let (stop_sender, stop) = watch::channel(false);
+        let started = Instant::now();
+        let stopper = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(3000)).await;
+            let _ = stop_sender.send(true);
+        });
+        poller.run(|_| {}, stop).await;
+        stopper.await.unwrap();
+
+        let requests = modbus.requests();
+        let cycles: Vec<&[ModbusRequest]> = requests.chunks_exact(24).collect();
+        assert!(cycles.len() >= 4, "只完成了 {} 轮采集", cycles.len());
+        for (index, cycle) in cycles.iter().enumerate() {
+            // 按读请求轮流执行：每6个请求是各从站读取计划中的同一个请求
+            for (block, round) in cycle.chunks(6).enumerate() {
+                let slaves: BTreeSet<u8> = round.iter().map(|r| r.slave_id).collect();
+                assert_eq!(slaves, (1..=6).collect(), "第 {} 轮第 {} 个读请求", index, block);
+                assert!(round.iter().all(|r| r.address == block as u16 * 200));
+            }
+            // 每轮都超时，起始从站依次后移
+            assert_eq!(cycle[0].slave_id as usize, index % 6 + 1, "第 {} 轮的起始从站", index);
+        }
+
+        // 每个从站的数据在每轮的最后6个读请求内更新完，相邻两次更新的间隔有上限
+        let bound = Duration::from_millis(1000);
+        for slave_id in 1..=6 {
+            let updates: Vec<Instant> = requests
+                .iter()
+                .filter(|r| r.slave_id == slave_id && r.address == 600)
+                .map(|r| r.received)
+                .collect();
+            assert!(updates[0] - started < bound, "从站 {} 的首次更新过晚", slave_id);
+            for pair in updates.windows(2) {
+                assert!(pair[1] - pair[0] < bound, "从站 {} 的数据间隔 {:?}", slave_id, pair[1] - pair[0]);
+            }
+        }
+
+        let stats = stats.lock().unwrap();
+        let counts: Vec<u64> = stats.slaves.values().map(|s| s.cycles).collect();
+        assert_eq!(counts.len(), 6);
+        assert!(counts.iter().max().unwrap() - counts.iter().min().unwrap() <= 1, "{:?}", counts);
+        for (slave_id, slave) in &stats.slaves {
+            assert!(slave.overruns > 0, "从站 {} 没有记录超时", slave_id);
+            let age = SystemTime::now().duration_since(slave.last_success.unwrap()).unwrap();
+            assert!(age < bound, "从站 {} 的最近一次成功采集在 {:?} 之前", slave_id, age);
+        }
+    }
+
+    // 6个从站各一个读请求，每个请求延迟100毫秒，单次采集后返回服务器上同时处理的最大请求数、连接数和耗时
+    async fn measure_parallelism(max_in_flight: u32) -> (usize, usize, Duration) {
+        let modbus = MockModbus::start().await;
+        modbus.set_delay(Duration::from_millis(100));
+        let config = config(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    max_in_flight: {}\n    slave_ids: [1, 2, 3, 4, 5, 6]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            modbus.port, max_in_flight
+        ));
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        let started = Instant::now();
+        let mut count = 0;
+        poller
+            .run_once(|event| {
+                if let PollEvent::Readings(readings) = event {
+                    count += readings.len();
+                }
+            })
+            .await;
+        let elapsed = started.elapsed();
+        assert_eq!(count, 6);
+        (modbus.max_in_flight(), modbus.connections(), elapsed)
+    }
+
+    #[tokio::test]
+    async fn max_in_flight_sets_achieved_parallelism() {
+        let (in_flight, connections, elapsed) = measure_parallelism(1).await;
+        assert_eq!((in_flight, connections), (1, 1));
+        assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+
+        // 3个连接并发读取，6个请求分两轮完成
+        let (in_flight, connections, elapsed) = measure_parallelism(3).await;
+        assert_eq!((in_flight, connections), (3, 3));
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(450), "{:?}", elapsed);
+    }
+
+    // 两个共用同一地址的网关，各有3个连接和3个从站，返回服务器上同时处理的最大请求数
+    async fn measure_shared_limit(limit: Option<usize>) -> usize {
+        let modbus = MockModbus::start().await;
+        modbus.set_delay(Duration::from_millis(100));
+        let gateway = |name: &str, slaves: &str| {
+            format!(
+                "  - {{ ip: 127.0.0.1, port: {}, name: {}, allow_duplicates: true, max_in_flight: 3, poll_interval_ms: 60000, slave_ids: {}, points: [{{ name: power, address: 0 }}] }}\n",
+                modbus.port, name, slaves
+            )
+        };
+        let limit = limit.map(|n| format!("max_concurrent_requests: {}\n", n)).unwrap_or_default();
+        let config = config(&format!(
+            "version: 2\n{}gateways:\n{}{}",
+            limit,
+            gateway("A", "[1, 2, 3]"),
+            gateway("B", "[4, 5, 6]")
+        ));
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while modbus.requests().len() < 6 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        tasks.shutdown().await;
+        modbus.max_in_flight()
+    }
+
+    #[tokio::test]
+    async fn global_request_limit_is_shared_by_all_gateways() {
+        assert_eq!(measure_shared_limit(None).await, 6);
+        assert_eq!(measure_shared_limit(Some(2)).await, 2);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::f64::consts::TAU;
+use std::io;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::device_configuration::modbus::{Config, ModbusDevice as GatewayConfig};
+use crate::device_configuration::point::Point;
+use crate::device_configuration::simulation::{SimulationHint, SimulationKind};
+use crate::modbus::decode::DataType;
+
+// 一个模拟点位
+struct SimulatedPoint {
+    point: Point,
+    hint: SimulationHint,
+    // random_walk 的当前值
+    current: f64,
+}
+
+/// 模拟的 Modbus 设备，按点位的 sim 参数生成寄存器值，代替真实的网关
+///
+/// # 说明
+/// * 读请求范围内的点位按当前时间生成工程值，再按数据类型、字节序和 scale 编码为寄存器值，
+///   不属于任何点位的寄存器为0
+/// * 写入的寄存器被记住，之后读取时返回写入的值，写入校验和回读都能通过
+/// * 按 `sim_error_rate` 的概率让读请求失败，用于检验失败时的处理
+pub struct Simulator {
+    started: Instant,
+    error_rate: f64,
+    rng: u64,
+    // (从站ID, 读取功能码) 对应的点位
+    points: HashMap<(u8, u8), Vec<SimulatedPoint>>,
+    // (从站ID, 读取功能码, 地址) 被写入的寄存器值
+    written: HashMap<(u8, u8, u16), u16>,
+}
+
+impl Simulator {
+    /// 按网关中每个已启用从站的点位创建
+    pub fn new(config: &Config, gateway: &GatewayConfig) -> Result<Self, Box<dyn Error>> {
+        let mut points: HashMap<(u8, u8), Vec<SimulatedPoint>> = HashMap::new();
+        for slave in gateway.enabled_slaves() {
+            for point in config.enabled_points(gateway, slave)? {
+                let hint = point
+                    .sim
+                    .clone()
+                    .unwrap_or_else(|| SimulationHint::default_for(point.data_type == DataType::Bool));
+                let current = (hint.min() + hint.max()) / 2.0;
+                points
+                    .entry((slave.id, point.function_code))
+                    .or_default()
+                    .push(SimulatedPoint {
+                        point,
+                        hint,
+                        current,
+                    });
+            }
+        }
+        // 各网关的随机序列不同
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let seed = format!("{}:{}", gateway.ip, gateway.port)
+            .bytes()
+            .fold(nanos, |hash, b| hash.rotate_left(5) ^ b as u64);
+        Ok(Simulator {
+            started: Instant::now(),
+            error_rate: gateway.sim_error_rate,
+            rng: seed | 1,
+            points,
+            written: HashMap::new(),
+        })
+    }
+
+    /// 读取寄存器（线圈为0或1）
+    pub fn read(
+        &mut self,
+        slave_id: u8,
+        function_code: u8,
+        address: u16,
+        quantity: u16,
+    ) -> Result<Vec<u16>, Box<dyn Error>> {
+        if !(0x01..=0x04).contains(&function_code) {
+            return Err("不支持的功能码".into());
+        }
+        if self.error_rate > 0.0 && self.random() < self.error_rate {
+            return Err(io::Error::other("模拟的通信故障").into());
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let mut registers = vec![0u16; quantity as usize];
+        let start = address as u32;
+        let end = start + quantity as u32;
+        let mut rng = self.rng;
+        if let Some(points) = self.points.get_mut(&(slave_id, function_code)) {
+            for simulated in points {
+                if simulated.point.address as u32 >= end || simulated.point.end_address() < start {
+                    continue;
+                }
+                let value = simulated.next_value(elapsed, &mut rng);
+                let point = &simulated.point;
+                // 字符串点位模拟为点位名称，超出长度的部分截掉
+                let values = if point.data_type.is_string() {
+                    let capacity = point.register_count() as usize * 2;
+                    let mut end = point.name.len().min(capacity);
+                    while !point.name.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    point.registers_for_text(&point.name[..end])
+                } else {
+                    point.registers_for_value(value)
+                };
+                let Ok(values) = values else {
+                    continue;
+                };
+                for (offset, register) in values.into_iter().enumerate() {
+                    let address = point.address as u32 + offset as u32;
+                    if (start..end).contains(&address) {
+                        registers[(address - start) as usize] = register;
+                    }
+                }
+            }
+        }
+        self.rng = rng;
+        for (offset, register) in registers.iter_mut().enumerate() {
+            let address = address.wrapping_add(offset as u16);
+            if let Some(written) = self.written.get(&(slave_id, function_code, address)) {
+                *register = *written;
+            }
+        }
+        Ok(registers)
+    }
+
+    /// 写入寄存器或线圈，之后对应的读取功能码读到写入的值
+    pub fn write(
+        &mut self,
+        slave_id: u8,
+        function_code: u8,
+        address: u16,
+        values: &[u16],
+    ) -> Result<(), Box<dyn Error>> {
+        let (read_code, values): (u8, Vec<u16>) = match function_code {
+            0x05 | 0x0F => (0x01, values.iter().map(|&v| (v >= 1) as u16).collect()),
+            0x06 | 0x10 => (0x03, values.to_vec()),
+            _ => return Err("不支持的功能码".into()),
+        };
+        for (offset, value) in values.into_iter().enumerate() {
+            let address = address.wrapping_add(offset as u16);
+            self.written.insert((slave_id, read_code, address), value);
+        }
+        Ok(())
+    }
+
+    fn random(&mut self) -> f64 {
+        next_random(&mut self.rng)
+    }
+}
+
+// xorshift64，返回 [0, 1) 之间的随机数
+fn next_random(state: &mut u64) -> f64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    (*state >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl SimulatedPoint {
+    // 当前时刻的工程值，elapsed 为模拟开始以来的秒数
+    fn next_value(&mut self, elapsed: f64, rng: &mut u64) -> f64 {
+        let hint = &self.hint;
+        let (min, max, period) = (hint.min(), hint.max(), hint.period_s());
+        let value = match hint.kind {
+            SimulationKind::Sine => {
+                min + (max - min) * (0.5 + 0.5 * (TAU * elapsed / period).sin())
+            }
+            SimulationKind::RandomWalk => {
+                let step = hint.step() * (next_random(rng) * 2.0 - 1.0);
+                self.current = (self.current + step).clamp(min, max);
+                self.current
+            }
+            SimulationKind::Constant => hint.value.unwrap_or(min),
+            SimulationKind::Toggle => {
+                if ((elapsed / period) as u64).is_multiple_of(2) {
+                    min
+                } else {
+                    max
+                }
+            }
+        };
+        // 布尔点位只能是0或1
+        if self.point.data_type == DataType::Bool {
+            return (value >= 0.5) as u8 as f64;
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINTS: &str = "    points:\n      - { name: voltage, address: 0, scale: 0.1, sim: { kind: sine, min: 210, max: 240, period_s: 60 } }\n      - { name: soc, address: 1, scale: 0.01, sim: { kind: random_walk, min: 20, max: 90, step: 0.5 } }\n      - { name: mode, address: 2, sim: { kind: constant, value: 3 } }\n      - { name: power, address: 3, data_type: f32, word_order: cdab, sim: { kind: sine, min: -50, max: 50, period_s: 10 } }\n      - { name: running, function_code: 1, address: 0, data_type: bool, sim: { kind: toggle, period_s: 30 } }\n";
+
+    fn create(gateway: &str) -> (Config, Simulator) {
+        let yaml = format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    simulation: true\n{}    slave_ids: [1]\n{}",
+            gateway, POINTS
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        let simulator = Simulator::new(&config, &config.gateways[0]).unwrap();
+        (config, simulator)
+    }
+
+    fn point<'a>(
+        simulator: &'a mut Simulator,
+        function_code: u8,
+        name: &str,
+    ) -> &'a mut SimulatedPoint {
+        simulator
+            .points
+            .get_mut(&(1, function_code))
+            .unwrap()
+            .iter_mut()
+            .find(|p| p.point.name == name)
+            .unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn sine_and_toggle_repeat_with_their_period() {
+        let (_, mut simulator) = create("");
+        let mut rng = 1;
+
+        let voltage = point(&mut simulator, 3, "voltage");
+        for (elapsed, expected) in [(0.0, 225.0), (15.0, 240.0), (30.0, 225.0), (45.0, 210.0)] {
+            assert_close(voltage.next_value(elapsed, &mut rng), expected);
+            assert_close(voltage.next_value(elapsed + 60.0, &mut rng), expected);
+            assert_close(voltage.next_value(elapsed + 600.0, &mut rng), expected);
+        }
+
+        let running = point(&mut simulator, 1, "running");
+        let values: Vec<f64> = [0.0, 29.9, 30.0, 59.9, 60.0, 90.0]
+            .iter()
+            .map(|&elapsed| running.next_value(elapsed, &mut rng))
+            .collect();
+        assert_eq!(values, [0.0, 0.0, 1.0, 1.0, 0.0, 1.0]);
+
+        let mode = point(&mut simulator, 3, "mode");
+        assert_eq!(mode.next_value(12.3, &mut rng), 3.0);
+    }
+
+    #[test]
+    fn generated_registers_decode_within_configured_ranges() {
+        let (config, mut simulator) = create("");
+        let gateway = &config.gateways[0];
+        let points = config.enabled_points(gateway, &gateway.slave_ids[0]).unwrap();
+        let decode = |name: &str, registers: &[u16]| {
+            let point = points.iter().find(|p| p.name == name).unwrap();
+            point.value_from(&registers[point.address as usize..]).unwrap()
+        };
+
+        let mut previous_soc: Option<f64> = None;
+        for _ in 0..1000 {
+            let registers = simulator.read(1, 0x03, 0, 5).unwrap();
+            let voltage = decode("voltage", &registers);
+            assert!((210.0..=240.0).contains(&voltage), "{}", voltage);
+            let soc = decode("soc", &registers);
+            assert!((20.0..=90.0).contains(&soc), "{}", soc);
+            if let Some(previous) = previous_soc {
+                assert!((soc - previous).abs() <= 0.51, "{} -> {}", previous, soc);
+            }
+            previous_soc = Some(soc);
+            assert_eq!(decode("mode", &registers), 3.0);
+            let power = decode("power", &registers);
+            assert!((-50.0..=50.0).contains(&power), "{}", power);
+        }
+        // 请求范围只覆盖点位的一部分时只返回范围内的寄存器，不属于点位的寄存器为0
+        assert_eq!(simulator.read(1, 0x03, 4, 3).unwrap()[1..], [0, 0]);
+        assert!(simulator.read(2, 0x03, 0, 2).unwrap().iter().all(|r| *r == 0));
+        assert_eq!(simulator.read(1, 0x01, 0, 1).unwrap(), [0], "toggle 开始时为 min");
+    }
+
+    #[test]
+    fn writes_are_read_back() {
+        let (_, mut simulator) = create("");
+        simulator.write(1, 0x06, 2, &[7]).unwrap();
+        simulator.write(1, 0x10, 10, &[1, 2]).unwrap();
+        simulator.write(1, 0x05, 0, &[0xFF00]).unwrap();
+        assert_eq!(simulator.read(1, 0x03, 2, 1).unwrap(), [7]);
+        assert_eq!(simulator.read(1, 0x03, 10, 2).unwrap(), [1, 2]);
+        assert_eq!(simulator.read(1, 0x01, 0, 1).unwrap(), [1]);
+        assert!(simulator.write(1, 0x03, 0, &[1]).is_err());
+        assert!(simulator.read(1, 0x05, 0, 1).is_err());
+    }
+
+    #[test]
+    fn error_rate_fails_a_share_of_reads() {
+        let (_, mut simulator) = create("    sim_error_rate: 1\n");
+        let error = simulator.read(1, 0x03, 0, 1).unwrap_err();
+        assert_eq!(error.to_string(), "模拟的通信故障");
+        assert!(error.downcast_ref::<io::Error>().is_some(), "与真实的通信故障一样是 IO 错误");
+
+        let (_, mut simulator) = create("    sim_error_rate: 0.25\n");
+        let failed = (0..4000).filter(|_| simulator.read(1, 0x03, 0, 1).is_err()).count();
+        assert!((800..1200).contains(&failed), "{}", failed);
+
+        let (_, mut simulator) = create("");
+        assert!((0..1000).all(|_| simulator.read(1, 0x03, 0, 1).is_ok()));
+    }
+}
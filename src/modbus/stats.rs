@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::time::error::Elapsed;
+use tokio_modbus::ExceptionCode;
+
+use crate::metrics::Histogram;
+
+/// 单个从站的采集统计
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SlaveStats {
+    /// 从站名称
+    pub name: Option<String>,
+    /// 已执行的采集周期数（每个采集组每次执行计一次）
+    pub cycles: u64,
+    /// 成功的读请求数
+    pub reads_ok: u64,
+    /// 失败的读请求数
+    pub reads_failed: u64,
+    /// 执行耗时超过采集周期的次数
+    pub overruns: u64,
+    /// 最近一次失败的错误信息
+    pub last_error: Option<String>,
+    /// 最近一次有成功读请求的采集周期的完成时间，长时间不更新说明该从站的采集被饿死或一直失败
+    pub last_success: Option<SystemTime>,
+}
+
+/// Modbus 请求的结果分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RequestResult {
+    /// 成功
+    Ok,
+    /// 设备返回异常响应
+    Exception,
+    /// 超时
+    Timeout,
+    /// 连接断开等其他错误
+    Error,
+}
+
+impl RequestResult {
+    /// 按请求的结果分类
+    pub fn of<T>(result: &Result<T, Box<dyn Error>>) -> Self {
+        let Err(error) = result else {
+            return RequestResult::Ok;
+        };
+        if error.downcast_ref::<ExceptionCode>().is_some() {
+            RequestResult::Exception
+        } else if error.downcast_ref::<Elapsed>().is_some()
+            || error
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::TimedOut)
+        {
+            RequestResult::Timeout
+        } else {
+            RequestResult::Error
+        }
+    }
+
+    /// 指标标签中使用的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RequestResult::Ok => "ok",
+            RequestResult::Exception => "exception",
+            RequestResult::Timeout => "timeout",
+            RequestResult::Error => "error",
+        }
+    }
+}
+
+/// 单个网关的采集统计，按从站ID区分
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GatewayStats {
+    /// 网关名称，未配置时为 ip:port
+    pub name: String,
+    /// 从站ID到从站统计的映射
+    pub slaves: BTreeMap<u8, SlaveStats>,
+    /// 是否已连接到网关
+    pub connected: bool,
+    /// （从站ID，功能码，结果）到请求数的映射，包括采集、写入和临时读取
+    pub requests: BTreeMap<(u8, u8, RequestResult), u64>,
+    /// Modbus 请求的耗时
+    pub request_durations: Histogram,
+    /// 每次执行一个采集任务（一个从站的一个采集组）的耗时，从本次采集开始计到该任务完成，包括轮流执行其他任务的时间
+    pub cycle_durations: Histogram,
+    /// 采集任务崩溃或意外结束后被重启的次数
+    pub restarts: u64,
+    /// 最近一次崩溃或意外结束的原因
+    pub last_failure: Option<String>,
+    /// 重启过于频繁，已停止采集该网关
+    pub failed: bool,
+    /// 已通过控制命令暂停采集，采集任务重启后保持暂停
+    pub paused: bool,
+}
+
+/// 可在多个任务间共享的网关统计
+pub type SharedStats = Arc<Mutex<GatewayStats>>;
@@ -0,0 +1,646 @@
+use futures_util::future::join_all;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::time::Duration;
+use tokio_modbus::ExceptionCode;
+use tracing::{info, warn};
+
+use crate::device_configuration::modbus::{Config, ModbusDevice as GatewayConfig};
+use crate::device_configuration::point::{merge_generated, Point};
+use crate::device_configuration::units::quantity_of;
+use crate::modbus::client::{ModbusClient, ModbusDevice, ModbusOperation};
+use crate::modbus::decode::{DataType, WordOrder};
+
+/// 依次尝试的 SunSpec 基地址
+pub const BASE_ADDRESSES: [u16; 3] = [40000, 0, 50000];
+/// 基地址处的标识 "SunS"
+pub const MAGIC: [u16; 2] = [0x5375, 0x6e53];
+/// 发现失败的从站再次尝试的最短间隔
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+// 模型链结束标记
+const END_MODEL: u16 = 0xFFFF;
+// 最多遍历的模型数量，避免设备返回错误的长度时一直读下去
+const MAX_MODELS: usize = 64;
+
+/// 模型链中的一个模型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Model {
+    /// 模型ID，例如 1（公共模型）、103（三相逆变器）
+    pub id: u16,
+    /// 模型数据的起始地址（模型头之后）
+    pub address: u16,
+    /// 模型数据的寄存器数量
+    pub length: u16,
+}
+
+// 模型中数据项的类型
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    // 字符串，参数为寄存器数量
+    Str(u16),
+    U16,
+    I16,
+    // acc32、bitfield32
+    U32,
+    // 比例因子 sunssf
+    Sf,
+}
+
+// (偏移, 名称, 类型, 单位, 比例因子名称)，单位和比例因子为空字符串表示没有
+type Field = (u16, &'static str, Kind, &'static str, &'static str);
+
+// 公共模型（1）
+const COMMON: &[Field] = &[
+    (0, "manufacturer", Kind::Str(16), "", ""),
+    (16, "model", Kind::Str(16), "", ""),
+    (32, "options", Kind::Str(8), "", ""),
+    (40, "version", Kind::Str(8), "", ""),
+    (48, "serial_number", Kind::Str(16), "", ""),
+    (64, "device_address", Kind::U16, "", ""),
+];
+
+// 单相、分相、三相逆变器（101、102、103），整数加比例因子格式，名称与内置点位表 sunspec_inverter 相同
+const INVERTER: &[Field] = &[
+    (0, "ac_current", Kind::U16, "A", "ac_current_sf"),
+    (1, "ac_current_a", Kind::U16, "A", "ac_current_sf"),
+    (2, "ac_current_b", Kind::U16, "A", "ac_current_sf"),
+    (3, "ac_current_c", Kind::U16, "A", "ac_current_sf"),
+    (4, "ac_current_sf", Kind::Sf, "", ""),
+    (5, "voltage_ab", Kind::U16, "V", "voltage_sf"),
+    (6, "voltage_bc", Kind::U16, "V", "voltage_sf"),
+    (7, "voltage_ca", Kind::U16, "V", "voltage_sf"),
+    (8, "voltage_an", Kind::U16, "V", "voltage_sf"),
+    (9, "voltage_bn", Kind::U16, "V", "voltage_sf"),
+    (10, "voltage_cn", Kind::U16, "V", "voltage_sf"),
+    (11, "voltage_sf", Kind::Sf, "", ""),
+    (12, "ac_power", Kind::I16, "W", "ac_power_sf"),
+    (13, "ac_power_sf", Kind::Sf, "", ""),
+    (14, "frequency", Kind::U16, "Hz", "frequency_sf"),
+    (15, "frequency_sf", Kind::Sf, "", ""),
+    (16, "apparent_power", Kind::I16, "VA", "apparent_power_sf"),
+    (17, "apparent_power_sf", Kind::Sf, "", ""),
+    (18, "reactive_power", Kind::I16, "var", "reactive_power_sf"),
+    (19, "reactive_power_sf", Kind::Sf, "", ""),
+    (20, "power_factor", Kind::I16, "%", "power_factor_sf"),
+    (21, "power_factor_sf", Kind::Sf, "", ""),
+    (22, "lifetime_energy", Kind::U32, "Wh", "lifetime_energy_sf"),
+    (24, "lifetime_energy_sf", Kind::Sf, "", ""),
+    (25, "dc_current", Kind::U16, "A", "dc_current_sf"),
+    (26, "dc_current_sf", Kind::Sf, "", ""),
+    (27, "dc_voltage", Kind::U16, "V", "dc_voltage_sf"),
+    (28, "dc_voltage_sf", Kind::Sf, "", ""),
+    (29, "dc_power", Kind::I16, "W", "dc_power_sf"),
+    (30, "dc_power_sf", Kind::Sf, "", ""),
+    (31, "cabinet_temperature", Kind::I16, "°C", "temperature_sf"),
+    (32, "heat_sink_temperature", Kind::I16, "°C", "temperature_sf"),
+    (33, "transformer_temperature", Kind::I16, "°C", "temperature_sf"),
+    (34, "other_temperature", Kind::I16, "°C", "temperature_sf"),
+    (35, "temperature_sf", Kind::Sf, "", ""),
+    (36, "operating_state", Kind::U16, "", ""),
+    (37, "vendor_operating_state", Kind::U16, "", ""),
+    (38, "events", Kind::U32, "", ""),
+];
+
+// 三相星形接法电表（203），整数加比例因子格式
+const WYE_METER: &[Field] = &[
+    (0, "current", Kind::I16, "A", "current_sf"),
+    (1, "current_a", Kind::I16, "A", "current_sf"),
+    (2, "current_b", Kind::I16, "A", "current_sf"),
+    (3, "current_c", Kind::I16, "A", "current_sf"),
+    (4, "current_sf", Kind::Sf, "", ""),
+    (5, "voltage_ln", Kind::I16, "V", "voltage_sf"),
+    (6, "voltage_an", Kind::I16, "V", "voltage_sf"),
+    (7, "voltage_bn", Kind::I16, "V", "voltage_sf"),
+    (8, "voltage_cn", Kind::I16, "V", "voltage_sf"),
+    (9, "voltage_ll", Kind::I16, "V", "voltage_sf"),
+    (10, "voltage_ab", Kind::I16, "V", "voltage_sf"),
+    (11, "voltage_bc", Kind::I16, "V", "voltage_sf"),
+    (12, "voltage_ca", Kind::I16, "V", "voltage_sf"),
+    (13, "voltage_sf", Kind::Sf, "", ""),
+    (14, "frequency", Kind::I16, "Hz", "frequency_sf"),
+    (15, "frequency_sf", Kind::Sf, "", ""),
+    (16, "power", Kind::I16, "W", "power_sf"),
+    (17, "power_a", Kind::I16, "W", "power_sf"),
+    (18, "power_b", Kind::I16, "W", "power_sf"),
+    (19, "power_c", Kind::I16, "W", "power_sf"),
+    (20, "power_sf", Kind::Sf, "", ""),
+    (21, "apparent_power", Kind::I16, "VA", "apparent_power_sf"),
+    (25, "apparent_power_sf", Kind::Sf, "", ""),
+    (26, "reactive_power", Kind::I16, "var", "reactive_power_sf"),
+    (30, "reactive_power_sf", Kind::Sf, "", ""),
+    (31, "power_factor", Kind::I16, "%", "power_factor_sf"),
+    (35, "power_factor_sf", Kind::Sf, "", ""),
+    (36, "energy_exported", Kind::U32, "Wh", "energy_sf"),
+    (44, "energy_imported", Kind::U32, "Wh", "energy_sf"),
+    (52, "energy_sf", Kind::Sf, "", ""),
+    (103, "events", Kind::U32, "", ""),
+];
+
+// 支持的模型：模型ID、类别（同类模型重复出现时用于区分名称）、第一个该类模型的名称前缀、数据项
+const SUPPORTED: &[(u16, &str, &str, &[Field])] = &[
+    (1, "common", "", COMMON),
+    (101, "inverter", "", INVERTER),
+    (102, "inverter", "", INVERTER),
+    (103, "inverter", "", INVERTER),
+    (203, "meter", "meter_", WYE_METER),
+];
+
+/// 读取从站的 SunSpec 模型链
+///
+/// 依次在 [`BASE_ADDRESSES`] 处读取两个保持寄存器，找到 "SunS" 标识后从其后逐个读取模型头（模型ID、长度），
+/// 直到模型ID为 0xFFFF 或读取模型头得到异常响应。
+///
+/// # 参数说明
+/// * `client` - 已连接并设置好从站ID的客户端
+///
+/// # 返回值
+/// * `Err` - 所有基地址处都没有标识、模型链超出地址范围或通信失败
+pub async fn scan(client: &mut ModbusClient) -> Result<Vec<Model>, Box<dyn Error>> {
+    let mut base = None;
+    for address in BASE_ADDRESSES {
+        let reconnect = match client.read_registers(0x03, address, 2).await {
+            Ok(values) if values == MAGIC => {
+                base = Some(address);
+                break;
+            }
+            Ok(_) => false,
+            // 异常响应说明该地址没有数据，连接仍然可用；
+            // 超时等错误后客户端已断开连接，重新连接后尝试下一个基地址
+            Err(e) => e.downcast_ref::<ExceptionCode>().is_none(),
+        };
+        if reconnect {
+            client.connect().await?;
+        }
+    }
+    let Some(base) = base else {
+        return Err(format!("基地址 {:?} 处都没有 SunSpec 标识", BASE_ADDRESSES).into());
+    };
+
+    let mut models = Vec::new();
+    let mut address = base as u32 + 2;
+    while models.len() < MAX_MODELS {
+        if address + 2 > u16::MAX as u32 + 1 {
+            return Err("SunSpec 模型链超出地址范围".into());
+        }
+        let header = match client.read_registers(0x03, address as u16, 2).await {
+            Ok(header) => header,
+            // 部分设备没有结束标记，读到模型链之后的地址时返回异常响应
+            Err(e) if e.downcast_ref::<ExceptionCode>().is_some() => return Ok(models),
+            Err(e) => return Err(e),
+        };
+        let [id, length] = header[..] else {
+            return Err(format!("读取地址 {} 的模型头返回了 {} 个寄存器", address, header.len()).into());
+        };
+        if id == END_MODEL {
+            return Ok(models);
+        }
+        let start = address + 2;
+        if start + length as u32 > u16::MAX as u32 + 1 {
+            return Err(format!("SunSpec 模型 {}（地址 {}，长度 {}）超出地址范围", id, start, length).into());
+        }
+        models.push(Model {
+            id,
+            address: start as u16,
+            length,
+        });
+        address = start + length as u32;
+    }
+    Err(format!("SunSpec 模型链超过 {} 个模型", MAX_MODELS).into())
+}
+
+/// 为支持的模型（1、101、102、103、203）生成点位
+///
+/// # 说明
+/// * 公共模型和逆变器模型的点位名称不加前缀，电表模型加 `meter_`；同类模型再次出现时加 `<类别><序号>_`，例如 `meter2_`
+/// * 整数加比例因子格式的数值点位通过 scale_factor 引用对应的 `*_sf` 点位，读取时按比例因子换算
+/// * 可换算的单位（W、V、A 等）设置为 source_unit，其余（VA、var）设置为 unit
+/// * 长度小于数据项所需的模型按不支持处理
+///
+/// # 返回值
+/// 生成的点位，以及不支持的模型ID
+pub fn points_for(models: &[Model]) -> (Vec<Point>, Vec<u16>) {
+    let mut points = Vec::new();
+    let mut unsupported = Vec::new();
+    let mut occurrences: BTreeMap<&str, usize> = BTreeMap::new();
+    for model in models {
+        let Some((_, family, first_prefix, fields)) =
+            SUPPORTED.iter().find(|(id, ..)| *id == model.id)
+        else {
+            unsupported.push(model.id);
+            continue;
+        };
+        let required = fields
+            .iter()
+            .map(|(offset, _, kind, ..)| offset + register_count(*kind))
+            .max()
+            .unwrap_or(0);
+        if model.length < required {
+            warn!(model = model.id, length = model.length, required, "SunSpec 模型长度不足，已跳过");
+            unsupported.push(model.id);
+            continue;
+        }
+        let count = occurrences.entry(family).or_default();
+        *count += 1;
+        let prefix = match *count {
+            1 => first_prefix.to_string(),
+            n => format!("{}{}_", family, n),
+        };
+        for (offset, name, kind, unit, scale_factor) in fields.iter() {
+            points.push(point(
+                format!("{}{}", prefix, name),
+                model.address + offset,
+                *kind,
+                unit,
+                (!scale_factor.is_empty()).then(|| format!("{}{}", prefix, scale_factor)),
+            ));
+        }
+    }
+    (points, unsupported)
+}
+
+/// 对设置了 `sunspec: auto`、尚未发现的从站进行发现，生成的点位保存在从站的 discovered_points 中
+///
+/// # 说明
+/// * 只发现已启用网关中的已启用从站，模拟模式的网关跳过
+/// * 各网关同时发现，使用单独的连接，同一网关的从站依次发现，完成后断开
+/// * 发现失败的从站 discovered_points 保持为 None，可以再次调用重试
+pub async fn discover(config: &mut Config) {
+    let pending: Vec<(usize, Vec<u8>)> = config
+        .gateways
+        .iter()
+        .enumerate()
+        .filter(|(_, gateway)| gateway.enabled && !gateway.simulation)
+        .map(|(index, gateway)| {
+            let slaves = gateway
+                .enabled_slaves()
+                .filter(|slave| slave.sunspec.is_some() && slave.discovered_points.is_none())
+                .map(|slave| slave.id)
+                .collect::<Vec<u8>>();
+            (index, slaves)
+        })
+        .filter(|(_, slaves)| !slaves.is_empty())
+        .collect();
+    if pending.is_empty() {
+        return;
+    }
+    let results = join_all(
+        pending
+            .iter()
+            .map(|(index, slaves)| discover_gateway(&config.gateways[*index], slaves)),
+    )
+    .await;
+
+    for ((index, _), results) in pending.iter().zip(results) {
+        for (slave_id, points) in results {
+            let gateway = &config.gateways[*index];
+            let Some(slave) = gateway.find_slave(slave_id) else {
+                continue;
+            };
+            // 与手动配置的点位冲突的生成点位不会采集
+            if let Ok(mut existing) = config.effective_points(gateway, slave) {
+                let skipped = merge_generated(&mut existing, &points);
+                if !skipped.is_empty() {
+                    warn!(
+                        gateway = %gateway.display_name(),
+                        slave = %slave.display_name(),
+                        points = ?skipped,
+                        "SunSpec 生成的点位与已配置的点位名称或地址冲突，已跳过"
+                    );
+                }
+            }
+            if let Some(slave) = config.gateways[*index]
+                .slave_ids
+                .iter_mut()
+                .find(|slave| slave.id == slave_id)
+            {
+                slave.discovered_points = Some(points);
+            }
+        }
+    }
+}
+
+/// 沿用之前配置中同一网关（ip:port）、同一从站的发现结果，用于配置热加载时避免重复发现
+pub fn carry_over(config: &mut Config, previous: &Config) {
+    for gateway in &mut config.gateways {
+        let Some(old) = previous
+            .gateways
+            .iter()
+            .find(|old| old.ip == gateway.ip && old.port == gateway.port)
+        else {
+            continue;
+        };
+        for slave in gateway.slave_ids.iter_mut().filter(|slave| slave.sunspec.is_some()) {
+            if let Some(points) = old
+                .find_slave(slave.id)
+                .filter(|old| old.sunspec.is_some())
+                .and_then(|old| old.discovered_points.clone())
+            {
+                slave.discovered_points = Some(points);
+            }
+        }
+    }
+}
+
+/// 是否有需要发现但尚未成功的从站
+pub fn has_pending(config: &Config) -> bool {
+    config
+        .gateways
+        .iter()
+        .filter(|gateway| gateway.enabled && !gateway.simulation)
+        .flat_map(|gateway| gateway.enabled_slaves())
+        .any(|slave| slave.sunspec.is_some() && slave.discovered_points.is_none())
+}
+
+// 发现一个网关中的多个从站，返回发现成功的从站及其点位
+async fn discover_gateway(gateway: &GatewayConfig, slaves: &[u8]) -> Vec<(u8, Vec<Point>)> {
+    let name = gateway.display_name();
+    let mut client = ModbusClient::new(ModbusDevice {
+        name: gateway.name.clone(),
+        ip: gateway.ip.clone(),
+        port: gateway.port,
+        slave_id: slaves[0],
+        connect_timeout: Duration::from_millis(gateway.connect_timeout_ms),
+        request_timeout: Duration::from_millis(gateway.request_timeout_ms),
+    });
+    if let Err(e) = client.connect().await {
+        warn!(gateway = %name, error = %e, "SunSpec 发现失败：无法连接网关，稍后重试");
+        return Vec::new();
+    }
+    let mut results = Vec::new();
+    for &slave_id in slaves {
+        if !client.is_connected()
+            && let Err(e) = client.connect().await
+        {
+            warn!(gateway = %name, slave_id, error = %e, "SunSpec 发现失败：无法连接网关，稍后重试");
+            continue;
+        }
+        client.set_slave_id(slave_id);
+        match scan(&mut client).await {
+            Ok(models) => {
+                let (points, unsupported) = points_for(&models);
+                let ids: Vec<u16> = models.iter().map(|model| model.id).collect();
+                info!(gateway = %name, slave_id, models = ?ids, points = points.len(), "SunSpec 发现完成");
+                if !unsupported.is_empty() {
+                    let unsupported: BTreeSet<u16> = unsupported.into_iter().collect();
+                    info!(gateway = %name, slave_id, models = ?unsupported, "跳过不支持的 SunSpec 模型");
+                }
+                results.push((slave_id, points));
+            }
+            Err(e) => warn!(gateway = %name, slave_id, error = %e, "SunSpec 发现失败，稍后重试"),
+        }
+    }
+    let _ = client.disconnect().await;
+    results
+}
+
+fn register_count(kind: Kind) -> u16 {
+    match kind {
+        Kind::Str(length) => length,
+        Kind::U16 | Kind::I16 | Kind::Sf => 1,
+        Kind::U32 => 2,
+    }
+}
+
+// 由模型中的数据项生成点位，SunSpec 使用保持寄存器，多寄存器数据高位在前
+fn point(name: String, address: u16, kind: Kind, unit: &str, scale_factor: Option<String>) -> Point {
+    let (data_type, length) = match kind {
+        Kind::Str(length) => (DataType::String { length_registers: 0 }, Some(length)),
+        Kind::U16 => (DataType::U16, None),
+        Kind::I16 | Kind::Sf => (DataType::I16, None),
+        Kind::U32 => (DataType::U32, None),
+    };
+    let convertible = quantity_of(unit).is_some();
+    Point {
+        name,
+        function_code: 0x03,
+        address,
+        data_type,
+        length,
+        word_order: WordOrder::Abcd,
+        scale: 1.0,
+        offset: 0.0,
+        scale_factor,
+        unit: (!unit.is_empty() && !convertible).then(|| unit.to_string()),
+        source_unit: convertible.then(|| unit.to_string()),
+        group: None,
+        enabled: true,
+        writable: false,
+        verify_write: false,
+        qos: None,
+        retain: None,
+        deadband: None,
+        device_class: None,
+        sim: None,
+        bits: BTreeMap::new(),
+        bit: None,
+        conversion: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::decode::encode_string;
+    use crate::modbus::scheduler::{GatewayPoller, PollEvent};
+    use crate::test_support::MockModbus;
+
+    fn text(value: &str, registers: u16) -> Vec<u16> {
+        encode_string(WordOrder::Abcd, value, registers).unwrap()
+    }
+
+    // 一个模型：模型头和按偏移设置的数据
+    fn model(id: u16, length: u16, values: &[(u16, &[u16])]) -> Vec<u16> {
+        let mut data = vec![0u16; length as usize];
+        for (offset, words) in values {
+            data[*offset as usize..*offset as usize + words.len()].copy_from_slice(words);
+        }
+        [vec![id, length], data].concat()
+    }
+
+    // 一台三相逆变器的寄存器：公共模型、逆变器模型 103、不支持的模型 120、电表模型 203 和结束标记
+    fn image() -> Vec<u16> {
+        let sf = |value: i16| value as u16;
+        let common = model(
+            1,
+            66,
+            &[
+                (0, &text("Fronius", 16)),
+                (16, &text("Symo 10.0-3-M", 16)),
+                (40, &text("3.14.1-2", 8)),
+                (48, &text("28490123", 16)),
+                (64, &[1]),
+            ],
+        );
+        let inverter = model(
+            103,
+            50,
+            &[
+                // 交流电流 21.50 A，比例因子 -2
+                (0, &[2150, 717, 716, 717, sf(-2)]),
+                // 相电压 230.1 V，比例因子 -1
+                (8, &[2301, 2299, 2302, sf(-1)]),
+                // 交流功率 987 × 10 W
+                (12, &[987, sf(1)]),
+                // 频率 50.02 Hz
+                (14, &[5002, sf(-2)]),
+                // 累计发电量 123456789 Wh（acc32）
+                (22, &[0x075B, 0xCD15, sf(0)]),
+                // 散热器温度 45.2 °C
+                (31, &[0, 452, 0, 0, sf(-1)]),
+                (36, &[4]),
+            ],
+        );
+        let meter = model(203, 105, &[(16, &[sf(-1500), 0, 0, 0, sf(0)]), (36, &[0, 4200]), (52, &[sf(0)])]);
+        [
+            MAGIC.to_vec(),
+            common,
+            inverter,
+            model(120, 26, &[(0, &[10_000])]),
+            meter,
+            vec![END_MODEL, 0],
+        ]
+        .concat()
+    }
+
+    async fn client(modbus: &MockModbus, slave_id: u8) -> ModbusClient {
+        let mut client = ModbusClient::new(ModbusDevice {
+            name: None,
+            ip: "127.0.0.1".to_string(),
+            port: modbus.port,
+            slave_id,
+            connect_timeout: Duration::from_millis(500),
+            request_timeout: Duration::from_millis(500),
+        });
+        client.connect().await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn scan_walks_the_model_chain() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 40000, &image());
+        let models = scan(&mut client(&modbus, 1).await).await.unwrap();
+        assert_eq!(
+            models,
+            [
+                Model { id: 1, address: 40004, length: 66 },
+                Model { id: 103, address: 40072, length: 50 },
+                Model { id: 120, address: 40124, length: 26 },
+                Model { id: 203, address: 40152, length: 105 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_tries_each_base_address() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 0, &image());
+        modbus.set(2, 3, 50000, &image());
+        let models = scan(&mut client(&modbus, 1).await).await.unwrap();
+        assert_eq!(models[0], Model { id: 1, address: 4, length: 66 });
+        let models = scan(&mut client(&modbus, 2).await).await.unwrap();
+        assert_eq!(models[3], Model { id: 203, address: 50152, length: 105 });
+
+        // 基地址处返回异常响应或没有标识
+        modbus.reject(4, 0x02);
+        let error = scan(&mut client(&modbus, 4).await).await.unwrap_err();
+        assert_eq!(error.to_string(), "基地址 [40000, 0, 50000] 处都没有 SunSpec 标识");
+        let error = scan(&mut client(&modbus, 5).await).await.unwrap_err();
+        assert_eq!(error.to_string(), "基地址 [40000, 0, 50000] 处都没有 SunSpec 标识");
+    }
+
+    #[test]
+    fn points_for_supported_models() {
+        let models = [
+            Model { id: 1, address: 40004, length: 66 },
+            Model { id: 103, address: 40072, length: 50 },
+            Model { id: 120, address: 40124, length: 26 },
+            Model { id: 203, address: 40152, length: 105 },
+            Model { id: 203, address: 40259, length: 105 },
+            // 长度不足的模型按不支持处理
+            Model { id: 101, address: 40366, length: 10 },
+        ];
+        let (points, unsupported) = points_for(&models);
+        assert_eq!(unsupported, [120, 101]);
+        let find = |name: &str| points.iter().find(|p| p.name == name).unwrap_or_else(|| panic!("没有点位 {}", name));
+
+        let manufacturer = find("manufacturer");
+        assert_eq!((manufacturer.address, manufacturer.length), (40004, Some(16)));
+        assert!(manufacturer.data_type.is_string());
+        let current = find("ac_current");
+        assert_eq!(current.address, 40072);
+        assert_eq!(current.scale_factor.as_deref(), Some("ac_current_sf"));
+        assert_eq!(current.source_unit.as_deref(), Some("A"));
+        assert_eq!(find("ac_current_sf").data_type, DataType::I16);
+        let energy = find("lifetime_energy");
+        assert_eq!((energy.address, energy.data_type), (40094, DataType::U32));
+        // VA、var 不能换算，作为 unit
+        let apparent = find("apparent_power");
+        assert_eq!((apparent.unit.as_deref(), apparent.source_unit.as_deref()), (Some("VA"), None));
+        assert_eq!(find("operating_state").scale_factor, None);
+
+        // 第一个电表加 meter_ 前缀，第二个加 meter2_，比例因子引用同一模型中的点位
+        assert_eq!(find("meter_power").address, 40168);
+        assert_eq!(find("meter2_power").address, 40275);
+        assert_eq!(find("meter2_power").scale_factor.as_deref(), Some("meter2_power_sf"));
+        assert_eq!(points.len(), COMMON.len() + INVERTER.len() + WYE_METER.len() * 2);
+    }
+
+    #[tokio::test]
+    async fn discovered_points_are_read_with_scale_factors() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 40000, &image());
+        modbus.set(1, 3, 100, &[55]);
+        let yaml = format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    slave_ids: [{{ id: 1, sunspec: auto }}]\n    points:\n      - {{ name: export_limit, address: 100 }}\n",
+            modbus.port
+        );
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        assert!(has_pending(&config));
+        discover(&mut config).await;
+        assert!(!has_pending(&config));
+        assert!(config.gateways[0].slave_ids[0].discovered_points.is_some());
+
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        let mut readings = BTreeMap::new();
+        poller
+            .run_once(|event| {
+                if let PollEvent::Readings(batch) = event {
+                    readings.extend(batch.into_iter().map(|r| (r.point.clone(), r)));
+                }
+            })
+            .await;
+        // 比例因子在之后的读请求中的点位第一次采集时跳过，之后使用上次读到的比例因子
+        assert!(!readings.contains_key("meter_energy_exported"));
+        poller
+            .run_once(|event| {
+                if let PollEvent::Readings(batch) = event {
+                    readings.extend(batch.into_iter().map(|r| (r.point.clone(), r)));
+                }
+            })
+            .await;
+        let value = |name: &str| readings[name].value;
+        let close = |name: &str, expected: f64| {
+            assert!((value(name) - expected).abs() < 1e-9, "{} = {}，应为 {}", name, value(name), expected);
+        };
+        close("ac_current", 21.5);
+        close("ac_current_a", 7.17);
+        close("voltage_an", 230.1);
+        close("ac_power", 9870.0);
+        close("frequency", 50.02);
+        close("lifetime_energy", 123_456_789.0);
+        close("heat_sink_temperature", 45.2);
+        close("operating_state", 4.0);
+        close("meter_power", -1500.0);
+        close("meter_energy_exported", 4200.0);
+        // 手动配置的点位照常采集
+        close("export_limit", 55.0);
+        assert_eq!(readings["manufacturer"].text.as_deref(), Some("Fronius"));
+        assert_eq!(readings["model"].text.as_deref(), Some("Symo 10.0-3-M"));
+        assert_eq!(readings["serial_number"].text.as_deref(), Some("28490123"));
+        assert_eq!(readings["ac_power"].unit.as_deref(), Some("W"));
+        // 不支持的模型 120 没有生成点位
+        assert!(!readings.keys().any(|name| name.starts_with("model120")));
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::device_configuration::modbus::ModbusDevice as GatewayConfig;
+use crate::device_configuration::watchdog::WatchdogSettings;
+use crate::modbus::availability::Availability;
+use crate::modbus::client::{ModbusClient, ModbusOperation};
+
+/// 看门狗状态变化：连续写入失败达到 `max_missed` 次，或之后恢复
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchdogEvent {
+    /// 网关地址，格式为 ip:port
+    pub gateway: String,
+    /// 网关名称
+    pub gateway_name: Option<String>,
+    /// 从站ID
+    pub slave_id: u8,
+    /// 从站名称
+    pub slave_name: Option<String>,
+    /// true 为心跳写入失败，false 为已恢复
+    pub failed: bool,
+    /// 连续失败的次数
+    pub missed: u32,
+    /// 最近一次失败的原因
+    pub error: Option<String>,
+    /// 变化发生的时间
+    pub timestamp: SystemTime,
+}
+
+impl WatchdogEvent {
+    /// 网关名称，未配置时为 ip:port
+    pub fn gateway_display(&self) -> &str {
+        self.gateway_name.as_deref().unwrap_or(&self.gateway)
+    }
+
+    /// 从站名称，未配置时为从站ID
+    pub fn slave_display(&self) -> String {
+        self.slave_name
+            .clone()
+            .unwrap_or_else(|| self.slave_id.to_string())
+    }
+}
+
+// 一个从站的看门狗
+struct Watchdog {
+    slave_id: u8,
+    slave_name: Option<String>,
+    settings: WatchdogSettings,
+    next_due: Instant,
+    // 已成功写入的次数，决定下一次写入的值
+    count: u64,
+    missed: u32,
+    failed: bool,
+}
+
+/// 一个网关下所有从站的看门狗，由该网关的采集调度器执行
+///
+/// # 说明
+/// * 到期的心跳在采集之前写入，执行较长的读取计划时也会在两个读请求之间写入，不会被采集拖延
+/// * 心跳与写入和临时读取请求共用连接，正在执行的请求不会被打断
+/// * 从站离线时暂停写入，不计入失败次数，恢复在线后继续
+/// * 连续失败达到 `max_missed` 次时产生一次失败事件，之后第一次写入成功时产生恢复事件
+pub(crate) struct Watchdogs {
+    gateway: String,
+    gateway_name: Option<String>,
+    name: String,
+    list: Vec<Watchdog>,
+    events: Vec<WatchdogEvent>,
+}
+
+impl Watchdogs {
+    /// 按网关配置创建，只包含启用且配置了 watchdog 的从站
+    pub(crate) fn new(gateway: &GatewayConfig) -> Self {
+        let now = Instant::now();
+        Watchdogs {
+            gateway: format!("{}:{}", gateway.ip, gateway.port),
+            gateway_name: gateway.name.clone(),
+            name: gateway.display_name(),
+            list: gateway
+                .enabled_slaves()
+                .filter_map(|slave| {
+                    let settings = slave.watchdog.clone()?;
+                    Some(Watchdog {
+                        slave_id: slave.id,
+                        slave_name: slave.name.clone(),
+                        settings,
+                        next_due: now,
+                        count: 0,
+                        missed: 0,
+                        failed: false,
+                    })
+                })
+                .collect(),
+            events: Vec::new(),
+        }
+    }
+
+    /// 停用所有看门狗，`--once` 单次采集时不写心跳
+    pub(crate) fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// 最近一个需要写入的心跳的时间，没有看门狗时返回 None
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.list.iter().map(|w| w.next_due).min()
+    }
+
+    /// 写入所有已到期的心跳；写入后连接上的从站ID会改变，调用方需要重新设置
+    pub(crate) async fn service(
+        &mut self,
+        client: &mut ModbusClient,
+        availability: &BTreeMap<u8, Availability>,
+    ) {
+        let now = Instant::now();
+        for index in 0..self.list.len() {
+            let watchdog = &mut self.list[index];
+            if watchdog.next_due > now {
+                continue;
+            }
+            // 按周期累加计算下一次写入时间，错过的周期直接跳过
+            let interval = watchdog.settings.interval();
+            watchdog.next_due += interval;
+            while watchdog.next_due <= now {
+                watchdog.next_due += interval;
+            }
+            if availability.get(&watchdog.slave_id) == Some(&Availability::Offline) {
+                continue;
+            }
+
+            let result = beat(client, watchdog).await;
+            let watchdog = &mut self.list[index];
+            let error = match result {
+                Ok(()) => {
+                    watchdog.count += 1;
+                    watchdog.missed = 0;
+                    if !watchdog.failed {
+                        continue;
+                    }
+                    watchdog.failed = false;
+                    info!(gateway = %self.name, slave_id = watchdog.slave_id, "看门狗心跳恢复");
+                    None
+                }
+                Err(e) => {
+                    watchdog.missed += 1;
+                    warn!(
+                        gateway = %self.name,
+                        slave_id = watchdog.slave_id,
+                        address = watchdog.settings.address,
+                        missed = watchdog.missed,
+                        error = %e,
+                        "写入看门狗心跳失败"
+                    );
+                    if watchdog.failed || watchdog.missed < watchdog.settings.max_missed {
+                        continue;
+                    }
+                    watchdog.failed = true;
+                    Some(e)
+                }
+            };
+            self.events.push(WatchdogEvent {
+                gateway: self.gateway.clone(),
+                gateway_name: self.gateway_name.clone(),
+                slave_id: watchdog.slave_id,
+                slave_name: watchdog.slave_name.clone(),
+                failed: watchdog.failed,
+                missed: watchdog.missed,
+                error,
+                timestamp: SystemTime::now(),
+            });
+        }
+    }
+
+    /// 取出上次调用以来的状态变化
+    pub(crate) fn take_events(&mut self) -> Vec<WatchdogEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+// 写入一次心跳
+async fn beat(client: &mut ModbusClient, watchdog: &Watchdog) -> Result<(), String> {
+    if !client.is_connected() {
+        client.connect().await.map_err(|e| e.to_string())?;
+    }
+    client.set_slave_id(watchdog.slave_id);
+    client
+        .write_registers(
+            watchdog.settings.function_code,
+            watchdog.settings.address,
+            1,
+            vec![watchdog.settings.value_at(watchdog.count)],
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tokio::sync::watch;
+
+    use crate::device_configuration::modbus::Config;
+    use crate::modbus::scheduler::{GatewayPoller, PollEvent};
+    use crate::test_support::{MockModbus, ModbusRequest};
+
+    // 每个读请求的应答延迟
+    const DELAY: Duration = Duration::from_millis(40);
+
+    // 20个相距较远的点位，每个从站的读取计划有20个读请求，一轮采集远长于心跳周期
+    fn config(port: u16) -> Config {
+        let points: String = (0..20)
+            .map(|i| format!("      - {{ name: p{}, address: {} }}\n", i, i * 200))
+            .collect();
+        let yaml = format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    poll_interval_ms: 100\n    slave_ids:\n      - {{ id: 1, watchdog: {{ address: 5000, interval_ms: 200, pattern: toggle }} }}\n      - {{ id: 2, watchdog: {{ address: 5000, interval_ms: 300, pattern: increment, value: 10 }} }}\n    points:\n{}",
+            port, points
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    // 运行采集 `window` 时长，返回收到的请求
+    async fn run_for(modbus: &MockModbus, window: Duration) -> Vec<ModbusRequest> {
+        let config = config(modbus.port);
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+        let (stop_sender, stop) = watch::channel(false);
+        let stopper = tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let _ = stop_sender.send(true);
+        });
+        poller.run(|_: PollEvent| {}, stop).await;
+        stopper.await.unwrap();
+        modbus.requests()
+    }
+
+    fn heartbeats(requests: &[ModbusRequest], slave_id: u8) -> Vec<&ModbusRequest> {
+        requests
+            .iter()
+            .filter(|r| r.slave_id == slave_id && r.function_code == 6 && r.address == 5000)
+            .collect()
+    }
+
+    // 相邻两次心跳之间的间隔不超出周期加减一个读请求的耗时（留出调度的余量）
+    fn assert_cadence(beats: &[&ModbusRequest], interval: Duration) {
+        let slack = DELAY + Duration::from_millis(40);
+        for pair in beats.windows(2) {
+            let gap = pair[1].received - pair[0].received;
+            assert!(
+                gap + slack >= interval && gap <= interval + slack,
+                "心跳间隔 {:?} 偏离周期 {:?}",
+                gap,
+                interval
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn heartbeats_keep_their_cadence_during_long_read_plans() {
+        let modbus = MockModbus::start().await;
+        modbus.set_delay(DELAY);
+        let requests = run_for(&modbus, Duration::from_millis(2000)).await;
+
+        // 两个从站各20个读请求，一轮采集至少1.6秒，期间一直在读取
+        let reads = requests.iter().filter(|r| r.function_code == 3).count();
+        assert!(reads >= 40, "只执行了 {} 个读请求", reads);
+        assert!(requests.iter().all(|r| matches!(r.function_code, 3 | 6)));
+
+        let toggle = heartbeats(&requests, 1);
+        assert!(toggle.len() >= 8, "从站1只写入了 {} 次心跳", toggle.len());
+        let values: Vec<u16> = toggle.iter().map(|r| r.values[0]).collect();
+        let expected: Vec<u16> = (0..values.len()).map(|i| if i % 2 == 0 { 1 } else { 0 }).collect();
+        assert_eq!(values, expected);
+        assert_cadence(&toggle, Duration::from_millis(200));
+
+        let increment = heartbeats(&requests, 2);
+        assert!(increment.len() >= 5, "从站2只写入了 {} 次心跳", increment.len());
+        let values: Vec<u16> = increment.iter().map(|r| r.values[0]).collect();
+        let expected: Vec<u16> = (10..10 + values.len() as u16).collect();
+        assert_eq!(values, expected);
+        assert_cadence(&increment, Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn heartbeats_are_interleaved_with_reads_of_one_cycle() {
+        let modbus = MockModbus::start().await;
+        modbus.set_delay(DELAY);
+        let requests = run_for(&modbus, Duration::from_millis(1200)).await;
+
+        // 第一轮采集的40个读请求之间穿插着心跳，而不是等一轮采集结束后才写入
+        let first_cycle: Vec<&ModbusRequest> = requests
+            .iter()
+            .take_while(|r| r.function_code != 3 || r.address != 3800 || r.slave_id != 2)
+            .collect();
+        let beats = first_cycle.iter().filter(|r| r.function_code == 6).count();
+        let reads = first_cycle.iter().filter(|r| r.function_code == 3).count();
+        assert!(reads >= 30, "第一轮采集只有 {} 个读请求", reads);
+        assert!(beats >= 6, "第一轮采集期间只写入了 {} 次心跳", beats);
+        assert_eq!(requests[0].function_code, 6);
+    }
+}
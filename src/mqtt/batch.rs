@@ -0,0 +1,561 @@
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::device_configuration::mqtt::{Aggregation, MqttSettings};
+use crate::modbus::reading::{PointValue, Quality, Reading};
+use crate::mqtt::compression::Compression;
+use crate::mqtt::payload::{
+    format_reading, gateway_name, key_value, quality_of, slave_name, CyclePayload, DevicePayload,
+    Envelope, EnvelopeBuilder, PayloadFormat,
+};
+use crate::mqtt::topic::{TopicTemplate, TopicValues};
+
+/// 一条待发布的采集数据消息
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataMessage {
+    /// 发布主题
+    pub topic: String,
+    /// 消息内所有点位共同的 QoS
+    pub qos: u8,
+    /// 消息内所有点位共同的 retain
+    pub retain: bool,
+    /// 消息内所有点位共同的格式
+    pub format: PayloadFormat,
+    /// 消息内所有点位共同的压缩方式，发布时才压缩
+    pub compression: Compression,
+    /// 已按格式编码的消息内容
+    pub payload: Vec<u8>,
+    /// 消息包含数据的从站（网关地址，从站ID）
+    pub devices: Vec<(String, u8)>,
+    /// 消息所属的一组点位，拆分为多条消息时每条都带有整组点位；本地存储据此标记已发布
+    pub readings: Vec<ReadingKey>,
+}
+
+/// 一个点位的一次采集：网关地址、从站ID、点位名称和采集时间
+pub type ReadingKey = (String, u8, String, SystemTime);
+
+/// 采集数据对应的 [`ReadingKey`]
+pub fn reading_key(reading: &Reading) -> ReadingKey {
+    (
+        reading.gateway.clone(),
+        reading.slave_id,
+        reading.point.clone(),
+        reading.timestamp,
+    )
+}
+
+/// 按合并方式把一批采集数据组成待发布的消息
+///
+/// # 说明
+/// * 同一消息内的点位 QoS、retain、格式和压缩方式相同，不同时分成多条消息发布到同一主题
+/// * influx_line 和 key_value 合并时每个点位一行；per_cycle 的 key_value 以 `从站.点位` 为键
+/// * 配置了 max_payload_bytes 时，合并后的消息超出限制则按点位名称顺序拆分为多条消息，
+///   单个点位已超出限制时单独成为一条消息；per_point 不拆分
+/// * 每条消息的公共字段（采集时间、序号、站点等）由 [`EnvelopeBuilder`] 生成
+#[derive(Debug)]
+pub struct Batcher {
+    topic: TopicTemplate,
+    topic_prefix: String,
+    site: Option<String>,
+    aggregation: Aggregation,
+    max_payload_bytes: Option<usize>,
+    envelopes: Mutex<EnvelopeBuilder>,
+}
+
+// 合并时一组点位的键：主题、网关地址、从站ID（per_cycle 为 None）、QoS、retain、格式、压缩方式
+type GroupKey = (String, String, Option<u8>, u8, bool, PayloadFormat, Compression);
+
+impl Batcher {
+    /// 根据 MQTT 配置创建，`timezone` 为数据消息中 local_time 使用的全局时区
+    pub fn new(settings: &MqttSettings, timezone: Option<Tz>) -> Result<Self, Box<dyn Error>> {
+        Ok(Batcher {
+            topic: settings.data_topic()?,
+            topic_prefix: settings.topic_prefix.clone(),
+            site: settings.site.clone(),
+            aggregation: settings.effective_aggregation()?,
+            max_payload_bytes: settings.max_payload_bytes,
+            envelopes: Mutex::new(EnvelopeBuilder::new(settings, timezone)),
+        })
+    }
+
+    /// 组成消息，内容为空的消息（例如 influx_line 中的 NaN）不输出
+    ///
+    /// # 返回值
+    /// * 消息列表，合并时按主题、网关、从站和发布参数排序
+    pub fn messages(&self, readings: &[Reading]) -> Vec<DataMessage> {
+        if self.aggregation == Aggregation::Point {
+            let mut messages = Vec::new();
+            for reading in readings {
+                let envelope =
+                    self.envelope(&reading.gateway, Some(reading.slave_id), reading.timestamp);
+                let payload = format_reading(reading, reading.publish.format, &envelope);
+                if payload.is_empty() {
+                    continue;
+                }
+                messages.push(DataMessage {
+                    topic: self.topic(reading),
+                    qos: reading.publish.qos,
+                    retain: reading.publish.retain,
+                    format: reading.publish.format,
+                    compression: reading.publish.compression,
+                    payload,
+                    devices: vec![(reading.gateway.clone(), reading.slave_id)],
+                    readings: vec![reading_key(reading)],
+                });
+            }
+            return messages;
+        }
+
+        let mut groups: BTreeMap<GroupKey, Vec<&Reading>> = BTreeMap::new();
+        for reading in readings {
+            let slave_id = match self.aggregation {
+                Aggregation::Cycle => None,
+                _ => Some(reading.slave_id),
+            };
+            let key = (
+                self.topic(reading),
+                reading.gateway.clone(),
+                slave_id,
+                reading.publish.qos,
+                reading.publish.retain,
+                reading.publish.format,
+                reading.publish.compression,
+            );
+            groups.entry(key).or_default().push(reading);
+        }
+
+        let mut messages = Vec::new();
+        for ((topic, _, _, qos, retain, format, compression), readings) in groups {
+            let mut devices: Vec<(String, u8)> = Vec::new();
+            for reading in &readings {
+                let device = (reading.gateway.clone(), reading.slave_id);
+                if !devices.contains(&device) {
+                    devices.push(device);
+                }
+            }
+            let keys: Vec<ReadingKey> = readings.iter().map(|r| reading_key(r)).collect();
+            for payload in self.encode(format, &readings) {
+                messages.push(DataMessage {
+                    topic: topic.clone(),
+                    qos,
+                    retain,
+                    format,
+                    compression,
+                    payload,
+                    devices: devices.clone(),
+                    readings: keys.clone(),
+                });
+            }
+        }
+        messages
+    }
+
+    /// 展开采集数据对应的数据主题
+    pub fn topic(&self, reading: &Reading) -> String {
+        reading_topic(&self.topic, &self.topic_prefix, self.site.as_deref(), reading)
+    }
+
+    fn envelope(&self, gateway: &str, slave: Option<u8>, timestamp: SystemTime) -> Envelope {
+        let mut envelopes = self.envelopes.lock().unwrap_or_else(|e| e.into_inner());
+        envelopes.next(gateway, slave, timestamp)
+    }
+
+    // 拆分消息时，后面的每条消息使用设备的下一个序号
+    fn next_envelope(&self, gateway: &str, slave: Option<u8>, envelope: &Envelope) -> Envelope {
+        let mut envelopes = self.envelopes.lock().unwrap_or_else(|e| e.into_inner());
+        Envelope {
+            seq: envelopes.next_seq(gateway, slave),
+            ..envelope.clone()
+        }
+    }
+
+    // 把一组点位编码为一条或多条（超出大小限制时）消息
+    fn encode(&self, format: PayloadFormat, readings: &[&Reading]) -> Vec<Vec<u8>> {
+        let cycle = self.aggregation == Aggregation::Cycle;
+        let first = readings[0];
+        let slave = (!cycle).then_some(first.slave_id);
+        let latest = readings
+            .iter()
+            .map(|r| r.timestamp)
+            .max()
+            .unwrap_or(first.timestamp);
+        let envelope = self.envelope(&first.gateway, slave, latest);
+        match format {
+            PayloadFormat::Json if cycle => self
+                .split_cycle(&first.gateway, cycle_payload(readings, envelope))
+                .iter()
+                .map(encode_json)
+                .collect(),
+            PayloadFormat::Json => self
+                .split_device(&first.gateway, device_payload(readings, envelope))
+                .iter()
+                .map(encode_json)
+                .collect(),
+            PayloadFormat::KeyValue if cycle => {
+                let lines = readings
+                    .iter()
+                    .map(|r| key_value(&format!("{}.{}", slave_name(r), r.point), &r.point_value()));
+                self.split_lines(lines)
+            }
+            // 配置校验保证 raw_registers 只用于 per_point，这里按顺序拼接寄存器
+            PayloadFormat::RawRegisters => {
+                vec![
+                    readings
+                        .iter()
+                        .flat_map(|r| format_reading(r, format, &envelope))
+                        .collect(),
+                ]
+            }
+            _ => self.split_lines(readings.iter().map(|r| format_reading(r, format, &envelope))),
+        }
+    }
+
+    // 依次放入点位，放入后超出限制则把该点位留给下一条消息，新的消息使用下一个序号
+    fn split_device(&self, address: &str, payload: DevicePayload) -> Vec<DevicePayload> {
+        let Some(limit) = self.max_payload_bytes else {
+            return vec![payload];
+        };
+        if encode_json(&payload).len() <= limit {
+            return vec![payload];
+        }
+
+        let DevicePayload {
+            gateway,
+            slave,
+            envelope,
+            values,
+            quality,
+        } = payload;
+        let empty = |envelope: Envelope| DevicePayload {
+            gateway: gateway.clone(),
+            slave,
+            envelope,
+            values: BTreeMap::new(),
+            quality: BTreeMap::new(),
+        };
+        let mut parts = Vec::new();
+        let mut current = empty(envelope.clone());
+        for (name, value) in values {
+            let point_quality = quality.get(&name).copied();
+            current.insert(&name, value.clone(), point_quality);
+            if current.values.len() > 1 && encode_json(&current).len() > limit {
+                current.values.remove(&name);
+                current.quality.remove(&name);
+                let next = empty(self.next_envelope(address, Some(slave), &envelope));
+                parts.push(std::mem::replace(&mut current, next));
+                current.insert(&name, value, point_quality);
+            }
+        }
+        parts.push(current);
+        parts
+    }
+
+    fn split_cycle(&self, address: &str, payload: CyclePayload) -> Vec<CyclePayload> {
+        let Some(limit) = self.max_payload_bytes else {
+            return vec![payload];
+        };
+        if encode_json(&payload).len() <= limit {
+            return vec![payload];
+        }
+
+        let CyclePayload {
+            gateway,
+            envelope,
+            slaves,
+            quality,
+        } = payload;
+        let empty = |envelope: Envelope| CyclePayload {
+            gateway: gateway.clone(),
+            envelope,
+            slaves: BTreeMap::new(),
+            quality: BTreeMap::new(),
+        };
+        let mut parts = Vec::new();
+        let mut current = empty(envelope.clone());
+        let mut count = 0;
+        for (slave, values) in slaves {
+            for (name, value) in values {
+                let point_quality = quality.get(&slave).and_then(|q| q.get(&name)).copied();
+                current.insert(&slave, &name, value.clone(), point_quality);
+                count += 1;
+                if count > 1 && encode_json(&current).len() > limit {
+                    current.remove(&slave, &name);
+                    let next = empty(self.next_envelope(address, None, &envelope));
+                    parts.push(std::mem::replace(&mut current, next));
+                    current.insert(&slave, &name, value, point_quality);
+                    count = 1;
+                }
+            }
+        }
+        parts.push(current);
+        parts
+    }
+
+    // 每个点位一行，超出限制时开始新的消息；空行（无法表示的值）跳过
+    fn split_lines(&self, lines: impl Iterator<Item = Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut parts = Vec::new();
+        let mut current: Vec<u8> = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            if !current.is_empty()
+                && self
+                    .max_payload_bytes
+                    .is_some_and(|limit| current.len() + 1 + line.len() > limit)
+            {
+                parts.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(b'\n');
+            }
+            current.extend(line);
+        }
+        if !current.is_empty() {
+            parts.push(current);
+        }
+        parts
+    }
+}
+
+/// 展开采集数据对应的数据主题
+///
+/// 网关和从站优先使用配置的名称，未配置时分别使用 ip:port 和从站ID。
+pub fn reading_topic(
+    template: &TopicTemplate,
+    prefix: &str,
+    site: Option<&str>,
+    reading: &Reading,
+) -> String {
+    let gateway = reading.gateway_name.as_deref().unwrap_or(&reading.gateway);
+    let gateway_host = reading
+        .gateway
+        .rsplit_once(':')
+        .map_or(reading.gateway.as_str(), |(host, _)| host);
+    let slave = slave_name(reading);
+    template.expand(&TopicValues {
+        prefix,
+        site,
+        gateway,
+        gateway_host,
+        slave: &slave,
+        point: Some(&reading.point),
+        group: reading.group.as_deref(),
+    })
+}
+
+// 合并一个从站的点位
+fn device_payload(readings: &[&Reading], envelope: Envelope) -> DevicePayload {
+    let first = readings[0];
+    DevicePayload {
+        gateway: gateway_name(first),
+        slave: first.slave_id,
+        envelope,
+        values: readings.iter().map(|r| (r.point.clone(), r.point_value())).collect(),
+        quality: quality_of(readings.iter().copied()),
+    }
+}
+
+// 合并一个网关所有从站的点位
+fn cycle_payload(readings: &[&Reading], envelope: Envelope) -> CyclePayload {
+    let first = readings[0];
+    let mut payload = CyclePayload {
+        gateway: gateway_name(first),
+        envelope,
+        slaves: BTreeMap::new(),
+        quality: BTreeMap::new(),
+    };
+    for reading in readings {
+        let quality = (reading.quality != Quality::Good).then_some(reading.quality);
+        payload.insert(&slave_name(reading), &reading.point, reading.point_value(), quality);
+    }
+    payload
+}
+
+impl DevicePayload {
+    // 放入一个点位的值和质量
+    fn insert(&mut self, name: &str, value: PointValue, quality: Option<Quality>) {
+        self.values.insert(name.to_string(), value);
+        if let Some(quality) = quality {
+            self.quality.insert(name.to_string(), quality);
+        }
+    }
+}
+
+impl CyclePayload {
+    // 放入一个从站的一个点位的值和质量
+    fn insert(&mut self, slave: &str, name: &str, value: PointValue, quality: Option<Quality>) {
+        self.slaves
+            .entry(slave.to_string())
+            .or_default()
+            .insert(name.to_string(), value);
+        if let Some(quality) = quality {
+            self.quality
+                .entry(slave.to_string())
+                .or_default()
+                .insert(name.to_string(), quality);
+        }
+    }
+
+    // 取出一个从站的一个点位，从站没有其他点位时一并删除
+    fn remove(&mut self, slave: &str, name: &str) {
+        if let Some(values) = self.slaves.get_mut(slave) {
+            values.remove(name);
+            if values.is_empty() {
+                self.slaves.remove(slave);
+            }
+        }
+        if let Some(quality) = self.quality.get_mut(slave) {
+            quality.remove(name);
+            if quality.is_empty() {
+                self.quality.remove(slave);
+            }
+        }
+    }
+}
+
+// 这些类型只含字符串和数字，序列化不会失败
+fn encode_json<T: Serialize>(payload: &T) -> Vec<u8> {
+    serde_json::to_vec(payload).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latest::ReadingCache;
+    use crate::mqtt::change_filter::ChangeFilter;
+    use crate::test_support::reading;
+    use serde_json::{json, Value};
+
+    fn batcher(aggregation: Aggregation, max_payload_bytes: Option<usize>) -> Batcher {
+        let mut settings = MqttSettings::new("localhost", "ems-1");
+        settings.aggregation = Some(aggregation);
+        settings.max_payload_bytes = max_payload_bytes;
+        if aggregation == Aggregation::Cycle {
+            settings.topic_template = "{prefix}/{gateway}".to_string();
+        }
+        Batcher::new(&settings, None).unwrap()
+    }
+
+    fn json_of(message: &DataMessage) -> Value {
+        serde_json::from_slice(&message.payload).unwrap()
+    }
+
+    fn points(count: usize) -> Vec<Reading> {
+        (0..count)
+            .map(|i| reading("10.0.0.1:502", 1, &format!("p{:02}", i), i as f64))
+            .collect()
+    }
+
+    #[test]
+    fn per_device_combines_points_of_each_slave() {
+        let readings = [
+            reading("10.0.0.1:502", 1, "voltage", 230.5),
+            reading("10.0.0.1:502", 1, "current", 12.0),
+            reading("10.0.0.1:502", 2, "power", 7.25),
+        ];
+        let messages = batcher(Aggregation::Device, None).messages(&readings);
+        let topics: Vec<&str> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(topics, ["ems/10.0.0.1:502/1", "ems/10.0.0.1:502/2"]);
+
+        let first = json_of(&messages[0]);
+        assert_eq!(first["gateway"], "10.0.0.1:502");
+        assert_eq!(first["slave"], 1);
+        assert_eq!(first["values"], json!({ "current": 12.0, "voltage": 230.5 }));
+        assert_eq!(first["seq"], 1);
+        assert!(first.get("quality").is_none());
+        assert_eq!(messages[0].devices, [("10.0.0.1:502".to_string(), 1)]);
+        assert_eq!(messages[0].readings.len(), 2);
+        assert_eq!(json_of(&messages[1])["values"], json!({ "power": 7.25 }));
+    }
+
+    #[test]
+    fn per_point_and_per_cycle_messages() {
+        let readings = [
+            reading("10.0.0.1:502", 1, "voltage", 230.5),
+            reading("10.0.0.1:502", 2, "power", 7.25),
+        ];
+        let messages = batcher(Aggregation::Point, None).messages(&readings);
+        let topics: Vec<&str> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(topics, ["ems/10.0.0.1:502/1/voltage", "ems/10.0.0.1:502/2/power"]);
+
+        let messages = batcher(Aggregation::Cycle, None).messages(&readings);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].topic, "ems/10.0.0.1:502");
+        assert_eq!(
+            json_of(&messages[0])["slaves"],
+            json!({ "1": { "voltage": 230.5 }, "2": { "power": 7.25 } })
+        );
+        assert_eq!(messages[0].devices.len(), 2);
+    }
+
+    #[test]
+    fn oversized_batches_are_split_at_the_limit() {
+        let readings = points(20);
+        // 公共字段之外能放下约5个点位
+        let single = batcher(Aggregation::Device, None).messages(&readings[..1]);
+        let limit = single[0].payload.len() + 50;
+
+        let messages = batcher(Aggregation::Device, Some(limit)).messages(&readings);
+        assert!(messages.len() >= 3, "{}", messages.len());
+        let mut names = Vec::new();
+        for (index, message) in messages.iter().enumerate() {
+            assert!(message.payload.len() <= limit, "{} > {}", message.payload.len(), limit);
+            let value = json_of(message);
+            // 每条消息各占一个序号，且都带有整组点位
+            assert_eq!(value["seq"], index as u64 + 1);
+            assert_eq!(message.readings.len(), 20);
+            names.extend(value["values"].as_object().unwrap().keys().cloned());
+        }
+        // 按点位名称顺序拆分，不丢失也不重复
+        let expected: Vec<String> = (0..20).map(|i| format!("p{:02}", i)).collect();
+        assert_eq!(names, expected);
+
+        // 单个点位已超出限制时单独成为一条消息
+        let messages = batcher(Aggregation::Device, Some(1)).messages(&readings[..3]);
+        assert_eq!(messages.len(), 3);
+
+        // 按行的格式在行之间拆分
+        let mut lines = points(20);
+        for reading in &mut lines {
+            reading.publish.format = PayloadFormat::KeyValue;
+        }
+        let messages = batcher(Aggregation::Device, Some(40)).messages(&lines);
+        assert!(messages.len() > 1);
+        let joined: Vec<String> = messages
+            .iter()
+            .inspect(|m| assert!(m.payload.len() <= 40))
+            .flat_map(|m| String::from_utf8(m.payload.clone()).unwrap().lines().map(str::to_string).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(joined.len(), 20);
+    }
+
+    #[test]
+    fn deadband_suppression_runs_before_aggregation() {
+        let cycle = |voltage: f64, current: f64| {
+            let mut readings = [
+                reading("10.0.0.1:502", 1, "voltage", voltage),
+                reading("10.0.0.1:502", 1, "current", current),
+            ];
+            for reading in &mut readings {
+                reading.publish.deadband = Some(1.0);
+            }
+            readings
+        };
+        let batcher = batcher(Aggregation::Device, None);
+        let mut filter = ChangeFilter::new(ReadingCache::new());
+
+        let first = batcher.messages(&filter.filter(&cycle(230.0, 10.0), false));
+        assert_eq!(json_of(&first[0])["values"], json!({ "current": 10.0, "voltage": 230.0 }));
+        // 只有超出死区的点位进入消息
+        let second = batcher.messages(&filter.filter(&cycle(230.5, 12.0), false));
+        assert_eq!(json_of(&second[0])["values"], json!({ "current": 12.0 }));
+        // 都没有变化时不发布
+        assert!(batcher.messages(&filter.filter(&cycle(230.5, 12.0), false)).is_empty());
+        // include_unchanged 时从站有变化就带上所有点位
+        let fourth = batcher.messages(&filter.filter(&cycle(232.0, 12.0), true));
+        assert_eq!(json_of(&fourth[0])["values"], json!({ "current": 12.0, "voltage": 232.0 }));
+        assert!(batcher.messages(&filter.filter(&cycle(232.0, 12.0), true)).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeSet;
+
+use crate::latest::SharedCache;
+use crate::modbus::reading::{PointValue, Reading};
+
+/// 按死区过滤采集数据，只保留相对上次发布的值有变化的点位
+///
+/// 没有配置死区的点位每次都保留。过滤在合并消息之前进行，
+/// 因此合并发布时消息中只包含有变化的点位。
+/// 上次发布的值记录在 [`ReadingCache`](crate::latest::ReadingCache) 中，与采集值放在一起。
+#[derive(Debug)]
+pub struct ChangeFilter {
+    cache: SharedCache,
+}
+
+impl ChangeFilter {
+    /// 创建过滤器，缓存中还没有发布记录的点位第一次都会发布
+    pub fn new(cache: SharedCache) -> Self {
+        ChangeFilter { cache }
+    }
+
+    /// 过滤一批采集数据，并记录保留下来的点位的值
+    ///
+    /// # 参数说明
+    /// * `include_unchanged` - 为 true 时，从站只要有一个点位被保留，该从站本次的所有点位都保留
+    ///
+    /// # 说明
+    /// * 与上次记录的值之差的绝对值大于死区才算变化，第一次采集和数据质量变化（例如变为 stale）总是变化
+    /// * 字符串点位的内容不同即为变化
+    /// * 因 include_unchanged 保留的未变化点位不更新记录的值，缓慢漂移仍能累计超出死区
+    pub fn filter(&mut self, readings: &[Reading], include_unchanged: bool) -> Vec<Reading> {
+        let changed: Vec<bool> = readings.iter().map(|r| self.update(r)).collect();
+        if !include_unchanged {
+            return readings
+                .iter()
+                .zip(&changed)
+                .filter(|(_, changed)| **changed)
+                .map(|(reading, _)| reading.clone())
+                .collect();
+        }
+
+        let devices: BTreeSet<(&str, u8)> = readings
+            .iter()
+            .zip(&changed)
+            .filter(|(_, changed)| **changed)
+            .map(|(reading, _)| (reading.gateway.as_str(), reading.slave_id))
+            .collect();
+        readings
+            .iter()
+            .filter(|r| devices.contains(&(r.gateway.as_str(), r.slave_id)))
+            .cloned()
+            .collect()
+    }
+
+    /// 清除从站已记录的值，下一次采集的所有点位都会发布
+    ///
+    /// 消息发布失败时调用，避免 Broker 上的数据一直停留在旧值。
+    pub fn forget(&mut self, gateway: &str, slave_id: u8) {
+        self.cache.forget_published(gateway, slave_id);
+    }
+
+    // 判断是否有变化，有变化时记录新值
+    fn update(&mut self, reading: &Reading) -> bool {
+        let Some(deadband) = reading.publish.deadband else {
+            return true;
+        };
+        let last = self
+            .cache
+            .last_published(&reading.gateway, reading.slave_id, &reading.point);
+        let changed = match last {
+            None => true,
+            Some(last) if last.quality != reading.quality => true,
+            Some(last) => match (last.point_value(), reading.point_value()) {
+                (PointValue::Number(previous), PointValue::Number(value)) => {
+                    // NaN 与任何值比较都不成立，按是否为 NaN 单独判断
+                    if previous.is_nan() || value.is_nan() {
+                        previous.is_nan() != value.is_nan()
+                    } else {
+                        (value - previous).abs() > deadband
+                    }
+                }
+                (previous, value) => previous != value,
+            },
+        };
+        if changed {
+            self.cache.mark_published(std::slice::from_ref(reading));
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latest::ReadingCache;
+    use crate::modbus::reading::Quality;
+    use crate::test_support::reading;
+
+    fn with_deadband(point: &str, value: f64, deadband: f64) -> Reading {
+        let mut reading = reading("10.0.0.1:502", 1, point, value);
+        reading.publish.deadband = Some(deadband);
+        reading
+    }
+
+    fn names(readings: &[Reading]) -> Vec<&str> {
+        readings.iter().map(|r| r.point.as_str()).collect()
+    }
+
+    #[test]
+    fn compares_with_last_published_value() {
+        let cache = ReadingCache::new();
+        let mut filter = ChangeFilter::new(cache.clone());
+        assert_eq!(filter.filter(&[with_deadband("p", 10.0, 1.0)], false).len(), 1);
+        assert!(filter.filter(&[with_deadband("p", 10.6, 1.0)], false).is_empty());
+        // 相对上次发布的 10.0 已超出死区，缓慢漂移不会被吞掉
+        assert_eq!(filter.filter(&[with_deadband("p", 11.2, 1.0)], false).len(), 1);
+        assert_eq!(cache.last_published("10.0.0.1:502", 1, "p").unwrap().value, 11.2);
+        // 采集值缓存不受死区影响
+        assert!(cache.get("10.0.0.1:502", 1, "p").is_none());
+    }
+
+    #[test]
+    fn quality_change_and_nan_are_changes() {
+        let mut filter = ChangeFilter::new(ReadingCache::new());
+        filter.filter(&[with_deadband("p", 5.0, 10.0)], false);
+        let mut stale = with_deadband("p", 5.0, 10.0);
+        stale.quality = Quality::Stale;
+        assert_eq!(filter.filter(&[stale], false).len(), 1);
+        assert!(filter.filter(&[{
+            let mut r = with_deadband("p", 5.0, 10.0);
+            r.quality = Quality::Stale;
+            r
+        }], false).is_empty());
+        let mut nan = with_deadband("p", f64::NAN, 10.0);
+        nan.quality = Quality::Stale;
+        assert_eq!(filter.filter(std::slice::from_ref(&nan), false).len(), 1);
+        assert!(filter.filter(&[nan], false).is_empty());
+    }
+
+    #[test]
+    fn points_without_deadband_always_pass() {
+        let mut filter = ChangeFilter::new(ReadingCache::new());
+        let plain = reading("10.0.0.1:502", 1, "p", 1.0);
+        for _ in 0..2 {
+            assert_eq!(filter.filter(std::slice::from_ref(&plain), false).len(), 1);
+        }
+    }
+
+    #[test]
+    fn include_unchanged_keeps_whole_device() {
+        let mut filter = ChangeFilter::new(ReadingCache::new());
+        let first = [with_deadband("a", 1.0, 1.0), with_deadband("b", 1.0, 1.0)];
+        filter.filter(&first, true);
+        let mut other = with_deadband("c", 1.0, 1.0);
+        other.slave_id = 2;
+        filter.filter(std::slice::from_ref(&other), true);
+        let next = [with_deadband("a", 5.0, 1.0), with_deadband("b", 1.1, 1.0), other];
+        assert_eq!(names(&filter.filter(&next, true)), ["a", "b"]);
+        // 因 include_unchanged 保留的 b 不更新发布记录
+        assert_eq!(
+            filter.cache.last_published("10.0.0.1:502", 1, "b").unwrap().value,
+            1.0
+        );
+    }
+
+    #[test]
+    fn forget_republishes_device() {
+        let cache = ReadingCache::new();
+        let mut filter = ChangeFilter::new(cache.clone());
+        filter.filter(&[with_deadband("p", 1.0, 1.0)], false);
+        filter.forget("10.0.0.1:502", 1);
+        assert!(cache.last_published("10.0.0.1:502", 1, "p").is_none());
+        assert_eq!(filter.filter(&[with_deadband("p", 1.0, 1.0)], false).len(), 1);
+    }
+}
@@ -1,22 +1,928 @@
-use rumqttc::{MqttOptions, Client};
+use rumqttc::{
+    valid_filter, valid_topic, AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing,
+    Packet, QoS, TlsConfiguration, Transport,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{info, warn};
 
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::mqtt::error::MqttError;
+use crate::mqtt::tls::client_config;
+use crate::mqtt::topic::topic_matches;
+
+/// 客户端请求队列长度
+const REQUEST_CAPACITY: usize = 100;
+/// 未指定时连接出错后第一次重试前的等待时间
+pub const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// 未指定时连续失败后重试等待时间的上限
+pub const DEFAULT_MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// 关闭时等待队列中的消息和 DISCONNECT 发出的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// 发出 DISCONNECT 后等待 Broker 关闭连接的最长时间
+const CLOSE_WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+/// 每个订阅收到的消息在被处理前最多缓存的条数，超出后丢弃
+const INCOMING_CAPACITY: usize = 64;
+
+/// 程序正在运行时状态主题的内容
+pub const STATUS_ONLINE: &str = "online";
+/// 程序退出或异常断线后状态主题的内容
+pub const STATUS_OFFLINE: &str = "offline";
+
+/// 收到的 MQTT 消息
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttMessage {
+    /// 主题
+    pub topic: String,
+    /// 内容
+    pub payload: Vec<u8>,
+    /// QoS 等级
+    pub qos: QoS,
+    /// 是否为保留消息
+    pub retain: bool,
+}
+
+/// 连接会话钩子，用于需要随连接变化的遗嘱消息和连接后立即发布的消息（例如 Sparkplug B 的 NDEATH 和 NBIRTH）
+///
+/// 配置了钩子时，遗嘱消息使用钩子提供的内容，不再使用状态主题的 offline。
+pub trait SessionHook: Send + Sync + 'static {
+    /// 下一次连接使用的遗嘱消息
+    fn last_will(&self) -> Option<LastWill>;
+    /// 收到 CONNACK 后调用，返回的消息在其他消息之前按顺序放入发送队列
+    fn on_connected(&self) -> Vec<MqttMessage>;
+    /// 客户端正常关闭、发送 DISCONNECT 之前调用（正常断开时 Broker 不会发布遗嘱消息）
+    fn on_shutdown(&self) -> Vec<MqttMessage>;
+}
+
+/// MQTT 连接状态，可以通过 [`MqttClient::watch_state`] 订阅变化
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionState {
+    /// 是否已收到 Broker 的 CONNACK
+    pub connected: bool,
+    /// 成功连接的次数，大于1说明发生过重连，需要补发数据的模块可以据此判断
+    pub connections: u64,
+    /// 最近一次连接错误
+    pub last_error: Option<String>,
+    /// 后台事件循环是否已结束，结束后所有请求都会失败
+    pub stopped: bool,
+}
+
+/// 订阅和重连时需要恢复的会话内容
+#[derive(Debug, Default)]
+struct Session {
+    next_id: u64,
+    subscriptions: Vec<Subscription>,
+    // 状态主题到最近一次发布的内容
+    states: BTreeMap<String, Vec<u8>>,
+}
+
+// 一次 subscribe 调用，接收端被 drop 后移除
+#[derive(Debug)]
+struct Subscription {
+    id: u64,
+    filter: String,
+    qos: QoS,
+    sender: mpsc::Sender<MqttMessage>,
+}
+
+impl Session {
+    // 需要向 Broker 订阅的过滤器，同一过滤器使用其中最高的 QoS
+    fn filters(&self) -> BTreeMap<String, QoS> {
+        let mut filters: BTreeMap<String, QoS> = BTreeMap::new();
+        for subscription in &self.subscriptions {
+            let qos = filters.entry(subscription.filter.clone()).or_insert(subscription.qos);
+            if (subscription.qos as u8) > (*qos as u8) {
+                *qos = subscription.qos;
+            }
+        }
+        filters
+    }
+}
+
+/// 重连的等待时间：从初始值开始每次失败翻倍，不超过上限
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// 第一次重试前的等待时间，之后每次失败翻倍
+    pub initial: Duration,
+    /// 重试等待时间的上限
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: DEFAULT_RECONNECT_DELAY,
+            max: DEFAULT_MAX_RECONNECT_DELAY,
+        }
+    }
+}
+
+/// 异步 MQTT 客户端
+///
+/// 创建时启动一个后台任务持续驱动 rumqttc 的事件循环，发布和订阅请求由该任务实际发送；
+/// 连接断开后按退避时间自动重连。调用 [`MqttClient::close`] 或客户端被 drop 时发送 DISCONNECT
+/// 并结束后台任务；只有 close 会等待队列中的消息发出。
+///
+/// 根据配置创建的客户端带有状态主题：连接时设置遗嘱消息 offline，每次连接成功后发布保留消息
+/// online，正常退出时先发布 offline 再断开。
+///
+/// 重连成功后（Broker 未保留会话时）重新订阅所有仍在使用的订阅，并重新发布通过
+/// [`MqttClient::publish_state`] 发布过的状态消息，Broker 重启后订阅和保留消息不会丢失。
+///
+/// 收到的消息按主题过滤器分发给每个匹配的订阅，过滤器重叠时每个订阅各收到一份。
+///
+/// # 说明
+/// * 必须在 tokio 运行时中创建
 pub struct MqttClient {
-    client: Client,
+    client: AsyncClient,
+    state: watch::Receiver<ConnectionState>,
+    session: Arc<Mutex<Session>>,
+    dropped: Arc<AtomicU64>,
+    // 关闭信号，内容为 DISCONNECT 是否已放入发送队列
+    shutdown: Mutex<Option<oneshot::Sender<bool>>>,
+    status_topic: Option<String>,
+    hook: Option<Arc<dyn SessionHook>>,
+    max_packet_size: usize,
 }
 
+/// PUBLISH 报文中除主题和内容外最多占用的字节数：固定头部5字节、主题长度2字节、报文ID 2字节
+const PUBLISH_OVERHEAD: usize = 9;
+
 impl MqttClient {
-    pub fn new(client_id: &str, broker: &str) -> MqttClient {
-        let mut settings = MqttOptions::new(client_id, broker);
-        settings.set_keep_alive(5);
+    /// 使用明文 TCP 连接到指定 Broker，不设置状态主题
+    ///
+    /// # 参数说明
+    /// * `keep_alive` - 心跳间隔，rumqttc 要求不小于1秒
+    pub fn new(
+        client_id: &str,
+        broker_host: &str,
+        broker_port: u16,
+        keep_alive: Duration,
+    ) -> MqttClient {
+        let mut options = MqttOptions::new(client_id, broker_host, broker_port);
+        options.set_keep_alive(keep_alive);
+        MqttClient::with_options(options, None, Backoff::default(), None)
+    }
+
+    /// 根据配置文件中的 MQTT 配置创建客户端
+    ///
+    /// # 参数说明
+    /// * `hook` - 连接会话钩子，Sparkplug B 模式下使用
+    ///
+    /// # 返回值
+    /// * `Err` - TLS 证书或私钥文件无法读取、格式错误或不匹配
+    pub fn from_settings(
+        settings: &MqttSettings,
+        hook: Option<Arc<dyn SessionHook>>,
+    ) -> Result<MqttClient, Box<dyn Error>> {
+        let backoff = Backoff {
+            initial: Duration::from_millis(settings.reconnect_delay_ms),
+            max: Duration::from_millis(settings.max_reconnect_delay_ms),
+        };
+        Ok(MqttClient::with_options(
+            mqtt_options(settings)?,
+            Some(settings.status_topic()),
+            backoff,
+            hook,
+        ))
+    }
+
+    /// 使用 rumqttc 的连接参数创建客户端
+    ///
+    /// # 参数说明
+    /// * `status_topic` - 程序状态主题，None 时不发布 online/offline
+    /// * `backoff` - 连接出错后重试的等待时间
+    /// * `hook` - 连接会话钩子，提供遗嘱消息和连接后发布的消息
+    pub fn with_options(
+        mut options: MqttOptions,
+        status_topic: Option<String>,
+        backoff: Backoff,
+        hook: Option<Arc<dyn SessionHook>>,
+    ) -> MqttClient {
+        if let Some(will) = hook.as_ref().and_then(|hook| hook.last_will()) {
+            options.set_last_will(will);
+        }
+        let broker = format!("{}:{}", options.broker_address().0, options.broker_address().1);
+        // set_max_packet_size 同时设置收发两个方向，这里取得的接收上限即发送上限
+        let max_packet_size = options.max_packet_size();
+        let (client, event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let (state_tx, state) = watch::channel(ConnectionState::default());
+        let session = Arc::new(Mutex::new(Session::default()));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let driver = Driver {
+            broker,
+            client: client.clone(),
+            status_topic: status_topic.clone(),
+            backoff,
+            hook: hook.clone(),
+            state: state_tx,
+            session: Arc::clone(&session),
+            dropped: Arc::clone(&dropped),
+        };
+        tokio::spawn(drive_event_loop(driver, event_loop, shutdown_rx));
+        MqttClient {
+            client,
+            state,
+            session,
+            dropped,
+            shutdown: Mutex::new(Some(shutdown)),
+            status_topic,
+            hook,
+            max_packet_size,
+        }
+    }
+
+    /// 检查消息能否发出：主题合法，且报文不超出最大长度
+    ///
+    /// 超出长度的报文会让事件循环报错并断开连接，因此发布前先检查。
+    pub fn check_publish(&self, topic: &str, payload_len: usize) -> Result<(), MqttError> {
+        if !valid_topic(topic) {
+            return Err(MqttError::InvalidTopic(topic.to_string()));
+        }
+        let size = topic.len() + payload_len + PUBLISH_OVERHEAD;
+        if size > self.max_packet_size {
+            return Err(MqttError::PayloadTooLarge {
+                size,
+                max: self.max_packet_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// 发布消息
+    ///
+    /// # 说明
+    /// * 消息进入发送队列即返回，实际发送由后台事件循环完成
+    /// * 队列已满时等待（断线期间队列不会被取走），后台任务已结束时返回 `Disconnected`
+    pub async fn publish(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: &[u8],
+    ) -> Result<(), MqttError> {
+        self.check_publish(topic, payload.len())?;
+        self.client
+            .publish(topic, qos, retain, payload.to_vec())
+            .await
+            .map_err(|_| MqttError::Disconnected)
+    }
+
+    /// 不等待地发布消息
+    ///
+    /// # 返回值
+    /// * `Err(Disconnected)` - 未连接到 Broker 或后台任务已结束，且消息无法放入队列
+    /// * `Err(QueueFull)` - 已连接但发送队列已满
+    /// * `Err(InvalidTopic)` / `Err(PayloadTooLarge)` - 主题不合法或消息过大，重试也无法发出
+    pub fn try_publish(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+    ) -> Result<(), MqttError> {
+        self.check_publish(topic, payload.len())?;
+        self.client
+            .try_publish(topic, qos, retain, payload)
+            .map_err(|_| {
+                let state = self.state.borrow();
+                if state.stopped || !state.connected {
+                    MqttError::Disconnected
+                } else {
+                    MqttError::QueueFull
+                }
+            })
+    }
+
+    /// 以保留消息（QoS 1）发布状态，例如设备可用性
+    ///
+    /// 每个主题最近一次的内容会被记录，重连后重新发布。未连接时消息留在队列中等待连接。
+    pub fn publish_state(&self, topic: &str, payload: &[u8]) -> Result<(), MqttError> {
+        if !valid_topic(topic) {
+            return Err(MqttError::InvalidTopic(topic.to_string()));
+        }
+        lock(&self.session)
+            .states
+            .insert(topic.to_string(), payload.to_vec());
+        self.try_publish(topic, QoS::AtLeastOnce, true, payload.to_vec())
+    }
+
+    /// 发布空的保留消息，清除 Broker 上该主题的保留消息，重连后不再重新发布该主题
+    pub fn clear_state(&self, topic: &str) -> Result<(), MqttError> {
+        if !valid_topic(topic) {
+            return Err(MqttError::InvalidTopic(topic.to_string()));
+        }
+        lock(&self.session).states.remove(topic);
+        self.try_publish(topic, QoS::AtLeastOnce, true, Vec::new())
+    }
+
+    /// 订阅主题过滤器，返回接收匹配消息的接收端
+    ///
+    /// # 说明
+    /// * 过滤器可以使用 `+` 和 `#`，与其他订阅重叠时各自收到匹配的消息；
+    ///   部分 Broker 对重叠的订阅每个投递一次，此时会重复收到
+    /// * 接收端被 drop 后，没有其他订阅使用同一过滤器时向 Broker 取消订阅
+    /// * 接收端缓存已满时新消息被丢弃并计入 [`MqttClient::dropped_messages`]，不会阻塞事件循环
+    /// * 重连后自动重新订阅，客户端关闭后接收端返回 None
+    pub async fn subscribe(
+        &self,
+        filter: &str,
+        qos: QoS,
+    ) -> Result<mpsc::Receiver<MqttMessage>, MqttError> {
+        if !valid_filter(filter) {
+            return Err(MqttError::InvalidTopic(filter.to_string()));
+        }
+        let (sender, receiver) = mpsc::channel(INCOMING_CAPACITY);
+        let (id, changed) = {
+            let mut session = lock(&self.session);
+            let before = session.filters().get(filter).copied();
+            session.next_id += 1;
+            let id = session.next_id;
+            session.subscriptions.push(Subscription {
+                id,
+                filter: filter.to_string(),
+                qos,
+                sender: sender.clone(),
+            });
+            // 重复订阅同一过滤器会替换 Broker 上已有的订阅，只在 QoS 提高时才需要重新订阅
+            (id, before.is_none_or(|before| (qos as u8) > (before as u8)))
+        };
+        tokio::spawn(unsubscribe_when_closed(
+            self.client.clone(),
+            Arc::clone(&self.session),
+            self.state.clone(),
+            id,
+            sender,
+        ));
+        if changed {
+            self.client
+                .subscribe(filter, qos)
+                .await
+                .map_err(|_| MqttError::Disconnected)?;
+        }
+        Ok(receiver)
+    }
+
+    /// 因接收端处理不及时被丢弃的消息数
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 当前连接状态
+    pub fn state(&self) -> ConnectionState {
+        self.state.borrow().clone()
+    }
+
+    /// 订阅连接状态的变化
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// 是否已连接到 Broker
+    pub fn is_connected(&self) -> bool {
+        self.state.borrow().connected
+    }
+
+    /// 关闭客户端：发布 offline 后发送 DISCONNECT，等待队列中的消息发出后返回
+    ///
+    /// # 说明
+    /// * 已连接时最多等待 SHUTDOWN_TIMEOUT，未连接时队列中的消息直接丢弃
+    /// * 关闭后发布和订阅都会失败；重复调用直接返回
+    pub async fn close(&self) {
+        self.shutdown();
+        let mut state = self.state.clone();
+        let _ = state.wait_for(|state| state.stopped).await;
+    }
+
+    // 排队发布 offline 和关闭消息，然后通知后台任务发送 DISCONNECT；只执行一次
+    fn shutdown(&self) {
+        let Some(shutdown) = lock(&self.shutdown).take() else {
+            return;
+        };
+        // 正常断开不会触发遗嘱消息，需要自己发布 offline
+        if let Some(topic) = &self.status_topic {
+            let _ = self
+                .client
+                .try_publish(topic, QoS::AtLeastOnce, true, STATUS_OFFLINE);
+        }
+        if let Some(hook) = &self.hook {
+            for message in hook.on_shutdown() {
+                let _ = self
+                    .client
+                    .try_publish(message.topic, message.qos, message.retain, message.payload);
+            }
+        }
+        let queued = self.client.try_disconnect().is_ok();
+        let _ = shutdown.send(queued);
+    }
+}
+
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// 后台事件循环任务使用的内容
+struct Driver {
+    broker: String,
+    client: AsyncClient,
+    status_topic: Option<String>,
+    backoff: Backoff,
+    hook: Option<Arc<dyn SessionHook>>,
+    state: watch::Sender<ConnectionState>,
+    session: Arc<Mutex<Session>>,
+    dropped: Arc<AtomicU64>,
+}
+
+// 持续驱动事件循环并记录连接状态，收到关闭信号后尽量把 DISCONNECT 发出去再退出
+async fn drive_event_loop(
+    driver: Driver,
+    mut event_loop: EventLoop,
+    mut shutdown: oneshot::Receiver<bool>,
+) {
+    let Driver {
+        broker,
+        client,
+        status_topic,
+        backoff,
+        hook,
+        state,
+        session,
+        dropped,
+    } = driver;
+    let mut delay = backoff.initial;
+    let disconnect_queued = loop {
+        let event = tokio::select! {
+            event = event_loop.poll() => event,
+            queued = &mut shutdown => break queued.unwrap_or(true),
+        };
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                delay = backoff.initial;
+                // 在标记为已连接之前排队，其他模块发布的消息不会排在这些消息之前
+                if let Some(hook) = &hook {
+                    for message in hook.on_connected() {
+                        if let Err(e) = client.try_publish(
+                            &message.topic,
+                            message.qos,
+                            message.retain,
+                            message.payload,
+                        ) {
+                            warn!("发布 {} 失败: {}", message.topic, e);
+                        }
+                    }
+                    // 遗嘱消息在下一次连接时才生效
+                    if let Some(will) = hook.last_will() {
+                        event_loop.mqtt_options.set_last_will(will);
+                    }
+                }
+                let mut connections = 0;
+                state.send_modify(|state| {
+                    state.connected = true;
+                    state.connections += 1;
+                    state.last_error = None;
+                    connections = state.connections;
+                });
+                if connections > 1 {
+                    info!("已重新连接到 MQTT Broker {}", broker);
+                } else {
+                    info!("已连接到 MQTT Broker {}", broker);
+                }
+                if let Some(topic) = &status_topic
+                    && let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, STATUS_ONLINE)
+                {
+                    warn!("发布程序状态 {} 失败: {}", topic, e);
+                }
+                // 第一次连接前的订阅和状态消息还在队列中，不需要补发
+                if connections > 1 {
+                    tokio::spawn(resync(
+                        client.clone(),
+                        Arc::clone(&session),
+                        !ack.session_present,
+                    ));
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let message = MqttMessage {
+                    topic: publish.topic,
+                    payload: publish.payload.to_vec(),
+                    qos: publish.qos,
+                    retain: publish.retain,
+                };
+                dispatch(&session, &dropped, message);
+            }
+            Ok(Event::Incoming(Packet::Disconnect)) => {
+                info!("MQTT Broker {} 断开了连接", broker);
+                state.send_modify(|state| state.connected = false);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let message = MqttError::from(e).to_string();
+                let previous = state.borrow().clone();
+                // 重连失败时错误相同，只在状态变化时输出
+                if previous.connected || previous.last_error.as_ref() != Some(&message) {
+                    warn!(
+                        "{}（Broker {}），{} 毫秒后重试",
+                        message,
+                        broker,
+                        delay.as_millis()
+                    );
+                }
+                state.send_modify(|state| {
+                    state.connected = false;
+                    state.last_error = Some(message);
+                });
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    queued = &mut shutdown => break queued.unwrap_or(true),
+                }
+                delay = (delay * 2).min(backoff.max);
+            }
+        }
+    };
+
+    if state.borrow().connected {
+        // 出错后再调用 poll 会重新连接，因此连接断开时直接结束
+        let flushed = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            // 队列已满时 DISCONNECT 没能放入队列，等事件循环取走消息后再放入
+            let disconnect = async {
+                if !disconnect_queued {
+                    let _ = client.disconnect().await;
+                }
+                std::future::pending::<()>().await
+            };
+            tokio::pin!(disconnect);
+            loop {
+                tokio::select! {
+                    event = event_loop.poll() => match event {
+                        Ok(Event::Outgoing(Outgoing::Disconnect)) => return true,
+                        Ok(_) => {}
+                        Err(_) => return false,
+                    },
+                    _ = &mut disconnect => {}
+                }
+            }
+        })
+        .await;
+        match flushed {
+            Err(_) => warn!("MQTT 连接 {} 关闭超时", broker),
+            // 继续读取 Broker 的确认直到对方关闭连接。接收缓冲区中还有未读数据时关闭套接字会发送 RST，
+            // Broker 可能因此丢弃还没处理的消息和 DISCONNECT
+            Ok(true) => {
+                let _ = tokio::time::timeout(CLOSE_WAIT_TIMEOUT, async {
+                    while event_loop.poll().await.is_ok() {}
+                })
+                .await;
+            }
+            Ok(false) => {}
+        }
+    }
+    lock(&session).subscriptions.clear();
+    state.send_modify(|state| {
+        state.connected = false;
+        state.stopped = true;
+    });
+}
+
+// 把收到的消息交给每个匹配的订阅，接收端已满时丢弃
+fn dispatch(session: &Mutex<Session>, dropped: &AtomicU64, message: MqttMessage) {
+    let session = lock(session);
+    for subscription in &session.subscriptions {
+        if !topic_matches(&subscription.filter, &message.topic) {
+            continue;
+        }
+        if let Err(mpsc::error::TrySendError::Full(_)) = subscription.sender.try_send(message.clone()) {
+            dropped.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "订阅 {} 的消息处理不及时，丢弃主题 {} 的消息",
+                subscription.filter, message.topic
+            );
+        }
+    }
+}
 
-        let (client, _) = Client::new(settings, 10);
-        MqttClient { client }
+// 等待订阅的接收端被 drop，移除订阅；没有其他订阅使用同一过滤器时向 Broker 取消订阅。
+// 客户端关闭时直接退出，释放发送端使接收端结束
+async fn unsubscribe_when_closed(
+    client: AsyncClient,
+    session: Arc<Mutex<Session>>,
+    mut state: watch::Receiver<ConnectionState>,
+    id: u64,
+    sender: mpsc::Sender<MqttMessage>,
+) {
+    tokio::select! {
+        _ = sender.closed() => {}
+        _ = state.wait_for(|state| state.stopped) => return,
     }
+    let filter = {
+        let mut session = lock(&session);
+        let Some(index) = session.subscriptions.iter().position(|s| s.id == id) else {
+            return;
+        };
+        let subscription = session.subscriptions.remove(index);
+        if session.subscriptions.iter().any(|s| s.filter == subscription.filter) {
+            return;
+        }
+        subscription.filter
+    };
+    // 客户端已关闭时连接也已断开，不需要取消
+    let _ = client.unsubscribe(filter).await;
+}
 
-    pub fn publish(&self, topic: &str, payload: &[u8]) -> Result<(), rumqttc::Error> {
-        self.client.publish(topic, payload, 1, false)
+// 重连后恢复订阅并重新发布状态消息；在单独的任务中排队，不阻塞事件循环
+async fn resync(client: AsyncClient, session: Arc<Mutex<Session>>, resubscribe: bool) {
+    let (subscriptions, states) = {
+        let session = lock(&session);
+        let subscriptions = if resubscribe {
+            session.filters()
+        } else {
+            BTreeMap::new()
+        };
+        (subscriptions, session.states.clone())
+    };
+    for (filter, qos) in subscriptions {
+        if let Err(e) = client.subscribe(&filter, qos).await {
+            warn!("重新订阅 {} 失败: {}", filter, e);
+            return;
+        }
+        info!("已重新订阅 {}", filter);
+    }
+    for (topic, payload) in states {
+        if let Err(e) = client.publish(&topic, QoS::AtLeastOnce, true, payload).await {
+            warn!("重新发布状态 {} 失败: {}", topic, e);
+            return;
+        }
     }
+}
 
-    // ...处理 MQTT 客户端功能的附加方法...
+// 锁不会在持有期间 panic，这里忽略锁中毒
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 将 MQTT 配置转换为 rumqttc 的连接参数，配置了 TLS 时读取证书文件
+pub fn mqtt_options(settings: &MqttSettings) -> Result<MqttOptions, Box<dyn Error>> {
+    let mut options = MqttOptions::new(
+        settings.client_id.clone(),
+        settings.broker_host.clone(),
+        settings.broker_port,
+    );
+    options.set_keep_alive(Duration::from_secs(settings.keep_alive_secs));
+    options.set_clean_session(settings.clean_session);
+    options.set_last_will(LastWill::new(
+        settings.status_topic(),
+        STATUS_OFFLINE,
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &settings.username {
+        options.set_credentials(
+            username.clone(),
+            settings.effective_password().unwrap_or_default(),
+        );
+    }
+    if let Some(tls) = &settings.tls {
+        let config = client_config(tls)?;
+        options.set_transport(Transport::tls_with_config(TlsConfiguration::Rustls(
+            Arc::new(config),
+        )));
+    }
+    Ok(options)
 }
 
+/// 将配置中的 QoS 等级（0/1/2）转换为 rumqttc 的 QoS，超出范围按 2 处理
+pub fn qos_from_level(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockBroker, Received};
+
+    fn client(broker: &MockBroker) -> MqttClient {
+        MqttClient::new("client-test", "127.0.0.1", broker.port, Duration::from_secs(5))
+    }
+
+    #[tokio::test]
+    async fn publish_is_flushed_by_event_loop() {
+        let broker = MockBroker::start().await;
+        let client = client(&broker);
+        // 连接前发布的消息留在队列中，连接后由事件循环发出
+        client.publish("test/a", QoS::AtLeastOnce, false, b"1").await.unwrap();
+        client.publish("test/a", QoS::ExactlyOnce, true, b"2").await.unwrap();
+        client.try_publish("test/a", QoS::AtMostOnce, false, b"3".to_vec()).unwrap();
+
+        assert_eq!(
+            broker.wait_for_topic("test/a", 3).await,
+            [b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]
+        );
+        let publishes = broker.publishes();
+        assert_eq!(
+            publishes.iter().map(|p| (p.qos, p.retain)).collect::<Vec<_>>(),
+            [(QoS::AtLeastOnce, false), (QoS::ExactlyOnce, true), (QoS::AtMostOnce, false)]
+        );
+        let state = client.state();
+        assert!(state.connected);
+        assert_eq!(state.connections, 1);
+        assert_eq!(broker.connects()[0].client_id, "client-test");
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn close_flushes_queue_then_disconnects() {
+        let broker = MockBroker::start().await;
+        let client = client(&broker);
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        for i in 0..20 {
+            client.publish("test/queue", QoS::AtLeastOnce, false, i.to_string().as_bytes()).await.unwrap();
+        }
+        client.close().await;
+
+        let received = broker.wait_for("DISCONNECT", |r| r.iter().any(|p| matches!(p, Received::Disconnect))).await;
+        let publishes = received.iter().filter(|p| matches!(p, Received::Publish(_))).count();
+        assert_eq!(publishes, 20);
+        assert!(matches!(received.last(), Some(Received::Disconnect)));
+        assert!(client.state().stopped);
+        assert!(matches!(
+            client.publish("test/queue", QoS::AtLeastOnce, false, b"late").await,
+            Err(MqttError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn status_topic_is_online_then_offline() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "status-test");
+        settings.broker_port = broker.port;
+        let client = MqttClient::from_settings(&settings, None).unwrap();
+        let topic = "ems/status-test/status";
+        assert_eq!(broker.wait_for_topic(topic, 1).await, [b"online".to_vec()]);
+        client.close().await;
+        assert_eq!(broker.wait_for_topic(topic, 2).await, [b"online".to_vec(), b"offline".to_vec()]);
+        assert!(broker.publishes().iter().all(|p| p.retain));
+    }
+
+    #[tokio::test]
+    async fn reconnect_resubscribes_and_republishes_state() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "reconnect-test");
+        settings.broker_port = broker.port;
+        settings.reconnect_delay_ms = 100;
+        let client = MqttClient::from_settings(&settings, None).unwrap();
+        let mut commands = client.subscribe("ems/reconnect-test/cmd/#", QoS::AtLeastOnce).await.unwrap();
+        client.publish_state("ems/PCS-A/1/availability", b"online").unwrap();
+        let mut state = client.watch_state();
+        state.wait_for(|state| state.connected).await.unwrap();
+        broker.wait_for_topic("ems/PCS-A/1/availability", 1).await;
+
+        broker.drop_connections();
+        // 状态变化可以通过 watch 观察到
+        state.wait_for(|state| !state.connected).await.unwrap();
+        state.wait_for(|state| state.connected && state.connections == 2).await.unwrap();
+
+        assert_eq!(
+            broker.wait_for_topic("ems/PCS-A/1/availability", 2).await,
+            [b"online".to_vec(), b"online".to_vec()]
+        );
+        assert_eq!(
+            broker.wait_for_topic("ems/reconnect-test/status", 2).await,
+            [b"online".to_vec(), b"online".to_vec()]
+        );
+        let received = broker
+            .wait_for("重新订阅", |received| {
+                received
+                    .iter()
+                    .filter(|p| matches!(p, Received::Subscribe(filter) if filter == "ems/reconnect-test/cmd/#"))
+                    .count()
+                    == 2
+            })
+            .await;
+        // 重新订阅在第二次 CONNECT 之后
+        let second_connect = received
+            .iter()
+            .rposition(|p| matches!(p, Received::Connect(_)))
+            .unwrap();
+        assert!(
+            received[second_connect..]
+                .iter()
+                .any(|p| matches!(p, Received::Subscribe(_)))
+        );
+
+        // 重新订阅后仍能收到消息
+        broker.inject("ems/reconnect-test/cmd/write", b"{}");
+        let message = commands.recv().await.unwrap();
+        assert_eq!(message.topic, "ems/reconnect-test/cmd/write");
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn overlapping_subscriptions_and_unsubscribe_on_drop() {
+        let broker = MockBroker::start().await;
+        let client = client(&broker);
+        let mut all = client.subscribe("ems/#", QoS::AtLeastOnce).await.unwrap();
+        let mut writes = client.subscribe("ems/+/write", QoS::AtMostOnce).await.unwrap();
+        let second = client.subscribe("ems/+/write", QoS::AtMostOnce).await.unwrap();
+        broker
+            .wait_for("订阅", |received| received.iter().filter(|p| matches!(p, Received::Subscribe(_))).count() >= 2)
+            .await;
+
+        broker.inject("ems/PCS-A/read", b"1");
+        broker.inject("ems/PCS-A/write", b"2");
+        // 重叠的订阅各自收到匹配的消息。测试 Broker 对每个匹配的订阅各投递一次，ems/# 会收到两次 write
+        assert_eq!(all.recv().await.unwrap().topic, "ems/PCS-A/read");
+        assert_eq!(all.recv().await.unwrap().topic, "ems/PCS-A/write");
+        let message = writes.recv().await.unwrap();
+        assert_eq!(
+            message,
+            MqttMessage {
+                topic: "ems/PCS-A/write".to_string(),
+                payload: b"2".to_vec(),
+                qos: QoS::AtMostOnce,
+                retain: false,
+            }
+        );
+
+        // 还有其他订阅使用同一过滤器时不取消订阅
+        drop(writes);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!broker.received().iter().any(|p| matches!(p, Received::Unsubscribe(_))));
+        drop(second);
+        broker
+            .wait_for("取消订阅", |received| {
+                received.iter().any(|p| matches!(p, Received::Unsubscribe(filter) if filter == "ems/+/write"))
+            })
+            .await;
+        broker.inject("ems/PCS-A/write", b"3");
+        while all.recv().await.unwrap().payload != b"3" {}
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn full_receiver_drops_messages_without_blocking() {
+        let broker = MockBroker::start().await;
+        let client = client(&broker);
+        let slow = client.subscribe("ems/slow", QoS::AtMostOnce).await.unwrap();
+        let mut fast = client.subscribe("ems/fast", QoS::AtMostOnce).await.unwrap();
+        broker
+            .wait_for("订阅", |received| received.iter().filter(|p| matches!(p, Received::Subscribe(_))).count() >= 2)
+            .await;
+
+        let extra = 10;
+        for i in 0..INCOMING_CAPACITY + extra {
+            broker.inject("ems/slow", i.to_string().as_bytes());
+        }
+        broker.inject("ems/fast", b"done");
+        // 事件循环没有被阻塞，其他订阅仍能收到消息
+        assert_eq!(fast.recv().await.unwrap().payload, b"done");
+        assert_eq!(client.dropped_messages(), extra as u64);
+        drop(slow);
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn last_will_is_retained_offline_status() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "will-test");
+        settings.broker_port = broker.port;
+        let options = mqtt_options(&settings).unwrap();
+        let will = options.last_will().unwrap();
+        assert_eq!(will.topic, "ems/will-test/status");
+        assert_eq!(will.message, STATUS_OFFLINE);
+        assert_eq!(will.qos, QoS::AtLeastOnce);
+        assert!(will.retain);
+
+        // 遗嘱消息随 CONNECT 发给 Broker
+        let client = MqttClient::from_settings(&settings, None).unwrap();
+        broker.wait_for_topic("ems/will-test/status", 1).await;
+        let will = broker.connects()[0].last_will.clone().unwrap();
+        assert_eq!(will.topic, "ems/will-test/status");
+        assert_eq!(will.message, STATUS_OFFLINE);
+        assert!(will.retain);
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn invalid_or_oversized_publishes_are_rejected() {
+        let broker = MockBroker::start().await;
+        let client = client(&broker);
+        assert!(matches!(
+            client.publish("test/+", QoS::AtLeastOnce, false, b"x").await,
+            Err(MqttError::InvalidTopic(_))
+        ));
+        let oversized = vec![0u8; client.max_packet_size];
+        let error = client.try_publish("test/big", QoS::AtLeastOnce, false, oversized).unwrap_err();
+        assert!(error.is_rejected(), "{}", error);
+        client.close().await;
+    }
+}
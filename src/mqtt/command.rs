@@ -0,0 +1,415 @@
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::device_configuration::point::Point;
+use crate::modbus::reading::PointValue;
+use crate::modbus::scheduler::{GatewayRequest, WriteRequest};
+use crate::mqtt::client::MqttClient;
+use crate::mqtt::dead_letter::{DeadLetterCategory, SharedDeadLetters};
+use crate::mqtt::topic::{TopicTemplate, TopicValues};
+use crate::reload::SharedWriters;
+
+// 命令无法执行的原因分类和错误信息
+type Rejection = (DeadLetterCategory, String);
+
+/// 等待采集任务完成写入的最长时间，包括排队等待正在进行的读取
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 写入命令的消息格式
+///
+/// 例如 `{"id": "req-1", "point": "有功功率设定", "value": 50.5}`，线圈也可以写 `true`/`false`，字符串点位写字符串。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteCommand {
+    /// 请求标识，原样带回应答，可以是字符串或数字
+    #[serde(default)]
+    pub id: Option<Value>,
+    /// 点位名称
+    pub point: String,
+    /// 要写入的工程值
+    pub value: CommandValue,
+}
+
+/// 命令中的写入值
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum CommandValue {
+    /// 数值
+    Number(f64),
+    /// 开关量，true 为1，false 为0
+    Bool(bool),
+    /// 字符串，只能写入字符串点位
+    Text(String),
+}
+
+impl CommandValue {
+    // 应答中的写入值，开关量为0或1
+    fn to_point_value(&self) -> PointValue {
+        match self {
+            CommandValue::Number(value) => PointValue::Number(*value),
+            CommandValue::Bool(value) => PointValue::Number(f64::from(u8::from(*value))),
+            CommandValue::Text(text) => PointValue::Text(text.clone()),
+        }
+    }
+}
+
+/// 写入命令的应答，发布到 `<命令主题>/ack`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommandAck {
+    /// 命令中的 id，原样带回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    /// 点位名称，命令格式错误时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub point: Option<String>,
+    /// 写入值，命令格式错误时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<PointValue>,
+    /// 是否写入成功
+    pub success: bool,
+    /// 失败原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 订阅命令主题，把收到的写入命令交给对应网关的采集任务执行
+///
+/// 命令与采集在同一个 Modbus 连接上按顺序执行，不会与读取交错。
+/// 配置了死信主题时，执行失败的命令连同原始内容发布到死信主题。
+pub struct CommandHandler {
+    client: Arc<MqttClient>,
+    topic: TopicTemplate,
+    topic_prefix: String,
+    site: Option<String>,
+    writers: SharedWriters,
+    dead_letters: Option<SharedDeadLetters>,
+}
+
+impl CommandHandler {
+    /// 创建命令处理器
+    ///
+    /// # 参数说明
+    /// * `client` - MQTT 客户端，用于订阅命令主题和发布应答
+    /// * `settings` - MQTT 配置，使用其中的命令主题模板、前缀和站点名称
+    /// * `writers` - 正在采集的网关的写入入口
+    /// * `dead_letters` - 死信发布器，未配置死信主题时为 None
+    pub fn new(
+        client: Arc<MqttClient>,
+        settings: &MqttSettings,
+        writers: SharedWriters,
+        dead_letters: Option<SharedDeadLetters>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(CommandHandler {
+            client,
+            topic: settings.command_topic()?,
+            topic_prefix: settings.topic_prefix.clone(),
+            site: settings.site.clone(),
+            writers,
+            dead_letters,
+        })
+    }
+
+    /// 订阅所有设备命令的主题过滤器
+    pub fn filter(&self) -> String {
+        self.topic
+            .subscription_filter(&self.topic_prefix, self.site.as_deref())
+    }
+
+    /// 订阅命令主题并持续处理收到的消息，MQTT 客户端关闭时返回
+    ///
+    /// 每条命令在单独的任务中执行，不同网关的写入互不等待。
+    pub async fn run(self) {
+        let filter = self.filter();
+        let mut messages = match self.client.subscribe(&filter, QoS::AtLeastOnce).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                warn!("订阅命令主题 {} 失败: {}", filter, e);
+                return;
+            }
+        };
+        info!("已订阅命令主题 {}", filter);
+
+        let handler = Arc::new(self);
+        while let Some(message) = messages.recv().await {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                let ack = handler.execute(&message.topic, &message.payload).await;
+                handler.reply(&message.topic, &ack).await;
+            });
+        }
+    }
+
+    /// 执行一条命令并返回应答
+    ///
+    /// # 说明
+    /// * 消息格式错误、找不到设备或点位、点位已停用或不可写、写入值无法换算时直接拒绝，
+    ///   不会发出 Modbus 请求
+    /// * 写入失败或超时时 `success` 为 false，`error` 为原因
+    pub async fn execute(&self, topic: &str, payload: &[u8]) -> CommandAck {
+        let command: WriteCommand = match serde_json::from_slice(payload) {
+            Ok(command) => command,
+            Err(e) => {
+                // 语法错误为 malformed_json，字段缺失或类型不对为 validation_failed
+                let category = if e.is_syntax() || e.is_eof() {
+                    DeadLetterCategory::MalformedJson
+                } else {
+                    DeadLetterCategory::ValidationFailed
+                };
+                let ack = CommandAck {
+                    id: None,
+                    point: None,
+                    value: None,
+                    success: false,
+                    error: Some(format!("命令格式错误: {}", e)),
+                };
+                self.reject(topic, payload, category, &ack);
+                return ack;
+            }
+        };
+        let value = command.value.to_point_value();
+        let result = self.write(topic, &command.point, &value).await;
+        let ack = CommandAck {
+            id: command.id,
+            point: Some(command.point),
+            value: Some(value),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|(_, message)| message.clone()),
+        };
+        if let Err((category, _)) = result {
+            self.reject(topic, payload, category, &ack);
+        }
+        ack
+    }
+
+    async fn write(&self, topic: &str, point_name: &str, value: &PointValue) -> Result<(), Rejection> {
+        let (sender, slave_id, point) = self.find_point(topic, point_name)?;
+        let registers = match value {
+            PointValue::Number(value) => point.registers_for_value(*value),
+            PointValue::Text(text) => point.registers_for_text(text),
+        }
+        .map_err(|e| (DeadLetterCategory::ValidationFailed, e.to_string()))?;
+
+        let (reply, result) = oneshot::channel();
+        let request = WriteRequest {
+            slave_id,
+            point,
+            registers,
+            function_code: None,
+            reply,
+        };
+        let modbus_error = |message: String| (DeadLetterCategory::ModbusError, message);
+        sender
+            .try_send(GatewayRequest::Write(Box::new(request)))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => modbus_error("网关的写入队列已满".to_string()),
+                mpsc::error::TrySendError::Closed(_) => modbus_error("网关已停止采集".to_string()),
+            })?;
+        match tokio::time::timeout(WRITE_TIMEOUT, result).await {
+            Ok(Ok(result)) => result.map_err(modbus_error),
+            Ok(Err(_)) => Err(modbus_error("网关已停止采集".to_string())),
+            Err(_) => Err(modbus_error(format!(
+                "{} 秒内未完成写入",
+                WRITE_TIMEOUT.as_secs()
+            ))),
+        }
+    }
+
+    // 按命令主题找到对应的网关、从站和点位
+    fn find_point(
+        &self,
+        topic: &str,
+        point_name: &str,
+    ) -> Result<(mpsc::Sender<GatewayRequest>, u8, Point), Rejection> {
+        let writers = self.writers.lock().unwrap_or_else(|e| e.into_inner());
+        for writer in writers.iter() {
+            let gateway = &writer.gateway;
+            let gateway_name = gateway.display_name();
+            for (slave_id, points) in &writer.points {
+                let slave = gateway
+                    .find_slave(*slave_id)
+                    .map_or_else(|| slave_id.to_string(), |slave| slave.display_name());
+                let device_topic = self.topic.expand(&TopicValues {
+                    prefix: &self.topic_prefix,
+                    site: self.site.as_deref(),
+                    gateway: &gateway_name,
+                    gateway_host: &gateway.ip,
+                    slave: &slave,
+                    ..TopicValues::default()
+                });
+                if device_topic != topic {
+                    continue;
+                }
+
+                let point = points.iter().find(|p| p.name == point_name).ok_or_else(|| {
+                    let message = format!("从站 {} 没有点位 {}", slave, point_name);
+                    (DeadLetterCategory::UnknownPoint, message)
+                })?;
+                let invalid = |message: String| (DeadLetterCategory::ValidationFailed, message);
+                if !point.enabled {
+                    return Err(invalid(format!("点位 {} 已停用", point_name)));
+                }
+                if !point.writable {
+                    return Err(invalid(format!("点位 {} 不允许写入", point_name)));
+                }
+                return Ok((writer.sender.clone(), *slave_id, point.clone()));
+            }
+        }
+        Err((
+            DeadLetterCategory::UnknownPoint,
+            format!("没有与主题 {} 对应的正在采集的设备", topic),
+        ))
+    }
+
+    // 应答发布到命令主题下的 ack 子主题，不保留
+    async fn reply(&self, topic: &str, ack: &CommandAck) {
+        let ack_topic = format!("{}/ack", topic);
+        let result = match serde_json::to_vec(ack) {
+            Ok(payload) => self
+                .client
+                .publish(&ack_topic, QoS::AtLeastOnce, false, &payload)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            warn!("发布命令应答 {} 失败: {}", ack_topic, e);
+        }
+    }
+
+    // 输出日志，配置了死信主题时连同原始命令发布到死信主题
+    fn reject(&self, topic: &str, payload: &[u8], category: DeadLetterCategory, ack: &CommandAck) {
+        let error = ack.error.as_deref().unwrap_or("");
+        warn!("MQTT 命令 {} 执行失败: {}", topic, error);
+        if let Some(dead_letters) = &self.dead_letters {
+            dead_letters.report(topic, payload, category, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::scheduler::PollEvent;
+    use crate::reload::GatewayTasks;
+    use crate::test_support::{config, MockBroker, MockModbus, Received};
+    use serde_json::json;
+
+    const TOPIC: &str = "ems/PCS-A/meter/cmd";
+
+    // 连接模拟 Modbus 服务器的网关，轮询间隔足够长，测试期间只有命令产生写请求
+    fn start_gateway(port: u16) -> GatewayTasks<impl Fn(PollEvent) + Clone + Send + 'static> {
+        let config = config(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    request_timeout_ms: 200\n    poll_interval_ms: 60000\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: power, address: 0 }}\n      - {{ name: setpoint, address: 10, data_type: i32, word_order: cdab, scale: 0.1, writable: true }}\n      - {{ name: limit, address: 20, writable: true }}\n      - {{ name: spare, address: 21, writable: true, enabled: false }}\n      - {{ name: relay, function_code: 1, address: 0, data_type: bool, writable: true }}\n",
+            port
+        ));
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config);
+        tasks
+    }
+
+    fn handler(broker: &MockBroker, client_id: &str, writers: SharedWriters) -> (CommandHandler, Arc<MqttClient>) {
+        let mut settings = MqttSettings::new("127.0.0.1", client_id);
+        settings.broker_port = broker.port;
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let handler = CommandHandler::new(Arc::clone(&client), &settings, writers, None).unwrap();
+        (handler, client)
+    }
+
+    // Modbus 服务器收到的写请求：(功能码, 地址, 写入值)
+    fn writes(modbus: &MockModbus) -> Vec<(u8, u16, Vec<u16>)> {
+        modbus
+            .requests()
+            .into_iter()
+            .filter(|r| !r.values.is_empty())
+            .map(|r| (r.function_code, r.address, r.values))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scripted_command_is_written_and_acknowledged() {
+        let modbus = MockModbus::start().await;
+        let tasks = start_gateway(modbus.port);
+        let broker = MockBroker::start().await;
+        let (handler, client) = handler(&broker, "cmd-test", tasks.writers());
+        assert_eq!(handler.filter(), "ems/+/+/cmd");
+        tokio::spawn(handler.run());
+        broker
+            .wait_for("订阅命令主题", |received| {
+                received.iter().any(|p| matches!(p, Received::Subscribe(f) if f == "ems/+/+/cmd"))
+            })
+            .await;
+
+        broker.inject(TOPIC, br#"{"id":"req-1","point":"setpoint","value":-1234.5}"#);
+        let acks = broker.wait_for_topic("ems/PCS-A/meter/cmd/ack", 1).await;
+        let ack: Value = serde_json::from_slice(&acks[0]).unwrap();
+        assert_eq!(ack, json!({"id": "req-1", "point": "setpoint", "value": -1234.5, "success": true}));
+        // 原始值 = -1234.5 / 0.1 = -12345 = 0xFFFF_CFC7，cdab 低位字在前
+        assert_eq!(writes(&modbus), [(0x10, 10, vec![0xCFC7, 0xFFFF])]);
+
+        // 开关量写线圈，应答中的值为0或1
+        broker.inject(TOPIC, br#"{"id":7,"point":"relay","value":true}"#);
+        let acks = broker.wait_for_topic("ems/PCS-A/meter/cmd/ack", 2).await;
+        let ack: Value = serde_json::from_slice(&acks[1]).unwrap();
+        assert_eq!(ack, json!({"id": 7, "point": "relay", "value": 1.0, "success": true}));
+        assert_eq!(writes(&modbus)[1], (0x05, 0, vec![1]));
+
+        // 应答不保留
+        assert!(broker.publishes().iter().filter(|p| p.topic.ends_with("/ack")).all(|p| !p.retain));
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn rejected_commands_are_acknowledged_without_modbus_writes() {
+        let modbus = MockModbus::start().await;
+        let tasks = start_gateway(modbus.port);
+        let broker = MockBroker::start().await;
+        let (handler, client) = handler(&broker, "cmd-test", tasks.writers());
+        let error = |ack: CommandAck| {
+            assert!(!ack.success);
+            ack.error.unwrap()
+        };
+
+        let ack = handler.execute(TOPIC, b"{not json").await;
+        assert_eq!((ack.id.clone(), ack.point.clone(), ack.value.clone()), (None, None, None));
+        assert!(error(ack).starts_with("命令格式错误"));
+        let ack = handler.execute(TOPIC, br#"{"id":1,"point":"setpoint"}"#).await;
+        assert!(error(ack).starts_with("命令格式错误"));
+
+        let ack = handler.execute(TOPIC, br#"{"id":2,"point":"missing","value":1}"#).await;
+        assert_eq!((ack.id.clone(), ack.point.as_deref()), (Some(json!(2)), Some("missing")));
+        assert_eq!(error(ack), "从站 meter 没有点位 missing");
+        let ack = handler.execute("ems/PCS-A/2/cmd", br#"{"point":"setpoint","value":1}"#).await;
+        assert_eq!(error(ack), "没有与主题 ems/PCS-A/2/cmd 对应的正在采集的设备");
+
+        let ack = handler.execute(TOPIC, br#"{"point":"power","value":1}"#).await;
+        assert_eq!(error(ack), "点位 power 不允许写入");
+        let ack = handler.execute(TOPIC, br#"{"point":"spare","value":1}"#).await;
+        assert_eq!(error(ack), "点位 spare 已停用");
+
+        // u16 点位超出范围，字符串写入数值点位
+        let ack = handler.execute(TOPIC, br#"{"point":"limit","value":70000}"#).await;
+        assert!(error(ack).starts_with("点位 limit 写入值 70000 无效"));
+        let ack = handler.execute(TOPIC, br#"{"point":"limit","value":-1}"#).await;
+        assert!(error(ack).starts_with("点位 limit 写入值 -1 无效"));
+        let ack = handler.execute(TOPIC, br#"{"point":"limit","value":"on"}"#).await;
+        assert_eq!(error(ack), "点位 limit 不是字符串，只能写入数值");
+
+        assert!(writes(&modbus).is_empty());
+
+        // 通过 reply 发布的失败应答不包含缺失的字段
+        let ack = handler.execute(TOPIC, b"{not json").await;
+        handler.reply(TOPIC, &ack).await;
+        let acks = broker.wait_for_topic("ems/PCS-A/meter/cmd/ack", 1).await;
+        let ack: Value = serde_json::from_slice(&acks[0]).unwrap();
+        assert_eq!(ack.as_object().unwrap().keys().collect::<Vec<_>>(), ["error", "success"]);
+        assert_eq!(ack["success"], false);
+        client.close().await;
+    }
+}
@@ -0,0 +1,213 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// 压缩后的数据主题追加的后缀
+pub const GZIP_TOPIC_SUFFIX: &str = "/gz";
+/// gzip 数据开头的两个字节
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// 采集数据消息的压缩方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// 不压缩
+    #[default]
+    None,
+    /// 超出阈值的消息使用 gzip 压缩
+    Gzip,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+        })
+    }
+}
+
+/// 接收方识别压缩消息的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionMarker {
+    /// 压缩的消息发布到数据主题加 `/gz` 的主题
+    #[default]
+    TopicSuffix,
+    /// 主题不变，接收方按 gzip 数据开头的 `1f 8b` 识别
+    Magic,
+}
+
+/// 需要时压缩一条采集数据消息
+///
+/// # 参数说明
+/// * `threshold` - 消息不超过该字节数时不压缩，避免小消息因 gzip 头部反而变大
+///
+/// # 返回值
+/// * 发布使用的主题和内容；压缩后没有变小时按原样发布
+pub fn compress(
+    topic: &str,
+    payload: Vec<u8>,
+    compression: Compression,
+    threshold: usize,
+    marker: CompressionMarker,
+) -> (String, Vec<u8>) {
+    if compression == Compression::None || payload.len() <= threshold {
+        return (topic.to_string(), payload);
+    }
+    let compressed = match gzip(&payload) {
+        Ok(compressed) if compressed.len() < payload.len() => compressed,
+        _ => return (topic.to_string(), payload),
+    };
+    match marker {
+        CompressionMarker::TopicSuffix => (format!("{}{}", topic, GZIP_TOPIC_SUFFIX), compressed),
+        CompressionMarker::Magic => (topic.to_string(), compressed),
+    }
+}
+
+/// 解码收到的采集数据消息，供订阅数据主题的程序使用
+///
+/// 主题以 `/gz` 结尾时按 gzip 解压并去掉后缀；否则内容以 gzip 头开头且能解压时返回解压后的内容，
+/// 不能解压时（例如恰好以 `1f 8b` 开头的 raw_registers）原样返回。
+///
+/// # 返回值
+/// * 原始的数据主题和消息内容
+/// * `Err` - 主题带有 `/gz` 后缀但内容不是合法的 gzip 数据
+pub fn decode_payload(topic: &str, payload: &[u8]) -> io::Result<(String, Vec<u8>)> {
+    if let Some(topic) = topic.strip_suffix(GZIP_TOPIC_SUFFIX) {
+        return Ok((topic.to_string(), gunzip(payload)?));
+    }
+    if payload.starts_with(&GZIP_MAGIC)
+        && let Ok(decoded) = gunzip(payload)
+    {
+        return Ok((topic.to_string(), decoded));
+    }
+    Ok((topic.to_string(), payload.to_vec()))
+}
+
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::device_configuration::mqtt::MqttSettings;
+    use crate::latest::ReadingCache;
+    use crate::modbus::availability::{Availability, DeviceAvailability};
+    use crate::mqtt::client::MqttClient;
+    use crate::mqtt::discovery::Discovery;
+    use crate::mqtt::publisher::Publisher;
+    use crate::test_support::{reading, MockBroker};
+    use std::sync::Arc;
+
+    fn large_json() -> Vec<u8> {
+        let values: Vec<String> = (0..100).map(|i| format!("\"p{}\":{}", i, i)).collect();
+        format!("{{{}}}", values.join(",")).into_bytes()
+    }
+
+    #[test]
+    fn only_payloads_over_threshold_are_compressed() {
+        let payload = large_json();
+        let size = payload.len();
+        let marker = CompressionMarker::TopicSuffix;
+        assert_eq!(
+            compress("ems/a/1", payload.clone(), Compression::Gzip, size, marker),
+            ("ems/a/1".to_string(), payload.clone())
+        );
+        assert_eq!(
+            compress("ems/a/1", payload.clone(), Compression::None, 0, marker),
+            ("ems/a/1".to_string(), payload.clone())
+        );
+        let (topic, compressed) = compress("ems/a/1", payload.clone(), Compression::Gzip, size - 1, marker);
+        assert_eq!(topic, "ems/a/1/gz");
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        assert!(compressed.len() < size);
+
+        // 压缩后没有变小时原样发布
+        let tiny = b"{}".to_vec();
+        assert_eq!(
+            compress("ems/a/1", tiny.clone(), Compression::Gzip, 0, marker),
+            ("ems/a/1".to_string(), tiny)
+        );
+    }
+
+    #[test]
+    fn decode_payload_round_trips_both_markers() {
+        let payload = large_json();
+        for marker in [CompressionMarker::TopicSuffix, CompressionMarker::Magic] {
+            let (topic, compressed) = compress("ems/a/1", payload.clone(), Compression::Gzip, 0, marker);
+            assert_ne!(compressed, payload);
+            assert_eq!(
+                decode_payload(&topic, &compressed).unwrap(),
+                ("ems/a/1".to_string(), payload.clone())
+            );
+        }
+        // 未压缩的消息和恰好以 gzip 头开头的其他数据原样返回
+        assert_eq!(decode_payload("ems/a/1", b"{}").unwrap(), ("ems/a/1".to_string(), b"{}".to_vec()));
+        let raw = [0x1f, 0x8b, 0x00, 0x01];
+        assert_eq!(decode_payload("ems/a/1", &raw).unwrap().1, raw);
+        assert!(decode_payload("ems/a/1/gz", b"not gzip").is_err());
+    }
+
+    #[tokio::test]
+    async fn availability_and_discovery_are_never_compressed() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "compression-test");
+        settings.broker_port = broker.port;
+        settings.compression = Compression::Gzip;
+        settings.compression_threshold_bytes = 0;
+        settings.home_assistant = Some(Default::default());
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher = Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, None, None).unwrap();
+        let mut discovery = Discovery::new(Arc::clone(&client), &settings).unwrap();
+
+        let mut readings: Vec<_> = (0..50).map(|i| reading("10.0.0.1:502", 1, &format!("p{}", i), i as f64)).collect();
+        for reading in &mut readings {
+            reading.publish.compression = Compression::Gzip;
+        }
+        publisher.publish(&readings);
+        publisher.publish_availability(&DeviceAvailability {
+            gateway: "10.0.0.1:502".to_string(),
+            gateway_name: None,
+            slave_id: 1,
+            slave_name: None,
+            availability: Availability::Offline,
+        });
+        let config: Config = serde_yaml::from_str(
+            "version: 2\ngateways:\n  - { ip: 10.0.0.1, slave_ids: [1], points: [{ name: power, address: 0, unit: kW }] }\n",
+        )
+        .unwrap();
+        discovery.apply(&config);
+
+        let data = broker.wait_for_topic("ems/10.0.0.1:502/1/gz", 1).await;
+        assert_eq!(data[0][..2], GZIP_MAGIC);
+        assert_eq!(
+            broker.wait_for_topic("ems/10.0.0.1:502/1/availability", 1).await,
+            [b"offline".to_vec()]
+        );
+        let discovery = broker
+            .wait_for_topic("homeassistant/sensor/compression-test/10_0_0_1_502_1_power/config", 1)
+            .await;
+        assert!(serde_json::from_slice::<serde_json::Value>(&discovery[0]).is_ok());
+        assert!(
+            broker
+                .publishes()
+                .iter()
+                .filter(|p| p.topic != "ems/10.0.0.1:502/1/gz")
+                .all(|p| !p.topic.ends_with(GZIP_TOPIC_SUFFIX) && !p.payload.starts_with(&GZIP_MAGIC))
+        );
+        client.close().await;
+    }
+}
@@ -0,0 +1,658 @@
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::device_configuration::mqtt::{Control, MqttSettings};
+use crate::modbus::scheduler::{ControlRequest, GatewayControl, GatewayRequest};
+use crate::mqtt::client::MqttClient;
+use crate::mqtt::dead_letter::{DeadLetterCategory, SharedDeadLetters};
+use crate::mqtt::payload::format_timestamp;
+use crate::mqtt::rate_limit::TokenBucket;
+use crate::reload::{GatewayWriter, ReloadRequest, SharedWriters};
+
+/// 等待控制命令执行完成的最长时间，包括排队等待正在进行的采集
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 命令无法执行的原因和错误信息，原因为 None 时不算死信
+type Rejection = (Option<DeadLetterCategory>, String);
+
+/// 控制命令的消息格式
+///
+/// 例如 `{"action": "pause", "device": "PCS-B", "request_id": "abc"}`。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ControlCommand {
+    /// 请求标识，原样带回应答，可以是字符串或数字
+    #[serde(default)]
+    pub request_id: Option<Value>,
+    /// 操作名称，见 [`ControlAction`]
+    pub action: String,
+    /// 网关名称或 ip:port，pause、resume 和 restart_device 需要
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+/// 控制命令支持的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlAction {
+    /// 立即重新加载配置文件，与定期检查配置文件变化的流程相同
+    ReloadConfig,
+    /// 暂停网关的定时采集
+    Pause,
+    /// 恢复网关的定时采集
+    Resume,
+    /// 在应答中返回所有网关的采集统计
+    Stats,
+    /// 断开并重新连接网关
+    RestartDevice,
+}
+
+impl ControlAction {
+    /// 按名称查找操作，未知的名称返回 None
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reload_config" => Some(ControlAction::ReloadConfig),
+            "pause" => Some(ControlAction::Pause),
+            "resume" => Some(ControlAction::Resume),
+            "stats" => Some(ControlAction::Stats),
+            "restart_device" => Some(ControlAction::RestartDevice),
+            _ => None,
+        }
+    }
+
+    // 需要交给网关采集任务执行的操作
+    fn gateway_control(self) -> Option<GatewayControl> {
+        match self {
+            ControlAction::Pause => Some(GatewayControl::Pause),
+            ControlAction::Resume => Some(GatewayControl::Resume),
+            ControlAction::RestartDevice => Some(GatewayControl::Reconnect),
+            ControlAction::ReloadConfig | ControlAction::Stats => None,
+        }
+    }
+}
+
+/// 控制命令的应答，发布到 `<控制主题>/response`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlAck {
+    /// 命令中的 request_id，原样带回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Value>,
+    /// 命令中的操作，命令格式错误时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// 命令中的网关
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// 是否执行成功
+    pub success: bool,
+    /// reload_config 时配置是否有变化
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed: Option<bool>,
+    /// stats 时所有网关的采集统计
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Value>,
+    /// 失败原因，reload_config 被拒绝时为配置的加载或校验错误
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// 执行成功时应答中的附加内容
+#[derive(Default)]
+struct Outcome {
+    changed: Option<bool>,
+    stats: Option<Value>,
+}
+
+/// 订阅控制主题，执行重新加载配置、暂停和恢复采集等命令
+///
+/// # 说明
+/// * 设备在 CGNAT 后无法直接访问时，MQTT 是唯一的控制通道；谁能发送命令由 Broker 的权限控制决定
+/// * pause、resume 和 restart_device 交给网关的采集任务，在正在进行的采集完成后执行
+/// * 暂停状态在采集任务崩溃重启后保持，网关配置变化或程序重启后恢复采集
+/// * 超出 `control.max_per_second` 的命令直接拒绝，不会排队
+/// * 配置了死信主题时，格式错误、未知操作和找不到网关的命令发布到死信主题
+pub struct ControlHandler {
+    client: Arc<MqttClient>,
+    topic: String,
+    writers: SharedWriters,
+    reload: mpsc::Sender<ReloadRequest>,
+    limiter: Mutex<TokenBucket>,
+    dead_letters: Option<SharedDeadLetters>,
+}
+
+impl ControlHandler {
+    /// 创建控制命令处理器
+    ///
+    /// # 参数说明
+    /// * `client` - MQTT 客户端，用于订阅控制主题和发布应答
+    /// * `settings` - MQTT 配置，使用其中的主题前缀和客户端ID
+    /// * `control` - 远程控制配置
+    /// * `writers` - 正在采集的网关的请求入口
+    /// * `reload` - 立即重新加载配置的请求入口
+    /// * `dead_letters` - 死信发布器，未配置死信主题时为 None
+    pub fn new(
+        client: Arc<MqttClient>,
+        settings: &MqttSettings,
+        control: &Control,
+        writers: SharedWriters,
+        reload: mpsc::Sender<ReloadRequest>,
+        dead_letters: Option<SharedDeadLetters>,
+    ) -> Self {
+        ControlHandler {
+            client,
+            topic: settings.control_topic(),
+            writers,
+            reload,
+            limiter: Mutex::new(TokenBucket::new(control.max_per_second, Instant::now())),
+            dead_letters,
+        }
+    }
+
+    /// 订阅控制主题并持续处理收到的消息，MQTT 客户端关闭时返回
+    pub async fn run(self) {
+        let mut messages = match self.client.subscribe(&self.topic, QoS::AtLeastOnce).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                warn!("订阅控制主题 {} 失败: {}", self.topic, e);
+                return;
+            }
+        };
+        info!("已订阅控制主题 {}", self.topic);
+
+        let handler = Arc::new(self);
+        while let Some(message) = messages.recv().await {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                let ack = handler.execute(&message.payload).await;
+                handler.reply(&ack).await;
+            });
+        }
+    }
+
+    /// 执行一条控制命令并返回应答
+    pub async fn execute(&self, payload: &[u8]) -> ControlAck {
+        let command: ControlCommand = match serde_json::from_slice(payload) {
+            Ok(command) => command,
+            Err(e) => {
+                let category = if e.is_syntax() || e.is_eof() {
+                    DeadLetterCategory::MalformedJson
+                } else {
+                    DeadLetterCategory::ValidationFailed
+                };
+                let message = format!("命令格式错误: {}", e);
+                self.dead_letter(payload, Some(category), &message);
+                return failed(None, None, None, message);
+            }
+        };
+        match self.dispatch(&command).await {
+            Ok(outcome) => {
+                info!(action = %command.action, device = ?command.device, "控制命令执行成功");
+                ControlAck {
+                    request_id: command.request_id,
+                    action: Some(command.action),
+                    device: command.device,
+                    success: true,
+                    changed: outcome.changed,
+                    stats: outcome.stats,
+                    error: None,
+                }
+            }
+            Err((category, message)) => {
+                self.dead_letter(payload, category, &message);
+                failed(command.request_id, Some(command.action), command.device, message)
+            }
+        }
+    }
+
+    async fn dispatch(&self, command: &ControlCommand) -> Result<Outcome, Rejection> {
+        let action = ControlAction::from_name(&command.action).ok_or_else(|| {
+            let message = format!(
+                "未知的操作 {}，只能是 reload_config、pause、resume、stats 或 restart_device",
+                command.action
+            );
+            (Some(DeadLetterCategory::ValidationFailed), message)
+        })?;
+        let allowed = self
+            .limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .try_take(Instant::now());
+        if !allowed {
+            return Err((None, "控制命令过于频繁".to_string()));
+        }
+
+        if let Some(control) = action.gateway_control() {
+            let device = command.device.as_deref().ok_or_else(|| {
+                let message = format!("操作 {} 需要指定 device", command.action);
+                (Some(DeadLetterCategory::ValidationFailed), message)
+            })?;
+            let sender = self.find_gateway(device)?;
+            self.control(sender, control).await?;
+            return Ok(Outcome::default());
+        }
+        match action {
+            ControlAction::ReloadConfig => {
+                let changed = self.reload_config().await?;
+                Ok(Outcome {
+                    changed: Some(changed),
+                    ..Outcome::default()
+                })
+            }
+            _ => {
+                let writers = self.writers.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                Ok(Outcome {
+                    stats: Some(stats_snapshot(&writers)),
+                    ..Outcome::default()
+                })
+            }
+        }
+    }
+
+    async fn reload_config(&self) -> Result<bool, Rejection> {
+        let (reply, result) = oneshot::channel();
+        self.reload
+            .try_send(ReloadRequest { reply })
+            .map_err(|_| (None, "正在重新加载配置".to_string()))?;
+        match tokio::time::timeout(CONTROL_TIMEOUT, result).await {
+            Ok(Ok(result)) => {
+                result.map_err(|e| (None, format!("新配置无法加载，继续使用当前配置: {}", e)))
+            }
+            Ok(Err(_)) => Err((None, "程序正在退出".to_string())),
+            Err(_) => Err((None, format!("{} 秒内未完成重新加载", CONTROL_TIMEOUT.as_secs()))),
+        }
+    }
+
+    async fn control(
+        &self,
+        sender: mpsc::Sender<GatewayRequest>,
+        action: GatewayControl,
+    ) -> Result<(), Rejection> {
+        let (reply, result) = oneshot::channel();
+        sender
+            .try_send(GatewayRequest::Control(ControlRequest { action, reply }))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => (None, "网关的请求队列已满".to_string()),
+                mpsc::error::TrySendError::Closed(_) => (None, "网关已停止采集".to_string()),
+            })?;
+        match tokio::time::timeout(CONTROL_TIMEOUT, result).await {
+            Ok(Ok(result)) => result.map_err(|e| (None, e)),
+            Ok(Err(_)) => Err((None, "网关已停止采集".to_string())),
+            Err(_) => Err((None, format!("{} 秒内未完成", CONTROL_TIMEOUT.as_secs()))),
+        }
+    }
+
+    // 按网关名称或 ip:port 找到正在采集的网关
+    fn find_gateway(&self, device: &str) -> Result<mpsc::Sender<GatewayRequest>, Rejection> {
+        let writers = self.writers.lock().unwrap_or_else(|e| e.into_inner());
+        writers
+            .iter()
+            .find(|w| {
+                let gateway = &w.gateway;
+                gateway.display_name() == device || format!("{}:{}", gateway.ip, gateway.port) == device
+            })
+            .map(|w| w.sender.clone())
+            .ok_or_else(|| {
+                let message = format!("没有正在采集的网关 {}", device);
+                (Some(DeadLetterCategory::UnknownPoint), message)
+            })
+    }
+
+    fn dead_letter(&self, payload: &[u8], category: Option<DeadLetterCategory>, error: &str) {
+        if let (Some(dead_letters), Some(category)) = (&self.dead_letters, category) {
+            dead_letters.report(&self.topic, payload, category, error);
+        }
+    }
+
+    // 应答发布到控制主题下的 response 子主题，不保留
+    async fn reply(&self, ack: &ControlAck) {
+        let topic = format!("{}/response", self.topic);
+        let result = match serde_json::to_vec(ack) {
+            Ok(payload) => self
+                .client
+                .publish(&topic, QoS::AtLeastOnce, false, &payload)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            warn!("发布控制命令应答 {} 失败: {}", topic, e);
+        }
+    }
+}
+
+fn failed(
+    request_id: Option<Value>,
+    action: Option<String>,
+    device: Option<String>,
+    message: String,
+) -> ControlAck {
+    warn!(action = ?action, device = ?device, error = %message, "控制命令执行失败");
+    ControlAck {
+        request_id,
+        action,
+        device,
+        success: false,
+        changed: None,
+        stats: None,
+        error: Some(message),
+    }
+}
+
+/// 所有正在采集的网关及其从站的采集统计
+pub fn stats_snapshot(writers: &[GatewayWriter]) -> Value {
+    let gateways: Vec<Value> = writers
+        .iter()
+        .map(|writer| {
+            let gateway = &writer.gateway;
+            let stats = writer.stats.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            let slaves: Vec<Value> = gateway
+                .enabled_slaves()
+                .map(|slave| {
+                    let slave_stats = stats.slaves.get(&slave.id).cloned().unwrap_or_default();
+                    let requests: Vec<Value> = stats
+                        .requests
+                        .iter()
+                        .filter(|((slave_id, _, _), _)| *slave_id == slave.id)
+                        .map(|((_, function, result), count)| {
+                            json!({ "function": function, "result": result.as_str(), "count": count })
+                        })
+                        .collect();
+                    json!({
+                        "id": slave.id,
+                        "name": slave.display_name(),
+                        "cycles": slave_stats.cycles,
+                        "reads_ok": slave_stats.reads_ok,
+                        "reads_failed": slave_stats.reads_failed,
+                        "overruns": slave_stats.overruns,
+                        "last_error": slave_stats.last_error,
+                        "last_success": slave_stats.last_success.map(format_timestamp),
+                        "requests": requests,
+                    })
+                })
+                .collect();
+            json!({
+                "name": gateway.display_name(),
+                "address": format!("{}:{}", gateway.ip, gateway.port),
+                "connected": stats.connected,
+                "paused": stats.paused,
+                "restarts": stats.restarts,
+                "last_failure": stats.last_failure,
+                "failed": stats.failed,
+                "poll_cycles": stats.cycle_durations.count(),
+                "poll_seconds": stats.cycle_durations.sum(),
+                "slaves": slaves,
+            })
+        })
+        .collect();
+    Value::Array(gateways)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::device_configuration::mqtt::DeadLetterTopic;
+    use crate::mqtt::dead_letter::DeadLetters;
+    use crate::test_support::{MockBroker, Received};
+    use std::collections::BTreeMap;
+
+    const CONFIG: &str = "version: 2\ngateways:\n  - ip: 10.0.0.1\n    name: PCS-A\n    slave_ids: [{ id: 1, name: meter }]\n    points:\n      - { name: power, address: 0 }\n  - ip: 10.0.0.2\n    name: PCS-B\n    slave_ids: [1]\n    points:\n      - { name: power, address: 0 }\n";
+
+    // 收到的控制操作（按网关名称），模拟的采集任务按操作修改暂停状态
+    type Controls = Arc<Mutex<Vec<(String, GatewayControl)>>>;
+
+    // 模拟的网关采集任务：记录收到的控制操作并应答，不连接设备
+    fn fake_writers() -> (SharedWriters, Controls) {
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        config.validate().unwrap();
+        let received: Controls = Arc::default();
+        let writers = config
+            .gateways
+            .iter()
+            .map(|gateway| {
+                let (sender, mut requests) = mpsc::channel(4);
+                let stats: crate::modbus::stats::SharedStats = Arc::default();
+                tokio::spawn({
+                    let (name, stats, received) =
+                        (gateway.display_name(), Arc::clone(&stats), Arc::clone(&received));
+                    async move {
+                        while let Some(request) = requests.recv().await {
+                            let GatewayRequest::Control(request) = request else { continue };
+                            match request.action {
+                                GatewayControl::Pause => stats.lock().unwrap().paused = true,
+                                GatewayControl::Resume => stats.lock().unwrap().paused = false,
+                                GatewayControl::Reconnect => {}
+                            }
+                            received.lock().unwrap().push((name.clone(), request.action));
+                            let _ = request.reply.send(Ok(()));
+                        }
+                    }
+                });
+                GatewayWriter {
+                    gateway: gateway.clone(),
+                    points: BTreeMap::new(),
+                    sender,
+                    stats,
+                }
+            })
+            .collect();
+        (Arc::new(Mutex::new(writers)), received)
+    }
+
+    // 控制命令处理器，重新加载配置的请求依次应答 `reloads` 中的结果
+    fn handler(
+        broker: &MockBroker,
+        client_id: &str,
+        writers: &SharedWriters,
+        reloads: Vec<Result<bool, String>>,
+    ) -> (ControlHandler, Arc<MqttClient>) {
+        let mut settings = MqttSettings::new("127.0.0.1", client_id);
+        settings.broker_port = broker.port;
+        settings.dead_letter = Some(DeadLetterTopic { topic: None, max_per_second: 100.0 });
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let (reload, mut requests) = mpsc::channel::<ReloadRequest>(1);
+        tokio::spawn(async move {
+            for result in reloads {
+                let Some(request) = requests.recv().await else { return };
+                let _ = request.reply.send(result);
+            }
+        });
+        let dead_letters = DeadLetters::new(Arc::clone(&client), &settings);
+        let control = Control { max_per_second: 100.0 };
+        let handler =
+            ControlHandler::new(Arc::clone(&client), &settings, &control, Arc::clone(writers), reload, dead_letters);
+        (handler, client)
+    }
+
+    async fn ack(handler: &ControlHandler, payload: &str) -> Value {
+        serde_json::to_value(handler.execute(payload.as_bytes()).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pause_resume_and_restart_reach_the_gateway() {
+        let broker = MockBroker::start().await;
+        let (writers, received) = fake_writers();
+        let (handler, client) = handler(&broker, "control-gateway", &writers, Vec::new());
+        let paused = |index: usize| writers.lock().unwrap()[index].stats.lock().unwrap().paused;
+
+        assert_eq!(
+            ack(&handler, r#"{"action": "pause", "device": "PCS-B", "request_id": "abc"}"#).await,
+            json!({ "request_id": "abc", "action": "pause", "device": "PCS-B", "success": true })
+        );
+        assert!(!paused(0) && paused(1));
+        // 也可以用 ip:port 指定网关，request_id 可以是数字
+        assert_eq!(
+            ack(&handler, r#"{"action": "resume", "device": "10.0.0.2:502", "request_id": 7}"#).await,
+            json!({ "request_id": 7, "action": "resume", "device": "10.0.0.2:502", "success": true })
+        );
+        assert!(!paused(1));
+        assert_eq!(
+            ack(&handler, r#"{"action": "restart_device", "device": "PCS-A"}"#).await,
+            json!({ "action": "restart_device", "device": "PCS-A", "success": true })
+        );
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                ("PCS-B".to_string(), GatewayControl::Pause),
+                ("PCS-B".to_string(), GatewayControl::Resume),
+                ("PCS-A".to_string(), GatewayControl::Reconnect),
+            ]
+        );
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn reload_config_reports_changes_and_validation_errors() {
+        let broker = MockBroker::start().await;
+        let (writers, _) = fake_writers();
+        let reloads = vec![Ok(true), Ok(false), Err("网关 10.0.0.1:502 的点位 power 地址重复".to_string())];
+        let (handler, client) = handler(&broker, "control-reload", &writers, reloads);
+
+        assert_eq!(
+            ack(&handler, r#"{"action": "reload_config"}"#).await,
+            json!({ "action": "reload_config", "success": true, "changed": true })
+        );
+        assert_eq!(
+            ack(&handler, r#"{"action": "reload_config"}"#).await,
+            json!({ "action": "reload_config", "success": true, "changed": false })
+        );
+        assert_eq!(
+            ack(&handler, r#"{"action": "reload_config", "request_id": "r3"}"#).await,
+            json!({
+                "request_id": "r3",
+                "action": "reload_config",
+                "success": false,
+                "error": "新配置无法加载，继续使用当前配置: 网关 10.0.0.1:502 的点位 power 地址重复",
+            })
+        );
+        // 重新加载的入口已关闭
+        let value = ack(&handler, r#"{"action": "reload_config"}"#).await;
+        assert_eq!(value["success"], false);
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn stats_returns_a_snapshot_of_every_gateway() {
+        let broker = MockBroker::start().await;
+        let (writers, _) = fake_writers();
+        {
+            let writers = writers.lock().unwrap();
+            let mut stats = writers[0].stats.lock().unwrap();
+            stats.connected = true;
+            stats.restarts = 2;
+            let slave = stats.slaves.entry(1).or_default();
+            slave.cycles = 5;
+            slave.reads_ok = 4;
+            slave.reads_failed = 1;
+            slave.last_error = Some("超时".to_string());
+        }
+        let (handler, client) = handler(&broker, "control-stats", &writers, Vec::new());
+        ack(&handler, r#"{"action": "pause", "device": "PCS-B"}"#).await;
+
+        let value = ack(&handler, r#"{"action": "stats"}"#).await;
+        assert_eq!(value["success"], true);
+        let stats = value["stats"].as_array().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0]["name"], "PCS-A");
+        assert_eq!(stats[0]["address"], "10.0.0.1:502");
+        assert_eq!(stats[0]["connected"], true);
+        assert_eq!(stats[0]["paused"], false);
+        assert_eq!(stats[0]["restarts"], 2);
+        assert_eq!(
+            stats[0]["slaves"],
+            json!([{
+                "id": 1,
+                "name": "meter",
+                "cycles": 5,
+                "reads_ok": 4,
+                "reads_failed": 1,
+                "overruns": 0,
+                "last_error": "超时",
+                "last_success": null,
+                "requests": [],
+            }])
+        );
+        assert_eq!(stats[1]["name"], "PCS-B");
+        assert_eq!(stats[1]["paused"], true);
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn rejected_commands_go_to_the_dead_letter_topic() {
+        let broker = MockBroker::start().await;
+        let (writers, received) = fake_writers();
+        let (handler, client) = handler(&broker, "control-rejected", &writers, Vec::new());
+
+        let cases = [
+            ("{bad", "malformed_json", "命令格式错误"),
+            (r#"{"action": "pause", "devices": "PCS-A"}"#, "validation_failed", "命令格式错误"),
+            (r#"{"action": "shutdown"}"#, "validation_failed", "未知的操作 shutdown"),
+            (r#"{"action": "pause"}"#, "validation_failed", "操作 pause 需要指定 device"),
+            (r#"{"action": "resume", "device": "PCS-C"}"#, "unknown_point", "没有正在采集的网关 PCS-C"),
+        ];
+        for (payload, _, error) in cases {
+            let value = ack(&handler, payload).await;
+            assert_eq!(value["success"], false, "{}", payload);
+            assert!(value["error"].as_str().unwrap().contains(error), "{}", value);
+        }
+        let value = ack(&handler, r#"{"action": "shutdown", "request_id": 1}"#).await;
+        assert_eq!(value["request_id"], 1);
+        assert_eq!(value["action"], "shutdown");
+
+        let letters: Vec<Value> = broker
+            .wait_for_topic("ems/control-rejected/dlq", cases.len())
+            .await
+            .iter()
+            .map(|payload| serde_json::from_slice(payload).unwrap())
+            .collect();
+        for (letter, (payload, category, _)) in letters.iter().zip(cases) {
+            assert_eq!(letter["topic"], "ems/control-rejected/control");
+            assert_eq!(letter["payload"], payload);
+            assert_eq!(letter["category"], category);
+        }
+        assert!(received.lock().unwrap().is_empty());
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn excess_commands_are_rejected_without_dead_letters() {
+        let broker = MockBroker::start().await;
+        let (writers, received) = fake_writers();
+        let (mut handler, client) = handler(&broker, "control-limit", &writers, Vec::new());
+        handler.limiter = Mutex::new(TokenBucket::new(1.0, Instant::now()));
+
+        assert_eq!(ack(&handler, r#"{"action": "pause", "device": "PCS-A"}"#).await["success"], true);
+        let value = ack(&handler, r#"{"action": "resume", "device": "PCS-A"}"#).await;
+        assert_eq!(value["success"], false);
+        assert_eq!(value["error"], "控制命令过于频繁");
+        assert_eq!(received.lock().unwrap().len(), 1);
+        assert!(broker.payloads("ems/control-limit/dlq").is_empty());
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn commands_on_the_control_topic_are_acknowledged() {
+        let broker = MockBroker::start().await;
+        let (writers, received) = fake_writers();
+        let (handler, client) = handler(&broker, "control-topic", &writers, Vec::new());
+        tokio::spawn(handler.run());
+        let topic = "ems/control-topic/control";
+        broker
+            .wait_for("订阅控制主题", |received| {
+                received.iter().any(|p| matches!(p, Received::Subscribe(f) if f == topic))
+            })
+            .await;
+        broker.inject(topic, br#"{"action": "pause", "device": "PCS-A", "request_id": "x"}"#);
+        broker.wait_for_topic(&format!("{}/response", topic), 1).await;
+
+        let responses = broker.payloads(&format!("{}/response", topic));
+        let value: Value = serde_json::from_slice(&responses[0]).unwrap();
+        assert_eq!(value, json!({ "request_id": "x", "action": "pause", "device": "PCS-A", "success": true }));
+        assert_eq!(received.lock().unwrap()[0], ("PCS-A".to_string(), GatewayControl::Pause));
+        client.close().await;
+    }
+}
@@ -0,0 +1,344 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rumqttc::QoS;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tracing::warn;
+
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::mqtt::client::MqttClient;
+use crate::mqtt::payload::format_timestamp;
+use crate::mqtt::rate_limit::TokenBucket;
+
+/// 死信中保留的原始内容的最大字节数，超出部分截掉，避免死信本身也无法发出
+pub const MAX_ORIGINAL_BYTES: usize = 1024;
+
+/// 消息无法处理的原因分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterCategory {
+    /// 不是合法的 JSON
+    MalformedJson,
+    /// 找不到主题对应的设备或命令中的点位
+    UnknownPoint,
+    /// 字段缺失、类型错误、点位不可写、数值超出范围等
+    ValidationFailed,
+    /// Modbus 读写失败或超时
+    ModbusError,
+    /// 消息被拒绝，重试也无法发出（主题不合法或超出最大报文长度）
+    PublishRejected,
+}
+
+impl fmt::Display for DeadLetterCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeadLetterCategory::MalformedJson => "malformed_json",
+            DeadLetterCategory::UnknownPoint => "unknown_point",
+            DeadLetterCategory::ValidationFailed => "validation_failed",
+            DeadLetterCategory::ModbusError => "modbus_error",
+            DeadLetterCategory::PublishRejected => "publish_rejected",
+        })
+    }
+}
+
+/// 原始内容的编码方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding {
+    /// 原始内容是合法的 UTF-8，原样保留
+    Utf8,
+    /// 原始内容不是合法的 UTF-8，以 base64 编码
+    Base64,
+}
+
+/// 发布到死信主题的消息
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeadLetter {
+    /// 原始消息的主题
+    pub topic: String,
+    /// 原始消息的内容，不是合法的 UTF-8 时为 base64
+    pub payload: String,
+    /// 原始内容的编码方式
+    pub encoding: PayloadEncoding,
+    /// 原始内容超出 MAX_ORIGINAL_BYTES 被截断
+    #[serde(skip_serializing_if = "is_false")]
+    pub truncated: bool,
+    /// 原因分类
+    pub category: DeadLetterCategory,
+    /// 错误信息
+    pub error: String,
+    /// 发生时间（UTC，RFC 3339 格式）
+    pub timestamp: String,
+}
+
+impl DeadLetter {
+    /// 包装一条无法处理的消息
+    pub fn new(topic: &str, payload: &[u8], category: DeadLetterCategory, error: &str) -> Self {
+        let truncated = payload.len() > MAX_ORIGINAL_BYTES;
+        let original = &payload[..payload.len().min(MAX_ORIGINAL_BYTES)];
+        // 截断可能切开多字节字符，只去掉末尾不完整的部分
+        let text = match std::str::from_utf8(original) {
+            Ok(text) => Some(text),
+            Err(e) if truncated && e.error_len().is_none() => {
+                std::str::from_utf8(&original[..e.valid_up_to()]).ok()
+            }
+            Err(_) => None,
+        };
+        let (payload, encoding) = match text {
+            Some(text) => (text.to_string(), PayloadEncoding::Utf8),
+            None => (BASE64.encode(original), PayloadEncoding::Base64),
+        };
+        DeadLetter {
+            topic: topic.to_string(),
+            payload,
+            encoding,
+            truncated,
+            category,
+            error: error.to_string(),
+            timestamp: format_timestamp(SystemTime::now()),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// 把无法处理的消息发布到死信主题
+///
+/// 死信以 QoS 1、不保留的方式发布。发布速度受 `dead_letter.max_per_second` 限制，
+/// 大量错误消息涌入时超出的部分只输出日志并计数，不会压垮 Broker。
+pub struct DeadLetters {
+    client: Arc<MqttClient>,
+    topic: String,
+    limiter: Mutex<TokenBucket>,
+    published: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// 可在发布器和命令处理器间共享的死信发布器
+pub type SharedDeadLetters = Arc<DeadLetters>;
+
+impl DeadLetters {
+    /// 根据 MQTT 配置创建，未配置 dead_letter 时返回 None
+    pub fn new(client: Arc<MqttClient>, settings: &MqttSettings) -> Option<SharedDeadLetters> {
+        let dead_letter = settings.dead_letter.as_ref()?;
+        let topic = settings.dead_letter_topic()?;
+        Some(Arc::new(DeadLetters {
+            client,
+            topic,
+            limiter: Mutex::new(TokenBucket::new(dead_letter.max_per_second, Instant::now())),
+            published: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }))
+    }
+
+    /// 死信主题
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// 发布一条死信，超出限速或发布失败时丢弃
+    ///
+    /// # 参数说明
+    /// * `topic` - 原始消息的主题
+    /// * `payload` - 原始消息的内容
+    /// * `category` - 原因分类
+    /// * `error` - 错误信息
+    pub fn report(&self, topic: &str, payload: &[u8], category: DeadLetterCategory, error: &str) {
+        let allowed = self
+            .limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .try_take(Instant::now());
+        if !allowed {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let letter = DeadLetter::new(topic, payload, category, error);
+        // 只含字符串的结构序列化不会失败
+        let payload = serde_json::to_vec(&letter).unwrap_or_default();
+        match self
+            .client
+            .try_publish(&self.topic, QoS::AtLeastOnce, false, payload)
+        {
+            Ok(()) => {
+                self.published.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                warn!("发布死信 {} 失败: {}", self.topic, e);
+            }
+        }
+    }
+
+    /// 已发布的死信数
+    pub fn published(&self) -> u64 {
+        self.published.load(Ordering::Relaxed)
+    }
+
+    /// 超出限速或发布失败被丢弃的死信数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::device_configuration::mqtt::DeadLetterTopic;
+    use crate::latest::ReadingCache;
+    use crate::modbus::scheduler::PollEvent;
+    use crate::mqtt::command::CommandHandler;
+    use crate::mqtt::publisher::Publisher;
+    use crate::reload::GatewayTasks;
+    use crate::test_support::{reading, MockBroker, MockModbus};
+    use serde_json::{json, Value};
+
+    fn settings(broker: &MockBroker, client_id: &str, max_per_second: f64) -> MqttSettings {
+        let mut settings = MqttSettings::new("127.0.0.1", client_id);
+        settings.broker_port = broker.port;
+        settings.dead_letter = Some(DeadLetterTopic { topic: None, max_per_second });
+        settings
+    }
+
+    // 死信主题上收到的消息
+    async fn letters(broker: &MockBroker, topic: &str, count: usize) -> Vec<Value> {
+        broker
+            .wait_for_topic(topic, count)
+            .await
+            .iter()
+            .map(|payload| serde_json::from_slice(payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn original_payload_is_kept_as_text_or_base64() {
+        let letter = DeadLetter::new("ems/a/cmd", b"{bad", DeadLetterCategory::MalformedJson, "格式错误");
+        let value = serde_json::to_value(&letter).unwrap();
+        assert_eq!(value["topic"], "ems/a/cmd");
+        assert_eq!(value["payload"], "{bad");
+        assert_eq!(value["encoding"], "utf8");
+        assert_eq!(value["category"], "malformed_json");
+        assert_eq!(value["error"], "格式错误");
+        // 未截断时不输出 truncated
+        assert!(value.get("truncated").is_none());
+
+        let letter = DeadLetter::new("t", &[0xff, 0x00, 0x41], DeadLetterCategory::ValidationFailed, "");
+        assert_eq!(letter.encoding, PayloadEncoding::Base64);
+        assert_eq!(letter.payload, "/wBB");
+    }
+
+    #[test]
+    fn truncation_drops_partial_trailing_character() {
+        let mut payload = "a".repeat(MAX_ORIGINAL_BYTES - 1).into_bytes();
+        payload.extend_from_slice("中文".as_bytes());
+        let letter = DeadLetter::new("t", &payload, DeadLetterCategory::PublishRejected, "");
+        assert!(letter.truncated);
+        assert_eq!(letter.encoding, PayloadEncoding::Utf8);
+        assert_eq!(letter.payload, "a".repeat(MAX_ORIGINAL_BYTES - 1));
+
+        // 截断处之前就有非法字节时整体改用 base64
+        let mut payload = vec![0xff; 10];
+        payload.extend(vec![b'a'; MAX_ORIGINAL_BYTES]);
+        let letter = DeadLetter::new("t", &payload, DeadLetterCategory::PublishRejected, "");
+        assert!(letter.truncated);
+        assert_eq!(letter.encoding, PayloadEncoding::Base64);
+        assert_eq!(BASE64.decode(&letter.payload).unwrap().len(), MAX_ORIGINAL_BYTES);
+    }
+
+    #[tokio::test]
+    async fn rejected_commands_are_reported_by_category() {
+        let modbus = MockModbus::start().await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    request_timeout_ms: 200\n    poll_interval_ms: 60000\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: power, address: 0 }}\n      - {{ name: setpoint, address: 10, writable: true }}\n",
+            modbus.port
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config);
+
+        let broker = MockBroker::start().await;
+        let settings = settings(&broker, "dlq-test", 100.0);
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let dead_letters = DeadLetters::new(Arc::clone(&client), &settings).unwrap();
+        assert_eq!(dead_letters.topic(), "ems/dlq-test/dlq");
+        let handler =
+            CommandHandler::new(Arc::clone(&client), &settings, tasks.writers(), Some(Arc::clone(&dead_letters)))
+                .unwrap();
+
+        let topic = "ems/PCS-A/meter/cmd";
+        let cases: [(&str, &str, &str); 6] = [
+            (topic, "{bad", "malformed_json"),
+            (topic, r#"{"point":"setpoint"}"#, "validation_failed"),
+            (topic, r#"{"point":"missing","value":1}"#, "unknown_point"),
+            ("ems/PCS-B/meter/cmd", r#"{"point":"setpoint","value":1}"#, "unknown_point"),
+            (topic, r#"{"point":"power","value":1}"#, "validation_failed"),
+            (topic, r#"{"point":"setpoint","value":1}"#, "modbus_error"),
+        ];
+        for (i, (topic, payload, _)) in cases.iter().enumerate() {
+            if i == cases.len() - 1 {
+                modbus.reject(1, 0x04);
+            }
+            assert!(!handler.execute(topic, payload.as_bytes()).await.success, "{}", payload);
+        }
+        // 执行成功的命令不是死信
+        modbus.restore(1);
+        assert!(handler.execute(topic, br#"{"point":"setpoint","value":2}"#).await.success);
+
+        let letters = letters(&broker, "ems/dlq-test/dlq", cases.len()).await;
+        assert_eq!(letters.len(), cases.len());
+        for (letter, (topic, payload, category)) in letters.iter().zip(cases) {
+            assert_eq!(letter["topic"], topic);
+            assert_eq!(letter["payload"], payload);
+            assert_eq!(letter["category"], category, "{}", letter);
+            assert!(!letter["error"].as_str().unwrap().is_empty());
+            assert!(!letter["timestamp"].as_str().unwrap().is_empty());
+        }
+        assert_eq!(dead_letters.published(), cases.len() as u64);
+        assert!(broker.publishes().iter().filter(|p| p.topic == "ems/dlq-test/dlq").all(|p| !p.retain));
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn oversized_data_messages_are_reported_as_publish_rejected() {
+        let broker = MockBroker::start().await;
+        let settings = settings(&broker, "dlq-publish-test", 100.0);
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let dead_letters = DeadLetters::new(Arc::clone(&client), &settings);
+        let publisher =
+            Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, dead_letters, None).unwrap();
+
+        let mut big = reading("10.0.0.1:502", 1, "serial", 0.0);
+        big.text = Some("x".repeat(1024 * 1024));
+        publisher.publish(&[big]);
+
+        let letters = letters(&broker, "ems/dlq-publish-test/dlq", 1).await;
+        assert_eq!(letters[0]["topic"], "ems/10.0.0.1:502/1");
+        assert_eq!(letters[0]["category"], "publish_rejected");
+        assert_eq!(letters[0]["truncated"], true);
+        assert_eq!(letters[0]["payload"].as_str().unwrap().len(), MAX_ORIGINAL_BYTES);
+        assert!(broker.payloads("ems/10.0.0.1:502/1").is_empty());
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn letters_over_the_rate_are_dropped_and_counted() {
+        let broker = MockBroker::start().await;
+        let settings = settings(&broker, "dlq-rate-test", 0.001);
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let dead_letters = DeadLetters::new(Arc::clone(&client), &settings).unwrap();
+        for payload in [b"a", b"b", b"c"] {
+            dead_letters.report("ems/x/cmd", payload, DeadLetterCategory::MalformedJson, "格式错误");
+        }
+        assert_eq!((dead_letters.published(), dead_letters.dropped()), (1, 2));
+        let letters = letters(&broker, "ems/dlq-rate-test/dlq", 1).await;
+        assert_eq!(letters[0]["payload"], json!("a"));
+        client.close().await;
+    }
+}
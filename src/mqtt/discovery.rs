@@ -0,0 +1,598 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::device_configuration::modbus::{Config, ModbusDevice};
+use crate::device_configuration::mqtt::{Aggregation, HomeAssistant, MqttSettings};
+use crate::device_configuration::point::Point;
+use crate::device_configuration::slave::SlaveConfig;
+use crate::modbus::availability::Availability;
+use crate::mqtt::client::MqttClient;
+use crate::mqtt::payload::PayloadFormat;
+use crate::mqtt::topic::{TopicTemplate, TopicValues};
+
+/// 点位在 Home Assistant 中的实体类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// 只读点位
+    Sensor,
+    /// 可写的保持寄存器
+    Number,
+    /// 可写的线圈
+    Switch,
+    /// 寄存器中单独一位的位域点位
+    BinarySensor,
+}
+
+impl Component {
+    /// 点位对应的实体类型，字符串点位总是 sensor
+    pub fn of(point: &Point) -> Component {
+        match (point.writable, point.function_code) {
+            _ if point.data_type.is_string() => Component::Sensor,
+            _ if point.bit.is_some_and(|bit| bit.is_single()) => Component::BinarySensor,
+            (true, 0x01) => Component::Switch,
+            (true, _) => Component::Number,
+            _ => Component::Sensor,
+        }
+    }
+
+    /// 发现主题中的实体类型名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Component::Sensor => "sensor",
+            Component::Number => "number",
+            Component::Switch => "switch",
+            Component::BinarySensor => "binary_sensor",
+        }
+    }
+}
+
+/// 一个实体的发现消息内容
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntityConfig {
+    /// 实体名称
+    pub name: String,
+    /// 全局唯一的实体ID
+    pub unique_id: String,
+    /// Home Assistant 生成 entity_id 使用的ID
+    pub object_id: String,
+    /// 状态主题（数据主题）
+    pub state_topic: String,
+    /// 从消息中取出点位值的模板
+    pub value_template: String,
+    /// 从站的可用性主题
+    pub availability_topic: String,
+    /// 可用性主题中 disabled 也按不可用处理
+    pub availability_template: String,
+    /// 单位
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_of_measurement: Option<String>,
+    /// 设备类别
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    /// 状态类别，用于长期统计
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_class: Option<&'static str>,
+    /// 命令主题，可写点位才有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_topic: Option<String>,
+    /// 命令消息模板
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_template: Option<String>,
+    /// 开关打开时发送的值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_on: Option<String>,
+    /// 开关关闭时发送的值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_off: Option<String>,
+    /// 表示打开的状态值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_on: Option<&'static str>,
+    /// 表示关闭的状态值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_off: Option<&'static str>,
+    /// 数值实体的最小值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// 数值实体的最大值
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// 数值实体的步长
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+    /// 数值实体的输入方式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<&'static str>,
+    /// 所属设备
+    pub device: DeviceInfo,
+}
+
+/// 发现消息中的设备信息，同一从站的实体共用，Home Assistant 据此把实体归到同一设备下
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    /// 设备标识
+    pub identifiers: Vec<String>,
+    /// 设备名称
+    pub name: String,
+    /// 设备型号（点位表名称）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// 根据配置生成的发现消息
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Discovered {
+    /// 发现主题到消息内容
+    pub messages: BTreeMap<String, Vec<u8>>,
+    /// 消息格式无法在 Home Assistant 中解析而跳过的点位，例如 "PCS-A/1/p_total（influx_line）"
+    pub skipped: Vec<String>,
+}
+
+// 生成发现消息需要的主题模板和前缀
+struct Topics<'a> {
+    settings: &'a MqttSettings,
+    data: TopicTemplate,
+    availability: TopicTemplate,
+    command: TopicTemplate,
+    aggregation: Aggregation,
+}
+
+/// 为配置中所有正在采集的点位生成发现消息
+///
+/// # 说明
+/// * 只包括已启用网关、已启用从站下已启用的点位
+/// * 发现主题为 `<discovery_prefix>/<实体类型>/<client_id>/<网关>_<从站>_<点位>/config`，
+///   其中不能用于主题的字符（包括中文）会被替换
+/// * 只读点位为 sensor，可写的线圈为 switch，可写的保持寄存器为 number，
+///   寄存器中单独一位的位域点位为 binary_sensor
+/// * json 格式的消息都可以解析；key_value 只支持 per_point，其余格式的点位跳过
+pub fn discovery_messages(
+    config: &Config,
+    settings: &MqttSettings,
+    home_assistant: &HomeAssistant,
+) -> Result<Discovered, Box<dyn Error>> {
+    let topics = Topics {
+        settings,
+        data: settings.data_topic()?,
+        availability: settings.availability_topic()?,
+        command: settings.command_topic()?,
+        aggregation: settings.effective_aggregation()?,
+    };
+    let node_id = object_id(&settings.client_id);
+
+    let mut discovered = Discovered::default();
+    for gateway in config.gateways.iter().filter(|g| g.enabled && g.has_points()) {
+        for slave in gateway.enabled_slaves() {
+            for point in config.published_points(gateway, slave)? {
+                let format = config.publish_options(&point).format;
+                let Some(entity) = entity_config(&topics, gateway, slave, &point, format) else {
+                    discovered.skipped.push(format!(
+                        "{}/{}/{}（{}）",
+                        gateway.display_name(),
+                        slave.display_name(),
+                        point.name,
+                        format
+                    ));
+                    continue;
+                };
+                let topic = format!(
+                    "{}/{}/{}/{}/config",
+                    home_assistant.discovery_prefix,
+                    Component::of(&point).as_str(),
+                    node_id,
+                    entity.object_id
+                );
+                // 只含字符串和数字的结构序列化不会失败
+                let payload = serde_json::to_vec(&entity).unwrap_or_default();
+                discovered.messages.insert(topic, payload);
+            }
+        }
+    }
+    Ok(discovered)
+}
+
+// 生成一个点位的实体配置，消息格式无法在 Home Assistant 中解析时返回 None
+fn entity_config(
+    topics: &Topics,
+    gateway: &ModbusDevice,
+    slave: &SlaveConfig,
+    point: &Point,
+    format: PayloadFormat,
+) -> Option<EntityConfig> {
+    let settings = topics.settings;
+    let value = value_expression(topics.aggregation, format, slave, point)?;
+    let gateway_name = gateway.display_name();
+    let slave_name = slave.display_name();
+    let values = TopicValues {
+        prefix: &settings.topic_prefix,
+        site: settings.site.as_deref(),
+        gateway: &gateway_name,
+        gateway_host: &gateway.ip,
+        slave: &slave_name,
+        point: Some(&point.name),
+        group: point.group.as_deref(),
+    };
+    let device_values = TopicValues {
+        point: None,
+        group: None,
+        ..values.clone()
+    };
+
+    let node_id = object_id(&settings.client_id);
+    let device_id = object_id(&format!("{}_{}_{}", settings.client_id, gateway_name, slave_name));
+    let entity_id = object_id(&format!("{}_{}_{}", gateway_name, slave_name, point.name));
+    let component = Component::of(point);
+    let device_class = point.device_class.clone().or_else(|| match component {
+        Component::Switch | Component::BinarySensor => None,
+        _ => point.unit.as_deref().and_then(infer_device_class).map(str::to_string),
+    });
+    let mut entity = EntityConfig {
+        name: point.name.clone(),
+        unique_id: format!("{}_{}", node_id, entity_id),
+        object_id: entity_id,
+        state_topic: topics.data.expand(&values),
+        value_template: format!("{{{{ {} }}}}", value),
+        availability_topic: topics.availability.expand(&device_values),
+        availability_template: format!(
+            "{{{{ 'online' if value == '{}' else 'offline' }}}}",
+            Availability::Online
+        ),
+        unit_of_measurement: point.unit.clone(),
+        device_class: None,
+        state_class: None,
+        command_topic: None,
+        command_template: None,
+        payload_on: None,
+        payload_off: None,
+        state_on: None,
+        state_off: None,
+        min: None,
+        max: None,
+        step: None,
+        mode: None,
+        device: DeviceInfo {
+            identifiers: vec![device_id],
+            name: format!("{} {}", gateway_name, slave_name),
+            model: slave.profile.clone().or_else(|| gateway.profile.clone()),
+        },
+    };
+
+    let name = json_string(&point.name);
+    match component {
+        // 字符串不是测量值，Home Assistant 不允许设置 state_class
+        Component::Sensor if point.data_type.is_string() => {}
+        Component::Sensor => {
+            entity.state_class = Some(match device_class.as_deref() {
+                Some("energy") => "total_increasing",
+                _ => "measurement",
+            });
+        }
+        Component::Number => {
+            let (raw_min, raw_max) = point.data_type.range();
+            let a = point.convert(raw_min * point.scale + point.offset);
+            let b = point.convert(raw_max * point.scale + point.offset);
+            entity.command_topic = Some(topics.command.expand(&device_values));
+            entity.command_template = Some(format!("{{\"point\": {}, \"value\": {{{{ value }}}}}}", name));
+            entity.min = Some(a.min(b));
+            entity.max = Some(a.max(b));
+            entity.step = Some(if point.data_type.is_float() {
+                MIN_STEP
+            } else {
+                (point.scale * point.conversion.map_or(1.0, |c| c.ratio())).abs().max(MIN_STEP)
+            });
+            entity.mode = Some("box");
+        }
+        Component::Switch => {
+            entity.value_template = format!("{{{{ 'ON' if ({}) | float(0) != 0 else 'OFF' }}}}", value);
+            entity.command_topic = Some(topics.command.expand(&device_values));
+            entity.payload_on = Some(format!("{{\"point\": {}, \"value\": true}}", name));
+            entity.payload_off = Some(format!("{{\"point\": {}, \"value\": false}}", name));
+            entity.state_on = Some("ON");
+            entity.state_off = Some("OFF");
+        }
+        // binary_sensor 默认以 ON/OFF 判断状态
+        Component::BinarySensor => {
+            entity.value_template = format!("{{{{ 'ON' if ({}) | float(0) != 0 else 'OFF' }}}}", value);
+        }
+    }
+    entity.device_class = device_class;
+    Some(entity)
+}
+
+/// Home Assistant 中 number 实体允许的最小步长
+const MIN_STEP: f64 = 0.001;
+
+// 从消息中取出点位值的模板表达式，不含外层的 {{ }}
+fn value_expression(
+    aggregation: Aggregation,
+    format: PayloadFormat,
+    slave: &SlaveConfig,
+    point: &Point,
+) -> Option<String> {
+    let name = json_string(&point.name);
+    match (format, aggregation) {
+        (PayloadFormat::Json, Aggregation::Cycle) => Some(format!(
+            "value_json['slaves'][{}][{}]",
+            json_string(&slave.display_name()),
+            name
+        )),
+        (PayloadFormat::Json, _) => Some(format!("value_json['values'][{}]", name)),
+        // 键中的等号会被转义，值在最后一个等号之后
+        (PayloadFormat::KeyValue, Aggregation::Point) => Some("value.rsplit('=', 1)[1]".to_string()),
+        _ => None,
+    }
+}
+
+// 模板中的字符串字面量，Jinja 的双引号字符串与 JSON 的转义规则兼容
+fn json_string(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// 按单位推断 device_class，无法推断时返回 None
+pub fn infer_device_class(unit: &str) -> Option<&'static str> {
+    Some(match unit {
+        "V" | "mV" | "kV" => "voltage",
+        "A" | "mA" => "current",
+        "W" | "kW" | "MW" => "power",
+        "Wh" | "kWh" | "MWh" => "energy",
+        "var" | "kvar" => "reactive_power",
+        "VA" | "kVA" => "apparent_power",
+        "Hz" => "frequency",
+        "°C" | "℃" | "°F" | "℉" | "K" => "temperature",
+        _ => return None,
+    })
+}
+
+/// 把名称转换为发现主题和 object_id 可以使用的字符
+///
+/// 字母、数字、`_` 和 `-` 保留，其他 ASCII 字符替换为 `_`，非 ASCII 字符替换为 `u` 加十六进制码点，
+/// 使中文名称的点位也能得到不重复的 ID。
+pub fn object_id(name: &str) -> String {
+    let mut id = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '-' => id.push(c),
+            c if c.is_ascii() => id.push('_'),
+            c => id.push_str(&format!("u{:x}", c as u32)),
+        }
+    }
+    id
+}
+
+/// 发布 Home Assistant 自动发现消息
+///
+/// 每次 `apply` 时与上次发布的内容比较：新增或变化的实体发布保留消息，
+/// 不再存在的实体（点位被删除或停用、改变了实体类型）发布空的保留消息，Home Assistant 随即删除该实体。
+/// 程序停止期间删除的点位无法得知，不会被清除。
+pub struct Discovery {
+    client: Arc<MqttClient>,
+    settings: MqttSettings,
+    home_assistant: HomeAssistant,
+    published: BTreeMap<String, Vec<u8>>,
+}
+
+impl Discovery {
+    /// 创建发现消息发布器
+    ///
+    /// # 返回值
+    /// * `None` - MQTT 配置中没有 home_assistant 段
+    pub fn new(client: Arc<MqttClient>, settings: &MqttSettings) -> Option<Discovery> {
+        Some(Discovery {
+            client,
+            home_assistant: settings.home_assistant.clone()?,
+            settings: settings.clone(),
+            published: BTreeMap::new(),
+        })
+    }
+
+    /// 按配置发布发现消息并清除已删除的实体；MQTT 配置使用创建时的配置
+    pub fn apply(&mut self, config: &Config) {
+        let discovered = match discovery_messages(config, &self.settings, &self.home_assistant) {
+            Ok(discovered) => discovered,
+            Err(e) => {
+                warn!("生成 Home Assistant 发现消息失败: {}", e);
+                return;
+            }
+        };
+        for point in &discovered.skipped {
+            warn!("点位 {} 的消息格式不支持 Home Assistant 自动发现，已跳过", point);
+        }
+
+        let mut changed = 0;
+        for (topic, payload) in &discovered.messages {
+            if self.published.get(topic) == Some(payload) {
+                continue;
+            }
+            changed += 1;
+            if let Err(e) = self.client.publish_state(topic, payload) {
+                warn!("发布 Home Assistant 发现消息 {} 失败: {}", topic, e);
+            }
+        }
+        let mut removed = 0;
+        for topic in self.published.keys().filter(|t| !discovered.messages.contains_key(*t)) {
+            removed += 1;
+            if let Err(e) = self.client.clear_state(topic) {
+                warn!("清除 Home Assistant 发现消息 {} 失败: {}", topic, e);
+            }
+        }
+        if changed > 0 || removed > 0 {
+            info!(
+                "已发布 {} 个 Home Assistant 实体，清除 {} 个",
+                changed, removed
+            );
+        }
+        self.published = discovered.messages;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, MockBroker};
+    use serde_json::{json, Value};
+
+    const CONFIG: &str = "version: 2\nmqtt:\n  broker_host: 127.0.0.1\n  client_id: ems-1\n  home_assistant: {}\ngateways:\n  - ip: 10.0.0.1\n    name: PCS-A\n    slave_ids: [{ id: 1, name: meter }]\n    points:\n      - { name: power, address: 0, data_type: i16, scale: 0.1, unit: kW }\n      - { name: setpoint, address: 10, data_type: i16, scale: 0.1, unit: kW, writable: true }\n      - { name: relay, function_code: 1, address: 0, data_type: bool, writable: true }\n";
+
+    fn discovered(config: &Config) -> BTreeMap<String, Value> {
+        let settings = config.mqtt.as_ref().unwrap();
+        discovery_messages(config, settings, settings.home_assistant.as_ref().unwrap())
+            .unwrap()
+            .messages
+            .into_iter()
+            .map(|(topic, payload)| (topic, serde_json::from_slice(&payload).unwrap()))
+            .collect()
+    }
+
+    fn device() -> Value {
+        json!({ "identifiers": ["ems-1_PCS-A_meter"], "name": "PCS-A meter" })
+    }
+
+    #[test]
+    fn discovery_json_for_each_component() {
+        let messages = discovered(&config(CONFIG));
+        assert_eq!(
+            messages.keys().collect::<Vec<_>>(),
+            [
+                "homeassistant/number/ems-1/PCS-A_meter_setpoint/config",
+                "homeassistant/sensor/ems-1/PCS-A_meter_power/config",
+                "homeassistant/switch/ems-1/PCS-A_meter_relay/config",
+            ]
+        );
+        assert_eq!(
+            messages["homeassistant/sensor/ems-1/PCS-A_meter_power/config"],
+            json!({
+                "name": "power",
+                "unique_id": "ems-1_PCS-A_meter_power",
+                "object_id": "PCS-A_meter_power",
+                "state_topic": "ems/PCS-A/meter",
+                "value_template": "{{ value_json['values'][\"power\"] }}",
+                "availability_topic": "ems/PCS-A/meter/availability",
+                "availability_template": "{{ 'online' if value == 'online' else 'offline' }}",
+                "unit_of_measurement": "kW",
+                "device_class": "power",
+                "state_class": "measurement",
+                "device": device(),
+            })
+        );
+        assert_eq!(
+            messages["homeassistant/number/ems-1/PCS-A_meter_setpoint/config"],
+            json!({
+                "name": "setpoint",
+                "unique_id": "ems-1_PCS-A_meter_setpoint",
+                "object_id": "PCS-A_meter_setpoint",
+                "state_topic": "ems/PCS-A/meter",
+                "value_template": "{{ value_json['values'][\"setpoint\"] }}",
+                "availability_topic": "ems/PCS-A/meter/availability",
+                "availability_template": "{{ 'online' if value == 'online' else 'offline' }}",
+                "unit_of_measurement": "kW",
+                "device_class": "power",
+                "command_topic": "ems/PCS-A/meter/cmd",
+                "command_template": "{\"point\": \"setpoint\", \"value\": {{ value }}}",
+                "min": -32768.0 * 0.1,
+                "max": 32767.0 * 0.1,
+                "step": 0.1,
+                "mode": "box",
+                "device": device(),
+            })
+        );
+        assert_eq!(
+            messages["homeassistant/switch/ems-1/PCS-A_meter_relay/config"],
+            json!({
+                "name": "relay",
+                "unique_id": "ems-1_PCS-A_meter_relay",
+                "object_id": "PCS-A_meter_relay",
+                "state_topic": "ems/PCS-A/meter",
+                "value_template": "{{ 'ON' if (value_json['values'][\"relay\"]) | float(0) != 0 else 'OFF' }}",
+                "availability_topic": "ems/PCS-A/meter/availability",
+                "availability_template": "{{ 'online' if value == 'online' else 'offline' }}",
+                "command_topic": "ems/PCS-A/meter/cmd",
+                "payload_on": "{\"point\": \"relay\", \"value\": true}",
+                "payload_off": "{\"point\": \"relay\", \"value\": false}",
+                "state_on": "ON",
+                "state_off": "OFF",
+                "device": device(),
+            })
+        );
+    }
+
+    #[test]
+    fn per_point_key_value_and_skipped_formats() {
+        let yaml = CONFIG.replace(
+            "  home_assistant: {}\n",
+            "  home_assistant: { discovery_prefix: ha }\n  aggregation: per_point\n  payload_format: key_value\n",
+        );
+        let messages = discovered(&config(&yaml));
+        let power = &messages["ha/sensor/ems-1/PCS-A_meter_power/config"];
+        assert_eq!(power["state_topic"], "ems/PCS-A/meter/power");
+        assert_eq!(power["value_template"], "{{ value.rsplit('=', 1)[1] }}");
+
+        let yaml = CONFIG.replace("  home_assistant: {}\n", "  home_assistant: {}\n  payload_format: influx_line\n");
+        let config = config(&yaml);
+        let settings = config.mqtt.as_ref().unwrap();
+        let discovered =
+            discovery_messages(&config, settings, settings.home_assistant.as_ref().unwrap()).unwrap();
+        assert!(discovered.messages.is_empty());
+        assert_eq!(discovered.skipped[0], "PCS-A/meter/power（influx_line）");
+    }
+
+    #[test]
+    fn bit_points_are_binary_sensors_and_ranges_are_sensors() {
+        let yaml = CONFIG.replace(
+            "      - { name: power,",
+            "      - { name: status, address: 20, bits: { contactor_closed: 3, run_mode: \"4-6\" } }\n      - { name: power,",
+        );
+        let messages = discovered(&config(&yaml));
+        assert_eq!(
+            messages["homeassistant/binary_sensor/ems-1/PCS-A_meter_contactor_closed/config"],
+            json!({
+                "name": "contactor_closed",
+                "unique_id": "ems-1_PCS-A_meter_contactor_closed",
+                "object_id": "PCS-A_meter_contactor_closed",
+                "state_topic": "ems/PCS-A/meter",
+                "value_template": "{{ 'ON' if (value_json['values'][\"contactor_closed\"]) | float(0) != 0 else 'OFF' }}",
+                "availability_topic": "ems/PCS-A/meter/availability",
+                "availability_template": "{{ 'online' if value == 'online' else 'offline' }}",
+                "device": device(),
+            })
+        );
+        // 多位的位域是数值，与所属的状态字一样是 sensor
+        let run_mode = &messages["homeassistant/sensor/ems-1/PCS-A_meter_run_mode/config"];
+        assert_eq!(run_mode["value_template"], "{{ value_json['values'][\"run_mode\"] }}");
+        assert!(messages.contains_key("homeassistant/sensor/ems-1/PCS-A_meter_status/config"));
+        assert_eq!(messages.keys().filter(|topic| topic.contains("binary_sensor")).count(), 1);
+    }
+
+    #[test]
+    fn names_are_made_topic_safe() {
+        assert_eq!(object_id("PCS-A meter/1"), "PCS-A_meter_1");
+        assert_eq!(object_id("电表"), "u7535u8868");
+        assert_eq!(infer_device_class("kWh"), Some("energy"));
+        assert_eq!(infer_device_class("%"), None);
+    }
+
+    #[tokio::test]
+    async fn removed_points_are_cleared_with_empty_retained_message() {
+        let broker = MockBroker::start().await;
+        let mut config = config(CONFIG);
+        let mut settings = config.mqtt.clone().unwrap();
+        settings.broker_port = broker.port;
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let mut discovery = Discovery::new(Arc::clone(&client), &settings).unwrap();
+
+        discovery.apply(&config);
+        let relay = "homeassistant/switch/ems-1/PCS-A_meter_relay/config";
+        assert_eq!(broker.wait_for_topic(relay, 1).await.len(), 1);
+
+        config.gateways[0].points.retain(|point| point.name != "relay");
+        discovery.apply(&config);
+        let payloads = broker.wait_for_topic(relay, 2).await;
+        assert!(payloads[1].is_empty());
+        let cleared = broker.publishes().into_iter().filter(|p| p.topic == relay).last().unwrap();
+        assert!(cleared.retain);
+        // 没有变化的实体不重新发布
+        assert_eq!(broker.payloads("homeassistant/sensor/ems-1/PCS-A_meter_power/config").len(), 1);
+        client.close().await;
+    }
+}
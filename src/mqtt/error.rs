@@ -0,0 +1,165 @@
+use rumqttc::{ClientError, ConnectionError};
+use std::error::Error;
+use std::fmt;
+
+/// MQTT 客户端的错误
+///
+/// 发布失败时调用方通常只需要区分 `Disconnected`（等待重连）和 `QueueFull`（发送过快）。
+#[derive(Debug)]
+pub enum MqttError {
+    /// 未连接到 Broker 或客户端已关闭，消息无法发出
+    Disconnected,
+    /// 已连接，但发送队列已满
+    QueueFull,
+    /// 主题不合法，例如为空或包含通配符
+    InvalidTopic(String),
+    /// 消息超出允许的最大报文长度，发出后会导致连接断开，因此直接拒绝
+    PayloadTooLarge {
+        /// 报文长度
+        size: usize,
+        /// 允许的最大报文长度
+        max: usize,
+    },
+    /// 发送请求到事件循环失败
+    Client(Box<ClientError>),
+    /// 与 Broker 的连接出错
+    Connection(Box<ConnectionError>),
+}
+
+impl fmt::Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MqttError::Disconnected => write!(f, "未连接到 MQTT Broker"),
+            MqttError::QueueFull => write!(f, "MQTT 发送队列已满"),
+            MqttError::InvalidTopic(topic) => write!(f, "MQTT 主题 \"{}\" 不合法", topic),
+            MqttError::PayloadTooLarge { size, max } => {
+                write!(f, "MQTT 报文长度 {} 字节超出上限 {} 字节", size, max)
+            }
+            MqttError::Client(e) => write!(f, "MQTT 请求失败: {}", e),
+            MqttError::Connection(e) => write!(f, "MQTT 连接出错: {}", e),
+        }
+    }
+}
+
+impl MqttError {
+    /// 是否为重试也无法发出的错误（主题不合法或消息过大）
+    pub fn is_rejected(&self) -> bool {
+        matches!(self, MqttError::InvalidTopic(_) | MqttError::PayloadTooLarge { .. })
+    }
+}
+
+impl Error for MqttError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MqttError::Client(e) => Some(e.as_ref()),
+            MqttError::Connection(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ClientError> for MqttError {
+    fn from(e: ClientError) -> Self {
+        MqttError::Client(Box::new(e))
+    }
+}
+
+impl From<ConnectionError> for MqttError {
+    fn from(e: ConnectionError) -> Self {
+        MqttError::Connection(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::mqtt::MqttSettings;
+    use crate::mqtt::client::MqttClient;
+    use crate::test_support::{MockBroker, Received};
+    use rumqttc::{PingReq, QoS, Request};
+    use std::time::Duration;
+
+    // 同步地放入消息直到失败，期间不让出执行权，事件循环无法取走队列中的消息
+    fn fill_queue(client: &MqttClient) -> MqttError {
+        for _ in 0..1000 {
+            if let Err(e) = client.try_publish("test/fill", QoS::AtLeastOnce, false, b"x".to_vec()) {
+                return e;
+            }
+        }
+        panic!("发送队列一直没有满");
+    }
+
+    #[test]
+    fn wraps_rumqttc_errors() {
+        let error = MqttError::from(ClientError::TryRequest(Request::PingReq(PingReq)));
+        assert!(matches!(error, MqttError::Client(_)));
+        assert!(error.to_string().starts_with("MQTT 请求失败: "));
+        assert!(error.source().is_some());
+
+        let error = MqttError::from(ConnectionError::RequestsDone);
+        assert!(matches!(error, MqttError::Connection(_)));
+        assert!(error.to_string().starts_with("MQTT 连接出错: "));
+        assert!(error.source().is_some());
+
+        assert!(MqttError::InvalidTopic("a/#".to_string()).is_rejected());
+        assert!(MqttError::PayloadTooLarge { size: 2, max: 1 }.is_rejected());
+        assert!(!MqttError::Disconnected.is_rejected());
+        assert!(!MqttError::QueueFull.is_rejected());
+        assert!(MqttError::QueueFull.source().is_none());
+    }
+
+    #[tokio::test]
+    async fn constructors_pass_keep_alive_and_clean_session() {
+        let broker = MockBroker::start().await;
+        let client = MqttClient::new("keep-alive-test", "127.0.0.1", broker.port, Duration::from_secs(7));
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        client.close().await;
+
+        let mut settings = MqttSettings::new("127.0.0.1", "settings-test");
+        settings.broker_port = broker.port;
+        settings.keep_alive_secs = 12;
+        settings.clean_session = false;
+        let client = MqttClient::from_settings(&settings, None).unwrap();
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        client.close().await;
+
+        let connects: Vec<(String, u16, bool)> = broker
+            .connects()
+            .into_iter()
+            .map(|c| (c.client_id, c.keep_alive, c.clean_session))
+            .collect();
+        assert_eq!(
+            connects,
+            [
+                ("keep-alive-test".to_string(), 7, true),
+                ("settings-test".to_string(), 12, false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn full_queue_maps_to_disconnected_or_queue_full() {
+        // 未连接时队列满了说明消息发不出去
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = listener.local_addr().unwrap().port();
+        drop(listener);
+        let client = MqttClient::new("offline-test", "127.0.0.1", closed, Duration::from_secs(5));
+        assert!(matches!(fill_queue(&client), MqttError::Disconnected));
+        client.close().await;
+        assert!(matches!(
+            client.try_publish("test/fill", QoS::AtLeastOnce, false, b"x".to_vec()),
+            Err(MqttError::Disconnected)
+        ));
+
+        // 已连接时队列满了是发送过快
+        let broker = MockBroker::start().await;
+        let client = MqttClient::new("busy-test", "127.0.0.1", broker.port, Duration::from_secs(5));
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        assert!(matches!(fill_queue(&client), MqttError::QueueFull));
+        // 队列已满时关闭也会发出 DISCONNECT
+        client.close().await;
+        broker
+            .wait_for("DISCONNECT", |received| received.iter().any(|p| matches!(p, Received::Disconnect)))
+            .await;
+    }
+}
@@ -1 +1,32 @@
+/// 按 aggregation 把采集数据合并为消息
+pub mod batch;
+/// 按死区过滤没有变化的点位
+pub mod change_filter;
+/// 带自动重连的异步 MQTT 客户端
 pub mod client;
+/// 通过命令主题远程写入点位
+pub mod command;
+/// 消息的 gzip 压缩
+pub mod compression;
+/// 通过 MQTT 控制命令重新加载配置、暂停和恢复采集
+pub mod control;
+/// 无法处理的消息发布到死信主题
+pub mod dead_letter;
+/// Home Assistant 自动发现
+pub mod discovery;
+/// MQTT 错误类型
+pub mod error;
+/// 数据消息的格式
+pub mod payload;
+/// 采集数据和设备可用性的发布
+pub mod publisher;
+/// 数据消息的发布限速
+pub mod rate_limit;
+/// 通过 MQTT 临时读取寄存器
+pub mod read_request;
+/// Sparkplug B 输出模式
+pub mod sparkplug;
+/// MQTT 的 TLS 连接配置
+pub mod tls;
+/// 主题模板
+pub mod topic;
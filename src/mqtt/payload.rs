@@ -0,0 +1,571 @@
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::modbus::reading::{PointValue, Quality, Reading};
+
+/// InfluxDB 行协议使用的 measurement
+pub const INFLUX_MEASUREMENT: &str = "modbus";
+/// 行协议中程序自己使用的 tag，mqtt.tags 不能使用这些名称
+pub const RESERVED_TAGS: [&str; 6] = ["gateway", "slave", "group", "unit", "site", "node"];
+
+/// 采集数据消息的格式
+///
+/// # 说明
+/// * `json`：JSON 对象，NaN 和无穷大输出为 null，字符串点位输出为字符串；质量不是 good 的点位在 `quality` 中列出
+/// * `influx_line`：InfluxDB 行协议，measurement 为 `modbus`，网关、从站、采集组和单位为 tag，
+///   点位名称为 field，字符串点位为带引号的字符串 field；NaN 和无穷大无法表示，该点位不输出
+/// * `key_value`：每行一个 `点位名称=值`，NaN 和无穷大输出为 `NaN`、`inf`、`-inf`，字符串原样输出
+/// * `raw_registers`：未解码的寄存器值，每个寄存器按大端序输出2个字节，只能用于 per_point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// JSON
+    #[default]
+    Json,
+    /// InfluxDB 行协议
+    InfluxLine,
+    /// `点位名称=值` 文本
+    KeyValue,
+    /// 未解码的寄存器值
+    RawRegisters,
+}
+
+impl PayloadFormat {
+    /// 是否可以把多个点位合并到一条消息中
+    pub fn can_aggregate(self) -> bool {
+        self != PayloadFormat::RawRegisters
+    }
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PayloadFormat::Json => "json",
+            PayloadFormat::InfluxLine => "influx_line",
+            PayloadFormat::KeyValue => "key_value",
+            PayloadFormat::RawRegisters => "raw_registers",
+        })
+    }
+}
+
+/// 采集数据消息的公共字段
+///
+/// JSON 格式展开到消息的顶层；influx_line 只使用其中的站点、节点和标签作为 tag；
+/// key_value 和 raw_registers 只有数据本身。
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Envelope {
+    /// 采集时间（UTC，RFC 3339 格式），不是发布时间；合并多个点位时取其中最新的一个
+    pub timestamp: String,
+    /// 按全局 timezone 表示的采集时间（RFC 3339 格式，带时区偏移），配置了 mqtt.local_time 时输出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_time: Option<String>,
+    /// 消息序号，同一设备每条消息加1，断线重连不影响，程序重启后从1开始
+    pub seq: u64,
+    /// 程序本次运行的 UUID，重启后变化，接收方据此判断 seq 重新开始
+    pub session_id: String,
+    /// 站点名称（mqtt.site）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    /// 节点名称（mqtt.client_id）
+    pub node: String,
+    /// mqtt.tags 中配置的标签
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// 生成消息的公共字段，并为每个设备维护消息序号
+///
+/// 设备为一个从站；per_cycle 的消息包含一个网关的所有从站，以网关为设备单独计数。
+/// 超出 max_payload_bytes 拆分的每条消息各占一个序号。
+#[derive(Debug, Clone)]
+pub struct EnvelopeBuilder {
+    session_id: String,
+    site: Option<String>,
+    node: String,
+    tags: BTreeMap<String, String>,
+    // 配置了 mqtt.local_time 时为 Some，其中为 None 表示使用系统时区
+    local_time: Option<Option<Tz>>,
+    // (网关地址，从站ID) 到上一条消息的序号，per_cycle 时从站ID为 None
+    seqs: HashMap<(String, Option<u8>), u64>,
+}
+
+impl EnvelopeBuilder {
+    /// 根据 MQTT 配置创建，生成新的 session_id
+    ///
+    /// # 参数说明
+    /// * `settings` - MQTT 配置
+    /// * `timezone` - 全局时区，为 None 时使用系统时区；只在配置了 mqtt.local_time 时使用
+    pub fn new(settings: &MqttSettings, timezone: Option<Tz>) -> Self {
+        EnvelopeBuilder {
+            session_id: Uuid::new_v4().to_string(),
+            site: settings.site.clone(),
+            node: settings.client_id.clone(),
+            tags: settings.tags.clone(),
+            local_time: settings.local_time.then_some(timezone),
+            seqs: HashMap::new(),
+        }
+    }
+
+    /// 设备下一条消息的公共字段
+    ///
+    /// # 参数说明
+    /// * `gateway` - 网关地址 ip:port
+    /// * `slave` - 从站ID，per_cycle 时为 None
+    /// * `timestamp` - 采集时间
+    pub fn next(&mut self, gateway: &str, slave: Option<u8>, timestamp: SystemTime) -> Envelope {
+        Envelope {
+            timestamp: format_timestamp(timestamp),
+            local_time: self
+                .local_time
+                .map(|tz| to_local(timestamp, tz).to_rfc3339_opts(SecondsFormat::Millis, false)),
+            seq: self.next_seq(gateway, slave),
+            session_id: self.session_id.clone(),
+            site: self.site.clone(),
+            node: self.node.clone(),
+            tags: self.tags.clone(),
+        }
+    }
+
+    /// 设备下一条消息的序号，拆分消息时使用
+    pub fn next_seq(&mut self, gateway: &str, slave: Option<u8>) -> u64 {
+        let seq = self.seqs.entry((gateway.to_string(), slave)).or_default();
+        *seq += 1;
+        *seq
+    }
+}
+
+/// 一个从站的采集数据，JSON 格式使用
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DevicePayload {
+    /// 网关名称，未配置时为 ip:port
+    pub gateway: String,
+    /// 从站ID
+    pub slave: u8,
+    /// 时间戳、序号、会话ID等公共字段
+    #[serde(flatten)]
+    pub envelope: Envelope,
+    /// 点位名称到工程值的映射
+    pub values: BTreeMap<String, PointValue>,
+    /// 质量不是 good 的点位的数据质量，都为 good 时不输出
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub quality: BTreeMap<String, Quality>,
+}
+
+/// 一次采集中一个网关所有从站的数据，JSON 格式的 per_cycle 使用
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CyclePayload {
+    /// 网关名称，未配置时为 ip:port
+    pub gateway: String,
+    /// 时间戳、序号、会话ID等公共字段
+    #[serde(flatten)]
+    pub envelope: Envelope,
+    /// 从站名称（未配置时为从站ID）到点位数据的映射
+    pub slaves: BTreeMap<String, BTreeMap<String, PointValue>>,
+    /// 质量不是 good 的点位的数据质量，按从站名称和点位名称组织，都为 good 时不输出
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub quality: BTreeMap<String, BTreeMap<String, Quality>>,
+}
+
+/// 按格式输出一个点位的采集数据
+///
+/// # 返回值
+/// * 消息内容；influx_line 遇到 NaN 或无穷大时为空，调用方应跳过
+pub fn format_reading(reading: &Reading, format: PayloadFormat, envelope: &Envelope) -> Vec<u8> {
+    match format {
+        PayloadFormat::Json => {
+            let payload = DevicePayload {
+                gateway: gateway_name(reading),
+                slave: reading.slave_id,
+                envelope: envelope.clone(),
+                values: BTreeMap::from([(reading.point.clone(), reading.point_value())]),
+                quality: quality_of([reading]),
+            };
+            // 只含字符串和数字的结构序列化不会失败
+            serde_json::to_vec(&payload).unwrap_or_default()
+        }
+        PayloadFormat::InfluxLine => influx_line(reading, envelope)
+            .map(String::into_bytes)
+            .unwrap_or_default(),
+        PayloadFormat::KeyValue => key_value(&reading.point, &reading.point_value()),
+        PayloadFormat::RawRegisters => reading.raw.iter().flat_map(|word| word.to_be_bytes()).collect(),
+    }
+}
+
+/// 质量不是 good 的点位名称到数据质量的映射
+pub fn quality_of<'a>(readings: impl IntoIterator<Item = &'a Reading>) -> BTreeMap<String, Quality> {
+    readings
+        .into_iter()
+        .filter(|r| r.quality != Quality::Good)
+        .map(|r| (r.point.clone(), r.quality))
+        .collect()
+}
+
+/// 一行 key_value 格式的数据 `键=值`，键中的等号用反斜杠转义，字符串中的换行转义为 `\n`
+pub fn key_value(key: &str, value: &PointValue) -> Vec<u8> {
+    let value = match value {
+        PointValue::Number(value) => value.to_string(),
+        PointValue::Text(text) => escape(text, &[]),
+    };
+    format!("{}={}", escape_key(key), value).into_bytes()
+}
+
+// 一行 InfluxDB 行协议：
+// modbus,gateway=<网关>,slave=<从站>[,group=<采集组>][,unit=<单位>][,site=<站点>],node=<节点>[,<标签>...] <点位>=<值> <纳秒时间戳>
+fn influx_line(reading: &Reading, envelope: &Envelope) -> Option<String> {
+    let value = match &reading.text {
+        Some(text) => format!("\"{}\"", escape_field_string(text)),
+        None if reading.value.is_finite() => reading.value.to_string(),
+        None => return None,
+    };
+    let mut line = escape_measurement(INFLUX_MEASUREMENT);
+    let tags = [
+        ("gateway", Some(gateway_name(reading))),
+        ("slave", Some(slave_name(reading))),
+        ("group", reading.group.clone()),
+        ("unit", reading.unit.clone()),
+        ("site", envelope.site.clone()),
+        ("node", Some(envelope.node.clone())),
+    ];
+    let extra = envelope.tags.iter().map(|(k, v)| (k.as_str(), Some(v.clone())));
+    for (key, value) in tags.into_iter().chain(extra) {
+        // 行协议不允许空的 tag 值
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            line.push(',');
+            line.push_str(&escape_tag(key));
+            line.push('=');
+            line.push_str(&escape_tag(&value));
+        }
+    }
+    let nanos = reading
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    line.push_str(&format!(
+        " {}={} {}",
+        escape_tag(&reading.point),
+        value,
+        nanos
+    ));
+    Some(line)
+}
+
+// measurement 中需要转义逗号和空格
+fn escape_measurement(name: &str) -> String {
+    escape(name, &[',', ' '])
+}
+
+// tag 键、tag 值和 field 键中需要转义逗号、等号和空格
+fn escape_tag(name: &str) -> String {
+    escape(name, &[',', '=', ' '])
+}
+
+// 字符串 field 值中需要转义双引号和反斜杠，换行可以原样保留
+fn escape_field_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// key_value 格式的键中需要转义等号
+fn escape_key(name: &str) -> String {
+    escape(name, &['='])
+}
+
+// 用反斜杠转义指定字符和反斜杠本身，换行无法出现在一行中，转义为 \n
+fn escape(name: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\\' => escaped.push_str("\\\\"),
+            c if special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 将时间格式化为 UTC 的 RFC 3339 字符串，精确到毫秒
+pub fn format_timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// 按时区表示的时间，时区为 None 时使用系统时区
+///
+/// 时区偏移随夏令时变化，同一 UTC 时间只对应一个本地时间
+pub fn to_local(time: SystemTime, tz: Option<Tz>) -> DateTime<FixedOffset> {
+    let utc = DateTime::<Utc>::from(time);
+    match tz {
+        Some(tz) => utc.with_timezone(&tz).fixed_offset(),
+        None => utc.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+/// 网关名称，未配置时为 ip:port
+pub fn gateway_name(reading: &Reading) -> String {
+    reading
+        .gateway_name
+        .clone()
+        .unwrap_or_else(|| reading.gateway.clone())
+}
+
+/// 从站名称，未配置时为从站ID
+pub fn slave_name(reading: &Reading) -> String {
+    reading
+        .slave_name
+        .clone()
+        .unwrap_or_else(|| reading.slave_id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latest::ReadingCache;
+    use crate::mqtt::client::MqttClient;
+    use crate::mqtt::publisher::Publisher;
+    use crate::test_support::{at, reading, MockBroker};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn envelope() -> Envelope {
+        Envelope {
+            timestamp: "2023-11-14T22:13:20.000Z".to_string(),
+            local_time: None,
+            seq: 7,
+            session_id: "session".to_string(),
+            site: Some("site one".to_string()),
+            node: "ems-1".to_string(),
+            tags: BTreeMap::from([("room".to_string(), "B 2".to_string())]),
+        }
+    }
+
+    // 名称中带有空格、逗号、等号和中文的点位
+    fn tricky(value: f64) -> Reading {
+        let mut reading = reading("10.0.0.1:502", 1, "有功 功率=total", value);
+        reading.gateway_name = Some("Gateway A,1".to_string());
+        reading.slave_name = Some("电表 1".to_string());
+        reading.unit = Some("kW".to_string());
+        reading
+    }
+
+    fn text(reading: &Reading, format: PayloadFormat) -> String {
+        String::from_utf8(format_reading(reading, format, &envelope())).unwrap()
+    }
+
+    #[test]
+    fn json_keeps_names_and_writes_null_for_nan() {
+        let payload = text(&tricky(12.5), PayloadFormat::Json);
+        // 中文原样输出，不转义为 \u
+        assert!(payload.contains("\"有功 功率=total\":12.5"), "{}", payload);
+        let value: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "gateway": "Gateway A,1",
+                "slave": 1,
+                "timestamp": "2023-11-14T22:13:20.000Z",
+                "seq": 7,
+                "session_id": "session",
+                "site": "site one",
+                "node": "ems-1",
+                "tags": { "room": "B 2" },
+                "values": { "有功 功率=total": 12.5 },
+            })
+        );
+
+        for special in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let mut reading = tricky(special);
+            reading.quality = Quality::Bad;
+            let value: Value = serde_json::from_str(&text(&reading, PayloadFormat::Json)).unwrap();
+            assert_eq!(value["values"]["有功 功率=total"], Value::Null);
+            assert_eq!(value["quality"]["有功 功率=total"], "bad");
+        }
+
+        let mut reading = tricky(0.0);
+        reading.text = Some("SN \"01\"".to_string());
+        let value: Value = serde_json::from_str(&text(&reading, PayloadFormat::Json)).unwrap();
+        assert_eq!(value["values"]["有功 功率=total"], "SN \"01\"");
+    }
+
+    #[test]
+    fn influx_line_escapes_names() {
+        assert_eq!(
+            text(&tricky(12.5), PayloadFormat::InfluxLine),
+            "modbus,gateway=Gateway\\ A\\,1,slave=电表\\ 1,unit=kW,site=site\\ one,node=ems-1,room=B\\ 2 \
+             有功\\ 功率\\=total=12.5 1700000000000000000"
+        );
+
+        let mut reading = reading("10.0.0.1:502", 2, "serial", 0.0);
+        reading.group = Some("slow".to_string());
+        reading.text = Some("say \"hi\" \\ ok".to_string());
+        assert_eq!(
+            text(&reading, PayloadFormat::InfluxLine),
+            "modbus,gateway=10.0.0.1:502,slave=2,group=slow,site=site\\ one,node=ems-1,room=B\\ 2 \
+             serial=\"say \\\"hi\\\" \\\\ ok\" 1700000000000000000"
+        );
+
+        // 空的 tag 值不输出
+        let mut reading = tricky(1.0);
+        reading.unit = Some(String::new());
+        assert!(!text(&reading, PayloadFormat::InfluxLine).contains("unit="));
+    }
+
+    #[test]
+    fn influx_line_skips_nan_and_infinity() {
+        for special in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(format_reading(&tricky(special), PayloadFormat::InfluxLine, &envelope()).is_empty());
+        }
+    }
+
+    #[test]
+    fn key_value_escapes_key_and_stringifies_special_values() {
+        assert_eq!(text(&tricky(12.5), PayloadFormat::KeyValue), "有功 功率\\=total=12.5");
+        assert_eq!(text(&tricky(f64::NAN), PayloadFormat::KeyValue), "有功 功率\\=total=NaN");
+        assert_eq!(text(&tricky(f64::INFINITY), PayloadFormat::KeyValue), "有功 功率\\=total=inf");
+        assert_eq!(text(&tricky(f64::NEG_INFINITY), PayloadFormat::KeyValue), "有功 功率\\=total=-inf");
+
+        // 字符串中的换行和反斜杠转义后仍占一行
+        let mut reading = reading("10.0.0.1:502", 1, "message", 0.0);
+        reading.text = Some("line 1\nline\\2".to_string());
+        assert_eq!(text(&reading, PayloadFormat::KeyValue), "message=line 1\\nline\\\\2");
+    }
+
+    #[test]
+    fn raw_registers_are_big_endian_words() {
+        let mut reading = tricky(f64::NAN);
+        reading.raw = vec![0x1234, 0xABCD, 0x0001];
+        assert_eq!(
+            format_reading(&reading, PayloadFormat::RawRegisters, &envelope()),
+            [0x12, 0x34, 0xAB, 0xCD, 0x00, 0x01]
+        );
+        assert!(!PayloadFormat::RawRegisters.can_aggregate());
+        assert!(PayloadFormat::InfluxLine.can_aggregate());
+    }
+
+    fn settings() -> MqttSettings {
+        let mut settings = MqttSettings::new("127.0.0.1", "ems-1");
+        settings.site = Some("site one".to_string());
+        settings.tags = BTreeMap::from([
+            ("building".to_string(), "B2".to_string()),
+            ("line".to_string(), "3".to_string()),
+        ]);
+        settings
+    }
+
+    #[test]
+    fn envelope_has_read_time_metadata_and_per_device_seq() {
+        let mut settings = settings();
+        settings.local_time = true;
+        let mut builder = EnvelopeBuilder::new(&settings, Some(chrono_tz::Asia::Shanghai));
+        let read_at = at(1_700_000_000_123);
+        let first = builder.next("10.0.0.1:502", Some(1), read_at);
+        assert_eq!(first.timestamp, "2023-11-14T22:13:20.123Z");
+        assert_eq!(first.local_time.as_deref(), Some("2023-11-15T06:13:20.123+08:00"));
+        assert_eq!(first.seq, 1);
+        assert_eq!(first.site.as_deref(), Some("site one"));
+        assert_eq!(first.node, "ems-1");
+        assert_eq!(first.tags, settings.tags);
+        assert!(Uuid::parse_str(&first.session_id).is_ok());
+
+        // 每个设备单独计数，per_cycle 以网关为设备
+        assert_eq!(builder.next("10.0.0.1:502", Some(2), read_at).seq, 1);
+        assert_eq!(builder.next("10.0.0.1:502", None, read_at).seq, 1);
+        assert_eq!(builder.next("10.0.0.1:502", Some(1), read_at).seq, 2);
+        assert_eq!(builder.next_seq("10.0.0.1:502", Some(1)), 3);
+        assert_eq!(builder.next("10.0.0.1:502", Some(1), read_at).session_id, first.session_id);
+
+        // 重新创建（程序重启）后序号从1开始，session_id 改变
+        let mut restarted = EnvelopeBuilder::new(&settings, None);
+        let after_restart = restarted.next("10.0.0.1:502", Some(1), read_at);
+        assert_eq!(after_restart.seq, 1);
+        assert_ne!(after_restart.session_id, first.session_id);
+
+        // 标签和站点出现在 JSON 的顶层
+        let value: Value =
+            serde_json::from_str(&text(&reading("10.0.0.1:502", 1, "p", 1.0), PayloadFormat::Json)).unwrap();
+        assert_eq!(value["tags"], json!({ "room": "B 2" }));
+        assert_eq!(value["site"], "site one");
+    }
+
+    #[test]
+    fn json_payload_has_utc_timestamp_and_local_time_across_dst() {
+        let mut settings = settings();
+        settings.local_time = true;
+        let mut builder = EnvelopeBuilder::new(&settings, Some(chrono_tz::America::New_York));
+        let mut payload = |millis: u64| -> Value {
+            let mut reading = reading("10.0.0.1:502", 1, "power", 1.5);
+            reading.timestamp = at(millis);
+            let envelope = builder.next(&reading.gateway, Some(1), reading.timestamp);
+            serde_json::from_slice(&format_reading(&reading, PayloadFormat::Json, &envelope)).unwrap()
+        };
+        // 2024-11-03 夏令时结束，当地 01:30 出现两次，UTC 时间不重复
+        for (millis, timestamp, local_time) in [
+            (1_730_611_800_123, "2024-11-03T05:30:00.123Z", "2024-11-03T01:30:00.123-04:00"),
+            (1_730_615_400_123, "2024-11-03T06:30:00.123Z", "2024-11-03T01:30:00.123-05:00"),
+            // 2024-03-10 夏令时开始，当地时间从 01:59:59 跳到 03:00
+            (1_710_053_999_000, "2024-03-10T06:59:59.000Z", "2024-03-10T01:59:59.000-05:00"),
+            (1_710_054_000_000, "2024-03-10T07:00:00.000Z", "2024-03-10T03:00:00.000-04:00"),
+        ] {
+            let value = payload(millis);
+            assert_eq!(value["timestamp"], timestamp);
+            assert_eq!(value["local_time"], local_time);
+            assert_eq!(value["values"]["power"], 1.5);
+        }
+
+        // 未配置 local_time 时只有 UTC 时间
+        settings.local_time = false;
+        let mut builder = EnvelopeBuilder::new(&settings, Some(chrono_tz::America::New_York));
+        let reading = reading("10.0.0.1:502", 1, "power", 1.5);
+        let envelope = builder.next(&reading.gateway, Some(1), at(1_730_611_800_123));
+        let value: Value =
+            serde_json::from_slice(&format_reading(&reading, PayloadFormat::Json, &envelope)).unwrap();
+        assert_eq!(value["timestamp"], "2024-11-03T05:30:00.123Z");
+        assert!(value.get("local_time").is_none());
+    }
+
+    #[tokio::test]
+    async fn seq_keeps_increasing_across_broker_outage() {
+        let broker = MockBroker::start().await;
+        let mut settings = settings();
+        settings.broker_port = broker.port;
+        settings.reconnect_delay_ms = 500;
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher =
+            Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, None, None).unwrap();
+        let mut state = client.watch_state();
+        state.wait_for(|state| state.connected).await.unwrap();
+
+        let cycle = |value: f64| [reading("10.0.0.1:502", 1, "power", value)];
+        publisher.publish(&cycle(1.0));
+        publisher.publish(&cycle(2.0));
+        let topic = "ems/10.0.0.1:502/1";
+        broker.wait_for_topic(topic, 2).await;
+
+        // 断线期间的消息留在发送队列中，重连后补发
+        broker.drop_connections();
+        state.wait_for(|state| !state.connected).await.unwrap();
+        publisher.publish(&cycle(3.0));
+        publisher.publish(&cycle(4.0));
+        state.wait_for(|state| state.connected).await.unwrap();
+        publisher.publish(&cycle(5.0));
+
+        let payloads = broker.wait_for_topic(topic, 5).await;
+        let envelopes: Vec<Value> = payloads.iter().map(|p| serde_json::from_slice(p).unwrap()).collect();
+        let seqs: Vec<u64> = envelopes.iter().map(|e| e["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, [1, 2, 3, 4, 5]);
+        let values: Vec<f64> = envelopes.iter().map(|e| e["values"]["power"].as_f64().unwrap()).collect();
+        assert_eq!(values, [1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(envelopes.iter().all(|e| e["session_id"] == envelopes[0]["session_id"]));
+        assert!(envelopes.iter().all(|e| e["tags"] == json!({ "building": "B2", "line": "3" })));
+        assert!(envelopes.iter().all(|e| e["timestamp"] == "2023-11-14T22:13:20.000Z"));
+        client.close().await;
+    }
+}
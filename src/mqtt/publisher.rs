@@ -0,0 +1,514 @@
+use chrono_tz::Tz;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::latest::SharedCache;
+use crate::modbus::availability::{Availability, DeviceAvailability};
+use crate::modbus::reading::{Quality, Reading};
+use crate::mqtt::batch::{reading_key, Batcher, DataMessage, ReadingKey};
+use crate::mqtt::change_filter::ChangeFilter;
+use crate::mqtt::client::{qos_from_level, MqttClient};
+use crate::mqtt::compression::{compress, CompressionMarker};
+use crate::mqtt::dead_letter::{DeadLetterCategory, SharedDeadLetters};
+use crate::mqtt::error::MqttError;
+use crate::mqtt::rate_limit::RateLimiter;
+use crate::mqtt::sparkplug::{MessageType, SharedNode};
+use crate::mqtt::topic::{TopicTemplate, TopicValues};
+use crate::pipeline::Sink;
+use crate::storage::SharedPublishLog;
+
+/// 展开从站的可用性主题
+pub fn availability_topic(
+    template: &TopicTemplate,
+    prefix: &str,
+    site: Option<&str>,
+    device: &DeviceAvailability,
+) -> String {
+    let gateway = device.gateway_name.as_deref().unwrap_or(&device.gateway);
+    let gateway_host = device
+        .gateway
+        .rsplit_once(':')
+        .map_or(device.gateway.as_str(), |(host, _)| host);
+    let slave = device
+        .slave_name
+        .clone()
+        .unwrap_or_else(|| device.slave_id.to_string());
+    template.expand(&TopicValues {
+        prefix,
+        site,
+        gateway,
+        gateway_host,
+        slave: &slave,
+        ..TopicValues::default()
+    })
+}
+
+/// 将采集数据发布到 MQTT
+///
+/// 采集数据先按死区过滤（与上次发布的值比较，发布的值记录在最新值缓存中），再按 mqtt.aggregation 合并为消息，消息格式由 payload_format 决定。
+///
+/// 发布只是放入客户端的发送队列，不会等待 Broker；队列已满或连接已关闭时丢弃消息并计数，
+/// 不影响采集。连续失败时只在开始失败和恢复时输出日志。发布失败的从站下次采集时全部点位重新发布。
+///
+/// 配置了压缩时，超出阈值的数据消息以 gzip 压缩后发布；可用性消息不压缩。
+///
+/// 配置了限速时，超出限速的数据消息进入等待队列，由 [`Publisher::run_rate_limiter`] 稍后发布；
+/// 队列中同一主题的消息只保留最新的一条，被合并掉的消息对应的从站下次采集时全部点位重新发布。
+/// 可用性消息和 Sparkplug B 消息不限速。
+///
+/// 主题不合法或超出 Broker 最大报文长度的数据消息重试也无法发出，配置了死信主题时发布到死信主题。
+///
+/// 配置了本地存储的 forward 时，每条数据消息的结果记录到 [`PublishLog`](crate::storage::PublishLog)，
+/// 本地存储据此标记数据是否已发布。
+///
+/// 从站可用性以保留消息发布到可用性主题，内容为 online、offline 或 disabled。
+/// 从站过期点位数变化时，以保留消息发布到可用性主题下的 `stale_points` 子主题。
+///
+/// Sparkplug B 模式下，死区过滤后的点位合并为一条 NDATA 发布，不使用数据主题和消息格式；
+/// 未连接时不发布 NDATA，重连后的 NBIRTH 带有最近一次的值。
+pub struct Publisher {
+    client: Arc<MqttClient>,
+    batcher: Batcher,
+    include_unchanged: bool,
+    filter: Mutex<ChangeFilter>,
+    availability_topic: TopicTemplate,
+    topic_prefix: String,
+    site: Option<String>,
+    sparkplug: Option<SharedNode>,
+    dead_letters: Option<SharedDeadLetters>,
+    publish_log: Option<SharedPublishLog>,
+    compression_threshold: usize,
+    compression_marker: CompressionMarker,
+    limiter: Option<Mutex<RateLimiter>>,
+    published: AtomicU64,
+    failed: AtomicU64,
+    coalesced: AtomicU64,
+    delayed: AtomicU64,
+    failing: AtomicBool,
+    // 每个从站（网关地址、从站ID）当前过期的点位
+    stale: Mutex<HashMap<(String, u8), HashSet<String>>>,
+}
+
+impl Publisher {
+    /// 创建发布器
+    ///
+    /// # 参数说明
+    /// * `client` - MQTT 客户端
+    /// * `settings` - MQTT 配置，使用其中的主题模板、前缀、站点名称和合并方式
+    /// * `cache` - 最新采集值缓存，死区过滤在其中记录和比较上次发布的值
+    /// * `timezone` - 全局时区，用于数据消息的 local_time，为 None 时使用系统时区
+    /// * `sparkplug` - Sparkplug B 节点状态，配置了 mqtt.sparkplug 时使用
+    /// * `dead_letters` - 死信发布器，未配置死信主题时为 None
+    /// * `publish_log` - 与本地存储共用的发布记录，未配置 storage.forward 时为 None
+    pub fn new(
+        client: Arc<MqttClient>,
+        settings: &MqttSettings,
+        cache: SharedCache,
+        timezone: Option<Tz>,
+        sparkplug: Option<SharedNode>,
+        dead_letters: Option<SharedDeadLetters>,
+        publish_log: Option<SharedPublishLog>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Publisher {
+            client,
+            batcher: Batcher::new(settings, timezone)?,
+            include_unchanged: settings.include_unchanged,
+            filter: Mutex::new(ChangeFilter::new(cache)),
+            availability_topic: settings.availability_topic()?,
+            topic_prefix: settings.topic_prefix.clone(),
+            site: settings.site.clone(),
+            sparkplug,
+            dead_letters,
+            publish_log,
+            compression_threshold: settings.compression_threshold_bytes,
+            compression_marker: settings.compression_marker,
+            limiter: RateLimiter::new(settings, Instant::now()).map(Mutex::new),
+            published: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            delayed: AtomicU64::new(0),
+            failing: AtomicBool::new(false),
+            stale: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 发布一批采集数据，QoS 和 retain 使用每个点位解析后的配置
+    pub fn publish(&self, readings: &[Reading]) {
+        self.publish_stale_points(readings);
+        let mut filter = self.filter.lock().unwrap_or_else(|e| e.into_inner());
+        let readings = filter.filter(readings, self.include_unchanged);
+        if let Some(node) = &self.sparkplug {
+            let (topic, payload) = {
+                let mut node = node.lock().unwrap_or_else(|e| e.into_inner());
+                (node.topic(MessageType::Data), node.data(&readings))
+            };
+            let Some(payload) = payload else {
+                return;
+            };
+            let result = if self.client.is_connected() {
+                self.client.try_publish(&topic, qos_from_level(0), false, payload)
+            } else {
+                Err(MqttError::Disconnected)
+            };
+            let keys: Vec<ReadingKey> = readings.iter().map(reading_key).collect();
+            if !self.record(&topic, result, &keys) {
+                for reading in &readings {
+                    filter.forget(&reading.gateway, reading.slave_id);
+                }
+            }
+            return;
+        }
+        let mut messages = self.batcher.messages(&readings);
+        if let Some(limiter) = &self.limiter {
+            let mut limiter = limiter.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let delayed = limiter.drain(now);
+            self.delayed.fetch_add(delayed.len() as u64, Ordering::Relaxed);
+            let (ready, coalesced) = limiter.submit(messages, now);
+            self.coalesced.fetch_add(coalesced.len() as u64, Ordering::Relaxed);
+            for message in &coalesced {
+                for (gateway, slave_id) in &message.devices {
+                    filter.forget(gateway, *slave_id);
+                }
+                if let Some(log) = &self.publish_log {
+                    log.reject(&message.readings, now);
+                }
+            }
+            messages = delayed;
+            messages.extend(ready);
+        }
+        for message in messages {
+            self.publish_data(message, &mut filter);
+        }
+    }
+
+    /// 按限速的节奏发布等待队列中的消息，未配置限速时直接返回
+    pub async fn run_rate_limiter(self: Arc<Self>) {
+        let Some(limiter) = &self.limiter else {
+            return;
+        };
+        let interval = limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retry_interval();
+        loop {
+            tokio::time::sleep(interval).await;
+            let mut filter = self.filter.lock().unwrap_or_else(|e| e.into_inner());
+            let delayed = limiter
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .drain(Instant::now());
+            self.delayed.fetch_add(delayed.len() as u64, Ordering::Relaxed);
+            for message in delayed {
+                self.publish_data(message, &mut filter);
+            }
+        }
+    }
+
+    // 按需压缩后放入发送队列，失败时让消息中的从站下次重新发布全部点位
+    fn publish_data(&self, message: DataMessage, filter: &mut ChangeFilter) {
+        let (topic, payload) = compress(
+            &message.topic,
+            message.payload,
+            message.compression,
+            self.compression_threshold,
+            self.compression_marker,
+        );
+        let result = match self.client.check_publish(&topic, payload.len()) {
+            Ok(()) => self.client.try_publish(
+                &topic,
+                qos_from_level(message.qos),
+                message.retain,
+                payload,
+            ),
+            Err(e) => {
+                if let Some(dead_letters) = &self.dead_letters {
+                    let error = e.to_string();
+                    dead_letters.report(&topic, &payload, DeadLetterCategory::PublishRejected, &error);
+                }
+                Err(e)
+            }
+        };
+        if !self.record(&topic, result, &message.readings) {
+            for (gateway, slave_id) in &message.devices {
+                filter.forget(gateway, *slave_id);
+            }
+        }
+    }
+
+    // 记录发布结果，只在开始失败和恢复时输出日志；返回是否成功
+    fn record(&self, topic: &str, result: Result<(), MqttError>, keys: &[ReadingKey]) -> bool {
+        if let Some(log) = &self.publish_log {
+            let now = Instant::now();
+            match &result {
+                Ok(()) => log.record(keys, self.client.state().connections, now),
+                Err(_) => log.reject(keys, now),
+            }
+        }
+        match result {
+            Ok(()) => {
+                self.published.fetch_add(1, Ordering::Relaxed);
+                if self.failing.swap(false, Ordering::Relaxed) {
+                    info!("MQTT 发布已恢复（累计失败 {} 次）", self.failed());
+                }
+                true
+            }
+            Err(e) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                if !self.failing.swap(true, Ordering::Relaxed) {
+                    warn!("MQTT 发布 {} 失败，数据将被丢弃直到恢复: {}", topic, e);
+                }
+                false
+            }
+        }
+    }
+
+    /// 发布从站的可用性（保留消息，QoS 1），重连后自动重新发布
+    pub fn publish_availability(&self, device: &DeviceAvailability) {
+        let topic = availability_topic(
+            &self.availability_topic,
+            &self.topic_prefix,
+            self.site.as_deref(),
+            device,
+        );
+        let payload = device.availability.as_str().as_bytes();
+        if let Err(e) = self.client.publish_state(&topic, payload) {
+            warn!("发布设备可用性 {} 失败: {}", topic, e);
+        }
+    }
+
+    // 更新各从站的过期点位，数量变化时发布到可用性主题下的 stale_points（保留消息）
+    fn publish_stale_points(&self, readings: &[Reading]) {
+        let mut stale = self.stale.lock().unwrap_or_else(|e| e.into_inner());
+        let mut changed: Vec<&Reading> = Vec::new();
+        for reading in readings {
+            let key = (reading.gateway.clone(), reading.slave_id);
+            let points = stale.entry(key).or_default();
+            let before = points.len();
+            if reading.quality == Quality::Stale {
+                points.insert(reading.point.clone());
+            } else {
+                points.remove(&reading.point);
+            }
+            if points.len() != before
+                && !changed
+                    .iter()
+                    .any(|r| r.gateway == reading.gateway && r.slave_id == reading.slave_id)
+            {
+                changed.push(reading);
+            }
+        }
+        for reading in changed {
+            let count = stale
+                .get(&(reading.gateway.clone(), reading.slave_id))
+                .map_or(0, HashSet::len);
+            let device = DeviceAvailability {
+                gateway: reading.gateway.clone(),
+                gateway_name: reading.gateway_name.clone(),
+                slave_id: reading.slave_id,
+                slave_name: reading.slave_name.clone(),
+                availability: Availability::Online,
+            };
+            let topic = format!(
+                "{}/stale_points",
+                availability_topic(
+                    &self.availability_topic,
+                    &self.topic_prefix,
+                    self.site.as_deref(),
+                    &device,
+                )
+            );
+            if let Err(e) = self.client.publish_state(&topic, count.to_string().as_bytes()) {
+                warn!("发布过期点位数 {} 失败: {}", topic, e);
+            }
+        }
+    }
+
+    /// 已放入发送队列的消息数
+    pub fn published(&self) -> u64 {
+        self.published.load(Ordering::Relaxed)
+    }
+
+    /// 发布失败被丢弃的消息数
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// 超出限速时被同一主题的新消息替换掉的消息数
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// 超出限速后在等待队列中延后发布的消息数
+    pub fn delayed(&self) -> u64 {
+        self.delayed.load(Ordering::Relaxed)
+    }
+
+    /// 超出限速、正在等待队列中的消息数，未配置限速时为0
+    pub fn pending(&self) -> usize {
+        self.limiter
+            .as_ref()
+            .map(|limiter| limiter.lock().unwrap_or_else(|e| e.into_inner()).pending())
+            .unwrap_or(0)
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Arc<Publisher> {
+    async fn deliver(&mut self, batch: &[Reading]) {
+        self.publish(batch);
+    }
+
+    async fn availability(&mut self, device: &DeviceAvailability) {
+        self.publish_availability(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::latest::ReadingCache;
+    use crate::modbus::scheduler::{GatewayPoller, PollEvent};
+    use crate::device_configuration::mqtt::PublishOptions;
+    use crate::test_support::{reading, MockBroker, MockModbus};
+    use rumqttc::QoS;
+    use serde_json::{json, Value};
+
+    // 采集一次，把可用性变化交给发布器，返回变化的内容
+    async fn poll_once(poller: &mut GatewayPoller, publisher: &Publisher) -> Vec<Availability> {
+        let mut changes = Vec::new();
+        poller
+            .run_once(|event| {
+                if let PollEvent::Availability(device) = event {
+                    publisher.publish_availability(&device);
+                    changes.push(device.availability);
+                }
+            })
+            .await;
+        changes
+    }
+
+    #[tokio::test]
+    async fn slave_transitions_publish_retained_availability() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 0, &[100]);
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    request_timeout_ms: 200\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            modbus.port
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        let mut poller = GatewayPoller::new(&config, &config.gateways[0]).unwrap();
+
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "availability-test");
+        settings.broker_port = broker.port;
+        settings.availability_topic_template = "{prefix}/{gateway}/{slave}/health".to_string();
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher = Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, None, None).unwrap();
+        let topic = "ems/PCS-A/meter/health";
+
+        assert_eq!(poll_once(&mut poller, &publisher).await, [Availability::Online]);
+        // 可用性不变时不重复发布
+        assert_eq!(poll_once(&mut poller, &publisher).await, []);
+        modbus.reject(1, 0x0B);
+        assert_eq!(poll_once(&mut poller, &publisher).await, [Availability::Offline]);
+        modbus.restore(1);
+        assert_eq!(poll_once(&mut poller, &publisher).await, [Availability::Online]);
+
+        assert_eq!(
+            broker.wait_for_topic(topic, 3).await,
+            [b"online".to_vec(), b"offline".to_vec(), b"online".to_vec()]
+        );
+        let publishes = broker.publishes();
+        assert!(
+            publishes
+                .iter()
+                .filter(|p| p.topic == topic)
+                .all(|p| p.retain && p.qos == QoS::AtLeastOnce)
+        );
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn readings_are_published_with_their_own_qos_and_retain() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "publish-options-test");
+        settings.broker_port = broker.port;
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher = Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, None, None).unwrap();
+
+        let with = |point: &str, qos: u8, retain: bool| {
+            let mut reading = reading("10.0.0.1:502", 1, point, 1.0);
+            reading.publish = PublishOptions { qos, retain, ..PublishOptions::default() };
+            reading
+        };
+        publisher.publish(&[with("power", 0, false), with("energy", 1, true), with("alarm", 2, false)]);
+
+        // 同一从站的点位发布到同一主题，QoS 和 retain 不同时分成多条消息
+        let topic = "ems/10.0.0.1:502/1";
+        broker.wait_for_topic(topic, 3).await;
+        let published: Vec<(QoS, bool, String)> = broker
+            .publishes()
+            .into_iter()
+            .filter(|p| p.topic == topic)
+            .map(|p| (p.qos, p.retain, String::from_utf8(p.payload.to_vec()).unwrap()))
+            .collect();
+        let expected = [
+            (QoS::AtMostOnce, false, "power"),
+            (QoS::AtLeastOnce, true, "energy"),
+            (QoS::ExactlyOnce, false, "alarm"),
+        ];
+        assert_eq!(published.len(), expected.len());
+        for ((qos, retain, payload), (expected_qos, expected_retain, point)) in published.iter().zip(expected) {
+            assert_eq!((*qos, *retain), (expected_qos, expected_retain), "{}", payload);
+            assert!(payload.contains(&format!("\"{}\"", point)), "{}", payload);
+        }
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn empty_prefix_publishes_default_shape_to_gateway_and_slave() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "empty-prefix-test");
+        settings.broker_port = broker.port;
+        settings.topic_prefix = String::new();
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher = Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, None, None).unwrap();
+
+        let named = |point: &str, value: f64| {
+            let mut reading = reading("10.0.0.1:502", 1, point, value);
+            reading.gateway_name = Some("PCS-A".to_string());
+            reading.slave_name = Some("meter".to_string());
+            reading
+        };
+        publisher.publish(&[named("power", 12.5), named("energy", 300.0)]);
+
+        // 默认模板 {prefix}/{gateway}/{slave} 在前缀为空时省略前缀段
+        let payloads = broker.wait_for_topic("PCS-A/meter", 1).await;
+        let payload: Value = serde_json::from_slice(&payloads[0]).unwrap();
+        // session_id 每次启动随机生成，其余字段逐一比较
+        let session_id = payload["session_id"].as_str().unwrap();
+        assert!(!session_id.is_empty());
+        assert_eq!(
+            payload,
+            json!({
+                "gateway": "PCS-A",
+                "slave": 1,
+                "timestamp": "2023-11-14T22:13:20.000Z",
+                "values": { "power": 12.5, "energy": 300.0 },
+                "seq": 1,
+                "session_id": session_id,
+                "node": "empty-prefix-test",
+            })
+        );
+        assert_eq!(broker.publishes().len(), 1);
+        client.close().await;
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::mqtt::batch::DataMessage;
+
+/// 令牌桶，按固定速率补充令牌，最多积累1秒的令牌（至少1个）
+///
+/// 时间由调用方传入，便于用固定的时间点验证补充过程。
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// 创建装满令牌的桶
+    ///
+    /// # 参数说明
+    /// * `rate` - 每秒补充的令牌数，必须大于0
+    /// * `now` - 当前时间
+    pub fn new(rate: f64, now: Instant) -> Self {
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// 是否有可用的令牌
+    pub fn available(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// 取走一个令牌，应在 available 返回 true 后调用
+    pub fn take(&mut self) {
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+
+    /// 取一个令牌，没有可用的令牌时返回 false
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        if !self.available(now) {
+            return false;
+        }
+        self.take();
+        true
+    }
+
+    // 时间倒退（调用方传入较早的时间）时不补充
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = self.last.max(now);
+    }
+}
+
+// 等待发布的消息的键：主题，以及同一批消息中发布到该主题的第几条（拆分或发布参数不同时有多条）
+type PendingKey = (String, usize);
+
+/// 采集数据消息的发布限速
+///
+/// # 说明
+/// * 全局和每个设备（从站，per_cycle 时为消息包含的所有从站）各有一个令牌桶，
+///   消息需要同时从全局和所有相关设备的桶中取到令牌才能立即发布
+/// * 取不到令牌的消息进入等待队列；队列中已有同一主题的消息时用新消息替换旧消息（合并），
+///   因此队列长度不超过主题数，不会无限增长
+/// * 同一主题或同一从站的消息按提交顺序发布；不相关的消息不会因为某个从站超出限速而被堵住
+/// * 只限制采集数据；程序状态、设备可用性、远程写入的应答等消息不经过限速
+#[derive(Debug)]
+pub struct RateLimiter {
+    global: Option<TokenBucket>,
+    device_rate: Option<f64>,
+    devices: HashMap<(String, u8), TokenBucket>,
+    pending: VecDeque<(PendingKey, DataMessage)>,
+}
+
+impl RateLimiter {
+    /// 根据 MQTT 配置创建，两个限速都未配置时返回 None
+    pub fn new(settings: &MqttSettings, now: Instant) -> Option<Self> {
+        if settings.max_messages_per_second.is_none()
+            && settings.max_device_messages_per_second.is_none()
+        {
+            return None;
+        }
+        Some(RateLimiter {
+            global: settings
+                .max_messages_per_second
+                .map(|rate| TokenBucket::new(rate, now)),
+            device_rate: settings.max_device_messages_per_second,
+            devices: HashMap::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// 提交一批新的消息，调用前应先用 drain 取出等待队列中可以发布的消息
+    ///
+    /// # 返回值
+    /// * 可以立即发布的消息，以及被合并掉的旧消息（调用方据此计数，并让对应从站下次重新发布全部点位）
+    ///
+    /// # 说明
+    /// * 等待队列中有同一主题或包含同一从站的消息时，新消息排在后面，不会乱序
+    pub fn submit(
+        &mut self,
+        messages: Vec<DataMessage>,
+        now: Instant,
+    ) -> (Vec<DataMessage>, Vec<DataMessage>) {
+        let mut ready = Vec::new();
+        let mut coalesced = Vec::new();
+        let mut parts: HashMap<String, usize> = HashMap::new();
+        for message in messages {
+            let part = parts.entry(message.topic.clone()).or_default();
+            let key = (message.topic.clone(), *part);
+            *part += 1;
+            if let Some(index) = self.pending.iter().position(|(k, _)| *k == key) {
+                let old = std::mem::replace(&mut self.pending[index].1, message);
+                coalesced.push(old);
+            } else if !self.pending.iter().any(|(_, queued)| related(queued, &message))
+                && self.try_acquire(&message, now)
+            {
+                ready.push(message);
+            } else {
+                self.pending.push_back((key, message));
+            }
+        }
+        (ready, coalesced)
+    }
+
+    /// 取出等待队列中已经取到令牌的消息
+    ///
+    /// 按队列顺序检查每条消息；取不到令牌的消息留在队列中，排在它后面的同一主题或同一从站的消息也留下，
+    /// 其余消息继续检查。
+    pub fn drain(&mut self, now: Instant) -> Vec<DataMessage> {
+        let mut ready = Vec::new();
+        let mut blocked: Vec<(PendingKey, DataMessage)> = Vec::new();
+        while let Some((key, message)) = self.pending.pop_front() {
+            if blocked.iter().any(|(_, queued)| related(queued, &message))
+                || !self.try_acquire(&message, now)
+            {
+                blocked.push((key, message));
+                continue;
+            }
+            ready.push(message);
+        }
+        self.pending = blocked.into();
+        ready
+    }
+
+    /// 等待队列中的消息数
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 检查等待队列的间隔，取较快的限速补充一个令牌的时间，不小于 10ms
+    pub fn retry_interval(&self) -> Duration {
+        let rate = match (self.global.as_ref().map(|b| b.rate), self.device_rate) {
+            (Some(global), Some(device)) => global.max(device),
+            (Some(rate), None) | (None, Some(rate)) => rate,
+            (None, None) => 1.0,
+        };
+        Duration::from_secs_f64(1.0 / rate).max(Duration::from_millis(10))
+    }
+
+    // 全局和所有相关设备的桶都有令牌时才一起取走，避免只扣了部分桶
+    fn try_acquire(&mut self, message: &DataMessage, now: Instant) -> bool {
+        if let Some(global) = &mut self.global
+            && !global.available(now)
+        {
+            return false;
+        }
+        if let Some(rate) = self.device_rate {
+            for device in &message.devices {
+                let bucket = self
+                    .devices
+                    .entry(device.clone())
+                    .or_insert_with(|| TokenBucket::new(rate, now));
+                if !bucket.available(now) {
+                    return false;
+                }
+            }
+            for device in &message.devices {
+                if let Some(bucket) = self.devices.get_mut(device) {
+                    bucket.take();
+                }
+            }
+        }
+        if let Some(global) = &mut self.global {
+            global.take();
+        }
+        true
+    }
+}
+
+// 两条消息是否需要保持顺序：主题相同，或包含同一个从站
+fn related(a: &DataMessage, b: &DataMessage) -> bool {
+    a.topic == b.topic || a.devices.iter().any(|device| b.devices.contains(device))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latest::ReadingCache;
+    use crate::modbus::availability::{Availability, DeviceAvailability};
+    use crate::mqtt::client::MqttClient;
+    use crate::mqtt::compression::Compression;
+    use crate::mqtt::payload::PayloadFormat;
+    use crate::mqtt::publisher::Publisher;
+    use crate::test_support::{reading, MockBroker};
+    use std::sync::Arc;
+
+    fn message(topic: &str, slave_id: u8, payload: &str) -> DataMessage {
+        DataMessage {
+            topic: topic.to_string(),
+            qos: 0,
+            retain: false,
+            format: PayloadFormat::Json,
+            compression: Compression::None,
+            payload: payload.as_bytes().to_vec(),
+            devices: vec![("10.0.0.1:502".to_string(), slave_id)],
+            readings: Vec::new(),
+        }
+    }
+
+    fn new_limiter(global: Option<f64>, device: Option<f64>, now: Instant) -> RateLimiter {
+        let mut settings = MqttSettings::new("localhost", "test");
+        settings.max_messages_per_second = global;
+        settings.max_device_messages_per_second = device;
+        RateLimiter::new(&settings, now).unwrap()
+    }
+
+    fn payloads(messages: &[DataMessage]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|m| std::str::from_utf8(&m.payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn bucket_refills_at_rate_up_to_capacity() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(!bucket.try_take(start + Duration::from_millis(400)));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        // 空闲很久也只积累1秒的令牌
+        let later = start + Duration::from_secs(10);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+        // 时间倒退不补充
+        assert!(!bucket.try_take(start));
+    }
+
+    #[test]
+    fn slow_bucket_holds_one_token() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0.5, start);
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start + Duration::from_millis(1999)));
+        assert!(bucket.try_take(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn not_configured_returns_none() {
+        let settings = MqttSettings::new("localhost", "test");
+        assert!(RateLimiter::new(&settings, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn coalesces_same_topic_newest_wins() {
+        let start = Instant::now();
+        let mut limiter = new_limiter(Some(1.0), None, start);
+        let (ready, coalesced) = limiter.submit(
+            vec![message("a", 1, "a1"), message("b", 2, "b1")],
+            start,
+        );
+        assert_eq!(payloads(&ready), ["a1"]);
+        assert!(coalesced.is_empty());
+
+        let (ready, coalesced) = limiter.submit(vec![message("b", 2, "b2")], start);
+        assert!(ready.is_empty());
+        assert_eq!(payloads(&coalesced), ["b1"]);
+        assert_eq!(limiter.pending(), 1);
+
+        assert!(limiter.drain(start + Duration::from_millis(500)).is_empty());
+        assert_eq!(payloads(&limiter.drain(start + Duration::from_secs(1))), ["b2"]);
+        assert_eq!(limiter.pending(), 0);
+    }
+
+    #[test]
+    fn split_parts_coalesce_separately() {
+        let start = Instant::now();
+        let mut limiter = new_limiter(Some(1.0), None, start);
+        limiter.submit(vec![message("x", 9, "x0")], start);
+        let (_, coalesced) = limiter.submit(
+            vec![message("a", 1, "a1"), message("a", 1, "a2")],
+            start,
+        );
+        assert!(coalesced.is_empty());
+        let (_, coalesced) = limiter.submit(
+            vec![message("a", 1, "a3"), message("a", 1, "a4")],
+            start,
+        );
+        assert_eq!(payloads(&coalesced), ["a1", "a2"]);
+        assert_eq!(limiter.pending(), 2);
+    }
+
+    #[test]
+    fn blocked_device_does_not_block_other_devices() {
+        let start = Instant::now();
+        let mut limiter = new_limiter(None, Some(1.0), start);
+        let (ready, _) = limiter.submit(
+            vec![message("a", 1, "a1"), message("b", 1, "b1")],
+            start,
+        );
+        assert_eq!(payloads(&ready), ["a1"]);
+        assert_eq!(limiter.pending(), 1);
+
+        // 从站1的消息在等待，从站2的消息不受影响
+        let (ready, _) = limiter.submit(vec![message("c", 2, "c1")], start);
+        assert_eq!(payloads(&ready), ["c1"]);
+
+        // 排在被堵住的消息后面的其他从站的消息在 drain 时也能发出
+        let mut other = new_limiter(None, Some(1.0), start);
+        other.submit(vec![message("a", 1, "a1")], start);
+        other.submit(vec![message("c", 2, "c1")], start);
+        other.submit(vec![message("b", 1, "b1"), message("d", 2, "d1")], start);
+        assert_eq!(other.pending(), 2);
+        let later = start + Duration::from_secs(1);
+        assert_eq!(payloads(&other.drain(later)), ["b1", "d1"]);
+    }
+
+    #[test]
+    fn drain_keeps_order_for_same_device() {
+        let start = Instant::now();
+        let mut limiter = new_limiter(None, Some(1.0), start);
+        limiter.submit(vec![message("a", 1, "a1")], start);
+        limiter.submit(
+            vec![message("b", 1, "b1"), message("c", 1, "c1"), message("d", 2, "d1")],
+            start,
+        );
+        // d 所在的从站2还有令牌，但 submit 时队列里没有从站2的消息，所以已经发出
+        assert_eq!(limiter.pending(), 2);
+        let later = start + Duration::from_secs(1);
+        assert_eq!(payloads(&limiter.drain(later)), ["b1"]);
+        assert_eq!(payloads(&limiter.drain(later)), Vec::<&str>::new());
+        assert_eq!(payloads(&limiter.drain(later + Duration::from_secs(1))), ["c1"]);
+    }
+
+    #[test]
+    fn same_topic_waits_behind_pending_message() {
+        let start = Instant::now();
+        let mut limiter = new_limiter(None, Some(1.0), start);
+        let mut shared = message("site/all", 1, "1");
+        shared.devices.push(("10.0.0.1:502".to_string(), 2));
+        limiter.submit(vec![message("a", 1, "a1")], start);
+        limiter.submit(vec![shared], start);
+        // 从站2有令牌，但与等待中的消息包含同一从站，排在后面
+        let (ready, _) = limiter.submit(vec![message("b", 2, "b1")], start);
+        assert!(ready.is_empty());
+        assert_eq!(limiter.pending(), 2);
+        let later = start + Duration::from_secs(1);
+        assert_eq!(payloads(&limiter.drain(later)), ["1"]);
+        assert_eq!(payloads(&limiter.drain(later + Duration::from_secs(1))), ["b1"]);
+    }
+
+    #[test]
+    fn takes_tokens_only_when_all_buckets_have_one() {
+        let start = Instant::now();
+        let mut limiter = new_limiter(Some(1.0), Some(1.0), start);
+        limiter.submit(vec![message("a", 1, "a1")], start);
+        let later = start + Duration::from_secs(1);
+        // 从站1的桶在 later 时有令牌，全局桶也有；第二条消息的从站桶未被扣
+        let (ready, _) = limiter.submit(vec![message("b", 2, "b1")], later);
+        assert_eq!(payloads(&ready), ["b1"]);
+    }
+
+    #[test]
+    fn retry_interval_uses_faster_rate() {
+        let now = Instant::now();
+        assert_eq!(
+            new_limiter(Some(2.0), Some(10.0), now).retry_interval(),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            new_limiter(Some(1000.0), None, now).retry_interval(),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[tokio::test]
+    async fn availability_bypasses_rate_limit() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "rate-limit-test");
+        settings.broker_port = broker.port;
+        settings.max_messages_per_second = Some(1.0);
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let publisher = Publisher::new(Arc::clone(&client), &settings, ReadingCache::new(), None, None, None, None).unwrap();
+        let mut state = client.watch_state();
+        state.wait_for(|state| state.connected).await.unwrap();
+
+        publisher.publish(&[reading("10.0.0.1:502", 1, "a", 1.0)]);
+        publisher.publish(&[reading("10.0.0.1:502", 1, "b", 2.0)]);
+        assert_eq!(publisher.pending(), 1);
+        publisher.publish_availability(&DeviceAvailability {
+            gateway: "10.0.0.1:502".to_string(),
+            gateway_name: None,
+            slave_id: 1,
+            slave_name: None,
+            availability: Availability::Offline,
+        });
+        let topic = "ems/10.0.0.1:502/1/availability";
+        assert_eq!(broker.wait_for_topic(topic, 1).await, [b"offline".to_vec()]);
+        assert_eq!(broker.payloads("ems/10.0.0.1:502/1").len(), 1);
+        assert_eq!(publisher.pending(), 1);
+        client.close().await;
+    }
+}
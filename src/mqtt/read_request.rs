@@ -0,0 +1,542 @@
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::device_configuration::mqtt::{MqttSettings, ReadRequests};
+use crate::modbus::decode::{decode, decode_string, DataType, WordOrder};
+use crate::modbus::reading::PointValue;
+use crate::modbus::scheduler::{GatewayRequest, ReadRequest};
+use crate::mqtt::client::MqttClient;
+use crate::mqtt::dead_letter::{DeadLetterCategory, SharedDeadLetters};
+use crate::mqtt::rate_limit::TokenBucket;
+use crate::reload::SharedWriters;
+
+/// 等待采集任务完成读取的最长时间，包括排队等待正在进行的采集
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// 一次最多读取的寄存器数量（Modbus 协议限制）
+pub const MAX_READ_REGISTERS: u16 = 125;
+/// 一次最多读取的线圈数量（Modbus 协议限制）
+pub const MAX_READ_BITS: u16 = 2000;
+
+/// 临时读取请求的消息格式
+///
+/// 例如 `{"gateway": "PCS-A", "slave": 7, "fc": 3, "address": 100, "quantity": 2, "data_type": "f32", "request_id": "abc"}`。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadCommand {
+    /// 请求标识，原样带回应答，可以是字符串或数字
+    #[serde(default)]
+    pub request_id: Option<Value>,
+    /// 网关名称或 ip:port
+    pub gateway: String,
+    /// 从站ID
+    pub slave: u8,
+    /// 读取功能码（1-4）
+    pub fc: u8,
+    /// 起始地址
+    pub address: u16,
+    /// 读取数量，未指定时为 data_type 占用的寄存器数量（未指定 data_type 时为1）；data_type 为 string 时必须指定
+    #[serde(default)]
+    pub quantity: Option<u16>,
+    /// 指定时把读到的寄存器解析为数值
+    #[serde(default)]
+    pub data_type: Option<DataType>,
+    /// 多寄存器数据的字节序（默认 abcd）
+    #[serde(default)]
+    pub word_order: WordOrder,
+}
+
+/// 临时读取失败的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadErrorKind {
+    /// 消息格式错误或参数超出范围
+    InvalidRequest,
+    /// 请求过于频繁
+    RateLimited,
+    /// 找不到正在采集的网关或从站
+    NotFound,
+    /// 网关的请求队列已满
+    Busy,
+    /// 未在限定时间内完成
+    Timeout,
+    /// Modbus 连接或读取失败
+    Modbus,
+}
+
+/// 临时读取失败的原因
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadError {
+    /// 失败类型
+    pub kind: ReadErrorKind,
+    /// 错误信息
+    pub message: String,
+}
+
+impl ReadError {
+    fn new(kind: ReadErrorKind, message: impl Into<String>) -> Self {
+        ReadError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// 临时读取的应答，发布到 `<请求主题>/response`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadResponse {
+    /// 请求中的 request_id，原样带回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Value>,
+    /// 是否读取成功
+    pub success: bool,
+    /// 读到的寄存器（线圈为0或1）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registers: Option<Vec<u16>>,
+    /// 按 data_type 解析后的数值，string 为字符串
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<PointValue>,
+    /// 失败原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ReadError>,
+}
+
+/// 订阅临时读取主题，按请求立即读取指定网关和从站的寄存器
+///
+/// # 说明
+/// * 读取交给网关的采集任务，在当前正在进行的采集或写入完成后执行，与采集共用同一个连接，
+///   不会与其他请求交错
+/// * 超出 `read_requests.max_per_second` 的请求直接拒绝，不会排队
+/// * 只能读取配置中已启用、正在采集的网关和从站
+/// * 配置了死信主题时，格式错误、找不到设备和读取失败的请求发布到死信主题；
+///   限速和队列已满的请求只是暂时无法执行，不算死信
+pub struct ReadHandler {
+    client: Arc<MqttClient>,
+    topic: String,
+    writers: SharedWriters,
+    limiter: Mutex<TokenBucket>,
+    dead_letters: Option<SharedDeadLetters>,
+}
+
+impl ReadHandler {
+    /// 创建读取处理器
+    ///
+    /// # 参数说明
+    /// * `client` - MQTT 客户端，用于订阅请求主题和发布应答
+    /// * `settings` - MQTT 配置，使用其中的主题前缀和客户端ID
+    /// * `read_requests` - 临时读取配置
+    /// * `writers` - 正在采集的网关的请求入口
+    /// * `dead_letters` - 死信发布器，未配置死信主题时为 None
+    pub fn new(
+        client: Arc<MqttClient>,
+        settings: &MqttSettings,
+        read_requests: &ReadRequests,
+        writers: SharedWriters,
+        dead_letters: Option<SharedDeadLetters>,
+    ) -> Self {
+        ReadHandler {
+            client,
+            topic: settings.read_topic(),
+            writers,
+            limiter: Mutex::new(TokenBucket::new(read_requests.max_per_second, Instant::now())),
+            dead_letters,
+        }
+    }
+
+    /// 订阅请求主题并持续处理收到的消息，MQTT 客户端关闭时返回
+    pub async fn run(self) {
+        let mut messages = match self.client.subscribe(&self.topic, QoS::AtLeastOnce).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                warn!("订阅临时读取主题 {} 失败: {}", self.topic, e);
+                return;
+            }
+        };
+        info!("已订阅临时读取主题 {}", self.topic);
+
+        let handler = Arc::new(self);
+        while let Some(message) = messages.recv().await {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                let response = handler.execute(&message.payload).await;
+                handler.reply(&response).await;
+            });
+        }
+    }
+
+    /// 执行一条读取请求并返回应答
+    pub async fn execute(&self, payload: &[u8]) -> ReadResponse {
+        let command: ReadCommand = match serde_json::from_slice(payload) {
+            Ok(command) => command,
+            Err(e) => {
+                let category = if e.is_syntax() || e.is_eof() {
+                    DeadLetterCategory::MalformedJson
+                } else {
+                    DeadLetterCategory::ValidationFailed
+                };
+                let error = ReadError::new(ReadErrorKind::InvalidRequest, format!("请求格式错误: {}", e));
+                self.dead_letter(payload, Some(category), &error);
+                return self.failed(None, error);
+            }
+        };
+        let request_id = command.request_id.clone();
+        match self.read(&command).await {
+            Ok((registers, value)) => ReadResponse {
+                request_id,
+                success: true,
+                registers: Some(registers),
+                value,
+                error: None,
+            },
+            Err(error) => {
+                self.dead_letter(payload, dead_letter_category(error.kind), &error);
+                self.failed(request_id, error)
+            }
+        }
+    }
+
+    async fn read(&self, command: &ReadCommand) -> Result<(Vec<u16>, Option<PointValue>), ReadError> {
+        let quantity = check_command(command)?;
+        let allowed = self
+            .limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .try_take(Instant::now());
+        if !allowed {
+            return Err(ReadError::new(ReadErrorKind::RateLimited, "读取请求过于频繁"));
+        }
+
+        let sender = self.find_gateway(command)?;
+        let (reply, result) = oneshot::channel();
+        let request = ReadRequest {
+            slave_id: command.slave,
+            function_code: command.fc,
+            address: command.address,
+            quantity,
+            reply,
+        };
+        sender
+            .try_send(GatewayRequest::Read(request))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => {
+                    ReadError::new(ReadErrorKind::Busy, "网关的请求队列已满")
+                }
+                mpsc::error::TrySendError::Closed(_) => {
+                    ReadError::new(ReadErrorKind::NotFound, "网关已停止采集")
+                }
+            })?;
+        let registers = match tokio::time::timeout(READ_TIMEOUT, result).await {
+            Ok(Ok(result)) => result.map_err(|e| ReadError::new(ReadErrorKind::Modbus, e))?,
+            Ok(Err(_)) => return Err(ReadError::new(ReadErrorKind::NotFound, "网关已停止采集")),
+            Err(_) => {
+                let message = format!("{} 秒内未完成读取", READ_TIMEOUT.as_secs());
+                return Err(ReadError::new(ReadErrorKind::Timeout, message));
+            }
+        };
+        let value = command.data_type.and_then(|data_type| match data_type {
+            DataType::String { .. } => Some(PointValue::Text(decode_string(command.word_order, &registers))),
+            _ => decode(data_type, command.word_order, &registers).map(PointValue::Number),
+        });
+        Ok((registers, value))
+    }
+
+    // 按网关名称或 ip:port 找到正在采集的网关，从站需要已启用
+    fn find_gateway(&self, command: &ReadCommand) -> Result<mpsc::Sender<GatewayRequest>, ReadError> {
+        let writers = self.writers.lock().unwrap_or_else(|e| e.into_inner());
+        let writer = writers
+            .iter()
+            .find(|w| {
+                let gateway = &w.gateway;
+                gateway.display_name() == command.gateway
+                    || format!("{}:{}", gateway.ip, gateway.port) == command.gateway
+            })
+            .ok_or_else(|| {
+                let message = format!("没有正在采集的网关 {}", command.gateway);
+                ReadError::new(ReadErrorKind::NotFound, message)
+            })?;
+        if !writer.points.contains_key(&command.slave) {
+            let message = format!("网关 {} 没有已启用的从站 {}", command.gateway, command.slave);
+            return Err(ReadError::new(ReadErrorKind::NotFound, message));
+        }
+        Ok(writer.sender.clone())
+    }
+
+    fn dead_letter(&self, payload: &[u8], category: Option<DeadLetterCategory>, error: &ReadError) {
+        if let (Some(dead_letters), Some(category)) = (&self.dead_letters, category) {
+            dead_letters.report(&self.topic, payload, category, &error.message);
+        }
+    }
+
+    fn failed(&self, request_id: Option<Value>, error: ReadError) -> ReadResponse {
+        warn!("临时读取 {} 失败: {}", self.topic, error.message);
+        ReadResponse {
+            request_id,
+            success: false,
+            registers: None,
+            value: None,
+            error: Some(error),
+        }
+    }
+
+    // 应答发布到请求主题下的 response 子主题，不保留
+    async fn reply(&self, response: &ReadResponse) {
+        let topic = format!("{}/response", self.topic);
+        let result = match serde_json::to_vec(response) {
+            Ok(payload) => self
+                .client
+                .publish(&topic, QoS::AtLeastOnce, false, &payload)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            warn!("发布临时读取应答 {} 失败: {}", topic, e);
+        }
+    }
+}
+
+// 读取失败对应的死信分类，暂时无法执行的请求（限速、队列已满）不算死信
+fn dead_letter_category(kind: ReadErrorKind) -> Option<DeadLetterCategory> {
+    match kind {
+        ReadErrorKind::InvalidRequest => Some(DeadLetterCategory::ValidationFailed),
+        ReadErrorKind::NotFound => Some(DeadLetterCategory::UnknownPoint),
+        ReadErrorKind::Timeout | ReadErrorKind::Modbus => Some(DeadLetterCategory::ModbusError),
+        ReadErrorKind::RateLimited | ReadErrorKind::Busy => None,
+    }
+}
+
+/// 检查读取请求的功能码、数量和地址范围
+///
+/// # 返回值
+/// * 实际读取的数量
+/// * `Err` - 功能码不是1-4，数量为0或超出协议限制，地址超出范围，
+///   线圈读取指定了 bool 以外的数据类型，或数量少于数据类型占用的寄存器数量
+pub fn check_command(command: &ReadCommand) -> Result<u16, ReadError> {
+    let invalid = |message: String| ReadError::new(ReadErrorKind::InvalidRequest, message);
+    let bits = match command.fc {
+        1 | 2 => true,
+        3 | 4 => false,
+        fc => return Err(invalid(format!("功能码只能是1-4，当前为 {}", fc))),
+    };
+    if command.data_type.is_some_and(|t| t.is_string()) && command.quantity.is_none() {
+        return Err(invalid("data_type 为 string 时需要指定 quantity".to_string()));
+    }
+    let quantity = command
+        .quantity
+        .unwrap_or_else(|| command.data_type.map_or(1, |t| t.register_count()));
+    let limit = if bits { MAX_READ_BITS } else { MAX_READ_REGISTERS };
+    if quantity == 0 || quantity > limit {
+        return Err(invalid(format!("读取数量必须在 1-{} 之间，当前为 {}", limit, quantity)));
+    }
+    if u32::from(command.address) + u32::from(quantity) > 65536 {
+        return Err(invalid(format!(
+            "地址 {} 开始的 {} 个{}超出范围",
+            command.address,
+            quantity,
+            if bits { "线圈" } else { "寄存器" }
+        )));
+    }
+    if let Some(data_type) = command.data_type {
+        if bits && data_type != DataType::Bool {
+            return Err(invalid("读取线圈时 data_type 只能是 bool".to_string()));
+        }
+        if quantity < data_type.register_count() {
+            return Err(invalid(format!(
+                "数据类型需要 {} 个寄存器，读取数量为 {}",
+                data_type.register_count(),
+                quantity
+            )));
+        }
+    }
+    Ok(quantity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::modbus::Config;
+    use crate::modbus::scheduler::PollEvent;
+    use crate::reload::GatewayTasks;
+    use crate::test_support::{MockBroker, MockModbus, Received};
+    use serde_json::json;
+
+    // 只有一个网关 PCS-A 的配置，从站7启用，从站8停用
+    fn config(port: u16) -> Config {
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    request_timeout_ms: 200\n    poll_interval_ms: 60000\n    slave_ids: [{{ id: 7 }}, {{ id: 8, enabled: false }}]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            port
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    fn settings(port: u16, client_id: &str) -> MqttSettings {
+        let mut settings = MqttSettings::new("127.0.0.1", client_id);
+        settings.broker_port = port;
+        settings
+    }
+
+    fn handler(client: &Arc<MqttClient>, settings: &MqttSettings, rate: f64, writers: SharedWriters) -> ReadHandler {
+        let read_requests = ReadRequests { max_per_second: rate };
+        ReadHandler::new(Arc::clone(client), settings, &read_requests, writers, None)
+    }
+
+    fn to_json(response: &ReadResponse) -> Value {
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn scripted_request_is_answered_on_response_topic() {
+        let modbus = MockModbus::start().await;
+        // 12.5f32 = 0x4148_0000
+        modbus.set(7, 3, 100, &[0x4148, 0x0000]);
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config(modbus.port));
+
+        let broker = MockBroker::start().await;
+        let settings = settings(broker.port, "read-test");
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        tokio::spawn(handler(&client, &settings, 10.0, tasks.writers()).run());
+        broker
+            .wait_for("订阅读取主题", |received| {
+                received.iter().any(|p| matches!(p, Received::Subscribe(f) if f == "ems/read-test/read"))
+            })
+            .await;
+
+        broker.inject(
+            "ems/read-test/read",
+            br#"{"gateway":"PCS-A","slave":7,"fc":3,"address":100,"quantity":2,"data_type":"f32","request_id":"abc"}"#,
+        );
+        let responses = broker.wait_for_topic("ems/read-test/read/response", 1).await;
+        let response: Value = serde_json::from_slice(&responses[0]).unwrap();
+        assert_eq!(
+            response,
+            json!({"request_id": "abc", "success": true, "registers": [0x4148, 0], "value": 12.5})
+        );
+        // 读取在网关的连接上执行
+        assert!(
+            modbus
+                .requests()
+                .iter()
+                .any(|r| r.slave_id == 7 && r.function_code == 3 && r.address == 100 && r.quantity == 2)
+        );
+        let publishes = broker.publishes();
+        let reply = publishes.iter().find(|p| p.topic == "ems/read-test/read/response").unwrap();
+        assert!(!reply.retain);
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn gateway_can_be_addressed_by_ip_and_port() {
+        let modbus = MockModbus::start().await;
+        modbus.set(7, 4, 0, &[0x4142]);
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config(modbus.port));
+        let settings = settings(1, "read-test");
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let handler = handler(&client, &settings, 10.0, tasks.writers());
+
+        let request = format!(
+            r#"{{"gateway":"127.0.0.1:{}","slave":7,"fc":4,"address":0,"data_type":"string","quantity":1,"request_id":5}}"#,
+            modbus.port
+        );
+        let response = handler.execute(request.as_bytes()).await;
+        assert_eq!(
+            to_json(&response),
+            json!({"request_id": 5, "success": true, "registers": [0x4142], "value": "AB"})
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_carry_kind_and_request_id() {
+        let modbus = MockModbus::start().await;
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config(modbus.port));
+        let settings = settings(1, "read-test");
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let handler = handler(&client, &settings, 100.0, tasks.writers());
+        let kind = |response: ReadResponse| {
+            assert!(!response.success);
+            assert_eq!(response.registers, None);
+            (response.request_id, response.error.unwrap().kind)
+        };
+
+        let response = handler.execute(b"{not json").await;
+        assert_eq!(kind(response), (None, ReadErrorKind::InvalidRequest));
+        let response = handler
+            .execute(br#"{"gateway":"PCS-A","slave":7,"fc":6,"address":0,"request_id":"a"}"#)
+            .await;
+        assert_eq!(kind(response), (Some(json!("a")), ReadErrorKind::InvalidRequest));
+        let response = handler
+            .execute(br#"{"gateway":"PCS-B","slave":7,"fc":3,"address":0,"request_id":"b"}"#)
+            .await;
+        assert_eq!(kind(response), (Some(json!("b")), ReadErrorKind::NotFound));
+        // 停用的从站不能读取
+        let response = handler
+            .execute(br#"{"gateway":"PCS-A","slave":8,"fc":3,"address":0,"request_id":"c"}"#)
+            .await;
+        assert_eq!(kind(response), (Some(json!("c")), ReadErrorKind::NotFound));
+
+        modbus.reject(7, 0x02);
+        let response = handler
+            .execute(br#"{"gateway":"PCS-A","slave":7,"fc":3,"address":0,"request_id":"d"}"#)
+            .await;
+        assert_eq!(kind(response), (Some(json!("d")), ReadErrorKind::Modbus));
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_rate_are_rejected() {
+        let modbus = MockModbus::start().await;
+        modbus.set(7, 3, 0, &[1]);
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config(modbus.port));
+        let settings = settings(1, "read-test");
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        let handler = handler(&client, &settings, 0.001, tasks.writers());
+        let request = br#"{"gateway":"PCS-A","slave":7,"fc":3,"address":0}"#;
+
+        // 格式错误的请求不消耗令牌
+        assert!(!handler.execute(br#"{"gateway":"PCS-A","slave":7,"fc":9,"address":0}"#).await.success);
+        assert!(handler.execute(request).await.success);
+        let response = handler.execute(request).await;
+        assert_eq!(response.error.unwrap().kind, ReadErrorKind::RateLimited);
+    }
+
+    #[test]
+    fn check_command_enforces_protocol_limits() {
+        let command = |fc: u8, address: u16, quantity: Option<u16>, data_type: Option<DataType>| ReadCommand {
+            request_id: None,
+            gateway: "PCS-A".to_string(),
+            slave: 1,
+            fc,
+            address,
+            quantity,
+            data_type,
+            word_order: WordOrder::default(),
+        };
+        assert_eq!(check_command(&command(3, 0, None, None)), Ok(1));
+        assert_eq!(check_command(&command(3, 0, None, Some(DataType::F64))), Ok(4));
+        assert_eq!(check_command(&command(1, 0, Some(MAX_READ_BITS), None)), Ok(MAX_READ_BITS));
+        assert_eq!(check_command(&command(3, 65535, Some(1), None)), Ok(1));
+
+        let rejected = [
+            command(5, 0, None, None),
+            command(3, 0, Some(0), None),
+            command(3, 0, Some(MAX_READ_REGISTERS + 1), None),
+            command(1, 0, Some(MAX_READ_BITS + 1), None),
+            command(3, 65535, Some(2), None),
+            command(1, 0, None, Some(DataType::U16)),
+            command(3, 0, Some(1), Some(DataType::F32)),
+            command(3, 0, None, Some(DataType::String { length_registers: 0 })),
+        ];
+        for command in rejected {
+            let error = check_command(&command).unwrap_err();
+            assert_eq!(error.kind, ReadErrorKind::InvalidRequest, "{:?}", command);
+        }
+    }
+}
@@ -0,0 +1,610 @@
+use prost::Message;
+use rumqttc::{LastWill, QoS};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::Sparkplug;
+use crate::modbus::decode::DataType;
+use crate::modbus::reading::{PointValue, Reading};
+use crate::mqtt::client::{MqttClient, MqttMessage, SessionHook};
+
+/// Sparkplug B 主题的命名空间
+pub const NAMESPACE: &str = "spBv1.0";
+/// 出生/死亡证明中的会话序号 metric
+pub const BD_SEQ_METRIC: &str = "bdSeq";
+/// 主机应用请求重新发布 NBIRTH 的 metric
+pub const REBIRTH_METRIC: &str = "Node Control/Rebirth";
+
+/// Sparkplug B 的 metric 数据类型（只列出用到的）
+pub mod data_type {
+    /// 无符号64位整数
+    pub const UINT64: u32 = 8;
+    /// 双精度浮点数
+    pub const DOUBLE: u32 = 10;
+    /// 布尔值
+    pub const BOOLEAN: u32 = 11;
+    /// 字符串
+    pub const STRING: u32 = 12;
+}
+
+/// Sparkplug B 的 Payload，只包含本程序用到的字段，字段号与 sparkplug_b.proto 一致
+#[derive(Clone, PartialEq, Message)]
+pub struct Payload {
+    /// 消息生成时间（Unix 毫秒）
+    #[prost(uint64, optional, tag = "1")]
+    pub timestamp: Option<u64>,
+    /// metric 列表
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+    /// 消息序号（0-255 循环）
+    #[prost(uint64, optional, tag = "3")]
+    pub seq: Option<u64>,
+}
+
+/// Sparkplug B 的 Metric
+#[derive(Clone, PartialEq, Message)]
+pub struct Metric {
+    /// metric 名称，NBIRTH 中必须有
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+    /// metric 别名，NDATA 中代替名称
+    #[prost(uint64, optional, tag = "2")]
+    pub alias: Option<u64>,
+    /// 采集时间（Unix 毫秒）
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+    /// 数据类型，取值见 data_type
+    #[prost(uint32, optional, tag = "4")]
+    pub datatype: Option<u32>,
+    /// 值是否为空
+    #[prost(bool, optional, tag = "7")]
+    pub is_null: Option<bool>,
+    /// 值
+    #[prost(oneof = "MetricValue", tags = "10, 11, 12, 13, 14, 15")]
+    pub value: Option<MetricValue>,
+}
+
+/// Metric 的值
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum MetricValue {
+    /// 32位无符号整数
+    #[prost(uint32, tag = "10")]
+    Int(u32),
+    /// 64位无符号整数
+    #[prost(uint64, tag = "11")]
+    Long(u64),
+    /// 单精度浮点数
+    #[prost(float, tag = "12")]
+    Float(f32),
+    /// 双精度浮点数
+    #[prost(double, tag = "13")]
+    Double(f64),
+    /// 布尔值
+    #[prost(bool, tag = "14")]
+    Boolean(bool),
+    /// 字符串
+    #[prost(string, tag = "15")]
+    String(String),
+}
+
+/// 节点级消息类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// NBIRTH
+    Birth,
+    /// NDEATH
+    Death,
+    /// NDATA
+    Data,
+    /// NCMD
+    Command,
+}
+
+impl MessageType {
+    /// 主题中使用的消息类型名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MessageType::Birth => "NBIRTH",
+            MessageType::Death => "NDEATH",
+            MessageType::Data => "NDATA",
+            MessageType::Command => "NCMD",
+        }
+    }
+}
+
+// 点位的标识：（网关地址，从站ID，点位名称）
+type PointKey = (String, u8, String);
+
+// 一个点位对应的 metric
+#[derive(Debug, Clone, PartialEq)]
+struct MetricDef {
+    key: PointKey,
+    name: String,
+    alias: u64,
+    datatype: u32,
+}
+
+/// Sparkplug B 边缘节点的状态
+///
+/// # 说明
+/// * 每个点位是一个 metric，名称为 `<网关>/<从站>/<点位>`，alias 按配置顺序从1开始编号
+/// * bdSeq 每次连接加1（0-255 循环），连接时的遗嘱消息 NDEATH 与该连接的 NBIRTH 使用相同的 bdSeq
+/// * seq 在 NBIRTH 时为0，之后每条 NDATA 加1，255 之后回到0
+/// * NBIRTH 中每个 metric 的值为最近一次采集的值，还没有采集到时为 null
+#[derive(Debug)]
+pub struct SparkplugNode {
+    group_id: String,
+    edge_node_id: String,
+    // 当前连接使用的 bdSeq，还未连接时为 None
+    session_bd_seq: Option<u64>,
+    // 下一次连接使用的 bdSeq
+    next_bd_seq: u64,
+    seq: u8,
+    metrics: Vec<MetricDef>,
+    aliases: BTreeMap<PointKey, u64>,
+    // 最近一次的值和采集时间（毫秒），metric 列表变化后仍然保留
+    last: BTreeMap<PointKey, (PointValue, u64)>,
+}
+
+/// 可在客户端后台任务和发布器间共享的节点状态
+pub type SharedNode = Arc<Mutex<SparkplugNode>>;
+
+impl SparkplugNode {
+    /// 按配置创建节点，metric 列表由 `set_metrics` 设置
+    pub fn new(settings: &Sparkplug) -> Self {
+        SparkplugNode {
+            group_id: settings.group_id.clone(),
+            edge_node_id: settings.edge_node_id.clone(),
+            session_bd_seq: None,
+            next_bd_seq: 0,
+            seq: 0,
+            metrics: Vec::new(),
+            aliases: BTreeMap::new(),
+            last: BTreeMap::new(),
+        }
+    }
+
+    /// 节点消息的主题 `spBv1.0/<group_id>/<消息类型>/<edge_node_id>`
+    pub fn topic(&self, message_type: MessageType) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            NAMESPACE,
+            self.group_id,
+            message_type.as_str(),
+            self.edge_node_id
+        )
+    }
+
+    /// 按配置中正在采集的点位更新 metric 列表
+    ///
+    /// # 返回值
+    /// * `Ok(true)` - metric 列表有变化，已连接时需要重新发布 NBIRTH
+    pub fn set_metrics(&mut self, config: &Config) -> Result<bool, Box<dyn Error>> {
+        let mut metrics = Vec::new();
+        let mut aliases = BTreeMap::new();
+        for gateway in config.gateways.iter().filter(|g| g.enabled && g.has_points()) {
+            let address = format!("{}:{}", gateway.ip, gateway.port);
+            for slave in gateway.enabled_slaves() {
+                for point in config.published_points(gateway, slave)? {
+                    // allow_duplicates 时可能重复，只保留第一个
+                    let key = (address.clone(), slave.id, point.name.clone());
+                    if aliases.contains_key(&key) {
+                        continue;
+                    }
+                    let alias = metrics.len() as u64 + 1;
+                    aliases.insert(key.clone(), alias);
+                    let datatype = if point.data_type == DataType::Bool
+                        && point.scale == 1.0
+                        && point.offset == 0.0
+                    {
+                        data_type::BOOLEAN
+                    } else if point.data_type.is_string() {
+                        data_type::STRING
+                    } else {
+                        data_type::DOUBLE
+                    };
+                    metrics.push(MetricDef {
+                        key,
+                        name: format!(
+                            "{}/{}/{}",
+                            gateway.display_name(),
+                            slave.display_name(),
+                            point.name
+                        ),
+                        alias,
+                        datatype,
+                    });
+                }
+            }
+        }
+        if metrics == self.metrics {
+            return Ok(false);
+        }
+        self.last.retain(|key, _| aliases.contains_key(key));
+        self.metrics = metrics;
+        self.aliases = aliases;
+        Ok(true)
+    }
+
+    /// 是否已发布过 NBIRTH（正常关闭后为 false）
+    pub fn is_born(&self) -> bool {
+        self.session_bd_seq.is_some()
+    }
+
+    /// 新连接建立：使用下一个 bdSeq 并生成 NBIRTH
+    pub fn start_session(&mut self) -> Vec<u8> {
+        self.session_bd_seq = Some(self.next_bd_seq);
+        self.next_bd_seq = (self.next_bd_seq + 1) % 256;
+        self.birth()
+    }
+
+    /// 正常关闭，之后不再生成 NDATA
+    pub fn end_session(&mut self) {
+        self.session_bd_seq = None;
+    }
+
+    /// 生成当前连接的 NBIRTH，seq 重置为0；主机应用请求重新出生时也调用
+    pub fn birth(&mut self) -> Vec<u8> {
+        self.seq = 0;
+        let now = now_millis();
+        let mut metrics = vec![
+            bd_seq_metric(self.session_bd_seq.unwrap_or(self.next_bd_seq)),
+            Metric {
+                name: Some(REBIRTH_METRIC.to_string()),
+                datatype: Some(data_type::BOOLEAN),
+                value: Some(MetricValue::Boolean(false)),
+                ..Metric::default()
+            },
+        ];
+        for def in &self.metrics {
+            let last = self.last.get(&def.key);
+            metrics.push(Metric {
+                name: Some(def.name.clone()),
+                alias: Some(def.alias),
+                timestamp: Some(last.map_or(now, |(_, timestamp)| *timestamp)),
+                datatype: Some(def.datatype),
+                is_null: last.is_none().then_some(true),
+                value: last.map(|(value, _)| metric_value(def.datatype, value)),
+            });
+        }
+        self.encode(now, metrics)
+    }
+
+    /// 下一次连接使用的 NDEATH
+    pub fn death(&self) -> Vec<u8> {
+        Payload {
+            timestamp: Some(now_millis()),
+            metrics: vec![bd_seq_metric(self.next_bd_seq)],
+            seq: None,
+        }
+        .encode_to_vec()
+    }
+
+    /// 当前连接的 NDEATH，正常关闭时主动发布
+    pub fn session_death(&self) -> Option<Vec<u8>> {
+        let bd_seq = self.session_bd_seq?;
+        Some(
+            Payload {
+                timestamp: Some(now_millis()),
+                metrics: vec![bd_seq_metric(bd_seq)],
+                seq: None,
+            }
+            .encode_to_vec(),
+        )
+    }
+
+    /// 记录采集数据，返回只包含这些点位（按 alias）的 NDATA
+    ///
+    /// # 返回值
+    /// * `None` - 没有属于已出生 metric 的点位，或还未发布 NBIRTH（此时只记录值，下一次 NBIRTH 时带上）
+    pub fn data(&mut self, readings: &[Reading]) -> Option<Vec<u8>> {
+        let mut metrics = Vec::new();
+        for reading in readings {
+            let key = (reading.gateway.clone(), reading.slave_id, reading.point.clone());
+            let Some(&alias) = self.aliases.get(&key) else {
+                continue;
+            };
+            let timestamp = millis(reading.timestamp);
+            let value = reading.point_value();
+            let datatype = self.metrics[alias as usize - 1].datatype;
+            let metric = metric_value(datatype, &value);
+            self.last.insert(key, (value, timestamp));
+            metrics.push(Metric {
+                alias: Some(alias),
+                timestamp: Some(timestamp),
+                datatype: Some(datatype),
+                value: Some(metric),
+                ..Metric::default()
+            });
+        }
+        if metrics.is_empty() || !self.is_born() {
+            return None;
+        }
+        Some(self.encode(now_millis(), metrics))
+    }
+
+    // 编码为带 seq 的消息，并把 seq 加1
+    fn encode(&mut self, timestamp: u64, metrics: Vec<Metric>) -> Vec<u8> {
+        let payload = Payload {
+            timestamp: Some(timestamp),
+            metrics,
+            seq: Some(self.seq as u64),
+        };
+        self.seq = self.seq.wrapping_add(1);
+        payload.encode_to_vec()
+    }
+}
+
+/// NCMD 消息是否为重新出生请求（`Node Control/Rebirth` 为 true）
+pub fn is_rebirth_request(payload: &[u8]) -> bool {
+    let Ok(payload) = Payload::decode(payload) else {
+        return false;
+    };
+    payload.metrics.iter().any(|metric| {
+        metric.name.as_deref() == Some(REBIRTH_METRIC)
+            && metric.value == Some(MetricValue::Boolean(true))
+    })
+}
+
+impl SessionHook for Mutex<SparkplugNode> {
+    fn last_will(&self) -> Option<LastWill> {
+        let node = lock(self);
+        Some(LastWill::new(
+            node.topic(MessageType::Death),
+            node.death(),
+            QoS::AtLeastOnce,
+            false,
+        ))
+    }
+
+    fn on_connected(&self) -> Vec<MqttMessage> {
+        let mut node = lock(self);
+        let payload = node.start_session();
+        vec![MqttMessage {
+            topic: node.topic(MessageType::Birth),
+            payload,
+            qos: QoS::AtMostOnce,
+            retain: false,
+        }]
+    }
+
+    fn on_shutdown(&self) -> Vec<MqttMessage> {
+        let mut node = lock(self);
+        let Some(payload) = node.session_death() else {
+            return Vec::new();
+        };
+        node.end_session();
+        vec![MqttMessage {
+            topic: node.topic(MessageType::Death),
+            payload,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+        }]
+    }
+}
+
+/// 重新发布 NBIRTH，用于主机应用请求重新出生或 metric 列表变化；未连接时不发布，连接后自动发布
+pub fn publish_birth(client: &MqttClient, node: &SharedNode) {
+    let (topic, payload) = {
+        let mut node = lock(node);
+        if !node.is_born() || !client.is_connected() {
+            return;
+        }
+        (node.topic(MessageType::Birth), node.birth())
+    };
+    if let Err(e) = client.try_publish(&topic, QoS::AtMostOnce, false, payload) {
+        warn!("发布 Sparkplug NBIRTH 失败: {}", e);
+    }
+}
+
+/// 订阅 NCMD 主题，收到重新出生请求时重新发布 NBIRTH，MQTT 客户端关闭时返回
+pub async fn run_commands(client: Arc<MqttClient>, node: SharedNode) {
+    let topic = lock(&node).topic(MessageType::Command);
+    let mut messages = match client.subscribe(&topic, QoS::AtLeastOnce).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            warn!("订阅 Sparkplug 命令主题 {} 失败: {}", topic, e);
+            return;
+        }
+    };
+    info!("已订阅 Sparkplug 命令主题 {}", topic);
+    while let Some(message) = messages.recv().await {
+        if is_rebirth_request(&message.payload) {
+            info!("收到 Sparkplug 重新出生请求，重新发布 NBIRTH");
+            publish_birth(&client, &node);
+        }
+    }
+}
+
+fn bd_seq_metric(bd_seq: u64) -> Metric {
+    Metric {
+        name: Some(BD_SEQ_METRIC.to_string()),
+        datatype: Some(data_type::UINT64),
+        value: Some(MetricValue::Long(bd_seq)),
+        ..Metric::default()
+    }
+}
+
+fn metric_value(datatype: u32, value: &PointValue) -> MetricValue {
+    match value {
+        PointValue::Text(text) => MetricValue::String(text.clone()),
+        PointValue::Number(value) if datatype == data_type::BOOLEAN => MetricValue::Boolean(*value != 0.0),
+        PointValue::Number(value) => MetricValue::Double(*value),
+    }
+}
+
+fn now_millis() -> u64 {
+    millis(SystemTime::now())
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// 锁不会在持有期间 panic，这里忽略锁中毒
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::mqtt::MqttSettings;
+    use crate::test_support::{reading, MockBroker, Received};
+
+    const CONFIG: &str = "version: 2\ngateways:\n  - ip: 10.0.0.1\n    name: PCS-A\n    slave_ids: [1]\n    points:\n      - { name: power, address: 0, data_type: f32 }\n      - { name: relay, function_code: 1, address: 0, data_type: bool }\n";
+
+    fn node() -> SparkplugNode {
+        let mut node = SparkplugNode::new(&Sparkplug {
+            group_id: "site".to_string(),
+            edge_node_id: "ems-1".to_string(),
+        });
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        assert!(node.set_metrics(&config).unwrap());
+        // 列表没有变化时不需要重新出生
+        assert!(!node.set_metrics(&config).unwrap());
+        node
+    }
+
+    fn decode(payload: &[u8]) -> Payload {
+        Payload::decode(payload).unwrap()
+    }
+
+    fn bd_seq(payload: &Payload) -> u64 {
+        let metric = payload
+            .metrics
+            .iter()
+            .find(|m| m.name.as_deref() == Some(BD_SEQ_METRIC))
+            .unwrap();
+        match metric.value {
+            Some(MetricValue::Long(bd_seq)) => bd_seq,
+            _ => panic!("bdSeq 的值不是 uint64: {:?}", metric),
+        }
+    }
+
+    #[test]
+    fn birth_lists_every_metric_with_alias_and_datatype() {
+        let mut node = node();
+        assert_eq!(node.topic(MessageType::Birth), "spBv1.0/site/NBIRTH/ems-1");
+        // 出生前采集到的值只记录下来，NBIRTH 时带上
+        assert!(node.data(&[reading("10.0.0.1:502", 1, "power", 12.5)]).is_none());
+
+        let birth = decode(&node.start_session());
+        assert_eq!(birth.seq, Some(0));
+        assert_eq!(bd_seq(&birth), 0);
+        assert_eq!(birth.metrics[2].timestamp, Some(1_700_000_000_000));
+        // 还没有采集到的点位使用出生时间，这里不比较时间
+        let metrics: Vec<Metric> = birth
+            .metrics
+            .into_iter()
+            .map(|metric| Metric { timestamp: None, ..metric })
+            .collect();
+        let metric = |name: &str, alias, datatype, value: Option<MetricValue>| Metric {
+            name: Some(name.to_string()),
+            alias,
+            timestamp: None,
+            datatype: Some(datatype),
+            is_null: value.is_none().then_some(true),
+            value,
+        };
+        assert_eq!(
+            metrics,
+            [
+                metric(BD_SEQ_METRIC, None, data_type::UINT64, Some(MetricValue::Long(0))),
+                metric(REBIRTH_METRIC, None, data_type::BOOLEAN, Some(MetricValue::Boolean(false))),
+                metric("PCS-A/1/power", Some(1), data_type::DOUBLE, Some(MetricValue::Double(12.5))),
+                metric("PCS-A/1/relay", Some(2), data_type::BOOLEAN, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_uses_aliases_and_seq_wraps_after_255() {
+        let mut node = node();
+        node.start_session();
+        let data = decode(&node.data(&[reading("10.0.0.1:502", 1, "relay", 1.0), reading("10.0.0.1:502", 9, "other", 1.0)]).unwrap());
+        assert_eq!(data.seq, Some(1));
+        assert_eq!(data.metrics.len(), 1);
+        assert_eq!(data.metrics[0].name, None);
+        assert_eq!(data.metrics[0].alias, Some(2));
+        assert_eq!(data.metrics[0].value, Some(MetricValue::Boolean(true)));
+        assert_eq!(data.metrics[0].timestamp, Some(1_700_000_000_000));
+
+        let power = [reading("10.0.0.1:502", 1, "power", 1.0)];
+        let seqs: Vec<u64> = (0..256).map(|_| decode(&node.data(&power).unwrap()).seq.unwrap()).collect();
+        assert_eq!(seqs[0], 2);
+        assert_eq!(seqs[253], 255);
+        assert_eq!(seqs[254], 0);
+        // 重新出生时 seq 回到0，bdSeq 不变
+        let birth = decode(&node.birth());
+        assert_eq!((birth.seq, bd_seq(&birth)), (Some(0), 0));
+    }
+
+    #[test]
+    fn rebirth_requests_are_recognized() {
+        let request = |value: bool| {
+            Payload {
+                timestamp: Some(0),
+                metrics: vec![Metric {
+                    name: Some(REBIRTH_METRIC.to_string()),
+                    datatype: Some(data_type::BOOLEAN),
+                    value: Some(MetricValue::Boolean(value)),
+                    ..Metric::default()
+                }],
+                seq: None,
+            }
+            .encode_to_vec()
+        };
+        assert!(is_rebirth_request(&request(true)));
+        assert!(!is_rebirth_request(&request(false)));
+        assert!(!is_rebirth_request(b"not protobuf \xff"));
+    }
+
+    #[tokio::test]
+    async fn bd_seq_and_seq_across_reconnect() {
+        let broker = MockBroker::start().await;
+        let mut settings = MqttSettings::new("127.0.0.1", "ems-1");
+        settings.broker_port = broker.port;
+        settings.reconnect_delay_ms = 100;
+        let node: SharedNode = Arc::new(Mutex::new(node()));
+        let hook = Arc::clone(&node) as Arc<dyn SessionHook>;
+        let client = MqttClient::from_settings(&settings, Some(hook)).unwrap();
+        let birth_topic = "spBv1.0/site/NBIRTH/ems-1";
+        broker.wait_for_topic(birth_topic, 1).await;
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+
+        let data = node.lock().unwrap().data(&[reading("10.0.0.1:502", 1, "power", 3.0)]).unwrap();
+        assert_eq!(decode(&data).seq, Some(1));
+
+        broker.drop_connections();
+        let births = broker.wait_for_topic(birth_topic, 2).await;
+        let births: Vec<Payload> = births.iter().map(|p| decode(p)).collect();
+        assert_eq!(births.iter().map(|b| (b.seq, bd_seq(b))).collect::<Vec<_>>(), [(Some(0), 0), (Some(0), 1)]);
+        // 重连后 NBIRTH 带有断线前的值
+        assert!(births[1].metrics.iter().any(|m| m.alias == Some(1) && m.value == Some(MetricValue::Double(3.0))));
+
+        // 每次连接的遗嘱 NDEATH 与该连接的 NBIRTH 使用相同的 bdSeq
+        let wills: Vec<u64> = broker
+            .connects()
+            .into_iter()
+            .map(|connect| {
+                let will = connect.last_will.unwrap();
+                assert_eq!(will.topic, "spBv1.0/site/NDEATH/ems-1");
+                bd_seq(&decode(&will.message))
+            })
+            .collect();
+        assert_eq!(wills, [0, 1]);
+
+        // 正常关闭时主动发布当前连接的 NDEATH
+        client.close().await;
+        let deaths = broker.wait_for_topic("spBv1.0/site/NDEATH/ems-1", 1).await;
+        assert_eq!(bd_seq(&decode(&deaths[0])), 1);
+        assert!(!node.lock().unwrap().is_born());
+        assert!(broker.received().iter().any(|p| matches!(p, Received::Disconnect)));
+    }
+}
@@ -0,0 +1,296 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::device_configuration::mqtt::MqttTls;
+
+/// 根据 TLS 配置创建 rustls 客户端配置
+///
+/// # 说明
+/// * 未配置 ca_cert_path 时使用系统的根证书
+/// * 配置了客户端证书时检查私钥与证书是否匹配
+/// * 错误信息中包含出错的配置项和文件路径
+pub fn client_config(tls: &MqttTls) -> Result<ClientConfig, Box<dyn Error>> {
+    let builder = ClientConfig::builder();
+    let builder = if tls.insecure_skip_verify {
+        warn!("已设置 mqtt.tls.insecure_skip_verify，不校验 Broker 的证书");
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(
+                ring::default_provider().signature_verification_algorithms,
+            )))
+    } else {
+        let roots = match &tls.ca_cert_path {
+            Some(path) => root_store(path)?,
+            None => native_root_store()?,
+        };
+        builder.with_root_certificates(roots)
+    };
+
+    match (&tls.client_cert_path, &tls.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = read_certs("mqtt.tls.client_cert_path", cert_path)?;
+            let key = read_key(key_path)?;
+            check_key_matches(cert_path, key_path, &certs[0], &key)?;
+            builder.with_client_auth_cert(certs, key).map_err(|e| {
+                format!(
+                    "客户端证书 {} 和私钥 {} 无法使用: {}",
+                    cert_path, key_path, e
+                )
+                .into()
+            })
+        }
+        _ => Ok(builder.with_no_client_auth()),
+    }
+}
+
+// 读取 PEM 文件中的所有证书，文件中没有证书时报错
+fn read_certs(field: &str, path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("{} 引用的文件 {} 无法读取: {}", field, path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{} 引用的文件 {} 不是有效的 PEM 证书: {}", field, path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{} 引用的文件 {} 中没有证书", field, path).into());
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, Box<dyn Error>> {
+    let field = "mqtt.tls.client_key_path";
+    let file = File::open(path).map_err(|e| format!("{} 引用的文件 {} 无法读取: {}", field, path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("{} 引用的文件 {} 不是有效的 PEM 私钥: {}", field, path, e))?
+        .ok_or_else(|| format!("{} 引用的文件 {} 中没有私钥", field, path).into())
+}
+
+fn root_store(path: &str) -> Result<RootCertStore, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs("mqtt.tls.ca_cert_path", path)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("mqtt.tls.ca_cert_path 引用的文件 {} 中的证书无效: {}", path, e))?;
+    }
+    Ok(roots)
+}
+
+fn native_root_store() -> Result<RootCertStore, Box<dyn Error>> {
+    let certs = rustls_native_certs::load_native_certs()
+        .map_err(|e| format!("无法加载系统根证书，请配置 mqtt.tls.ca_cert_path: {}", e))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(certs);
+    if added == 0 {
+        return Err("系统中没有可用的根证书，请配置 mqtt.tls.ca_cert_path".into());
+    }
+    Ok(roots)
+}
+
+// 用私钥签名一段数据，再用证书中的公钥验证，验证失败说明两者不是一对
+fn check_key_matches(
+    cert_path: &str,
+    key_path: &str,
+    cert: &CertificateDer<'_>,
+    key: &PrivateKeyDer<'_>,
+) -> Result<(), Box<dyn Error>> {
+    const MESSAGE: &[u8] = b"ems client certificate check";
+    let mismatch = || -> Box<dyn Error> {
+        format!("客户端证书 {} 与私钥 {} 不匹配", cert_path, key_path).into()
+    };
+
+    let signing_key = ring::sign::any_supported_type(key)
+        .map_err(|e| format!("mqtt.tls.client_key_path 引用的私钥 {} 无法使用: {}", key_path, e))?;
+    let algorithms = ring::default_provider().signature_verification_algorithms;
+    let signer = signing_key
+        .choose_scheme(&algorithms.supported_schemes())
+        .ok_or_else(mismatch)?;
+    let signature = signer.sign(MESSAGE).map_err(|_| mismatch())?;
+
+    // 无法解析的证书（例如 X.509 v1）交给 Broker 判断，这里不做检查
+    let Ok(cert) = webpki::EndEntityCert::try_from(cert) else {
+        return Ok(());
+    };
+    let verified = algorithms
+        .mapping
+        .iter()
+        .filter(|(scheme, _)| *scheme == signer.scheme())
+        .flat_map(|(_, candidates)| candidates.iter())
+        .any(|algorithm| cert.verify_signature(*algorithm, MESSAGE, &signature).is_ok());
+    if verified { Ok(()) } else { Err(mismatch()) }
+}
+
+// insecure_skip_verify 使用的证书校验器：接受任何证书，但仍校验握手签名
+#[derive(Debug)]
+struct NoVerification(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_configuration::mqtt::MqttSettings;
+    use crate::mqtt::client::{mqtt_options, MqttClient};
+    use rumqttc::{TlsConfiguration, Transport};
+
+    // tests/fixtures/tls 下的测试证书：ca.pem 签发了 client.pem，client.key 是其私钥，other.key 是另一个私钥
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn mutual_tls() -> MqttTls {
+        MqttTls {
+            ca_cert_path: Some(fixture("ca.pem")),
+            client_cert_path: Some(fixture("client.pem")),
+            client_key_path: Some(fixture("client.key")),
+            insecure_skip_verify: false,
+        }
+    }
+
+    fn error(tls: &MqttTls) -> String {
+        client_config(tls).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn loads_ca_and_client_certificate() {
+        let config = client_config(&mutual_tls()).unwrap();
+        assert!(config.client_auth_cert_resolver.has_certs());
+
+        // 只配置 CA 证书时不使用双向认证
+        let tls = MqttTls {
+            client_cert_path: None,
+            client_key_path: None,
+            ..mutual_tls()
+        };
+        assert!(!client_config(&tls).unwrap().client_auth_cert_resolver.has_certs());
+    }
+
+    #[test]
+    fn insecure_skip_verify_does_not_need_ca() {
+        let tls = MqttTls {
+            ca_cert_path: Some(fixture("missing.pem")),
+            insecure_skip_verify: true,
+            ..mutual_tls()
+        };
+        assert!(client_config(&tls).is_ok());
+    }
+
+    #[test]
+    fn missing_files_are_reported_with_path() {
+        for (tls, field, name) in [
+            (
+                MqttTls { ca_cert_path: Some(fixture("missing-ca.pem")), ..mutual_tls() },
+                "mqtt.tls.ca_cert_path",
+                "missing-ca.pem",
+            ),
+            (
+                MqttTls { client_cert_path: Some(fixture("missing-client.pem")), ..mutual_tls() },
+                "mqtt.tls.client_cert_path",
+                "missing-client.pem",
+            ),
+            (
+                MqttTls { client_key_path: Some(fixture("missing-client.key")), ..mutual_tls() },
+                "mqtt.tls.client_key_path",
+                "missing-client.key",
+            ),
+        ] {
+            let message = error(&tls);
+            assert!(message.starts_with(field), "{}", message);
+            assert!(message.contains(&fixture(name)), "{}", message);
+            assert!(message.contains("无法读取"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        // 私钥文件中没有证书
+        let tls = MqttTls { ca_cert_path: Some(fixture("client.key")), ..mutual_tls() };
+        assert_eq!(
+            error(&tls),
+            format!("mqtt.tls.ca_cert_path 引用的文件 {} 中没有证书", fixture("client.key"))
+        );
+        // 证书文件中没有私钥
+        let tls = MqttTls { client_key_path: Some(fixture("client.pem")), ..mutual_tls() };
+        assert_eq!(
+            error(&tls),
+            format!("mqtt.tls.client_key_path 引用的文件 {} 中没有私钥", fixture("client.pem"))
+        );
+    }
+
+    #[test]
+    fn mismatched_key_is_rejected() {
+        let tls = MqttTls { client_key_path: Some(fixture("other.key")), ..mutual_tls() };
+        assert_eq!(
+            error(&tls),
+            format!("客户端证书 {} 与私钥 {} 不匹配", fixture("client.pem"), fixture("other.key"))
+        );
+    }
+
+    #[test]
+    fn settings_map_to_tls_transport() {
+        let mut settings = MqttSettings::new("broker.local", "tls-test");
+        settings.broker_port = 8883;
+        settings.username = Some("device-1".to_string());
+        settings.password = Some("secret".to_string());
+        settings.tls = Some(mutual_tls());
+        let options = mqtt_options(&settings).unwrap();
+        assert_eq!(options.broker_address(), ("broker.local".to_string(), 8883));
+        assert_eq!(
+            options.credentials(),
+            Some(("device-1".to_string(), "secret".to_string()))
+        );
+        assert!(matches!(
+            options.transport(),
+            Transport::Tls(TlsConfiguration::Rustls(config)) if config.client_auth_cert_resolver.has_certs()
+        ));
+
+        // 没有 TLS 配置时使用 TCP
+        settings.tls = None;
+        assert!(matches!(mqtt_options(&settings).unwrap().transport(), Transport::Tcp));
+    }
+
+    #[tokio::test]
+    async fn client_fails_at_startup_when_certificate_is_missing() {
+        let mut settings = MqttSettings::new("127.0.0.1", "tls-test");
+        settings.tls = Some(MqttTls { client_cert_path: Some(fixture("missing-client.pem")), ..mutual_tls() });
+        let message = MqttClient::from_settings(&settings, None).err().unwrap().to_string();
+        assert!(message.contains(&fixture("missing-client.pem")), "{}", message);
+    }
+}
@@ -0,0 +1,477 @@
+use std::error::Error;
+use std::fmt;
+
+/// 默认的数据主题模板
+pub const DEFAULT_DATA_TEMPLATE: &str = "{prefix}/{gateway}/{slave}";
+/// 默认的可用性主题模板
+pub const DEFAULT_AVAILABILITY_TEMPLATE: &str = "{prefix}/{gateway}/{slave}/availability";
+/// 默认的命令主题模板
+pub const DEFAULT_COMMAND_TEMPLATE: &str = "{prefix}/{gateway}/{slave}/cmd";
+/// 点位未指定采集组时 `{group}` 展开的值
+pub const DEFAULT_GROUP: &str = "default";
+
+/// 主题模板中的占位符
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// `{prefix}`：mqtt.topic_prefix，可以为空
+    Prefix,
+    /// `{site}`：mqtt.site，可以不配置
+    Site,
+    /// `{gateway}`：网关名称，未配置时为 ip:port
+    Gateway,
+    /// `{gateway_host}`：网关 IP
+    GatewayHost,
+    /// `{slave}`：从站名称，未配置时为从站ID
+    Slave,
+    /// `{point}`：点位名称
+    Point,
+    /// `{group}`：采集组名称，未指定时为 default
+    Group,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 7] = [
+        Placeholder::Prefix,
+        Placeholder::Site,
+        Placeholder::Gateway,
+        Placeholder::GatewayHost,
+        Placeholder::Slave,
+        Placeholder::Point,
+        Placeholder::Group,
+    ];
+
+    /// 设备级主题（可用性、命令）可以使用的占位符
+    pub const DEVICE: &'static [Placeholder] = &[
+        Placeholder::Prefix,
+        Placeholder::Site,
+        Placeholder::Gateway,
+        Placeholder::GatewayHost,
+        Placeholder::Slave,
+    ];
+
+    /// 数据主题可以使用的占位符
+    pub const DATA: &'static [Placeholder] = &Placeholder::ALL;
+
+    fn name(self) -> &'static str {
+        match self {
+            Placeholder::Prefix => "prefix",
+            Placeholder::Site => "site",
+            Placeholder::Gateway => "gateway",
+            Placeholder::GatewayHost => "gateway_host",
+            Placeholder::Slave => "slave",
+            Placeholder::Point => "point",
+            Placeholder::Group => "group",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Placeholder> {
+        Placeholder::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    // 值可能为空的占位符，整段只有它且值为空时该段省略
+    fn is_optional(self) -> bool {
+        matches!(self, Placeholder::Prefix | Placeholder::Site)
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{{}}}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// 展开主题模板所需的值
+#[derive(Debug, Clone, Default)]
+pub struct TopicValues<'a> {
+    /// 主题前缀
+    pub prefix: &'a str,
+    /// 站点名称
+    pub site: Option<&'a str>,
+    /// 网关名称，未配置时为 ip:port
+    pub gateway: &'a str,
+    /// 网关IP地址
+    pub gateway_host: &'a str,
+    /// 从站名称，未配置时为从站ID
+    pub slave: &'a str,
+    /// 点位名称
+    pub point: Option<&'a str>,
+    /// 采集组名称
+    pub group: Option<&'a str>,
+}
+
+impl TopicValues<'_> {
+    fn get(&self, placeholder: Placeholder) -> &str {
+        match placeholder {
+            Placeholder::Prefix => self.prefix,
+            Placeholder::Site => self.site.unwrap_or(""),
+            Placeholder::Gateway => self.gateway,
+            Placeholder::GatewayHost => self.gateway_host,
+            Placeholder::Slave => self.slave,
+            Placeholder::Point => self.point.unwrap_or(""),
+            Placeholder::Group => self.group.unwrap_or(DEFAULT_GROUP),
+        }
+    }
+}
+
+/// 解析后的主题模板，例如 `site/{site}/modbus/{gateway}/{slave}/{point}`
+///
+/// # 说明
+/// * 按 `/` 分段，整段只有 `{prefix}` 或 `{site}` 且值为空时该段省略，
+///   因此 `topic_prefix` 为空时默认模板展开为 `<网关>/<从站>`
+/// * `{prefix}` 可以包含多段，例如 `site/a`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicTemplate {
+    template: String,
+    segments: Vec<Vec<Part>>,
+}
+
+impl TopicTemplate {
+    /// 解析并校验主题模板
+    ///
+    /// # 参数说明
+    /// * `field` - 配置项名称，用于错误信息
+    /// * `template` - 模板字符串
+    /// * `allowed` - 允许使用的占位符
+    ///
+    /// # 校验规则
+    /// * 不能为空，不能以 `/` 开头或结尾，不能有空段
+    /// * 不能包含 `+`、`#` 和空白字符
+    /// * 只能使用 `allowed` 中的占位符，花括号必须成对
+    pub fn parse(
+        field: &str,
+        template: &str,
+        allowed: &[Placeholder],
+    ) -> Result<TopicTemplate, Box<dyn Error>> {
+        let invalid = |reason: &str| -> Box<dyn Error> {
+            format!("{} \"{}\" {}", field, template, reason).into()
+        };
+        if template.is_empty() {
+            return Err(format!("{} 不能为空", field).into());
+        }
+        if template.starts_with('/') || template.ends_with('/') {
+            return Err(invalid("不能以 / 开头或结尾"));
+        }
+        if let Some(c) = template
+            .chars()
+            .find(|c| matches!(c, '+' | '#') || c.is_whitespace())
+        {
+            return Err(invalid(&format!("包含不能用于 MQTT 主题的字符 {:?}", c)));
+        }
+
+        let mut segments = Vec::new();
+        for segment in template.split('/') {
+            if segment.is_empty() {
+                return Err(invalid("包含空段"));
+            }
+            let mut parts = Vec::new();
+            let mut rest = segment;
+            while !rest.is_empty() {
+                match rest.find(['{', '}']) {
+                    Some(0) if rest.starts_with('{') => {
+                        let end = rest.find('}').ok_or_else(|| invalid("的花括号不成对"))?;
+                        let name = &rest[1..end];
+                        let placeholder = Placeholder::from_name(name)
+                            .ok_or_else(|| invalid(&format!("使用了未知的占位符 {{{}}}", name)))?;
+                        if !allowed.contains(&placeholder) {
+                            return Err(invalid(&format!("中不能使用 {}", placeholder)));
+                        }
+                        parts.push(Part::Placeholder(placeholder));
+                        rest = &rest[end + 1..];
+                    }
+                    Some(0) => return Err(invalid("的花括号不成对")),
+                    Some(index) => {
+                        parts.push(Part::Literal(rest[..index].to_string()));
+                        rest = &rest[index..];
+                    }
+                    None => {
+                        parts.push(Part::Literal(rest.to_string()));
+                        rest = "";
+                    }
+                }
+            }
+            segments.push(parts);
+        }
+        Ok(TopicTemplate {
+            template: template.to_string(),
+            segments,
+        })
+    }
+
+    /// 模板是否使用了某个占位符
+    pub fn uses(&self, placeholder: Placeholder) -> bool {
+        self.segments
+            .iter()
+            .flatten()
+            .any(|part| *part == Part::Placeholder(placeholder))
+    }
+
+    /// 展开模板
+    pub fn expand(&self, values: &TopicValues) -> String {
+        let mut segments = Vec::with_capacity(self.segments.len());
+        for parts in &self.segments {
+            if let [Part::Placeholder(placeholder)] = parts.as_slice()
+                && placeholder.is_optional()
+                && values.get(*placeholder).is_empty()
+            {
+                continue;
+            }
+            let mut segment = String::new();
+            for part in parts {
+                match part {
+                    Part::Literal(text) => segment.push_str(text),
+                    Part::Placeholder(placeholder) => segment.push_str(values.get(*placeholder)),
+                }
+            }
+            segments.push(segment);
+        }
+        segments.join("/")
+    }
+
+    /// 生成订阅用的主题过滤器
+    ///
+    /// `{prefix}` 和 `{site}` 按配置展开，其余占位符所在的段替换为 `+`，
+    /// 例如 `{prefix}/{gateway}/{slave}/cmd` 在前缀为 `ems` 时为 `ems/+/+/cmd`。
+    pub fn subscription_filter(&self, prefix: &str, site: Option<&str>) -> String {
+        let values = TopicValues {
+            prefix,
+            site,
+            ..TopicValues::default()
+        };
+        let mut segments = Vec::with_capacity(self.segments.len());
+        for parts in &self.segments {
+            let dynamic = parts
+                .iter()
+                .any(|part| matches!(part, Part::Placeholder(p) if !p.is_optional()));
+            if dynamic {
+                segments.push("+".to_string());
+                continue;
+            }
+            let segment = TopicTemplate {
+                template: String::new(),
+                segments: vec![parts.clone()],
+            }
+            .expand(&values);
+            if !segment.is_empty() {
+                segments.push(segment);
+            }
+        }
+        segments.join("/")
+    }
+
+    /// 检查在给定的前缀和站点名称下，展开结果不会为空
+    pub fn check_not_empty(&self, field: &str, prefix: &str, site: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let only_optional = self.segments.iter().all(|parts| match parts.as_slice() {
+            [Part::Placeholder(placeholder)] => placeholder.is_optional(),
+            _ => false,
+        });
+        let values = TopicValues {
+            prefix,
+            site,
+            ..TopicValues::default()
+        };
+        if only_optional && self.expand(&values).is_empty() {
+            return Err(format!("{} \"{}\" 展开后为空", field, self.template).into());
+        }
+        Ok(())
+    }
+}
+
+/// 主题是否匹配订阅使用的主题过滤器
+///
+/// # 说明
+/// * `+` 匹配一个层级，`#` 匹配所在位置及之后的任意层级（`a/#` 也匹配 `a`）
+/// * 以通配符开头的过滤器不匹配以 `$` 开头的主题，例如 `#` 不匹配 `$SYS/broker/uptime`
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) if filter_level == topic_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+impl fmt::Display for TopicTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_filters_match_only_the_same_topic() {
+        assert!(topic_matches("ems/cmd/write", "ems/cmd/write"));
+        assert!(!topic_matches("ems/cmd/write", "ems/cmd"));
+        assert!(!topic_matches("ems/cmd", "ems/cmd/write"));
+        assert!(!topic_matches("ems/cmd/", "ems/cmd"));
+        assert!(topic_matches("ems/cmd/", "ems/cmd/"));
+    }
+
+    #[test]
+    fn plus_matches_exactly_one_level() {
+        assert!(topic_matches("ems/+/cmd", "ems/PCS-A/cmd"));
+        assert!(topic_matches("ems/+/cmd", "ems//cmd"));
+        assert!(!topic_matches("ems/+/cmd", "ems/PCS-A/1/cmd"));
+        assert!(!topic_matches("ems/+", "ems"));
+        assert!(topic_matches("+/+", "a/b"));
+        assert!(topic_matches("+", "电表"));
+    }
+
+    #[test]
+    fn hash_matches_remaining_levels_including_parent() {
+        assert!(topic_matches("ems/#", "ems"));
+        assert!(topic_matches("ems/#", "ems/PCS-A/1/cmd"));
+        assert!(topic_matches("ems/+/#", "ems/PCS-A"));
+        assert!(!topic_matches("ems/#", "other/ems"));
+        assert!(topic_matches("#", "a/b/c"));
+    }
+
+    #[test]
+    fn wildcards_at_start_do_not_match_system_topics() {
+        assert!(!topic_matches("#", "$SYS/broker/uptime"));
+        assert!(!topic_matches("+/broker/uptime", "$SYS/broker/uptime"));
+        assert!(topic_matches("$SYS/#", "$SYS/broker/uptime"));
+    }
+
+    fn values<'a>(prefix: &'a str, site: Option<&'a str>) -> TopicValues<'a> {
+        TopicValues {
+            prefix,
+            site,
+            gateway: "PCS-A",
+            gateway_host: "10.0.0.1",
+            slave: "meter",
+            point: Some("power"),
+            group: None,
+        }
+    }
+
+    fn parse_error(template: &str, allowed: &[Placeholder]) -> String {
+        TopicTemplate::parse("mqtt.topic_template", template, allowed)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn templates_expand_every_placeholder() {
+        let template = TopicTemplate::parse(
+            "mqtt.topic_template",
+            "{prefix}/{site}/gw-{gateway}@{gateway_host}/{slave}/{group}/{point}",
+            Placeholder::DATA,
+        )
+        .unwrap();
+        assert_eq!(
+            template.expand(&values("plant/a", Some("north"))),
+            "plant/a/north/gw-PCS-A@10.0.0.1/meter/default/power"
+        );
+        let mut grouped = values("ems", Some("north"));
+        grouped.group = Some("fast");
+        assert_eq!(template.expand(&grouped), "ems/north/gw-PCS-A@10.0.0.1/meter/fast/power");
+        assert!(template.uses(Placeholder::Point));
+        assert_eq!(template.to_string(), "{prefix}/{site}/gw-{gateway}@{gateway_host}/{slave}/{group}/{point}");
+
+        // 不含占位符的模板原样展开
+        let fixed = TopicTemplate::parse("mqtt.topic_template", "plant/data", Placeholder::DATA).unwrap();
+        assert_eq!(fixed.expand(&values("ems", None)), "plant/data");
+        assert!(!fixed.uses(Placeholder::Gateway));
+    }
+
+    #[test]
+    fn empty_optional_segments_are_omitted() {
+        let template = TopicTemplate::parse("mqtt.topic_template", "{prefix}/{site}/{gateway}/{slave}", Placeholder::DATA)
+            .unwrap();
+        assert_eq!(template.expand(&values("ems", Some("north"))), "ems/north/PCS-A/meter");
+        assert_eq!(template.expand(&values("ems", None)), "ems/PCS-A/meter");
+        assert_eq!(template.expand(&values("", Some("north"))), "north/PCS-A/meter");
+        assert_eq!(template.expand(&values("", None)), "PCS-A/meter");
+
+        // 与其他文字同段时不省略该段
+        let template = TopicTemplate::parse("mqtt.topic_template", "site-{site}/{slave}", Placeholder::DATA).unwrap();
+        assert_eq!(template.expand(&values("", None)), "site-/meter");
+    }
+
+    #[test]
+    fn illegal_templates_are_rejected() {
+        let data = Placeholder::DATA;
+        assert_eq!(parse_error("", data), "mqtt.topic_template 不能为空");
+        assert_eq!(parse_error("/ems/{slave}", data), "mqtt.topic_template \"/ems/{slave}\" 不能以 / 开头或结尾");
+        assert_eq!(parse_error("ems/{slave}/", data), "mqtt.topic_template \"ems/{slave}/\" 不能以 / 开头或结尾");
+        assert_eq!(parse_error("ems//{slave}", data), "mqtt.topic_template \"ems//{slave}\" 包含空段");
+        assert_eq!(
+            parse_error("ems/+/{slave}", data),
+            "mqtt.topic_template \"ems/+/{slave}\" 包含不能用于 MQTT 主题的字符 '+'"
+        );
+        assert_eq!(
+            parse_error("ems/{slave}/#", data),
+            "mqtt.topic_template \"ems/{slave}/#\" 包含不能用于 MQTT 主题的字符 '#'"
+        );
+        assert_eq!(
+            parse_error("ems/{slave} x", data),
+            "mqtt.topic_template \"ems/{slave} x\" 包含不能用于 MQTT 主题的字符 ' '"
+        );
+        assert_eq!(
+            parse_error("ems/{device}", data),
+            "mqtt.topic_template \"ems/{device}\" 使用了未知的占位符 {device}"
+        );
+        for unbalanced in ["ems/{slave", "ems/slave}", "ems/{gateway/{slave}", "ems/}{slave}"] {
+            assert_eq!(
+                parse_error(unbalanced, data),
+                format!("mqtt.topic_template \"{}\" 的花括号不成对", unbalanced),
+                "{}",
+                unbalanced
+            );
+        }
+        // 设备级主题不能使用点位和采集组
+        assert_eq!(
+            parse_error("{prefix}/{slave}/{point}", Placeholder::DEVICE),
+            "mqtt.topic_template \"{prefix}/{slave}/{point}\" 中不能使用 {point}"
+        );
+        assert!(TopicTemplate::parse("f", "{prefix}/{group}", Placeholder::DEVICE).is_err());
+    }
+
+    #[test]
+    fn subscription_filters_replace_device_segments_with_plus() {
+        let command = TopicTemplate::parse("f", DEFAULT_COMMAND_TEMPLATE, Placeholder::DEVICE).unwrap();
+        assert_eq!(command.subscription_filter("ems", None), "ems/+/+/cmd");
+        assert_eq!(command.subscription_filter("plant/a", Some("north")), "plant/a/+/+/cmd");
+        assert_eq!(command.subscription_filter("", None), "+/+/cmd");
+
+        let template = TopicTemplate::parse("f", "{prefix}/{site}/dev-{gateway_host}/{slave}/cmd", Placeholder::DEVICE)
+            .unwrap();
+        assert_eq!(template.subscription_filter("ems", Some("north")), "ems/north/+/+/cmd");
+        assert_eq!(template.subscription_filter("ems", None), "ems/+/+/cmd");
+        // 过滤器匹配展开后的主题
+        let topic = template.expand(&values("ems", Some("north")));
+        assert!(topic_matches(&template.subscription_filter("ems", Some("north")), &topic));
+    }
+
+    #[test]
+    fn templates_that_expand_to_nothing_are_rejected() {
+        let template = TopicTemplate::parse("mqtt.topic_template", "{prefix}/{site}", Placeholder::DATA).unwrap();
+        assert!(template.check_not_empty("mqtt.topic_template", "ems", None).is_ok());
+        assert!(template.check_not_empty("mqtt.topic_template", "", Some("north")).is_ok());
+        assert_eq!(
+            template.check_not_empty("mqtt.topic_template", "", None).unwrap_err().to_string(),
+            "mqtt.topic_template \"{prefix}/{site}\" 展开后为空"
+        );
+
+        // 含有其他段的模板总能展开出内容
+        let template = TopicTemplate::parse("f", DEFAULT_DATA_TEMPLATE, Placeholder::DATA).unwrap();
+        assert!(template.check_not_empty("f", "", None).is_ok());
+    }
+}
@@ -0,0 +1,465 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use crate::modbus::availability::DeviceAvailability;
+use crate::modbus::reading::Reading;
+use crate::modbus::scheduler::PollEvent;
+use crate::modbus::watchdog::WatchdogEvent;
+
+/// 每个输出默认可以积压的采集数据批数
+pub const DEFAULT_SINK_CAPACITY: usize = 64;
+
+/// 采集数据的输出，例如 MQTT 发布、CSV 文件、日志
+///
+/// 每个输出在自己的任务中按顺序收到采集事件，处理得慢只会让自己的队列积压，
+/// 不会影响采集任务和其他输出。
+#[async_trait::async_trait]
+pub trait Sink: Send {
+    /// 处理一个采集周期得到的一批点位数据
+    async fn deliver(&mut self, batch: &[Reading]);
+
+    /// 处理从站可用性的变化，默认忽略
+    async fn availability(&mut self, _device: &DeviceAvailability) {}
+
+    /// 处理看门狗心跳写入失败或恢复，默认忽略
+    async fn watchdog(&mut self, _event: &WatchdogEvent) {}
+
+    /// 定期调用 [`Sink::flush`] 的间隔，默认不定期调用
+    fn flush_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// 写出缓存的数据，按 [`Sink::flush_interval`] 定期调用，停止前还会调用一次
+    async fn flush(&mut self) {}
+}
+
+/// 一个输出的投递统计
+#[derive(Debug)]
+pub struct SinkStats {
+    name: String,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    queued: AtomicU64,
+    // 队列中等待处理的采集数据批数，不含可用性和看门狗事件
+    queued_readings: AtomicU64,
+    overflowing: AtomicBool,
+}
+
+impl SinkStats {
+    /// 输出名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 已交给输出处理的事件数
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+
+    /// 队列已满被丢弃的采集数据批数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 队列中等待处理的事件数
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
+
+struct SinkInput {
+    sender: mpsc::UnboundedSender<PollEvent>,
+    capacity: u64,
+    stats: Arc<SinkStats>,
+}
+
+/// 把采集事件分发给各个输出
+///
+/// # 说明
+/// * 每个输出有一个队列和一个处理任务，采集任务通过 [`PipelineSender`] 把事件放入所有队列，输出按放入的顺序处理
+/// * 队列中的采集数据达到容量时丢弃该输出的这批数据并计数，采集任务不会因为某个输出处理得慢而停顿
+/// * 可用性和看门狗事件不受容量限制、不会丢弃，保留的可用性主题不会停留在错误的状态；这类事件只在状态变化时产生，不会大量积压
+/// * [`Pipeline::close`] 关闭所有队列，等各输出处理完已入队的事件并调用 [`Sink::flush`] 后返回
+pub struct Pipeline {
+    inputs: Arc<Mutex<Vec<SinkInput>>>,
+    stats: Vec<Arc<SinkStats>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipeline {
+    /// 创建没有输出的分发器
+    pub fn new() -> Self {
+        Pipeline {
+            inputs: Arc::new(Mutex::new(Vec::new())),
+            stats: Vec::new(),
+            handles: Vec::new(),
+        }
+    }
+
+    /// 添加一个输出并启动它的处理任务
+    ///
+    /// # 参数说明
+    /// * `name` - 输出名称，用于日志和统计
+    /// * `capacity` - 队列中最多积压的采集数据批数，至少为1
+    /// * `sink` - 输出
+    pub fn add_sink<S: Sink + 'static>(&mut self, name: &str, capacity: usize, mut sink: S) {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<PollEvent>();
+        let stats = Arc::new(SinkStats {
+            name: name.to_string(),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            queued: AtomicU64::new(0),
+            queued_readings: AtomicU64::new(0),
+            overflowing: AtomicBool::new(false),
+        });
+        let task_stats = Arc::clone(&stats);
+        self.handles.push(tokio::spawn(async move {
+            let mut ticker = sink.flush_interval().map(|period| {
+                let mut ticker = tokio::time::interval_at(Instant::now() + period, period);
+                ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                ticker
+            });
+            loop {
+                let event = tokio::select! {
+                    event = receiver.recv() => event,
+                    _ = tick(&mut ticker) => {
+                        sink.flush().await;
+                        continue;
+                    }
+                };
+                let Some(event) = event else {
+                    break;
+                };
+                task_stats.queued.fetch_sub(1, Ordering::Relaxed);
+                match &event {
+                    PollEvent::Readings(batch) => {
+                        task_stats.queued_readings.fetch_sub(1, Ordering::Relaxed);
+                        sink.deliver(batch).await
+                    }
+                    PollEvent::Availability(device) => sink.availability(device).await,
+                    PollEvent::Watchdog(watchdog) => sink.watchdog(watchdog).await,
+                }
+                task_stats.delivered.fetch_add(1, Ordering::Relaxed);
+            }
+            sink.flush().await;
+            debug!(sink = %task_stats.name, "输出已停止");
+        }));
+        self.inputs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(SinkInput {
+                sender,
+                capacity: capacity.max(1) as u64,
+                stats: Arc::clone(&stats),
+            });
+        self.stats.push(stats);
+    }
+
+    /// 供采集任务使用的发送端，可以克隆
+    pub fn sender(&self) -> PipelineSender {
+        PipelineSender {
+            inputs: Arc::clone(&self.inputs),
+        }
+    }
+
+    /// 各输出的投递统计
+    pub fn stats(&self) -> &[Arc<SinkStats>] {
+        &self.stats
+    }
+
+    /// 关闭所有队列，等待各输出处理完已入队的事件
+    ///
+    /// 关闭后发送端仍可使用，但发送的事件会被忽略。
+    pub async fn close(self) {
+        self.inputs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        for handle in self.handles {
+            let _ = handle.await;
+        }
+        for stats in &self.stats {
+            if stats.dropped() > 0 {
+                warn!(sink = %stats.name, dropped = stats.dropped(), "输出处理不及时，部分采集数据被丢弃");
+            }
+        }
+    }
+}
+
+// 没有定期调用时一直等待
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// 把采集事件放入各输出队列的发送端
+#[derive(Clone)]
+pub struct PipelineSender {
+    inputs: Arc<Mutex<Vec<SinkInput>>>,
+}
+
+impl PipelineSender {
+    /// 把事件放入所有输出的队列，不等待
+    ///
+    /// 某个输出积压的采集数据达到容量时丢弃该输出的这批数据并计数，开始丢弃和恢复时各输出一次日志；
+    /// 可用性和看门狗事件总是放入队列。
+    pub fn send(&self, event: PollEvent) {
+        // 持有锁期间检查并增加积压计数，多个发送端不会同时超过容量
+        let inputs = self.inputs.lock().unwrap_or_else(|e| e.into_inner());
+        let readings = matches!(event, PollEvent::Readings(_));
+        for input in inputs.iter() {
+            let stats = &input.stats;
+            if readings {
+                if stats.queued_readings.load(Ordering::Relaxed) >= input.capacity {
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    if !stats.overflowing.swap(true, Ordering::Relaxed) {
+                        warn!(sink = %stats.name, "输出队列已满，丢弃采集数据");
+                    }
+                    continue;
+                }
+                if stats.overflowing.swap(false, Ordering::Relaxed) {
+                    info!(sink = %stats.name, dropped = stats.dropped(), "输出队列已恢复");
+                }
+                stats.queued_readings.fetch_add(1, Ordering::Relaxed);
+            }
+            // 先计入队列，避免处理任务取出事件时计数还未增加
+            stats.queued.fetch_add(1, Ordering::Relaxed);
+            // 处理任务已结束（输出发生 panic），不再投递
+            if input.sender.send(event.clone()).is_err() {
+                stats.queued.fetch_sub(1, Ordering::Relaxed);
+                if readings {
+                    stats.queued_readings.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// 以 debug 级别把采集数据输出到日志
+#[derive(Debug, Default)]
+pub struct LogSink;
+
+#[async_trait::async_trait]
+impl Sink for LogSink {
+    async fn deliver(&mut self, batch: &[Reading]) {
+        for reading in batch {
+            debug!(
+                gateway = reading.gateway_name.as_deref().unwrap_or(&reading.gateway),
+                slave_id = reading.slave_id,
+                point = %reading.point,
+                value = reading.value,
+                unit = reading.unit.as_deref().unwrap_or(""),
+                "采集数据"
+            );
+        }
+    }
+
+    async fn availability(&mut self, device: &DeviceAvailability) {
+        debug!(
+            gateway = device.gateway_name.as_deref().unwrap_or(&device.gateway),
+            slave_id = device.slave_id,
+            availability = %device.availability,
+            "从站可用性变化"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::availability::Availability;
+    use crate::reload::GatewayTasks;
+    use crate::test_support::{config, reading};
+
+    type Times = Arc<Mutex<Vec<Instant>>>;
+
+    // 记录每批数据的收到时间，每批处理 `delay`
+    struct FakeSink {
+        delay: Duration,
+        batches: Times,
+        flushes: Arc<AtomicU64>,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Sink for FakeSink {
+        async fn deliver(&mut self, batch: &[Reading]) {
+            self.batches.lock().unwrap().push(Instant::now());
+            let values: Vec<String> = batch.iter().map(|r| r.value.to_string()).collect();
+            self.events.lock().unwrap().push(values.join(","));
+            tokio::time::sleep(self.delay).await;
+        }
+
+        async fn availability(&mut self, device: &DeviceAvailability) {
+            self.events.lock().unwrap().push(device.availability.to_string());
+        }
+
+        async fn watchdog(&mut self, event: &WatchdogEvent) {
+            let state = if event.failed { "watchdog failed" } else { "watchdog recovered" };
+            self.events.lock().unwrap().push(state.to_string());
+        }
+
+        async fn flush(&mut self) {
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn fake(delay: Duration) -> (FakeSink, Times, Arc<AtomicU64>) {
+        let batches = Times::default();
+        let flushes = Arc::new(AtomicU64::new(0));
+        let sink = FakeSink {
+            delay,
+            batches: Arc::clone(&batches),
+            flushes: Arc::clone(&flushes),
+            events: Arc::default(),
+        };
+        (sink, batches, flushes)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_sink_does_not_disturb_poller_cadence() {
+        let mut pipeline = Pipeline::new();
+        let (slow, slow_batches, _) = fake(Duration::from_secs(30));
+        let (fast, fast_batches, _) = fake(Duration::ZERO);
+        pipeline.add_sink("slow", 2, slow);
+        pipeline.add_sink("fast", 2, fast);
+
+        // 采集任务记录每次产生数据的时间
+        let polled = Times::default();
+        let sender = pipeline.sender();
+        let recorded = Arc::clone(&polled);
+        let mut tasks = GatewayTasks::new(move |event: PollEvent| {
+            if matches!(event, PollEvent::Readings(_)) {
+                recorded.lock().unwrap().push(Instant::now());
+            }
+            sender.send(event);
+        });
+        let config = config(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    simulation: true\n    poll_interval_ms: 1000\n    slave_ids: [1]\n    points:\n      - { name: power, address: 0 }\n",
+        );
+        tasks.apply(&config);
+        tokio::time::sleep(Duration::from_millis(10_500)).await;
+        tasks.shutdown().await;
+
+        // 采集按1秒的周期进行，不受慢速输出影响
+        let polled = polled.lock().unwrap().clone();
+        assert!(polled.len() >= 10, "{}", polled.len());
+        for pair in polled.windows(2) {
+            assert_eq!(pair[1] - pair[0], Duration::from_secs(1));
+        }
+        // 快速输出收到每一批，慢速输出只处理了第一批，其余入队或被丢弃
+        assert_eq!(fast_batches.lock().unwrap().len(), polled.len());
+        assert_eq!(slow_batches.lock().unwrap().len(), 1);
+        let stats = pipeline.stats();
+        assert_eq!((stats[0].name(), stats[1].name()), ("slow", "fast"));
+        assert_eq!(stats[1].dropped(), 0);
+        assert!(stats[0].dropped() > 0);
+        // 积压的是容量内的两批数据，以及启动时上线、停止时离线两次不会丢弃的可用性变化
+        assert_eq!(stats[0].queued(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_queue_drops_with_counter_and_close_drains_the_rest() {
+        let mut pipeline = Pipeline::new();
+        let (sink, batches, flushes) = fake(Duration::from_secs(1));
+        pipeline.add_sink("slow", 2, sink);
+        let sender = pipeline.sender();
+
+        // 第一批立即被取出处理，之后两批入队，其余丢弃
+        for value in 0..6 {
+            sender.send(PollEvent::Readings(vec![reading("10.0.0.1:502", 1, "power", f64::from(value))]));
+            tokio::task::yield_now().await;
+        }
+        let stats = Arc::clone(&pipeline.stats()[0]);
+        assert_eq!((stats.queued(), stats.dropped()), (2, 3));
+
+        pipeline.close().await;
+        assert_eq!(batches.lock().unwrap().len(), 3);
+        assert_eq!((stats.delivered(), stats.queued()), (3, 0));
+        assert_eq!(flushes.load(Ordering::Relaxed), 1);
+        // 关闭后发送的事件被忽略
+        sender.send(PollEvent::Readings(Vec::new()));
+        assert_eq!(stats.dropped(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_sink_receives_every_state_change_in_order() {
+        let mut pipeline = Pipeline::new();
+        let (sink, _, _) = fake(Duration::from_secs(1));
+        let events = Arc::clone(&sink.events);
+        pipeline.add_sink("slow", 1, sink);
+        let sender = pipeline.sender();
+
+        // 队列中积压着采集数据时从站离线又恢复，看门狗失败又恢复，最后再次离线
+        let send_readings = |value: f64| {
+            sender.send(PollEvent::Readings(vec![reading("10.0.0.1:502", 1, "power", value)]));
+        };
+        send_readings(1.0);
+        tokio::task::yield_now().await;
+        send_readings(2.0);
+        send_readings(3.0);
+        sender.send(availability(Availability::Offline));
+        sender.send(watchdog(true));
+        send_readings(4.0);
+        sender.send(availability(Availability::Online));
+        sender.send(watchdog(false));
+        send_readings(5.0);
+        sender.send(availability(Availability::Offline));
+        let stats = Arc::clone(&pipeline.stats()[0]);
+        assert_eq!((stats.queued(), stats.dropped()), (6, 3));
+
+        pipeline.close().await;
+        // 只有超过容量的采集数据被丢弃，状态变化按发生的顺序全部送达，最终状态为离线
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "1",
+                "2",
+                "offline",
+                "watchdog failed",
+                "online",
+                "watchdog recovered",
+                "offline"
+            ]
+        );
+        assert_eq!((stats.delivered(), stats.queued(), stats.dropped()), (7, 0, 3));
+    }
+
+    fn availability(availability: Availability) -> PollEvent {
+        PollEvent::Availability(DeviceAvailability {
+            gateway: "10.0.0.1:502".to_string(),
+            gateway_name: None,
+            slave_id: 1,
+            slave_name: None,
+            availability,
+        })
+    }
+
+    fn watchdog(failed: bool) -> PollEvent {
+        PollEvent::Watchdog(WatchdogEvent {
+            gateway: "10.0.0.1:502".to_string(),
+            gateway_name: None,
+            slave_id: 1,
+            slave_name: None,
+            failed,
+            missed: 3,
+            error: None,
+            timestamp: std::time::SystemTime::UNIX_EPOCH,
+        })
+    }
+}
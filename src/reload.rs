@@ -0,0 +1,794 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch, Semaphore};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::device_configuration::modbus::{read_config_with, Config, LoadOptions, ModbusDevice};
+use crate::device_configuration::point::Point;
+use crate::device_configuration::poll_group::PollGroup;
+use crate::modbus::availability::{Availability, DeviceAvailability};
+use crate::modbus::scheduler::{GatewayPoller, GatewayRequest, PollEvent, REQUEST_QUEUE_CAPACITY};
+use crate::modbus::stats::SharedStats;
+use crate::modbus::sunspec;
+
+/// 检查配置文件是否变化的间隔
+pub const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// 停止时等待正在进行的 Modbus 读写完成的最长时间，超时后强制断开
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// 采集任务崩溃后第一次重启前的等待时间，之后一小时内每次重启翻倍
+pub const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+/// 重启前等待时间的上限
+pub const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+// 统计重启次数的时间窗口
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+
+// 决定网关采集任务是否需要重启的配置内容
+#[derive(PartialEq)]
+struct GatewaySpec {
+    gateway: ModbusDevice,
+    templates: BTreeMap<String, Vec<Point>>,
+    poll_groups: BTreeMap<String, PollGroup>,
+    // 上限变化时所有网关改用新的信号量
+    max_concurrent_requests: Option<usize>,
+}
+
+struct RunningGateway {
+    name: String,
+    spec: GatewaySpec,
+    writer: GatewayWriter,
+    handle: JoinHandle<()>,
+}
+
+/// 正在采集的网关的请求入口，MQTT 写入命令和临时读取通过它把请求交给对应的采集任务
+#[derive(Debug, Clone)]
+pub struct GatewayWriter {
+    /// 网关配置
+    pub gateway: ModbusDevice,
+    /// 每个已启用从站展开后的点位（包括停用的点位，用于给出明确的错误）
+    pub points: BTreeMap<u8, Vec<Point>>,
+    /// 写入、读取和控制请求发送端
+    pub sender: mpsc::Sender<GatewayRequest>,
+    /// 采集统计
+    pub stats: SharedStats,
+}
+
+/// 可在多个任务间共享的写入入口列表，随配置热加载更新
+pub type SharedWriters = Arc<Mutex<Vec<GatewayWriter>>>;
+
+/// 立即重新加载配置的请求，例如 MQTT 控制命令 `reload_config`
+#[derive(Debug)]
+pub struct ReloadRequest {
+    /// 配置有变化并已应用时为 true，没有变化时为 false；无法加载或校验失败时为错误信息
+    pub reply: oneshot::Sender<Result<bool, String>>,
+}
+
+/// 正在运行的网关采集任务
+///
+/// 每次 `apply` 时与新配置比较：新增或重新启用的网关启动采集，删除或停用的网关停止采集，
+/// 配置有变化的网关重启采集，其余网关不受影响。
+/// 停用的网关和从站通过 `on_event` 报告为 disabled，从配置中删除的网关报告为 offline。
+///
+/// 采集任务崩溃（panic）或意外结束时，等待 [`RESTART_BACKOFF`] 起逐次翻倍的时间后以新的连接重启，
+/// 写入入口和采集统计保持不变；一小时内重启次数超过网关的 `max_restarts_per_hour` 时
+/// 停止采集该网关，其从站报告为 offline，程序继续运行。
+pub struct GatewayTasks<F> {
+    running: BTreeMap<String, RunningGateway>,
+    disabled: BTreeSet<String>,
+    disabled_slaves: BTreeSet<(String, u8)>,
+    writers: SharedWriters,
+    // 所有网关共用的并发请求上限及对应的信号量
+    request_limit: Option<(usize, Arc<Semaphore>)>,
+    stop: watch::Sender<bool>,
+    on_event: F,
+}
+
+impl<F> GatewayTasks<F>
+where
+    F: Fn(PollEvent) + Clone + Send + 'static,
+{
+    /// 创建任务集合
+    ///
+    /// # 参数说明
+    /// * `on_event` - 采集数据和设备可用性变化的处理函数
+    pub fn new(on_event: F) -> Self {
+        GatewayTasks {
+            running: BTreeMap::new(),
+            disabled: BTreeSet::new(),
+            disabled_slaves: BTreeSet::new(),
+            writers: SharedWriters::default(),
+            request_limit: None,
+            stop: watch::channel(false).0,
+            on_event,
+        }
+    }
+
+    /// 正在采集的网关的写入入口
+    pub fn writers(&self) -> SharedWriters {
+        Arc::clone(&self.writers)
+    }
+
+    /// 按配置启动、停止或重启网关采集任务，只处理配置了点位的网关
+    pub fn apply(&mut self, config: &Config) {
+        let mut wanted = BTreeMap::new();
+        let mut disabled = BTreeSet::new();
+        let mut disabled_slaves = BTreeSet::new();
+        let mut occurrences: BTreeMap<String, usize> = BTreeMap::new();
+        for gateway in config.gateways.iter().filter(|g| g.has_points()) {
+            // 设置了 allow_duplicates 时 ip:port 可能重复，按出现次序区分
+            let address = format!("{}:{}", gateway.ip, gateway.port);
+            let count = occurrences.entry(address.clone()).or_default();
+            let key = format!("{}#{}", address, count);
+            *count += 1;
+
+            if gateway.enabled {
+                for slave in gateway.slave_ids.iter().filter(|s| !s.enabled) {
+                    let slave_key = (key.clone(), slave.id);
+                    if !self.disabled_slaves.contains(&slave_key) {
+                        self.report(gateway, slave.id, Availability::Disabled);
+                    }
+                    disabled_slaves.insert(slave_key);
+                }
+                wanted.insert(key, gateway);
+            } else {
+                if !self.disabled.contains(&key) {
+                    info!(gateway = %gateway.display_name(), "网关已停用，不进行采集");
+                    for slave in &gateway.slave_ids {
+                        self.report(gateway, slave.id, Availability::Disabled);
+                    }
+                }
+                disabled.insert(key);
+            }
+        }
+
+        let keys: Vec<String> = self.running.keys().cloned().collect();
+        for key in keys {
+            let keep = wanted
+                .get(&key)
+                .is_some_and(|gateway| self.running[&key].spec == spec_of(config, gateway));
+            if !keep && let Some(task) = self.running.remove(&key) {
+                task.handle.abort();
+                info!(gateway = %task.name, "停止网关的周期采集");
+                // 停用的网关已报告为 disabled，重启的网关由新任务重新报告
+                if !wanted.contains_key(&key) && !disabled.contains(&key) {
+                    for slave in task.writer.gateway.enabled_slaves() {
+                        self.report(&task.writer.gateway, slave.id, Availability::Offline);
+                    }
+                }
+            }
+        }
+        self.disabled = disabled;
+        self.disabled_slaves = disabled_slaves;
+        // 上限变化时正在运行的网关都已在上面停止，之后用新的信号量启动
+        if self.request_limit.as_ref().map(|(max, _)| *max) != config.max_concurrent_requests {
+            self.request_limit = config
+                .max_concurrent_requests
+                .map(|max| (max, Arc::new(Semaphore::new(max))));
+        }
+        let request_limit = self.request_limit.as_ref().map(|(_, limit)| Arc::clone(limit));
+
+        let shared = Arc::new(config.clone());
+        for (key, gateway) in wanted {
+            if self.running.contains_key(&key) {
+                continue;
+            }
+            let mut poller = match GatewayPoller::new(config, gateway) {
+                Ok(poller) => poller,
+                Err(e) => {
+                    error!(gateway = %gateway.display_name(), error = %e, "网关无法启动采集");
+                    continue;
+                }
+            };
+            if let Some(limit) = &request_limit {
+                poller.set_request_limit(Arc::clone(limit));
+            }
+            let mut points = BTreeMap::new();
+            for slave in gateway.enabled_slaves() {
+                points.insert(slave.id, config.effective_points(gateway, slave).unwrap_or_default());
+            }
+            // 写入入口的队列由监督任务转发给当前的采集任务，重启后仍然有效
+            let (sender, requests) = mpsc::channel(REQUEST_QUEUE_CAPACITY);
+            let writer = GatewayWriter {
+                gateway: gateway.clone(),
+                points,
+                sender,
+                stats: poller.stats(),
+            };
+
+            let name = poller.name().to_string();
+            info!(gateway = %name, "启动网关的周期采集");
+            let supervisor = Supervisor {
+                config: Arc::clone(&shared),
+                gateway: gateway.clone(),
+                stats: poller.stats(),
+                request_limit: request_limit.clone(),
+                on_event: self.on_event.clone(),
+                stop: self.stop.subscribe(),
+                requests,
+            };
+            let handle = tokio::spawn(supervisor.run(poller));
+            self.running.insert(
+                key,
+                RunningGateway {
+                    name,
+                    spec: spec_of(config, gateway),
+                    writer,
+                    handle,
+                },
+            );
+        }
+
+        let writers = self.running.values().map(|task| task.writer.clone()).collect();
+        *self.writers.lock().unwrap_or_else(|e| e.into_inner()) = writers;
+    }
+
+    /// 停止所有采集任务，所有从站报告为 offline
+    ///
+    /// 不再开始新的采集和请求，等待正在进行的读写完成、断开连接后返回；
+    /// 超过 STOP_TIMEOUT 仍未停止的任务直接终止。
+    pub async fn shutdown(&mut self) {
+        self.stop.send_replace(true);
+        self.writers.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        for (_, task) in std::mem::take(&mut self.running) {
+            let mut handle = task.handle;
+            if tokio::time::timeout_at(deadline, &mut handle).await.is_err() {
+                handle.abort();
+                warn!(
+                    gateway = %task.name,
+                    "网关未在 {} 秒内完成正在进行的读写，强制断开",
+                    STOP_TIMEOUT.as_secs()
+                );
+            }
+            info!(gateway = %task.name, "停止网关的周期采集");
+            for slave in task.writer.gateway.enabled_slaves() {
+                self.report(&task.writer.gateway, slave.id, Availability::Offline);
+            }
+        }
+    }
+
+    fn report(&self, gateway: &ModbusDevice, slave_id: u8, availability: Availability) {
+        (self.on_event)(PollEvent::Availability(DeviceAvailability {
+            gateway: format!("{}:{}", gateway.ip, gateway.port),
+            gateway_name: gateway.name.clone(),
+            slave_id,
+            slave_name: gateway.find_slave(slave_id).and_then(|s| s.name.clone()),
+            availability,
+        }));
+    }
+}
+
+// 被丢弃时终止任务的句柄，监督任务被终止时一并终止正在运行的采集任务
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// 一个网关的监督任务：运行采集任务，崩溃或意外结束时按退避时间重启
+struct Supervisor<F> {
+    config: Arc<Config>,
+    gateway: ModbusDevice,
+    stats: SharedStats,
+    request_limit: Option<Arc<Semaphore>>,
+    on_event: F,
+    stop: watch::Receiver<bool>,
+    requests: mpsc::Receiver<GatewayRequest>,
+}
+
+impl<F> Supervisor<F>
+where
+    F: Fn(PollEvent) + Clone + Send + 'static,
+{
+    async fn run(mut self, first: GatewayPoller) {
+        let name = self.gateway.display_name();
+        let mut restarts: Vec<Instant> = Vec::new();
+        let mut poller = Some(first);
+        loop {
+            let mut current = match poller.take() {
+                Some(poller) => poller,
+                None => match GatewayPoller::new(&self.config, &self.gateway) {
+                    Ok(mut poller) => {
+                        poller.set_stats(Arc::clone(&self.stats));
+                        if let Some(limit) = &self.request_limit {
+                            poller.set_request_limit(Arc::clone(limit));
+                        }
+                        poller
+                    }
+                    Err(e) => {
+                        error!(gateway = %name, error = %e, "网关无法重启采集");
+                        return;
+                    }
+                },
+            };
+            let forward = current.request_sender();
+            let on_event = self.on_event.clone();
+            let stop = self.stop.clone();
+            let mut task = AbortOnDrop(tokio::spawn(async move { current.run(on_event, stop).await }));
+            let mut open = true;
+            let result = loop {
+                tokio::select! {
+                    result = &mut task.0 => break result,
+                    request = self.requests.recv(), if open => match request {
+                        // 采集任务已结束时请求被丢弃，请求方收到失败
+                        Some(request) => {
+                            let _ = forward.send(request).await;
+                        }
+                        None => open = false,
+                    },
+                }
+            };
+            if *self.stop.borrow() {
+                return;
+            }
+            let failure = match result {
+                Ok(()) => "采集任务意外结束".to_string(),
+                Err(e) if e.is_panic() => {
+                    let panic = e.into_panic();
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "未知原因".to_string());
+                    format!("采集任务崩溃: {}", message)
+                }
+                // 被终止，由 apply 或 shutdown 处理
+                Err(_) => return,
+            };
+
+            let now = Instant::now();
+            restarts.retain(|at| now.duration_since(*at) < RESTART_WINDOW);
+            restarts.push(now);
+            let exhausted = self
+                .gateway
+                .max_restarts_per_hour
+                .is_some_and(|max| restarts.len() > max as usize);
+            {
+                let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+                stats.last_failure = Some(failure.clone());
+                stats.connected = false;
+                if exhausted {
+                    stats.failed = true;
+                } else {
+                    stats.restarts += 1;
+                }
+            }
+            if exhausted {
+                error!(
+                    gateway = %name,
+                    error = %failure,
+                    "采集任务一小时内重启次数超过 {} 次，停止采集该网关",
+                    self.gateway.max_restarts_per_hour.unwrap_or_default()
+                );
+                for slave in self.gateway.enabled_slaves() {
+                    (self.on_event)(PollEvent::Availability(DeviceAvailability {
+                        gateway: format!("{}:{}", self.gateway.ip, self.gateway.port),
+                        gateway_name: self.gateway.name.clone(),
+                        slave_id: slave.id,
+                        slave_name: slave.name.clone(),
+                        availability: Availability::Offline,
+                    }));
+                }
+                return;
+            }
+
+            let backoff = RESTART_BACKOFF
+                .saturating_mul(1 << (restarts.len() - 1).min(16))
+                .min(MAX_RESTART_BACKOFF);
+            error!(
+                gateway = %name,
+                error = %failure,
+                delay_ms = backoff.as_millis() as u64,
+                "网关采集任务失败，稍后重启"
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = self.stop.wait_for(|stopped| *stopped) => return,
+            }
+            info!(gateway = %name, "重启网关的周期采集");
+        }
+    }
+}
+
+/// 配置了点位的已启用网关各采集一次后返回，用于 `--once` 单次采集
+///
+/// 各网关同时采集，每个网关的所有采集组各执行一次。
+///
+/// # 参数说明
+/// * `on_event` - 采集数据和设备可用性变化的处理函数
+pub async fn poll_once<F>(config: &Config, on_event: F)
+where
+    F: Fn(PollEvent) + Clone + Send + 'static,
+{
+    let mut pollers = JoinSet::new();
+    let request_limit = config
+        .max_concurrent_requests
+        .map(|max| Arc::new(Semaphore::new(max)));
+    for gateway in config.gateways.iter().filter(|g| g.has_points()) {
+        if !gateway.enabled {
+            info!(gateway = %gateway.display_name(), "网关已停用，不进行采集");
+            continue;
+        }
+        let mut poller = match GatewayPoller::new(config, gateway) {
+            Ok(poller) => poller,
+            Err(e) => {
+                error!(gateway = %gateway.display_name(), error = %e, "网关无法启动采集");
+                continue;
+            }
+        };
+        if let Some(limit) = &request_limit {
+            poller.set_request_limit(Arc::clone(limit));
+        }
+        info!(gateway = %poller.name(), "网关单次采集");
+        let on_event = on_event.clone();
+        pollers.spawn(async move { poller.run_once(on_event).await });
+    }
+    while pollers.join_next().await.is_some() {}
+}
+
+fn spec_of(config: &Config, gateway: &ModbusDevice) -> GatewaySpec {
+    GatewaySpec {
+        gateway: gateway.clone(),
+        templates: config.templates.clone(),
+        poll_groups: config.poll_groups.clone(),
+        max_concurrent_requests: config.max_concurrent_requests,
+    }
+}
+
+/// 定期重新加载配置文件，内容变化时调整采集任务（不会返回）
+///
+/// 新配置无法加载或校验失败时继续使用当前配置，同样的错误只输出一次。
+/// 收到 `requests` 中的请求时立即重新加载，与定期检查走同样的流程，结果通过请求中的 reply 返回。
+/// 同一网关、同一从站的 SunSpec 发现结果沿用当前配置中的；发现失败的从站每隔 [`sunspec::RETRY_INTERVAL`]
+/// （或收到请求时）重新发现，成功后与配置变化一样重启该网关的采集。
+/// MQTT 配置的变化需要重启程序才能生效。
+///
+/// # 参数说明
+/// * `file_path` - 配置文件路径
+/// * `options` - 启动时读取配置的选项，重新加载时不写回迁移结果、不输出迁移提示
+/// * `current` - 当前正在使用的配置
+/// * `tasks` - 已按 `current` 启动的采集任务
+/// * `requests` - 立即重新加载的请求，发送端全部关闭后只定期检查
+/// * `on_reload` - 新配置应用到采集任务后调用，例如更新 Home Assistant 发现消息
+pub async fn watch_config<F, R>(
+    file_path: &str,
+    options: &LoadOptions,
+    mut current: Config,
+    tasks: &mut GatewayTasks<F>,
+    mut requests: mpsc::Receiver<ReloadRequest>,
+    mut on_reload: R,
+) where
+    F: Fn(PollEvent) + Clone + Send + 'static,
+    R: FnMut(&Config),
+{
+    let mut last_error: Option<String> = None;
+    let mut open = true;
+    let mut last_discovery = Instant::now();
+    loop {
+        let reply = tokio::select! {
+            _ = tokio::time::sleep(RELOAD_CHECK_INTERVAL) => None,
+            request = requests.recv(), if open => match request {
+                Some(request) => Some(request.reply),
+                None => {
+                    open = false;
+                    continue;
+                }
+            },
+        };
+
+        let mut config = match load(file_path, options) {
+            Ok(config) => config,
+            Err(e) => {
+                let message = e.to_string();
+                if let Some(reply) = reply {
+                    error!(error = %message, "按请求重新加载配置失败，继续使用当前配置");
+                    let _ = reply.send(Err(message));
+                } else if last_error.as_ref() != Some(&message) {
+                    error!(error = %message, "重新加载配置失败，继续使用当前配置");
+                    last_error = Some(message);
+                }
+                continue;
+            }
+        };
+        last_error = None;
+        sunspec::carry_over(&mut config, &current);
+        if sunspec::has_pending(&config)
+            && (reply.is_some() || last_discovery.elapsed() >= sunspec::RETRY_INTERVAL)
+        {
+            sunspec::discover(&mut config).await;
+            last_discovery = Instant::now();
+        }
+        if config == current {
+            if let Some(reply) = reply {
+                let _ = reply.send(Ok(false));
+            }
+            continue;
+        }
+
+        info!(path = file_path, "配置文件已变化，重新应用配置");
+        if config.mqtt != current.mqtt {
+            warn!("MQTT 配置的变化需要重启程序才能生效");
+        }
+        tasks.apply(&config);
+        on_reload(&config);
+        current = config;
+        if let Some(reply) = reply {
+            let _ = reply.send(Ok(true));
+        }
+    }
+}
+
+// 重新加载时不写回迁移结果，文件被删除时也不重新创建
+fn load(file_path: &str, options: &LoadOptions) -> Result<Config, Box<dyn Error>> {
+    if !Path::new(file_path).try_exists()? {
+        return Err(format!("配置文件 {} 不存在", file_path).into());
+    }
+    let options = LoadOptions {
+        write_back_migration: false,
+        quiet: true,
+        ..options.clone()
+    };
+    read_config_with(file_path, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, MockModbus};
+
+    type Events = Arc<Mutex<Vec<PollEvent>>>;
+    // 可用性变化（从站ID，可用性）和读到的（从站ID，点位）
+    type Recorded = (Vec<(u8, Availability)>, BTreeSet<(u8, String)>);
+
+    fn gateway_yaml(enabled: bool) -> String {
+        format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    simulation: true\n    enabled: {}\n    slave_ids: [1, {{ id: 2, enabled: false }}]\n    points:\n      - {{ name: power, address: 0 }}\n      - {{ name: spare, address: 1, enabled: false }}\n",
+            enabled
+        )
+    }
+
+    fn tasks() -> (GatewayTasks<impl Fn(PollEvent) + Clone + Send + 'static>, Events) {
+        let events = Events::default();
+        let recorded = Arc::clone(&events);
+        let tasks = GatewayTasks::new(move |event| {
+            recorded.lock().unwrap_or_else(|e| e.into_inner()).push(event);
+        });
+        (tasks, events)
+    }
+
+    // 取出目前记录的事件
+    fn take(events: &Events) -> Recorded {
+        let mut availability = Vec::new();
+        let mut points = BTreeSet::new();
+        for event in events.lock().unwrap().drain(..) {
+            match event {
+                PollEvent::Availability(change) => availability.push((change.slave_id, change.availability)),
+                PollEvent::Readings(readings) => {
+                    points.extend(readings.into_iter().map(|r| (r.slave_id, r.point)))
+                }
+                PollEvent::Watchdog(_) => {}
+            }
+        }
+        (availability, points)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disabled_gateway_is_reported_and_not_polled() {
+        let (mut tasks, events) = tasks();
+        tasks.apply(&config(&gateway_yaml(false)));
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        let (availability, points) = take(&events);
+        assert_eq!(availability, [(1, Availability::Disabled), (2, Availability::Disabled)]);
+        assert!(points.is_empty());
+        assert!(tasks.writers().lock().unwrap().is_empty());
+
+        // 配置没有变化时不重复报告
+        tasks.apply(&config(&gateway_yaml(false)));
+        assert_eq!(take(&events).0, []);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disabled_slaves_and_points_are_skipped() {
+        let (mut tasks, events) = tasks();
+        tasks.apply(&config(&gateway_yaml(true)));
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        let (availability, points) = take(&events);
+        assert_eq!(availability, [(2, Availability::Disabled), (1, Availability::Online)]);
+        assert_eq!(points, BTreeSet::from([(1, "power".to_string())]));
+        let writers = tasks.writers();
+        let writers = writers.lock().unwrap();
+        // 写入入口只有已启用的从站，但包括停用的点位，以便给出明确的错误
+        assert_eq!(writers[0].points.keys().copied().collect::<Vec<_>>(), [1]);
+        assert_eq!(writers[0].points[&1].len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reload_starts_and_stops_gateways() {
+        let (mut tasks, events) = tasks();
+        tasks.apply(&config(&gateway_yaml(false)));
+        take(&events);
+
+        // 启用网关后开始采集
+        tasks.apply(&config(&gateway_yaml(true)));
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let (availability, points) = take(&events);
+        assert_eq!(availability, [(2, Availability::Disabled), (1, Availability::Online)]);
+        assert!(!points.is_empty());
+        assert_eq!(tasks.writers().lock().unwrap().len(), 1);
+
+        // 再次停用后报告为 disabled，不再采集
+        tasks.apply(&config(&gateway_yaml(false)));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        take(&events);
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert_eq!(take(&events), (Vec::new(), BTreeSet::new()));
+        assert!(tasks.writers().lock().unwrap().is_empty());
+
+        // 从配置中删除的网关报告为 offline
+        tasks.apply(&config(&gateway_yaml(true)));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        take(&events);
+        tasks.apply(&config("version: 2\ngateways: []\n"));
+        assert_eq!(take(&events).0, [(1, Availability::Offline)]);
+
+        tasks.apply(&config(&gateway_yaml(true)));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        take(&events);
+        tasks.shutdown().await;
+        assert_eq!(take(&events).0, [(1, Availability::Offline)]);
+        assert!(tasks.writers().lock().unwrap().is_empty());
+    }
+
+    fn modbus_yaml(port: u16, request_timeout_ms: u64) -> String {
+        format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    request_timeout_ms: {}\n    poll_interval_ms: 60000\n    slave_ids: [1]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            port, request_timeout_ms
+        )
+    }
+
+    // 等到 Modbus 服务器收到第一个请求
+    async fn wait_for_request(modbus: &MockModbus) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while modbus.requests().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_lets_in_flight_read_finish_before_reporting_offline() {
+        let modbus = MockModbus::start().await;
+        modbus.set(1, 3, 0, &[42]);
+        modbus.set_delay(Duration::from_millis(300));
+        let (mut tasks, events) = tasks();
+        tasks.apply(&config(&modbus_yaml(modbus.port, 2000)));
+        wait_for_request(&modbus).await;
+
+        tasks.shutdown().await;
+        // 正在进行的读取完成并上报，之后才报告 offline
+        let (availability, points) = take(&events);
+        assert_eq!(availability, [(1, Availability::Online), (1, Availability::Offline)]);
+        assert_eq!(points, BTreeSet::from([(1, "power".to_string())]));
+        // 停止后不再开始新的采集，连接已断开
+        assert_eq!(modbus.requests().len(), 1);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while modbus.closed() < modbus.connections() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn shutdown_aborts_gateways_stuck_past_the_deadline() {
+        let modbus = MockModbus::start().await;
+        modbus.silence(1);
+        let (mut tasks, events) = tasks();
+        tasks.apply(&config(&modbus_yaml(modbus.port, 600_000)));
+        wait_for_request(&modbus).await;
+
+        // 请求已发出，之后暂停时钟，等待期间时间自动前进
+        tokio::time::pause();
+        let started = Instant::now();
+        tasks.shutdown().await;
+        assert!(started.elapsed() >= STOP_TIMEOUT);
+        assert!(started.elapsed() < STOP_TIMEOUT + Duration::from_secs(1));
+        let (availability, points) = take(&events);
+        assert_eq!(availability, [(1, Availability::Offline)]);
+        assert!(points.is_empty());
+    }
+
+    // 前 `failures` 批采集数据到达时 panic 的处理函数，panic 发生在采集任务中，相当于采集任务崩溃；
+    // failures 为 None 时每次都 panic
+    fn panicking_tasks(
+        failures: Option<usize>,
+    ) -> (GatewayTasks<impl Fn(PollEvent) + Clone + Send + 'static>, Events) {
+        let events = Events::default();
+        let recorded = Arc::clone(&events);
+        let panics = Arc::new(Mutex::new(0));
+        let tasks = GatewayTasks::new(move |event| {
+            if matches!(event, PollEvent::Readings(_)) {
+                let mut panics = panics.lock().unwrap_or_else(|e| e.into_inner());
+                if failures.is_none_or(|failures| *panics < failures) {
+                    *panics += 1;
+                    drop(panics);
+                    panic!("注入的故障");
+                }
+            }
+            recorded.lock().unwrap_or_else(|e| e.into_inner()).push(event);
+        });
+        (tasks, events)
+    }
+
+    fn supervisor_yaml(max_restarts: &str) -> String {
+        format!(
+            "version: 2\ngateways:\n  - ip: 127.0.0.1\n    simulation: true\n    poll_interval_ms: 1000\n{}    slave_ids: [1]\n    points:\n      - {{ name: power, address: 0 }}\n",
+            max_restarts
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn panicking_poller_restarts_with_backoff_and_counts_restarts() {
+        let (mut tasks, events) = panicking_tasks(Some(2));
+        tasks.apply(&config(&supervisor_yaml("")));
+        let writers = tasks.writers();
+        let stats = Arc::clone(&writers.lock().unwrap()[0].stats);
+        let restarts = || stats.lock().unwrap().restarts;
+
+        // 第一次采集时崩溃，1 秒后重启；重启后再次崩溃，等待时间翻倍为 2 秒
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(restarts(), 1);
+        assert_eq!(
+            stats.lock().unwrap().last_failure.as_deref(),
+            Some("采集任务崩溃: 注入的故障")
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(restarts(), 2);
+        assert!(take(&events).1.is_empty());
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(take(&events).1.is_empty(), "退避时间内不重启");
+
+        // 第二次重启后恢复采集，写入入口和统计不变
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(take(&events).1, BTreeSet::from([(1, "power".to_string())]));
+        let current = stats.lock().unwrap().clone();
+        assert_eq!((current.restarts, current.failed), (2, false));
+        assert!(Arc::ptr_eq(&writers.lock().unwrap()[0].stats, &stats));
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        assert!(!take(&events).1.is_empty());
+        assert_eq!(restarts(), 2);
+        tasks.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gateway_over_the_restart_limit_is_marked_failed_and_offline() {
+        let (mut tasks, events) = panicking_tasks(None);
+        tasks.apply(&config(&supervisor_yaml("    max_restarts_per_hour: 2\n")));
+        let stats = Arc::clone(&tasks.writers().lock().unwrap()[0].stats);
+
+        // 崩溃后分别在 1 秒和 2 秒后重启，第三次崩溃时超过上限
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let current = stats.lock().unwrap().clone();
+        assert_eq!((current.restarts, current.failed, current.connected), (2, true, false));
+        assert_eq!(current.last_failure.as_deref(), Some("采集任务崩溃: 注入的故障"));
+        let (availability, points) = take(&events);
+        assert_eq!(availability.last(), Some(&(1, Availability::Offline)));
+        assert!(points.is_empty());
+
+        // 不再重启，其他功能照常
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        assert_eq!(stats.lock().unwrap().restarts, 2);
+        assert_eq!(take(&events), (Vec::new(), BTreeSet::new()));
+    }
+}
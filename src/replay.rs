@@ -0,0 +1,411 @@
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::{MqttSettings, PublishOptions};
+use crate::device_configuration::storage::{ReplayMode, ReplaySettings};
+use crate::modbus::reading::{Quality, Reading};
+use crate::mqtt::batch::Batcher;
+use crate::mqtt::client::{qos_from_level, MqttClient};
+use crate::mqtt::compression::{compress, CompressionMarker};
+use crate::mqtt::error::MqttError;
+use crate::storage::{ReadingStore, StoredReading};
+
+/// 连接没有变化时检查是否有未发布数据的间隔，用于补发断开期间缓存、恢复连接后才写入数据库的数据
+pub const REPLAY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 把本地数据库中未发布的数据补发到 MQTT
+///
+/// # 说明
+/// * 连接到 Broker（包括启动后的第一次连接和每次重连）后开始补发，之后每隔 [`REPLAY_CHECK_INTERVAL`] 检查一次
+/// * 按采集时间顺序每次取出 batch_rows 行，这一批的消息都放入发送队列后在一个事务中标记为已发布；
+///   补发途中连接断开时当前批次不标记，恢复连接后从这一批重新开始（已发出的部分会重复）
+/// * 消息格式和合并方式与实时数据相同，使用单独的 session_id 和序号；retain 始终为 false，不会覆盖实时数据的保留消息
+/// * backfill 模式发布到 replay.topic_template，original 模式发布到实时数据的主题，消息中的 timestamp 都是原来的采集时间
+/// * 按 max_messages_per_second 限速，实时数据照常发布，不受补发影响
+pub struct Replayer {
+    store: ReadingStore,
+    client: Arc<MqttClient>,
+    settings: ReplaySettings,
+    batcher: Batcher,
+    // 未配置采集组时的发布参数，以及各采集组的发布参数
+    default_publish: PublishOptions,
+    group_publish: BTreeMap<String, PublishOptions>,
+    compression_threshold: usize,
+    compression_marker: CompressionMarker,
+}
+
+impl Replayer {
+    /// 创建补发任务
+    ///
+    /// # 参数说明
+    /// * `store` - 已打开的数据库，与本地存储输出共用
+    /// * `client` - MQTT 客户端
+    /// * `config` - 配置，使用其中的 mqtt、采集组和 timezone
+    /// * `settings` - 补发配置
+    pub fn new(
+        store: ReadingStore,
+        client: Arc<MqttClient>,
+        config: &Config,
+        settings: &ReplaySettings,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut mqtt: MqttSettings = config.mqtt.clone().ok_or("补发需要配置 mqtt")?;
+        if settings.mode == ReplayMode::Backfill {
+            mqtt.topic_template = settings.topic_template.clone();
+        }
+        let timezone: Option<Tz> = config.tz()?;
+        let default_publish = PublishOptions {
+            retain: false,
+            ..config.default_publish_options()
+        };
+        let group_publish = config
+            .poll_groups
+            .iter()
+            .map(|(name, group)| {
+                let options = PublishOptions {
+                    qos: group.qos.unwrap_or(default_publish.qos),
+                    retain: false,
+                    deadband: None,
+                    format: group.payload_format.unwrap_or(default_publish.format),
+                    compression: group.compression.unwrap_or(default_publish.compression),
+                };
+                (name.clone(), options)
+            })
+            .collect();
+        Ok(Replayer {
+            store,
+            client,
+            settings: settings.clone(),
+            batcher: Batcher::new(&mqtt, timezone)?,
+            default_publish,
+            group_publish,
+            compression_threshold: mqtt.compression_threshold_bytes,
+            compression_marker: mqtt.compression_marker,
+        })
+    }
+
+    /// 持续运行：连接到 Broker 后补发，MQTT 客户端结束后返回
+    pub async fn run(self) {
+        let mut state = self.client.watch_state();
+        loop {
+            let current = state.borrow_and_update().clone();
+            if current.stopped {
+                return;
+            }
+            if current.connected {
+                self.replay().await;
+            }
+            tokio::select! {
+                changed = state.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+                _ = tokio::time::sleep(REPLAY_CHECK_INTERVAL) => {}
+            }
+        }
+    }
+
+    // 补发所有未发布的数据，连接断开或数据库出错时中止
+    async fn replay(&self) {
+        let pending = match self.store.unpublished_count().await {
+            Ok(0) => return,
+            Ok(pending) => pending,
+            Err(e) => {
+                warn!(error = %e, "查询未发布的数据失败");
+                return;
+            }
+        };
+        info!(rows = pending, mode = ?self.settings.mode, "开始补发本地数据库中未发布的数据");
+        let interval = Duration::from_secs_f64(1.0 / self.settings.max_messages_per_second);
+        let mut next = Instant::now();
+        let mut replayed = 0;
+        loop {
+            let rows = match self.store.unpublished(self.settings.batch_rows).await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    warn!(error = %e, "读取未发布的数据失败，补发中止");
+                    return;
+                }
+            };
+            if rows.is_empty() {
+                info!(rows = replayed, "补发完成");
+                return;
+            }
+            for chunk in cycles(&rows) {
+                let readings: Vec<Reading> = chunk.iter().map(|row| self.reading(row)).collect();
+                for message in self.batcher.messages(&readings) {
+                    if !self.client.is_connected() {
+                        warn!(rows = replayed, "MQTT 连接断开，补发暂停，恢复连接后继续");
+                        return;
+                    }
+                    tokio::time::sleep_until(next).await;
+                    next = next.max(Instant::now()) + interval;
+                    let (topic, payload) = compress(
+                        &message.topic,
+                        message.payload,
+                        message.compression,
+                        self.compression_threshold,
+                        self.compression_marker,
+                    );
+                    match self
+                        .client
+                        .publish(&topic, qos_from_level(message.qos), false, &payload)
+                        .await
+                    {
+                        Ok(()) => {}
+                        Err(MqttError::Disconnected) => {
+                            warn!(rows = replayed, "MQTT 连接断开，补发暂停，恢复连接后继续");
+                            return;
+                        }
+                        // 主题不合法或消息过大，重试也无法发出，跳过这条消息
+                        Err(e) => warn!(topic = %topic, error = %e, "补发消息失败，已跳过"),
+                    }
+                }
+            }
+            let ids: Vec<i64> = rows.iter().map(|row| row.id).collect();
+            if let Err(e) = self.store.mark_published(&ids).await {
+                warn!(error = %e, "标记已发布失败，补发中止");
+                return;
+            }
+            replayed += rows.len();
+        }
+    }
+
+    // 数据库中的一行还原为采集数据
+    fn reading(&self, row: &StoredReading) -> Reading {
+        let publish = row
+            .group
+            .as_ref()
+            .and_then(|group| self.group_publish.get(group))
+            .copied()
+            .unwrap_or(self.default_publish);
+        Reading {
+            gateway: row.gateway.clone(),
+            gateway_name: row.gateway_name.clone(),
+            slave_id: row.slave_id,
+            slave_name: row.slave_name.clone(),
+            point: row.point.clone(),
+            group: row.group.clone(),
+            value: row.value,
+            text: row.text.clone(),
+            raw: Vec::new(),
+            unit: row.unit.clone(),
+            timestamp: row.timestamp,
+            quality: if row.quality == Quality::Bad.as_str() {
+                Quality::Bad
+            } else {
+                Quality::Good
+            },
+            publish,
+        }
+    }
+}
+
+// 按顺序把数据分成多段，每段中同一从站的同一点位只出现一次；
+// 合并为消息时同一点位的多次采集不会互相覆盖，各段按采集时间先后发布
+fn cycles(rows: &[StoredReading]) -> Vec<&[StoredReading]> {
+    let mut chunks = Vec::new();
+    let mut seen: HashSet<(&str, u8, &str)> = HashSet::new();
+    let mut start = 0;
+    for (index, row) in rows.iter().enumerate() {
+        if !seen.insert((&row.gateway, row.slave_id, &row.point)) {
+            chunks.push(&rows[start..index]);
+            start = index;
+            seen.clear();
+            seen.insert((&row.gateway, row.slave_id, &row.point));
+        }
+    }
+    if start < rows.len() {
+        chunks.push(&rows[start..]);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::payload::format_timestamp;
+    use crate::test_support::{at, reading, MockBroker};
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    // 每个采集周期两个点位，第 i 个周期的采集时间为 1_700_000_000_000 + i 秒
+    const START: u64 = 1_700_000_000_000;
+
+    struct Setup {
+        broker: MockBroker,
+        client: Arc<MqttClient>,
+        store: ReadingStore,
+        _dir: tempfile::TempDir,
+    }
+
+    // 数据库中有 `cycles` 个未发布的采集周期（倒序写入，行ID与采集时间顺序相反）和一个已发布的周期
+    async fn setup(client_id: &str, cycles: u64) -> Setup {
+        let broker = MockBroker::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = ReadingStore::open(dir.path().join("readings.db").to_str().unwrap()).await.unwrap();
+        let cycle = |index: u64| {
+            ["a", "b"]
+                .iter()
+                .map(|point| {
+                    let mut reading = reading("10.0.0.1:502", 1, point, index as f64);
+                    reading.gateway_name = Some("PCS-A".to_string());
+                    reading.slave_name = Some("meter".to_string());
+                    reading.timestamp = at(START + index * 1000);
+                    reading
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut batches: Vec<(Vec<Reading>, bool)> = (0..cycles).rev().map(|i| (cycle(i), false)).collect();
+        batches.push((cycle(cycles), true));
+        store.insert(&batches).await.unwrap();
+
+        let config = config(&broker, client_id);
+        let client = Arc::new(MqttClient::from_settings(config.mqtt.as_ref().unwrap(), None).unwrap());
+        Setup {
+            broker,
+            client,
+            store,
+            _dir: dir,
+        }
+    }
+
+    fn config(broker: &MockBroker, client_id: &str) -> Config {
+        let config: Config = serde_yaml::from_str(&format!(
+            "version: 2\nmqtt:\n  broker_host: 127.0.0.1\n  broker_port: {}\n  client_id: {}\ngateways:\n  - ip: 10.0.0.1\n    name: PCS-A\n    slave_ids: [{{ id: 1, name: meter }}]\n    points:\n      - {{ name: a, address: 0 }}\n      - {{ name: b, address: 1 }}\n",
+            broker.port, client_id
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    fn replay_settings(mode: ReplayMode, batch_rows: u32, max_messages_per_second: f64) -> ReplaySettings {
+        ReplaySettings {
+            mode,
+            topic_template: "{prefix}/backfill/{gateway}/{slave}".to_string(),
+            batch_rows,
+            max_messages_per_second,
+        }
+    }
+
+    fn start(setup: &Setup, client_id: &str, settings: &ReplaySettings) {
+        let config = config(&setup.broker, client_id);
+        let replayer = Replayer::new(setup.store.clone(), Arc::clone(&setup.client), &config, settings).unwrap();
+        tokio::spawn(replayer.run());
+    }
+
+    // 消息中的周期序号（a 的值）和采集时间
+    fn cycles_of(payloads: &[Vec<u8>]) -> Vec<(u64, String)> {
+        payloads
+            .iter()
+            .map(|payload| {
+                let value: Value = serde_json::from_slice(payload).unwrap();
+                assert_eq!(value["values"]["a"], value["values"]["b"]);
+                let index = value["values"]["a"].as_f64().unwrap() as u64;
+                (index, value["timestamp"].as_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    fn timestamp(index: u64) -> String {
+        format_timestamp(at(START + index * 1000))
+    }
+
+    async fn wait_until_published(store: &ReadingStore) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while store.unpublished_count().await.unwrap() > 0 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn backfill_publishes_in_time_order_and_marks_rows() {
+        let setup = setup("replay-order", 6).await;
+        assert_eq!(setup.store.unpublished_count().await.unwrap(), 12);
+        start(&setup, "replay-order", &replay_settings(ReplayMode::Backfill, 4, 100.0));
+
+        let payloads = setup.broker.wait_for_topic("ems/backfill/PCS-A/meter", 6).await;
+        let expected: Vec<(u64, String)> = (0..6).map(|i| (i, timestamp(i))).collect();
+        assert_eq!(cycles_of(&payloads), expected);
+        wait_until_published(&setup.store).await;
+
+        // 已发布的周期不补发，实时数据的主题上没有补发的消息，补发的消息不保留
+        assert!(setup.broker.payloads("ems/PCS-A/meter").is_empty());
+        let publishes = setup.broker.publishes();
+        let backfill: Vec<_> = publishes.iter().filter(|p| p.topic.starts_with("ems/backfill/")).collect();
+        assert_eq!(backfill.len(), 6);
+        assert!(backfill.iter().all(|p| !p.retain));
+        setup.client.close().await;
+    }
+
+    #[tokio::test]
+    async fn original_mode_uses_live_topics_with_original_timestamps() {
+        let setup = setup("replay-original", 3).await;
+        start(&setup, "replay-original", &replay_settings(ReplayMode::Original, 500, 100.0));
+
+        let payloads = setup.broker.wait_for_topic("ems/PCS-A/meter", 3).await;
+        let expected: Vec<(u64, String)> = (0..3).map(|i| (i, timestamp(i))).collect();
+        assert_eq!(cycles_of(&payloads), expected);
+        wait_until_published(&setup.store).await;
+        assert!(setup.broker.payloads("ems/backfill/PCS-A/meter").is_empty());
+        setup.client.close().await;
+    }
+
+    #[tokio::test]
+    async fn replay_is_rate_limited() {
+        let setup = setup("replay-rate", 10).await;
+        setup.client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        let started = Instant::now();
+        start(&setup, "replay-rate", &replay_settings(ReplayMode::Backfill, 500, 20.0));
+
+        // 每秒20条，第一条立即发出，之后每50毫秒一条
+        tokio::time::sleep(Duration::from_millis(220)).await;
+        let early = setup.broker.payloads("ems/backfill/PCS-A/meter").len();
+        assert!((3..=6).contains(&early), "220 毫秒内发出了 {} 条", early);
+        setup.broker.wait_for_topic("ems/backfill/PCS-A/meter", 10).await;
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{:?}", elapsed);
+        setup.client.close().await;
+    }
+
+    #[tokio::test]
+    async fn interrupted_replay_resumes_from_the_unmarked_batch() {
+        let setup = setup("replay-resume", 10).await;
+        // 每批4行即2个周期，每秒10条
+        start(&setup, "replay-resume", &replay_settings(ReplayMode::Backfill, 4, 10.0));
+        setup.broker.wait_for_topic("ems/backfill/PCS-A/meter", 3).await;
+        setup.broker.drop_connections();
+        let mut state = setup.client.watch_state();
+        state.wait_for(|state| !state.connected).await.unwrap();
+        // 断开前已完整发出的第一批已标记，第二批还没有
+        let remaining = setup.store.unpublished_count().await.unwrap();
+        assert!((12..=16).contains(&remaining), "断开时还有 {} 行未发布", remaining);
+
+        state.wait_for(|state| state.connected).await.unwrap();
+        wait_until_published(&setup.store).await;
+        let cycles: Vec<u64> = cycles_of(&setup.broker.payloads("ems/backfill/PCS-A/meter"))
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        // 每个周期都发出过；重新发出的只有断开时未标记的那一批，之后仍按时间顺序
+        let distinct: BTreeSet<u64> = cycles.iter().copied().collect();
+        assert_eq!(distinct, (0..10).collect());
+        assert!(cycles.len() <= 10 + 2, "{:?}", cycles);
+        let resumed = cycles.windows(2).position(|pair| pair[1] < pair[0]);
+        if let Some(position) = resumed {
+            assert_eq!(cycles[position + 1] % 2, 0, "应从一批的开头重新发出: {:?}", cycles);
+            assert!(cycles[position + 1..].windows(2).all(|pair| pair[0] < pair[1]), "{:?}", cycles);
+        }
+        setup.client.close().await;
+    }
+}
@@ -0,0 +1,651 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, SecondsFormat, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
+use rumqttc::QoS;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::MqttSettings;
+use crate::device_configuration::point::Point;
+use crate::device_configuration::schedule::{ScheduleSettings, Trigger};
+use crate::modbus::decode::{DataType, WordOrder};
+use crate::modbus::scheduler::{GatewayRequest, WriteRequest};
+use crate::mqtt::client::MqttClient;
+use crate::reload::SharedWriters;
+
+/// 检查是否有需要执行的定时写入的间隔
+pub const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// 两次检查相隔超过该时间（程序停顿、系统休眠或时钟跳变）时，期间应执行的写入视为错过
+pub const MAX_CHECK_GAP: Duration = Duration::from_secs(60);
+/// 设置了 catch_up 的计划在程序启动时向前查找错过的写入的范围
+pub const CATCH_UP_WINDOW: Duration = Duration::from_secs(24 * 3600);
+// 等待采集任务完成写入的最长时间，包括排队等待正在进行的读取
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 定时写入的执行结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleOutcome {
+    /// 写入成功
+    Ok,
+    /// 网关未在采集、点位不存在或不可写，没有发出 Modbus 请求
+    Rejected,
+    /// Modbus 请求失败或超时
+    ModbusError,
+    /// 写入成功但回读的值不一致
+    VerifyFailed,
+}
+
+impl ScheduleOutcome {
+    /// 发布到结果主题的名称
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScheduleOutcome::Ok => "ok",
+            ScheduleOutcome::Rejected => "rejected",
+            ScheduleOutcome::ModbusError => "modbus_error",
+            ScheduleOutcome::VerifyFailed => "verify_failed",
+        }
+    }
+}
+
+impl fmt::Display for ScheduleOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 一次到期的定时写入
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledWrite {
+    /// 计划配置
+    pub settings: ScheduleSettings,
+    /// 应执行的时间（计划所在时区）
+    pub scheduled_at: DateTime<FixedOffset>,
+}
+
+// 一个定时写入计划的状态
+struct Entry {
+    settings: ScheduleSettings,
+    trigger: Trigger,
+    tz: Option<Tz>,
+    // 上一次检查的时间，从未检查过时为 None
+    checked: Option<DateTime<Utc>>,
+    // 上一次执行对应的本地时间，避免夏令时结束时同一时刻执行两次
+    fired: Option<NaiveDateTime>,
+}
+
+/// 定时写入的计时，与 Modbus 和 MQTT 无关，时间由调用方传入
+///
+/// # 说明
+/// * 每次检查时找出上一次检查之后到当前时间之间应执行的写入，精确到分钟
+/// * 两次检查相隔超过 [`MAX_CHECK_GAP`] 时，期间的写入视为错过：默认跳过并输出警告，
+///   设置了 catch_up 时只补执行最近的一次
+/// * 程序启动、计划新加入或重新启用后第一次检查时，之前的写入同样视为错过；
+///   设置了 catch_up 时补执行 [`CATCH_UP_WINDOW`] 内最近的一次
+/// * 本地时间按计划的时区计算（未配置时使用全局 timezone，都未配置时使用系统时区）：夏令时开始时不存在的时刻不执行，结束时重复的时刻只执行一次
+/// * 同一次检查中多个计划写入同一目标时只执行配置中靠后的一个，并输出警告
+#[derive(Default)]
+pub struct ScheduleEngine {
+    entries: Vec<Entry>,
+}
+
+impl ScheduleEngine {
+    /// 按配置中已启用的计划创建，`timezone` 为计划未配置时区时使用的全局时区
+    pub fn new(schedules: &[ScheduleSettings], timezone: Option<Tz>) -> Self {
+        let mut engine = ScheduleEngine::default();
+        engine.apply(schedules, timezone);
+        engine
+    }
+
+    /// 应用新的配置，配置和时区未变的计划保持原来的状态；新加入和重新启用的计划从下一次检查开始计时
+    pub fn apply(&mut self, schedules: &[ScheduleSettings], timezone: Option<Tz>) {
+        let mut old = std::mem::take(&mut self.entries);
+        for settings in schedules.iter().filter(|s| s.enabled) {
+            // 配置已校验过
+            let (Ok(trigger), Ok(tz)) = (settings.trigger(), settings.tz()) else {
+                continue;
+            };
+            let tz = tz.or(timezone);
+            if let Some(index) = old.iter().position(|e| e.settings == *settings && e.tz == tz) {
+                self.entries.push(old.swap_remove(index));
+                continue;
+            }
+            self.entries.push(Entry {
+                settings: settings.clone(),
+                trigger,
+                tz,
+                checked: None,
+                fired: None,
+            });
+        }
+    }
+
+    /// 检查到当前时间为止需要执行的写入
+    pub fn due(&mut self, now: DateTime<Utc>) -> Vec<ScheduledWrite> {
+        let mut due: Vec<ScheduledWrite> = Vec::new();
+        for entry in &mut self.entries {
+            let name = &entry.settings.name;
+            let times = match entry.checked.replace(now) {
+                None if entry.settings.catch_up => {
+                    let window = TimeDelta::from_std(CATCH_UP_WINDOW).unwrap_or_default();
+                    entry.occurrences(now - window, now).pop().into_iter().collect()
+                }
+                None => Vec::new(),
+                // 时钟回拨，等时间追上后继续
+                Some(checked) if now <= checked => {
+                    entry.checked = Some(checked);
+                    Vec::new()
+                }
+                Some(checked) if (now - checked).to_std().unwrap_or_default() > MAX_CHECK_GAP => {
+                    let mut missed = entry.occurrences(checked, now);
+                    let latest = missed.pop();
+                    if let Some(latest) = latest
+                        && entry.settings.catch_up
+                    {
+                        warn!(
+                            schedule = %name,
+                            missed = missed.len() + 1,
+                            "定时写入错过了执行时间，补执行最近的一次"
+                        );
+                        vec![latest]
+                    } else {
+                        if latest.is_some() {
+                            warn!(schedule = %name, missed = missed.len() + 1, "定时写入错过了执行时间，跳过");
+                        }
+                        Vec::new()
+                    }
+                }
+                Some(checked) => entry.occurrences(checked, now),
+            };
+            for time in times {
+                let local = time.naive_local();
+                if entry.fired.is_some_and(|fired| local <= fired) {
+                    continue;
+                }
+                entry.fired = Some(local);
+                let target = (&entry.settings.gateway, entry.settings.slave, entry.settings.target());
+                if let Some(index) = due.iter().position(|d| {
+                    (&d.settings.gateway, d.settings.slave, d.settings.target()) == target
+                }) {
+                    let replaced = due.remove(index);
+                    warn!(
+                        schedule = %name,
+                        replaced = %replaced.settings.name,
+                        target = %target.2,
+                        "多个定时写入同时写入同一目标，只执行配置中靠后的一个"
+                    );
+                }
+                due.push(ScheduledWrite {
+                    settings: entry.settings.clone(),
+                    scheduled_at: time,
+                });
+            }
+        }
+        due
+    }
+}
+
+impl Entry {
+    // (from, to] 之间应执行的时间，按先后排列
+    fn occurrences(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<DateTime<FixedOffset>> {
+        let mut times = Vec::new();
+        // from 之后的第一个整分钟
+        let Some(mut minute) = from
+            .with_second(0)
+            .and_then(|t| t.with_nanosecond(0))
+            .map(|t| t + TimeDelta::minutes(1))
+        else {
+            return times;
+        };
+        while minute <= to {
+            let local = match self.tz {
+                Some(tz) => minute.with_timezone(&tz).fixed_offset(),
+                None => minute.with_timezone(&Local).fixed_offset(),
+            };
+            if self.trigger.matches(&local.naive_local()) {
+                times.push(local);
+            }
+            minute += TimeDelta::minutes(1);
+        }
+        times
+    }
+}
+
+/// 可在检查任务和配置热加载间共享的定时写入
+pub type SharedSchedules = Arc<Schedules>;
+
+/// 定时写入：按计划把写入请求交给对应网关的采集任务执行，记录日志并把结果发布到 MQTT
+///
+/// 配置了 MQTT 时，每次执行的结果发布到 `<topic_prefix>/<client_id>/schedules/<name>`（不保留）。
+pub struct Schedules {
+    engine: Mutex<ScheduleEngine>,
+    writers: SharedWriters,
+    mqtt: Option<(Arc<MqttClient>, MqttSettings)>,
+}
+
+impl Schedules {
+    /// 创建定时写入
+    ///
+    /// # 参数说明
+    /// * `config` - 配置，使用其中的 schedules、timezone 和 mqtt
+    /// * `client` - MQTT 客户端，未配置 MQTT 时为 None
+    /// * `writers` - 正在采集的网关的写入入口
+    pub fn new(config: &Config, client: Option<Arc<MqttClient>>, writers: SharedWriters) -> SharedSchedules {
+        Arc::new(Schedules {
+            engine: Mutex::new(ScheduleEngine::new(&config.schedules, config.tz().unwrap_or_default())),
+            writers,
+            mqtt: client.zip(config.mqtt.clone()),
+        })
+    }
+
+    /// 配置热加载后应用新的定时写入配置
+    pub fn apply(&self, config: &Config) {
+        self.engine
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .apply(&config.schedules, config.tz().unwrap_or_default());
+    }
+
+    /// 每隔 [`SCHEDULE_CHECK_INTERVAL`] 检查一次，到期的写入在单独的任务中执行
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.check(Utc::now());
+        }
+    }
+
+    /// 检查到 `now` 为止需要执行的写入，每个写入在单独的任务中执行，返回开始执行的写入
+    pub fn check(self: &Arc<Self>, now: DateTime<Utc>) -> Vec<ScheduledWrite> {
+        let due = self
+            .engine
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .due(now);
+        for job in &due {
+            let schedules = Arc::clone(self);
+            let job = job.clone();
+            tokio::spawn(async move {
+                let result = schedules.execute(&job.settings).await;
+                schedules.report(&job, result);
+            });
+        }
+        due
+    }
+
+    // 执行一次写入
+    async fn execute(&self, settings: &ScheduleSettings) -> Result<(), (ScheduleOutcome, String)> {
+        let rejected = |message: String| (ScheduleOutcome::Rejected, message);
+        let (sender, point, registers) = {
+            let writers = self.writers.lock().unwrap_or_else(|e| e.into_inner());
+            let writer = writers
+                .iter()
+                .find(|w| {
+                    w.gateway.name.as_deref() == Some(settings.gateway.as_str())
+                        || format!("{}:{}", w.gateway.ip, w.gateway.port) == settings.gateway
+                })
+                .ok_or_else(|| rejected(format!("网关 {} 未在采集", settings.gateway)))?;
+            let points = writer
+                .points
+                .get(&settings.slave)
+                .ok_or_else(|| rejected(format!("从站 {} 未在采集", settings.slave)))?;
+            let mut point = match (&settings.point, settings.function_code, settings.address) {
+                (Some(name), _, _) => {
+                    let point = points
+                        .iter()
+                        .find(|p| p.name == *name)
+                        .ok_or_else(|| rejected(format!("从站 {} 没有点位 {}", settings.slave, name)))?;
+                    if !point.enabled {
+                        return Err(rejected(format!("点位 {} 已停用", name)));
+                    }
+                    if !point.writable {
+                        return Err(rejected(format!("点位 {} 不允许写入", name)));
+                    }
+                    point.clone()
+                }
+                (None, Some(code), Some(address)) => raw_point(code, address),
+                _ => return Err(rejected("没有配置写入目标".to_string())),
+            };
+            point.verify_write |= settings.verify;
+            let registers = point
+                .registers_for_value(settings.value)
+                .map_err(|e| rejected(e.to_string()))?;
+            (writer.sender.clone(), point, registers)
+        };
+
+        let modbus_error = |message: String| (ScheduleOutcome::ModbusError, message);
+        let (reply, result) = oneshot::channel();
+        let request = WriteRequest {
+            slave_id: settings.slave,
+            point,
+            registers,
+            function_code: settings.function_code,
+            reply,
+        };
+        let send = sender.send(GatewayRequest::Write(Box::new(request)));
+        match tokio::time::timeout(WRITE_TIMEOUT, send).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => return Err(modbus_error("网关已停止采集".to_string())),
+            Err(_) => return Err(modbus_error("网关的写入队列已满".to_string())),
+        }
+        match tokio::time::timeout(WRITE_TIMEOUT, result).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            // 回读不一致时写入本身已成功
+            Ok(Ok(Err(e))) if e.starts_with("回读校验失败") => Err((ScheduleOutcome::VerifyFailed, e)),
+            Ok(Ok(Err(e))) => Err(modbus_error(e)),
+            Ok(Err(_)) => Err(modbus_error("网关已停止采集".to_string())),
+            Err(_) => Err(modbus_error(format!("{} 秒内未完成写入", WRITE_TIMEOUT.as_secs()))),
+        }
+    }
+
+    // 输出日志，配置了 MQTT 时发布执行结果
+    fn report(&self, job: &ScheduledWrite, result: Result<(), (ScheduleOutcome, String)>) {
+        let settings = &job.settings;
+        let (outcome, error) = match result {
+            Ok(()) => (ScheduleOutcome::Ok, None),
+            Err((outcome, error)) => (outcome, Some(error)),
+        };
+        let target = settings.target();
+        match &error {
+            None => info!(
+                schedule = %settings.name,
+                gateway = %settings.gateway,
+                slave_id = settings.slave,
+                target = %target,
+                value = settings.value,
+                "定时写入成功"
+            ),
+            Some(error) => warn!(
+                schedule = %settings.name,
+                gateway = %settings.gateway,
+                slave_id = settings.slave,
+                target = %target,
+                value = settings.value,
+                result = %outcome,
+                error = %error,
+                "定时写入失败"
+            ),
+        }
+        let Some((client, mqtt)) = &self.mqtt else {
+            return;
+        };
+        let payload = json!({
+            "schedule": settings.name,
+            "gateway": settings.gateway,
+            "slave_id": settings.slave,
+            "target": target,
+            "value": settings.value,
+            "scheduled_at": job.scheduled_at.to_rfc3339_opts(SecondsFormat::Secs, false),
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "result": outcome.as_str(),
+            "error": error,
+        });
+        let topic = mqtt.schedule_topic(&settings.name);
+        let client = Arc::clone(client);
+        tokio::spawn(async move {
+            let payload = payload.to_string();
+            if let Err(e) = client
+                .publish(&topic, QoS::AtLeastOnce, false, payload.as_bytes())
+                .await
+            {
+                warn!(topic = %topic, error = %e, "发布定时写入结果失败");
+            }
+        });
+    }
+}
+
+// 直接写入寄存器时使用的点位：线圈为 bool，寄存器为 u16
+fn raw_point(function_code: u8, address: u16) -> Point {
+    let coil = matches!(function_code, 0x05 | 0x0F);
+    Point {
+        name: format!("fc{}:{}", function_code, address),
+        function_code: if coil { 0x01 } else { 0x03 },
+        address,
+        data_type: if coil { DataType::Bool } else { DataType::U16 },
+        length: None,
+        word_order: WordOrder::default(),
+        scale: 1.0,
+        offset: 0.0,
+        scale_factor: None,
+        unit: None,
+        source_unit: None,
+        group: None,
+        enabled: true,
+        writable: true,
+        verify_write: false,
+        qos: None,
+        retain: None,
+        deadband: None,
+        device_class: None,
+        sim: None,
+        bits: BTreeMap::new(),
+        bit: None,
+        conversion: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::scheduler::PollEvent;
+    use crate::reload::GatewayTasks;
+    use crate::test_support::{MockBroker, MockModbus};
+    use serde_json::Value;
+
+    const SCHEDULES: &str = "- { name: valley_charge, gateway: PCS-A, slave: 1, point: power_setpoint, value: -100, at: \"23:00\", days: [mon, tue, wed, thu, fri] }\n- { name: peak_discharge, gateway: PCS-A, slave: 1, point: power_setpoint, value: 80, cron: \"0 10,18 * * *\", verify: true }\n- { name: reset_fault, gateway: PCS-A, slave: 1, function_code: 5, address: 16, value: 1, cron: \"0 */6 * * *\" }\n";
+
+    fn schedules(yaml: &str) -> Vec<ScheduleSettings> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().to_utc()
+    }
+
+    // 从 from 到 to 每隔 step 秒检查一次，返回 (计划名称, 应执行的时间)
+    fn step(
+        engine: &mut ScheduleEngine,
+        from: &str,
+        to: &str,
+        step: i64,
+    ) -> Vec<(String, String)> {
+        let (mut now, to) = (time(from), time(to));
+        let mut fired = Vec::new();
+        while now <= to {
+            for job in engine.due(now) {
+                fired.push((job.settings.name, job.scheduled_at.to_rfc3339()));
+            }
+            now += TimeDelta::seconds(step);
+        }
+        fired
+    }
+
+    fn names(fired: &[(String, String)]) -> Vec<(&str, &str)> {
+        fired.iter().map(|(name, at)| (name.as_str(), at.as_str())).collect()
+    }
+
+    #[test]
+    fn stepping_through_two_days_fires_each_schedule_at_local_time() {
+        let shanghai: Tz = "Asia/Shanghai".parse().unwrap();
+        let mut engine = ScheduleEngine::new(&schedules(SCHEDULES), Some(shanghai));
+        // 2024-03-03 为星期日，valley_charge 只在工作日执行
+        let fired = step(&mut engine, "2024-03-02T23:59:30+08:00", "2024-03-04T23:59:59+08:00", 10);
+        let mut expected = Vec::new();
+        for day in ["2024-03-03", "2024-03-04"] {
+            for (name, hour) in [
+                ("reset_fault", "00"),
+                ("reset_fault", "06"),
+                ("peak_discharge", "10"),
+                ("reset_fault", "12"),
+                ("peak_discharge", "18"),
+                ("reset_fault", "18"),
+            ] {
+                expected.push((name, format!("{}T{}:00:00+08:00", day, hour)));
+            }
+        }
+        expected.push(("valley_charge", "2024-03-04T23:00:00+08:00".to_string()));
+        let expected: Vec<(&str, &str)> = expected.iter().map(|(n, t)| (*n, t.as_str())).collect();
+        // 同一分钟的计划按配置顺序
+        assert_eq!(names(&fired), expected);
+    }
+
+    #[test]
+    fn missed_runs_are_skipped_unless_catch_up() {
+        let yaml = "- { name: noon, gateway: PCS-A, slave: 1, point: p, value: 1, at: \"12:00\", timezone: Asia/Shanghai }\n- { name: hourly, gateway: PCS-A, slave: 1, point: q, value: 1, cron: \"0 * * * *\", timezone: Asia/Shanghai, catch_up: true }\n";
+        let mut engine = ScheduleEngine::new(&schedules(yaml), None);
+
+        // 启动时只有 catch_up 的计划补执行最近一次
+        let fired = step(&mut engine, "2024-03-04T10:30:00+08:00", "2024-03-04T10:30:00+08:00", 10);
+        assert_eq!(names(&fired), [("hourly", "2024-03-04T10:00:00+08:00")]);
+        // 停顿超过 MAX_CHECK_GAP：noon 跳过，hourly 只补执行 13:00
+        let fired = step(&mut engine, "2024-03-04T13:30:00+08:00", "2024-03-04T13:30:00+08:00", 10);
+        assert_eq!(names(&fired), [("hourly", "2024-03-04T13:00:00+08:00")]);
+        // 时钟回拨后等时间追上再继续，不重复执行
+        let fired = step(&mut engine, "2024-03-04T13:00:00+08:00", "2024-03-04T13:29:50+08:00", 10);
+        assert!(fired.is_empty());
+        let fired = step(&mut engine, "2024-03-04T13:30:10+08:00", "2024-03-04T14:00:30+08:00", 10);
+        assert_eq!(names(&fired), [("hourly", "2024-03-04T14:00:00+08:00")]);
+
+        // 不超过 MAX_CHECK_GAP 的停顿不算错过
+        let fired = step(&mut engine, "2024-03-04T14:59:30+08:00", "2024-03-04T15:00:29+08:00", 59);
+        assert_eq!(names(&fired), [("hourly", "2024-03-04T15:00:00+08:00")]);
+    }
+
+    #[test]
+    fn same_target_at_same_time_runs_last_configured() {
+        let yaml = "- { name: first, gateway: PCS-A, slave: 1, point: p, value: 1, at: \"08:00\" }\n- { name: other, gateway: PCS-A, slave: 2, point: p, value: 2, at: \"08:00\" }\n- { name: second, gateway: PCS-A, slave: 1, point: p, value: 3, at: \"08:00\" }\n";
+        let mut engine = ScheduleEngine::new(&schedules(yaml), Some(Tz::UTC));
+        let fired = step(&mut engine, "2024-03-04T07:59:50Z", "2024-03-04T08:00:10Z", 10);
+        assert_eq!(
+            names(&fired),
+            [("other", "2024-03-04T08:00:00+00:00"), ("second", "2024-03-04T08:00:00+00:00")]
+        );
+    }
+
+    #[test]
+    fn daylight_saving_gaps_skip_and_repeats_fire_once() {
+        let yaml = "- { name: gap, gateway: PCS-A, slave: 1, point: p, value: 1, at: \"02:30\", timezone: America/New_York }\n- { name: repeat, gateway: PCS-A, slave: 1, point: q, value: 1, at: \"01:30\", timezone: America/New_York }\n";
+        let mut engine = ScheduleEngine::new(&schedules(yaml), None);
+        // 2024-03-10 02:00 跳到 03:00，02:30 不存在
+        let fired = step(&mut engine, "2024-03-10T00:00:00-05:00", "2024-03-10T05:00:00-04:00", 30);
+        assert_eq!(names(&fired), [("repeat", "2024-03-10T01:30:00-05:00")]);
+        // 2024-11-03 02:00 回到 01:00，01:30 出现两次只执行第一次
+        let fired = step(&mut engine, "2024-11-03T00:00:00-04:00", "2024-11-03T04:00:00-05:00", 30);
+        assert_eq!(
+            names(&fired),
+            [("repeat", "2024-11-03T01:30:00-04:00"), ("gap", "2024-11-03T02:30:00-05:00")]
+        );
+    }
+
+    #[test]
+    fn apply_keeps_state_of_unchanged_and_restarts_reenabled_schedules() {
+        let yaml = "- { name: hourly, gateway: PCS-A, slave: 1, point: p, value: 1, cron: \"0 * * * *\", catch_up: true }\n";
+        let mut engine = ScheduleEngine::new(&schedules(yaml), Some(Tz::UTC));
+        assert_eq!(step(&mut engine, "2024-03-04T10:30:00Z", "2024-03-04T10:30:00Z", 1).len(), 1);
+        // 配置不变时保留状态，不再补执行
+        engine.apply(&schedules(yaml), Some(Tz::UTC));
+        assert!(step(&mut engine, "2024-03-04T10:30:10Z", "2024-03-04T10:30:10Z", 1).is_empty());
+        // 停用后不执行，重新启用时作为新的计划补执行
+        engine.apply(&schedules(&yaml.replace(" }", ", enabled: false }")), Some(Tz::UTC));
+        assert!(step(&mut engine, "2024-03-04T10:59:50Z", "2024-03-04T11:00:10Z", 10).is_empty());
+        engine.apply(&schedules(yaml), Some(Tz::UTC));
+        let fired = step(&mut engine, "2024-03-04T11:00:20Z", "2024-03-04T11:00:20Z", 1);
+        assert_eq!(names(&fired), [("hourly", "2024-03-04T11:00:00+00:00")]);
+    }
+
+    #[tokio::test]
+    async fn writes_hit_the_mock_server_at_scheduled_times() {
+        let modbus = MockModbus::start().await;
+        let broker = MockBroker::start().await;
+        let yaml = format!(
+            "version: 2\ntimezone: Asia/Shanghai\nmqtt:\n  broker_host: 127.0.0.1\n  broker_port: {}\n  client_id: schedule-test\ngateways:\n  - ip: 127.0.0.1\n    port: {}\n    name: PCS-A\n    poll_interval_ms: 600000\n    slave_ids: [1]\n    points:\n      - {{ name: power_setpoint, address: 10, data_type: i16, writable: true }}\nschedules:\n{}",
+            broker.port,
+            modbus.port,
+            SCHEDULES
+                .lines()
+                .map(|line| format!("  {}\n", line))
+                .collect::<String>()
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        let mut tasks = GatewayTasks::new(|_: PollEvent| {});
+        tasks.apply(&config);
+        let settings = config.mqtt.clone().unwrap();
+        let client = Arc::new(MqttClient::from_settings(&settings, None).unwrap());
+        client.watch_state().wait_for(|state| state.connected).await.unwrap();
+        let schedules = Schedules::new(&config, Some(Arc::clone(&client)), tasks.writers());
+
+        // 模拟的时钟每次前进10秒，走完 2024-03-04（星期一）一整天
+        let writes = || {
+            modbus
+                .requests()
+                .into_iter()
+                .filter(|r| matches!(r.function_code, 5 | 6 | 16))
+                .collect::<Vec<_>>()
+        };
+        let mut now = time("2024-03-03T23:59:30+08:00");
+        let mut hits = Vec::new();
+        while now < time("2024-03-05T00:00:00+08:00") {
+            let due = schedules.check(now);
+            if !due.is_empty() {
+                let before = writes().len();
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while writes().len() < before + due.len() {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                })
+                .await
+                .unwrap();
+                let local = now.with_timezone(&"Asia/Shanghai".parse::<Tz>().unwrap());
+                let mut landed: Vec<_> = writes()[before..]
+                    .iter()
+                    .map(|r| (r.function_code, r.address, r.values.clone()))
+                    .collect();
+                landed.sort();
+                for (function_code, address, values) in landed {
+                    hits.push((local.format("%H:%M").to_string(), function_code, address, values));
+                }
+            }
+            now += TimeDelta::seconds(10);
+        }
+        let expected: Vec<(String, u8, u16, Vec<u16>)> = [
+            ("00:00", 5, 16, vec![1]),
+            ("06:00", 5, 16, vec![1]),
+            ("10:00", 6, 10, vec![80]),
+            ("12:00", 5, 16, vec![1]),
+            ("18:00", 5, 16, vec![1]),
+            ("18:00", 6, 10, vec![80]),
+            ("23:00", 6, 10, vec![(-100i16) as u16]),
+        ]
+        .into_iter()
+        .map(|(at, code, address, values)| (at.to_string(), code, address, values))
+        .collect();
+        assert_eq!(hits, expected);
+        assert_eq!(modbus.get(1, 3, 10, 1), [(-100i16) as u16]);
+
+        // 每次执行的结果发布到 MQTT，设置了 verify 的写入回读校验
+        let payloads = broker.wait_for_topic(&settings.schedule_topic("peak_discharge"), 2).await;
+        let result: Value = serde_json::from_slice(&payloads[1]).unwrap();
+        assert_eq!(result["result"], "ok");
+        assert_eq!(result["scheduled_at"], "2024-03-04T18:00:00+08:00");
+        assert_eq!(result["target"], "power_setpoint");
+        assert_eq!(result["value"], 80.0);
+        assert!(result["error"].is_null());
+        let verified = modbus
+            .requests()
+            .windows(2)
+            .filter(|pair| {
+                pair[0].function_code == 6 && pair[0].values == [80] && pair[1].function_code == 3
+            })
+            .count();
+        assert_eq!(verified, 2);
+        broker.wait_for_topic(&settings.schedule_topic("reset_fault"), 4).await;
+        broker.wait_for_topic(&settings.schedule_topic("valley_charge"), 1).await;
+        tasks.shutdown().await;
+    }
+}
@@ -0,0 +1,42 @@
+use tracing::{error, warn};
+
+/// 等待退出信号：Ctrl+C（SIGINT），Unix 下还有 SIGTERM
+///
+/// 无法注册信号处理时输出错误并一直等待，程序只能被强制结束。
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                error!(error = %e, "无法注册 SIGTERM 处理");
+                ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    ctrl_c().await;
+}
+
+/// 收到第二个退出信号时立即结束程序，不再等待正常退出完成
+pub fn force_exit_on_signal() {
+    tokio::spawn(async {
+        wait_for_signal().await;
+        warn!("再次收到退出信号，立即退出");
+        std::process::exit(1);
+    });
+}
+
+// 注册失败时一直等待，避免误判为收到信号
+async fn ctrl_c() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!(error = %e, "无法注册 Ctrl+C 处理");
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+use crate::device_configuration::modbus::Config;
+use crate::modbus::reading::{Quality, Reading};
+use crate::modbus::scheduler::PollEvent;
+
+/// 检查点位是否过期的间隔
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// 点位的键：网关地址、从站ID和点位名称
+type PointKey = (String, u8, String);
+
+// 一个点位最近一次读到的值
+struct Tracked {
+    last: Reading,
+    seen: Instant,
+    stale: bool,
+}
+
+/// 过期数据检测：点位超过 采集周期 × multiplier 没有读到新值时，以最后一次的值产生一条质量为 stale 的数据
+///
+/// # 说明
+/// * 每个点位过期时只产生一次 stale 数据，之后重新读到值时恢复为正常的数据
+/// * 只检测配置中已启用的采集点位，从未读到过值的点位不检测
+/// * 时间由调用方传入，不依赖系统时钟
+#[derive(Default)]
+pub struct StalenessMonitor {
+    multiplier: f64,
+    // 每个点位允许的最长间隔
+    limits: HashMap<PointKey, Duration>,
+    points: HashMap<PointKey, Tracked>,
+}
+
+/// 可在采集任务和检测任务间共享的过期数据检测
+pub type SharedStaleness = Arc<Mutex<StalenessMonitor>>;
+
+impl StalenessMonitor {
+    /// 按配置创建，未配置 staleness 段时不检测任何点位
+    pub fn new(config: &Config) -> Self {
+        let mut monitor = StalenessMonitor::default();
+        monitor.apply(config);
+        monitor
+    }
+
+    /// 应用新的配置，按新的采集周期计算过期时间，不再采集的点位不再检测
+    pub fn apply(&mut self, config: &Config) {
+        self.limits.clear();
+        if let Some(settings) = &config.staleness {
+            self.multiplier = settings.multiplier;
+            for gateway in config.gateways.iter().filter(|g| g.enabled) {
+                let address = format!("{}:{}", gateway.ip, gateway.port);
+                for slave in gateway.enabled_slaves() {
+                    // 配置已校验过，点位表一定存在
+                    let Ok(points) = config.published_points(gateway, slave) else {
+                        continue;
+                    };
+                    for point in points {
+                        let limit = config
+                            .poll_interval(gateway, &point)
+                            .mul_f64(self.multiplier);
+                        self.limits
+                            .insert((address.clone(), slave.id, point.name), limit);
+                    }
+                }
+            }
+        }
+        let limits = &self.limits;
+        self.points.retain(|key, _| limits.contains_key(key));
+    }
+
+    /// 记录读到的数据
+    pub fn record(&mut self, readings: &[Reading], now: Instant) {
+        for reading in readings.iter().filter(|r| r.quality != Quality::Stale) {
+            let key = (reading.gateway.clone(), reading.slave_id, reading.point.clone());
+            if !self.limits.contains_key(&key) {
+                continue;
+            }
+            if self.points.get(&key).is_some_and(|p| p.stale) {
+                debug!(
+                    gateway = %reading.gateway,
+                    slave_id = reading.slave_id,
+                    point = %reading.point,
+                    "点位恢复更新"
+                );
+            }
+            self.points.insert(
+                key,
+                Tracked {
+                    last: reading.clone(),
+                    seen: now,
+                    stale: false,
+                },
+            );
+        }
+    }
+
+    /// 检查所有点位，返回刚刚过期的点位（质量为 stale、值和时间为最后一次读到的）
+    pub fn check(&mut self, now: Instant) -> Vec<Reading> {
+        let mut expired = Vec::new();
+        for (key, point) in &mut self.points {
+            let Some(limit) = self.limits.get(key) else {
+                continue;
+            };
+            if point.stale || now.saturating_duration_since(point.seen) <= *limit {
+                continue;
+            }
+            point.stale = true;
+            expired.push(Reading {
+                quality: Quality::Stale,
+                ..point.last.clone()
+            });
+        }
+        if !expired.is_empty() {
+            info!(count = expired.len(), "点位超过预期时间没有更新，标记为过期");
+        }
+        expired
+    }
+}
+
+/// 每隔 [`CHECK_INTERVAL`] 检查一次，把过期的点位作为采集数据交给 on_event
+pub async fn run<F>(monitor: SharedStaleness, on_event: F)
+where
+    F: Fn(PollEvent),
+{
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let expired = monitor
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .check(Instant::now());
+        if !expired.is_empty() {
+            on_event(PollEvent::Readings(expired));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::latest::ReadingCache;
+    use crate::test_support::reading;
+
+    const GATEWAY: &str = "10.0.0.1:502";
+
+    fn config(staleness: &str) -> Config {
+        let yaml = format!(
+            "version: 2\n{}gateways:\n  - ip: 10.0.0.1\n    poll_interval_ms: 1000\n    slave_ids: [1]\n    points:\n      - {{ name: power, address: 0 }}\n      - {{ name: voltage, address: 1 }}\n",
+            staleness
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    #[test]
+    fn missed_cycles_emit_stale_once_and_next_read_restores_good() {
+        let mut monitor = StalenessMonitor::new(&config("staleness: { multiplier: 2 }\n"));
+        let cache = ReadingCache::new();
+        let mut changes = cache.subscribe_changes();
+        let start = Instant::now();
+        let second = |n: f64| start + Duration::from_secs_f64(n);
+
+        let first = reading(GATEWAY, 1, "power", 12.5);
+        monitor.record(std::slice::from_ref(&first), start);
+        cache.update(std::slice::from_ref(&first));
+
+        // 错过一个周期还不算过期，错过两个周期后产生一条 stale 数据
+        assert!(monitor.check(second(1.5)).is_empty());
+        assert!(monitor.check(second(2.0)).is_empty());
+        let expired = monitor.check(second(2.5));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].quality, Quality::Stale);
+        assert_eq!(
+            (expired[0].point.as_str(), expired[0].value, expired[0].timestamp),
+            ("power", 12.5, first.timestamp),
+            "值和时间为最后一次读到的"
+        );
+        cache.update(&expired);
+        assert_eq!(cache.stale_count(GATEWAY, 1), 1);
+
+        // 之后的检查不再重复产生，交回给 record 的 stale 数据也不算读到新值
+        monitor.record(&expired, second(3.0));
+        for n in [3.0, 5.0, 60.0] {
+            assert!(monitor.check(second(n)).is_empty());
+        }
+
+        // 重新读到值后恢复，之后再次错过两个周期时重新过期
+        let recovered = reading(GATEWAY, 1, "power", 13.0);
+        monitor.record(std::slice::from_ref(&recovered), second(61.0));
+        cache.update(std::slice::from_ref(&recovered));
+        assert_eq!(cache.stale_count(GATEWAY, 1), 0);
+        assert!(monitor.check(second(62.0)).is_empty());
+        assert_eq!(monitor.check(second(63.5)).len(), 1);
+
+        let mut qualities = Vec::new();
+        while let Ok(reading) = changes.try_recv() {
+            qualities.push(reading.quality);
+        }
+        assert_eq!(qualities, [Quality::Good, Quality::Stale, Quality::Good]);
+    }
+
+    #[test]
+    fn only_configured_points_that_were_read_are_checked() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(3600);
+
+        // 未配置 staleness 段时不检测
+        let mut monitor = StalenessMonitor::new(&config(""));
+        monitor.record(&[reading(GATEWAY, 1, "power", 1.0)], start);
+        assert!(monitor.check(later).is_empty());
+
+        // 从未读到过的点位（voltage）和配置中没有的点位不检测
+        let mut monitor = StalenessMonitor::new(&config("staleness: {}\n"));
+        monitor.record(
+            &[
+                reading(GATEWAY, 1, "power", 1.0),
+                reading(GATEWAY, 2, "power", 1.0),
+                reading(GATEWAY, 1, "unknown", 1.0),
+            ],
+            start,
+        );
+        assert!(monitor.check(start + Duration::from_secs(3)).is_empty());
+        let expired = monitor.check(start + Duration::from_millis(3001));
+        let points: Vec<_> = expired.iter().map(|r| (r.slave_id, r.point.as_str())).collect();
+        assert_eq!(points, [(1, "power")]);
+
+        // 配置中删除的点位不再检测，新的采集周期立即生效
+        let mut monitor = StalenessMonitor::new(&config("staleness: {}\n"));
+        monitor.record(
+            &[reading(GATEWAY, 1, "power", 1.0), reading(GATEWAY, 1, "voltage", 1.0)],
+            start,
+        );
+        let mut reduced = config("staleness: {}\n");
+        reduced.gateways[0].points.retain(|p| p.name == "voltage");
+        reduced.gateways[0].poll_interval_ms = 10_000;
+        monitor.apply(&reduced);
+        assert!(monitor.check(start + Duration::from_secs(29)).is_empty());
+        let expired = monitor.check(start + Duration::from_secs(31));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].point, "voltage");
+    }
+}
@@ -0,0 +1,721 @@
+use sqlx::Row;
+use sqlx::sqlite::{
+    SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::device_configuration::storage::StorageSettings;
+use crate::modbus::reading::{Quality, Reading};
+use crate::mqtt::batch::ReadingKey;
+use crate::mqtt::client::{ConnectionState, MqttClient};
+use crate::pipeline::Sink;
+
+// 依次执行的建表和升级语句，已执行到第几条记录在 PRAGMA user_version 中
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE readings (
+        id INTEGER PRIMARY KEY,
+        gateway TEXT NOT NULL,
+        gateway_name TEXT,
+        slave_id INTEGER NOT NULL,
+        slave_name TEXT,
+        point TEXT NOT NULL,
+        poll_group TEXT,
+        timestamp INTEGER NOT NULL,
+        value REAL NOT NULL,
+        unit TEXT,
+        quality TEXT NOT NULL,
+        published INTEGER NOT NULL
+    );
+    CREATE INDEX readings_timestamp ON readings (timestamp);
+    CREATE INDEX readings_unpublished ON readings (timestamp, id) WHERE published = 0;",
+    // 质量为 bad 的数据值为 NaN，SQLite 保存为 NULL，value 列需要允许 NULL
+    "CREATE TABLE readings_v2 (
+        id INTEGER PRIMARY KEY,
+        gateway TEXT NOT NULL,
+        gateway_name TEXT,
+        slave_id INTEGER NOT NULL,
+        slave_name TEXT,
+        point TEXT NOT NULL,
+        poll_group TEXT,
+        timestamp INTEGER NOT NULL,
+        value REAL,
+        unit TEXT,
+        quality TEXT NOT NULL,
+        published INTEGER NOT NULL
+    );
+    INSERT INTO readings_v2 SELECT * FROM readings;
+    DROP TABLE readings;
+    ALTER TABLE readings_v2 RENAME TO readings;
+    CREATE INDEX readings_timestamp ON readings (timestamp);
+    CREATE INDEX readings_unpublished ON readings (timestamp, id) WHERE published = 0;",
+    // 字符串点位的值保存在 text 列，value 为 NULL
+    "ALTER TABLE readings ADD COLUMN text TEXT;",
+];
+
+// 超出大小上限时每次删除的最早数据行数
+const PRUNE_BATCH_ROWS: i64 = 1000;
+
+// 检查保留天数和大小上限的间隔
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// 本地数据库中保存的一条采集数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredReading {
+    /// 行ID，按写入顺序递增
+    pub id: i64,
+    /// 网关地址，格式为 ip:port
+    pub gateway: String,
+    /// 网关名称
+    pub gateway_name: Option<String>,
+    /// 从站ID
+    pub slave_id: u8,
+    /// 从站名称
+    pub slave_name: Option<String>,
+    /// 点位名称
+    pub point: String,
+    /// 点位所属的采集组
+    pub group: Option<String>,
+    /// 采集时间
+    pub timestamp: SystemTime,
+    /// 工程值，值不可用（NaN）时数据库中为 NULL，读出后为 NaN
+    pub value: f64,
+    /// 字符串点位的值
+    pub text: Option<String>,
+    /// 工程单位
+    pub unit: Option<String>,
+    /// 数据质量
+    pub quality: String,
+    /// 是否已通过 MQTT 发出
+    pub published: bool,
+}
+
+/// SQLite 数据库中的采集数据
+///
+/// 打开时自动建表，旧版本的数据库自动升级。时间以 UTC 毫秒时间戳保存。
+#[derive(Debug, Clone)]
+pub struct ReadingStore {
+    pool: SqlitePool,
+}
+
+impl ReadingStore {
+    /// 打开数据库，文件不存在时创建
+    pub async fn open(path: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .auto_vacuum(SqliteAutoVacuum::Incremental);
+        // 写入都来自同一个任务，一个连接就够了
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        let store = ReadingStore { pool };
+        store.migrate().await?;
+        Ok(store)
+    }
+
+    // 执行尚未执行过的建表和升级语句
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let version: i64 = sqlx::query_scalar("PRAGMA user_version")
+            .fetch_one(&mut *tx)
+            .await?;
+        let applied = usize::try_from(version).unwrap_or(0);
+        if applied > MIGRATIONS.len() {
+            return Err(sqlx::Error::Protocol(format!(
+                "数据库版本 {} 高于程序支持的版本 {}",
+                version,
+                MIGRATIONS.len()
+            )));
+        }
+        for migration in &MIGRATIONS[applied..] {
+            sqlx::raw_sql(migration).execute(&mut *tx).await?;
+        }
+        if applied < MIGRATIONS.len() {
+            // PRAGMA 不支持绑定参数
+            sqlx::raw_sql(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))
+                .execute(&mut *tx)
+                .await?;
+            info!(from = applied, to = MIGRATIONS.len(), "本地数据库已升级");
+        }
+        tx.commit().await
+    }
+
+    /// 在一个事务中写入多个采集周期的数据
+    ///
+    /// # 参数说明
+    /// * `batches` - 每个采集周期的数据，以及这些数据是否已通过 MQTT 发出
+    pub async fn insert(&self, batches: &[(Vec<Reading>, bool)]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for (readings, published) in batches {
+            for reading in readings {
+                sqlx::query(
+                    "INSERT INTO readings (gateway, gateway_name, slave_id, slave_name, point, \
+                     poll_group, timestamp, value, text, unit, quality, published) \
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&reading.gateway)
+                .bind(&reading.gateway_name)
+                .bind(reading.slave_id)
+                .bind(&reading.slave_name)
+                .bind(&reading.point)
+                .bind(&reading.group)
+                .bind(to_millis(reading.timestamp))
+                .bind(reading.value)
+                .bind(&reading.text)
+                .bind(&reading.unit)
+                .bind(reading.quality.as_str())
+                .bind(*published)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await
+    }
+
+    /// 按采集时间顺序取出最早的未发布数据
+    pub async fn unpublished(&self, limit: u32) -> Result<Vec<StoredReading>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT id, gateway, gateway_name, slave_id, slave_name, point, poll_group, \
+             timestamp, value, text, unit, quality, published \
+             FROM readings WHERE published = 0 ORDER BY timestamp, id LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(stored_reading).collect()
+    }
+
+    /// 未发布的数据条数
+    pub async fn unpublished_count(&self) -> Result<u64, sqlx::Error> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM readings WHERE published = 0")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count.max(0) as u64)
+    }
+
+    /// 在一个事务中把数据标记为已发布
+    pub async fn mark_published(&self, ids: &[i64]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for id in ids {
+            sqlx::query("UPDATE readings SET published = 1 WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    /// 在一个事务中按（网关地址，从站ID，点位名称，采集时间）把数据标记为已发布
+    ///
+    /// # 返回值
+    /// * 数据库中还没有对应行的键，例如本地存储的写入晚于 MQTT 发布
+    pub async fn mark_published_keys(
+        &self,
+        keys: &[ReadingKey],
+    ) -> Result<Vec<ReadingKey>, sqlx::Error> {
+        let mut missing = Vec::new();
+        let mut tx = self.pool.begin().await?;
+        for key in keys {
+            let (gateway, slave_id, point, timestamp) = key;
+            let result = sqlx::query(
+                "UPDATE readings SET published = 1 \
+                 WHERE gateway = ? AND slave_id = ? AND point = ? AND timestamp = ?",
+            )
+            .bind(gateway)
+            .bind(slave_id)
+            .bind(point)
+            .bind(to_millis(*timestamp))
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() == 0 {
+                missing.push(key.clone());
+            }
+        }
+        tx.commit().await?;
+        Ok(missing)
+    }
+
+    /// 删除采集时间早于 cutoff 的数据，返回删除的行数
+    pub async fn delete_before(&self, cutoff: SystemTime) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM readings WHERE timestamp < ?")
+            .bind(to_millis(cutoff))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 数据实际占用的字节数（不含已释放、等待回收的页）
+    pub async fn size_bytes(&self) -> Result<u64, sqlx::Error> {
+        let pages: i64 = sqlx::query_scalar("PRAGMA page_count")
+            .fetch_one(&self.pool)
+            .await?;
+        let free: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+            .fetch_one(&self.pool)
+            .await?;
+        let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(((pages - free).max(0) * page_size) as u64)
+    }
+
+    /// 从最早的数据开始删除，直到占用不超过 max_bytes，返回删除的行数
+    ///
+    /// 删除后回收空闲页，数据库文件随之缩小。
+    pub async fn shrink_to(&self, max_bytes: u64) -> Result<u64, sqlx::Error> {
+        let mut deleted = 0;
+        while self.size_bytes().await? > max_bytes {
+            let result = sqlx::query(
+                "DELETE FROM readings WHERE id IN \
+                 (SELECT id FROM readings ORDER BY timestamp, id LIMIT ?)",
+            )
+            .bind(PRUNE_BATCH_ROWS)
+            .execute(&self.pool)
+            .await?;
+            if result.rows_affected() == 0 {
+                break;
+            }
+            deleted += result.rows_affected();
+        }
+        if deleted > 0 {
+            sqlx::raw_sql("PRAGMA incremental_vacuum")
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(deleted)
+    }
+
+    /// 按保留天数和大小上限删除旧数据，返回删除的行数
+    pub async fn enforce_retention(&self, settings: &StorageSettings) -> Result<u64, sqlx::Error> {
+        let mut deleted = 0;
+        if let Some(days) = settings.retention_days {
+            let age = Duration::from_secs(u64::from(days) * 86_400);
+            let cutoff = SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH);
+            deleted += self.delete_before(cutoff).await?;
+        }
+        if let Some(max_mb) = settings.max_size_mb {
+            deleted += self.shrink_to(max_mb.saturating_mul(1024 * 1024)).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// 关闭数据库连接
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+fn stored_reading(row: &sqlx::sqlite::SqliteRow) -> Result<StoredReading, sqlx::Error> {
+    let millis: i64 = row.try_get("timestamp")?;
+    Ok(StoredReading {
+        id: row.try_get("id")?,
+        gateway: row.try_get("gateway")?,
+        gateway_name: row.try_get("gateway_name")?,
+        slave_id: row.try_get("slave_id")?,
+        slave_name: row.try_get("slave_name")?,
+        point: row.try_get("point")?,
+        group: row.try_get("poll_group")?,
+        timestamp: UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64),
+        value: row.try_get::<Option<f64>, _>("value")?.unwrap_or(f64::NAN),
+        text: row.try_get("text")?,
+        unit: row.try_get("unit")?,
+        quality: row.try_get("quality")?,
+        published: row.try_get("published")?,
+    })
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// 发布记录中的一个点位
+#[derive(Debug)]
+struct LogEntry {
+    // 最后一次放入发送队列的时间和当时的连接次数
+    sent: Option<(Instant, u64)>,
+    // 是否有包含该点位的消息没有发出，有则不能标记为已发布
+    rejected: bool,
+    // 第一次记录的时间，超过确认时间后删除
+    first: Instant,
+}
+
+/// MQTT 发布结果的记录，本地存储据此把数据标记为已发布
+///
+/// # 说明
+/// * 发布器在消息放入发送队列后调用 [`PublishLog::record`]；消息被限速合并、发送队列已满、
+///   连接已断开或发到死信主题时调用 [`PublishLog::reject`]
+/// * 放入发送队列不等于送达：连接半开时消息会在 keep-alive 发现断开之前丢失。
+///   因此放入发送队列后还要等待 `settle`（keep-alive 的2倍），期间连接一直保持（没有断开和重连）才算已发布
+/// * 同一点位的任一条消息被拒绝时不算已发布，由补发重新发出
+#[derive(Debug)]
+pub struct PublishLog {
+    settle: Duration,
+    entries: Mutex<HashMap<ReadingKey, LogEntry>>,
+}
+
+/// 可在发布器和本地存储之间共享的发布记录
+pub type SharedPublishLog = Arc<PublishLog>;
+
+impl PublishLog {
+    /// 创建记录，`settle` 为放入发送队列后确认送达前需要等待的时间
+    pub fn new(settle: Duration) -> SharedPublishLog {
+        Arc::new(PublishLog {
+            settle,
+            entries: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 记录已放入发送队列的点位
+    ///
+    /// # 参数说明
+    /// * `keys` - 消息包含的点位
+    /// * `connections` - 放入发送队列时 MQTT 客户端的连接次数（[`ConnectionState::connections`]）
+    /// * `now` - 当前时间
+    pub fn record(&self, keys: &[ReadingKey], connections: u64, now: Instant) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        for key in keys {
+            entries
+                .entry(key.clone())
+                .or_insert(LogEntry {
+                    sent: None,
+                    rejected: false,
+                    first: now,
+                })
+                .sent = Some((now, connections));
+        }
+    }
+
+    /// 记录没有发出的点位，这些点位不会被标记为已发布
+    pub fn reject(&self, keys: &[ReadingKey], now: Instant) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        for key in keys {
+            entries
+                .entry(key.clone())
+                .or_insert(LogEntry {
+                    sent: None,
+                    rejected: false,
+                    first: now,
+                })
+                .rejected = true;
+        }
+    }
+
+    /// 取出已确认送达的点位，并删除超过确认时间的记录
+    ///
+    /// 放入发送队列已超过 `settle`、期间连接没有断开过（连接次数不变且当前已连接）的点位确认送达；
+    /// 被拒绝或期间断开过的点位不确认，保持未发布，由补发重新发出。
+    pub fn confirmed(&self, state: &ConnectionState, now: Instant) -> Vec<ReadingKey> {
+        let mut confirmed = Vec::new();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|key, entry| {
+            let settled = |time: Instant| now.saturating_duration_since(time) >= self.settle;
+            match entry.sent {
+                Some((sent, connections)) if settled(sent) => {
+                    if !entry.rejected && state.connected && state.connections == connections {
+                        confirmed.push(key.clone());
+                    }
+                    false
+                }
+                Some(_) => true,
+                None => !settled(entry.first),
+            }
+        });
+        confirmed
+    }
+
+    /// 记录中的点位数
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// 记录是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// 已确认送达但数据库中还没有对应行的点位最多保留的时间
+const MISSING_KEY_RETENTION: Duration = Duration::from_secs(600);
+
+/// 把采集数据写入本地数据库的输出
+///
+/// # 说明
+/// * 采集数据先缓存在内存中，按 `flush_interval_ms` 在一个事务中写入；为0时每个采集周期立即写入
+/// * 配置了 `forward` 时数据写入时都是未发布，MQTT 发布器确认送达（见 [`PublishLog`]）后再标记为已发布；
+///   未配置 `forward` 时都标记为已发布
+/// * 每10分钟按保留天数和大小上限删除一次旧数据
+pub struct StorageSink {
+    store: ReadingStore,
+    settings: StorageSettings,
+    forward: Option<(Arc<MqttClient>, SharedPublishLog)>,
+    pending: Vec<(Vec<Reading>, bool)>,
+    // 已确认送达但写入时还没有对应行的点位，以及确认的时间
+    missing: Vec<(ReadingKey, Instant)>,
+    last_retention: Option<Instant>,
+}
+
+impl StorageSink {
+    /// 创建输出
+    ///
+    /// # 参数说明
+    /// * `store` - 已打开的数据库
+    /// * `settings` - 存储配置
+    /// * `forward` - MQTT 客户端和发布器共用的发布记录，用于标记数据已发布；未配置 MQTT 时为 None
+    pub fn new(
+        store: ReadingStore,
+        settings: &StorageSettings,
+        forward: Option<(Arc<MqttClient>, SharedPublishLog)>,
+    ) -> Self {
+        StorageSink {
+            store,
+            settings: settings.clone(),
+            forward,
+            pending: Vec::new(),
+            missing: Vec::new(),
+            last_retention: None,
+        }
+    }
+
+    // 把发布器确认送达的点位标记为已发布
+    async fn mark_confirmed(&mut self) {
+        let Some((client, log)) = &self.forward else {
+            return;
+        };
+        let now = Instant::now();
+        let state = client.state();
+        let mut keys: Vec<ReadingKey> = self.missing.iter().map(|(key, _)| key.clone()).collect();
+        keys.extend(log.confirmed(&state, now));
+        if keys.is_empty() {
+            return;
+        }
+        match self.store.mark_published_keys(&keys).await {
+            Ok(missing) => {
+                let first: HashMap<ReadingKey, Instant> = self.missing.drain(..).collect();
+                self.missing = missing
+                    .into_iter()
+                    .map(|key| {
+                        let at = first.get(&key).copied().unwrap_or(now);
+                        (key, at)
+                    })
+                    .filter(|(_, at)| now.duration_since(*at) < MISSING_KEY_RETENTION)
+                    .collect();
+            }
+            Err(e) => warn!(error = %e, rows = keys.len(), "标记已发布失败，这些数据之后会被补发"),
+        }
+    }
+
+    // 到了检查间隔时删除旧数据
+    async fn retain(&mut self) {
+        if self.settings.retention_days.is_none() && self.settings.max_size_mb.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .last_retention
+            .is_some_and(|last| now.duration_since(last) < RETENTION_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_retention = Some(now);
+        match self.store.enforce_retention(&self.settings).await {
+            Ok(0) => {}
+            Ok(deleted) => info!(deleted, "已删除本地数据库中的旧数据"),
+            Err(e) => warn!(error = %e, "删除本地数据库中的旧数据失败"),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for StorageSink {
+    async fn deliver(&mut self, batch: &[Reading]) {
+        // 过期标记不是新的采集数据，不写入数据库
+        let readings: Vec<Reading> = batch
+            .iter()
+            .filter(|r| r.quality != Quality::Stale)
+            .cloned()
+            .collect();
+        if readings.is_empty() {
+            return;
+        }
+        let published = !(self.settings.forward && self.forward.is_some());
+        self.pending.push((readings, published));
+        if self.settings.flush_interval_ms == 0 {
+            self.flush().await;
+        }
+    }
+
+    fn flush_interval(&self) -> Option<Duration> {
+        (self.settings.flush_interval_ms > 0)
+            .then(|| Duration::from_millis(self.settings.flush_interval_ms))
+    }
+
+    async fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            let batches = std::mem::take(&mut self.pending);
+            if let Err(e) = self.store.insert(&batches).await {
+                let rows: usize = batches.iter().map(|(readings, _)| readings.len()).sum();
+                warn!(error = %e, rows, "写入本地数据库失败，数据被丢弃");
+            }
+        }
+        if self.settings.forward {
+            self.mark_confirmed().await;
+        }
+        self.retain().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::batch::reading_key;
+    use crate::test_support::{at, reading};
+
+    const SETTLE: Duration = Duration::from_secs(60);
+
+    fn connected(connections: u64) -> ConnectionState {
+        ConnectionState {
+            connected: true,
+            connections,
+            ..ConnectionState::default()
+        }
+    }
+
+    fn key(point: &str) -> ReadingKey {
+        reading_key(&reading("10.0.0.1:502", 1, point, 1.0))
+    }
+
+    #[test]
+    fn log_confirms_after_settle_on_same_connection() {
+        let log = PublishLog::new(SETTLE);
+        let start = Instant::now();
+        log.record(&[key("a")], 1, start);
+        assert!(log.confirmed(&connected(1), start + SETTLE / 2).is_empty());
+        assert_eq!(log.confirmed(&connected(1), start + SETTLE), vec![key("a")]);
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn log_drops_entries_sent_before_reconnect() {
+        let log = PublishLog::new(SETTLE);
+        let start = Instant::now();
+        log.record(&[key("a")], 1, start);
+        assert!(log.confirmed(&connected(2), start + SETTLE).is_empty());
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn log_drops_entries_while_disconnected() {
+        let log = PublishLog::new(SETTLE);
+        let start = Instant::now();
+        log.record(&[key("a")], 1, start);
+        let state = ConnectionState {
+            connections: 1,
+            ..ConnectionState::default()
+        };
+        assert!(log.confirmed(&state, start + SETTLE).is_empty());
+    }
+
+    #[test]
+    fn log_rejection_wins_over_record() {
+        let log = PublishLog::new(SETTLE);
+        let start = Instant::now();
+        // 拆分后的第一条发出、第二条被合并，或者反过来，都不算已发布
+        log.record(&[key("a"), key("b")], 1, start);
+        log.reject(&[key("a"), key("b")], start);
+        log.reject(&[key("c")], start);
+        log.record(&[key("c")], 1, start);
+        assert!(log.confirmed(&connected(1), start + SETTLE).is_empty());
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn log_expires_rejected_only_entries() {
+        let log = PublishLog::new(SETTLE);
+        let start = Instant::now();
+        log.reject(&[key("a")], start);
+        assert!(log.confirmed(&connected(1), start).is_empty());
+        assert_eq!(log.len(), 1);
+        assert!(log.confirmed(&connected(1), start + SETTLE).is_empty());
+        assert!(log.is_empty());
+    }
+
+    async fn open_store(dir: &tempfile::TempDir) -> ReadingStore {
+        let path = dir.path().join("readings.db");
+        ReadingStore::open(path.to_str().unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn insert_and_mark_published_by_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(&dir).await;
+        let mut first = reading("10.0.0.1:502", 1, "a", 1.0);
+        first.timestamp = at(1000);
+        let mut second = reading("10.0.0.1:502", 1, "b", f64::NAN);
+        second.timestamp = at(2000);
+        second.quality = Quality::Bad;
+        store
+            .insert(&[(vec![first.clone(), second.clone()], false)])
+            .await
+            .unwrap();
+        assert_eq!(store.unpublished_count().await.unwrap(), 2);
+
+        let missing = store
+            .mark_published_keys(&[reading_key(&first), key("unknown")])
+            .await
+            .unwrap();
+        assert_eq!(missing, vec![key("unknown")]);
+        let rows = store.unpublished(10).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].point, "b");
+        assert!(rows[0].value.is_nan());
+        assert_eq!(rows[0].quality, "bad");
+        assert_eq!(rows[0].timestamp, at(2000));
+        store.close().await;
+    }
+
+    #[tokio::test]
+    async fn delete_before_cutoff() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(&dir).await;
+        let mut old = reading("10.0.0.1:502", 1, "a", 1.0);
+        old.timestamp = at(1000);
+        let mut new = old.clone();
+        new.timestamp = at(5000);
+        store.insert(&[(vec![old, new], true)]).await.unwrap();
+        assert_eq!(store.delete_before(at(3000)).await.unwrap(), 1);
+        assert_eq!(store.unpublished_count().await.unwrap(), 0);
+        store.close().await;
+    }
+
+    #[tokio::test]
+    async fn sink_without_forward_marks_everything_published() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open_store(&dir).await;
+        let settings = StorageSettings {
+            path: String::new(),
+            retention_days: None,
+            max_size_mb: None,
+            flush_interval_ms: 0,
+            forward: false,
+            replay: None,
+        };
+        let mut sink = StorageSink::new(store.clone(), &settings, None);
+        let mut stale = reading("10.0.0.1:502", 1, "s", 1.0);
+        stale.quality = Quality::Stale;
+        sink.deliver(&[reading("10.0.0.1:502", 1, "a", 1.0), stale]).await;
+        assert_eq!(store.unpublished_count().await.unwrap(), 0);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM readings")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        // 过期标记不写入
+        assert_eq!(count, 1);
+        store.close().await;
+    }
+}
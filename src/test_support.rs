@@ -0,0 +1,560 @@
+//! 单元测试共用的辅助函数
+
+// 各模块的测试只用到其中一部分
+#![allow(dead_code)]
+
+use bytes::BytesMut;
+use rumqttc::mqttbytes::v4::{
+    ConnAck, Connect, ConnectReturnCode, Packet, PingResp, PubAck, PubComp, PubRec, Publish,
+    SubAck, SubscribeReasonCode, UnsubAck,
+};
+use rumqttc::QoS;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Notify};
+
+use crate::device_configuration::modbus::Config;
+use crate::device_configuration::mqtt::PublishOptions;
+use crate::modbus::reading::{Quality, Reading};
+use crate::mqtt::topic::topic_matches;
+
+/// 解析并校验 YAML 配置，失败时 panic
+pub fn config(yaml: &str) -> Config {
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    config.validate().unwrap();
+    config
+}
+
+/// 固定的采集时间：1970-01-01 之后 `millis` 毫秒
+pub fn at(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// 质量为 good 的数值采集数据
+pub fn reading(gateway: &str, slave_id: u8, point: &str, value: f64) -> Reading {
+    Reading {
+        gateway: gateway.to_string(),
+        gateway_name: None,
+        slave_id,
+        slave_name: None,
+        point: point.to_string(),
+        group: None,
+        value,
+        text: None,
+        raw: Vec::new(),
+        unit: None,
+        timestamp: at(1_700_000_000_000),
+        quality: Quality::Good,
+        publish: PublishOptions::default(),
+    }
+}
+
+/// 测试用的 MQTT Broker，只实现 MQTT 3.1.1 中客户端用到的部分
+///
+/// 记录收到的所有报文；客户端发布的消息转发给匹配的订阅，保留消息在订阅时补发。
+/// drop 时停止监听并断开所有连接。
+pub struct MockBroker {
+    /// 监听的端口，地址为 127.0.0.1
+    pub port: u16,
+    shared: Arc<BrokerShared>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// Broker 收到的报文
+#[derive(Debug, Clone)]
+pub enum Received {
+    /// CONNECT
+    Connect(Connect),
+    /// PUBLISH
+    Publish(Publish),
+    /// SUBSCRIBE 中的一个过滤器
+    Subscribe(String),
+    /// UNSUBSCRIBE 中的一个过滤器
+    Unsubscribe(String),
+    /// DISCONNECT
+    Disconnect,
+}
+
+#[derive(Default)]
+struct BrokerShared {
+    received: Mutex<Vec<Received>>,
+    // 连接编号和订阅的过滤器
+    subscriptions: Mutex<Vec<(u64, String, mpsc::UnboundedSender<Publish>)>>,
+    retained: Mutex<BTreeMap<String, Publish>>,
+    changed: Notify,
+    kick: Mutex<Vec<oneshot::Sender<()>>>,
+}
+
+impl BrokerShared {
+    fn push(&self, packet: Received) {
+        lock(&self.received).push(packet);
+        self.changed.notify_waiters();
+    }
+
+    fn route(&self, publish: &Publish) {
+        if publish.retain {
+            let mut retained = lock(&self.retained);
+            if publish.payload.is_empty() {
+                retained.remove(&publish.topic);
+            } else {
+                retained.insert(publish.topic.clone(), publish.clone());
+            }
+        }
+        for (_, filter, sender) in lock(&self.subscriptions).iter() {
+            if topic_matches(filter, &publish.topic) {
+                let mut forwarded = Publish::new(&publish.topic, QoS::AtMostOnce, publish.payload.to_vec());
+                forwarded.retain = false;
+                let _ = sender.send(forwarded);
+            }
+        }
+    }
+}
+
+impl MockBroker {
+    /// 在随机端口上启动
+    pub async fn start() -> MockBroker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(BrokerShared::default());
+        let task = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            async move {
+                let mut next_id = 0;
+                while let Ok((stream, _)) = listener.accept().await {
+                    next_id += 1;
+                    let (kick, kicked) = oneshot::channel();
+                    lock(&shared.kick).push(kick);
+                    tokio::spawn(serve_mqtt(Arc::clone(&shared), next_id, stream, kicked));
+                }
+            }
+        });
+        MockBroker { port, shared, task }
+    }
+
+    /// 到目前为止收到的报文
+    pub fn received(&self) -> Vec<Received> {
+        lock(&self.shared.received).clone()
+    }
+
+    /// 收到的 PUBLISH
+    pub fn publishes(&self) -> Vec<Publish> {
+        self.received()
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Received::Publish(publish) => Some(publish),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 发布到指定主题的消息内容
+    pub fn payloads(&self, topic: &str) -> Vec<Vec<u8>> {
+        self.publishes()
+            .into_iter()
+            .filter(|publish| publish.topic == topic)
+            .map(|publish| publish.payload.to_vec())
+            .collect()
+    }
+
+    /// 收到的 CONNECT
+    pub fn connects(&self) -> Vec<Connect> {
+        self.received()
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Received::Connect(connect) => Some(connect),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 等到收到的报文满足条件，5秒后仍不满足时 panic
+    pub async fn wait_for<F>(&self, what: &str, mut condition: F) -> Vec<Received>
+    where
+        F: FnMut(&[Received]) -> bool,
+    {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let notified = self.shared.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            let received = self.received();
+            if condition(&received) {
+                return received;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                panic!("等待 {} 超时，已收到 {:#?}", what, self.received());
+            }
+        }
+    }
+
+    /// 等到收到发布到指定主题的消息，返回该主题所有消息的内容
+    pub async fn wait_for_topic(&self, topic: &str, count: usize) -> Vec<Vec<u8>> {
+        self.wait_for(topic, |received| {
+            received
+                .iter()
+                .filter(|packet| matches!(packet, Received::Publish(p) if p.topic == topic))
+                .count()
+                >= count
+        })
+        .await;
+        self.payloads(topic)
+    }
+
+    /// 以 Broker 的身份向匹配的订阅发布消息
+    pub fn inject(&self, topic: &str, payload: &[u8]) {
+        self.shared.route(&Publish::new(topic, QoS::AtMostOnce, payload.to_vec()));
+    }
+
+    /// 断开当前所有连接（不发送 DISCONNECT），客户端随后会重连
+    pub fn drop_connections(&self) {
+        for kick in lock(&self.shared.kick).drain(..) {
+            let _ = kick.send(());
+        }
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.drop_connections();
+    }
+}
+
+// 处理一个客户端连接，直到客户端断开或被踢掉
+async fn serve_mqtt(
+    shared: Arc<BrokerShared>,
+    id: u64,
+    mut stream: TcpStream,
+    mut kicked: oneshot::Receiver<()>,
+) {
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<Publish>();
+    let mut buffer = BytesMut::new();
+    let mut reply = BytesMut::new();
+    'connection: loop {
+        loop {
+            let packet = match rumqttc::mqttbytes::v4::read(&mut buffer, 1 << 20) {
+                Ok(packet) => packet,
+                Err(rumqttc::mqttbytes::Error::InsufficientBytes(_)) => break,
+                Err(_) => break 'connection,
+            };
+            match packet {
+                Packet::Connect(connect) => {
+                    shared.push(Received::Connect(connect));
+                    ConnAck::new(ConnectReturnCode::Success, false).write(&mut reply).unwrap();
+                }
+                Packet::Publish(publish) => {
+                    match publish.qos {
+                        QoS::AtMostOnce => {}
+                        QoS::AtLeastOnce => {
+                            PubAck::new(publish.pkid).write(&mut reply).unwrap();
+                        }
+                        QoS::ExactlyOnce => {
+                            PubRec::new(publish.pkid).write(&mut reply).unwrap();
+                        }
+                    }
+                    shared.route(&publish);
+                    shared.push(Received::Publish(publish));
+                }
+                Packet::PubRel(rel) => {
+                    PubComp::new(rel.pkid).write(&mut reply).unwrap();
+                }
+                Packet::Subscribe(subscribe) => {
+                    let mut codes = Vec::new();
+                    for filter in subscribe.filters {
+                        codes.push(SubscribeReasonCode::Success(filter.qos));
+                        lock(&shared.subscriptions).push((id, filter.path.clone(), sender.clone()));
+                        for retained in lock(&shared.retained).values() {
+                            if topic_matches(&filter.path, &retained.topic) {
+                                let _ = sender.send(retained.clone());
+                            }
+                        }
+                        shared.push(Received::Subscribe(filter.path));
+                    }
+                    SubAck::new(subscribe.pkid, codes).write(&mut reply).unwrap();
+                }
+                Packet::Unsubscribe(unsubscribe) => {
+                    for filter in unsubscribe.topics {
+                        lock(&shared.subscriptions).retain(|(conn, f, _)| !(*conn == id && *f == filter));
+                        shared.push(Received::Unsubscribe(filter));
+                    }
+                    UnsubAck::new(unsubscribe.pkid).write(&mut reply).unwrap();
+                }
+                Packet::PingReq => {
+                    PingResp.write(&mut reply).unwrap();
+                }
+                Packet::Disconnect => {
+                    shared.push(Received::Disconnect);
+                    break 'connection;
+                }
+                _ => {}
+            }
+        }
+        if !reply.is_empty() && stream.write_all(&reply.split()).await.is_err() {
+            break;
+        }
+        tokio::select! {
+            read = stream.read_buf(&mut buffer) => {
+                if !matches!(read, Ok(n) if n > 0) {
+                    break;
+                }
+            }
+            Some(publish) = outgoing.recv() => {
+                publish.write(&mut reply).unwrap();
+            }
+            _ = &mut kicked => break,
+        }
+    }
+    lock(&shared.subscriptions).retain(|(conn, _, _)| *conn != id);
+}
+
+/// 测试用的 Modbus TCP 服务器，支持功能码 1-6、15、16
+///
+/// 所有从站共用一份按（从站ID，读取功能码，地址）保存的寄存器，未设置的寄存器为0；
+/// 线圈和保持寄存器可以写入。记录收到的所有请求，可以让每个请求延迟应答。
+/// drop 时停止监听并断开所有连接。
+pub struct MockModbus {
+    /// 监听的端口，地址为 127.0.0.1
+    pub port: u16,
+    shared: Arc<ModbusShared>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// Modbus 服务器收到的一个请求
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModbusRequest {
+    /// 从站ID
+    pub slave_id: u8,
+    /// 功能码
+    pub function_code: u8,
+    /// 起始地址
+    pub address: u16,
+    /// 数量
+    pub quantity: u16,
+    /// 写入的值（线圈为0或1），读请求为空
+    pub values: Vec<u16>,
+    /// 收到请求的时间
+    pub received: tokio::time::Instant,
+}
+
+#[derive(Default)]
+struct ModbusShared {
+    registers: Mutex<HashMap<(u8, u8, u16), u16>>,
+    requests: Mutex<Vec<ModbusRequest>>,
+    delay: Mutex<Duration>,
+    // 不应答的从站
+    silent: Mutex<Vec<u8>>,
+    // 返回异常响应的从站和异常码
+    exceptions: Mutex<HashMap<u8, u8>>,
+    connections: Mutex<usize>,
+    closed: Mutex<usize>,
+    in_flight: Mutex<(usize, usize)>,
+    tasks: Mutex<Vec<tokio::task::JoinHandle<()>>>,
+}
+
+impl MockModbus {
+    /// 在随机端口上启动
+    pub async fn start() -> MockModbus {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(ModbusShared::default());
+        let task = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    *lock(&shared.connections) += 1;
+                    let connection = tokio::spawn({
+                        let shared = Arc::clone(&shared);
+                        async move {
+                            serve_modbus(Arc::clone(&shared), stream).await;
+                            *lock(&shared.closed) += 1;
+                        }
+                    });
+                    lock(&shared.tasks).push(connection);
+                }
+            }
+        });
+        MockModbus { port, shared, task }
+    }
+
+    /// 设置从 `address` 开始的寄存器（线圈和离散输入为0或1），`function_code` 为读取功能码 1-4
+    pub fn set(&self, slave_id: u8, function_code: u8, address: u16, values: &[u16]) {
+        let mut registers = lock(&self.shared.registers);
+        for (offset, value) in values.iter().enumerate() {
+            registers.insert((slave_id, function_code, address + offset as u16), *value);
+        }
+    }
+
+    /// 读取从 `address` 开始的 `quantity` 个寄存器
+    pub fn get(&self, slave_id: u8, function_code: u8, address: u16, quantity: u16) -> Vec<u16> {
+        let registers = lock(&self.shared.registers);
+        (address..address + quantity)
+            .map(|a| registers.get(&(slave_id, function_code, a)).copied().unwrap_or(0))
+            .collect()
+    }
+
+    /// 之后的每个请求都延迟 `delay` 应答
+    pub fn set_delay(&self, delay: Duration) {
+        *lock(&self.shared.delay) = delay;
+    }
+
+    /// 发给该从站的请求不再应答，客户端只能等到超时
+    pub fn silence(&self, slave_id: u8) {
+        lock(&self.shared.silent).push(slave_id);
+    }
+
+    /// 发给该从站的请求都返回异常码为 `code` 的异常响应
+    pub fn reject(&self, slave_id: u8, code: u8) {
+        lock(&self.shared.exceptions).insert(slave_id, code);
+    }
+
+    /// 取消 [`MockModbus::silence`] 和 [`MockModbus::reject`]，该从站恢复正常应答
+    pub fn restore(&self, slave_id: u8) {
+        lock(&self.shared.silent).retain(|id| *id != slave_id);
+        lock(&self.shared.exceptions).remove(&slave_id);
+    }
+
+    /// 到目前为止收到的请求
+    pub fn requests(&self) -> Vec<ModbusRequest> {
+        lock(&self.shared.requests).clone()
+    }
+
+    /// 到目前为止接受的连接数
+    pub fn connections(&self) -> usize {
+        *lock(&self.shared.connections)
+    }
+
+    /// 到目前为止已断开的连接数
+    pub fn closed(&self) -> usize {
+        *lock(&self.shared.closed)
+    }
+
+    /// 同时在处理中的请求数的最大值
+    pub fn max_in_flight(&self) -> usize {
+        lock(&self.shared.in_flight).1
+    }
+}
+
+impl Drop for MockModbus {
+    fn drop(&mut self) {
+        self.task.abort();
+        for task in lock(&self.shared.tasks).drain(..) {
+            task.abort();
+        }
+    }
+}
+
+// 处理一个客户端连接，按顺序应答请求
+async fn serve_modbus(shared: Arc<ModbusShared>, mut stream: TcpStream) {
+    let mut header = [0u8; 7];
+    while stream.read_exact(&mut header).await.is_ok() {
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut pdu = vec![0u8; length.saturating_sub(1)];
+        if pdu.is_empty() || stream.read_exact(&mut pdu).await.is_err() {
+            return;
+        }
+        let slave_id = header[6];
+        {
+            let mut in_flight = lock(&shared.in_flight);
+            in_flight.0 += 1;
+            in_flight.1 = in_flight.1.max(in_flight.0);
+        }
+        let response = modbus_response(&shared, slave_id, &pdu);
+        let delay = *lock(&shared.delay);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        lock(&shared.in_flight).0 -= 1;
+        if lock(&shared.silent).contains(&slave_id) {
+            continue;
+        }
+        let mut frame = header[..4].to_vec();
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(slave_id);
+        frame.extend_from_slice(&response);
+        if stream.write_all(&frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+// 执行一个请求，返回应答的 PDU
+fn modbus_response(shared: &ModbusShared, slave_id: u8, pdu: &[u8]) -> Vec<u8> {
+    let function_code = pdu[0];
+    let word = |index: usize| u16::from_be_bytes([pdu[index], pdu[index + 1]]);
+    let address = word(1);
+    let (quantity, values) = match function_code {
+        1..=4 => (word(3), Vec::new()),
+        5 => (1, vec![u16::from(word(3) == 0xFF00)]),
+        6 => (1, vec![word(3)]),
+        15 => {
+            let quantity = word(3);
+            let bits = (0..quantity).map(|i| u16::from(pdu[6 + i as usize / 8] >> (i % 8) & 1)).collect();
+            (quantity, bits)
+        }
+        16 => {
+            let quantity = word(3);
+            (quantity, (0..quantity as usize).map(|i| word(6 + i * 2)).collect())
+        }
+        _ => return vec![function_code | 0x80, 0x01],
+    };
+    lock(&shared.requests).push(ModbusRequest {
+        slave_id,
+        function_code,
+        address,
+        quantity,
+        values: values.clone(),
+        received: tokio::time::Instant::now(),
+    });
+    if let Some(code) = lock(&shared.exceptions).get(&slave_id) {
+        return vec![function_code | 0x80, *code];
+    }
+    let mut registers = lock(&shared.registers);
+    let mut read = |table: u8| -> Vec<u16> {
+        (address..address + quantity)
+            .map(|a| *registers.entry((slave_id, table, a)).or_default())
+            .collect()
+    };
+    match function_code {
+        1 | 2 => {
+            let bits = read(function_code);
+            let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+            for (i, bit) in bits.iter().enumerate() {
+                if *bit != 0 {
+                    bytes[i / 8] |= 1 << (i % 8);
+                }
+            }
+            let mut response = vec![function_code, bytes.len() as u8];
+            response.extend(bytes);
+            response
+        }
+        3 | 4 => {
+            let mut response = vec![function_code, (quantity * 2) as u8];
+            for value in read(function_code) {
+                response.extend_from_slice(&value.to_be_bytes());
+            }
+            response
+        }
+        _ => {
+            let table = if matches!(function_code, 5 | 15) { 1 } else { 3 };
+            for (offset, value) in values.iter().enumerate() {
+                registers.insert((slave_id, table, address + offset as u16), *value);
+            }
+            if matches!(function_code, 5 | 6) {
+                pdu[..5].to_vec()
+            } else {
+                let mut response = vec![function_code];
+                response.extend_from_slice(&address.to_be_bytes());
+                response.extend_from_slice(&quantity.to_be_bytes());
+                response
+            }
+        }
+    }
+}
+
+// 测试中忽略锁中毒
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
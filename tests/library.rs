@@ -0,0 +1,137 @@
+//! 只通过公开接口使用库：Modbus 客户端、配置读取和按配置运行
+
+use std::fs;
+use std::time::Duration;
+
+use modbus_pub::app::{App, AppOptions};
+use modbus_pub::{read_config, ModbusClient, ModbusDevice, ModbusOperation};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// 只支持功能码3的 Modbus TCP 服务器，寄存器的值等于地址
+async fn register_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut header = [0u8; 7];
+                while stream.read_exact(&mut header).await.is_ok() {
+                    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                    let mut pdu = vec![0u8; length - 1];
+                    if stream.read_exact(&mut pdu).await.is_err() {
+                        return;
+                    }
+                    let start = u16::from_be_bytes([pdu[1], pdu[2]]);
+                    let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+                    let mut response = vec![0x03, (count * 2) as u8];
+                    for address in start..start + count {
+                        response.extend_from_slice(&address.to_be_bytes());
+                    }
+                    let mut frame = header[..4].to_vec();
+                    frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+                    frame.push(header[6]);
+                    frame.extend_from_slice(&response);
+                    if stream.write_all(&frame).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn client_reads_holding_registers() {
+    let port = register_server().await;
+    let mut client = ModbusClient::new(ModbusDevice {
+        name: None,
+        ip: "127.0.0.1".to_string(),
+        port,
+        slave_id: 1,
+        connect_timeout: Duration::from_secs(1),
+        request_timeout: Duration::from_secs(1),
+    });
+    client.connect().await.unwrap();
+    assert_eq!(client.read_registers(0x03, 100, 3).await.unwrap(), [100, 101, 102]);
+    client.disconnect().await.unwrap();
+}
+
+fn write_config(dir: &tempfile::TempDir) -> String {
+    let path = dir.path().join("config.yaml");
+    let csv = dir.path().join("csv");
+    fs::write(
+        &path,
+        format!(
+            r#"version: 2
+csv:
+  directory: {}
+  flush_interval_ms: 100
+gateways:
+  - ip: 10.0.0.1
+    slave_ids: [1]
+    simulation: true
+    poll_interval_ms: 100
+    points:
+      - {{ name: voltage, address: 0, scale: 0.1, sim: {{ kind: constant, value: 230 }} }}
+"#,
+            csv.display()
+        ),
+    )
+    .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn app_polls_until_shutdown() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_config(&dir);
+    let config = read_config(&path).unwrap();
+    let app = App::start(config, &path, AppOptions::default()).await.unwrap();
+    assert!(app.mqtt().is_none());
+    let cache = app.cache();
+
+    let polled = {
+        let cache = cache.clone();
+        async move {
+            loop {
+                if app.pending(true).is_empty() && cache.get("10.0.0.1:502", 1, "voltage").is_some() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            app
+        }
+    };
+    let app = tokio::time::timeout(Duration::from_secs(5), polled).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), app.run(async {})).await.unwrap();
+
+    let reading = cache.get("10.0.0.1:502", 1, "voltage").unwrap();
+    assert!((reading.value - 230.0).abs() < 1e-9);
+    // 停止时输出已写入磁盘
+    let files: Vec<_> = fs::read_dir(dir.path().join("csv")).unwrap().collect();
+    assert_eq!(files.len(), 1);
+    let contents = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+    // 文件以 UTF-8 BOM 开头，便于 Excel 识别编码
+    let contents = contents.trim_start_matches('\u{feff}');
+    assert!(contents.starts_with("timestamp,voltage\r\n"), "{}", contents);
+    assert!(contents.lines().count() >= 2, "{}", contents);
+}
+
+#[tokio::test]
+async fn app_once_returns_without_shutdown() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_config(&dir);
+    let config = read_config(&path).unwrap();
+    let options = AppOptions {
+        once: true,
+        ..AppOptions::default()
+    };
+    let app = App::start(config, &path, options).await.unwrap();
+    let cache = app.cache();
+    tokio::time::timeout(Duration::from_secs(10), app.run(std::future::pending()))
+        .await
+        .unwrap();
+    assert!(cache.get("10.0.0.1:502", 1, "voltage").is_some());
+}